@@ -0,0 +1,86 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+// Lighting subpass of the deferred path, see deferred::DeferredPass. Shades every
+// pixel of the G-buffer written by shaders/gbuffer.frag with the lights of
+// shaders/shader.frag.
+
+// must match lighting::MAX_LIGHTS
+#define MAX_LIGHTS 8
+
+struct Light {
+    // w: 0 for directional, 1 for point lights
+    vec4 position;
+    // a: intensity
+    vec4 color;
+    // constant, linear, quadratic, range
+    vec4 attenuation;
+};
+
+layout(input_attachment_index = 0, binding = 0) uniform subpassInput in_albedo;
+layout(input_attachment_index = 1, binding = 1) uniform subpassInput in_normal;
+layout(input_attachment_index = 2, binding = 2) uniform subpassInput in_position;
+
+layout(binding = 3) uniform LightBlock {
+    // a: shininess
+    vec4 ambient;
+    vec4 camera_position;
+    uvec4 light_count;
+    Light lights[MAX_LIGHTS];
+} light_block;
+
+layout(location = 0) in vec2 frag_tex_coord;
+
+layout(location = 0) out vec4 out_color;
+
+// blinn-phong diffuse + specular contribution of a single light
+vec3 shade(Light light, vec3 position, vec3 normal, vec3 view_dir, vec3 albedo) {
+    vec3 light_dir;
+    float attenuation = 1.0;
+
+    if (light.position.w == 0.0) {
+        light_dir = normalize(-light.position.xyz);
+    } else {
+        vec3 to_light = light.position.xyz - position;
+        float distance = length(to_light);
+        if (distance > light.attenuation.w) {
+            return vec3(0.0);
+        }
+
+        light_dir = to_light / distance;
+        attenuation = 1.0 / (light.attenuation.x
+            + light.attenuation.y * distance
+            + light.attenuation.z * distance * distance);
+    }
+
+    vec3 radiance = light.color.rgb * light.color.a * attenuation;
+
+    float diffuse = max(dot(normal, light_dir), 0.0);
+
+    vec3 halfway = normalize(light_dir + view_dir);
+    float specular = diffuse > 0.0
+        ? pow(max(dot(normal, halfway), 0.0), light_block.ambient.a)
+        : 0.0;
+
+    return (albedo * diffuse + vec3(specular)) * radiance;
+}
+
+void main() {
+    vec4 position = subpassLoad(in_position);
+
+    // nothing was drawn here, the clear color is kept
+    if (position.w == 0.0) {
+        discard;
+    }
+
+    vec3 albedo = subpassLoad(in_albedo).rgb;
+    vec3 normal = normalize(subpassLoad(in_normal).xyz);
+    vec3 view_dir = normalize(light_block.camera_position.xyz - position.xyz);
+
+    vec3 color = light_block.ambient.rgb * albedo;
+    for (uint i = 0; i < min(light_block.light_count.x, MAX_LIGHTS); i++) {
+        color += shade(light_block.lights[i], position.xyz, normal, view_dir, albedo);
+    }
+
+    out_color = vec4(color, 1.0);
+}
@@ -0,0 +1,16 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+// A triangle covering the whole screen generated from the vertex index,
+// drawn with 3 vertices and no vertex buffer
+
+layout(location = 0) out vec2 frag_tex_coord;
+
+out gl_PerVertex {
+    vec4 gl_Position;
+};
+
+void main() {
+    frag_tex_coord = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2);
+    gl_Position = vec4(frag_tex_coord * 2.0 - 1.0, 0.0, 1.0);
+}
@@ -0,0 +1,30 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+// Geometry subpass of the deferred path, see deferred::DeferredPass. Writes what
+// shaders/deferred_lighting.frag needs to shade the pixel.
+
+layout(binding = 1) uniform sampler2D tex_sampler;
+
+layout(location = 0) in vec3 frag_color;
+layout(location = 1) in vec2 frag_tex_coord;
+layout(location = 2) in vec3 frag_position;
+layout(location = 3) in vec3 frag_normal;
+
+layout(location = 0) out vec4 out_albedo;
+layout(location = 1) out vec4 out_normal;
+// w: 1 where geometry was drawn, the clear value of 0 marks the background
+layout(location = 2) out vec4 out_position;
+
+void main() {
+    vec3 normal = normalize(frag_normal);
+
+    // back faces are only visible when the pipeline preset disables culling
+    if (!gl_FrontFacing) {
+        normal = -normal;
+    }
+
+    out_albedo = vec4(texture(tex_sampler, frag_tex_coord).rgb, 1.0);
+    out_normal = vec4(normal, 0.0);
+    out_position = vec4(frag_position, 1.0);
+}
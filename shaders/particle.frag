@@ -0,0 +1,13 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(location = 0) in vec4 frag_color;
+layout(location = 1) in vec2 frag_uv;
+
+layout(location = 0) out vec4 out_color;
+
+void main() {
+    // round particle with a soft edge
+    float distance = length(frag_uv - 0.5) * 2.0;
+    out_color = vec4(frag_color.rgb, frag_color.a * (1.0 - smoothstep(0.5, 1.0, distance)));
+}
@@ -0,0 +1,38 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(push_constant) uniform PushConstants {
+    mat4 view_proj;
+    vec4 camera_right;
+    vec4 camera_up;
+} pc;
+
+// per instance, straight from the particle storage buffer
+layout(location = 0) in vec4 in_position;
+layout(location = 1) in vec4 in_velocity;
+layout(location = 2) in vec4 in_color;
+
+layout(location = 0) out vec4 frag_color;
+layout(location = 1) out vec2 frag_uv;
+
+out gl_PerVertex {
+    vec4 gl_Position;
+};
+
+const vec2 corners[6] = vec2[](
+    vec2(-0.5, -0.5), vec2(0.5, -0.5), vec2(0.5, 0.5),
+    vec2(0.5, 0.5), vec2(-0.5, 0.5), vec2(-0.5, -0.5)
+);
+
+void main() {
+    vec2 corner = corners[gl_VertexIndex];
+
+    // dead particles collapse to a point and produce no fragments
+    float size = in_position.w > 0.0 ? in_velocity.w : 0.0;
+    vec3 offset = (pc.camera_right.xyz * corner.x + pc.camera_up.xyz * corner.y) * size;
+
+    gl_Position = pc.view_proj * vec4(in_position.xyz + offset, 1.0);
+    // fades out during the last second of its life
+    frag_color = vec4(in_color.rgb, in_color.a * clamp(in_position.w, 0.0, 1.0));
+    frag_uv = corner + 0.5;
+}
@@ -0,0 +1,224 @@
+
+// Reference metallic-roughness shading, see vulkan::pbr. The BRDF is split into the
+// direct part evaluated per light and the ambient part, which image based lighting
+// replaces with the prefiltered environment and the BRDF lookup table when
+// IMAGE_BASED_LIGHTING is defined, see vulkan::ibl. Included by pbr.frag and
+// pbr_ibl.frag.
+
+// must match lighting::MAX_LIGHTS
+#define MAX_LIGHTS 8
+
+#define PI 3.14159265359
+
+// the order of pbr::PBR_SLOTS
+#define SLOT_ALBEDO 0
+#define SLOT_NORMAL 1
+#define SLOT_METALLIC_ROUGHNESS 2
+#define SLOT_OCCLUSION 3
+
+struct Light {
+    // w: 0 for directional, 1 for point lights
+    vec4 position;
+    // a: intensity
+    vec4 color;
+    // constant, linear, quadratic, range
+    vec4 attenuation;
+};
+
+layout(binding = 2) uniform LightBlock {
+    // a: shininess, unused here
+    vec4 ambient;
+    vec4 camera_position;
+    uvec4 light_count;
+    Light lights[MAX_LIGHTS];
+} light_block;
+
+// the texture array material layout, see material::MaterialLayoutKind
+layout(set = 1, binding = 0) uniform sampler2D material_textures[4];
+
+#ifdef IMAGE_BASED_LIGHTING
+// ibl::EnvironmentLighting
+layout(set = 2, binding = 0) uniform samplerCube irradiance_map;
+layout(set = 2, binding = 1) uniform samplerCube prefiltered_map;
+// red: scale, green: bias of f0, by n.v and roughness
+layout(set = 2, binding = 2) uniform sampler2D brdf_lut;
+#endif
+
+// pbr::PbrParameters, after the bindless material indices
+layout(push_constant) uniform PbrParameters {
+    layout(offset = 16) vec4 base_color;
+    float metallic;
+    float roughness;
+    float normal_scale;
+    float occlusion_strength;
+} material;
+
+layout(location = 0) in vec3 frag_color;
+layout(location = 1) in vec2 frag_tex_coord;
+layout(location = 2) in vec3 frag_position;
+layout(location = 3) in vec3 frag_normal;
+
+layout(location = 0) out vec4 out_color;
+
+struct Surface {
+    vec3 albedo;
+    vec3 normal;
+    float metallic;
+    float roughness;
+    float occlusion;
+    // reflectance at normal incidence
+    vec3 f0;
+};
+
+float distribution_ggx(float n_dot_h, float roughness) {
+    float a = roughness * roughness;
+    float a2 = a * a;
+    float denominator = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * denominator * denominator);
+}
+
+float geometry_schlick_ggx(float n_dot_x, float k) {
+    return n_dot_x / (n_dot_x * (1.0 - k) + k);
+}
+
+// k is remapped for direct lights, image based lighting uses roughness^2 / 2
+float geometry_smith(float n_dot_v, float n_dot_l, float roughness) {
+    float r = roughness + 1.0;
+    float k = r * r / 8.0;
+    return geometry_schlick_ggx(n_dot_v, k) * geometry_schlick_ggx(n_dot_l, k);
+}
+
+vec3 fresnel_schlick(float cos_theta, vec3 f0) {
+    return f0 + (1.0 - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+// roughness keeps grazing reflections from going white on rough surfaces, used with
+// the BRDF lookup table of image based lighting
+vec3 fresnel_schlick_roughness(float cos_theta, vec3 f0, float roughness) {
+    return f0 + (max(vec3(1.0 - roughness), f0) - f0)
+        * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+// cook-torrance specular + lambert diffuse of light arriving from light_dir
+vec3 brdf_direct(Surface surface, vec3 view_dir, vec3 light_dir) {
+    vec3 halfway = normalize(view_dir + light_dir);
+    float n_dot_v = max(dot(surface.normal, view_dir), 1e-4);
+    float n_dot_l = max(dot(surface.normal, light_dir), 0.0);
+    float n_dot_h = max(dot(surface.normal, halfway), 0.0);
+
+    float d = distribution_ggx(n_dot_h, surface.roughness);
+    float g = geometry_smith(n_dot_v, n_dot_l, surface.roughness);
+    vec3 f = fresnel_schlick(max(dot(halfway, view_dir), 0.0), surface.f0);
+
+    vec3 specular = d * g * f / (4.0 * n_dot_v * max(n_dot_l, 1e-4));
+    vec3 diffuse = (1.0 - f) * (1.0 - surface.metallic) * surface.albedo / PI;
+
+    return (diffuse + specular) * n_dot_l;
+}
+
+#ifdef IMAGE_BASED_LIGHTING
+// cube maps hold the world mirrored in z, see reflection_probe::cube_direction
+vec3 cube_lookup(vec3 direction) {
+    return direction * vec3(1.0, 1.0, -1.0);
+}
+
+// the split sum: the environment prefiltered for the roughness, scaled and biased by
+// the lookup table, plus the diffuse irradiance around the normal
+vec3 ambient_lighting(Surface surface, vec3 view_dir) {
+    float n_dot_v = max(dot(surface.normal, view_dir), 0.0);
+    vec3 f = fresnel_schlick_roughness(n_dot_v, surface.f0, surface.roughness);
+    vec3 diffuse = (1.0 - f) * (1.0 - surface.metallic) * surface.albedo
+        * texture(irradiance_map, cube_lookup(surface.normal)).rgb;
+
+    // the levels are prefiltered for evenly spaced roughness, see ibl::roughness_of_level
+    float level = surface.roughness * float(textureQueryLevels(prefiltered_map) - 1);
+    vec3 reflected = cube_lookup(reflect(-view_dir, surface.normal));
+    vec3 prefiltered = textureLod(prefiltered_map, reflected, level).rgb;
+    vec2 brdf = texture(brdf_lut, vec2(n_dot_v, surface.roughness)).rg;
+    vec3 specular = prefiltered * (f * brdf.x + brdf.y);
+
+    return (diffuse + specular) * surface.occlusion;
+}
+#else
+// the uniform ambient color standing in for image based lighting
+vec3 ambient_lighting(Surface surface, vec3 view_dir) {
+    float n_dot_v = max(dot(surface.normal, view_dir), 0.0);
+    vec3 f = fresnel_schlick_roughness(n_dot_v, surface.f0, surface.roughness);
+    vec3 diffuse = (1.0 - f) * (1.0 - surface.metallic) * surface.albedo;
+
+    return (diffuse + f) * light_block.ambient.rgb * surface.occlusion;
+}
+#endif
+
+vec3 radiance(Light light, out vec3 light_dir) {
+    if (light.position.w == 0.0) {
+        light_dir = normalize(-light.position.xyz);
+        return light.color.rgb * light.color.a;
+    }
+
+    vec3 to_light = light.position.xyz - frag_position;
+    float distance = length(to_light);
+    light_dir = to_light / max(distance, 1e-4);
+    if (distance > light.attenuation.w) {
+        return vec3(0.0);
+    }
+
+    float attenuation = 1.0 / (light.attenuation.x
+        + light.attenuation.y * distance
+        + light.attenuation.z * distance * distance);
+    return light.color.rgb * light.color.a * attenuation;
+}
+
+// the normal map in tangent space, with the tangent frame derived from screen space
+// derivatives since the vertices carry no tangents
+vec3 perturbed_normal(vec3 normal) {
+    vec3 tangent_normal = texture(material_textures[SLOT_NORMAL], frag_tex_coord).xyz * 2.0 - 1.0;
+    tangent_normal.xy *= material.normal_scale;
+
+    vec3 dp_dx = dFdx(frag_position);
+    vec3 dp_dy = dFdy(frag_position);
+    vec2 duv_dx = dFdx(frag_tex_coord);
+    vec2 duv_dy = dFdy(frag_tex_coord);
+
+    vec3 tangent = dp_dx * duv_dy.t - dp_dy * duv_dx.t;
+    if (dot(tangent, tangent) < 1e-12) {
+        return normal;
+    }
+
+    tangent = normalize(tangent - normal * dot(normal, tangent));
+    vec3 bitangent = cross(normal, tangent);
+    return normalize(mat3(tangent, bitangent, normal) * tangent_normal);
+}
+
+void main() {
+    vec3 normal = normalize(frag_normal);
+
+    // back faces are only visible when the pipeline preset disables culling
+    if (!gl_FrontFacing) {
+        normal = -normal;
+    }
+
+    vec4 albedo = texture(material_textures[SLOT_ALBEDO], frag_tex_coord) * material.base_color;
+    vec4 metallic_roughness = texture(material_textures[SLOT_METALLIC_ROUGHNESS], frag_tex_coord);
+    float occlusion = texture(material_textures[SLOT_OCCLUSION], frag_tex_coord).r;
+
+    Surface surface;
+    surface.albedo = albedo.rgb;
+    surface.normal = perturbed_normal(normal);
+    surface.metallic = clamp(metallic_roughness.b * material.metallic, 0.0, 1.0);
+    // very low roughness makes the highlight of point lights disappear between pixels
+    surface.roughness = clamp(metallic_roughness.g * material.roughness, 0.04, 1.0);
+    surface.occlusion = mix(1.0, occlusion, material.occlusion_strength);
+    surface.f0 = mix(vec3(0.04), surface.albedo, surface.metallic);
+
+    vec3 view_dir = normalize(light_block.camera_position.xyz - frag_position);
+
+    vec3 color = ambient_lighting(surface, view_dir);
+    for (uint i = 0; i < min(light_block.light_count.x, MAX_LIGHTS); i++) {
+        vec3 light_dir;
+        vec3 light_radiance = radiance(light_block.lights[i], light_dir);
+        color += brdf_direct(surface, view_dir, light_dir) * light_radiance;
+    }
+
+    out_color = vec4(color, albedo.a);
+}
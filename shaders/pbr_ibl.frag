@@ -0,0 +1,7 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_GOOGLE_include_directive : require
+
+// pbr.glsl lit by the environment, see vulkan::ibl
+#define IMAGE_BASED_LIGHTING
+#include "pbr.glsl"
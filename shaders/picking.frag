@@ -0,0 +1,13 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(push_constant) uniform PushConstants {
+    mat4 mvp;
+    uint id;
+} pc;
+
+layout(location = 0) out uint out_id;
+
+void main() {
+    out_id = pc.id;
+}
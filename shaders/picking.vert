@@ -0,0 +1,17 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(push_constant) uniform PushConstants {
+    mat4 mvp;
+    uint id;
+} pc;
+
+layout(location = 0) in vec3 in_position;
+
+out gl_PerVertex {
+    vec4 gl_Position;
+};
+
+void main() {
+    gl_Position = pc.mvp * vec4(in_position, 1.0);
+}
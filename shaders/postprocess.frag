@@ -0,0 +1,152 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+// One pass of the post process chain, compiled once per effect with
+// TONEMAP, GAMMA or FXAA defined, see vulkan::postprocess
+
+layout(binding = 0) uniform sampler2D input_image;
+
+// viewport::TonemapOperator
+const uint TONEMAP_REINHARD = 0u;
+const uint TONEMAP_ACES = 1u;
+const uint TONEMAP_HABLE = 2u;
+// on an HDR swapchain, the display maps the exposed color itself
+const uint TONEMAP_NONE = 3u;
+
+// postprocess::OutputEncoding
+const uint OUTPUT_SDR = 0u;
+const uint OUTPUT_SCRGB = 1u;
+const uint OUTPUT_HDR10 = 2u;
+
+layout(push_constant) uniform PushConstants {
+    vec2 inverse_extent;
+    float exposure;
+    float gamma;
+    // nits of 1.0 on an HDR swapchain
+    float paper_white;
+    uint tonemap;
+    uint output_encoding;
+} pc;
+
+layout(location = 0) in vec2 frag_tex_coord;
+
+layout(location = 0) out vec4 out_color;
+
+float luma(vec3 color) {
+    return dot(color, vec3(0.299, 0.587, 0.114));
+}
+
+#ifdef FXAA
+// FXAA 3.11 console variant, expects the input to be tonemapped and gamma corrected
+vec3 fxaa(vec2 uv) {
+    const float reduce_min = 1.0 / 128.0;
+    const float reduce_mul = 1.0 / 8.0;
+    const float span_max = 8.0;
+
+    vec2 texel = pc.inverse_extent;
+
+    vec3 rgb_nw = texture(input_image, uv + vec2(-0.5, -0.5) * texel).rgb;
+    vec3 rgb_ne = texture(input_image, uv + vec2(0.5, -0.5) * texel).rgb;
+    vec3 rgb_sw = texture(input_image, uv + vec2(-0.5, 0.5) * texel).rgb;
+    vec3 rgb_se = texture(input_image, uv + vec2(0.5, 0.5) * texel).rgb;
+    vec3 rgb_m = texture(input_image, uv).rgb;
+
+    float luma_nw = luma(rgb_nw);
+    float luma_ne = luma(rgb_ne);
+    float luma_sw = luma(rgb_sw);
+    float luma_se = luma(rgb_se);
+    float luma_m = luma(rgb_m);
+
+    float luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+    float luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+
+    // direction along the edge
+    vec2 dir = vec2(
+        -((luma_nw + luma_ne) - (luma_sw + luma_se)),
+        (luma_nw + luma_sw) - (luma_ne + luma_se));
+
+    float dir_reduce = max((luma_nw + luma_ne + luma_sw + luma_se) * 0.25 * reduce_mul, reduce_min);
+    float rcp_dir_min = 1.0 / (min(abs(dir.x), abs(dir.y)) + dir_reduce);
+    dir = clamp(dir * rcp_dir_min, vec2(-span_max), vec2(span_max)) * texel;
+
+    vec3 rgb_a = 0.5 * (
+        texture(input_image, uv + dir * (1.0 / 3.0 - 0.5)).rgb +
+        texture(input_image, uv + dir * (2.0 / 3.0 - 0.5)).rgb);
+    vec3 rgb_b = rgb_a * 0.5 + 0.25 * (
+        texture(input_image, uv + dir * -0.5).rgb +
+        texture(input_image, uv + dir * 0.5).rgb);
+
+    // the wider sample left the local luma range, so it crossed another edge
+    float luma_b = luma(rgb_b);
+    return (luma_b < luma_min || luma_b > luma_max) ? rgb_a : rgb_b;
+}
+#endif
+
+vec3 hable(vec3 x) {
+    const float a = 0.15;
+    const float b = 0.50;
+    const float c = 0.10;
+    const float d = 0.20;
+    const float e = 0.02;
+    const float f = 0.30;
+    return ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f;
+}
+
+vec3 tonemap(vec3 color) {
+    if (pc.tonemap == TONEMAP_ACES) {
+        color = (color * (2.51 * color + 0.03)) / (color * (2.43 * color + 0.59) + 0.14);
+        return clamp(color, 0.0, 1.0);
+    } else if (pc.tonemap == TONEMAP_HABLE) {
+        const float white = 11.2;
+        return hable(color * 2.0) / hable(vec3(white));
+    } else if (pc.tonemap == TONEMAP_NONE) {
+        return color;
+    }
+
+    return color / (color + vec3(1.0));
+}
+
+// ST 2084 inverse EOTF of linear light normalized to 10000 nits
+vec3 pq(vec3 color) {
+    const float m1 = 2610.0 / 16384.0;
+    const float m2 = 2523.0 / 32.0;
+    const float c1 = 3424.0 / 4096.0;
+    const float c2 = 2413.0 / 128.0;
+    const float c3 = 2392.0 / 128.0;
+
+    vec3 p = pow(clamp(color, 0.0, 1.0), vec3(m1));
+    return pow((c1 + c2 * p) / (1.0 + c3 * p), vec3(m2));
+}
+
+vec3 encode_output(vec3 color) {
+    if (pc.output_encoding == OUTPUT_SCRGB) {
+        return color * pc.paper_white / 80.0;
+    } else if (pc.output_encoding == OUTPUT_HDR10) {
+        // rec. 709 to rec. 2020 primaries, columns as glsl matrices are column major
+        const mat3 to_rec2020 = mat3(
+            0.6274, 0.0691, 0.0164,
+            0.3293, 0.9195, 0.0880,
+            0.0433, 0.0114, 0.8956);
+        return pq(to_rec2020 * max(color, vec3(0.0)) * pc.paper_white / 10000.0);
+    }
+
+    return color;
+}
+
+void main() {
+#ifdef FXAA
+    vec3 color = fxaa(frag_tex_coord);
+#else
+    vec3 color = texture(input_image, frag_tex_coord).rgb;
+#endif
+
+#ifdef TONEMAP
+    color = tonemap(color * pc.exposure);
+#endif
+
+#ifdef GAMMA
+    color = pow(max(color, vec3(0.0)), vec3(1.0 / pc.gamma));
+#endif
+
+    out_color = vec4(encode_output(color), 1.0);
+}
@@ -1,13 +1,96 @@
 #version 450
 #extension GL_ARB_separate_shader_objects : enable
 
+// must match lighting::MAX_LIGHTS
+#define MAX_LIGHTS 8
+
+struct Light {
+    // w: 0 for directional, 1 for point lights
+    vec4 position;
+    // a: intensity
+    vec4 color;
+    // constant, linear, quadratic, range
+    vec4 attenuation;
+};
+
 layout(binding = 1) uniform sampler2D tex_sampler;
 
+layout(binding = 2) uniform LightBlock {
+    // a: shininess
+    vec4 ambient;
+    vec4 camera_position;
+    uvec4 light_count;
+    Light lights[MAX_LIGHTS];
+} light_block;
+
 layout(location = 0) in vec3 frag_color;
 layout(location = 1) in vec2 frag_tex_coord;
+layout(location = 2) in vec3 frag_position;
+layout(location = 3) in vec3 frag_normal;
 
 layout(location = 0) out vec4 out_color;
 
+// blinn-phong diffuse + specular contribution of a single light
+vec3 shade(Light light, vec3 normal, vec3 view_dir, vec3 albedo) {
+    vec3 light_dir;
+    float attenuation = 1.0;
+
+    if (light.position.w == 0.0) {
+        light_dir = normalize(-light.position.xyz);
+    } else {
+        vec3 to_light = light.position.xyz - frag_position;
+        float distance = length(to_light);
+        if (distance > light.attenuation.w) {
+            return vec3(0.0);
+        }
+
+        light_dir = to_light / distance;
+        attenuation = 1.0 / (light.attenuation.x
+            + light.attenuation.y * distance
+            + light.attenuation.z * distance * distance);
+    }
+
+    vec3 radiance = light.color.rgb * light.color.a * attenuation;
+
+    float diffuse = max(dot(normal, light_dir), 0.0);
+
+    vec3 halfway = normalize(light_dir + view_dir);
+    float specular = diffuse > 0.0
+        ? pow(max(dot(normal, halfway), 0.0), light_block.ambient.a)
+        : 0.0;
+
+    return (albedo * diffuse + vec3(specular)) * radiance;
+}
+
+// debug views are compiled as separate permutations, see permutation::DebugView
 void main() {
-    out_color = texture(tex_sampler, frag_tex_coord);
+#if defined(DEBUG_VIEW_LIGHTING_ONLY)
+    vec3 albedo = vec3(1.0);
+#else
+    vec3 albedo = texture(tex_sampler, frag_tex_coord).rgb;
+#endif
+    vec3 normal = normalize(frag_normal);
+    vec3 view_dir = normalize(light_block.camera_position.xyz - frag_position);
+
+    // back faces are only visible when the pipeline preset disables culling
+    if (!gl_FrontFacing) {
+        normal = -normal;
+    }
+
+#if defined(DEBUG_VIEW_ALBEDO)
+    out_color = vec4(albedo, 1.0);
+#elif defined(DEBUG_VIEW_NORMALS)
+    out_color = vec4(normal * 0.5 + 0.5, 1.0);
+#elif defined(DEBUG_VIEW_TEX_COORDS)
+    out_color = vec4(frag_tex_coord, 0.0, 1.0);
+#elif defined(DEBUG_VIEW_VERTEX_COLOR)
+    out_color = vec4(frag_color, 1.0);
+#else
+    vec3 color = light_block.ambient.rgb * albedo;
+    for (uint i = 0; i < min(light_block.light_count.x, MAX_LIGHTS); i++) {
+        color += shade(light_block.lights[i], normal, view_dir, albedo);
+    }
+
+    out_color = vec4(color, 1.0);
+#endif
 }
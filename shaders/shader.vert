@@ -10,9 +10,12 @@ layout(set = 0, binding = 0) uniform UniformBufferObject {
 layout(location = 0) in vec3 in_position;
 layout(location = 1) in vec3 in_color;
 layout(location = 2) in vec2 in_tex_coord;
+layout(location = 3) in vec3 in_normal;
 
 layout(location = 0) out vec3 frag_color;
 layout(location = 1) out vec2 frag_tex_coord;
+layout(location = 2) out vec3 frag_position;
+layout(location = 3) out vec3 frag_normal;
 
 out gl_PerVertex {
     vec4 gl_Position;
@@ -20,7 +23,11 @@ out gl_PerVertex {
 
 
 void main() {
-    gl_Position = ubo.proj * ubo.view * ubo.model * vec4(in_position, 1.0);
+    vec4 world_position = ubo.model * vec4(in_position, 1.0);
+
+    gl_Position = ubo.proj * ubo.view * world_position;
     frag_color = in_color;
     frag_tex_coord = in_tex_coord;
+    frag_position = world_position.xyz;
+    frag_normal = mat3(transpose(inverse(ubo.model))) * in_normal;
 }
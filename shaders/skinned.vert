@@ -0,0 +1,49 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+// must match skinning::MAX_JOINTS
+#define MAX_JOINTS 64
+
+layout(set = 0, binding = 0) uniform UniformBufferObject {
+    mat4 model;
+    mat4 view;
+    mat4 proj;
+} ubo;
+
+// skinning::BONES_BINDING, one palette per mesh selected with a dynamic offset
+layout(set = 0, binding = 3) uniform BonePalette {
+    mat4 joints[MAX_JOINTS];
+} bones;
+
+layout(location = 0) in vec3 in_position;
+layout(location = 1) in vec3 in_color;
+layout(location = 2) in vec2 in_tex_coord;
+layout(location = 3) in vec3 in_normal;
+layout(location = 4) in uvec4 in_joints;
+layout(location = 5) in vec4 in_weights;
+
+layout(location = 0) out vec3 frag_color;
+layout(location = 1) out vec2 frag_tex_coord;
+layout(location = 2) out vec3 frag_position;
+layout(location = 3) out vec3 frag_normal;
+
+out gl_PerVertex {
+    vec4 gl_Position;
+};
+
+
+void main() {
+    mat4 skin = in_weights.x * bones.joints[in_joints.x]
+        + in_weights.y * bones.joints[in_joints.y]
+        + in_weights.z * bones.joints[in_joints.z]
+        + in_weights.w * bones.joints[in_joints.w];
+
+    mat4 model = ubo.model * skin;
+    vec4 world_position = model * vec4(in_position, 1.0);
+
+    gl_Position = ubo.proj * ubo.view * world_position;
+    frag_color = in_color;
+    frag_tex_coord = in_tex_coord;
+    frag_position = world_position.xyz;
+    frag_normal = mat3(transpose(inverse(model))) * in_normal;
+}
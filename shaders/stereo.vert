@@ -0,0 +1,41 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_EXT_multiview : enable
+
+// shaders/shader.vert with the camera of the eye being rendered, see stereo::StereoPass
+
+layout(set = 0, binding = 0) uniform UniformBufferObject {
+    mat4 model;
+    mat4 view;
+    mat4 proj;
+} ubo;
+
+// must match stereo::EyeConstants
+layout(push_constant) uniform Eyes {
+    mat4 view_projection[2];
+} eyes;
+
+layout(location = 0) in vec3 in_position;
+layout(location = 1) in vec3 in_color;
+layout(location = 2) in vec2 in_tex_coord;
+layout(location = 3) in vec3 in_normal;
+
+layout(location = 0) out vec3 frag_color;
+layout(location = 1) out vec2 frag_tex_coord;
+layout(location = 2) out vec3 frag_position;
+layout(location = 3) out vec3 frag_normal;
+
+out gl_PerVertex {
+    vec4 gl_Position;
+};
+
+
+void main() {
+    vec4 world_position = ubo.model * vec4(in_position, 1.0);
+
+    gl_Position = eyes.view_projection[gl_ViewIndex] * world_position;
+    frag_color = in_color;
+    frag_tex_coord = in_tex_coord;
+    frag_position = world_position.xyz;
+    frag_normal = mat3(transpose(inverse(ubo.model))) * in_normal;
+}
@@ -0,0 +1,85 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+// must match lighting::MAX_LIGHTS
+#define MAX_LIGHTS 8
+
+// the order of terrain::SPLAT_SLOTS
+#define SLOT_SPLAT_MAP 0
+#define SLOT_FIRST_LAYER 1
+#define LAYER_COUNT 3
+
+// times the layers repeat across the terrain, the splat map spans it once
+#define LAYER_TILING 32.0
+
+struct Light {
+    // w: 0 for directional, 1 for point lights
+    vec4 position;
+    // a: intensity
+    vec4 color;
+    // constant, linear, quadratic, range
+    vec4 attenuation;
+};
+
+layout(binding = 2) uniform LightBlock {
+    // a: shininess, unused here
+    vec4 ambient;
+    vec4 camera_position;
+    uvec4 light_count;
+    Light lights[MAX_LIGHTS];
+} light_block;
+
+// the texture array material layout, see material::MaterialLayoutKind
+layout(set = 1, binding = 0) uniform sampler2D material_textures[4];
+
+layout(location = 0) in vec3 frag_color;
+layout(location = 1) in vec2 frag_tex_coord;
+layout(location = 2) in vec3 frag_position;
+layout(location = 3) in vec3 frag_normal;
+
+layout(location = 0) out vec4 out_color;
+
+// lambert diffuse contribution of a single light
+vec3 shade(Light light, vec3 normal, vec3 albedo) {
+    vec3 light_dir;
+    float attenuation = 1.0;
+
+    if (light.position.w == 0.0) {
+        light_dir = normalize(-light.position.xyz);
+    } else {
+        vec3 to_light = light.position.xyz - frag_position;
+        float distance = length(to_light);
+        if (distance > light.attenuation.w) {
+            return vec3(0.0);
+        }
+
+        light_dir = to_light / distance;
+        attenuation = 1.0 / (light.attenuation.x
+            + light.attenuation.y * distance
+            + light.attenuation.z * distance * distance);
+    }
+
+    vec3 radiance = light.color.rgb * light.color.a * attenuation;
+    return albedo * max(dot(normal, light_dir), 0.0) * radiance;
+}
+
+void main() {
+    vec3 weights = texture(material_textures[SLOT_SPLAT_MAP], frag_tex_coord).rgb;
+    // unpainted areas show the first layer
+    float total = weights.r + weights.g + weights.b;
+    weights = total > 0.0 ? weights / total : vec3(1.0, 0.0, 0.0);
+
+    vec2 layer_coord = frag_tex_coord * LAYER_TILING;
+    vec3 albedo = vec3(0.0);
+    for (int i = 0; i < LAYER_COUNT; i++) {
+        albedo += texture(material_textures[SLOT_FIRST_LAYER + i], layer_coord).rgb * weights[i];
+    }
+
+    vec3 normal = normalize(frag_normal);
+    vec3 color = light_block.ambient.rgb * albedo;
+    for (uint i = 0; i < min(light_block.light_count.x, MAX_LIGHTS); i++) {
+        color += shade(light_block.lights[i], normal, albedo);
+    }
+
+    out_color = vec4(color, 1.0);
+}
@@ -0,0 +1,657 @@
+// Skeletal animation on the cpu: skeletons, keyframed clips sampled into poses and a
+// player cross fading between clips. The joint matrices it produces are uploaded with
+// vulkan::skinning::BoneBuffer and applied by shaders/skinned.vert.
+
+use cgmath::{InnerSpace, Matrix4, One, Quaternion, Vector3, VectorSpace};
+
+use crate::error::{Error, Result};
+
+// Transform of a joint relative to its parent
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct JointPose {
+    pub translation: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+    pub scale: Vector3<f32>,
+}
+
+impl JointPose {
+    pub fn identity() -> JointPose {
+        JointPose {
+            translation: Vector3::new(0.0, 0.0, 0.0),
+            rotation: Quaternion::one(),
+            scale: Vector3::new(1.0, 1.0, 1.0),
+        }
+    }
+
+    pub fn matrix(&self) -> Matrix4<f32> {
+        Matrix4::from_translation(self.translation)
+            * Matrix4::from(self.rotation)
+            * Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+
+    // weight 0 is self, 1 is other
+    pub fn blend(&self, other: &JointPose, weight: f32) -> JointPose {
+        JointPose {
+            translation: self.translation.lerp(other.translation, weight),
+            rotation: blend_rotation(self.rotation, other.rotation, weight),
+            scale: self.scale.lerp(other.scale, weight),
+        }
+    }
+}
+
+// Takes the shorter way around, q and -q are the same rotation
+fn blend_rotation(from: Quaternion<f32>, to: Quaternion<f32>, weight: f32) -> Quaternion<f32> {
+    let to = if from.dot(to) < 0.0 { -to } else { to };
+    from.nlerp(to, weight)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Joint {
+    pub name: String,
+    // index into Skeleton::joints
+    pub parent: Option<usize>,
+    // from model space to the joint's space in the bind pose
+    pub inverse_bind: Matrix4<f32>,
+    // used for the parts of the pose no clip animates
+    pub rest: JointPose,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Skeleton {
+    pub joints: Vec<Joint>,
+    // applied to the joints without a parent, eg. the nodes above the skeleton in a file
+    pub transform: Matrix4<f32>,
+}
+
+// Local transforms of every joint of a skeleton
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pose {
+    pub joints: Vec<JointPose>,
+}
+
+impl Pose {
+    // weight 0 is self, 1 is other. Both poses have to be of the same skeleton.
+    pub fn blend(&self, other: &Pose, weight: f32) -> Pose {
+        Pose {
+            joints: self
+                .joints
+                .iter()
+                .zip(other.joints.iter())
+                .map(|(a, b)| a.blend(b, weight))
+                .collect(),
+        }
+    }
+}
+
+impl Skeleton {
+    // Parents can be listed after their children, but the hierarchy cannot have cycles
+    pub fn new(joints: Vec<Joint>) -> Result<Skeleton> {
+        for (index, joint) in joints.iter().enumerate() {
+            let mut parent = joint.parent;
+            let mut depth = 0;
+
+            while let Some(current) = parent {
+                if current >= joints.len() {
+                    return Err(Error::OutOfRange(format!(
+                        "joint {} has parent {}, the skeleton has {} joints",
+                        index,
+                        current,
+                        joints.len()
+                    )));
+                }
+
+                depth += 1;
+                if depth > joints.len() {
+                    return Err(Error::msg(format!(
+                        "joint {} is part of a cycle in the skeleton",
+                        index
+                    )));
+                }
+                parent = joints[current].parent;
+            }
+        }
+
+        Ok(Skeleton {
+            joints,
+            transform: Matrix4::one(),
+        })
+    }
+
+    pub fn with_transform(mut self, transform: Matrix4<f32>) -> Skeleton {
+        self.transform = transform;
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.joints.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.joints.is_empty()
+    }
+
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.joints.iter().position(|joint| joint.name == name)
+    }
+
+    pub fn rest_pose(&self) -> Pose {
+        Pose {
+            joints: self.joints.iter().map(|joint| joint.rest).collect(),
+        }
+    }
+
+    fn global_transform(
+        &self,
+        index: usize,
+        pose: &Pose,
+        globals: &mut Vec<Option<Matrix4<f32>>>,
+    ) -> Matrix4<f32> {
+        if let Some(global) = globals[index] {
+            return global;
+        }
+
+        let local = pose.joints[index].matrix();
+        let global = match self.joints[index].parent {
+            Some(parent) => self.global_transform(parent, pose, globals) * local,
+            None => self.transform * local,
+        };
+
+        globals[index] = Some(global);
+        global
+    }
+
+    // Model space transform of every joint
+    pub fn global_transforms(&self, pose: &Pose) -> Vec<Matrix4<f32>> {
+        let mut globals = vec![None; self.joints.len()];
+
+        (0..self.joints.len())
+            .map(|index| self.global_transform(index, pose, &mut globals))
+            .collect()
+    }
+
+    // What the vertices bound to each joint are transformed with, moves them from the
+    // bind pose to the pose
+    pub fn joint_matrices(&self, pose: &Pose) -> Vec<Matrix4<f32>> {
+        self.global_transforms(pose)
+            .into_iter()
+            .zip(self.joints.iter())
+            .map(|(global, joint)| global * joint.inverse_bind)
+            .collect()
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Interpolation {
+    // holds each key until the next one
+    Step,
+    Linear,
+}
+
+// One value per key time
+#[derive(Debug, Clone, PartialEq)]
+pub enum Keyframes {
+    Translation(Vec<Vector3<f32>>),
+    Rotation(Vec<Quaternion<f32>>),
+    Scale(Vec<Vector3<f32>>),
+}
+
+impl Keyframes {
+    pub fn len(&self) -> usize {
+        match self {
+            Keyframes::Translation(values) | Keyframes::Scale(values) => values.len(),
+            Keyframes::Rotation(values) => values.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+// Animates one property of a joint
+#[derive(Debug, Clone, PartialEq)]
+pub struct Channel {
+    pub joint: usize,
+    // in seconds, ascending
+    pub times: Vec<f32>,
+    pub keyframes: Keyframes,
+    pub interpolation: Interpolation,
+}
+
+impl Channel {
+    pub fn new(
+        joint: usize,
+        times: Vec<f32>,
+        keyframes: Keyframes,
+        interpolation: Interpolation,
+    ) -> Result<Channel> {
+        if times.is_empty() || times.len() != keyframes.len() {
+            return Err(Error::msg(format!(
+                "channel of joint {} has {} key times for {} values",
+                joint,
+                times.len(),
+                keyframes.len()
+            )));
+        }
+
+        Ok(Channel {
+            joint,
+            times,
+            keyframes,
+            interpolation,
+        })
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.times.last().cloned().unwrap_or(0.0)
+    }
+
+    // The keys around the time and how far it is from the first to the second one
+    fn segment(&self, time: f32) -> (usize, usize, f32) {
+        let last = self.times.len() - 1;
+
+        if time <= self.times[0] {
+            return (0, 0, 0.0);
+        }
+        if time >= self.times[last] {
+            return (last, last, 0.0);
+        }
+
+        let next = self
+            .times
+            .iter()
+            .position(|&key| key > time)
+            .unwrap_or(last);
+        let previous = next - 1;
+        let span = self.times[next] - self.times[previous];
+
+        let weight = match self.interpolation {
+            Interpolation::Step => 0.0,
+            Interpolation::Linear if span > 0.0 => (time - self.times[previous]) / span,
+            Interpolation::Linear => 0.0,
+        };
+
+        (previous, next, weight)
+    }
+
+    pub fn apply(&self, time: f32, pose: &mut JointPose) {
+        let (previous, next, weight) = self.segment(time);
+
+        match &self.keyframes {
+            Keyframes::Translation(values) => {
+                pose.translation = values[previous].lerp(values[next], weight)
+            }
+            Keyframes::Rotation(values) => {
+                pose.rotation = blend_rotation(values[previous], values[next], weight)
+            }
+            Keyframes::Scale(values) => pose.scale = values[previous].lerp(values[next], weight),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnimationClip {
+    pub name: String,
+    // in seconds, the last key time of the channels
+    pub duration: f32,
+    pub channels: Vec<Channel>,
+}
+
+impl AnimationClip {
+    pub fn new(name: &str, channels: Vec<Channel>) -> AnimationClip {
+        let duration = channels.iter().map(Channel::duration).fold(0.0, f32::max);
+
+        AnimationClip {
+            name: name.to_string(),
+            duration,
+            channels,
+        }
+    }
+
+    // Joints without a channel keep their rest pose, channels of joints the skeleton
+    // does not have are ignored
+    pub fn sample(&self, skeleton: &Skeleton, time: f32) -> Pose {
+        let mut pose = skeleton.rest_pose();
+
+        for channel in self.channels.iter() {
+            if let Some(joint) = pose.joints.get_mut(channel.joint) {
+                channel.apply(time, joint);
+            }
+        }
+
+        pose
+    }
+}
+
+// Channels of a NodeAnimation's clips animate this joint, the node itself
+pub const NODE_JOINT: usize = 0;
+
+// Keyframed rigid transform of one node, eg. a door swinging open or a camera moving
+// along a path. Played like a skeleton of a single joint, see scene::Animator.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeAnimation {
+    pub name: String,
+    // used for the properties no clip animates
+    pub rest: JointPose,
+    pub clips: Vec<AnimationClip>,
+}
+
+impl NodeAnimation {
+    pub fn new(name: &str, rest: JointPose) -> NodeAnimation {
+        NodeAnimation {
+            name: name.to_string(),
+            rest,
+            clips: vec![],
+        }
+    }
+
+    pub fn with_clip(mut self, clip: AnimationClip) -> NodeAnimation {
+        self.clips.push(clip);
+        self
+    }
+
+    pub fn find_clip(&self, name: &str) -> Option<usize> {
+        self.clips.iter().position(|clip| clip.name == name)
+    }
+
+    pub fn skeleton(&self) -> Skeleton {
+        Skeleton {
+            joints: vec![Joint {
+                name: self.name.clone(),
+                parent: None,
+                inverse_bind: Matrix4::one(),
+                rest: self.rest,
+            }],
+            transform: Matrix4::one(),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Playback {
+    clip: usize,
+    time: f32,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Fade {
+    from: Playback,
+    elapsed: f32,
+    duration: f32,
+}
+
+// Plays clips of a skeleton, advanced once per frame. Starting a clip with cross_fade
+// blends from the current one over the fade's duration while both keep playing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnimationPlayer {
+    current: Option<Playback>,
+    fade: Option<Fade>,
+    pub speed: f32,
+    // clips start over at their end, otherwise they hold their last pose
+    pub looping: bool,
+}
+
+impl Default for AnimationPlayer {
+    fn default() -> AnimationPlayer {
+        AnimationPlayer {
+            current: None,
+            fade: None,
+            speed: 1.0,
+            looping: true,
+        }
+    }
+}
+
+impl AnimationPlayer {
+    pub fn new() -> AnimationPlayer {
+        AnimationPlayer::default()
+    }
+
+    // Switches to the clip right away
+    pub fn play(&mut self, clip: usize) {
+        self.current = Some(Playback { clip, time: 0.0 });
+        self.fade = None;
+    }
+
+    pub fn cross_fade(&mut self, clip: usize, duration: f32) {
+        self.fade = match self.current {
+            Some(from) if duration > 0.0 => Some(Fade {
+                from,
+                elapsed: 0.0,
+                duration,
+            }),
+            _ => None,
+        };
+        self.current = Some(Playback { clip, time: 0.0 });
+    }
+
+    pub fn stop(&mut self) {
+        self.current = None;
+        self.fade = None;
+    }
+
+    pub fn current_clip(&self) -> Option<usize> {
+        self.current.map(|playback| playback.clip)
+    }
+
+    pub fn time(&self) -> f32 {
+        self.current.map(|playback| playback.time).unwrap_or(0.0)
+    }
+
+    fn advance_playback(&self, playback: &mut Playback, clips: &[AnimationClip], delta: f32) {
+        let duration = clips
+            .get(playback.clip)
+            .map(|clip| clip.duration)
+            .unwrap_or(0.0);
+        let time = playback.time + delta * self.speed;
+
+        playback.time = if duration <= 0.0 {
+            0.0
+        } else if self.looping {
+            time.rem_euclid(duration)
+        } else {
+            time.max(0.0).min(duration)
+        };
+    }
+
+    pub fn advance(&mut self, clips: &[AnimationClip], delta_time: f32) {
+        let mut current = self.current;
+        if let Some(playback) = current.as_mut() {
+            self.advance_playback(playback, clips, delta_time);
+        }
+
+        let mut fade = self.fade;
+        if let Some(fade) = fade.as_mut() {
+            self.advance_playback(&mut fade.from, clips, delta_time);
+            fade.elapsed += delta_time;
+        }
+
+        self.current = current;
+        self.fade = fade.filter(|fade| fade.elapsed < fade.duration);
+    }
+
+    // Whether a clip that does not loop has reached its end
+    pub fn is_finished(&self, clips: &[AnimationClip]) -> bool {
+        match self.current {
+            Some(playback) => {
+                !self.looping
+                    && clips
+                        .get(playback.clip)
+                        .map(|clip| playback.time >= clip.duration)
+                        .unwrap_or(true)
+            }
+            None => true,
+        }
+    }
+
+    fn sample(playback: &Playback, skeleton: &Skeleton, clips: &[AnimationClip]) -> Pose {
+        clips
+            .get(playback.clip)
+            .map(|clip| clip.sample(skeleton, playback.time))
+            .unwrap_or_else(|| skeleton.rest_pose())
+    }
+
+    // The rest pose while no clip is playing
+    pub fn pose(&self, skeleton: &Skeleton, clips: &[AnimationClip]) -> Pose {
+        let current = match self.current.as_ref() {
+            Some(playback) => AnimationPlayer::sample(playback, skeleton, clips),
+            None => return skeleton.rest_pose(),
+        };
+
+        match self.fade.as_ref() {
+            Some(fade) => AnimationPlayer::sample(&fade.from, skeleton, clips)
+                .blend(&current, fade.elapsed / fade.duration),
+            None => current,
+        }
+    }
+
+    pub fn joint_matrices(
+        &self,
+        skeleton: &Skeleton,
+        clips: &[AnimationClip],
+    ) -> Vec<Matrix4<f32>> {
+        skeleton.joint_matrices(&self.pose(skeleton, clips))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use cgmath::{Deg, Rotation3, SquareMatrix, Vector4};
+
+    // a root at the origin and a child one unit up the y axis
+    fn arm() -> Skeleton {
+        let child_rest = JointPose {
+            translation: Vector3::new(0.0, 1.0, 0.0),
+            ..JointPose::identity()
+        };
+
+        Skeleton::new(vec![
+            Joint {
+                name: "root".to_string(),
+                parent: None,
+                inverse_bind: Matrix4::one(),
+                rest: JointPose::identity(),
+            },
+            Joint {
+                name: "child".to_string(),
+                parent: Some(0),
+                inverse_bind: child_rest.matrix().invert().unwrap(),
+                rest: child_rest,
+            },
+        ])
+        .unwrap()
+    }
+
+    fn slide(to: f32) -> AnimationClip {
+        let channel = Channel::new(
+            0,
+            vec![0.0, 1.0],
+            Keyframes::Translation(vec![
+                Vector3::new(0.0, 0.0, 0.0),
+                Vector3::new(to, 0.0, 0.0),
+            ]),
+            Interpolation::Linear,
+        )
+        .unwrap();
+
+        AnimationClip::new("slide", vec![channel])
+    }
+
+    fn assert_near(actual: Vector4<f32>, expected: Vector4<f32>) {
+        assert!(
+            (actual - expected).magnitude() < 1e-5,
+            "{:?} != {:?}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn rest_pose_leaves_vertices_in_place() {
+        let skeleton = arm();
+        let matrices = skeleton.joint_matrices(&skeleton.rest_pose());
+
+        let vertex = Vector4::new(0.5, 1.5, 0.0, 1.0);
+        for matrix in matrices.iter() {
+            assert_near(matrix * vertex, vertex);
+        }
+    }
+
+    #[test]
+    fn children_follow_their_parent() {
+        let skeleton = arm();
+        let mut pose = skeleton.rest_pose();
+        pose.joints[0].rotation = Quaternion::from_angle_z(Deg(90.0));
+
+        let globals = skeleton.global_transforms(&pose);
+        // the child's origin swings from +y to -x
+        assert_near(
+            globals[1] * Vector4::new(0.0, 0.0, 0.0, 1.0),
+            Vector4::new(-1.0, 0.0, 0.0, 1.0),
+        );
+
+        let cycle = vec![
+            Joint {
+                parent: Some(1),
+                ..skeleton.joints[0].clone()
+            },
+            skeleton.joints[1].clone(),
+        ];
+        assert!(Skeleton::new(cycle).is_err());
+    }
+
+    #[test]
+    fn channels_interpolate_between_keys() {
+        let skeleton = arm();
+        let clip = slide(2.0);
+
+        assert_eq!(clip.duration, 1.0);
+        assert_eq!(clip.sample(&skeleton, 0.25).joints[0].translation.x, 0.5);
+        // clamped outside of the keys
+        assert_eq!(clip.sample(&skeleton, 4.0).joints[0].translation.x, 2.0);
+        // the child keeps its rest pose
+        assert_eq!(
+            clip.sample(&skeleton, 0.5).joints[1],
+            skeleton.joints[1].rest
+        );
+
+        let step = AnimationClip::new(
+            "step",
+            vec![Channel {
+                interpolation: Interpolation::Step,
+                ..clip.channels[0].clone()
+            }],
+        );
+        assert_eq!(step.sample(&skeleton, 0.75).joints[0].translation.x, 0.0);
+    }
+
+    #[test]
+    fn player_loops_and_cross_fades() {
+        let skeleton = arm();
+        let clips = vec![slide(2.0), slide(-2.0)];
+
+        let mut player = AnimationPlayer::new();
+        player.play(0);
+        player.advance(&clips, 1.25);
+        assert!((player.time() - 0.25).abs() < 1e-6);
+
+        player.cross_fade(1, 1.0);
+        player.advance(&clips, 0.5);
+        // halfway between 1.5 of the first clip and -1.0 of the second
+        let x = player.pose(&skeleton, &clips).joints[0].translation.x;
+        assert!((x - 0.25).abs() < 1e-5, "{}", x);
+
+        player.advance(&clips, 0.5);
+        assert_eq!(player.current_clip(), Some(1));
+        assert_eq!(
+            player.pose(&skeleton, &clips),
+            clips[1].sample(&skeleton, 0.0)
+        );
+
+        player.looping = false;
+        player.advance(&clips, 5.0);
+        assert!(player.is_finished(&clips));
+    }
+}
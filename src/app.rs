@@ -1,8 +1,7 @@
-use super::vulkan::{buffers, pipeline};
+use super::projection;
+use super::vulkan::{buffers, device, pipeline, vertex};
 use ash::vk;
 
-use memoffset::offset_of;
-
 use cgmath::{Deg, Matrix4, Point3, Vector3};
 
 #[repr(C)]
@@ -11,6 +10,7 @@ pub struct VertexData {
     pub pos: [f32; 3],
     pub color: [f32; 3],
     pub tex_coord: [f32; 2],
+    pub normal: [f32; 3],
 }
 
 pub const VERTICES: [VertexData; 8] = [
@@ -18,80 +18,168 @@ pub const VERTICES: [VertexData; 8] = [
         pos: [-0.75, -0.75, 0.0],
         color: [1.0, 0.0, 0.0],
         tex_coord: [0.0, 0.0],
+        normal: [0.0, 0.0, 1.0],
     },
     VertexData {
         pos: [0.75, -0.75, 0.0],
         color: [0.0, 1.0, 0.0],
         tex_coord: [1.0, 0.0],
+        normal: [0.0, 0.0, 1.0],
     },
     VertexData {
         pos: [0.75, 0.75, 0.0],
         color: [0.0, 0.0, 1.0],
         tex_coord: [1.0, 1.0],
+        normal: [0.0, 0.0, 1.0],
     },
     VertexData {
         pos: [-0.75, 0.75, 0.0],
         color: [1.0, 1.0, 1.0],
         tex_coord: [0.0, 1.0],
+        normal: [0.0, 0.0, 1.0],
     },
     VertexData {
         pos: [-0.75, -0.75, -0.75],
         color: [1.0, 0.0, 0.0],
         tex_coord: [0.0, 0.0],
+        normal: [0.0, 0.0, 1.0],
     },
     VertexData {
         pos: [0.75, -0.75, -0.75],
         color: [0.0, 1.0, 0.0],
         tex_coord: [1.0, 0.0],
+        normal: [0.0, 0.0, 1.0],
     },
     VertexData {
         pos: [0.75, 0.75, -0.75],
         color: [0.0, 0.0, 1.0],
         tex_coord: [1.0, 1.0],
+        normal: [0.0, 0.0, 1.0],
     },
     VertexData {
         pos: [-0.75, 0.75, -0.75],
         color: [1.0, 1.0, 1.0],
         tex_coord: [0.0, 1.0],
+        normal: [0.0, 0.0, 1.0],
     },
 ];
 
 pub const INDICES: [u32; 12] = [0, 1, 2, 2, 3, 0, 4, 5, 6, 6, 7, 4];
 
-impl pipeline::VertexData for VertexData {
+crate::impl_vertex_data!(VertexData {
+    pos,
+    color,
+    tex_coord,
+    normal
+});
+
+// VertexData in 20 instead of 44 bytes, read by the same shaders: half float positions
+// and texture coordinates, 8 bit colors and 10 bit normals
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct CompressedVertexData {
+    // w is 1
+    pub pos: [vertex::Half; 4],
+    // alpha is 1
+    pub color: [u8; 4],
+    pub tex_coord: [vertex::Half; 2],
+    pub normal: vertex::PackedNormal,
+}
+
+impl CompressedVertexData {
+    pub fn new(vertex: &VertexData) -> CompressedVertexData {
+        let half = vertex::Half::from_f32;
+        let unorm = |component: f32| (component.max(0.0).min(1.0) * 255.0).round() as u8;
+
+        CompressedVertexData {
+            pos: [
+                half(vertex.pos[0]),
+                half(vertex.pos[1]),
+                half(vertex.pos[2]),
+                half(1.0),
+            ],
+            color: [
+                unorm(vertex.color[0]),
+                unorm(vertex.color[1]),
+                unorm(vertex.color[2]),
+                255,
+            ],
+            tex_coord: [half(vertex.tex_coord[0]), half(vertex.tex_coord[1])],
+            normal: vertex::PackedNormal::new(vertex.normal),
+        }
+    }
+
+    // The vertex as the shaders read it
+    pub fn decompress(&self) -> VertexData {
+        let float = |half: vertex::Half| half.to_f32();
+        let unorm = |component: u8| component as f32 / 255.0;
+
+        VertexData {
+            pos: [float(self.pos[0]), float(self.pos[1]), float(self.pos[2])],
+            color: [
+                unorm(self.color[0]),
+                unorm(self.color[1]),
+                unorm(self.color[2]),
+            ],
+            tex_coord: [float(self.tex_coord[0]), float(self.tex_coord[1])],
+            normal: self.normal.unpack(),
+        }
+    }
+}
+
+crate::impl_vertex_data!(CompressedVertexData {
+    pos,
+    color,
+    tex_coord,
+    normal
+});
+
+pub fn compress_vertices(vertices: &[VertexData]) -> Vec<CompressedVertexData> {
+    vertices.iter().map(CompressedVertexData::new).collect()
+}
+
+// How the engine stores the vertices of its meshes, see EngineConfig::vertex_format
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum VertexFormat {
+    Full,
+    // CompressedVertexData, half the memory and bandwidth for a loss of precision. Far
+    // from the origin positions are only accurate to a fraction of a unit.
+    Compressed,
+}
+
+impl VertexFormat {
+    pub fn vertex_size(&self) -> usize {
+        match self {
+            VertexFormat::Full => std::mem::size_of::<VertexData>(),
+            VertexFormat::Compressed => std::mem::size_of::<CompressedVertexData>(),
+        }
+    }
+
+    // Every attribute format can be read from vertex buffers
+    pub fn is_supported(&self, instance: &ash::Instance, device: &device::Device) -> bool {
+        pipeline::VertexData::get_attribute_description(self)
+            .iter()
+            .all(|attribute| device.supports_vertex_format(instance, attribute.format))
+    }
+}
+
+impl pipeline::VertexData for VertexFormat {
     fn get_input_binding_description(&self) -> Vec<vk::VertexInputBindingDescription> {
-        // All of our per-vertex data is packed together in one array,
-        // so we're only going to have one binding.
-        [vk::VertexInputBindingDescription {
-            binding: 0,
-            stride: ::std::mem::size_of::<VertexData>() as u32,
-            input_rate: vk::VertexInputRate::VERTEX,
-        }]
-        .to_vec()
+        match self {
+            VertexFormat::Full => pipeline::VertexData::get_input_binding_description(&VERTICES[0]),
+            VertexFormat::Compressed => pipeline::VertexData::get_input_binding_description(
+                &CompressedVertexData::new(&VERTICES[0]),
+            ),
+        }
     }
 
     fn get_attribute_description(&self) -> Vec<vk::VertexInputAttributeDescription> {
-        [
-            vk::VertexInputAttributeDescription {
-                binding: 0,
-                location: 0,
-                format: vk::Format::R32G32B32_SFLOAT,
-                offset: offset_of!(VertexData, pos) as u32,
-            },
-            vk::VertexInputAttributeDescription {
-                binding: 0,
-                location: 1,
-                format: vk::Format::R32G32B32_SFLOAT,
-                offset: offset_of!(VertexData, color) as u32,
-            },
-            vk::VertexInputAttributeDescription {
-                binding: 0,
-                location: 2,
-                format: vk::Format::R32G32_SFLOAT,
-                offset: offset_of!(VertexData, tex_coord) as u32,
-            },
-        ]
-        .to_vec()
+        match self {
+            VertexFormat::Full => pipeline::VertexData::get_attribute_description(&VERTICES[0]),
+            VertexFormat::Compressed => pipeline::VertexData::get_attribute_description(
+                &CompressedVertexData::new(&VERTICES[0]),
+            ),
+        }
     }
 }
 
@@ -105,15 +193,13 @@ pub struct UniformBuffer {
 
 impl UniformBuffer {
     pub fn new(extent: vk::Extent2D) -> UniformBuffer {
-        let mut projection = cgmath::perspective(
-            Deg(45.0),
-            extent.width as f32 / extent.height as f32,
-            0.1,
-            10.0,
-        );
-
-        projection[1][1] = projection[1][1] * -1.0;
+        UniformBuffer::with_projection(extent, &projection::Projection::default())
+    }
 
+    pub fn with_projection(
+        extent: vk::Extent2D,
+        projection: &projection::Projection,
+    ) -> UniformBuffer {
         UniformBuffer {
             model: Matrix4::from_angle_z(Deg(90.0)),
             view: Matrix4::look_at(
@@ -121,7 +207,7 @@ impl UniformBuffer {
                 Point3::new(0.0, 0.0, 0.0),
                 Vector3::new(0.0, 0.0, 1.0),
             ),
-            proj: projection,
+            proj: projection.matrix(extent.width as f32 / extent.height as f32),
         }
     }
 }
@@ -141,4 +227,8 @@ impl buffers::UniformBuffers for UniformBuffer {
             proj: self.proj,
         }
     }
+
+    fn transforms(&self) -> Option<(Matrix4<f32>, Matrix4<f32>)> {
+        Some((self.model, self.proj * self.view))
+    }
 }
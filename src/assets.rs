@@ -0,0 +1,365 @@
+// Source files of the assets loaded at runtime, polled for changes so edited textures,
+// models and shaders are imported again while the engine runs, see
+// EngineConfig::hot_reload. Polling the modification times keeps this free of a native
+// file watcher, a handful of metadata calls a few times a second is cheap.
+
+use ash::vk;
+
+use crate::app;
+use crate::obj;
+use crate::vulkan::{gc, mesh_pool, registry};
+
+use std::collections::VecDeque;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+// What is replaced when the file changes
+#[derive(Debug, Clone, PartialEq)]
+pub enum AssetKind {
+    // the texture of the scene set, EngineConfig::texture_file
+    SceneTexture,
+    // either source of the scene pipeline, the pipeline is rebuilt from both
+    SceneShader,
+    // keeps its handle, descriptors written with the old texture have to be written again
+    Texture(registry::TextureHandle),
+    // the meshes of an OBJ file, mesh renderers drawing them are moved to the new ones
+    Model(Vec<mesh_pool::MeshAllocation>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssetChange {
+    pub path: PathBuf,
+    pub kind: AssetKind,
+}
+
+struct WatchedAsset {
+    path: PathBuf,
+    kind: AssetKind,
+    // None while the file does not exist
+    modified: Option<SystemTime>,
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+pub struct AssetRegistry {
+    assets: Vec<WatchedAsset>,
+    poll_interval: Duration,
+    last_poll: Option<Instant>,
+}
+
+impl AssetRegistry {
+    pub fn new() -> AssetRegistry {
+        AssetRegistry {
+            assets: vec![],
+            poll_interval: DEFAULT_POLL_INTERVAL,
+            last_poll: None,
+        }
+    }
+
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> AssetRegistry {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    // The current state of the file is the baseline, only later changes are reported
+    pub fn watch<P: Into<PathBuf>>(&mut self, path: P, kind: AssetKind) {
+        let path = path.into();
+        let modified = modified_time(&path);
+
+        self.assets.push(WatchedAsset {
+            path,
+            kind,
+            modified,
+        });
+    }
+
+    // Stops watching the texture, eg. once it was destroyed
+    pub fn unwatch_texture(&mut self, handle: registry::TextureHandle) {
+        self.assets
+            .retain(|asset| asset.kind != AssetKind::Texture(handle));
+    }
+
+    // Stops watching the model the mesh belongs to
+    pub fn unwatch_mesh(&mut self, mesh: &mesh_pool::MeshAllocation) {
+        self.assets.retain(|asset| match asset.kind {
+            AssetKind::Model(ref meshes) => !meshes.contains(mesh),
+            _ => true,
+        });
+    }
+
+    // Follows the meshes of watched models to where they were loaded again
+    pub fn move_meshes(
+        &mut self,
+        old_meshes: &[mesh_pool::MeshAllocation],
+        meshes: &[mesh_pool::MeshAllocation],
+    ) {
+        for asset in self.assets.iter_mut() {
+            if let AssetKind::Model(ref mut model_meshes) = asset.kind {
+                for mesh in model_meshes.iter_mut() {
+                    if let Some(position) = old_meshes.iter().position(|old| old == mesh) {
+                        *mesh = meshes[position];
+                    }
+                }
+            }
+        }
+    }
+
+    // Follows a reload of the model at the path
+    pub fn set_model_meshes(&mut self, path: &Path, meshes: Vec<mesh_pool::MeshAllocation>) {
+        for asset in self.assets.iter_mut().filter(|asset| asset.path == path) {
+            if let AssetKind::Model(_) = asset.kind {
+                asset.kind = AssetKind::Model(meshes.clone());
+            }
+        }
+    }
+
+    pub fn paths(&self) -> impl Iterator<Item = &Path> {
+        self.assets.iter().map(|asset| asset.path.as_path())
+    }
+
+    pub fn len(&self) -> usize {
+        self.assets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.assets.is_empty()
+    }
+
+    // The assets whose file was written since the last poll, at most once per poll
+    // interval. A file that is missing, eg. while an editor replaces it, is reported
+    // once it is back.
+    pub fn poll(&mut self, now: Instant) -> Vec<AssetChange> {
+        if let Some(last_poll) = self.last_poll {
+            if now.duration_since(last_poll) < self.poll_interval {
+                return vec![];
+            }
+        }
+        self.last_poll = Some(now);
+
+        self.assets
+            .iter_mut()
+            .filter_map(|asset| {
+                let modified = modified_time(&asset.path)?;
+                if asset.modified == Some(modified) {
+                    return None;
+                }
+
+                asset.modified = Some(modified);
+                Some(AssetChange {
+                    path: asset.path.clone(),
+                    kind: asset.kind.clone(),
+                })
+            })
+            .collect()
+    }
+}
+
+// A mesh loaded through the engine with the data it was uploaded from
+#[derive(Debug, Clone)]
+pub struct LoadedMesh {
+    pub allocation: mesh_pool::MeshAllocation,
+    pub vertices: Vec<app::VertexData>,
+    pub indices: Vec<u32>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BufferDescription {
+    pub size: vk::DeviceSize,
+    pub usage: vk::BufferUsageFlags,
+    pub memory_properties: vk::MemoryPropertyFlags,
+}
+
+// What the gpu resources created through the engine were made from, kept on the cpu to
+// create them again after the device was lost, see Engine::recover_device. Buffers are
+// only described, their contents are the application's to upload again.
+#[derive(Debug, Clone, Default)]
+pub struct AssetSources {
+    pub meshes: Vec<LoadedMesh>,
+    pub textures: Vec<(registry::TextureHandle, PathBuf)>,
+    pub buffers: Vec<(registry::BufferHandle, BufferDescription)>,
+}
+
+impl AssetSources {
+    pub fn new() -> AssetSources {
+        AssetSources::default()
+    }
+
+    pub fn add_mesh(
+        &mut self,
+        allocation: mesh_pool::MeshAllocation,
+        vertices: &[app::VertexData],
+        indices: &[u32],
+    ) {
+        self.meshes.push(LoadedMesh {
+            allocation,
+            vertices: vertices.to_vec(),
+            indices: indices.to_vec(),
+        });
+    }
+
+    pub fn remove_mesh(&mut self, allocation: &mesh_pool::MeshAllocation) {
+        self.meshes.retain(|mesh| mesh.allocation != *allocation);
+    }
+
+    pub fn add_texture<P: Into<PathBuf>>(&mut self, handle: registry::TextureHandle, path: P) {
+        self.textures.push((handle, path.into()));
+    }
+
+    pub fn remove_texture(&mut self, handle: registry::TextureHandle) {
+        self.textures.retain(|(texture, _)| *texture != handle);
+    }
+
+    pub fn add_buffer(&mut self, handle: registry::BufferHandle, description: BufferDescription) {
+        self.buffers.push((handle, description));
+    }
+
+    pub fn remove_buffer(&mut self, handle: registry::BufferHandle) {
+        self.buffers.retain(|(buffer, _)| *buffer != handle);
+    }
+}
+
+// Models cached by default, see ModelCache
+pub const MODEL_CACHE_CAPACITY: usize = 8;
+
+// Models imported from files, kept so loading the same file again skips parsing it. The
+// least recently used ones are dropped by the garbage collector once there are more than
+// capacity, see gc::Evictable.
+#[derive(Debug, Clone)]
+pub struct ModelCache {
+    // least recently used first
+    models: VecDeque<(PathBuf, obj::ObjModel)>,
+    pub capacity: usize,
+}
+
+impl ModelCache {
+    pub fn new(capacity: usize) -> ModelCache {
+        ModelCache {
+            models: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    pub fn get(&mut self, path: &Path) -> Option<&obj::ObjModel> {
+        let index = self.models.iter().position(|(cached, _)| cached == path)?;
+        let entry = self.models.remove(index)?;
+        self.models.push_back(entry);
+        self.models.back().map(|(_, model)| model)
+    }
+
+    pub fn insert<P: Into<PathBuf>>(&mut self, path: P, model: obj::ObjModel) {
+        let path = path.into();
+        self.remove(&path);
+        self.models.push_back((path, model));
+    }
+
+    // eg. once the file changed
+    pub fn remove(&mut self, path: &Path) {
+        self.models.retain(|(cached, _)| cached != path);
+    }
+
+    pub fn len(&self) -> usize {
+        self.models.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.models.is_empty()
+    }
+
+    fn evict(&mut self) -> bool {
+        self.models.len() > self.capacity && self.models.pop_front().is_some()
+    }
+}
+
+// Models only take cpu memory, the device is not needed to drop them
+impl gc::Evictable for ModelCache {
+    fn evict_next(&mut self, _device: &ash::Device) -> bool {
+        self.evict()
+    }
+
+    fn evict_all(&mut self, _device: &ash::Device) -> usize {
+        let evicted = self.models.len();
+        self.models.clear();
+        evicted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_files_written_after_watching() {
+        let file_name = format!("kelsier-assets-{}.vert", std::process::id());
+        let path = std::env::temp_dir().join(file_name);
+        let _ = fs::remove_file(&path);
+
+        let mut assets = AssetRegistry::new();
+        assets.watch(&path, AssetKind::SceneShader);
+
+        let start = Instant::now();
+        assert!(assets.poll(start).is_empty());
+
+        fs::write(&path, "void main() {}").unwrap();
+        assert!(
+            assets.poll(start).is_empty(),
+            "polled again within the interval"
+        );
+
+        let changes = assets.poll(start + DEFAULT_POLL_INTERVAL);
+        assert_eq!(
+            changes,
+            vec![AssetChange {
+                path: path.clone(),
+                kind: AssetKind::SceneShader,
+            }]
+        );
+        assert!(assets.poll(start + DEFAULT_POLL_INTERVAL * 2).is_empty());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn reloaded_models_keep_being_watched() {
+        let mesh = mesh_pool::MeshAllocation {
+            first_vertex: 0,
+            vertex_count: 3,
+            first_index: 0,
+            index_count: 3,
+        };
+        let moved = mesh_pool::MeshAllocation {
+            first_vertex: 3,
+            ..mesh
+        };
+
+        let mut assets = AssetRegistry::new();
+        assets.watch("model.obj", AssetKind::Model(vec![mesh]));
+        assets.set_model_meshes(Path::new("model.obj"), vec![moved]);
+
+        assets.unwatch_mesh(&mesh);
+        assert_eq!(assets.len(), 1);
+
+        assets.unwatch_mesh(&moved);
+        assert!(assets.is_empty());
+    }
+
+    #[test]
+    fn least_recently_used_models_are_evicted() {
+        let mut cache = ModelCache::new(2);
+        cache.insert("a.obj", obj::ObjModel::default());
+        cache.insert("b.obj", obj::ObjModel::default());
+        cache.insert("c.obj", obj::ObjModel::default());
+        assert!(cache.get(Path::new("a.obj")).is_some());
+
+        assert!(cache.evict());
+        assert!(!cache.evict(), "kept the models within capacity");
+        assert!(cache.get(Path::new("b.obj")).is_none());
+        assert_eq!(cache.len(), 2);
+    }
+}
@@ -0,0 +1,346 @@
+// Renders a generated scene of textured cubes for a fixed number of frames and reports
+// the frame times, to compare renderer changes on the same machine. The cubes are one
+// instanced draw, each instance reads its model matrix from a storage buffer in
+// shaders/benchmark.vert.
+
+use ash::vk;
+
+use cgmath::{Deg, Matrix4, Rad, Vector3};
+
+use crate::app;
+use crate::engine;
+use crate::error::{Error, Result};
+use crate::scene;
+use crate::vulkan::{descriptor, registry};
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+pub const BENCHMARK_VERTEX_SHADER_FILE: &str = "shaders/benchmark.vert";
+
+// The binding after skinning::BONES_BINDING, where shaders/benchmark.vert reads the
+// instance transforms
+pub const INSTANCES_BINDING: u32 = 4;
+
+// world units between the centers of neighbouring cubes
+const CUBE_SPACING: f32 = 2.0;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BenchmarkConfig {
+    pub cubes: u32,
+    // frames rendered before timing starts, eg. while pipelines are compiled
+    pub warmup_frames: u32,
+    pub frames: u32,
+}
+
+impl Default for BenchmarkConfig {
+    fn default() -> BenchmarkConfig {
+        BenchmarkConfig {
+            cubes: 10_000,
+            warmup_frames: 60,
+            frames: 1000,
+        }
+    }
+}
+
+impl BenchmarkConfig {
+    pub fn with_cubes(mut self, cubes: u32) -> BenchmarkConfig {
+        self.cubes = cubes;
+        self
+    }
+
+    pub fn with_warmup_frames(mut self, warmup_frames: u32) -> BenchmarkConfig {
+        self.warmup_frames = warmup_frames;
+        self
+    }
+
+    pub fn with_frames(mut self, frames: u32) -> BenchmarkConfig {
+        self.frames = frames;
+        self
+    }
+
+    // Draws the scene with the instancing shader, has to be applied before the engine
+    // is created
+    pub fn apply(&self, config: &mut engine::EngineConfig) {
+        config.vertex_shader_file = BENCHMARK_VERTEX_SHADER_FILE.to_string();
+        config
+            .scene_buffers
+            .push(descriptor::Binding::storage_buffer(
+                INSTANCES_BINDING,
+                vk::ShaderStageFlags::VERTEX,
+            ));
+    }
+}
+
+// A unit cube with its own vertices per face, so every face has its normal and the whole
+// texture
+pub fn cube_mesh() -> (Vec<app::VertexData>, Vec<u32>) {
+    // normal, then the two axes spanning the face
+    let faces: [([f32; 3], [f32; 3], [f32; 3]); 6] = [
+        ([1.0, 0.0, 0.0], [0.0, 0.0, -1.0], [0.0, 1.0, 0.0]),
+        ([-1.0, 0.0, 0.0], [0.0, 0.0, 1.0], [0.0, 1.0, 0.0]),
+        ([0.0, 1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, -1.0]),
+        ([0.0, -1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0]),
+        ([0.0, 0.0, 1.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+        ([0.0, 0.0, -1.0], [-1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+    ];
+    let corners = [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)];
+
+    let mut vertices = Vec::with_capacity(24);
+    let mut indices = Vec::with_capacity(36);
+    for (normal, u, v) in faces.iter() {
+        let first = vertices.len() as u32;
+
+        for &(s, t) in corners.iter() {
+            let pos = [
+                0.5 * (normal[0] + s * u[0] + t * v[0]),
+                0.5 * (normal[1] + s * u[1] + t * v[1]),
+                0.5 * (normal[2] + s * u[2] + t * v[2]),
+            ];
+
+            vertices.push(app::VertexData {
+                pos,
+                color: [1.0, 1.0, 1.0],
+                tex_coord: [(s + 1.0) * 0.5, (1.0 - t) * 0.5],
+                normal: *normal,
+            });
+        }
+
+        indices.extend_from_slice(&[first, first + 1, first + 2, first + 2, first + 3, first]);
+    }
+
+    (vertices, indices)
+}
+
+// Side length of the cube grid holding count cubes
+fn grid_size(count: u32) -> u32 {
+    (count as f32).cbrt().ceil().max(1.0) as u32
+}
+
+// The cubes fill a grid centered on the origin, each turned differently so the lighting
+// varies across them
+pub fn instance_transforms(count: u32) -> Vec<Matrix4<f32>> {
+    let size = grid_size(count);
+    let offset = (size - 1) as f32 * CUBE_SPACING * 0.5;
+
+    (0..count)
+        .map(|index| {
+            let (x, y, z) = (index % size, index / size % size, index / (size * size));
+            let position = Vector3::new(x as f32, y as f32, z as f32) * CUBE_SPACING
+                - Vector3::new(offset, offset, offset);
+
+            Matrix4::from_translation(position)
+                * Matrix4::from_angle_y(Rad(index as f32 * 0.37))
+                * Matrix4::from_angle_x(Rad(index as f32 * 0.21))
+        })
+        .collect()
+}
+
+// Value below which the fraction of the sorted samples lies, by the nearest rank
+fn percentile(sorted: &[f32], fraction: f32) -> f32 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+
+    let rank = (fraction * sorted.len() as f32).ceil() as usize;
+    sorted[rank.max(1).min(sorted.len()) - 1]
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchmarkReport {
+    pub cubes: u32,
+    pub frames: usize,
+    pub average_ms: f32,
+    pub min_ms: f32,
+    pub p50_ms: f32,
+    pub p95_ms: f32,
+    pub p99_ms: f32,
+    pub max_ms: f32,
+    // average of profiler::FrameStats::gpu_ms, 0 without timestamp queries
+    pub gpu_average_ms: f32,
+}
+
+impl BenchmarkReport {
+    pub fn from_frame_times(
+        cubes: u32,
+        frame_times_ms: &[f32],
+        gpu_times_ms: &[f32],
+    ) -> BenchmarkReport {
+        let mut sorted = frame_times_ms.to_vec();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+
+        let average = |times: &[f32]| {
+            if times.is_empty() {
+                0.0
+            } else {
+                times.iter().sum::<f32>() / times.len() as f32
+            }
+        };
+
+        BenchmarkReport {
+            cubes,
+            frames: sorted.len(),
+            average_ms: average(&sorted),
+            min_ms: sorted.first().cloned().unwrap_or(0.0),
+            p50_ms: percentile(&sorted, 0.5),
+            p95_ms: percentile(&sorted, 0.95),
+            p99_ms: percentile(&sorted, 0.99),
+            max_ms: sorted.last().cloned().unwrap_or(0.0),
+            gpu_average_ms: average(gpu_times_ms),
+        }
+    }
+}
+
+impl fmt::Display for BenchmarkReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} cubes, {} frames: avg {:.3} ms, min {:.3} ms, p50 {:.3} ms, p95 {:.3} ms, \
+             p99 {:.3} ms, max {:.3} ms, gpu avg {:.3} ms",
+            self.cubes,
+            self.frames,
+            self.average_ms,
+            self.min_ms,
+            self.p50_ms,
+            self.p95_ms,
+            self.p99_ms,
+            self.max_ms,
+            self.gpu_average_ms
+        )
+    }
+}
+
+// The benchmark scene of an engine created with BenchmarkConfig::apply. Call
+// frame_rendered after every rendered frame until is_done.
+pub struct Benchmark {
+    config: BenchmarkConfig,
+    instances: registry::BufferHandle,
+    rendered: u32,
+    last_frame: Option<Instant>,
+    frame_times_ms: Vec<f32>,
+    gpu_times_ms: Vec<f32>,
+}
+
+impl Benchmark {
+    pub fn new(engine: &mut engine::Engine, config: BenchmarkConfig) -> Result<Benchmark> {
+        if config.cubes == 0 || config.frames == 0 {
+            return Err(Error::OutOfRange(
+                "the benchmark needs at least one cube and one frame".to_string(),
+            ));
+        }
+
+        let transforms = instance_transforms(config.cubes);
+        let instances = engine.create_buffer(
+            std::mem::size_of_val(transforms.as_slice()) as vk::DeviceSize,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+        engine
+            .handles
+            .buffer(instances)?
+            .upload(&engine.shared_device().logical_device, &transforms)?;
+        let descriptor = engine.handles.buffer_descriptor(instances)?;
+        engine.bind_storage_buffer(INSTANCES_BINDING, descriptor)?;
+
+        let (vertices, indices) = cube_mesh();
+        let cube = engine.load_mesh(&vertices, &indices)?;
+        let cubes = engine.world.spawn();
+        engine.world.insert(
+            cubes,
+            scene::MeshRenderer::from_mesh(&cube).with_instances(config.cubes),
+        )?;
+
+        // far enough back for the whole grid to be in view
+        let extent = grid_size(config.cubes) as f32 * CUBE_SPACING;
+        let camera = engine.world.spawn();
+        engine.world.insert(
+            camera,
+            scene::Transform::at(Vector3::new(0.0, 0.0, extent * 1.5)),
+        )?;
+        engine.world.insert(
+            camera,
+            scene::Camera::perspective(Deg(60.0), 0.1, extent * 4.0),
+        )?;
+
+        Ok(Benchmark {
+            config,
+            instances,
+            rendered: 0,
+            last_frame: None,
+            frame_times_ms: Vec::with_capacity(config.frames as usize),
+            gpu_times_ms: Vec::with_capacity(config.frames as usize),
+        })
+    }
+
+    // Frame times are measured between consecutive calls once the warm-up is over
+    pub fn frame_rendered(&mut self, engine: &engine::Engine) {
+        let now = Instant::now();
+        self.rendered += 1;
+
+        if self.rendered > self.config.warmup_frames && !self.is_done() {
+            if let Some(last_frame) = self.last_frame {
+                let frame_time = now.duration_since(last_frame);
+                self.frame_times_ms.push(duration_ms(frame_time));
+                self.gpu_times_ms.push(engine.frame_stats().gpu_ms);
+            }
+        }
+
+        self.last_frame = Some(now);
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.frame_times_ms.len() >= self.config.frames as usize
+    }
+
+    pub fn report(&self) -> BenchmarkReport {
+        BenchmarkReport::from_frame_times(
+            self.config.cubes,
+            &self.frame_times_ms,
+            &self.gpu_times_ms,
+        )
+    }
+
+    // Frees the instance buffer, the engine has to be running
+    pub fn destroy(&self, engine: &mut engine::Engine) -> Result<()> {
+        engine.destroy_buffer(self.instances)
+    }
+}
+
+fn duration_ms(duration: Duration) -> f32 {
+    duration.as_secs_f32() * 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentiles_use_the_nearest_rank() {
+        let frame_times = (1..=100).rev().map(|ms| ms as f32).collect::<Vec<_>>();
+        let report = BenchmarkReport::from_frame_times(1, &frame_times, &[]);
+
+        assert_eq!(report.frames, 100);
+        assert_eq!(report.average_ms, 50.5);
+        assert_eq!((report.min_ms, report.max_ms), (1.0, 100.0));
+        assert_eq!(
+            (report.p50_ms, report.p95_ms, report.p99_ms),
+            (50.0, 95.0, 99.0)
+        );
+        assert_eq!(report.gpu_average_ms, 0.0);
+    }
+
+    #[test]
+    fn cubes_fill_a_centered_grid() {
+        let transforms = instance_transforms(27);
+        assert_eq!(transforms.len(), 27);
+
+        let center = transforms[13].w;
+        assert_eq!((center.x, center.y, center.z), (0.0, 0.0, 0.0));
+
+        let (vertices, indices) = cube_mesh();
+        assert_eq!((vertices.len(), indices.len()), (24, 36));
+        assert!(indices
+            .iter()
+            .all(|&index| (index as usize) < vertices.len()));
+    }
+}
@@ -0,0 +1,346 @@
+// Shows a model with an orbiting camera:
+//
+//   kelsier-viewer <model.obj> [--texture <image>] [--skybox <image.hdr>]
+//                  [--target x,y,z] [--distance <units>] [--yaw <deg>] [--pitch <deg>]
+//                  [--fov <deg>] [--near <units>] [--far <units>] [--hot-reload]
+//                  [--log <filter>]
+//
+// The arrow keys orbit around the target and the mouse wheel zooms, F toggles wireframe
+// and Escape quits. With --hot-reload the model, its texture and the scene shaders are
+// imported again when their files change.
+
+use winit::{
+    event::{ElementState, Event, VirtualKeyCode, WindowEvent},
+    event_loop::{ControlFlow, EventLoop},
+};
+
+use cgmath::{Deg, Quaternion, Rotation, Rotation3, Vector3};
+
+use kelsier::engine;
+use kelsier::input::{AxisBinding, Input, InputMap};
+use kelsier::logging;
+use kelsier::obj;
+use kelsier::scene;
+
+use anyhow::{anyhow, bail, Result};
+
+use std::path::PathBuf;
+
+const USAGE: &str = "usage: kelsier-viewer <model.obj> [--texture <image>] [--skybox <image.hdr>] \
+                     [--target x,y,z] [--distance <units>] [--yaw <deg>] [--pitch <deg>] \
+                     [--fov <deg>] [--near <units>] [--far <units>] [--hot-reload] \
+                     [--log <filter>]";
+
+// degrees per second while an arrow key is held
+const ORBIT_SPEED: f32 = 90.0;
+// fraction of the distance moved per line scrolled
+const ZOOM_STEP: f32 = 0.1;
+
+struct ViewerOptions {
+    model: PathBuf,
+    texture: Option<PathBuf>,
+    skybox: Option<PathBuf>,
+    target: Vector3<f32>,
+    distance: f32,
+    yaw: f32,
+    pitch: f32,
+    fov: f32,
+    near: f32,
+    far: f32,
+    hot_reload: bool,
+    log_filter: Option<String>,
+}
+
+fn parse_number(option: &str, value: &str) -> Result<f32> {
+    value
+        .parse()
+        .map_err(|_| anyhow!("{} expects a number, got {}", option, value))
+}
+
+fn parse_vector(option: &str, value: &str) -> Result<Vector3<f32>> {
+    let components = value
+        .split(',')
+        .map(|component| parse_number(option, component.trim()))
+        .collect::<Result<Vec<f32>>>()?;
+
+    match components.as_slice() {
+        &[x, y, z] => Ok(Vector3::new(x, y, z)),
+        _ => bail!("{} expects x,y,z, got {}", option, value),
+    }
+}
+
+impl ViewerOptions {
+    fn parse(args: &[String]) -> Result<ViewerOptions> {
+        let mut model = None;
+        let mut options = ViewerOptions {
+            model: PathBuf::new(),
+            texture: None,
+            skybox: None,
+            target: Vector3::new(0.0, 0.0, 0.0),
+            distance: 3.0,
+            yaw: 0.0,
+            pitch: -20.0,
+            fov: 45.0,
+            near: 0.1,
+            far: 100.0,
+            hot_reload: false,
+            log_filter: None,
+        };
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if arg == "--hot-reload" {
+                options.hot_reload = true;
+                continue;
+            }
+
+            if !arg.starts_with("--") {
+                if model.replace(PathBuf::from(arg)).is_some() {
+                    bail!("more than one model given\n{}", USAGE);
+                }
+                continue;
+            }
+
+            let value = args
+                .next()
+                .ok_or_else(|| anyhow!("{} expects a value\n{}", arg, USAGE))?;
+
+            match arg.as_str() {
+                "--texture" => options.texture = Some(value.into()),
+                "--skybox" => options.skybox = Some(value.into()),
+                "--target" => options.target = parse_vector(arg, value)?,
+                "--distance" => options.distance = parse_number(arg, value)?,
+                "--yaw" => options.yaw = parse_number(arg, value)?,
+                "--pitch" => options.pitch = parse_number(arg, value)?,
+                "--fov" => options.fov = parse_number(arg, value)?,
+                "--near" => options.near = parse_number(arg, value)?,
+                "--far" => options.far = parse_number(arg, value)?,
+                "--log" => options.log_filter = Some(value.clone()),
+                _ => bail!("unknown option {}\n{}", arg, USAGE),
+            }
+        }
+
+        options.model = model.ok_or_else(|| anyhow!("no model given\n{}", USAGE))?;
+        if options.distance <= 0.0 {
+            bail!("--distance has to be positive");
+        }
+
+        Ok(options)
+    }
+}
+
+// Circles the target at a distance, looking at it
+struct Orbit {
+    target: Vector3<f32>,
+    distance: f32,
+    yaw: f32,
+    pitch: f32,
+}
+
+impl Orbit {
+    fn transform(&self) -> scene::Transform {
+        let rotation =
+            Quaternion::from_angle_y(Deg(self.yaw)) * Quaternion::from_angle_x(Deg(self.pitch));
+        let position = self.target + rotation.rotate_vector(Vector3::new(0.0, 0.0, self.distance));
+
+        scene::Transform::at(position).with_rotation(rotation)
+    }
+}
+
+struct Viewer {
+    camera: scene::Entity,
+    orbit: Orbit,
+    // input of the current frame, applied in update_world
+    yaw_input: f32,
+    pitch_input: f32,
+    zoom_input: f32,
+}
+
+impl engine::Application for Viewer {
+    fn handle_input(&mut self, input: &InputMap) {
+        self.yaw_input = input.axis("orbit_yaw");
+        self.pitch_input = input.axis("orbit_pitch");
+        self.zoom_input = input.axis("zoom");
+    }
+
+    fn update_world(&mut self, world: &mut scene::World, delta_time: f32) {
+        let orbit = &mut self.orbit;
+        orbit.yaw += self.yaw_input * ORBIT_SPEED * delta_time;
+        // stays short of the poles, where the orbit would flip over
+        orbit.pitch = (orbit.pitch + self.pitch_input * ORBIT_SPEED * delta_time)
+            .max(-89.0)
+            .min(89.0);
+        orbit.distance *= (1.0 - ZOOM_STEP).powf(self.zoom_input);
+
+        if let Err(e) = world.insert(self.camera, orbit.transform()) {
+            tracing::error!("cannot move the camera: {}", e);
+        }
+    }
+}
+
+fn bind_viewer_input(input: &mut InputMap) {
+    input.bind_action("quit", Input::Key(VirtualKeyCode::Escape));
+    input.bind_action("toggle_wireframe", Input::Key(VirtualKeyCode::F));
+    input.bind_axis(
+        "orbit_yaw",
+        AxisBinding::Buttons {
+            negative: Input::Key(VirtualKeyCode::Left),
+            positive: Input::Key(VirtualKeyCode::Right),
+        },
+    );
+    input.bind_axis(
+        "orbit_pitch",
+        AxisBinding::Buttons {
+            negative: Input::Key(VirtualKeyCode::Down),
+            positive: Input::Key(VirtualKeyCode::Up),
+        },
+    );
+    input.bind_axis("zoom", AxisBinding::MouseWheel);
+}
+
+fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let options = ViewerOptions::parse(&args)?;
+    logging::init(options.log_filter.as_deref())?;
+
+    let mut config = engine::EngineConfig::default();
+    config.title = format!("kelsier-viewer {}", options.model.display());
+    config.hot_reload = options.hot_reload;
+
+    // room for the model twice, a reload imports it before the old meshes are freed
+    let model = obj::ObjModel::load(&options.model)?;
+    let (vertices, indices) = model.merged();
+    config.mesh_pool_vertices = config.mesh_pool_vertices.max(vertices.len() as u32 * 2);
+    config.mesh_pool_indices = config.mesh_pool_indices.max(indices.len() as u32 * 2);
+
+    // the texture of the model's first material unless one is given
+    match (options.texture.as_ref(), model.diffuse_texture()) {
+        (Some(texture), _) => config.texture_file = texture.clone(),
+        (None, Some(texture)) => config.texture_file = texture.to_path_buf(),
+        (None, None) => (),
+    }
+
+    if let Some(skybox) = options.skybox.as_ref() {
+        tracing::warn!(
+            "the scene has no background pass yet, {} is not shown",
+            skybox.display()
+        );
+    }
+
+    let event_loop = EventLoop::new();
+    let window = engine::Engine::init_window(&config, &event_loop)?;
+
+    let mut engine = engine::Engine::new(config, &window)?;
+    bind_viewer_input(&mut engine.input);
+
+    for mesh in engine.load_obj(&options.model)? {
+        let entity = engine.world.spawn();
+        engine
+            .world
+            .insert(entity, scene::MeshRenderer::from_mesh(&mesh))?;
+    }
+
+    let orbit = Orbit {
+        target: options.target,
+        distance: options.distance,
+        yaw: options.yaw,
+        pitch: options.pitch,
+    };
+
+    let camera = engine.world.spawn();
+    engine.world.insert(camera, orbit.transform())?;
+    engine.world.insert(
+        camera,
+        scene::Camera::perspective(Deg(options.fov), options.near, options.far),
+    )?;
+
+    let mut engine = engine.with_application(Box::new(Viewer {
+        camera,
+        orbit,
+        yaw_input: 0.0,
+        pitch_input: 0.0,
+        zoom_input: 0.0,
+    }));
+
+    event_loop.run(move |event, _, control_flow| match event {
+        Event::WindowEvent { event, .. } => {
+            match event {
+                WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
+
+                WindowEvent::Resized(_) | WindowEvent::ScaleFactorChanged { .. } => {
+                    if let Err(e) = engine.recreate_swapchain(&window) {
+                        tracing::error!("cannot recreate swapchain: {}", e);
+                    }
+                }
+
+                _ => (),
+            }
+
+            let pressed = engine
+                .on_event(&event)
+                .into_iter()
+                .filter(|action| action.state == ElementState::Pressed);
+
+            for action in pressed {
+                match action.action.as_str() {
+                    "quit" => *control_flow = ControlFlow::Exit,
+
+                    "toggle_wireframe" => {
+                        if let Err(e) = engine.toggle_wireframe() {
+                            tracing::error!("cannot toggle wireframe: {}", e);
+                        }
+                    }
+
+                    _ => (),
+                }
+            }
+        }
+
+        // no redraws are requested while suspended, the loop waits for the window to be restored
+        Event::MainEventsCleared => {
+            if engine.is_suspended() {
+                *control_flow = ControlFlow::Wait;
+            } else {
+                *control_flow = ControlFlow::Poll;
+                window.request_redraw();
+            }
+        }
+
+        Event::Suspended => engine.suspend(),
+
+        Event::Resumed => {
+            if let Err(e) = engine.resume(&window) {
+                tracing::error!("cannot resume rendering: {}", e);
+            }
+        }
+
+        Event::RedrawRequested(_window_id) => match engine.render_frame() {
+            Ok(_) => (),
+            Err(e) if e.is_swapchain_out_of_date() => {
+                if let Err(e) = engine.recreate_swapchain(&window) {
+                    tracing::error!("cannot recreate swapchain: {}", e);
+                }
+            }
+            Err(e) if e.is_device_lost() => match engine.handle_device_lost(&window, &e) {
+                Ok(engine::DeviceLostAction::Recover) => (),
+                Ok(engine::DeviceLostAction::Exit) => *control_flow = ControlFlow::Exit,
+                Err(e) => {
+                    tracing::error!("cannot recover from the lost device: {}", e);
+                    *control_flow = ControlFlow::Exit;
+                }
+            },
+            Err(e) => {
+                tracing::error!("cannot render frame: {}", e);
+                *control_flow = ControlFlow::Exit;
+            }
+        },
+
+        Event::LoopDestroyed => {
+            if let Err(e) = engine.shutdown() {
+                tracing::error!("failed to shut down cleanly: {:?}", e);
+            }
+        }
+
+        _ => (),
+    });
+}
@@ -0,0 +1,333 @@
+// Settings read from a kelsier.toml at startup, so they can change without recompiling.
+// Only the keys present in the file override the config built in code, eg.
+//
+//     [window]
+//     width = 1280
+//     height = 720
+//
+//     [render]
+//     vsync = true
+//
+//     [validation]
+//     enabled = false
+//
+//     [shaders]
+//     dir = "my_shaders"
+
+use ash::vk;
+use serde::Deserialize;
+
+use crate::engine;
+use crate::error::{Context, Error, Result};
+
+use std::path::{Path, PathBuf};
+
+// Looked up in the working directory by default
+pub const CONFIG_FILE: &str = "kelsier.toml";
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WindowSettings {
+    pub title: Option<String>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub icon: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RenderOptions {
+    // see swapchain::SwapchainConfig::with_vsync
+    pub vsync: Option<bool>,
+    // samples per pixel of the scene pass
+    pub msaa: Option<u32>,
+    pub frames_in_flight: Option<u32>,
+    pub swapchain_images: Option<u32>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ValidationLevel {
+    Errors,
+    Warnings,
+    Info,
+    Verbose,
+}
+
+impl ValidationLevel {
+    // The level and every more severe one
+    pub fn severities(&self) -> vk::DebugUtilsMessageSeverityFlagsEXT {
+        let errors = vk::DebugUtilsMessageSeverityFlagsEXT::ERROR;
+        let warnings = errors | vk::DebugUtilsMessageSeverityFlagsEXT::WARNING;
+        let info = warnings | vk::DebugUtilsMessageSeverityFlagsEXT::INFO;
+
+        match self {
+            ValidationLevel::Errors => errors,
+            ValidationLevel::Warnings => warnings,
+            ValidationLevel::Info => info,
+            ValidationLevel::Verbose => info | vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ValidationSettings {
+    pub enabled: Option<bool>,
+    pub level: Option<ValidationLevel>,
+    pub abort_on_error: Option<bool>,
+}
+
+// Relative paths are looked up in dir. Without a path of their own, the files of the
+// config built in code are looked up in dir by their file name.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AssetSettings {
+    pub dir: Option<PathBuf>,
+    pub texture: Option<PathBuf>,
+    // a Wavefront OBJ file, see engine::MeshSource::Obj
+    pub mesh: Option<PathBuf>,
+}
+
+// Resolved the same way as the asset paths
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ShaderSettings {
+    pub dir: Option<PathBuf>,
+    pub vertex: Option<PathBuf>,
+    pub fragment: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigFile {
+    pub window: WindowSettings,
+    pub render: RenderOptions,
+    pub validation: ValidationSettings,
+    pub assets: AssetSettings,
+    pub shaders: ShaderSettings,
+}
+
+fn resolve(dir: Option<&PathBuf>, path: Option<&PathBuf>, current: &Path) -> PathBuf {
+    match (dir, path) {
+        (Some(dir), Some(path)) => dir.join(path),
+        (None, Some(path)) => path.clone(),
+        (Some(dir), None) => match current.file_name() {
+            Some(file_name) => dir.join(file_name),
+            None => current.to_path_buf(),
+        },
+        (None, None) => current.to_path_buf(),
+    }
+}
+
+fn sample_count(msaa: u32) -> Result<vk::SampleCountFlags> {
+    let samples = match msaa {
+        1 => vk::SampleCountFlags::TYPE_1,
+        2 => vk::SampleCountFlags::TYPE_2,
+        4 => vk::SampleCountFlags::TYPE_4,
+        8 => vk::SampleCountFlags::TYPE_8,
+        16 => vk::SampleCountFlags::TYPE_16,
+        32 => vk::SampleCountFlags::TYPE_32,
+        64 => vk::SampleCountFlags::TYPE_64,
+        _ => {
+            return Err(Error::OutOfRange(format!(
+                "msaa has to be a power of two up to 64, got {}",
+                msaa
+            )))
+        }
+    };
+
+    // the scene pass and its attachments are single sampled
+    if samples != vk::SampleCountFlags::TYPE_1 {
+        return Err(Error::Unsupported(format!(
+            "msaa = {} is not supported, the scene is rendered with one sample per pixel",
+            msaa
+        )));
+    }
+
+    Ok(samples)
+}
+
+impl ConfigFile {
+    pub fn parse(contents: &str) -> Result<ConfigFile> {
+        Ok(toml::from_str(contents)?)
+    }
+
+    pub fn load(path: &Path) -> Result<ConfigFile> {
+        let contents = std::fs::read_to_string(path)
+            .context(format!("cannot read config file {}", path.display()))?;
+
+        ConfigFile::parse(&contents).context(format!("invalid config file {}", path.display()))
+    }
+
+    // None when there is no file at the path
+    pub fn load_if_exists(path: &Path) -> Result<Option<ConfigFile>> {
+        if path.exists() {
+            ConfigFile::load(path).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    // Overrides the config with the settings present in the file, has to be applied
+    // before the engine is created
+    pub fn apply(&self, config: &mut engine::EngineConfig) -> Result<()> {
+        let window = &self.window;
+        if let Some(title) = window.title.as_ref() {
+            config.title = title.clone();
+        }
+        if let Some(width) = window.width {
+            config.width = width;
+        }
+        if let Some(height) = window.height {
+            config.height = height;
+        }
+        if let Some(icon) = window.icon.as_ref() {
+            config.window_icon = Some(icon.clone());
+        }
+
+        let render = &self.render;
+        if let Some(vsync) = render.vsync {
+            config.swapchain = config.swapchain.clone().with_vsync(vsync);
+        }
+        if let Some(msaa) = render.msaa {
+            config.pipeline_state = config.pipeline_state.with_samples(sample_count(msaa)?);
+        }
+        if let Some(frames_in_flight) = render.frames_in_flight {
+            if frames_in_flight == 0 {
+                return Err(Error::OutOfRange(
+                    "frames_in_flight has to be at least 1".to_string(),
+                ));
+            }
+            config.frames_in_flight = frames_in_flight;
+        }
+        if let Some(image_count) = render.swapchain_images {
+            config.swapchain = config.swapchain.clone().with_image_count(image_count);
+        }
+
+        let validation = &self.validation;
+        let mut debug_messenger = config.debug_messenger.clone();
+        if let Some(enabled) = validation.enabled {
+            debug_messenger = debug_messenger.with_validation(enabled);
+        }
+        if let Some(level) = validation.level {
+            debug_messenger = debug_messenger.with_severities(level.severities());
+        }
+        if let Some(abort_on_error) = validation.abort_on_error {
+            debug_messenger = debug_messenger.with_abort_on_error(abort_on_error);
+        }
+        config.debug_messenger = debug_messenger;
+
+        let assets = &self.assets;
+        let dir = assets.dir.as_ref();
+        config.texture_file = resolve(dir, assets.texture.as_ref(), &config.texture_file);
+        config.mesh = match (&config.mesh, assets.mesh.as_ref()) {
+            (_, Some(mesh)) => engine::MeshSource::Obj(resolve(dir, Some(mesh), mesh)),
+            (engine::MeshSource::Obj(current), None) => {
+                engine::MeshSource::Obj(resolve(dir, None, current))
+            }
+            (engine::MeshSource::BuiltIn, None) => engine::MeshSource::BuiltIn,
+        };
+
+        let shaders = &self.shaders;
+        let dir = shaders.dir.as_ref();
+        let vertex = resolve(
+            dir,
+            shaders.vertex.as_ref(),
+            Path::new(&config.vertex_shader_file),
+        );
+        let fragment = resolve(
+            dir,
+            shaders.fragment.as_ref(),
+            Path::new(&config.fragment_shader_file),
+        );
+        config.vertex_shader_file = vertex.to_string_lossy().into_owned();
+        config.fragment_shader_file = fragment.to_string_lossy().into_owned();
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn present_keys_override_the_config() {
+        let file = ConfigFile::parse(
+            r#"
+            [window]
+            width = 1280
+
+            [render]
+            vsync = true
+            frames_in_flight = 2
+
+            [validation]
+            enabled = false
+            level = "errors"
+            "#,
+        )
+        .unwrap();
+
+        let mut config = engine::EngineConfig::default();
+        let height = config.height;
+        file.apply(&mut config).unwrap();
+
+        assert_eq!(config.width, 1280);
+        assert_eq!(config.height, height);
+        assert_eq!(config.frames_in_flight, 2);
+        assert_eq!(
+            config.swapchain.present_modes,
+            vec![vk::PresentModeKHR::FIFO]
+        );
+        assert!(!config.debug_messenger.validation);
+        assert_eq!(
+            config.debug_messenger.severities,
+            vk::DebugUtilsMessageSeverityFlagsEXT::ERROR
+        );
+
+        // an empty file changes nothing
+        let mut config = engine::EngineConfig::default();
+        ConfigFile::parse("").unwrap().apply(&mut config).unwrap();
+        assert_eq!(config.width, engine::EngineConfig::default().width);
+        assert!(config.debug_messenger.validation);
+
+        assert!(ConfigFile::parse("[window]\nwidht = 10").is_err());
+        let msaa = ConfigFile::parse("[render]\nmsaa = 3").unwrap();
+        assert!(msaa.apply(&mut config).is_err());
+    }
+
+    #[test]
+    fn paths_are_looked_up_in_the_directories() {
+        let file = ConfigFile::parse(
+            r#"
+            [assets]
+            dir = "assets"
+            mesh = "models/cube.obj"
+
+            [shaders]
+            dir = "my_shaders"
+            fragment = "/abs/toon.frag"
+            "#,
+        )
+        .unwrap();
+
+        let mut config = engine::EngineConfig::default();
+        file.apply(&mut config).unwrap();
+
+        assert_eq!(config.texture_file, PathBuf::from("assets/winter.jpeg"));
+        match config.mesh {
+            engine::MeshSource::Obj(path) => {
+                assert_eq!(path, PathBuf::from("assets/models/cube.obj"))
+            }
+            engine::MeshSource::BuiltIn => panic!("the mesh of the file is not used"),
+        }
+        assert_eq!(
+            PathBuf::from(config.vertex_shader_file),
+            PathBuf::from("my_shaders/shader.vert")
+        );
+        // joining an absolute path replaces the directory
+        assert_eq!(config.fragment_shader_file, "/abs/toon.frag");
+    }
+}
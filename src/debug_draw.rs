@@ -0,0 +1,309 @@
+use cgmath::{Deg, InnerSpace, Matrix4, Point3, SquareMatrix, Vector3, Vector4};
+
+use std::collections::HashMap;
+
+pub type Color = [f32; 3];
+
+pub const FRUSTUM_COLOR: Color = [1.0, 1.0, 0.0];
+pub const CASCADE_COLOR: Color = [0.0, 1.0, 1.0];
+pub const LIGHT_COLOR: Color = [1.0, 0.5, 0.0];
+pub const AXIS_COLORS: [Color; 3] = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+
+// Number of segments used to approximate circles
+const CIRCLE_SEGMENTS: u32 = 24;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct DebugLine {
+    pub start: [f32; 3],
+    pub end: [f32; 3],
+    pub color: Color,
+}
+
+// Which debug volumes are drawn for an object, everything is hidden until it is set
+#[derive(Debug, Copy, Clone, Default)]
+pub struct DebugVisibility {
+    pub frustum: bool,
+    pub cascades: bool,
+    pub light_volume: bool,
+}
+
+// Collects line primitives for a frame. The lines are consumed by the
+// debug renderer and cleared at the start of the next frame.
+#[derive(Default)]
+pub struct DebugDraw {
+    pub lines: Vec<DebugLine>,
+    visibility: HashMap<u32, DebugVisibility>,
+}
+
+impl DebugDraw {
+    pub fn new() -> DebugDraw {
+        DebugDraw::default()
+    }
+
+    pub fn clear(&mut self) {
+        self.lines.clear();
+    }
+
+    pub fn set_visibility(&mut self, object_id: u32, visibility: DebugVisibility) {
+        self.visibility.insert(object_id, visibility);
+    }
+
+    pub fn visibility(&self, object_id: u32) -> DebugVisibility {
+        self.visibility.get(&object_id).cloned().unwrap_or_default()
+    }
+
+    pub fn line(&mut self, start: Point3<f32>, end: Point3<f32>, color: Color) {
+        self.lines.push(DebugLine {
+            start: start.into(),
+            end: end.into(),
+            color,
+        });
+    }
+
+    // Draws the 12 edges of a box given its 8 corners, near plane first
+    fn box_edges(&mut self, corners: &[Point3<f32>; 8], color: Color) {
+        for i in 0..4 {
+            let next = (i + 1) % 4;
+            self.line(corners[i], corners[next], color);
+            self.line(corners[i + 4], corners[next + 4], color);
+            self.line(corners[i], corners[i + 4], color);
+        }
+    }
+
+    // Draws the frustum of a camera by unprojecting the corners of the clip space cube
+    pub fn frustum(&mut self, view_proj: Matrix4<f32>, color: Color) {
+        let inverse = match view_proj.invert() {
+            Some(inverse) => inverse,
+            None => return,
+        };
+
+        let ndc_corners = [
+            (-1.0, -1.0, 0.0),
+            (1.0, -1.0, 0.0),
+            (1.0, 1.0, 0.0),
+            (-1.0, 1.0, 0.0),
+            (-1.0, -1.0, 1.0),
+            (1.0, -1.0, 1.0),
+            (1.0, 1.0, 1.0),
+            (-1.0, 1.0, 1.0),
+        ];
+
+        let mut corners = [Point3::new(0.0, 0.0, 0.0); 8];
+        for (corner, (x, y, z)) in corners.iter_mut().zip(ndc_corners.iter()) {
+            let world = inverse * Vector4::new(*x, *y, *z, 1.0);
+            *corner = Point3::from_homogeneous(world);
+        }
+
+        self.box_edges(&corners, color);
+    }
+
+    // The frustum of the object's camera, when its visibility shows frusta
+    pub fn camera_frustum(&mut self, object_id: u32, view_proj: Matrix4<f32>) {
+        if self.visibility(object_id).frustum {
+            self.frustum(view_proj, FRUSTUM_COLOR);
+        }
+    }
+
+    pub fn aabb(&mut self, min: Point3<f32>, max: Point3<f32>, color: Color) {
+        let corners = [
+            Point3::new(min.x, min.y, min.z),
+            Point3::new(max.x, min.y, min.z),
+            Point3::new(max.x, max.y, min.z),
+            Point3::new(min.x, max.y, min.z),
+            Point3::new(min.x, min.y, max.z),
+            Point3::new(max.x, min.y, max.z),
+            Point3::new(max.x, max.y, max.z),
+            Point3::new(min.x, max.y, max.z),
+        ];
+
+        self.box_edges(&corners, color);
+    }
+
+    // Box of the given half extents placed by a model matrix, eg. an oriented bounding box
+    pub fn wire_box(&mut self, transform: Matrix4<f32>, half_extents: Vector3<f32>, color: Color) {
+        let (x, y, z) = (half_extents.x, half_extents.y, half_extents.z);
+        let local = [
+            (-x, -y, -z),
+            (x, -y, -z),
+            (x, y, -z),
+            (-x, y, -z),
+            (-x, -y, z),
+            (x, -y, z),
+            (x, y, z),
+            (-x, y, z),
+        ];
+
+        let mut corners = [Point3::new(0.0, 0.0, 0.0); 8];
+        for (corner, (x, y, z)) in corners.iter_mut().zip(local.iter()) {
+            *corner = Point3::from_homogeneous(transform * Vector4::new(*x, *y, *z, 1.0));
+        }
+
+        self.box_edges(&corners, color);
+    }
+
+    // The x, y and z axes of a transform in red, green and blue
+    pub fn axes(&mut self, transform: Matrix4<f32>, size: f32) {
+        let origin = Point3::from_homogeneous(transform * Vector4::new(0.0, 0.0, 0.0, 1.0));
+        let axes = [Vector3::unit_x(), Vector3::unit_y(), Vector3::unit_z()];
+
+        for (axis, color) in axes.iter().zip(AXIS_COLORS.iter()) {
+            let end = Point3::from_homogeneous(transform * (axis * size).extend(1.0));
+            self.line(origin, end, *color);
+        }
+    }
+
+    // A surface normal, eg. to check the normals of a loaded mesh
+    pub fn normal(&mut self, point: Point3<f32>, normal: Vector3<f32>, length: f32, color: Color) {
+        self.line(point, point + normal.normalize() * length, color);
+    }
+
+    // Shadow cascade bounds of the object's light, one box per cascade given as (min, max)
+    // pairs, when its visibility shows cascades
+    pub fn cascades(&mut self, object_id: u32, bounds: &[(Point3<f32>, Point3<f32>)]) {
+        if !self.visibility(object_id).cascades {
+            return;
+        }
+
+        for (min, max) in bounds {
+            self.aabb(*min, *max, CASCADE_COLOR);
+        }
+    }
+
+    fn circle(
+        &mut self,
+        center: Point3<f32>,
+        axis_u: Vector3<f32>,
+        axis_v: Vector3<f32>,
+        radius: f32,
+        color: Color,
+    ) {
+        let step = Deg(360.0 / CIRCLE_SEGMENTS as f32);
+        let point_at = |i: u32| {
+            let angle = step * i as f32;
+            center
+                + (axis_u * cgmath::Angle::cos(angle) + axis_v * cgmath::Angle::sin(angle)) * radius
+        };
+
+        for i in 0..CIRCLE_SEGMENTS {
+            self.line(point_at(i), point_at(i + 1), color);
+        }
+    }
+
+    // Range of a point light drawn as three axis aligned circles
+    pub fn sphere(&mut self, center: Point3<f32>, radius: f32, color: Color) {
+        let (x, y, z) = (Vector3::unit_x(), Vector3::unit_y(), Vector3::unit_z());
+
+        self.circle(center, x, y, radius, color);
+        self.circle(center, y, z, radius, color);
+        self.circle(center, z, x, radius, color);
+    }
+
+    // Spot light volume, `angle` is the half angle of the cone
+    pub fn cone(
+        &mut self,
+        apex: Point3<f32>,
+        direction: Vector3<f32>,
+        range: f32,
+        angle: Deg<f32>,
+        color: Color,
+    ) {
+        let direction = direction.normalize();
+        let helper = if direction.z.abs() < 0.99 {
+            Vector3::unit_z()
+        } else {
+            Vector3::unit_x()
+        };
+
+        let axis_u = direction.cross(helper).normalize();
+        let axis_v = direction.cross(axis_u).normalize();
+
+        let base_center = apex + direction * range;
+        let base_radius = range * cgmath::Angle::tan(angle);
+
+        self.circle(base_center, axis_u, axis_v, base_radius, color);
+
+        for offset in [axis_u, -axis_u, axis_v, -axis_v].iter() {
+            self.line(apex, base_center + *offset * base_radius, color);
+        }
+    }
+
+    // Range of the object's point light, when its visibility shows light volumes
+    pub fn point_light_volume(&mut self, object_id: u32, center: Point3<f32>, radius: f32) {
+        if self.visibility(object_id).light_volume {
+            self.sphere(center, radius, LIGHT_COLOR);
+        }
+    }
+
+    // Cone of the object's spot light, when its visibility shows light volumes
+    pub fn spot_light_volume(
+        &mut self,
+        object_id: u32,
+        apex: Point3<f32>,
+        direction: Vector3<f32>,
+        range: f32,
+        angle: Deg<f32>,
+    ) {
+        if self.visibility(object_id).light_volume {
+            self.cone(apex, direction, range, angle, LIGHT_COLOR);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hidden_objects_emit_no_lines() {
+        let mut draw = DebugDraw::new();
+        let bounds = [(Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 1.0, 1.0))];
+
+        draw.camera_frustum(1, Matrix4::identity());
+        draw.cascades(1, &bounds);
+        draw.point_light_volume(1, Point3::new(0.0, 0.0, 0.0), 1.0);
+        draw.spot_light_volume(
+            1,
+            Point3::new(0.0, 0.0, 0.0),
+            Vector3::unit_z(),
+            1.0,
+            Deg(30.0),
+        );
+        assert!(draw.lines.is_empty());
+
+        // only the volumes the visibility shows are drawn
+        draw.set_visibility(
+            1,
+            DebugVisibility {
+                cascades: true,
+                ..Default::default()
+            },
+        );
+        draw.camera_frustum(1, Matrix4::identity());
+        draw.point_light_volume(1, Point3::new(0.0, 0.0, 0.0), 1.0);
+        draw.cascades(1, &bounds);
+        assert_eq!(draw.lines.len(), 12);
+        assert!(draw.lines.iter().all(|line| line.color == CASCADE_COLOR));
+    }
+
+    #[test]
+    fn visibility_is_kept_per_object() {
+        let mut draw = DebugDraw::new();
+        draw.set_visibility(
+            1,
+            DebugVisibility {
+                frustum: true,
+                light_volume: true,
+                ..Default::default()
+            },
+        );
+
+        draw.camera_frustum(1, Matrix4::identity());
+        assert_eq!(draw.lines.len(), 12);
+
+        draw.clear();
+        draw.camera_frustum(2, Matrix4::identity());
+        draw.point_light_volume(1, Point3::new(0.0, 0.0, 0.0), 1.0);
+        assert_eq!(draw.lines.len(), 3 * CIRCLE_SEGMENTS as usize);
+    }
+}
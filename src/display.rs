@@ -0,0 +1,230 @@
+use winit::{
+    monitor::{MonitorHandle, VideoMode},
+    window::{Fullscreen, Icon, Window},
+};
+
+use crate::error::{Error, Result};
+
+use std::path::Path;
+
+// How the window covers its monitor. The swapchain has to be recreated after a change,
+// see Engine::set_window_mode.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WindowMode {
+    Windowed,
+    // a window without decorations covering the monitor, the desktop keeps its video mode
+    Borderless,
+    // takes over the monitor with the video mode of highest resolution and refresh rate,
+    // falls back to borderless where the platform does not support it, eg. Wayland
+    Exclusive,
+}
+
+impl WindowMode {
+    // The mode a fullscreen hotkey switches to
+    pub fn toggled(self) -> WindowMode {
+        match self {
+            WindowMode::Windowed => WindowMode::Borderless,
+            WindowMode::Borderless | WindowMode::Exclusive => WindowMode::Windowed,
+        }
+    }
+
+    // Windowed, borderless and exclusive in turn
+    pub fn next(self) -> WindowMode {
+        match self {
+            WindowMode::Windowed => WindowMode::Borderless,
+            WindowMode::Borderless => WindowMode::Exclusive,
+            WindowMode::Exclusive => WindowMode::Windowed,
+        }
+    }
+
+    pub fn of_window(window: &Window) -> WindowMode {
+        match window.fullscreen() {
+            None => WindowMode::Windowed,
+            Some(Fullscreen::Borderless(_)) => WindowMode::Borderless,
+            Some(Fullscreen::Exclusive(_)) => WindowMode::Exclusive,
+        }
+    }
+}
+
+// Whether the cursor is shown over the window and kept in it, see Engine::set_cursor_mode
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CursorMode {
+    Normal,
+    // hidden while over the window, it can still leave it
+    Hidden,
+    // hidden and grabbed by the window, mouse movement is read as relative motion
+    // instead, eg. for a fly camera
+    Locked,
+}
+
+impl CursorMode {
+    // The mode a capture hotkey switches to
+    pub fn toggled(self) -> CursorMode {
+        match self {
+            CursorMode::Normal | CursorMode::Hidden => CursorMode::Locked,
+            CursorMode::Locked => CursorMode::Normal,
+        }
+    }
+}
+
+// Returns the mode actually used, the cursor is only hidden where the platform cannot
+// grab it, eg. on Wayland before the window is focused
+pub fn set_cursor_mode(window: &Window, mode: CursorMode) -> CursorMode {
+    let applied = match mode {
+        CursorMode::Locked => match window.set_cursor_grab(true) {
+            Ok(()) => CursorMode::Locked,
+            Err(err) => {
+                tracing::warn!("cannot grab the cursor, hiding it instead: {}", err);
+                CursorMode::Hidden
+            }
+        },
+        CursorMode::Normal | CursorMode::Hidden => {
+            if let Err(err) = window.set_cursor_grab(false) {
+                tracing::warn!("cannot release the cursor: {}", err);
+            }
+            mode
+        }
+    };
+
+    window.set_cursor_visible(applied == CursorMode::Normal);
+    applied
+}
+
+pub fn icon_from_rgba(rgba: Vec<u8>, width: u32, height: u32) -> Result<Icon> {
+    Icon::from_rgba(rgba, width, height)
+        .map_err(|err| Error::msg(format!("invalid window icon: {}", err)))
+}
+
+// Any image format the image crate reads, eg. a 32x32 or 64x64 png
+pub fn load_icon(path: &Path) -> Result<Icon> {
+    let image = image::open(path)?.to_rgba();
+    let (width, height) = image.dimensions();
+
+    icon_from_rgba(image.into_raw(), width, height)
+}
+
+fn supports_exclusive(_window: &Window) -> bool {
+    #[cfg(all(unix, not(target_os = "android"), not(target_os = "macos")))]
+    {
+        use winit::platform::unix::WindowExtUnix;
+
+        _window.wayland_surface().is_none()
+    }
+
+    #[cfg(not(all(unix, not(target_os = "android"), not(target_os = "macos"))))]
+    true
+}
+
+// Index of the largest mode, preferring the higher refresh rate and then bit depth.
+// Takes (width, height, refresh rate, bit depth) to stay independent of the monitor.
+pub fn best_video_mode(modes: &[(u32, u32, u16, u16)]) -> Option<usize> {
+    modes
+        .iter()
+        .enumerate()
+        .max_by_key(|(_, &(width, height, refresh_rate, bit_depth))| {
+            (width as u64 * height as u64, refresh_rate, bit_depth)
+        })
+        .map(|(index, _)| index)
+}
+
+fn exclusive_video_mode(monitor: &MonitorHandle) -> Option<VideoMode> {
+    let modes = monitor.video_modes().collect::<Vec<VideoMode>>();
+    let keys = modes
+        .iter()
+        .map(|mode| {
+            let size = mode.size();
+            (
+                size.width,
+                size.height,
+                mode.refresh_rate(),
+                mode.bit_depth(),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    best_video_mode(&keys).map(|index| modes[index].clone())
+}
+
+// Switches the window to the mode on its current monitor and returns the mode actually
+// used, which is borderless when exclusive fullscreen is not available
+pub fn apply(window: &Window, mode: WindowMode) -> WindowMode {
+    let monitor = window.current_monitor();
+
+    let (fullscreen, applied) = match mode {
+        WindowMode::Windowed => (None, WindowMode::Windowed),
+        WindowMode::Borderless => (
+            Some(Fullscreen::Borderless(monitor)),
+            WindowMode::Borderless,
+        ),
+        WindowMode::Exclusive => {
+            let video_mode = if supports_exclusive(window) {
+                exclusive_video_mode(&monitor)
+            } else {
+                None
+            };
+
+            match video_mode {
+                Some(video_mode) => {
+                    tracing::info!(
+                        "exclusive fullscreen {:?} at {} Hz",
+                        video_mode.size(),
+                        video_mode.refresh_rate()
+                    );
+                    (
+                        Some(Fullscreen::Exclusive(video_mode)),
+                        WindowMode::Exclusive,
+                    )
+                }
+                None => {
+                    tracing::warn!("exclusive fullscreen is not supported, using borderless");
+                    (
+                        Some(Fullscreen::Borderless(monitor)),
+                        WindowMode::Borderless,
+                    )
+                }
+            }
+        }
+    };
+
+    window.set_fullscreen(fullscreen);
+    applied
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hotkey_toggles_between_windowed_and_borderless() {
+        assert_eq!(WindowMode::Windowed.toggled(), WindowMode::Borderless);
+        assert_eq!(WindowMode::Borderless.toggled(), WindowMode::Windowed);
+        assert_eq!(WindowMode::Exclusive.toggled(), WindowMode::Windowed);
+
+        assert_eq!(WindowMode::Borderless.next(), WindowMode::Exclusive);
+        assert_eq!(WindowMode::Exclusive.next(), WindowMode::Windowed);
+
+        assert_eq!(CursorMode::Normal.toggled(), CursorMode::Locked);
+        assert_eq!(CursorMode::Hidden.toggled(), CursorMode::Locked);
+        assert_eq!(CursorMode::Locked.toggled(), CursorMode::Normal);
+    }
+
+    #[test]
+    fn icons_need_four_bytes_per_pixel() {
+        assert!(icon_from_rgba(vec![255; 4 * 16 * 16], 16, 16).is_ok());
+        assert!(icon_from_rgba(vec![255; 3 * 16 * 16], 16, 16).is_err());
+    }
+
+    #[test]
+    fn largest_mode_with_highest_refresh_rate() {
+        let modes = [
+            (1920, 1080, 60, 32),
+            (2560, 1440, 60, 32),
+            (2560, 1440, 144, 24),
+            (2560, 1440, 144, 32),
+            (1280, 720, 240, 32),
+        ];
+
+        assert_eq!(best_video_mode(&modes), Some(3));
+        assert_eq!(best_video_mode(&[]), None);
+    }
+}
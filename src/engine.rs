@@ -0,0 +1,2146 @@
+use winit::{
+    event::{DeviceEvent, DeviceId, Event, WindowEvent},
+    event_loop::{ControlFlow, EventLoop},
+    window::{Window, WindowBuilder},
+};
+
+use ash::version::DeviceV1_0;
+use ash::vk;
+
+use crate::{
+    app, assets, debug_draw, display, engine_events, import, input, obj, projection, scene,
+    shaderc, terrain,
+    vulkan::constants::*,
+    vulkan::{
+        adapter, bounds, buffers, capture, compute_skinning, debug_lines, debug_messenger,
+        deferred, descriptor, device, events, image_effects, instance, lighting, mesh_pool,
+        object_uniforms, particles, permutation, picking, pipeline, postprocess, present, preset,
+        profiler, queue, registry, render_settings, requirements, scheduler, skinning, surface,
+        swapchain, sync, texture, timeline, ui, upload, validation_stats, viewport, warmup,
+    },
+};
+
+use crate::error::{Context, Error, Result};
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+
+// Geometry drawn by the scene pass
+#[derive(Debug, Clone, PartialEq)]
+pub enum MeshSource {
+    // the quads of app::VERTICES
+    BuiltIn,
+    // a Wavefront OBJ file, the first diffuse map of its materials replaces texture_file
+    Obj(PathBuf),
+}
+
+pub struct EngineConfig {
+    pub title: String,
+    pub width: u32,
+    pub height: u32,
+    // applied when the window is created, changed with Engine::set_window_mode
+    pub window_mode: display::WindowMode,
+    // applied when the engine is created, changed with Engine::set_cursor_mode
+    pub cursor_mode: display::CursorMode,
+    // image shown in the title bar and task bar, see display::load_icon
+    pub window_icon: Option<PathBuf>,
+    pub vertex_shader_file: String,
+    pub fragment_shader_file: String,
+    pub texture_file: PathBuf,
+    pub mesh: MeshSource,
+    // vertices and indices the scene's mesh pool has room for, grown to fit the mesh
+    // above. Further meshes are added with Engine::load_mesh.
+    pub mesh_pool_vertices: u32,
+    pub mesh_pool_indices: u32,
+    pub frames_in_flight: u32,
+    // image count and format preferences, see Engine::swapchain_image_count for the
+    // number of images actually created
+    pub swapchain: swapchain::SwapchainConfig,
+    pub pipeline_state: preset::FixedFunctionState,
+    // clear values and viewport of the scene pass, see Engine::set_render_settings
+    pub render_settings: render_settings::RenderSettings,
+    // used while the world has no active camera, see scene::Camera
+    pub projection: projection::Projection,
+    // storage and dynamic uniform buffers the scene shaders read, added to the scene set
+    // after pipeline::PipelineDetail::scene_bindings. Bound with Engine::bind_storage_buffer
+    // and Engine::bind_object_uniforms.
+    pub scene_buffers: Vec<descriptor::Binding>,
+    pub pipeline_cache_file: PathBuf,
+    pub pipeline_manifest_file: PathBuf,
+    pub ui_overlay: bool,
+    // draws the lines collected in `Engine::debug_draw` on top of the scene
+    pub debug_lines: bool,
+    // particles simulated with a compute shader and drawn over the scene,
+    // skipped when the graphics queue cannot run compute work
+    pub particles: Option<particles::EmitterConfig>,
+    // compute shader producing the final image instead of the scene render pass, eg.
+    // present::PRESENT_SHADER_FILE. Ignored when the swapchain cannot be written by it.
+    pub present_shader: Option<String>,
+    // areas of the swapchain, each with its own effects applied to the scene before
+    // presenting, see vulkan::viewport. Not applied when presenting with a compute shader.
+    pub viewports: Vec<viewport::ViewportConfig>,
+    // compute effects run in order on the finished swapchain image, see
+    // vulkan::image_effects. Ignored when the swapchain cannot be written by them.
+    pub image_effects: Vec<image_effects::ImageEffect>,
+    // shades the scene in the G-buffer subpasses of vulkan::deferred instead of with
+    // fragment_shader_file, when the device has enough attachments for it
+    pub deferred: bool,
+    // which gpu to use, overridden by the KELSIER_DEVICE environment variable
+    pub device_selection: adapter::DeviceSelection,
+    // features and extensions adapters need to be selected, and optional ones enabled
+    // when available, see Engine::is_feature_enabled
+    pub device_requirements: requirements::DeviceRequirements,
+    // warns when a gpu pass keeps exceeding its budget, eg. GpuBudget::new("main", 8.0, 30)
+    pub gpu_budgets: Vec<profiler::GpuBudget>,
+    // gpu time per frame given to background jobs, see vulkan::scheduler
+    pub background_budget_ms: f32,
+    // frames wait on a timeline semaphore instead of fences, when the device supports
+    // VK_KHR_timeline_semaphore
+    pub timeline_semaphores: bool,
+    // imports the scene shaders and texture, and textures and models loaded through the
+    // engine, again when their files change, see assets::AssetRegistry
+    pub hot_reload: bool,
+    // merges equal vertices of imported models and reorders them for the vertex cache,
+    // see mesh_processing::optimize
+    pub optimize_meshes: bool,
+    // app::VertexFormat::Compressed stores every mesh quantized, see
+    // app::CompressedVertexData
+    pub vertex_format: app::VertexFormat,
+    // which validation messages are logged and passed to a callback, see
+    // Engine::set_debug_messenger to change it while running
+    pub debug_messenger: debug_messenger::DebugMessengerConfig,
+}
+
+impl Default for EngineConfig {
+    fn default() -> EngineConfig {
+        EngineConfig {
+            title: WINDOW_TITLE.to_string(),
+            width: WINDOW_WIDTH,
+            height: WINDOW_HEIGHT,
+            window_mode: display::WindowMode::Windowed,
+            cursor_mode: display::CursorMode::Normal,
+            window_icon: None,
+            vertex_shader_file: "shaders/shader.vert".to_string(),
+            fragment_shader_file: "shaders/shader.frag".to_string(),
+            texture_file: PathBuf::from("textures/winter.jpeg"),
+            mesh: MeshSource::BuiltIn,
+            mesh_pool_vertices: mesh_pool::DEFAULT_VERTEX_CAPACITY,
+            mesh_pool_indices: mesh_pool::DEFAULT_INDEX_CAPACITY,
+            // limited to the swapchain's images on portability implementations like MoltenVK
+            frames_in_flight: 10,
+            swapchain: swapchain::SwapchainConfig::default(),
+            pipeline_state: preset::FixedFunctionState::from_preset(preset::Preset::Opaque3d),
+            render_settings: render_settings::RenderSettings::default(),
+            projection: projection::Projection::default(),
+            scene_buffers: vec![],
+            pipeline_cache_file: PathBuf::from("pipeline_cache.bin"),
+            pipeline_manifest_file: PathBuf::from("pipeline_manifest.json"),
+            ui_overlay: false,
+            debug_lines: false,
+            particles: None,
+            present_shader: None,
+            viewports: vec![viewport::ViewportConfig::new(viewport::MAIN_VIEWPORT)],
+            image_effects: vec![],
+            deferred: false,
+            device_selection: adapter::DeviceSelection::from_env(),
+            device_requirements: requirements::DeviceRequirements::default(),
+            gpu_budgets: vec![],
+            background_budget_ms: scheduler::DEFAULT_BACKGROUND_BUDGET_MS,
+            timeline_semaphores: false,
+            hot_reload: false,
+            optimize_meshes: false,
+            vertex_format: app::VertexFormat::Full,
+            debug_messenger: debug_messenger::DebugMessengerConfig::default(),
+        }
+    }
+}
+
+// What the engine does once the device was lost, see Application::on_device_lost
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DeviceLostAction {
+    // creates the device and the resources on it again, see Engine::recover_device
+    Recover,
+    // destroys what is left of the device, no frames are rendered afterwards
+    Exit,
+}
+
+// Whether Engine::run keeps running after an application hook
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LoopAction {
+    Continue,
+    Exit,
+}
+
+// Hooks for applications embedding the engine. The embedder owns the event loop
+// and forwards window events and redraw requests to the engine, or leaves the loop to
+// Engine::run.
+pub trait Application {
+    // Called every frame before update, the actions pressed or released since the last
+    // frame are still reported by `just_pressed` and `just_released`
+    fn handle_input(&mut self, _input: &input::InputMap) {}
+
+    fn update(&mut self, _delta_time: f32) {}
+
+    // Called every frame after update, then the animators of the world are advanced
+    // and it is drawn
+    fn update_world(&mut self, _world: &mut scene::World, _delta_time: f32) {}
+
+    fn on_event(&mut self, _event: &WindowEvent) {}
+
+    // Called for window, device hotplug and swapchain notifications, see engine_events
+    fn on_engine_event(&mut self, _event: &engine_events::EngineEvent) {}
+
+    // Called every frame when the ui overlay is enabled, the draw list is cleared beforehand
+    fn build_ui(&mut self, _draw_list: &mut ui::UiDrawList) {}
+
+    // Called every frame when debug lines are enabled, the lines are cleared beforehand
+    fn build_debug_draw(&mut self, _debug_draw: &mut debug_draw::DebugDraw) {}
+
+    // Called between frames after an asset was imported again, eg. to write a reloaded
+    // texture to the descriptor sets it was bound to
+    fn on_asset_reloaded(&mut self, _change: &assets::AssetChange) {}
+
+    // Called when rendering failed because the device was lost, before anything is
+    // recreated. Descriptors the application wrote itself have to be written again after a
+    // recovery, buffers created through the engine are back but empty.
+    fn on_device_lost(&mut self, _error: &Error) -> DeviceLostAction {
+        DeviceLostAction::Recover
+    }
+
+    // Called by Engine::run for every action a window event pressed or released. The
+    // application is taken out of the engine for the call, so the engine's own hooks do
+    // not reach it meanwhile.
+    fn on_action(
+        &mut self,
+        _engine: &mut Engine,
+        _window: &Window,
+        _action: &input::ActionEvent,
+    ) -> LoopAction {
+        LoopAction::Continue
+    }
+
+    // Called by Engine::run after every rendered frame, like on_action without the
+    // application in the engine
+    fn frame_rendered(&mut self, _engine: &mut Engine) -> LoopAction {
+        LoopAction::Continue
+    }
+}
+
+// A buffer bound to a binding of EngineConfig::scene_buffers, written again when the
+// scene's descriptor sets are recreated with the swapchain
+#[derive(Debug, Clone)]
+enum SceneBufferBinding {
+    Storage(vk::DescriptorBufferInfo),
+    // with the offset of every swapchain image
+    DynamicUniform(vk::DescriptorBufferInfo, Vec<u32>),
+}
+
+pub struct Engine {
+    pub config: EngineConfig,
+    pub frame: sync::Objects<app::UniformBuffer>,
+
+    application: Option<Box<dyn Application>>,
+    pub debug_draw: debug_draw::DebugDraw,
+    // actions and axes of the application, bound to keys by the application
+    pub input: input::InputMap,
+    // entities drawn by the scene pass, see sync_world
+    pub world: scene::World,
+    // window, device and swapchain notifications, see subscribe_events
+    events: engine_events::EngineEvents,
+    // see bind_storage_buffer and bind_object_uniforms
+    scene_buffer_bindings: BTreeMap<u32, SceneBufferBinding>,
+    last_frame_time: Instant,
+
+    pipeline_warmup: warmup::PipelineWarmup,
+    // batches copies of new assets into device local memory
+    pub uploads: upload::UploadManager,
+    // background gpu work spread over frames within a time budget
+    pub scheduler: scheduler::GpuScheduler,
+    capture: capture::FrameCapture,
+    // buffers and textures created by the application, see create_buffer
+    pub handles: registry::ResourceHandles,
+    // files imported again when they change, only filled when hot reloading is enabled
+    pub assets: assets::AssetRegistry,
+    // what the meshes, textures and buffers above were created from, see recover_device
+    sources: assets::AssetSources,
+    // textures of destroyed handles and imported models, given up by the garbage collector
+    texture_cache: texture::TextureCache,
+    model_cache: assets::ModelCache,
+    // shared with threads that upload assets or record commands, see shared_device
+    device: Arc<device::Device>,
+    // set once shutdown has started, no frames are rendered afterwards
+    is_shut_down: bool,
+    // set while the window is minimized or the application is in the background, no
+    // frames are rendered until the swapchain can be recreated, see resume
+    is_suspended: bool,
+
+    surface_info: surface::SurfaceInfo,
+    // instance needs to be dropped last
+    instance: instance::VulkanInstance,
+}
+
+impl Engine {
+    pub fn init_window<T>(config: &EngineConfig, event_loop: &EventLoop<T>) -> Result<Window> {
+        let icon = match config.window_icon.as_ref() {
+            Some(path) => Some(display::load_icon(path)?),
+            None => None,
+        };
+
+        let window = WindowBuilder::new()
+            .with_title(config.title.clone())
+            .with_inner_size(winit::dpi::LogicalSize::new(config.width, config.height))
+            .with_window_icon(icon)
+            .build(event_loop)
+            .context("failed to create window")?;
+
+        if config.window_mode != display::WindowMode::Windowed {
+            display::apply(&window, config.window_mode);
+        }
+
+        Ok(window)
+    }
+
+    fn setup(
+        instance: &instance::VulkanInstance,
+        config: &EngineConfig,
+        window: &Window,
+        surface_info: &surface::SurfaceInfo,
+    ) -> Result<(
+        sync::Objects<app::UniformBuffer>,
+        warmup::PipelineWarmup,
+        upload::UploadManager,
+        device::Device,
+    )> {
+        let _span = tracing::info_span!("setup").entered();
+
+        let device = device::Device::with_requirements(
+            &instance.instance,
+            surface_info,
+            &config.device_selection,
+            &config.device_requirements,
+        )?
+        .with_debug_utils(instance.debug_utils());
+
+        if !config
+            .vertex_format
+            .is_supported(&instance.instance, &device)
+        {
+            return Err(Error::Unsupported(format!(
+                "the device cannot read {:?} vertices",
+                config.vertex_format
+            )));
+        }
+
+        let (objects, pipeline_warmup, uploads) =
+            Engine::setup_frame(instance, &device, config, window, surface_info, None)?;
+
+        Ok((objects, pipeline_warmup, uploads, device))
+    }
+
+    // Everything depending on the swapchain, created again by recreate_swapchain
+    fn setup_frame(
+        instance: &instance::VulkanInstance,
+        device: &device::Device,
+        config: &EngineConfig,
+        window: &Window,
+        surface_info: &surface::SurfaceInfo,
+        // the scene mesh pool of the previous frame objects, see recreate_swapchain
+        mesh: Option<buffers::MeshBuffers>,
+    ) -> Result<(
+        sync::Objects<app::UniformBuffer>,
+        warmup::PipelineWarmup,
+        upload::UploadManager,
+    )> {
+        let _span = tracing::info_span!("setup_frame").entered();
+
+        let queue = queue::Queue::new(device);
+
+        // portability implementations like MoltenVK only have a few drawables to present to
+        let swapchain_config = match device.portability {
+            Some(portability) => portability.swapchain_config(&config.swapchain),
+            None => config.swapchain.clone(),
+        };
+
+        let swapchain = swapchain::SwapchainDetails::new(
+            &instance.instance,
+            device,
+            window,
+            &device.family_indices,
+            surface_info,
+            &swapchain_config,
+        )?;
+        tracing::info!("swapchain created");
+
+        // on an HDR swapchain the last pass of each chain encodes the image for its color
+        // space
+        let viewport_configs = config
+            .viewports
+            .iter()
+            .map(|viewport_config| {
+                if viewport_config.post_process.is_empty() && swapchain.is_hdr() {
+                    let post_process = viewport_config
+                        .post_process
+                        .clone()
+                        .with_effect(viewport::PostProcessEffect::Tonemap);
+                    viewport_config.clone().with_post_process(post_process)
+                } else {
+                    viewport_config.clone()
+                }
+            })
+            .collect::<Vec<viewport::ViewportConfig>>();
+
+        let viewports = if config.present_shader.is_some() {
+            let post_processed = viewport_configs
+                .iter()
+                .any(|viewport_config| !viewport_config.post_process.is_empty());
+            if post_processed {
+                tracing::warn!("post processing is skipped when presenting with a compute shader");
+            }
+            None
+        } else {
+            viewport::create_viewport_targets(
+                device,
+                queue.graphics,
+                &swapchain,
+                &viewport_configs,
+            )?
+        };
+
+        // the scene is rendered into the viewports' scene target instead of the swapchain images
+        let (scene_target, scene_images, scene_views) = match viewports.as_ref() {
+            Some(viewports) => (
+                viewports.scene_target(),
+                viewports.scene_images(),
+                viewports.scene_views(),
+            ),
+            None => (
+                pipeline::ColorTarget::swapchain(swapchain.format.format),
+                swapchain.images.clone(),
+                swapchain.image_views.clone(),
+            ),
+        };
+
+        // no render pass or framebuffers for the scene pass where the device allows it
+        let scene_target = if device.dynamic_rendering {
+            scene_target.with_dynamic_rendering()
+        } else {
+            scene_target
+        };
+
+        let shaders = shaderc::ShaderSource {
+            vertex_shader_file: config.vertex_shader_file.clone(),
+            fragment_shader_file: config.fragment_shader_file.clone(),
+        };
+
+        let mut pipeline_warmup = warmup::PipelineWarmup::start(
+            &instance.instance,
+            device,
+            scene_target,
+            &config.pipeline_cache_file,
+            &config.pipeline_manifest_file,
+            config.vertex_format,
+        )?;
+        pipeline_warmup.record(&shaders, &config.pipeline_state);
+
+        let scene_bindings = pipeline::PipelineDetail::scene_bindings()
+            .into_iter()
+            .chain(config.scene_buffers.iter().cloned())
+            .collect::<Vec<descriptor::Binding>>();
+
+        let pipeline_detail = pipeline::PipelineDetail::create_graphics_pipeline_with_bindings(
+            &instance.instance,
+            device,
+            scene_target,
+            shaders.clone(),
+            config.vertex_format,
+            &config.pipeline_state,
+            pipeline_warmup.cache.cache,
+            &scene_bindings,
+        )?;
+        pipeline_detail.set_name(device, "scene");
+        tracing::info!("pipeline created");
+
+        let permutations = permutation::PermutationManager::new(
+            shaders,
+            config.vertex_format,
+            config.pipeline_state,
+            &pipeline_detail,
+            pipeline_warmup.cache.cache,
+        );
+
+        let uniform_buffer_data =
+            app::UniformBuffer::with_projection(swapchain.extent, &config.projection);
+
+        let mut uploads = upload::UploadManager::new(
+            device,
+            queue.graphics,
+            upload::DEFAULT_STAGING_SIZE,
+            upload::DEFAULT_RING_SIZE,
+        )?;
+
+        let (vertices, indices, texture_file) = match &config.mesh {
+            MeshSource::BuiltIn => (
+                app::VERTICES.to_vec(),
+                app::INDICES.to_vec(),
+                config.texture_file.clone(),
+            ),
+            MeshSource::Obj(path) => {
+                let mut model = obj::ObjModel::load(path)?;
+                if config.optimize_meshes {
+                    model.optimize();
+                }
+
+                let texture_file = model
+                    .diffuse_texture()
+                    .map(Path::to_path_buf)
+                    .unwrap_or_else(|| config.texture_file.clone());
+                let (vertices, indices) = model.merged();
+
+                (vertices, indices, texture_file)
+            }
+        };
+
+        // the kept pool already holds the scene mesh and the meshes loaded since
+        let mesh = match mesh {
+            Some(mesh) => mesh,
+            None => {
+                // bounds are computed once from the loaded vertices and culled every frame
+                let mesh_bounds =
+                    bounds::MeshBounds::from_positions(vertices.iter().map(|vertex| vertex.pos))
+                        .context("the scene mesh has no vertices")?;
+
+                let mesh = match config.vertex_format {
+                    app::VertexFormat::Full => buffers::MeshBuffers::new(
+                        device,
+                        &mut uploads,
+                        &vertices,
+                        &indices,
+                        config.mesh_pool_vertices,
+                        config.mesh_pool_indices,
+                    )?,
+                    app::VertexFormat::Compressed => buffers::MeshBuffers::new(
+                        device,
+                        &mut uploads,
+                        &app::compress_vertices(&vertices),
+                        &indices,
+                        config.mesh_pool_vertices,
+                        config.mesh_pool_indices,
+                    )?,
+                };
+
+                mesh.with_bounds(mesh_bounds)
+            }
+        };
+
+        let mut buffer_details = buffers::BufferDetails::new(
+            &instance.instance,
+            device,
+            queue.graphics,
+            pipeline_detail,
+            scene_target,
+            &swapchain,
+            &scene_images,
+            &scene_views,
+            mesh,
+            uniform_buffer_data,
+            texture_file.as_path(),
+        )?;
+        tracing::info!("buffers created");
+
+        let overlay = if config.ui_overlay {
+            let ui_shaders = shaderc::ShaderSource {
+                vertex_shader_file: "shaders/ui.vert".to_string(),
+                fragment_shader_file: "shaders/ui.frag".to_string(),
+            };
+
+            Some(ui::UiOverlay::new(device, &swapchain, ui_shaders)?)
+        } else {
+            None
+        };
+
+        buffer_details.profiler.budgets = config.gpu_budgets.clone();
+        buffer_details.permutations = Some(permutations);
+        buffer_details.set_render_settings(config.render_settings);
+
+        if config.deferred {
+            if deferred::DeferredPass::is_supported(&device.limits) {
+                let deferred_pass = deferred::DeferredPass::new(
+                    &instance.instance,
+                    device,
+                    buffer_details.commands.pool,
+                    queue.graphics,
+                    &buffer_details.pipeline,
+                    scene_target,
+                    &scene_views,
+                    swapchain.extent,
+                    &buffer_details.uniforms.light_buffers().buffers,
+                )?;
+                buffer_details.set_deferred(device, deferred_pass);
+            } else {
+                tracing::warn!(
+                    "the device has too few attachments for the deferred path, shading forward"
+                );
+            }
+        }
+
+        let frames_in_flight = match device.portability {
+            Some(portability) => {
+                portability.frames_in_flight(config.frames_in_flight, swapchain.images.len() as u32)
+            }
+            None => config.frames_in_flight,
+        };
+
+        let mut objects = sync::Objects::new(
+            device.logical_device.clone(),
+            queue,
+            swapchain,
+            buffer_details,
+            frames_in_flight,
+        )?;
+        objects.overlay = overlay;
+        objects.viewports = viewports;
+
+        if config.timeline_semaphores {
+            if device.timeline_semaphore {
+                objects = objects.with_timeline(timeline::TimelineSemaphore::new(
+                    &instance.instance,
+                    device,
+                )?);
+            } else {
+                tracing::warn!("timeline semaphores are not supported, using fences");
+            }
+        }
+
+        if config.debug_lines {
+            objects.debug_lines = Some(debug_lines::DebugLineRenderer::new(
+                device,
+                &objects.swapchain_details,
+            )?);
+        }
+
+        if let Some(emitter) = config.particles {
+            if particles::ParticleSystem::is_supported(&instance.instance, device) {
+                objects.particles = Some(particles::ParticleSystem::new(
+                    &instance.instance,
+                    device,
+                    &objects.swapchain_details,
+                    emitter,
+                )?);
+            } else {
+                tracing::warn!("graphics queue does not support compute, particles are disabled");
+            }
+        }
+
+        if let Some(present_shader) = config.present_shader.as_ref() {
+            let swapchain = &objects.swapchain_details;
+
+            if present::ComputePresenter::is_supported(&instance.instance, device, swapchain) {
+                objects.compute_present = Some(present::ComputePresenter::new(
+                    &instance.instance,
+                    device,
+                    swapchain,
+                    present_shader,
+                )?);
+            } else {
+                tracing::warn!(
+                    "swapchain format {:?} does not support storage writes, presenting with the render pass",
+                    swapchain.format.format
+                );
+            }
+        }
+
+        if !config.image_effects.is_empty() {
+            let swapchain = &objects.swapchain_details;
+
+            if image_effects::ComputeImageEffects::is_supported_by_swapchain(
+                &instance.instance,
+                device,
+                swapchain,
+            ) {
+                objects.image_effects = Some(image_effects::ComputeImageEffects::new(
+                    &instance.instance,
+                    device,
+                    objects.queue.graphics,
+                    image_effects::EffectTargets::swapchain(swapchain),
+                    config.image_effects.clone(),
+                )?);
+            } else {
+                tracing::warn!(
+                    "swapchain format {:?} does not support storage writes, image effects are disabled",
+                    swapchain.format.format
+                );
+            }
+        }
+
+        Ok((objects, pipeline_warmup, uploads))
+    }
+
+    // Every gpu of the system, to choose one before the engine is created, eg. the discrete
+    // gpu of a laptop with hybrid graphics. The choice is made with
+    // EngineConfig::device_selection, see adapter::AdapterInfo::selection.
+    pub fn enumerate_adapters(
+        config: &EngineConfig,
+        window: &Window,
+    ) -> Result<Vec<adapter::AdapterInfo>> {
+        let instance = instance::VulkanInstance::new()?;
+        let surface_info = surface::SurfaceInfo::new(&instance, window)?;
+
+        let adapters = adapter::enumerate_adapters(
+            &instance.instance,
+            &surface_info,
+            &config.device_requirements,
+        );
+
+        surface_info.destroy();
+        adapters
+    }
+
+    pub fn new(mut config: EngineConfig, window: &Window) -> Result<Engine> {
+        let instance =
+            instance::VulkanInstance::with_debug_messenger(&[], config.debug_messenger.clone())?;
+
+        let surface_info = surface::SurfaceInfo::new(&instance, window)?;
+
+        let (frame, pipeline_warmup, uploads, device) =
+            Engine::setup(&instance, &config, window, &surface_info)?;
+
+        let scheduler = scheduler::GpuScheduler::new(
+            &device,
+            frame.queue.graphics,
+            config.background_budget_ms,
+        )?;
+
+        let mut assets = assets::AssetRegistry::new();
+        if config.hot_reload {
+            assets.watch(&config.vertex_shader_file, assets::AssetKind::SceneShader);
+            assets.watch(&config.fragment_shader_file, assets::AssetKind::SceneShader);
+            assets.watch(&config.texture_file, assets::AssetKind::SceneTexture);
+        }
+
+        let mut input = input::InputMap::new();
+        if config.cursor_mode != display::CursorMode::Normal {
+            config.cursor_mode = display::set_cursor_mode(window, config.cursor_mode);
+            input.set_relative_mouse(config.cursor_mode == display::CursorMode::Locked);
+        }
+
+        Ok(Engine {
+            config,
+            frame,
+            application: None,
+            debug_draw: debug_draw::DebugDraw::new(),
+            input,
+            world: scene::World::new(),
+            events: engine_events::EngineEvents::default(),
+            scene_buffer_bindings: BTreeMap::new(),
+            last_frame_time: Instant::now(),
+            pipeline_warmup,
+            uploads,
+            scheduler,
+            capture: capture::FrameCapture::new(),
+            handles: registry::ResourceHandles::new(),
+            assets,
+            sources: assets::AssetSources::new(),
+            texture_cache: texture::TextureCache::new(&device, texture::TEXTURE_CACHE_CAPACITY),
+            model_cache: assets::ModelCache::new(assets::MODEL_CACHE_CAPACITY),
+            device: Arc::new(device),
+            is_shut_down: false,
+            is_suspended: false,
+            surface_info,
+            instance,
+        })
+    }
+
+    pub fn with_application(mut self, application: Box<dyn Application>) -> Engine {
+        self.application = Some(application);
+        self
+    }
+
+    // Runs the event loop until the window is closed, for applications that react through
+    // their hooks and engine events instead of owning the loop. Resizes, suspending, out of
+    // date swapchains and lost devices are handled like the embedders of the engine would.
+    pub fn run<T: 'static>(mut self, window: Window, event_loop: EventLoop<T>) -> ! {
+        event_loop.run(move |event, _, control_flow| match event {
+            Event::WindowEvent { event, .. } => {
+                // the application sees the resize before the swapchain is recreated for it
+                let actions = self.on_event(&event);
+
+                match event {
+                    WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
+
+                    WindowEvent::Resized(_) | WindowEvent::ScaleFactorChanged { .. } => {
+                        if let Err(e) = self.recreate_swapchain(&window) {
+                            tracing::error!("cannot recreate swapchain: {}", e);
+                        }
+                    }
+
+                    _ => (),
+                }
+
+                for action in actions.iter() {
+                    let window = &window;
+                    let hook = |application: &mut dyn Application, engine: &mut Engine| {
+                        application.on_action(engine, window, action)
+                    };
+                    if self.call_application(hook) == Some(LoopAction::Exit) {
+                        *control_flow = ControlFlow::Exit;
+                    }
+                }
+            }
+
+            Event::DeviceEvent { device_id, event } => self.on_device_event(device_id, &event),
+
+            // no redraws are requested while suspended, the loop waits for the window to be restored
+            Event::MainEventsCleared => {
+                if self.is_suspended() {
+                    *control_flow = ControlFlow::Wait;
+                } else {
+                    *control_flow = ControlFlow::Poll;
+                    window.request_redraw();
+                }
+            }
+
+            Event::Suspended => self.suspend(),
+
+            Event::Resumed => {
+                if let Err(e) = self.resume(&window) {
+                    tracing::error!("cannot resume rendering: {}", e);
+                }
+            }
+
+            Event::RedrawRequested(_window_id) => match self.render_frame() {
+                Ok(_) => {
+                    let hook = |application: &mut dyn Application, engine: &mut Engine| {
+                        application.frame_rendered(engine)
+                    };
+                    if self.call_application(hook) == Some(LoopAction::Exit) {
+                        *control_flow = ControlFlow::Exit;
+                    }
+                }
+                Err(e) if e.is_swapchain_out_of_date() => {
+                    if let Err(e) = self.recreate_swapchain(&window) {
+                        tracing::error!("cannot recreate swapchain: {}", e);
+                    }
+                }
+                Err(e) if e.is_device_lost() => match self.handle_device_lost(&window, &e) {
+                    Ok(DeviceLostAction::Recover) => (),
+                    Ok(DeviceLostAction::Exit) => *control_flow = ControlFlow::Exit,
+                    Err(e) => {
+                        tracing::error!("cannot recover from the lost device: {}", e);
+                        *control_flow = ControlFlow::Exit;
+                    }
+                },
+                Err(e) => {
+                    tracing::error!("cannot render frame: {}", e);
+                    *control_flow = ControlFlow::Exit;
+                }
+            },
+
+            Event::LoopDestroyed => {
+                if let Err(e) = self.shutdown() {
+                    tracing::error!("failed to shut down cleanly: {:?}", e);
+                }
+            }
+
+            _ => (),
+        })
+    }
+
+    // Calls the hook with the application taken out of the engine, None without one
+    fn call_application<F, R>(&mut self, hook: F) -> Option<R>
+    where
+        F: FnOnce(&mut dyn Application, &mut Engine) -> R,
+    {
+        let mut application = self.application.take()?;
+        let result = hook(application.as_mut(), self);
+        self.application = Some(application);
+        Some(result)
+    }
+
+    // Calls back on every renderer lifecycle event, see vulkan::events
+    pub fn subscribe<F>(&mut self, callback: F)
+    where
+        F: FnMut(&events::RenderEvent) + Send + 'static,
+    {
+        self.frame.events.subscribe(callback);
+    }
+
+    pub fn event_channel(&mut self) -> std::sync::mpsc::Receiver<events::RenderEvent> {
+        self.frame.events.channel()
+    }
+
+    // Calls back on window, device hotplug and swapchain notifications, see engine_events
+    pub fn subscribe_events<F>(&mut self, callback: F)
+    where
+        F: FnMut(&engine_events::EngineEvent) + Send + 'static,
+    {
+        self.events.subscribe(callback);
+    }
+
+    pub fn engine_event_channel(
+        &mut self,
+    ) -> std::sync::mpsc::Receiver<engine_events::EngineEvent> {
+        self.events.channel()
+    }
+
+    // The notifications since the last call, eg. polled once per frame
+    pub fn drain_events(&mut self) -> Vec<engine_events::EngineEvent> {
+        self.events.drain()
+    }
+
+    fn emit_event(&mut self, event: engine_events::EngineEvent) {
+        if let Some(application) = self.application.as_mut() {
+            application.on_engine_event(&event);
+        }
+
+        self.events.emit(event);
+    }
+
+    // Returns the actions the event pressed or released
+    pub fn on_event(&mut self, event: &WindowEvent) -> Vec<input::ActionEvent> {
+        if let Some(application) = self.application.as_mut() {
+            application.on_event(event);
+        }
+
+        if let Some(event) = engine_events::EngineEvent::from_window_event(event) {
+            self.emit_event(event);
+        }
+
+        self.input.handle_event(event)
+    }
+
+    // Device events of the event loop, eg. the mouse motion of a locked cursor or a
+    // device being plugged in
+    pub fn on_device_event(&mut self, device_id: DeviceId, event: &DeviceEvent) {
+        if let Some(event) = engine_events::EngineEvent::from_device_event(device_id, event) {
+            self.emit_event(event);
+        }
+
+        self.input.handle_device_event(event);
+    }
+
+    pub fn render_frame(&mut self) -> Result<()> {
+        if self.is_shut_down || self.is_suspended {
+            return Ok(());
+        }
+
+        let _span = tracing::trace_span!("frame").entered();
+
+        if self.config.hot_reload {
+            self.reload_changed_assets();
+        }
+
+        let delta_time = self.last_frame_time.elapsed().as_secs_f32();
+        self.last_frame_time = Instant::now();
+
+        if let Some(application) = self.application.as_mut() {
+            application.handle_input(&self.input);
+            application.update(delta_time);
+            application.update_world(&mut self.world, delta_time);
+
+            if let Some(overlay) = self.frame.overlay.as_mut() {
+                overlay.draw_list.clear();
+                application.build_ui(&mut overlay.draw_list);
+            }
+        }
+        self.input.end_frame();
+        scene::animate(&mut self.world, delta_time);
+        self.sync_world()?;
+
+        if let Some(debug_lines) = self.frame.debug_lines.as_mut() {
+            if let Some(application) = self.application.as_mut() {
+                self.debug_draw.clear();
+                application.build_debug_draw(&mut self.debug_draw);
+            }
+
+            let camera = &self.frame.buffers.uniforms.data;
+            debug_lines.set_lines(&self.debug_draw.lines, camera.proj * camera.view);
+        }
+
+        if let Some(particles) = self.frame.particles.as_mut() {
+            let camera = &self.frame.buffers.uniforms.data;
+            particles.set_camera(camera.view, camera.proj);
+        }
+
+        self.scheduler.submit_frame()?;
+
+        self.capture.begin_frame();
+        let result = self
+            .frame
+            .draw_next_frame(&mut [&mut self.texture_cache, &mut self.model_cache]);
+        self.capture.end_frame();
+
+        if let Some(stats) = self.config.debug_messenger.stats.as_ref() {
+            stats.end_frame();
+        }
+
+        result
+    }
+
+    // Records the next rendered frame with RenderDoc, see vulkan::capture
+    pub fn capture_next_frame(&mut self) -> Result<()> {
+        self.capture.capture_next_frame()
+    }
+
+    pub fn wait_idle(&self) -> Result<()> {
+        unsafe {
+            self.frame
+                .device
+                .device_wait_idle()
+                .context("failed to wait for device idle")
+        }
+    }
+
+    // Saves the pipeline manifest and cache so the next run can warm up its pipelines
+    pub fn save_pipeline_data(&mut self) -> Result<()> {
+        self.pipeline_warmup.finish(&self.frame.device)
+    }
+
+    // Stops rendering and destroys all vulkan objects in dependency order.
+    // The instance itself is destroyed when the engine is dropped.
+    pub fn shutdown(&mut self) -> Result<()> {
+        if self.is_shut_down {
+            return Ok(());
+        }
+        self.is_shut_down = true;
+
+        self.frame.wait_for_in_flight_frames()?;
+        self.wait_idle()?;
+
+        // joins the warm-up thread, which may still be creating pipelines on the device
+        let saved = self.save_pipeline_data();
+
+        self.frame.destroy(
+            &self.device,
+            &mut [&mut self.texture_cache, &mut self.model_cache],
+        );
+        self.handles.destroy(&self.device);
+        self.uploads.destroy();
+        self.scheduler.destroy();
+        self.pipeline_warmup
+            .cache
+            .destroy(&self.device.logical_device);
+        self.device.destroy();
+        self.surface_info.destroy();
+
+        saved
+    }
+
+    // Releases every deferred resource right away, eg. when unloading a scene
+    pub fn flush_garbage(&mut self) -> Result<()> {
+        self.wait_idle()?;
+        self.frame.garbage.flush(
+            &self.frame.device,
+            &mut [&mut self.texture_cache, &mut self.model_cache],
+        );
+        Ok(())
+    }
+
+    // The device for use on other threads. It is destroyed on shutdown, so threads have to
+    // be done with it by then.
+    pub fn shared_device(&self) -> Arc<device::Device> {
+        self.device.clone()
+    }
+
+    // The graphics queue for submissions from other threads, the render loop submits
+    // through the same lock
+    pub fn graphics_queue(&self) -> queue::SharedQueue {
+        self.frame.queue.graphics_submit.clone()
+    }
+
+    // Buffers and images currently alive, eg. to assert on the resources a scene creates
+    pub fn resource_snapshot(&self) -> registry::ResourceSnapshot {
+        self.device.resource_snapshot()
+    }
+
+    // All gpus of the system along with their score and whether they can be used
+    pub fn adapters(&self) -> Result<Vec<adapter::AdapterInfo>> {
+        adapter::enumerate_adapters(
+            &self.instance.instance,
+            &self.surface_info,
+            &self.config.device_requirements,
+        )
+    }
+
+    // Whether an optional feature of EngineConfig::device_requirements was enabled, so
+    // render paths depending on it can be taken
+    pub fn is_feature_enabled(&self, feature: requirements::DeviceFeature) -> bool {
+        self.device.is_feature_enabled(feature)
+    }
+
+    pub fn is_extension_enabled(&self, name: &str) -> bool {
+        self.device.is_extension_enabled(name)
+    }
+
+    // Feeds the output of the scene systems to the scene pass. A world without cameras,
+    // lights or mesh renderers leaves that part to set_lighting and the uniform data.
+    fn sync_world(&mut self) -> Result<()> {
+        let buffers = &mut self.frame.buffers;
+        let extent = buffers.extent();
+        let aspect = extent.width as f32 / extent.height as f32;
+
+        if let Some((view, proj)) = scene::camera_matrices(&self.world, aspect) {
+            let mut data = buffers.uniforms.data;
+            if data.view != view || data.proj != proj {
+                data.view = view;
+                data.proj = proj;
+                buffers.set_uniform_data(data);
+            }
+        }
+
+        let lights = scene::lights(&self.world);
+        if !lights.is_empty() {
+            let mut lighting = buffers.uniforms.lighting.clone();
+            lighting.lights = lights;
+            if let Some((camera, _)) = scene::active_camera(&self.world) {
+                lighting.camera_position = self.world.transform(camera).position.into();
+            }
+
+            if lighting != buffers.uniforms.lighting {
+                buffers.set_lighting(lighting);
+            }
+        }
+
+        if self.world.query::<scene::MeshRenderer>().next().is_some() {
+            buffers.set_draws(scene::draw_list(&self.world))?;
+        }
+
+        Ok(())
+    }
+
+    // Projection used while the world has no active camera
+    pub fn set_projection(&mut self, projection: projection::Projection) {
+        let buffers = &mut self.frame.buffers;
+        let extent = buffers.extent();
+
+        let mut data = buffers.uniforms.data;
+        data.proj = projection.matrix(extent.width as f32 / extent.height as f32);
+        buffers.set_uniform_data(data);
+
+        self.config.projection = projection;
+    }
+
+    pub fn set_lighting(&mut self, lighting: lighting::Lighting) {
+        self.frame.buffers.set_lighting(lighting);
+    }
+
+    // Points a binding of EngineConfig::scene_buffers at the buffer range.
+    // Waits for the device to be idle as the descriptor sets of every frame are rewritten.
+    pub fn bind_storage_buffer(
+        &mut self,
+        binding: u32,
+        buffer_info: vk::DescriptorBufferInfo,
+    ) -> Result<()> {
+        self.wait_idle()?;
+        self.frame
+            .buffers
+            .bind_storage_buffer(&self.frame.device, binding, buffer_info)?;
+
+        self.scene_buffer_bindings
+            .insert(binding, SceneBufferBinding::Storage(buffer_info));
+        Ok(())
+    }
+
+    // Draws the scene with one object of the buffer bound to a dynamic uniform binding of
+    // EngineConfig::scene_buffers. The objects have to be uploaded before each image is
+    // drawn, eg. from an observer of the scene's PassBegin event.
+    pub fn bind_object_uniforms<T: Copy>(
+        &mut self,
+        binding: u32,
+        uniforms: &object_uniforms::ObjectUniforms<T>,
+        object: usize,
+    ) -> Result<()> {
+        let buffer_info = uniforms.descriptor_info()?;
+        let image_offsets = uniforms.image_offsets(object)?;
+
+        self.wait_idle()?;
+        self.frame.buffers.bind_dynamic_uniform_buffer(
+            &self.frame.device,
+            binding,
+            buffer_info,
+            &image_offsets,
+        )?;
+
+        self.scene_buffer_bindings.insert(
+            binding,
+            SceneBufferBinding::DynamicUniform(buffer_info, image_offsets),
+        );
+        Ok(())
+    }
+
+    // Writes the bound scene buffers to the descriptor sets created with the swapchain.
+    // Object uniforms created for another number of images are left for the application
+    // to bind again.
+    fn restore_scene_buffer_bindings(&mut self) -> Result<()> {
+        let image_count = self.frame.swapchain_details.images.len();
+
+        for (&binding, bound) in self.scene_buffer_bindings.iter() {
+            match bound {
+                SceneBufferBinding::Storage(buffer_info) => self
+                    .frame
+                    .buffers
+                    .bind_storage_buffer(&self.frame.device, binding, *buffer_info)?,
+                SceneBufferBinding::DynamicUniform(_, image_offsets)
+                    if image_offsets.len() != image_count =>
+                {
+                    tracing::warn!(
+                        "the object uniforms of binding {} were created for {} images, the \
+                         swapchain has {}, they have to be bound again",
+                        binding,
+                        image_offsets.len(),
+                        image_count
+                    );
+                }
+                SceneBufferBinding::DynamicUniform(buffer_info, image_offsets) => {
+                    self.frame.buffers.bind_dynamic_uniform_buffer(
+                        &self.frame.device,
+                        binding,
+                        *buffer_info,
+                        image_offsets,
+                    )?
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Index into the scene's draws of the one under the pixel, eg. the cursor position in
+    // physical pixels. Renders the ids of all draws and waits for them, see
+    // picking::PickingPass.
+    pub fn pick(&mut self, x: u32, y: u32) -> Result<Option<usize>> {
+        let buffers = &self.frame.buffers;
+        let extent = buffers.extent();
+
+        if self.frame.picking.is_none() {
+            self.frame.picking = Some(picking::PickingPass::new(
+                &self.instance.instance,
+                &self.device,
+                buffers.commands.pool,
+                self.frame.queue.graphics,
+                extent,
+                self.config.vertex_format,
+            )?);
+        }
+
+        let picking = match self.frame.picking.as_ref() {
+            Some(picking) => picking,
+            None => return Ok(None),
+        };
+
+        let data = &buffers.uniforms.data;
+        let mesh = buffers.mesh()?;
+        let id = picking.pick(
+            buffers.commands.pool,
+            self.frame.queue.graphics,
+            mesh.pool.vertex_buffer.buffer,
+            &mesh.pool.index_buffer,
+            buffers.draws(),
+            data.proj * data.view * data.model,
+            (x, y),
+        )?;
+
+        Ok(picking::draw_index(id))
+    }
+
+    // The entity whose mesh renderer is under the pixel, see pick
+    pub fn pick_entity(&mut self, x: u32, y: u32) -> Result<Option<scene::Entity>> {
+        let draw = match self.pick(x, y)? {
+            Some(draw) => draw,
+            None => return Ok(None),
+        };
+
+        // the draws are in the order of scene::draw_list
+        Ok(self
+            .world
+            .query::<scene::MeshRenderer>()
+            .nth(draw)
+            .map(|(entity, _)| entity))
+    }
+
+    // Uploads a mesh into the scene's mesh pool. It is drawn by an entity with a
+    // scene::MeshRenderer::from_mesh of the returned allocation.
+    pub fn load_mesh(
+        &mut self,
+        vertices: &[app::VertexData],
+        indices: &[u32],
+    ) -> Result<mesh_pool::MeshAllocation> {
+        let uploads = &mut self.uploads;
+        let mesh = self.frame.buffers.mesh_mut()?;
+        let allocation = match self.config.vertex_format {
+            app::VertexFormat::Full => mesh.load_mesh(uploads, vertices, indices)?,
+            app::VertexFormat::Compressed => {
+                mesh.load_mesh(uploads, &app::compress_vertices(vertices), indices)?
+            }
+        };
+
+        self.sources.add_mesh(allocation, vertices, indices);
+        Ok(allocation)
+    }
+
+    // One allocation per mesh of the file, in the order of obj::ObjModel::meshes
+    pub fn load_obj(&mut self, path: &Path) -> Result<Vec<mesh_pool::MeshAllocation>> {
+        let meshes = self.import_obj(path)?;
+
+        if self.config.hot_reload {
+            self.assets
+                .watch(path, assets::AssetKind::Model(meshes.clone()));
+        }
+
+        Ok(meshes)
+    }
+
+    fn import_obj(&mut self, path: &Path) -> Result<Vec<mesh_pool::MeshAllocation>> {
+        let model = match self.model_cache.get(path) {
+            Some(model) => model.clone(),
+            None => {
+                let mut model = obj::ObjModel::load(path)?;
+                if self.config.optimize_meshes {
+                    model.optimize();
+                }
+                self.model_cache.insert(path, model.clone());
+                model
+            }
+        };
+
+        model
+            .meshes
+            .iter()
+            .map(|mesh| self.load_mesh(&mesh.vertices, &mesh.indices))
+            .collect()
+    }
+
+    // One allocation per mesh, in the order of import::ImportedScene::meshes. The materials
+    // are added to a material library with ImportedScene::add_materials.
+    pub fn load_scene(
+        &mut self,
+        scene: &import::ImportedScene,
+    ) -> Result<Vec<mesh_pool::MeshAllocation>> {
+        scene
+            .meshes
+            .iter()
+            .map(|mesh| self.load_mesh(&mesh.vertices, &mesh.indices))
+            .collect()
+    }
+
+    // Skins meshes with a compute pass at the start of every frame, which the forward scene
+    // pass then draws, see vulkan::compute_skinning. Room for the bind pose vertices and
+    // indices of the meshes, and for the instances with up to output_capacity skinned
+    // vertices between them. Replaces the meshes and instances added before. They are
+    // lost along with the device, see Application::on_device_lost.
+    pub fn enable_compute_skinning(
+        &mut self,
+        vertex_capacity: u32,
+        index_capacity: u32,
+        instance_capacity: u32,
+        output_capacity: u32,
+    ) -> Result<()> {
+        if self.config.vertex_format != app::VertexFormat::Full || self.frame.buffers.is_deferred()
+        {
+            return Err(Error::Unsupported(
+                "skinned vertices are only drawn by the forward pass with full vertices"
+                    .to_string(),
+            ));
+        }
+
+        self.wait_idle()?;
+        let skinning = compute_skinning::ComputeSkinning::new(
+            &self.device,
+            vertex_capacity,
+            index_capacity,
+            instance_capacity,
+            output_capacity,
+            self.frame.swapchain_details.images.len() as u32,
+        )?;
+        self.frame.buffers.set_skinning(&self.device, skinning)
+    }
+
+    fn compute_skinning(&mut self) -> Result<&mut compute_skinning::ComputeSkinning> {
+        self.frame
+            .buffers
+            .skinning_mut()
+            .ok_or_else(|| Error::msg(buffers::SKINNING_DISABLED))
+    }
+
+    // The bind pose of a mesh, eg. the vertices and indices of a gltf::GltfModel
+    pub fn add_skinned_mesh(
+        &mut self,
+        vertices: &[skinning::SkinnedVertex],
+        indices: &[u32],
+    ) -> Result<compute_skinning::SkinnedMesh> {
+        self.wait_idle()?;
+        let device = self.device.clone();
+        let command_pool = self.frame.buffers.commands.pool;
+        let queue = self.frame.queue.graphics;
+
+        self.compute_skinning()?
+            .add_mesh(&device, command_pool, queue, vertices, indices)
+    }
+
+    // Drawn at bind pose until it is posed
+    pub fn add_skinned_instance(
+        &mut self,
+        mesh: compute_skinning::SkinnedMesh,
+    ) -> Result<compute_skinning::SkinnedInstance> {
+        let device = self.device.clone();
+        self.compute_skinning()?.add_instance(&device, mesh)
+    }
+
+    // Picked up by the next rendered frame, eg. with animation::AnimationPlayer::joint_matrices
+    pub fn pose_skinned_instance(
+        &mut self,
+        instance: &compute_skinning::SkinnedInstance,
+        palette: &skinning::BonePalette,
+    ) -> Result<()> {
+        self.frame.buffers.pose_skinned_instance(instance, palette)
+    }
+
+    pub fn clear_skinned_instances(&mut self) -> Result<()> {
+        self.wait_idle()?;
+        self.compute_skinning()?.clear_instances();
+        Ok(())
+    }
+
+    // One allocation per chunk, in the order of terrain::Terrain::chunks. Drawing only
+    // the allocations of Terrain::visible_chunks culls the rest.
+    pub fn load_terrain(
+        &mut self,
+        terrain: &terrain::Terrain,
+    ) -> Result<Vec<mesh_pool::MeshAllocation>> {
+        terrain
+            .chunks
+            .iter()
+            .map(|chunk| self.load_mesh(&chunk.vertices, &chunk.indices))
+            .collect()
+    }
+
+    // Gives the mesh's room in the pool back once no frame can be drawing it anymore.
+    // Entities drawing it have to be changed or despawned first.
+    pub fn free_mesh(&mut self, allocation: mesh_pool::MeshAllocation) -> Result<()> {
+        self.wait_idle()?;
+        self.frame.buffers.mesh_mut()?.pool.free(allocation);
+        self.assets.unwatch_mesh(&allocation);
+        self.sources.remove_mesh(&allocation);
+        Ok(())
+    }
+
+    // A buffer owned by the engine, reached through self.handles until destroy_buffer
+    pub fn create_buffer(
+        &mut self,
+        size: vk::DeviceSize,
+        usage: vk::BufferUsageFlags,
+        memory_properties: vk::MemoryPropertyFlags,
+    ) -> Result<registry::BufferHandle> {
+        let buffer = buffers::BufferInfo::create(&self.device, size, usage, memory_properties)?;
+        let handle = self.handles.add_buffer(buffer);
+
+        self.sources.add_buffer(
+            handle,
+            assets::BufferDescription {
+                size,
+                usage,
+                memory_properties,
+            },
+        );
+        Ok(handle)
+    }
+
+    // Destroys the buffer once no frame can be using it, the handle is stale afterwards
+    pub fn destroy_buffer(&mut self, handle: registry::BufferHandle) -> Result<()> {
+        self.wait_idle()?;
+        self.sources.remove_buffer(handle);
+        self.handles.destroy_buffer(&self.device, handle)
+    }
+
+    // A color texture owned by the engine, reached through self.handles until
+    // destroy_texture. A texture of the file destroyed before is reused while it is cached.
+    pub fn load_texture(&mut self, path: &Path) -> Result<registry::TextureHandle> {
+        let texture = match self.texture_cache.take(path) {
+            Some(texture) => texture,
+            None => self.import_texture(path)?,
+        };
+        let handle = self.handles.add_texture(texture);
+        self.sources.add_texture(handle, path);
+
+        if self.config.hot_reload {
+            self.assets.watch(path, assets::AssetKind::Texture(handle));
+        }
+
+        Ok(handle)
+    }
+
+    fn import_texture(&self, path: &Path) -> Result<texture::Texture> {
+        texture::Texture::new(
+            &self.device,
+            self.frame.buffers.commands.pool,
+            self.frame.queue.graphics,
+            path,
+        )
+    }
+
+    // The handle is stale afterwards, the texture is cached until the garbage collector
+    // evicts it, see flush_garbage
+    pub fn destroy_texture(&mut self, handle: registry::TextureHandle) -> Result<()> {
+        self.wait_idle()?;
+        let texture = self.handles.remove_texture(handle)?;
+        self.assets.unwatch_texture(handle);
+
+        let path = self
+            .sources
+            .textures
+            .iter()
+            .find(|(texture, _)| *texture == handle)
+            .map(|(_, path)| path.clone());
+        match path {
+            Some(path) => self.texture_cache.insert(path, texture),
+            None => texture.destroy(&self.device),
+        }
+        self.sources.remove_texture(handle);
+        Ok(())
+    }
+
+    // Imports the assets whose files changed and swaps them in, waiting for the gpu to be
+    // done with the old ones. An asset failing to import, eg. a shader with a syntax error
+    // while it is being edited, is reported and keeps its current version.
+    pub fn reload_changed_assets(&mut self) -> Vec<assets::AssetChange> {
+        let changes = self.assets.poll(Instant::now());
+        if changes.is_empty() {
+            return changes;
+        }
+
+        if let Err(err) = self.wait_idle() {
+            tracing::error!("cannot reload assets: {}", err);
+            return vec![];
+        }
+
+        let mut reloaded = vec![];
+        let mut shaders_reloaded = false;
+        for change in changes {
+            let result = match change.kind {
+                // both scene shaders are compiled by one rebuild
+                assets::AssetKind::SceneShader if shaders_reloaded => continue,
+                assets::AssetKind::SceneShader => {
+                    shaders_reloaded = true;
+                    self.frame
+                        .buffers
+                        .reload_shaders(&self.frame.device, &mut self.frame.garbage)
+                }
+                assets::AssetKind::SceneTexture => {
+                    self.import_texture(&change.path).and_then(|texture| {
+                        self.frame
+                            .buffers
+                            .replace_texture(&self.frame.device, texture)
+                    })
+                }
+                assets::AssetKind::Texture(handle) => {
+                    self.texture_cache.remove(&change.path);
+                    self.import_texture(&change.path).and_then(|texture| {
+                        self.handles.replace_texture(&self.device, handle, texture)
+                    })
+                }
+                assets::AssetKind::Model(ref meshes) => {
+                    let old_meshes = meshes.clone();
+                    self.reload_model(&change.path, &old_meshes)
+                }
+            };
+
+            match result {
+                Ok(()) => {
+                    tracing::info!("reloaded {}", change.path.display());
+                    if let Some(application) = self.application.as_mut() {
+                        application.on_asset_reloaded(&change);
+                    }
+                    reloaded.push(change);
+                }
+                Err(err) => tracing::error!("failed to reload {}: {}", change.path.display(), err),
+            }
+        }
+
+        reloaded
+    }
+
+    // Moves the mesh renderers drawing the old meshes to the new ones in order, the
+    // renderers of meshes the file no longer has are removed
+    fn reload_model(
+        &mut self,
+        path: &Path,
+        old_meshes: &[mesh_pool::MeshAllocation],
+    ) -> Result<()> {
+        self.model_cache.remove(path);
+        let meshes = self.import_obj(path)?;
+        self.move_mesh_renderers(old_meshes, &meshes)?;
+
+        let pool = &mut self.frame.buffers.mesh_mut()?.pool;
+        for old in old_meshes {
+            pool.free(*old);
+            self.sources.remove_mesh(old);
+        }
+
+        self.assets.set_model_meshes(path, meshes);
+        Ok(())
+    }
+
+    // Points the mesh renderers drawing the old meshes at the new ones in the same
+    // position, the renderers of old meshes without one are removed
+    fn move_mesh_renderers(
+        &mut self,
+        old_meshes: &[mesh_pool::MeshAllocation],
+        meshes: &[mesh_pool::MeshAllocation],
+    ) -> Result<()> {
+        let renderers = self
+            .world
+            .query::<scene::MeshRenderer>()
+            .map(|(entity, renderer)| (entity, *renderer))
+            .collect::<Vec<_>>();
+
+        for (entity, renderer) in renderers {
+            let position = old_meshes.iter().position(|old| {
+                old.first_index == renderer.first_index
+                    && old.first_vertex as i32 == renderer.vertex_offset
+            });
+
+            match position.map(|position| meshes.get(position)) {
+                Some(Some(mesh)) => {
+                    let moved = scene::MeshRenderer {
+                        first_index: mesh.first_index,
+                        index_count: mesh.index_count,
+                        vertex_offset: mesh.first_vertex as i32,
+                        ..renderer
+                    };
+                    self.world.insert(entity, moved)?;
+                }
+                Some(None) => {
+                    self.world.remove::<scene::MeshRenderer>(entity);
+                }
+                None => (),
+            }
+        }
+
+        Ok(())
+    }
+
+    // Changes how particles are spawned, needs `EngineConfig::particles` to be set
+    pub fn set_particle_emitter(&mut self, emitter: particles::EmitterConfig) -> Result<()> {
+        match self.frame.particles.as_mut() {
+            Some(particles) => particles.set_emitter(emitter),
+            None => Err(Error::Unsupported(
+                "particles are not enabled in the engine config".to_string(),
+            )),
+        }
+    }
+
+    // Switches the scene to a debug visualization, compiling its permutation if needed
+    pub fn set_debug_view(&mut self, view: permutation::DebugView) -> Result<()> {
+        self.frame.buffers.set_debug_view(&self.frame.device, view)
+    }
+
+    pub fn set_polygon_mode(&mut self, polygon_mode: vk::PolygonMode) -> Result<()> {
+        self.frame.buffers.set_polygon_mode(polygon_mode)
+    }
+
+    // Recreates the scene pipeline with new rasterizer, depth or blend state. The old
+    // pipeline is destroyed once the frames in flight using it have completed.
+    pub fn set_pipeline_state(&mut self, state: preset::FixedFunctionState) -> Result<()> {
+        self.frame
+            .buffers
+            .set_pipeline_state(&self.device, state, &mut self.frame.garbage)?;
+
+        self.pipeline_warmup
+            .record(self.frame.buffers.pipeline.shaders(), &state);
+        self.config.pipeline_state = state;
+
+        Ok(())
+    }
+
+    // Can be called every frame, the scene's commands are only re-recorded when the
+    // settings change. Kept when the swapchain is recreated.
+    pub fn set_render_settings(&mut self, settings: render_settings::RenderSettings) {
+        self.frame.buffers.set_render_settings(settings);
+        self.config.render_settings = settings;
+    }
+
+    pub fn render_settings(&self) -> render_settings::RenderSettings {
+        self.frame.buffers.render_settings()
+    }
+
+    pub fn set_clear_color(&mut self, clear_color: [f32; 4]) {
+        let settings = self.render_settings().with_clear_color(clear_color);
+        self.set_render_settings(settings);
+    }
+
+    pub fn polygon_mode(&self) -> vk::PolygonMode {
+        self.frame.buffers.pipeline.polygon_mode
+    }
+
+    pub fn toggle_wireframe(&mut self) -> Result<()> {
+        match self.polygon_mode() {
+            vk::PolygonMode::LINE => self.set_polygon_mode(vk::PolygonMode::FILL),
+            _ => self.set_polygon_mode(vk::PolygonMode::LINE),
+        }
+    }
+
+    pub fn debug_view(&self) -> permutation::DebugView {
+        self.frame
+            .buffers
+            .permutations
+            .as_ref()
+            .map(|permutations| permutations.current)
+            .unwrap_or_default()
+    }
+
+    pub fn frame_stats(&self) -> profiler::FrameStats {
+        self.frame.buffers.profiler.stats()
+    }
+
+    // Rebuilds everything depending on the swapchain for the current size of the window,
+    // eg. after a resize, a window mode change or an out of date swapchain. The texture of
+    // the config is loaded again, the mesh pool, lighting, polygon mode, debug view and
+    // observers are carried over.
+    pub fn recreate_swapchain(&mut self, window: &Window) -> Result<()> {
+        if self.is_shut_down {
+            return Ok(());
+        }
+
+        let _span = tracing::info_span!("recreate_swapchain").entered();
+
+        self.surface_info.update_size(window);
+
+        // a minimized window cannot be presented to, recreated once it is restored
+        if self.surface_info.is_minimized() {
+            self.suspend();
+            return Ok(());
+        }
+
+        self.frame.wait_for_in_flight_frames()?;
+        self.wait_idle()?;
+
+        let extent = swapchain::SwapchainDetails::surface_extent(&self.device, &self.surface_info)?;
+        if extent.width == 0 || extent.height == 0 {
+            self.suspend();
+            return Ok(());
+        }
+
+        // joins the warm-up thread and saves the cache the new pipelines are created from
+        self.save_pipeline_data()?;
+
+        let lighting = self.frame.buffers.uniforms.lighting.clone();
+        let polygon_mode = self.polygon_mode();
+        let debug_view = self.debug_view();
+        let observers = std::mem::take(&mut self.frame.events);
+        let mesh = self.frame.buffers.take_mesh();
+        let skinning = self.frame.buffers.take_skinning();
+
+        self.frame.destroy(
+            &self.device,
+            &mut [&mut self.texture_cache, &mut self.model_cache],
+        );
+        self.uploads.destroy();
+        self.pipeline_warmup
+            .cache
+            .destroy(&self.device.logical_device);
+
+        let (frame, pipeline_warmup, uploads) = match Engine::setup_frame(
+            &self.instance,
+            &self.device,
+            &self.config,
+            window,
+            &self.surface_info,
+            mesh,
+        ) {
+            Ok(created) => created,
+            Err(err) => {
+                // the old frame objects are gone, nothing can be rendered anymore
+                self.is_shut_down = true;
+                if let Some(skinning) = skinning {
+                    skinning.destroy(&self.device);
+                }
+                self.scheduler.destroy();
+                self.device.destroy();
+                self.surface_info.destroy();
+
+                return Err(err).context("failed to recreate the swapchain");
+            }
+        };
+
+        self.frame = frame;
+        self.pipeline_warmup = pipeline_warmup;
+        self.uploads = uploads;
+
+        self.frame.events = observers;
+        self.frame
+            .events
+            .emit(events::RenderEvent::swapchain_created(
+                &self.frame.swapchain_details,
+            ));
+        self.emit_event(engine_events::EngineEvent::swapchain_recreated(
+            &self.frame.swapchain_details,
+        ));
+
+        self.frame.buffers.set_lighting(lighting);
+        self.set_polygon_mode(polygon_mode)?;
+        self.set_debug_view(debug_view)?;
+        self.restore_scene_buffer_bindings()?;
+
+        if let Some(skinning) = skinning {
+            if let Err(err) = self.frame.buffers.set_skinning(&self.device, skinning) {
+                tracing::warn!("compute skinning is disabled: {}", err);
+            }
+        }
+
+        if self.is_suspended {
+            self.is_suspended = false;
+            // the time spent suspended is not passed to the application as one long frame
+            self.last_frame_time = Instant::now();
+            self.emit_event(engine_events::EngineEvent::Resumed);
+        }
+
+        Ok(())
+    }
+
+    // Stops rendering, eg. when the window is minimized or the application is sent to the
+    // background. The swapchain is kept, rendering continues after resume.
+    pub fn suspend(&mut self) {
+        if !self.is_suspended {
+            tracing::info!("rendering suspended");
+            self.is_suspended = true;
+            self.emit_event(engine_events::EngineEvent::Suspended);
+        }
+    }
+
+    // Recreates the swapchain for the restored window and continues rendering, or stays
+    // suspended while the window is still minimized
+    pub fn resume(&mut self, window: &Window) -> Result<()> {
+        if !self.is_suspended {
+            return Ok(());
+        }
+
+        self.recreate_swapchain(window)
+    }
+
+    pub fn is_suspended(&self) -> bool {
+        self.is_suspended
+    }
+
+    // Asks the application whether to recover from the lost device and does so, or
+    // releases what is left of the device. Called with the error of render_frame.
+    pub fn handle_device_lost(
+        &mut self,
+        window: &Window,
+        error: &Error,
+    ) -> Result<DeviceLostAction> {
+        tracing::error!("the device was lost: {}", error);
+
+        let action = match self.application.as_mut() {
+            Some(application) => application.on_device_lost(error),
+            None => DeviceLostAction::Recover,
+        };
+
+        match action {
+            DeviceLostAction::Recover => self.recover_device(window)?,
+            DeviceLostAction::Exit => {
+                if !self.is_shut_down {
+                    self.is_shut_down = true;
+                    self.destroy_lost_frame();
+                    self.handles.destroy(&self.device);
+                    self.device.destroy();
+                    self.surface_info.destroy();
+                }
+            }
+        }
+
+        Ok(action)
+    }
+
+    // Destroys the objects of the frame without waiting for the gpu, which a lost device
+    // reports as an error. Its work is never completed, so they can be destroyed right away.
+    fn destroy_lost_frame(&mut self) {
+        // joins the warm-up thread, the cache data may not be retrievable anymore
+        if let Err(err) = self.save_pipeline_data() {
+            tracing::warn!("cannot save the pipeline data of the lost device: {}", err);
+        }
+
+        self.frame.destroy(
+            &self.device,
+            &mut [&mut self.texture_cache, &mut self.model_cache],
+        );
+        self.uploads.destroy();
+        self.scheduler.destroy();
+        self.pipeline_warmup
+            .cache
+            .destroy(&self.device.logical_device);
+    }
+
+    // Creates the device, swapchain and every resource on them again after the device was
+    // lost. Meshes, textures and buffers created through the engine keep their allocations
+    // and handles, see assets::AssetSources, while pipelines added to the handles are gone.
+    // The settings and observers of the frame are carried over like in recreate_swapchain.
+    pub fn recover_device(&mut self, window: &Window) -> Result<()> {
+        if self.is_shut_down {
+            return Ok(());
+        }
+
+        let lighting = self.frame.buffers.uniforms.lighting.clone();
+        let polygon_mode = self.polygon_mode();
+        let debug_view = self.debug_view();
+        let observers = std::mem::take(&mut self.frame.events);
+
+        self.destroy_lost_frame();
+
+        // the handles are moved over to the new device before the old one is destroyed
+        let lost_device = (*self.device).clone();
+        let kept_buffers = self
+            .sources
+            .buffers
+            .iter()
+            .map(|(handle, _)| *handle)
+            .collect::<Vec<_>>();
+        let kept_textures = self
+            .sources
+            .textures
+            .iter()
+            .map(|(handle, _)| *handle)
+            .collect::<Vec<_>>();
+        self.handles
+            .destroy_except(&lost_device, &kept_buffers, &kept_textures);
+
+        self.surface_info.update_size(window);
+        let (frame, pipeline_warmup, uploads, device) =
+            match Engine::setup(&self.instance, &self.config, window, &self.surface_info) {
+                Ok(created) => created,
+                Err(err) => {
+                    self.is_shut_down = true;
+                    self.handles.destroy(&lost_device);
+                    lost_device.destroy();
+                    self.surface_info.destroy();
+
+                    return Err(err).context("failed to recreate the lost device");
+                }
+            };
+
+        self.device = Arc::new(device);
+        // emptied along with the lost frame
+        self.texture_cache = texture::TextureCache::new(&self.device, self.texture_cache.capacity);
+        self.frame = frame;
+        self.pipeline_warmup = pipeline_warmup;
+        self.uploads = uploads;
+        self.scheduler = scheduler::GpuScheduler::new(
+            &self.device,
+            self.frame.queue.graphics,
+            self.config.background_budget_ms,
+        )?;
+
+        self.frame.events = observers;
+        self.frame
+            .events
+            .emit(events::RenderEvent::swapchain_created(
+                &self.frame.swapchain_details,
+            ));
+        self.emit_event(engine_events::EngineEvent::swapchain_recreated(
+            &self.frame.swapchain_details,
+        ));
+
+        self.restore_handles(&lost_device)?;
+        lost_device.destroy();
+        self.restore_meshes()?;
+        // the bound buffers were on the lost device, see Application::on_device_lost
+        self.scene_buffer_bindings.clear();
+
+        self.frame.buffers.set_lighting(lighting);
+        self.set_polygon_mode(polygon_mode)?;
+        self.set_debug_view(debug_view)
+    }
+
+    // Replaces the buffers and textures of the sources with ones on the new device and
+    // destroys the old ones with the lost device
+    fn restore_handles(&mut self, lost_device: &device::Device) -> Result<()> {
+        for (handle, description) in self.sources.buffers.clone() {
+            let buffer = buffers::BufferInfo::create(
+                &self.device,
+                description.size,
+                description.usage,
+                description.memory_properties,
+            )?;
+            self.handles.replace_buffer(lost_device, handle, buffer)?;
+        }
+
+        for (handle, path) in self.sources.textures.clone() {
+            let texture = self.import_texture(&path)?;
+            self.handles.replace_texture(lost_device, handle, texture)?;
+        }
+
+        Ok(())
+    }
+
+    // Loads the meshes into the new mesh pool in their original order and moves their
+    // renderers and watched models along
+    fn restore_meshes(&mut self) -> Result<()> {
+        let loaded = std::mem::take(&mut self.sources.meshes);
+
+        let mut old_meshes = Vec::with_capacity(loaded.len());
+        let mut meshes = Vec::with_capacity(loaded.len());
+        for mesh in loaded.iter() {
+            old_meshes.push(mesh.allocation);
+            meshes.push(self.load_mesh(&mesh.vertices, &mesh.indices)?);
+        }
+
+        self.move_mesh_renderers(&old_meshes, &meshes)?;
+        self.assets.move_meshes(&old_meshes, &meshes);
+        Ok(())
+    }
+
+    // Switches between windowed, borderless and exclusive fullscreen and recreates the
+    // swapchain for the new size. Returns the mode used, see display::apply.
+    pub fn set_window_mode(
+        &mut self,
+        window: &Window,
+        mode: display::WindowMode,
+    ) -> Result<display::WindowMode> {
+        let applied = display::apply(window, mode);
+        self.config.window_mode = applied;
+
+        self.recreate_swapchain(window)?;
+        Ok(applied)
+    }
+
+    pub fn toggle_fullscreen(&mut self, window: &Window) -> Result<display::WindowMode> {
+        self.set_window_mode(window, self.window_mode().toggled())
+    }
+
+    pub fn window_mode(&self) -> display::WindowMode {
+        self.config.window_mode
+    }
+
+    // Hides or locks the cursor, a locked cursor switches the input's mouse axes to
+    // relative motion. Returns the mode used, see display::set_cursor_mode.
+    pub fn set_cursor_mode(
+        &mut self,
+        window: &Window,
+        mode: display::CursorMode,
+    ) -> display::CursorMode {
+        let applied = display::set_cursor_mode(window, mode);
+        self.config.cursor_mode = applied;
+        self.input
+            .set_relative_mouse(applied == display::CursorMode::Locked);
+
+        applied
+    }
+
+    pub fn toggle_cursor_lock(&mut self, window: &Window) -> display::CursorMode {
+        self.set_cursor_mode(window, self.cursor_mode().toggled())
+    }
+
+    pub fn cursor_mode(&self) -> display::CursorMode {
+        self.config.cursor_mode
+    }
+
+    // Replaces the window's icon, None restores the platform's default
+    pub fn set_window_icon(&mut self, window: &Window, path: Option<&Path>) -> Result<()> {
+        let icon = match path {
+            Some(path) => Some(display::load_icon(path)?),
+            None => None,
+        };
+
+        window.set_window_icon(icon);
+        self.config.window_icon = path.map(|path| path.to_path_buf());
+        Ok(())
+    }
+
+    // Whether the swapchain was created with one of the HDR formats of
+    // swapchain::SwapchainConfig::hdr_formats
+    pub fn is_hdr_output(&self) -> bool {
+        self.frame.swapchain_details.is_hdr()
+    }
+
+    // Scales the viewport's color before it is tonemapped, needs the tonemap effect in
+    // its config or an HDR swapchain
+    pub fn set_exposure(&mut self, viewport_name: &str, exposure: f32) -> Result<()> {
+        let chain = self.post_process_chain(viewport_name)?;
+        chain.set_exposure(exposure);
+        self.viewport_config(viewport_name)?.post_process.exposure = exposure;
+        Ok(())
+    }
+
+    // Ignored on an HDR swapchain, where the display maps the exposed color itself
+    pub fn set_tonemap(
+        &mut self,
+        viewport_name: &str,
+        tonemap: viewport::TonemapOperator,
+    ) -> Result<()> {
+        let chain = self.post_process_chain(viewport_name)?;
+        chain.set_tonemap(tonemap);
+        self.viewport_config(viewport_name)?.post_process.tonemap = tonemap;
+        Ok(())
+    }
+
+    fn viewport_config(&mut self, viewport_name: &str) -> Result<&mut viewport::ViewportConfig> {
+        self.config
+            .viewports
+            .iter_mut()
+            .find(|viewport_config| viewport_config.name == viewport_name)
+            .ok_or_else(|| Error::msg(format!("no viewport named {}", viewport_name)))
+    }
+
+    // The viewport's chain, the viewports have none when none of them has effects
+    fn post_process_chain(
+        &mut self,
+        viewport_name: &str,
+    ) -> Result<&mut postprocess::PostProcessChain> {
+        self.viewport_config(viewport_name)?;
+        self.frame
+            .viewports
+            .as_mut()
+            .and_then(|viewports| viewports.chain_mut(viewport_name))
+            .ok_or_else(|| {
+                Error::Unsupported(
+                    "post processing is not enabled in the engine config".to_string(),
+                )
+            })
+    }
+
+    // Replaces the compute effects run on the swapchain image, needs at least one of them
+    // in EngineConfig::image_effects
+    pub fn set_image_effects(&mut self, effects: Vec<image_effects::ImageEffect>) -> Result<()> {
+        let image_effects = self.frame.image_effects.as_mut().ok_or_else(|| {
+            Error::Unsupported("image effects are not enabled in the engine config".to_string())
+        })?;
+        image_effects.set_effects(effects.clone())?;
+        self.config.image_effects = effects;
+        Ok(())
+    }
+
+    pub fn swapchain_image_count(&self) -> u32 {
+        self.frame.swapchain_details.image_count()
+    }
+
+    pub fn surface(&self) -> &surface::SurfaceInfo {
+        &self.surface_info
+    }
+
+    pub fn instance(&self) -> &instance::VulkanInstance {
+        &self.instance
+    }
+
+    pub fn set_debug_messenger(
+        &mut self,
+        debug_config: debug_messenger::DebugMessengerConfig,
+    ) -> Result<()> {
+        self.instance.set_debug_messenger(debug_config.clone())?;
+        self.config.debug_messenger = debug_config;
+        Ok(())
+    }
+
+    // Counts of the validation messages per frame, when the debug messenger config has
+    // stats attached, see DebugMessengerConfig::with_stats
+    pub fn validation_stats(&self) -> Option<&validation_stats::ValidationStats> {
+        self.config.debug_messenger.stats.as_ref()
+    }
+}
+
+impl Drop for Engine {
+    fn drop(&mut self) {
+        if let Err(e) = self.shutdown() {
+            tracing::error!("failed to shut down engine: {:?}", e);
+        }
+    }
+}
@@ -0,0 +1,267 @@
+// Window, device and swapchain notifications for applications that leave the event loop
+// to the engine, see Engine::run. Events are delivered to Application::on_engine_event,
+// to subscribed callbacks and channels, and queued until Engine::drain_events.
+//
+// winit's events borrow from the loop, so the parts applications react to are copied
+// into EngineEvent.
+
+use ash::vk;
+use winit::event::{
+    DeviceEvent, DeviceId, ElementState, MouseButton, MouseScrollDelta, VirtualKeyCode, WindowEvent,
+};
+
+use crate::vulkan::swapchain;
+
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::mpsc;
+
+// Events kept for drain_events, the oldest are dropped when it is not called
+pub const QUEUE_CAPACITY: usize = 256;
+
+// Lines a wheel notch scrolls, to report pixel deltas of touchpads in the same unit
+const PIXELS_PER_LINE: f64 = 20.0;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EngineEvent {
+    Resized {
+        width: u32,
+        height: u32,
+    },
+    ScaleFactorChanged(f64),
+    Moved {
+        x: i32,
+        y: i32,
+    },
+    Focused(bool),
+    CloseRequested,
+    DroppedFile(PathBuf),
+    Key {
+        key: Option<VirtualKeyCode>,
+        scancode: u32,
+        state: ElementState,
+    },
+    Character(char),
+    MouseButton {
+        button: MouseButton,
+        state: ElementState,
+    },
+    CursorMoved {
+        x: f64,
+        y: f64,
+    },
+    CursorEntered,
+    CursorLeft,
+    // in lines, positive away from the user
+    MouseWheel {
+        x: f32,
+        y: f32,
+    },
+    // input devices plugged in or removed while running, eg. a gamepad or a mouse
+    DeviceAdded(DeviceId),
+    DeviceRemoved(DeviceId),
+    Suspended,
+    Resumed,
+    // sent after the swapchain was created again, eg. on resize or after a lost device
+    SwapchainRecreated {
+        extent: vk::Extent2D,
+        format: vk::Format,
+        image_count: usize,
+    },
+}
+
+impl EngineEvent {
+    // None for the events applications are not told about, eg. touch or axis motion
+    pub fn from_window_event(event: &WindowEvent) -> Option<EngineEvent> {
+        let event = match event {
+            WindowEvent::Resized(size) => EngineEvent::Resized {
+                width: size.width,
+                height: size.height,
+            },
+            WindowEvent::ScaleFactorChanged { scale_factor, .. } => {
+                EngineEvent::ScaleFactorChanged(*scale_factor)
+            }
+            WindowEvent::Moved(position) => EngineEvent::Moved {
+                x: position.x,
+                y: position.y,
+            },
+            WindowEvent::Focused(focused) => EngineEvent::Focused(*focused),
+            WindowEvent::CloseRequested => EngineEvent::CloseRequested,
+            WindowEvent::DroppedFile(path) => EngineEvent::DroppedFile(path.clone()),
+            WindowEvent::KeyboardInput { input, .. } => EngineEvent::Key {
+                key: input.virtual_keycode,
+                scancode: input.scancode,
+                state: input.state,
+            },
+            WindowEvent::ReceivedCharacter(character) => EngineEvent::Character(*character),
+            WindowEvent::MouseInput { button, state, .. } => EngineEvent::MouseButton {
+                button: *button,
+                state: *state,
+            },
+            WindowEvent::CursorMoved { position, .. } => EngineEvent::CursorMoved {
+                x: position.x,
+                y: position.y,
+            },
+            WindowEvent::CursorEntered { .. } => EngineEvent::CursorEntered,
+            WindowEvent::CursorLeft { .. } => EngineEvent::CursorLeft,
+            WindowEvent::MouseWheel { delta, .. } => {
+                let (x, y) = match delta {
+                    MouseScrollDelta::LineDelta(x, y) => (*x, *y),
+                    MouseScrollDelta::PixelDelta(position) => (
+                        (position.x / PIXELS_PER_LINE) as f32,
+                        (position.y / PIXELS_PER_LINE) as f32,
+                    ),
+                };
+                EngineEvent::MouseWheel { x, y }
+            }
+            _ => return None,
+        };
+
+        Some(event)
+    }
+
+    // Only hotplugging is reported, the motion of the mouse is read through the input map
+    pub fn from_device_event(device_id: DeviceId, event: &DeviceEvent) -> Option<EngineEvent> {
+        match event {
+            DeviceEvent::Added => Some(EngineEvent::DeviceAdded(device_id)),
+            DeviceEvent::Removed => Some(EngineEvent::DeviceRemoved(device_id)),
+            _ => None,
+        }
+    }
+
+    pub fn swapchain_recreated(swapchain: &swapchain::SwapchainDetails) -> EngineEvent {
+        EngineEvent::SwapchainRecreated {
+            extent: swapchain.extent,
+            format: swapchain.format.format,
+            image_count: swapchain.images.len(),
+        }
+    }
+}
+
+enum Observer {
+    Callback(Box<dyn FnMut(&EngineEvent) + Send>),
+    Channel(mpsc::Sender<EngineEvent>),
+}
+
+// Same delivery as vulkan::events::RenderEvents, plus a queue for applications that
+// poll once per frame instead
+pub struct EngineEvents {
+    observers: Vec<Observer>,
+    queue: VecDeque<EngineEvent>,
+    capacity: usize,
+}
+
+impl Default for EngineEvents {
+    fn default() -> EngineEvents {
+        EngineEvents::new(QUEUE_CAPACITY)
+    }
+}
+
+impl EngineEvents {
+    pub fn new(capacity: usize) -> EngineEvents {
+        EngineEvents {
+            observers: vec![],
+            queue: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn subscribe<F>(&mut self, callback: F)
+    where
+        F: FnMut(&EngineEvent) + Send + 'static,
+    {
+        self.observers.push(Observer::Callback(Box::new(callback)));
+    }
+
+    // Events are buffered until received, the sender is dropped once the receiver is
+    pub fn channel(&mut self) -> mpsc::Receiver<EngineEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.observers.push(Observer::Channel(sender));
+        receiver
+    }
+
+    pub fn emit(&mut self, event: EngineEvent) {
+        self.observers.retain(|observer| match observer {
+            Observer::Callback(_) => true,
+            Observer::Channel(sender) => sender.send(event.clone()).is_ok(),
+        });
+
+        for observer in self.observers.iter_mut() {
+            if let Observer::Callback(callback) = observer {
+                callback(&event);
+            }
+        }
+
+        if self.capacity > 0 {
+            if self.queue.len() == self.capacity {
+                self.queue.pop_front();
+            }
+            self.queue.push_back(event);
+        }
+    }
+
+    // The events since the last drain, oldest first
+    pub fn drain(&mut self) -> Vec<EngineEvent> {
+        self.queue.drain(..).collect()
+    }
+
+    pub fn pending(&self) -> usize {
+        self.queue.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{Arc, Mutex};
+    use winit::dpi::PhysicalSize;
+
+    #[test]
+    fn window_events_are_copied() {
+        let resized = WindowEvent::Resized(PhysicalSize::new(800, 600));
+        assert_eq!(
+            EngineEvent::from_window_event(&resized),
+            Some(EngineEvent::Resized {
+                width: 800,
+                height: 600
+            })
+        );
+        assert_eq!(
+            EngineEvent::from_window_event(&WindowEvent::Focused(false)),
+            Some(EngineEvent::Focused(false))
+        );
+        assert_eq!(
+            EngineEvent::from_window_event(&WindowEvent::HoveredFileCancelled),
+            None
+        );
+    }
+
+    #[test]
+    fn events_reach_observers_and_the_queue() {
+        let mut events = EngineEvents::new(2);
+
+        let seen = Arc::new(Mutex::new(vec![]));
+        let callback_seen = seen.clone();
+        events.subscribe(move |event| callback_seen.lock().unwrap().push(event.clone()));
+        let receiver = events.channel();
+
+        events.emit(EngineEvent::CloseRequested);
+        events.emit(EngineEvent::Suspended);
+        events.emit(EngineEvent::Resumed);
+
+        assert_eq!(seen.lock().unwrap().len(), 3);
+        assert_eq!(receiver.try_iter().count(), 3);
+
+        // the oldest event was dropped from the full queue
+        assert_eq!(
+            events.drain(),
+            vec![EngineEvent::Suspended, EngineEvent::Resumed]
+        );
+        assert_eq!(events.pending(), 0);
+
+        drop(receiver);
+        events.emit(EngineEvent::CursorLeft);
+        assert_eq!(events.observers.len(), 1);
+    }
+}
@@ -0,0 +1,175 @@
+use ash::vk;
+
+use thiserror::Error;
+
+use crate::vulkan::image::TransitionError;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+// Errors returned by the library. Binaries are free to wrap them in anyhow.
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("failed to compile shader {file}: {message}")]
+    ShaderCompile { file: String, message: String },
+
+    #[error("{0}")]
+    DeviceNotFound(String),
+
+    // the swapchain has to be recreated before the next frame can be drawn
+    #[error("swapchain is out of date")]
+    SwapchainOutOfDate,
+
+    // the surface has no area to present to, eg. while the window is minimized
+    #[error("the surface has a zero extent")]
+    SurfaceMinimized,
+
+    // a format, feature or mode the device or surface does not support
+    #[error("{0}")]
+    Unsupported(String),
+
+    // an index or size outside of what the resource holds
+    #[error("{0}")]
+    OutOfRange(String),
+
+    // a handle used after the resource it named was destroyed, see registry::HandlePool
+    #[error("{0}")]
+    StaleHandle(String),
+
+    // the shaders and the layouts or vertex data they are used with disagree
+    #[error("shader interface mismatch: {0}")]
+    InterfaceMismatch(String),
+
+    #[error("cannot load vulkan: {0}")]
+    Loading(String),
+
+    #[error(transparent)]
+    Vk(#[from] vk::Result),
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+
+    #[error(transparent)]
+    Image(#[from] image::ImageError),
+
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+
+    #[error(transparent)]
+    Toml(#[from] toml::de::Error),
+
+    #[error(transparent)]
+    Transition(#[from] TransitionError),
+
+    #[cfg(feature = "xr")]
+    #[error("openxr call failed: {0:?}")]
+    Xr(openxr::sys::Result),
+
+    #[error("{0}")]
+    Message(String),
+
+    // what was being done when the source error happened
+    #[error("{context}: {source}")]
+    Context {
+        context: String,
+        #[source]
+        source: Box<Error>,
+    },
+}
+
+impl Error {
+    pub fn msg<M: Into<String>>(message: M) -> Error {
+        Error::Message(message.into())
+    }
+
+    // The error without the context it was wrapped in
+    pub fn root(&self) -> &Error {
+        match self {
+            Error::Context { source, .. } => source.root(),
+            err => err,
+        }
+    }
+
+    pub fn vk_result(&self) -> Option<vk::Result> {
+        match self.root() {
+            Error::Vk(result) => Some(*result),
+            _ => None,
+        }
+    }
+
+    pub fn is_swapchain_out_of_date(&self) -> bool {
+        match self.root() {
+            Error::SwapchainOutOfDate => true,
+            Error::Vk(vk::Result::ERROR_OUT_OF_DATE_KHR) => true,
+            _ => false,
+        }
+    }
+
+    pub fn is_surface_minimized(&self) -> bool {
+        match self.root() {
+            Error::SurfaceMinimized => true,
+            _ => false,
+        }
+    }
+
+    // The gpu stopped executing the device's work, eg. after a hang or a driver reset.
+    // Nothing created from the device can be used anymore, see Engine::recover_device.
+    pub fn is_device_lost(&self) -> bool {
+        self.vk_result() == Some(vk::Result::ERROR_DEVICE_LOST)
+    }
+}
+
+impl From<std::ffi::NulError> for Error {
+    fn from(err: std::ffi::NulError) -> Error {
+        Error::Message(err.to_string())
+    }
+}
+
+impl From<ash::LoadingError> for Error {
+    fn from(err: ash::LoadingError) -> Error {
+        Error::Loading(format!("{:?}", err))
+    }
+}
+
+impl From<ash::InstanceError> for Error {
+    fn from(err: ash::InstanceError) -> Error {
+        match err {
+            ash::InstanceError::VkError(result) => Error::Vk(result),
+            ash::InstanceError::LoadError(names) => {
+                Error::Loading(format!("missing functions {:?}", names))
+            }
+        }
+    }
+}
+
+#[cfg(feature = "xr")]
+impl From<openxr::sys::Result> for Error {
+    fn from(err: openxr::sys::Result) -> Error {
+        Error::Xr(err)
+    }
+}
+
+impl From<winit::error::OsError> for Error {
+    fn from(err: winit::error::OsError) -> Error {
+        Error::Message(err.to_string())
+    }
+}
+
+// Same shape as anyhow's Context so call sites read the same
+pub trait Context<T> {
+    fn context<C: Into<String>>(self, context: C) -> Result<T>;
+}
+
+impl<T, E: Into<Error>> Context<T> for std::result::Result<T, E> {
+    fn context<C: Into<String>>(self, context: C) -> Result<T> {
+        self.map_err(|err| Error::Context {
+            context: context.into(),
+            source: Box::new(err.into()),
+        })
+    }
+}
+
+impl<T> Context<T> for Option<T> {
+    fn context<C: Into<String>>(self, context: C) -> Result<T> {
+        self.ok_or_else(|| Error::Message(context.into()))
+    }
+}
@@ -13,3 +13,12 @@ pub fn vk_to_string(raw_string_array: &[c_char]) -> String {
         .expect("Failed to convert vulkan raw string.")
         .to_owned()
 }
+
+/// Helper function to convert bool to vk::Bool32
+pub fn to_vk_bool(value: bool) -> ash::vk::Bool32 {
+    if value {
+        ash::vk::TRUE
+    } else {
+        ash::vk::FALSE
+    }
+}
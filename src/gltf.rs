@@ -0,0 +1,1371 @@
+// Loader for glTF 2.0 models with their skin and animations, both the .gltf + .bin and
+// the binary .glb form. Like the OBJ loader it covers what the engine draws without a
+// native importer: the primitives of the first mesh are merged into one triangle list,
+// the first skin becomes the skeleton and animations of its joints become clips.
+// Animations of other nodes become rigid node animations. Metallic-roughness materials
+// are read along with the images of their textures, images embedded in the document are
+// kept in GltfModel::images until they are extracted to files, see extract_images.
+// Cameras and morph targets are not read.
+
+use cgmath::{InnerSpace, Matrix3, Matrix4, One, Quaternion, Vector3};
+
+use serde::Deserialize;
+
+use crate::animation;
+use crate::error::{Context, Error, Result};
+use crate::vulkan::{material, pbr, skinning};
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const GLB_MAGIC: u32 = 0x4654_6C67;
+const GLB_JSON_CHUNK: u32 = 0x4E4F_534A;
+const GLB_BIN_CHUNK: u32 = 0x004E_4942;
+
+const TRIANGLES: u32 = 4;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Document {
+    #[serde(default)]
+    accessors: Vec<Accessor>,
+    #[serde(default)]
+    buffer_views: Vec<BufferView>,
+    #[serde(default)]
+    buffers: Vec<Buffer>,
+    #[serde(default)]
+    meshes: Vec<Mesh>,
+    #[serde(default)]
+    nodes: Vec<Node>,
+    #[serde(default)]
+    skins: Vec<Skin>,
+    #[serde(default)]
+    animations: Vec<Animation>,
+    #[serde(default)]
+    materials: Vec<Material>,
+    #[serde(default)]
+    textures: Vec<Texture>,
+    #[serde(default)]
+    images: Vec<Image>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Accessor {
+    buffer_view: Option<usize>,
+    #[serde(default)]
+    byte_offset: usize,
+    component_type: u32,
+    #[serde(default)]
+    normalized: bool,
+    count: usize,
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BufferView {
+    buffer: usize,
+    #[serde(default)]
+    byte_offset: usize,
+    byte_length: usize,
+    byte_stride: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Buffer {
+    // the binary chunk of a .glb when missing
+    uri: Option<String>,
+    byte_length: usize,
+}
+
+#[derive(Debug, Deserialize)]
+struct Mesh {
+    primitives: Vec<Primitive>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Primitive {
+    attributes: HashMap<String, usize>,
+    indices: Option<usize>,
+    mode: Option<u32>,
+    material: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Material {
+    name: Option<String>,
+    pbr_metallic_roughness: Option<PbrMetallicRoughness>,
+    normal_texture: Option<TextureInfo>,
+    occlusion_texture: Option<TextureInfo>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PbrMetallicRoughness {
+    base_color_factor: Option<[f32; 4]>,
+    metallic_factor: Option<f32>,
+    roughness_factor: Option<f32>,
+    base_color_texture: Option<TextureInfo>,
+    metallic_roughness_texture: Option<TextureInfo>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TextureInfo {
+    index: usize,
+    // of normal textures
+    scale: Option<f32>,
+    // of occlusion textures
+    strength: Option<f32>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Texture {
+    source: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Image {
+    // a file or a data uri, images in a buffer view have none
+    uri: Option<String>,
+    buffer_view: Option<usize>,
+    mime_type: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Node {
+    name: Option<String>,
+    #[serde(default)]
+    children: Vec<usize>,
+    mesh: Option<usize>,
+    skin: Option<usize>,
+    matrix: Option<[f32; 16]>,
+    translation: Option<[f32; 3]>,
+    // x, y, z, w
+    rotation: Option<[f32; 4]>,
+    scale: Option<[f32; 3]>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Skin {
+    inverse_bind_matrices: Option<usize>,
+    joints: Vec<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Animation {
+    name: Option<String>,
+    channels: Vec<AnimationChannel>,
+    samplers: Vec<AnimationSampler>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AnimationChannel {
+    sampler: usize,
+    target: ChannelTarget,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChannelTarget {
+    node: Option<usize>,
+    path: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct AnimationSampler {
+    input: usize,
+    output: usize,
+    interpolation: Option<String>,
+}
+
+// Where the image of a texture is read from
+#[derive(Debug, Clone, PartialEq)]
+pub enum GltfTexture {
+    File(PathBuf),
+    // index into GltfModel::images
+    Embedded(usize),
+}
+
+// An image stored in a buffer view or a data uri of the document
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmbeddedImage {
+    pub mime_type: Option<String>,
+    pub data: Vec<u8>,
+}
+
+impl EmbeddedImage {
+    // Extension of the file the image is extracted to, by its mime type or its header
+    pub fn extension(&self) -> Result<&'static str> {
+        match self.mime_type.as_ref().map(|mime_type| mime_type.as_str()) {
+            Some("image/png") => Ok("png"),
+            Some("image/jpeg") => Ok("jpg"),
+            _ if self.data.starts_with(b"\x89PNG") => Ok("png"),
+            _ if self.data.starts_with(&[0xFF, 0xD8]) => Ok("jpg"),
+            mime_type => Err(Error::Unsupported(format!(
+                "gltf: embedded images of type {} are not supported",
+                mime_type.unwrap_or("unknown")
+            ))),
+        }
+    }
+}
+
+// A metallic-roughness material, eg. added with material::MaterialLibrary::add_pbr
+#[derive(Debug, Clone, PartialEq)]
+pub struct GltfMaterial {
+    pub name: String,
+    pub parameters: pbr::PbrParameters,
+    // images of the slots that have one, the others are left to default textures
+    pub textures: Vec<(material::TextureSlot, GltfTexture)>,
+}
+
+impl GltfMaterial {
+    // The textures read from files, embedded ones are left out until they are extracted
+    pub fn texture_files(&self) -> Vec<(material::TextureSlot, &Path)> {
+        self.textures
+            .iter()
+            .filter_map(|(slot, texture)| match texture {
+                GltfTexture::File(path) => Some((*slot, path.as_path())),
+                GltfTexture::Embedded(_) => None,
+            })
+            .collect()
+    }
+}
+
+// The range of the merged index list one primitive was read into
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct GltfPrimitive {
+    pub first_index: u32,
+    pub index_count: u32,
+    // index into GltfModel::materials
+    pub material: Option<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct GltfModel {
+    pub vertices: Vec<skinning::SkinnedVertex>,
+    pub indices: Vec<u32>,
+    // the joints of the vertices index its joints
+    pub skeleton: Option<animation::Skeleton>,
+    pub clips: Vec<animation::AnimationClip>,
+    // animated nodes outside of the skeleton, eg. played with a scene::Animator
+    pub nodes: Vec<animation::NodeAnimation>,
+    pub materials: Vec<GltfMaterial>,
+    pub primitives: Vec<GltfPrimitive>,
+    // the images of the textures that are not files
+    pub images: Vec<EmbeddedImage>,
+}
+
+impl GltfModel {
+    // Writes the embedded images to files in the directory and points the textures of the
+    // materials at them, so they can be loaded like any image file
+    pub fn extract_images(&mut self, dir: &Path) -> Result<Vec<PathBuf>> {
+        if self.images.is_empty() {
+            return Ok(vec![]);
+        }
+
+        fs::create_dir_all(dir).context(format!("cannot create image directory {:?}", dir))?;
+
+        let files = self
+            .images
+            .iter()
+            .enumerate()
+            .map(|(index, image)| {
+                let path = dir.join(format!("image_{}.{}", index, image.extension()?));
+                fs::write(&path, &image.data)
+                    .context(format!("cannot extract embedded image to {:?}", path))?;
+                Ok(path)
+            })
+            .collect::<Result<Vec<PathBuf>>>()?;
+
+        for (_, texture) in self
+            .materials
+            .iter_mut()
+            .flat_map(|material| material.textures.iter_mut())
+        {
+            if let GltfTexture::Embedded(index) = *texture {
+                *texture = GltfTexture::File(files[index].clone());
+            }
+        }
+
+        Ok(files)
+    }
+}
+
+fn error(message: String) -> Error {
+    Error::msg(format!("gltf: {}", message))
+}
+
+fn components(kind: &str) -> Result<usize> {
+    match kind {
+        "SCALAR" => Ok(1),
+        "VEC2" => Ok(2),
+        "VEC3" => Ok(3),
+        "VEC4" => Ok(4),
+        "MAT2" => Ok(4),
+        "MAT3" => Ok(9),
+        "MAT4" => Ok(16),
+        _ => Err(error(format!("unknown accessor type {}", kind))),
+    }
+}
+
+fn component_size(component_type: u32) -> Result<usize> {
+    match component_type {
+        // byte, unsigned byte
+        5120 | 5121 => Ok(1),
+        // short, unsigned short
+        5122 | 5123 => Ok(2),
+        // unsigned int, float
+        5125 | 5126 => Ok(4),
+        _ => Err(error(format!("unknown component type {}", component_type))),
+    }
+}
+
+// Reads one component as a float, normalized integers are mapped to 0..1 or -1..1
+fn read_component(bytes: &[u8], component_type: u32, normalized: bool) -> f32 {
+    let (value, max) = match component_type {
+        5120 => (bytes[0] as i8 as f32, 127.0),
+        5121 => (bytes[0] as f32, 255.0),
+        5122 => (i16::from_le_bytes([bytes[0], bytes[1]]) as f32, 32767.0),
+        5123 => (u16::from_le_bytes([bytes[0], bytes[1]]) as f32, 65535.0),
+        5125 => (
+            u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f32,
+            4_294_967_295.0,
+        ),
+        _ => (
+            f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            1.0,
+        ),
+    };
+
+    if normalized && component_type != 5126 {
+        (value / max).max(-1.0)
+    } else {
+        value
+    }
+}
+
+// Decodes the base64 payload of a data uri
+pub fn decode_base64(encoded: &str) -> Result<Vec<u8>> {
+    let value = |c: u8| match c {
+        b'A'..=b'Z' => Some(c - b'A'),
+        b'a'..=b'z' => Some(c - b'a' + 26),
+        b'0'..=b'9' => Some(c - b'0' + 52),
+        b'+' | b'-' => Some(62),
+        b'/' | b'_' => Some(63),
+        _ => None,
+    };
+
+    let mut decoded = Vec::with_capacity(encoded.len() / 4 * 3);
+    let mut bits: u32 = 0;
+    let mut bit_count = 0;
+
+    for c in encoded
+        .bytes()
+        .filter(|c| !c.is_ascii_whitespace() && *c != b'=')
+    {
+        let sextet =
+            value(c).ok_or_else(|| error(format!("invalid base64 character {:?}", c as char)))?;
+
+        bits = (bits << 6) | sextet as u32;
+        bit_count += 6;
+        if bit_count >= 8 {
+            bit_count -= 8;
+            decoded.push((bits >> bit_count) as u8);
+            bits &= (1 << bit_count) - 1;
+        }
+    }
+
+    Ok(decoded)
+}
+
+struct Loader {
+    document: Document,
+    buffers: Vec<Vec<u8>>,
+    base_dir: PathBuf,
+}
+
+impl Loader {
+    fn new(json: &[u8], base_dir: &Path, binary_chunk: Option<Vec<u8>>) -> Result<Loader> {
+        let document: Document = serde_json::from_slice(json)
+            .map_err(|err| error(format!("invalid document: {}", err)))?;
+
+        let mut binary_chunk = binary_chunk;
+        let buffers = document
+            .buffers
+            .iter()
+            .map(|buffer| {
+                let data = match buffer.uri.as_ref() {
+                    Some(uri) if uri.starts_with("data:") => {
+                        let payload = uri
+                            .find(";base64,")
+                            .map(|start| &uri[start + ";base64,".len()..])
+                            .ok_or_else(|| {
+                                error("only base64 data uris are supported".to_string())
+                            })?;
+                        decode_base64(payload)?
+                    }
+                    Some(uri) => {
+                        let path = base_dir.join(uri);
+                        fs::read(&path).context(format!("cannot read gltf buffer {:?}", path))?
+                    }
+                    None => binary_chunk
+                        .take()
+                        .ok_or_else(|| error("buffer without uri outside of a glb".to_string()))?,
+                };
+
+                if data.len() < buffer.byte_length {
+                    return Err(error(format!(
+                        "buffer holds {} bytes, {} expected",
+                        data.len(),
+                        buffer.byte_length
+                    )));
+                }
+
+                Ok(data)
+            })
+            .collect::<Result<Vec<Vec<u8>>>>()?;
+
+        Ok(Loader {
+            document,
+            buffers,
+            base_dir: base_dir.to_path_buf(),
+        })
+    }
+
+    // Every element of the accessor as floats, components one after the other
+    fn read_floats(&self, index: usize) -> Result<Vec<f32>> {
+        let accessor = self
+            .document
+            .accessors
+            .get(index)
+            .ok_or_else(|| error(format!("no accessor {}", index)))?;
+
+        let count = components(&accessor.kind)?;
+        let size = component_size(accessor.component_type)?;
+
+        // accessors without a view are all zeros
+        let view = match accessor.buffer_view {
+            Some(view) => self
+                .document
+                .buffer_views
+                .get(view)
+                .ok_or_else(|| error(format!("no buffer view {}", view)))?,
+            None => return Ok(vec![0.0; accessor.count * count]),
+        };
+
+        let buffer = self
+            .buffers
+            .get(view.buffer)
+            .ok_or_else(|| error(format!("no buffer {}", view.buffer)))?;
+        let stride = view.byte_stride.unwrap_or(count * size);
+        let start = view.byte_offset + accessor.byte_offset;
+        let end = view.byte_offset + view.byte_length;
+
+        let mut values = Vec::with_capacity(accessor.count * count);
+        for element in 0..accessor.count {
+            for component in 0..count {
+                let offset = start + element * stride + component * size;
+                if offset + size > end || offset + size > buffer.len() {
+                    return Err(error(format!(
+                        "accessor {} reads past its buffer view",
+                        index
+                    )));
+                }
+
+                values.push(read_component(
+                    &buffer[offset..offset + size],
+                    accessor.component_type,
+                    accessor.normalized,
+                ));
+            }
+        }
+
+        Ok(values)
+    }
+
+    fn read_vectors<T>(
+        &self,
+        index: usize,
+        width: usize,
+        to: impl Fn(&[f32]) -> T,
+    ) -> Result<Vec<T>> {
+        Ok(self.read_floats(index)?.chunks(width).map(to).collect())
+    }
+
+    fn local_pose(node: &Node) -> animation::JointPose {
+        if let Some(m) = node.matrix {
+            // columns of the upper 3x3 carry rotation and scale
+            let column = |i: usize| Vector3::new(m[i * 4], m[i * 4 + 1], m[i * 4 + 2]);
+            let scale = Vector3::new(
+                column(0).magnitude(),
+                column(1).magnitude(),
+                column(2).magnitude(),
+            );
+            let rotation = Matrix3::from_cols(
+                column(0) / scale.x.max(std::f32::EPSILON),
+                column(1) / scale.y.max(std::f32::EPSILON),
+                column(2) / scale.z.max(std::f32::EPSILON),
+            );
+
+            return animation::JointPose {
+                translation: Vector3::new(m[12], m[13], m[14]),
+                rotation: Quaternion::from(rotation),
+                scale,
+            };
+        }
+
+        let identity = animation::JointPose::identity();
+        animation::JointPose {
+            translation: node
+                .translation
+                .map(Vector3::from)
+                .unwrap_or(identity.translation),
+            rotation: node
+                .rotation
+                .map(|[x, y, z, w]| Quaternion::new(w, x, y, z))
+                .unwrap_or(identity.rotation),
+            scale: node.scale.map(Vector3::from).unwrap_or(identity.scale),
+        }
+    }
+
+    fn parents(&self) -> Vec<Option<usize>> {
+        let mut parents = vec![None; self.document.nodes.len()];
+        for (index, node) in self.document.nodes.iter().enumerate() {
+            for &child in node.children.iter() {
+                if let Some(parent) = parents.get_mut(child) {
+                    *parent = Some(index);
+                }
+            }
+        }
+        parents
+    }
+
+    fn global_transform(&self, node: usize, parents: &[Option<usize>]) -> Matrix4<f32> {
+        let mut transform = Matrix4::one();
+        let mut current = Some(node);
+
+        // bounded by the node count in case of a malformed hierarchy
+        for _ in 0..self.document.nodes.len() {
+            match current {
+                Some(index) => {
+                    transform =
+                        Loader::local_pose(&self.document.nodes[index]).matrix() * transform;
+                    current = parents[index];
+                }
+                None => break,
+            }
+        }
+
+        transform
+    }
+
+    fn skeleton(&self, skin: &Skin) -> Result<animation::Skeleton> {
+        let parents = self.parents();
+
+        let inverse_binds = match skin.inverse_bind_matrices {
+            Some(accessor) => self.read_vectors(accessor, 16, |m| {
+                let mut columns = [[0.0; 4]; 4];
+                for (column, values) in columns.iter_mut().zip(m.chunks(4)) {
+                    column.copy_from_slice(values);
+                }
+                Matrix4::from(columns)
+            })?,
+            None => vec![Matrix4::one(); skin.joints.len()],
+        };
+
+        if inverse_binds.len() < skin.joints.len() {
+            return Err(error(format!(
+                "skin has {} joints but {} inverse bind matrices",
+                skin.joints.len(),
+                inverse_binds.len()
+            )));
+        }
+
+        let mut root_transform = None;
+        let joints = skin
+            .joints
+            .iter()
+            .zip(inverse_binds.into_iter())
+            .map(|(&node_index, inverse_bind)| {
+                let node = self
+                    .document
+                    .nodes
+                    .get(node_index)
+                    .ok_or_else(|| error(format!("no joint node {}", node_index)))?;
+
+                let parent_node = parents[node_index];
+                let parent = parent_node
+                    .and_then(|parent| skin.joints.iter().position(|&joint| joint == parent));
+
+                // nodes above the skeleton move all of it
+                if parent.is_none() && root_transform.is_none() {
+                    root_transform = Some(
+                        parent_node
+                            .map(|node| self.global_transform(node, &parents))
+                            .unwrap_or_else(Matrix4::one),
+                    );
+                }
+
+                Ok(animation::Joint {
+                    name: node
+                        .name
+                        .clone()
+                        .unwrap_or_else(|| format!("joint {}", node_index)),
+                    parent,
+                    inverse_bind,
+                    rest: Loader::local_pose(node),
+                })
+            })
+            .collect::<Result<Vec<animation::Joint>>>()?;
+
+        Ok(animation::Skeleton::new(joints)?
+            .with_transform(root_transform.unwrap_or_else(Matrix4::one)))
+    }
+
+    // joint maps the target node of a channel to the joint it animates, channels of
+    // nodes without a joint are skipped
+    fn clip(
+        &self,
+        index: usize,
+        animation: &Animation,
+        joint: impl Fn(usize) -> Option<usize>,
+    ) -> Result<animation::AnimationClip> {
+        let mut channels = vec![];
+
+        for channel in animation.channels.iter() {
+            let joint = match channel.target.node.and_then(&joint) {
+                Some(joint) => joint,
+                None => continue,
+            };
+
+            let sampler = animation
+                .samplers
+                .get(channel.sampler)
+                .ok_or_else(|| error(format!("no animation sampler {}", channel.sampler)))?;
+
+            let times = self.read_floats(sampler.input)?;
+            let interpolation = sampler.interpolation.as_ref().map(String::as_str);
+
+            // cubic spline keys hold an in tangent, the value and an out tangent
+            let (stride, offset) = match interpolation {
+                Some("CUBICSPLINE") => (3, 1),
+                _ => (1, 0),
+            };
+            let interpolation = match interpolation {
+                Some("STEP") => animation::Interpolation::Step,
+                _ => animation::Interpolation::Linear,
+            };
+
+            let keys = |width: usize| -> Result<Vec<Vec<f32>>> {
+                Ok(self
+                    .read_floats(sampler.output)?
+                    .chunks(width)
+                    .skip(offset)
+                    .step_by(stride)
+                    .map(|values| values.to_vec())
+                    .collect())
+            };
+
+            let keyframes = match channel.target.path.as_str() {
+                "translation" => animation::Keyframes::Translation(
+                    keys(3)?
+                        .iter()
+                        .map(|v| Vector3::new(v[0], v[1], v[2]))
+                        .collect(),
+                ),
+                "rotation" => animation::Keyframes::Rotation(
+                    keys(4)?
+                        .iter()
+                        .map(|v| Quaternion::new(v[3], v[0], v[1], v[2]).normalize())
+                        .collect(),
+                ),
+                "scale" => animation::Keyframes::Scale(
+                    keys(3)?
+                        .iter()
+                        .map(|v| Vector3::new(v[0], v[1], v[2]))
+                        .collect(),
+                ),
+                // morph target weights
+                _ => continue,
+            };
+
+            channels.push(animation::Channel::new(
+                joint,
+                times,
+                keyframes,
+                interpolation,
+            )?);
+        }
+
+        let name = animation
+            .name
+            .clone()
+            .unwrap_or_else(|| format!("animation {}", index));
+        Ok(animation::AnimationClip::new(&name, channels))
+    }
+
+    fn primitive(&self, primitive: &Primitive, model: &mut GltfModel) -> Result<()> {
+        if primitive.mode.unwrap_or(TRIANGLES) != TRIANGLES {
+            tracing::warn!("gltf: skipping a primitive that is not a triangle list");
+            return Ok(());
+        }
+
+        let attribute = |name: &str| primitive.attributes.get(name).cloned();
+
+        let positions = self.read_vectors(
+            attribute("POSITION").ok_or_else(|| error("primitive has no positions".to_string()))?,
+            3,
+            |v| [v[0], v[1], v[2]],
+        )?;
+        let count = positions.len();
+
+        let optional = |name: &str, width: usize| -> Result<Option<Vec<Vec<f32>>>> {
+            attribute(name)
+                .map(|accessor| self.read_vectors(accessor, width, |v| v.to_vec()))
+                .transpose()
+        };
+
+        let normals = optional("NORMAL", 3)?;
+        let tex_coords = optional("TEXCOORD_0", 2)?;
+        let colors = match attribute("COLOR_0") {
+            // rgb or rgba
+            Some(accessor) => {
+                let width = components(&self.document.accessors[accessor].kind)?;
+                Some(self.read_vectors(accessor, width, |v| v.to_vec())?)
+            }
+            None => None,
+        };
+        let joints = optional("JOINTS_0", 4)?;
+        let weights = optional("WEIGHTS_0", 4)?;
+
+        let base = model.vertices.len() as u32;
+        let first_index = model.indices.len() as u32;
+        for index in 0..count {
+            let mut vertex = skinning::SkinnedVertex {
+                pos: positions[index],
+                ..Default::default()
+            };
+
+            if let Some(normal) = normals.as_ref().and_then(|n| n.get(index)) {
+                vertex.normal = [normal[0], normal[1], normal[2]];
+            }
+            if let Some(tex_coord) = tex_coords.as_ref().and_then(|t| t.get(index)) {
+                // gltf puts the origin at the top left like vulkan
+                vertex.tex_coord = [tex_coord[0], tex_coord[1]];
+            }
+            if let Some(color) = colors.as_ref().and_then(|c| c.get(index)) {
+                vertex.color = [color[0], color[1], color[2]];
+            }
+            if let (Some(joint), Some(weight)) = (
+                joints.as_ref().and_then(|j| j.get(index)),
+                weights.as_ref().and_then(|w| w.get(index)),
+            ) {
+                let total: f32 = weight.iter().sum();
+                for i in 0..4 {
+                    vertex.joints[i] = joint[i] as u32;
+                    vertex.weights[i] = if total > 0.0 { weight[i] / total } else { 0.0 };
+                }
+            }
+
+            model.vertices.push(vertex);
+        }
+
+        match primitive.indices {
+            Some(accessor) => {
+                for index in self.read_floats(accessor)? {
+                    let index = index as u32;
+                    if index as usize >= count {
+                        return Err(error(format!(
+                            "index {} past the {} vertices",
+                            index, count
+                        )));
+                    }
+                    model.indices.push(base + index);
+                }
+            }
+            None => model.indices.extend(base..base + count as u32),
+        }
+
+        model.primitives.push(GltfPrimitive {
+            first_index,
+            index_count: model.indices.len() as u32 - first_index,
+            material: primitive.material,
+        });
+
+        Ok(())
+    }
+
+    fn view_bytes(&self, index: usize) -> Result<&[u8]> {
+        let view = self
+            .document
+            .buffer_views
+            .get(index)
+            .ok_or_else(|| error(format!("no buffer view {}", index)))?;
+
+        self.buffers
+            .get(view.buffer)
+            .and_then(|buffer| buffer.get(view.byte_offset..view.byte_offset + view.byte_length))
+            .ok_or_else(|| error(format!("buffer view {} is out of its buffer", index)))
+    }
+
+    // The images of the document that are not files, and the index of each image into
+    // them if it is one
+    fn embedded_images(&self) -> Result<(Vec<EmbeddedImage>, Vec<Option<usize>>)> {
+        let mut images = vec![];
+        let mut indices = vec![];
+
+        for image in self.document.images.iter() {
+            let embedded = match (image.uri.as_ref(), image.buffer_view) {
+                (Some(uri), _) if uri.starts_with("data:") => {
+                    let (header, payload) = uri
+                        .find(";base64,")
+                        .map(|start| (&uri["data:".len()..start], &uri[start + ";base64,".len()..]))
+                        .ok_or_else(|| error("only base64 data uris are supported".to_string()))?;
+
+                    Some(EmbeddedImage {
+                        mime_type: image
+                            .mime_type
+                            .clone()
+                            .or_else(|| Some(header.to_string()).filter(|h| !h.is_empty())),
+                        data: decode_base64(payload)?,
+                    })
+                }
+                (Some(_), _) => None,
+                (None, Some(view)) => Some(EmbeddedImage {
+                    mime_type: image.mime_type.clone(),
+                    data: self.view_bytes(view)?.to_vec(),
+                }),
+                (None, None) => return Err(error("image without uri or buffer view".to_string())),
+            };
+
+            indices.push(embedded.map(|embedded| {
+                images.push(embedded);
+                images.len() - 1
+            }));
+        }
+
+        Ok((images, indices))
+    }
+
+    // The image of a texture, None when it has none to load from
+    fn texture(
+        &self,
+        info: &TextureInfo,
+        embedded: &[Option<usize>],
+    ) -> Result<Option<GltfTexture>> {
+        let texture = self
+            .document
+            .textures
+            .get(info.index)
+            .ok_or_else(|| error(format!("no texture {}", info.index)))?;
+
+        let (source, image) = match texture.source {
+            Some(source) => (
+                source,
+                self.document
+                    .images
+                    .get(source)
+                    .ok_or_else(|| error(format!("no image {}", source)))?,
+            ),
+            None => return Ok(None),
+        };
+
+        Ok(match embedded[source] {
+            Some(index) => Some(GltfTexture::Embedded(index)),
+            None => image
+                .uri
+                .as_ref()
+                .map(|uri| GltfTexture::File(self.base_dir.join(uri))),
+        })
+    }
+
+    fn material(
+        &self,
+        index: usize,
+        material: &Material,
+        embedded: &[Option<usize>],
+    ) -> Result<GltfMaterial> {
+        let mut parameters = pbr::PbrParameters::default();
+        let mut textures = vec![];
+        let mut add_texture = |slot, info: Option<&TextureInfo>| -> Result<()> {
+            if let Some(texture) = info.map(|info| self.texture(info, embedded)).transpose()? {
+                textures.extend(texture.map(|texture| (slot, texture)));
+            }
+            Ok(())
+        };
+
+        if let Some(pbr) = material.pbr_metallic_roughness.as_ref() {
+            if let Some(base_color) = pbr.base_color_factor {
+                parameters = parameters.with_base_color(base_color);
+            }
+            parameters = parameters.with_metallic_roughness(
+                pbr.metallic_factor.unwrap_or(1.0),
+                pbr.roughness_factor.unwrap_or(1.0),
+            );
+
+            add_texture(
+                material::TextureSlot::Diffuse,
+                pbr.base_color_texture.as_ref(),
+            )?;
+            add_texture(
+                material::TextureSlot::MetallicRoughness,
+                pbr.metallic_roughness_texture.as_ref(),
+            )?;
+        }
+
+        add_texture(
+            material::TextureSlot::Normal,
+            material.normal_texture.as_ref(),
+        )?;
+        add_texture(
+            material::TextureSlot::Occlusion,
+            material.occlusion_texture.as_ref(),
+        )?;
+
+        if let Some(scale) = material.normal_texture.as_ref().and_then(|info| info.scale) {
+            parameters = parameters.with_normal_scale(scale);
+        }
+        if let Some(strength) = material
+            .occlusion_texture
+            .as_ref()
+            .and_then(|info| info.strength)
+        {
+            parameters = parameters.with_occlusion_strength(strength);
+        }
+
+        Ok(GltfMaterial {
+            name: material
+                .name
+                .clone()
+                .unwrap_or_else(|| format!("material {}", index)),
+            parameters,
+            textures,
+        })
+    }
+
+    fn model(&self) -> Result<GltfModel> {
+        let document = &self.document;
+
+        // the first node drawing a mesh, it decides the skin
+        let node = document
+            .nodes
+            .iter()
+            .find(|node| node.mesh.is_some())
+            .ok_or_else(|| error("no node draws a mesh".to_string()))?;
+        let mesh = node
+            .mesh
+            .and_then(|mesh| document.meshes.get(mesh))
+            .ok_or_else(|| error("node refers to a missing mesh".to_string()))?;
+
+        let (images, embedded) = self.embedded_images()?;
+
+        let mut model = GltfModel {
+            vertices: vec![],
+            indices: vec![],
+            skeleton: None,
+            clips: vec![],
+            nodes: vec![],
+            materials: document
+                .materials
+                .iter()
+                .enumerate()
+                .map(|(index, material)| self.material(index, material, &embedded))
+                .collect::<Result<Vec<GltfMaterial>>>()?,
+            primitives: vec![],
+            images,
+        };
+
+        for primitive in mesh.primitives.iter() {
+            if let Some(material) = primitive.material {
+                if material >= model.materials.len() {
+                    return Err(error(format!(
+                        "primitive refers to missing material {}",
+                        material
+                    )));
+                }
+            }
+            self.primitive(primitive, &mut model)?;
+        }
+
+        if let Some(skin) = node.skin.and_then(|skin| document.skins.get(skin)) {
+            if skin.joints.len() > skinning::MAX_JOINTS {
+                return Err(error(format!(
+                    "skin has {} joints, up to {} are supported",
+                    skin.joints.len(),
+                    skinning::MAX_JOINTS
+                )));
+            }
+
+            model.skeleton = Some(self.skeleton(skin)?);
+            model.clips = document
+                .animations
+                .iter()
+                .enumerate()
+                .map(|(index, animation)| {
+                    self.clip(index, animation, |node| {
+                        skin.joints.iter().position(|&joint| joint == node)
+                    })
+                })
+                .collect::<Result<Vec<animation::AnimationClip>>>()?;
+        }
+
+        model.nodes = self.node_animations(node.skin.and_then(|skin| document.skins.get(skin)))?;
+
+        Ok(model)
+    }
+
+    // Animated nodes that are not joints of the skin, with a clip for each animation
+    // that moves them
+    fn node_animations(&self, skin: Option<&Skin>) -> Result<Vec<animation::NodeAnimation>> {
+        let document = &self.document;
+
+        let mut targets: Vec<usize> = document
+            .animations
+            .iter()
+            .flat_map(|animation| animation.channels.iter())
+            .filter_map(|channel| channel.target.node)
+            .filter(|node| *node < document.nodes.len())
+            .filter(|node| !skin.map(|skin| skin.joints.contains(node)).unwrap_or(false))
+            .collect();
+        targets.sort();
+        targets.dedup();
+
+        targets
+            .into_iter()
+            .map(|target| {
+                let node = &document.nodes[target];
+                let name = node
+                    .name
+                    .clone()
+                    .unwrap_or_else(|| format!("node {}", target));
+                let mut node_animation =
+                    animation::NodeAnimation::new(&name, Loader::local_pose(node));
+
+                for (index, animation) in document.animations.iter().enumerate() {
+                    let clip = self.clip(index, animation, |node| {
+                        if node == target {
+                            Some(animation::NODE_JOINT)
+                        } else {
+                            None
+                        }
+                    })?;
+
+                    if !clip.channels.is_empty() {
+                        node_animation.clips.push(clip);
+                    }
+                }
+
+                Ok(node_animation)
+            })
+            .collect()
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32> {
+    bytes
+        .get(offset..offset + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| error("truncated glb".to_string()))
+}
+
+// The json chunk and the binary chunk if there is one
+fn split_glb(bytes: &[u8]) -> Result<(&[u8], Option<Vec<u8>>)> {
+    if read_u32(bytes, 0)? != GLB_MAGIC {
+        return Err(error("not a glb file".to_string()));
+    }
+    if read_u32(bytes, 4)? != 2 {
+        return Err(error("only glb version 2 is supported".to_string()));
+    }
+
+    let mut json = None;
+    let mut binary = None;
+    let mut offset = 12;
+
+    while offset + 8 <= bytes.len() {
+        let length = read_u32(bytes, offset)? as usize;
+        let kind = read_u32(bytes, offset + 4)?;
+        let chunk = bytes
+            .get(offset + 8..offset + 8 + length)
+            .ok_or_else(|| error("truncated glb chunk".to_string()))?;
+
+        match kind {
+            GLB_JSON_CHUNK => json = Some(chunk),
+            GLB_BIN_CHUNK => binary = Some(chunk.to_vec()),
+            _ => (),
+        }
+        offset += 8 + length;
+    }
+
+    json.map(|json| (json, binary))
+        .ok_or_else(|| error("glb has no json chunk".to_string()))
+}
+
+pub fn parse_glb(bytes: &[u8], base_dir: &Path) -> Result<GltfModel> {
+    let (json, binary) = split_glb(bytes)?;
+    Loader::new(json, base_dir, binary)?.model()
+}
+
+// Relative buffer uris are resolved against base_dir
+pub fn parse_gltf(json: &str, base_dir: &Path) -> Result<GltfModel> {
+    Loader::new(json.as_bytes(), base_dir, None)?.model()
+}
+
+// Loads a .gltf or .glb file
+pub fn load(path: &Path) -> Result<GltfModel> {
+    let bytes = fs::read(path).context(format!("cannot read gltf file {:?}", path))?;
+    let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+    if bytes.starts_with(b"glTF") {
+        parse_glb(&bytes, base_dir)
+    } else {
+        let json =
+            String::from_utf8(bytes).map_err(|_| error("document is not utf-8".to_string()))?;
+        parse_gltf(&json, base_dir)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn push_f32s(bytes: &mut Vec<u8>, values: &[f32]) {
+        for value in values {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    fn glb(json: &str, binary: &[u8]) -> Vec<u8> {
+        let mut json = json.as_bytes().to_vec();
+        while json.len() % 4 != 0 {
+            json.push(b' ');
+        }
+
+        let mut bytes = vec![];
+        let total = 12 + 8 + json.len() + 8 + binary.len();
+        for value in [
+            GLB_MAGIC,
+            2,
+            total as u32,
+            json.len() as u32,
+            GLB_JSON_CHUNK,
+        ]
+        .iter()
+        {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.extend_from_slice(&json);
+        bytes.extend_from_slice(&(binary.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&GLB_BIN_CHUNK.to_le_bytes());
+        bytes.extend_from_slice(binary);
+        bytes
+    }
+
+    #[test]
+    fn base64_payloads_are_decoded() {
+        assert_eq!(decode_base64("TWFu").unwrap(), b"Man".to_vec());
+        assert_eq!(decode_base64("TWE=").unwrap(), b"Ma".to_vec());
+        assert_eq!(decode_base64("TQ==").unwrap(), b"M".to_vec());
+        assert!(decode_base64("T!==").is_err());
+    }
+
+    #[test]
+    fn materials_of_primitives_are_read() {
+        let mut binary = vec![];
+        push_f32s(&mut binary, &[0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0]);
+
+        let json = r#"{
+            "asset": { "version": "2.0" },
+            "buffers": [{ "byteLength": 36 }],
+            "bufferViews": [{ "buffer": 0, "byteLength": 36 }],
+            "accessors": [
+                { "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3" }
+            ],
+            "images": [{ "uri": "albedo.png" }, { "uri": "data:image/png;base64,iVBORw==" }],
+            "textures": [{ "source": 0 }, { "source": 1 }],
+            "materials": [{
+                "name": "brass",
+                "pbrMetallicRoughness": {
+                    "baseColorFactor": [1.0, 0.8, 0.4, 1.0],
+                    "metallicFactor": 0.9,
+                    "baseColorTexture": { "index": 0 },
+                    "metallicRoughnessTexture": { "index": 1 }
+                },
+                "occlusionTexture": { "index": 0, "strength": 0.5 }
+            }],
+            "meshes": [{ "primitives": [
+                { "attributes": { "POSITION": 0 }, "material": 0 },
+                { "attributes": { "POSITION": 0 } }
+            ] }],
+            "nodes": [{ "mesh": 0 }]
+        }"#;
+
+        let model = parse_glb(&glb(json, &binary), Path::new("models")).unwrap();
+
+        assert_eq!(model.primitives.len(), 2);
+        assert_eq!(model.primitives[0].material, Some(0));
+        assert_eq!(
+            (
+                model.primitives[1].first_index,
+                model.primitives[1].index_count
+            ),
+            (3, 3)
+        );
+        assert_eq!(model.primitives[1].material, None);
+
+        let material = &model.materials[0];
+        assert_eq!(material.name, "brass");
+        assert_eq!(material.parameters.base_color, [1.0, 0.8, 0.4, 1.0]);
+        assert_eq!(
+            (material.parameters.metallic, material.parameters.roughness),
+            (0.9, 1.0)
+        );
+        assert_eq!(material.parameters.occlusion_strength, 0.5);
+        let albedo = GltfTexture::File(PathBuf::from("models/albedo.png"));
+        assert_eq!(
+            material.textures,
+            vec![
+                (material::TextureSlot::Diffuse, albedo.clone()),
+                (
+                    material::TextureSlot::MetallicRoughness,
+                    GltfTexture::Embedded(0)
+                ),
+                (material::TextureSlot::Occlusion, albedo),
+            ]
+        );
+        // embedded images are left out until they are extracted
+        assert_eq!(material.texture_files().len(), 2);
+        assert_eq!(model.images[0].mime_type.as_deref(), Some("image/png"));
+        assert_eq!(model.images[0].data, b"\x89PNG".to_vec());
+        assert_eq!(model.images[0].extension().unwrap(), "png");
+    }
+
+    #[test]
+    fn images_in_buffer_views_are_extracted() {
+        let mut binary = vec![];
+        push_f32s(&mut binary, &[0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0]);
+        // the start of a jpeg without a mime type
+        binary.extend_from_slice(&[0xFF, 0xD8, 0xFF, 0xE0]);
+
+        let json = r#"{
+            "asset": { "version": "2.0" },
+            "buffers": [{ "byteLength": 40 }],
+            "bufferViews": [
+                { "buffer": 0, "byteLength": 36 },
+                { "buffer": 0, "byteOffset": 36, "byteLength": 4 }
+            ],
+            "accessors": [
+                { "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3" }
+            ],
+            "images": [{ "bufferView": 1 }],
+            "textures": [{ "source": 0 }],
+            "materials": [{ "pbrMetallicRoughness": { "baseColorTexture": { "index": 0 } } }],
+            "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0 }, "material": 0 }] }],
+            "nodes": [{ "mesh": 0 }]
+        }"#;
+
+        let mut model = parse_glb(&glb(json, &binary), Path::new("")).unwrap();
+        assert_eq!(model.images[0].data, vec![0xFF, 0xD8, 0xFF, 0xE0]);
+
+        let dir = std::env::temp_dir().join(format!("kelsier_gltf_images_{}", std::process::id()));
+        let files = model.extract_images(&dir).unwrap();
+
+        assert_eq!(files, vec![dir.join("image_0.jpg")]);
+        assert_eq!(fs::read(&files[0]).unwrap(), vec![0xFF, 0xD8, 0xFF, 0xE0]);
+        assert_eq!(
+            model.materials[0].texture_files(),
+            vec![(material::TextureSlot::Diffuse, files[0].as_path())]
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn skinned_triangle_with_an_animation() {
+        let mut binary = vec![];
+        // positions, 36 bytes
+        push_f32s(&mut binary, &[0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0]);
+        // joints as unsigned bytes, 12 bytes
+        binary.extend_from_slice(&[0, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0]);
+        // weights, 48 bytes, the last vertex is split between both joints
+        push_f32s(
+            &mut binary,
+            &[1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.5, 0.5, 0.0, 0.0],
+        );
+        // key times and translations of the child, 8 + 24 bytes
+        push_f32s(&mut binary, &[0.0, 2.0, 0.0, 1.0, 0.0, 0.0, 3.0, 0.0]);
+
+        let json = format!(
+            r#"{{
+            "asset": {{ "version": "2.0" }},
+            "buffers": [{{ "byteLength": {} }}],
+            "bufferViews": [
+                {{ "buffer": 0, "byteOffset": 0, "byteLength": 36 }},
+                {{ "buffer": 0, "byteOffset": 36, "byteLength": 12 }},
+                {{ "buffer": 0, "byteOffset": 48, "byteLength": 48 }},
+                {{ "buffer": 0, "byteOffset": 96, "byteLength": 8 }},
+                {{ "buffer": 0, "byteOffset": 104, "byteLength": 24 }}
+            ],
+            "accessors": [
+                {{ "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3" }},
+                {{ "bufferView": 1, "componentType": 5121, "count": 3, "type": "VEC4" }},
+                {{ "bufferView": 2, "componentType": 5126, "count": 3, "type": "VEC4" }},
+                {{ "bufferView": 3, "componentType": 5126, "count": 2, "type": "SCALAR" }},
+                {{ "bufferView": 4, "componentType": 5126, "count": 2, "type": "VEC3" }}
+            ],
+            "meshes": [{{ "primitives": [{{
+                "attributes": {{ "POSITION": 0, "JOINTS_0": 1, "WEIGHTS_0": 2 }}
+            }}] }}],
+            "nodes": [
+                {{ "name": "armature", "translation": [0.0, 0.0, 5.0], "children": [1] }},
+                {{ "name": "root", "children": [2] }},
+                {{ "name": "tip", "translation": [0.0, 1.0, 0.0] }},
+                {{ "mesh": 0, "skin": 0 }}
+            ],
+            "skins": [{{ "joints": [1, 2] }}],
+            "animations": [{{
+                "name": "wave",
+                "channels": [
+                    {{ "sampler": 0, "target": {{ "node": 2, "path": "translation" }} }},
+                    {{ "sampler": 0, "target": {{ "node": 0, "path": "translation" }} }}
+                ],
+                "samplers": [{{ "input": 3, "output": 4, "interpolation": "LINEAR" }}]
+            }}]
+        }}"#,
+            binary.len()
+        );
+
+        let model = parse_glb(&glb(&json, &binary), Path::new("")).unwrap();
+
+        assert_eq!(model.vertices.len(), 3);
+        assert_eq!(model.indices, vec![0, 1, 2]);
+        assert_eq!(model.vertices[1].pos, [1.0, 0.0, 0.0]);
+        assert_eq!(model.vertices[1].joints, [1, 0, 0, 0]);
+        assert_eq!(model.vertices[2].weights, [0.5, 0.5, 0.0, 0.0]);
+
+        let skeleton = model.skeleton.unwrap();
+        assert_eq!(skeleton.len(), 2);
+        assert_eq!(skeleton.joints[1].parent, Some(0));
+        assert_eq!(skeleton.find("tip"), Some(1));
+        // the armature node above the skeleton
+        assert_eq!(skeleton.transform[3][2], 5.0);
+
+        let clip = &model.clips[0];
+        assert_eq!(clip.name, "wave");
+        assert_eq!(clip.duration, 2.0);
+        let pose = clip.sample(&skeleton, 1.0);
+        assert_eq!(pose.joints[1].translation, Vector3::new(0.0, 2.0, 0.0));
+        assert_eq!(clip.channels.len(), 1);
+
+        // the armature is animated too, but is not a joint
+        assert_eq!(model.nodes.len(), 1);
+        assert_eq!(model.nodes[0].name, "armature");
+        assert_eq!(model.nodes[0].rest.translation, Vector3::new(0.0, 0.0, 5.0));
+        assert_eq!(model.nodes[0].find_clip("wave"), Some(0));
+    }
+}
@@ -0,0 +1,313 @@
+// Models of either file format as meshes with their materials, eg. to load the meshes
+// with Engine::load_scene and the materials with ImportedScene::add_materials. Meshes
+// refer to materials and materials to textures by index, so a texture shared by several
+// materials is listed once.
+
+use ash::vk;
+
+use crate::app;
+use crate::error::{Error, Result};
+use crate::gltf;
+use crate::obj;
+use crate::vulkan::{device, material, pbr};
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone)]
+pub struct SceneMesh {
+    pub vertices: Vec<app::VertexData>,
+    pub indices: Vec<u32>,
+    // index into ImportedScene::materials
+    pub material: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SceneMaterial {
+    pub name: String,
+    pub parameters: pbr::PbrParameters,
+    // indices into ImportedScene::textures
+    pub textures: Vec<(material::TextureSlot, usize)>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ImportedScene {
+    pub meshes: Vec<SceneMesh>,
+    pub materials: Vec<SceneMaterial>,
+    pub textures: Vec<PathBuf>,
+}
+
+impl ImportedScene {
+    fn texture_index(&mut self, path: &Path) -> usize {
+        match self.textures.iter().position(|texture| texture == path) {
+            Some(index) => index,
+            None => {
+                self.textures.push(path.to_path_buf());
+                self.textures.len() - 1
+            }
+        }
+    }
+
+    pub fn from_obj(model: &obj::ObjModel) -> ImportedScene {
+        let mut scene = ImportedScene::default();
+
+        for material in model.materials.iter() {
+            let [r, g, b] = material.diffuse_color;
+            let textures = material
+                .diffuse_texture
+                .as_ref()
+                .map(|path| (material::TextureSlot::Diffuse, scene.texture_index(path)))
+                .into_iter()
+                .collect();
+
+            scene.materials.push(SceneMaterial {
+                name: material.name.clone(),
+                parameters: pbr::PbrParameters::default().with_base_color([r, g, b, 1.0]),
+                textures,
+            });
+        }
+
+        scene.meshes = model
+            .meshes
+            .iter()
+            .map(|mesh| SceneMesh {
+                vertices: mesh.vertices.clone(),
+                indices: mesh.indices.clone(),
+                material: mesh.material,
+            })
+            .collect();
+
+        scene
+    }
+
+    // One mesh per primitive, the joints of the vertices are dropped. Embedded images
+    // have to be extracted first, see gltf::GltfModel::extract_images.
+    pub fn from_gltf(model: &gltf::GltfModel) -> Result<ImportedScene> {
+        let mut scene = ImportedScene::default();
+
+        for material in model.materials.iter() {
+            let mut textures = vec![];
+            for (slot, texture) in material.textures.iter() {
+                match texture {
+                    gltf::GltfTexture::File(path) => {
+                        textures.push((*slot, scene.texture_index(path)))
+                    }
+                    gltf::GltfTexture::Embedded(_) => {
+                        return Err(Error::msg(format!(
+                            "gltf material {} has an embedded image that was not extracted",
+                            material.name
+                        )))
+                    }
+                }
+            }
+
+            scene.materials.push(SceneMaterial {
+                name: material.name.clone(),
+                parameters: material.parameters,
+                textures,
+            });
+        }
+
+        for primitive in model.primitives.iter() {
+            let first = primitive.first_index as usize;
+            let indices = model
+                .indices
+                .get(first..first + primitive.index_count as usize)
+                .ok_or_else(|| Error::msg("gltf primitive is out of the model's indices"))?;
+
+            // the vertices the primitive uses, in the order they are first referenced
+            let mut remap = HashMap::new();
+            let mut mesh = SceneMesh {
+                vertices: vec![],
+                indices: Vec::with_capacity(indices.len()),
+                material: primitive.material,
+            };
+
+            for &index in indices {
+                let local = match remap.get(&index) {
+                    Some(&local) => local,
+                    None => {
+                        let vertex = model.vertices.get(index as usize).ok_or_else(|| {
+                            Error::OutOfRange(format!(
+                                "gltf index {} is out of the model's {} vertices",
+                                index,
+                                model.vertices.len()
+                            ))
+                        })?;
+                        mesh.vertices.push(app::VertexData {
+                            pos: vertex.pos,
+                            color: vertex.color,
+                            tex_coord: vertex.tex_coord,
+                            normal: vertex.normal,
+                        });
+
+                        let local = mesh.vertices.len() as u32 - 1;
+                        remap.insert(index, local);
+                        local
+                    }
+                };
+                mesh.indices.push(local);
+            }
+
+            scene.meshes.push(mesh);
+        }
+
+        Ok(scene)
+    }
+
+    // Reads .obj, .gltf and .glb files, embedded gltf images are extracted to image_dir
+    pub fn load(path: &Path, image_dir: &Path) -> Result<ImportedScene> {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_ascii_lowercase());
+
+        match extension.as_ref().map(|extension| extension.as_str()) {
+            Some("obj") => Ok(ImportedScene::from_obj(&obj::ObjModel::load(path)?)),
+            Some("gltf") | Some("glb") => {
+                let mut model = gltf::load(path)?;
+                model.extract_images(image_dir)?;
+                ImportedScene::from_gltf(&model)
+            }
+            _ => Err(Error::Unsupported(format!(
+                "cannot import {:?}, only obj and gltf files are supported",
+                path
+            ))),
+        }
+    }
+
+    // The texture of each of the slots, the fallback for those the material has none for
+    pub fn material_textures<'a>(
+        &'a self,
+        material: usize,
+        slots: &[material::TextureSlot],
+        fallback: &'a Path,
+    ) -> Vec<(material::TextureSlot, &'a Path)> {
+        slots
+            .iter()
+            .map(|slot| {
+                let texture = self.materials[material]
+                    .textures
+                    .iter()
+                    .find(|(s, _)| s == slot)
+                    .map_or(fallback, |(_, index)| self.textures[*index].as_path());
+                (*slot, texture)
+            })
+            .collect()
+    }
+
+    // Adds every material with the same description, in the order of materials, so the
+    // material of a mesh is the id at its index. The parameters are only passed along
+    // when the description takes them.
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_materials(
+        &self,
+        library: &mut material::MaterialLibrary,
+        instance: &ash::Instance,
+        device: &device::Device,
+        command_pool: vk::CommandPool,
+        submit_queue: vk::Queue,
+        description: &material::MaterialDescription,
+        fallback: &Path,
+    ) -> Result<Vec<material::MaterialId>> {
+        (0..self.materials.len())
+            .map(|index| {
+                let textures = self.material_textures(index, &description.slots, fallback);
+                if description.pbr_parameters {
+                    library.add_pbr(
+                        instance,
+                        device,
+                        command_pool,
+                        submit_queue,
+                        description,
+                        &textures,
+                        self.materials[index].parameters,
+                    )
+                } else {
+                    library.add(
+                        instance,
+                        device,
+                        command_pool,
+                        submit_queue,
+                        description,
+                        &textures,
+                    )
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn obj_materials_share_textures() {
+        let model = obj::ObjModel::parse(
+            "mtllib scene.mtl\n\
+             v 0 0 0\nv 1 0 0\nv 0 1 0\n\
+             usemtl stone\nf 1 2 3\n\
+             usemtl moss\nf 1 2 3\n",
+            |_| {
+                obj::parse_mtl(
+                    "newmtl stone\nKd 0.5 0.5 0.5\nmap_Kd rock.png\n\
+                     newmtl moss\nKd 0.2 0.6 0.2\nmap_Kd rock.png\n",
+                    Path::new("models"),
+                )
+            },
+        )
+        .unwrap();
+
+        let scene = ImportedScene::from_obj(&model);
+
+        assert_eq!(scene.textures, vec![PathBuf::from("models/rock.png")]);
+        assert_eq!(scene.materials.len(), 2);
+        assert_eq!(
+            scene.materials[1].parameters.base_color,
+            [0.2, 0.6, 0.2, 1.0]
+        );
+        assert_eq!(
+            scene.material_textures(
+                1,
+                &[
+                    material::TextureSlot::Diffuse,
+                    material::TextureSlot::Normal
+                ],
+                Path::new("flat.png")
+            ),
+            vec![
+                (material::TextureSlot::Diffuse, Path::new("models/rock.png")),
+                (material::TextureSlot::Normal, Path::new("flat.png")),
+            ]
+        );
+    }
+
+    #[test]
+    fn gltf_indices_out_of_the_vertices_are_rejected() {
+        let model = gltf::GltfModel {
+            vertices: vec![],
+            indices: vec![0, 1, 2],
+            skeleton: None,
+            clips: vec![],
+            nodes: vec![],
+            materials: vec![],
+            primitives: vec![gltf::GltfPrimitive {
+                first_index: 0,
+                index_count: 3,
+                material: None,
+            }],
+            images: vec![],
+        };
+
+        assert!(match ImportedScene::from_gltf(&model) {
+            Err(Error::OutOfRange(_)) => true,
+            _ => false,
+        });
+    }
+
+    #[test]
+    fn unsupported_files_are_rejected() {
+        assert!(ImportedScene::load(Path::new("scene.fbx"), Path::new("images")).is_err());
+    }
+}
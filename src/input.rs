@@ -0,0 +1,343 @@
+use winit::event::{
+    DeviceEvent, ElementState, MouseButton, MouseScrollDelta, VirtualKeyCode, WindowEvent,
+};
+
+use std::collections::{HashMap, HashSet};
+
+// A button of a device that can be bound to an action. Devices winit does not report,
+// eg. gamepads, are fed with `InputMap::press` and `InputMap::release`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Input {
+    Key(VirtualKeyCode),
+    Mouse(MouseButton),
+    GamepadButton(u32),
+}
+
+// Where the value of an axis comes from
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum AxisBinding {
+    // -1 while negative is held, 1 while positive is held, 0 for both or neither
+    Buttons { negative: Input, positive: Input },
+    // cursor movement in pixels since the last frame, the mouse's own motion in relative
+    // mode, see InputMap::set_relative_mouse
+    MouseX,
+    MouseY,
+    // lines scrolled since the last frame
+    MouseWheel,
+    // set with `InputMap::set_axis_value`, eg. a gamepad stick
+    External(u32),
+}
+
+// Approximate number of pixels scrolled per line for touchpads reporting pixels
+const PIXELS_PER_LINE: f32 = 20.0;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ActionEvent {
+    pub action: String,
+    pub state: ElementState,
+}
+
+// Maps raw input to named actions and axes, eg. "jump" to space or "move_forward" to
+// W and S, so the application queries names and the bindings can be changed at runtime.
+// Actions that only changed within a frame are reported by `just_pressed` and
+// `just_released` until `end_frame` is called.
+#[derive(Debug, Default)]
+pub struct InputMap {
+    actions: HashMap<String, Vec<Input>>,
+    axes: HashMap<String, Vec<AxisBinding>>,
+
+    held: HashSet<Input>,
+    pressed_this_frame: HashSet<Input>,
+    released_this_frame: HashSet<Input>,
+
+    cursor_position: Option<(f32, f32)>,
+    cursor_delta: (f32, f32),
+    relative_mouse: bool,
+    // unaccelerated motion of the mouse, reported whether the cursor moves or not
+    motion_delta: (f32, f32),
+    wheel_delta: f32,
+    external_axes: HashMap<u32, f32>,
+}
+
+impl InputMap {
+    pub fn new() -> InputMap {
+        InputMap::default()
+    }
+
+    // Adds an input to the action, an action can have any number of them
+    pub fn bind_action(&mut self, action: &str, input: Input) {
+        let inputs = self.actions.entry(action.to_string()).or_default();
+        if !inputs.contains(&input) {
+            inputs.push(input);
+        }
+    }
+
+    // Replaces every input of the action
+    pub fn rebind_action(&mut self, action: &str, input: Input) {
+        self.actions.insert(action.to_string(), vec![input]);
+    }
+
+    pub fn unbind_action(&mut self, action: &str) {
+        self.actions.remove(action);
+    }
+
+    pub fn action_bindings(&self, action: &str) -> &[Input] {
+        self.actions
+            .get(action)
+            .map(|inputs| inputs.as_slice())
+            .unwrap_or(&[])
+    }
+
+    // Axes bound to several sources add up their values
+    pub fn bind_axis(&mut self, axis: &str, binding: AxisBinding) {
+        let bindings = self.axes.entry(axis.to_string()).or_default();
+        if !bindings.contains(&binding) {
+            bindings.push(binding);
+        }
+    }
+
+    pub fn rebind_axis(&mut self, axis: &str, binding: AxisBinding) {
+        self.axes.insert(axis.to_string(), vec![binding]);
+    }
+
+    pub fn unbind_axis(&mut self, axis: &str) {
+        self.axes.remove(axis);
+    }
+
+    pub fn axis_bindings(&self, axis: &str) -> &[AxisBinding] {
+        self.axes
+            .get(axis)
+            .map(|bindings| bindings.as_slice())
+            .unwrap_or(&[])
+    }
+
+    // Updates the input state from the event, returns the actions it pressed or released.
+    // Key repeats of a held key are not reported again.
+    pub fn handle_event(&mut self, event: &WindowEvent) -> Vec<ActionEvent> {
+        match event {
+            WindowEvent::KeyboardInput { input, .. } => match input.virtual_keycode {
+                Some(key) => self.set_state(Input::Key(key), input.state),
+                None => vec![],
+            },
+
+            WindowEvent::MouseInput { state, button, .. } => {
+                self.set_state(Input::Mouse(*button), *state)
+            }
+
+            WindowEvent::CursorMoved { position, .. } => {
+                let position = (position.x as f32, position.y as f32);
+                if let Some(last) = self.cursor_position {
+                    self.cursor_delta.0 += position.0 - last.0;
+                    self.cursor_delta.1 += position.1 - last.1;
+                }
+                self.cursor_position = Some(position);
+                vec![]
+            }
+
+            WindowEvent::CursorLeft { .. } => {
+                self.cursor_position = None;
+                vec![]
+            }
+
+            WindowEvent::MouseWheel { delta, .. } => {
+                self.wheel_delta += match delta {
+                    MouseScrollDelta::LineDelta(_, y) => *y,
+                    MouseScrollDelta::PixelDelta(position) => position.y as f32 / PIXELS_PER_LINE,
+                };
+                vec![]
+            }
+
+            // nothing is held anymore once the window is not receiving the releases
+            WindowEvent::Focused(false) => {
+                let held = self.held.iter().cloned().collect::<Vec<Input>>();
+                held.into_iter()
+                    .flat_map(|input| self.set_state(input, ElementState::Released))
+                    .collect()
+            }
+
+            _ => vec![],
+        }
+    }
+
+    // Raw mouse motion, which keeps being reported while the cursor is locked
+    pub fn handle_device_event(&mut self, event: &DeviceEvent) {
+        if let DeviceEvent::MouseMotion { delta } = event {
+            self.motion_delta.0 += delta.0 as f32;
+            self.motion_delta.1 += delta.1 as f32;
+        }
+    }
+
+    // Mouse axes follow the mouse's motion instead of the cursor, eg. while the cursor is
+    // locked, see display::CursorMode::Locked
+    pub fn set_relative_mouse(&mut self, relative: bool) {
+        self.relative_mouse = relative;
+    }
+
+    pub fn is_relative_mouse(&self) -> bool {
+        self.relative_mouse
+    }
+
+    pub fn press(&mut self, input: Input) -> Vec<ActionEvent> {
+        self.set_state(input, ElementState::Pressed)
+    }
+
+    pub fn release(&mut self, input: Input) -> Vec<ActionEvent> {
+        self.set_state(input, ElementState::Released)
+    }
+
+    pub fn set_axis_value(&mut self, id: u32, value: f32) {
+        self.external_axes.insert(id, value);
+    }
+
+    fn set_state(&mut self, input: Input, state: ElementState) -> Vec<ActionEvent> {
+        let changed = match state {
+            ElementState::Pressed => self.held.insert(input),
+            ElementState::Released => self.held.remove(&input),
+        };
+
+        if !changed {
+            return vec![];
+        }
+
+        match state {
+            ElementState::Pressed => self.pressed_this_frame.insert(input),
+            ElementState::Released => self.released_this_frame.insert(input),
+        };
+
+        let mut events = self
+            .actions
+            .iter()
+            .filter(|(_, inputs)| inputs.contains(&input))
+            .map(|(action, _)| ActionEvent {
+                action: action.clone(),
+                state,
+            })
+            .collect::<Vec<ActionEvent>>();
+
+        // the order of the map is arbitrary
+        events.sort_by(|a, b| a.action.cmp(&b.action));
+        events
+    }
+
+    fn any_bound(&self, action: &str, inputs: &HashSet<Input>) -> bool {
+        self.action_bindings(action)
+            .iter()
+            .any(|input| inputs.contains(input))
+    }
+
+    pub fn is_pressed(&self, action: &str) -> bool {
+        self.any_bound(action, &self.held)
+    }
+
+    pub fn just_pressed(&self, action: &str) -> bool {
+        self.any_bound(action, &self.pressed_this_frame)
+    }
+
+    pub fn just_released(&self, action: &str) -> bool {
+        self.any_bound(action, &self.released_this_frame)
+    }
+
+    // Sum of the axis' bindings, 0 for an unbound axis
+    pub fn axis(&self, axis: &str) -> f32 {
+        self.axis_bindings(axis)
+            .iter()
+            .map(|binding| match *binding {
+                AxisBinding::Buttons { negative, positive } => {
+                    let value = |input| if self.held.contains(&input) { 1.0 } else { 0.0 };
+                    value(positive) - value(negative)
+                }
+                AxisBinding::MouseX if self.relative_mouse => self.motion_delta.0,
+                AxisBinding::MouseY if self.relative_mouse => self.motion_delta.1,
+                AxisBinding::MouseX => self.cursor_delta.0,
+                AxisBinding::MouseY => self.cursor_delta.1,
+                AxisBinding::MouseWheel => self.wheel_delta,
+                AxisBinding::External(id) => self.external_axes.get(&id).cloned().unwrap_or(0.0),
+            })
+            .sum()
+    }
+
+    // Clears the per frame state, called once the application has been updated
+    pub fn end_frame(&mut self) {
+        self.pressed_this_frame.clear();
+        self.released_this_frame.clear();
+        self.cursor_delta = (0.0, 0.0);
+        self.motion_delta = (0.0, 0.0);
+        self.wheel_delta = 0.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn actions_follow_their_bindings() {
+        let mut input = InputMap::new();
+        input.bind_action("jump", Input::Key(VirtualKeyCode::Space));
+        input.bind_action("jump", Input::GamepadButton(0));
+
+        let events = input.press(Input::Key(VirtualKeyCode::Space));
+        assert_eq!(
+            events,
+            vec![ActionEvent {
+                action: "jump".to_string(),
+                state: ElementState::Pressed,
+            }]
+        );
+        // a key repeat is not a new press
+        assert!(input.press(Input::Key(VirtualKeyCode::Space)).is_empty());
+        assert!(input.is_pressed("jump") && input.just_pressed("jump"));
+
+        input.end_frame();
+        assert!(input.is_pressed("jump") && !input.just_pressed("jump"));
+
+        input.release(Input::Key(VirtualKeyCode::Space));
+        assert!(!input.is_pressed("jump") && input.just_released("jump"));
+
+        input.rebind_action("jump", Input::Key(VirtualKeyCode::W));
+        assert!(input.press(Input::GamepadButton(0)).is_empty());
+        assert!(!input.is_pressed("jump"));
+    }
+
+    #[test]
+    fn button_axes_cancel_out() {
+        let mut input = InputMap::new();
+        input.bind_axis(
+            "move_forward",
+            AxisBinding::Buttons {
+                negative: Input::Key(VirtualKeyCode::S),
+                positive: Input::Key(VirtualKeyCode::W),
+            },
+        );
+        input.bind_axis("move_forward", AxisBinding::External(1));
+
+        input.press(Input::Key(VirtualKeyCode::W));
+        assert_eq!(input.axis("move_forward"), 1.0);
+
+        input.press(Input::Key(VirtualKeyCode::S));
+        assert_eq!(input.axis("move_forward"), 0.0);
+
+        input.set_axis_value(1, -0.5);
+        assert_eq!(input.axis("move_forward"), -0.5);
+        assert_eq!(input.axis("unbound"), 0.0);
+    }
+
+    #[test]
+    fn relative_mode_reads_the_mouse_motion() {
+        let mut input = InputMap::new();
+        input.bind_axis("look_x", AxisBinding::MouseX);
+        input.bind_axis("look_y", AxisBinding::MouseY);
+
+        input.handle_device_event(&DeviceEvent::MouseMotion { delta: (3.0, -2.0) });
+        input.handle_device_event(&DeviceEvent::MouseMotion { delta: (1.0, 0.0) });
+        // the cursor did not move
+        assert_eq!(input.axis("look_x"), 0.0);
+
+        input.set_relative_mouse(true);
+        assert_eq!(input.axis("look_x"), 4.0);
+        assert_eq!(input.axis("look_y"), -2.0);
+
+        input.end_frame();
+        assert_eq!(input.axis("look_x"), 0.0);
+    }
+}
@@ -1,6 +1,28 @@
+pub mod animation;
 pub mod app;
+pub mod assets;
+pub mod benchmark;
+pub mod config_file;
+pub mod debug_draw;
+pub mod display;
+pub mod engine;
+pub mod engine_events;
+pub mod error;
 pub mod foreign;
+pub mod gltf;
+pub mod import;
+pub mod input;
+pub mod logging;
+pub mod mesh_processing;
+pub mod obj;
 pub mod platforms;
+pub mod projection;
+pub mod scene;
 
 pub mod shaderc;
+pub mod terrain;
 pub mod vulkan;
+#[cfg(feature = "xr")]
+pub mod xr;
+
+pub use error::{Error, Result};
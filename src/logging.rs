@@ -0,0 +1,25 @@
+// Sets up the tracing subscriber the engine logs to. The engine itself only emits events
+// and spans, setup stages under "setup" and every frame under "frame", so applications
+// with their own subscriber can skip this.
+
+use tracing_subscriber::EnvFilter;
+
+use crate::error::{Error, Result};
+
+// Status messages and everything worse, validation messages down to warnings
+pub const DEFAULT_FILTER: &str = "info,kelsier::vulkan::debug=warn";
+
+// Filters are directives like "kelsier=debug,kelsier::vulkan=trace". Without one the
+// RUST_LOG environment variable is used, then DEFAULT_FILTER.
+pub fn init(filter: Option<&str>) -> Result<()> {
+    let filter = match filter {
+        Some(filter) => EnvFilter::try_new(filter),
+        None => EnvFilter::try_from_default_env().or_else(|_| EnvFilter::try_new(DEFAULT_FILTER)),
+    }
+    .map_err(|e| Error::Message(format!("invalid log filter: {}", e)))?;
+
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .try_init()
+        .map_err(|e| Error::Message(format!("cannot install the logger: {}", e)))
+}
@@ -1,159 +1,231 @@
 use winit::{
-    event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent},
-    event_loop::{ControlFlow, EventLoop},
-    window::{Window, WindowBuilder},
+    event::{ElementState, VirtualKeyCode},
+    event_loop::EventLoop,
+    window::Window,
 };
 
-use ash::version::DeviceV1_0;
+use kelsier::benchmark;
+use kelsier::config_file;
+use kelsier::engine;
+use kelsier::input::{ActionEvent, Input, InputMap};
+use kelsier::logging;
+use kelsier::vulkan::{adapter, instance, probe, surface};
 
-use kelsier::{
-    app, shaderc,
-    vulkan::constants::*,
-    vulkan::{buffers, device, instance, pipeline, queue, surface, swapchain, sync},
-};
-
-use anyhow::{Context, Result};
+use anyhow::Result;
 
-struct VulkanApp {
-    instance: instance::VulkanInstance,
+struct Demo {
+    benchmark: Option<benchmark::Benchmark>,
 }
 
-impl VulkanApp {
-    fn init_window(event_loop: &EventLoop<()>) -> Result<Window> {
-        WindowBuilder::new()
-            .with_title(WINDOW_TITLE)
-            .with_inner_size(winit::dpi::LogicalSize::new(WINDOW_WIDTH, WINDOW_HEIGHT))
-            .build(event_loop)
-            .context("failed to create window")
-    }
+impl engine::Application for Demo {
+    fn on_action(
+        &mut self,
+        engine: &mut engine::Engine,
+        window: &Window,
+        action: &ActionEvent,
+    ) -> engine::LoopAction {
+        if action.state != ElementState::Pressed {
+            return engine::LoopAction::Continue;
+        }
+
+        match action.action.as_str() {
+            "quit" => return engine::LoopAction::Exit,
 
-    pub fn run_game_loop(
-        self,
-        event_loop: EventLoop<()>,
-        window: Window,
-        mut frame: sync::Objects<app::UniformBuffer>,
-    ) -> Result<()> {
-        event_loop.run(move |event, _, control_flow| {
-            // *control_flow = ControlFlow::Wait;
-
-            match event {
-                Event::WindowEvent { event, .. } => match event {
-                    WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
-
-                    WindowEvent::KeyboardInput { input, .. } => match input {
-                        KeyboardInput {
-                            virtual_keycode,
-                            state,
-                            ..
-                        } => match (virtual_keycode, state) {
-                            (Some(VirtualKeyCode::Escape), ElementState::Pressed) => {
-                                *control_flow = ControlFlow::Exit;
-                                ()
-                            }
-
-                            _ => (),
-                        },
-                    },
-
-                    _ => (),
-                },
-
-                Event::MainEventsCleared => window.request_redraw(),
-
-                // todo draw frame on this
-                Event::RedrawRequested(_window_id) => {
-                    match frame.next().transpose() {
-                        Ok(_) => (),
-                        Err(e) => {
-                            println!("Error occurred: {}", e);
-                            panic!(e)
-                        }
-                    };
+            "toggle_wireframe" => {
+                if let Err(e) = engine.toggle_wireframe() {
+                    tracing::error!("cannot toggle wireframe: {}", e);
                 }
+            }
 
-                Event::LoopDestroyed => unsafe {
-                    frame
-                        .device
-                        .device_wait_idle()
-                        .expect("failed to wait evice idele!")
-                },
+            "toggle_fullscreen" => match engine.toggle_fullscreen(window) {
+                Ok(mode) => tracing::info!("window mode {:?}", mode),
+                Err(e) => tracing::error!("cannot toggle fullscreen: {}", e),
+            },
 
-                _ => (),
+            "toggle_cursor_lock" => {
+                let mode = engine.toggle_cursor_lock(window);
+                tracing::info!("cursor mode {:?}", mode);
             }
-        });
+
+            "capture_frame" => {
+                if let Err(e) = engine.capture_next_frame() {
+                    tracing::error!("cannot capture frame: {}", e);
+                }
+            }
+
+            _ => (),
+        }
+
+        engine::LoopAction::Continue
     }
 
-    pub fn setup(
-        &self,
-        window: &winit::window::Window,
-    ) -> Result<sync::Objects<app::UniformBuffer>> {
-        let surface_info =
-            surface::SurfaceInfo::new(&self.instance, window, WINDOW_WIDTH, WINDOW_HEIGHT)?;
-
-        let device = device::Device::new(&self.instance.instance, &surface_info)?;
-
-        let queue = queue::Queue::new(&device);
-
-        let swapchain = swapchain::SwapchainDetails::new(
-            &self.instance.instance,
-            &device,
-            window,
-            &device.family_indices,
-            &surface_info,
-        )?;
-        println!("swapchain created");
-
-        let shaders = shaderc::ShaderSource {
-            vertex_shader_file: "shaders/shader.vert".to_string(),
-            fragment_shader_file: "shaders/shader.frag".to_string(),
-        };
-
-        let pipeline_detail = pipeline::PipelineDetail::create_graphics_pipeline(
-            &self.instance.instance,
-            &device,
-            &swapchain,
-            shaders,
-            app::VERTICES[0],
-        )?;
-        println!("pipeline created");
-
-        let uniform_buffer_data = app::UniformBuffer::new(swapchain.extent);
-
-        let buffer_details = buffers::BufferDetails::new(
-            &self.instance.instance,
-            &device,
-            queue.graphics,
-            pipeline_detail,
-            &swapchain,
-            app::VERTICES.to_vec(),
-            app::INDICES.to_vec(),
-            uniform_buffer_data,
-            std::path::Path::new("textures/winter.jpeg"),
-        )?;
-        println!("buffers created");
-
-        // For some reason frames in flight needs to be set to 3 as only 3 uniform buffers are being created in macOS.
-        //TODO: Need to fix this
-        sync::Objects::new(device.logical_device, queue, swapchain, buffer_details, 10)
+    fn frame_rendered(&mut self, engine: &mut engine::Engine) -> engine::LoopAction {
+        if let Some(benchmark) = self.benchmark.as_mut() {
+            benchmark.frame_rendered(engine);
+
+            if benchmark.is_done() {
+                println!("{}", benchmark.report());
+                return engine::LoopAction::Exit;
+            }
+        }
+
+        engine::LoopAction::Continue
     }
+}
+
+// Prints a capability report of all gpus as json, used with --probe
+fn print_probe_report(window: &Window) -> Result<()> {
+    let instance = instance::VulkanInstance::new()?;
+    let surface_info = surface::SurfaceInfo::new(&instance, window)?;
 
-    pub fn new() -> Result<VulkanApp> {
-        instance::VulkanInstance::new().map(|instance| VulkanApp { instance })
+    // the surface is destroyed before the instance is dropped, also when probing failed
+    let report = probe::probe_to_json(&instance, Some(&surface_info));
+    surface_info.destroy();
+
+    println!("{}", report?);
+    Ok(())
+}
+
+// Prints the gpus the engine can be started on, used with --gpus
+fn print_adapters(config: &engine::EngineConfig, window: &Window) -> Result<()> {
+    for adapter in engine::Engine::enumerate_adapters(config, window)? {
+        let queues = adapter
+            .queue_families
+            .iter()
+            .map(|family| {
+                let mut capabilities = vec![];
+                if family.graphics {
+                    capabilities.push("graphics");
+                }
+                if family.compute {
+                    capabilities.push("compute");
+                }
+                if family.present {
+                    capabilities.push("present");
+                }
+                format!("{}x {}", family.queue_count, capabilities.join("+"))
+            })
+            .collect::<Vec<String>>();
+
+        println!(
+            "{}: {} ({}, {} MiB local, {} MiB shared){} queues: {}",
+            adapter.index,
+            adapter.name,
+            adapter.device_type,
+            adapter.device_local_memory / (1024 * 1024),
+            adapter.shared_memory / (1024 * 1024),
+            if adapter.suitable { "" } else { " unsuitable," },
+            queues.join(", ")
+        );
     }
+    Ok(())
+}
+
+fn bind_demo_actions(input: &mut InputMap) {
+    input.bind_action("quit", Input::Key(VirtualKeyCode::Escape));
+    input.bind_action("toggle_wireframe", Input::Key(VirtualKeyCode::F));
+    input.bind_action("toggle_fullscreen", Input::Key(VirtualKeyCode::F11));
+    input.bind_action("capture_frame", Input::Key(VirtualKeyCode::F12));
+    input.bind_action("toggle_cursor_lock", Input::Key(VirtualKeyCode::Tab));
 }
 
 fn main() -> Result<()> {
-    let app = VulkanApp::new()?;
+    let args: Vec<String> = std::env::args().collect();
+
+    // --log <filter> overrides RUST_LOG, eg. --log kelsier=debug
+    let log_filter = args
+        .windows(2)
+        .find(|pair| pair[0] == "--log")
+        .map(|pair| pair[1].as_str());
+    logging::init(log_filter)?;
+
+    let mut config = engine::EngineConfig::default();
+
+    // --config <file> reads the settings from the file instead of kelsier.toml, the flags
+    // below override them
+    match args
+        .windows(2)
+        .find(|pair| pair[0] == "--config")
+        .map(|pair| &pair[1])
+    {
+        Some(path) => config_file::ConfigFile::load(path.as_ref())?.apply(&mut config)?,
+        None => {
+            let path = std::path::Path::new(config_file::CONFIG_FILE);
+            if let Some(file) = config_file::ConfigFile::load_if_exists(path)? {
+                file.apply(&mut config)?;
+            }
+        }
+    }
+
+    // --obj <file> draws an OBJ mesh instead of the built-in quads
+    if let Some(path) = args
+        .windows(2)
+        .find(|pair| pair[0] == "--obj")
+        .map(|pair| &pair[1])
+    {
+        config.mesh = engine::MeshSource::Obj(path.into());
+    }
+
+    // --gpu <index or name> renders with the given gpu, see --gpus
+    if let Some(selection) = args
+        .windows(2)
+        .find(|pair| pair[0] == "--gpu")
+        .map(|pair| adapter::DeviceSelection::parse(&pair[1]))
+    {
+        config.device_selection = selection;
+    }
+
+    // --benchmark renders the benchmark scene, --cubes and --frames override its defaults
+    // and --hidden keeps the window from being shown
+    let option_value = |name: &str| {
+        args.windows(2)
+            .find(|pair| pair[0] == name)
+            .map(|pair| pair[1].parse::<u32>())
+    };
+    let benchmark_config = if args.iter().any(|arg| arg == "--benchmark") {
+        let mut benchmark_config = benchmark::BenchmarkConfig::default();
+        if let Some(cubes) = option_value("--cubes") {
+            benchmark_config = benchmark_config.with_cubes(cubes?);
+        }
+        if let Some(frames) = option_value("--frames") {
+            benchmark_config = benchmark_config.with_frames(frames?);
+        }
+        benchmark_config.apply(&mut config);
+        Some(benchmark_config)
+    } else {
+        None
+    };
+
     let event_loop = EventLoop::new();
-    let window = VulkanApp::init_window(&event_loop).expect("cannot create window");
+    let window = engine::Engine::init_window(&config, &event_loop).expect("cannot create window");
 
-    let frame = match app.setup(&window) {
-        Ok(obj) => obj,
+    if args.iter().any(|arg| arg == "--probe") {
+        return print_probe_report(&window);
+    }
+
+    if args.iter().any(|arg| arg == "--gpus") {
+        return print_adapters(&config, &window);
+    }
+
+    if args.iter().any(|arg| arg == "--hidden") {
+        window.set_visible(false);
+    }
+
+    let mut engine = match engine::Engine::new(config, &window) {
+        Ok(engine) => engine,
         Err(e) => {
-            println!("Setup failed {:?}", e);
-            panic!(e);
+            tracing::error!("setup failed: {:?}", e);
+            return Err(e.into());
         }
     };
+    bind_demo_actions(&mut engine.input);
+
+    let benchmark = match benchmark_config {
+        Some(benchmark_config) => Some(benchmark::Benchmark::new(&mut engine, benchmark_config)?),
+        None => None,
+    };
 
-    app.run_game_loop(event_loop, window, frame)
+    engine
+        .with_application(Box::new(Demo { benchmark }))
+        .run(window, event_loop)
 }
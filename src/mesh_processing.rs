@@ -0,0 +1,325 @@
+// Cpu side processing of imported meshes before they are uploaded: identical vertices
+// are merged behind an index buffer, triangles are reordered for the gpu's post
+// transform vertex cache and vertices for fetch locality, like meshoptimizer does.
+
+use crate::app;
+
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+
+// Entries of the simulated vertex cache the triangle order is optimized for
+pub const VERTEX_CACHE_SIZE: usize = 32;
+
+// Scoring of Tom Forsyth's "Linear-Speed Vertex Cache Optimisation"
+const CACHE_DECAY_POWER: f32 = 1.5;
+const LAST_TRIANGLE_SCORE: f32 = 0.75;
+const VALENCE_BOOST_SCALE: f32 = 2.0;
+const VALENCE_BOOST_POWER: f32 = 0.5;
+
+// Indices drawing an unindexed vertex stream in order
+pub fn sequential_indices(vertex_count: usize) -> Vec<u32> {
+    (0..vertex_count as u32).collect()
+}
+
+// Merges the vertices with equal keys, returns the remaining vertices in the order they
+// are first used and the indices remapped to them. Vertices no index uses are dropped.
+pub fn deduplicate_by<V, K, F>(vertices: &[V], indices: &[u32], key: F) -> (Vec<V>, Vec<u32>)
+where
+    V: Copy,
+    K: Hash + Eq,
+    F: Fn(&V) -> K,
+{
+    let mut unique = Vec::with_capacity(vertices.len());
+    let mut remapped = HashMap::with_capacity(vertices.len());
+
+    let indices = indices
+        .iter()
+        .map(|&index| {
+            let vertex = vertices[index as usize];
+            *remapped.entry(key(&vertex)).or_insert_with(|| {
+                unique.push(vertex);
+                unique.len() as u32 - 1
+            })
+        })
+        .collect();
+
+    (unique, indices)
+}
+
+// Vertices are equal when every component has the same bits
+fn vertex_key(vertex: &app::VertexData) -> [u32; 11] {
+    let mut key = [0; 11];
+    let components = vertex
+        .pos
+        .iter()
+        .chain(vertex.color.iter())
+        .chain(vertex.tex_coord.iter())
+        .chain(vertex.normal.iter());
+
+    for (bits, component) in key.iter_mut().zip(components) {
+        *bits = component.to_bits();
+    }
+    key
+}
+
+pub fn deduplicate(
+    vertices: &[app::VertexData],
+    indices: &[u32],
+) -> (Vec<app::VertexData>, Vec<u32>) {
+    deduplicate_by(vertices, indices, vertex_key)
+}
+
+// Builds the index buffer of an unindexed vertex stream, three vertices per triangle
+pub fn index_stream(vertices: &[app::VertexData]) -> (Vec<app::VertexData>, Vec<u32>) {
+    deduplicate(vertices, &sequential_indices(vertices.len()))
+}
+
+fn vertex_score(cache_position: Option<usize>, remaining_triangles: usize) -> f32 {
+    // no triangle is left to be drawn with the vertex
+    if remaining_triangles == 0 {
+        return -1.0;
+    }
+
+    let cache_score = match cache_position {
+        None => 0.0,
+        // the vertices of the last triangle, which is in the cache either way
+        Some(position) if position < 3 => LAST_TRIANGLE_SCORE,
+        Some(position) => {
+            let scale = 1.0 / (VERTEX_CACHE_SIZE - 3) as f32;
+            (1.0 - (position - 3) as f32 * scale).powf(CACHE_DECAY_POWER)
+        }
+    };
+
+    // favors vertices with few triangles left, so lone triangles are not left behind
+    cache_score + VALENCE_BOOST_SCALE * (remaining_triangles as f32).powf(-VALENCE_BOOST_POWER)
+}
+
+// Reorders the triangles so consecutive ones reuse the vertices still in the cache.
+// Trailing indices that do not form a whole triangle are dropped.
+pub fn optimize_vertex_cache(indices: &[u32], vertex_count: usize) -> Vec<u32> {
+    let triangle_count = indices.len() / 3;
+    let triangle = |t: usize| &indices[t * 3..t * 3 + 3];
+
+    // the triangles each vertex is used by, that were not emitted yet
+    let mut adjacency = vec![vec![]; vertex_count];
+    for t in 0..triangle_count {
+        for &vertex in triangle(t) {
+            adjacency[vertex as usize].push(t);
+        }
+    }
+
+    let mut cache_position = vec![None; vertex_count];
+    let mut scores = (0..vertex_count)
+        .map(|vertex| vertex_score(None, adjacency[vertex].len()))
+        .collect::<Vec<f32>>();
+    let mut triangle_scores = (0..triangle_count)
+        .map(|t| triangle(t).iter().map(|&v| scores[v as usize]).sum())
+        .collect::<Vec<f32>>();
+
+    let mut emitted = vec![false; triangle_count];
+    // start of the unemitted triangles searched when no cached vertex has one left
+    let mut next_unemitted = 0;
+    let mut cache: Vec<u32> = Vec::with_capacity(VERTEX_CACHE_SIZE + 3);
+    let mut optimized = Vec::with_capacity(triangle_count * 3);
+    let mut best = None;
+
+    for _ in 0..triangle_count {
+        let next = match best {
+            Some(next) => next,
+            None => {
+                while emitted[next_unemitted] {
+                    next_unemitted += 1;
+                }
+                next_unemitted
+            }
+        };
+
+        emitted[next] = true;
+        optimized.extend_from_slice(triangle(next));
+
+        let mut updated_cache = Vec::with_capacity(VERTEX_CACHE_SIZE + 3);
+        for &vertex in triangle(next) {
+            adjacency[vertex as usize].retain(|&t| t != next);
+            if !updated_cache.contains(&vertex) {
+                updated_cache.push(vertex);
+            }
+        }
+        for &vertex in cache.iter() {
+            if !updated_cache.contains(&vertex) {
+                updated_cache.push(vertex);
+            }
+        }
+
+        // evicted vertices are rescored too, their score dropped
+        for (position, &vertex) in updated_cache.iter().enumerate() {
+            cache_position[vertex as usize] = if position < VERTEX_CACHE_SIZE {
+                Some(position)
+            } else {
+                None
+            };
+        }
+
+        best = None;
+        let mut best_score = std::f32::MIN;
+        for &vertex in updated_cache.iter() {
+            let vertex = vertex as usize;
+            let score = vertex_score(cache_position[vertex], adjacency[vertex].len());
+            let change = score - scores[vertex];
+            scores[vertex] = score;
+
+            for &t in adjacency[vertex].iter() {
+                triangle_scores[t] += change;
+            }
+        }
+        for &vertex in updated_cache.iter().take(VERTEX_CACHE_SIZE) {
+            for &t in adjacency[vertex as usize].iter() {
+                if triangle_scores[t] > best_score {
+                    best_score = triangle_scores[t];
+                    best = Some(t);
+                }
+            }
+        }
+
+        updated_cache.truncate(VERTEX_CACHE_SIZE);
+        cache = updated_cache;
+    }
+
+    optimized
+}
+
+// Reorders the vertices by their first use in the indices, so vertices drawn together are
+// fetched from nearby memory. Vertices no index uses are dropped.
+pub fn optimize_vertex_fetch<V: Copy>(vertices: &[V], indices: &[u32]) -> (Vec<V>, Vec<u32>) {
+    let mut remapped = vec![None; vertices.len()];
+    let mut reordered = Vec::with_capacity(vertices.len());
+
+    let indices = indices
+        .iter()
+        .map(|&index| {
+            *remapped[index as usize].get_or_insert_with(|| {
+                reordered.push(vertices[index as usize]);
+                reordered.len() as u32 - 1
+            })
+        })
+        .collect();
+
+    (reordered, indices)
+}
+
+// Vertices transformed per triangle with a FIFO cache of the size, 3 when nothing is
+// reused and 0.5 at best for large grids
+pub fn average_cache_miss_ratio(indices: &[u32], cache_size: usize) -> f32 {
+    let triangle_count = indices.len() / 3;
+    if triangle_count == 0 {
+        return 0.0;
+    }
+
+    let mut cache = VecDeque::with_capacity(cache_size);
+    let mut misses = 0;
+    for &index in indices[..triangle_count * 3].iter() {
+        if !cache.contains(&index) {
+            misses += 1;
+            if cache.len() == cache_size {
+                cache.pop_front();
+            }
+            cache.push_back(index);
+        }
+    }
+
+    misses as f32 / triangle_count as f32
+}
+
+// Deduplicates the vertices and optimizes the mesh for the vertex cache and for fetching
+pub fn optimize(vertices: &[app::VertexData], indices: &[u32]) -> (Vec<app::VertexData>, Vec<u32>) {
+    let (vertices, indices) = deduplicate(vertices, indices);
+    let indices = optimize_vertex_cache(&indices, vertices.len());
+    optimize_vertex_fetch(&vertices, &indices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vertex(x: f32, y: f32) -> app::VertexData {
+        app::VertexData {
+            pos: [x, y, 0.0],
+            color: [1.0, 1.0, 1.0],
+            tex_coord: [x, y],
+            normal: [0.0, 0.0, 1.0],
+        }
+    }
+
+    // Triangles of a grid of quads, row by row with the rows drawn in alternating columns
+    // so consecutive rows share few cached vertices
+    fn grid(size: u32) -> (Vec<app::VertexData>, Vec<u32>) {
+        let vertices = (0..=size)
+            .flat_map(|y| (0..=size).map(move |x| vertex(x as f32, y as f32)))
+            .collect();
+
+        let at = |x: u32, y: u32| y * (size + 1) + x;
+        let mut indices = vec![];
+        for x in 0..size {
+            for y in 0..size {
+                indices.extend_from_slice(&[at(x, y), at(x + 1, y), at(x + 1, y + 1)]);
+                indices.extend_from_slice(&[at(x, y), at(x + 1, y + 1), at(x, y + 1)]);
+            }
+        }
+
+        (vertices, indices)
+    }
+
+    #[test]
+    fn unindexed_streams_share_equal_vertices() {
+        let quad = [
+            vertex(0.0, 0.0),
+            vertex(1.0, 0.0),
+            vertex(1.0, 1.0),
+            vertex(0.0, 0.0),
+            vertex(1.0, 1.0),
+            vertex(0.0, 1.0),
+        ];
+
+        let (vertices, indices) = index_stream(&quad);
+        assert_eq!(vertices.len(), 4);
+        assert_eq!(indices, vec![0, 1, 2, 0, 2, 3]);
+
+        // only bit-identical vertices are merged
+        let mut flipped = quad;
+        flipped[3].pos[2] = -0.0;
+        assert_eq!(index_stream(&flipped).0.len(), 5);
+    }
+
+    #[test]
+    fn optimized_grids_miss_the_cache_less() {
+        let (vertices, indices) = grid(64);
+        let (optimized_vertices, optimized_indices) = optimize(&vertices, &indices);
+
+        assert_eq!(optimized_vertices.len(), vertices.len());
+        assert_eq!(optimized_indices.len(), indices.len());
+
+        // the same triangles, possibly in a different order
+        let triangles = |vertices: &[app::VertexData], indices: &[u32]| {
+            let mut triangles = indices
+                .chunks(3)
+                .map(|triangle| {
+                    let mut keys = triangle
+                        .iter()
+                        .map(|&index| vertex_key(&vertices[index as usize]))
+                        .collect::<Vec<_>>();
+                    keys.sort();
+                    keys
+                })
+                .collect::<Vec<_>>();
+            triangles.sort();
+            triangles
+        };
+        assert_eq!(
+            triangles(&vertices, &indices),
+            triangles(&optimized_vertices, &optimized_indices)
+        );
+
+        let before = average_cache_miss_ratio(&indices, VERTEX_CACHE_SIZE);
+        let after = average_cache_miss_ratio(&optimized_indices, VERTEX_CACHE_SIZE);
+        assert!(after < before * 0.8, "acmr {} -> {}", before, after);
+    }
+}
@@ -0,0 +1,483 @@
+// Loader for Wavefront OBJ meshes and their MTL materials, enough for simple assets
+// without pulling in a native importer. Faces are triangulated as fans, and groups using
+// a different material become separate meshes.
+
+use crate::app;
+use crate::error::{Context, Error, Result};
+use crate::mesh_processing;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjMaterial {
+    pub name: String,
+    // Kd, also used as the vertex color of the meshes using the material
+    pub diffuse_color: [f32; 3],
+    // map_Kd, relative paths are resolved against the directory of the MTL file
+    pub diffuse_texture: Option<PathBuf>,
+}
+
+impl ObjMaterial {
+    fn new(name: &str) -> ObjMaterial {
+        ObjMaterial {
+            name: name.to_string(),
+            diffuse_color: [1.0, 1.0, 1.0],
+            diffuse_texture: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ObjMesh {
+    pub vertices: Vec<app::VertexData>,
+    pub indices: Vec<u32>,
+    // index into ObjModel::materials
+    pub material: Option<usize>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ObjModel {
+    pub meshes: Vec<ObjMesh>,
+    pub materials: Vec<ObjMaterial>,
+}
+
+// Reads the materials of an MTL file, texture paths are resolved against base_dir
+pub fn parse_mtl(source: &str, base_dir: &Path) -> Result<Vec<ObjMaterial>> {
+    let mut materials: Vec<ObjMaterial> = vec![];
+
+    for (number, line) in source.lines().enumerate() {
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) if !keyword.starts_with('#') => keyword,
+            _ => continue,
+        };
+
+        let error = |message: &str| Error::msg(format!("mtl line {}: {}", number + 1, message));
+
+        match keyword {
+            "newmtl" => {
+                let name = tokens
+                    .next()
+                    .ok_or_else(|| error("material without name"))?;
+                materials.push(ObjMaterial::new(name));
+            }
+            "Kd" => {
+                let material = materials
+                    .last_mut()
+                    .ok_or_else(|| error("Kd before newmtl"))?;
+                let color = parse_floats(tokens, 3).ok_or_else(|| error("invalid Kd"))?;
+                material.diffuse_color = [color[0], color[1], color[2]];
+            }
+            "map_Kd" => {
+                let material = materials
+                    .last_mut()
+                    .ok_or_else(|| error("map_Kd before newmtl"))?;
+                // options like -s come before the file name, which is the last token
+                let file = tokens.last().ok_or_else(|| error("map_Kd without file"))?;
+                material.diffuse_texture = Some(base_dir.join(file));
+            }
+            // the rest of the lighting model is not used by the renderer
+            _ => {}
+        }
+    }
+
+    Ok(materials)
+}
+
+fn parse_floats<'a, I: Iterator<Item = &'a str>>(tokens: I, count: usize) -> Option<Vec<f32>> {
+    let values = tokens
+        .take(count)
+        .map(|token| token.parse::<f32>().ok())
+        .collect::<Option<Vec<f32>>>()?;
+
+    if values.len() == count {
+        Some(values)
+    } else {
+        None
+    }
+}
+
+// 1 based and negative indices, relative to the end of the list, into a 0 based index
+fn resolve_index(token: &str, len: usize) -> Option<usize> {
+    let index = token.parse::<i64>().ok()?;
+
+    let resolved = if index > 0 {
+        index - 1
+    } else {
+        len as i64 + index
+    };
+
+    if resolved >= 0 && (resolved as usize) < len {
+        Some(resolved as usize)
+    } else {
+        None
+    }
+}
+
+// Position, texture coordinate and normal indices of a face corner, eg. 3/1/2 or 3//2
+type Corner = (usize, Option<usize>, Option<usize>);
+
+struct Parser {
+    positions: Vec<[f32; 3]>,
+    tex_coords: Vec<[f32; 2]>,
+    normals: Vec<[f32; 3]>,
+    materials: Vec<ObjMaterial>,
+    meshes: Vec<ObjMesh>,
+    // vertices of the current mesh by the corner they were created from
+    corners: HashMap<Corner, u32>,
+}
+
+impl Parser {
+    fn current_mesh(&mut self) -> &mut ObjMesh {
+        if self.meshes.is_empty() {
+            self.meshes.push(ObjMesh::default());
+        }
+
+        let last = self.meshes.len() - 1;
+        &mut self.meshes[last]
+    }
+
+    fn use_material(&mut self, name: &str) -> Result<()> {
+        let material = self
+            .materials
+            .iter()
+            .position(|material| material.name == name)
+            .ok_or_else(|| Error::msg(format!("unknown material {}", name)))?;
+
+        match self.meshes.last_mut() {
+            // nothing was drawn with the previous material
+            Some(mesh) if mesh.indices.is_empty() => mesh.material = Some(material),
+            _ => self.meshes.push(ObjMesh {
+                material: Some(material),
+                ..Default::default()
+            }),
+        }
+
+        self.corners.clear();
+        Ok(())
+    }
+
+    fn parse_corner(&self, token: &str) -> Option<Corner> {
+        let mut parts = token.split('/');
+
+        let position = resolve_index(parts.next()?, self.positions.len())?;
+        let tex_coord = match parts.next() {
+            None | Some("") => None,
+            Some(index) => Some(resolve_index(index, self.tex_coords.len())?),
+        };
+        let normal = match parts.next() {
+            None | Some("") => None,
+            Some(index) => Some(resolve_index(index, self.normals.len())?),
+        };
+
+        Some((position, tex_coord, normal))
+    }
+
+    fn face_normal(&self, corners: &[Corner]) -> [f32; 3] {
+        let (a, b, c) = (
+            self.positions[corners[0].0],
+            self.positions[corners[1].0],
+            self.positions[corners[2].0],
+        );
+        let (u, v) = (
+            [b[0] - a[0], b[1] - a[1], b[2] - a[2]],
+            [c[0] - a[0], c[1] - a[1], c[2] - a[2]],
+        );
+        let normal = [
+            u[1] * v[2] - u[2] * v[1],
+            u[2] * v[0] - u[0] * v[2],
+            u[0] * v[1] - u[1] * v[0],
+        ];
+
+        let length = (normal[0] * normal[0] + normal[1] * normal[1] + normal[2] * normal[2]).sqrt();
+        if length > 0.0 {
+            [normal[0] / length, normal[1] / length, normal[2] / length]
+        } else {
+            [0.0, 0.0, 1.0]
+        }
+    }
+
+    fn add_face(&mut self, corners: &[Corner]) {
+        // corners without a normal share the flat normal of the face
+        let face_normal = self.face_normal(corners);
+
+        let color = self
+            .current_mesh()
+            .material
+            .map(|material| self.materials[material].diffuse_color)
+            .unwrap_or([1.0, 1.0, 1.0]);
+
+        let indices = corners
+            .iter()
+            .map(|&corner| {
+                // without a normal the same position can get a different flat normal in
+                // another face, so only corners with one are shared
+                let shared = corner.2.is_some();
+                if let (true, Some(&index)) = (shared, self.corners.get(&corner)) {
+                    return index;
+                }
+
+                let (position, tex_coord, normal) = corner;
+                // obj textures have their origin at the bottom left
+                let tex_coord = tex_coord
+                    .map(|index| self.tex_coords[index])
+                    .map(|[u, v]| [u, 1.0 - v])
+                    .unwrap_or([0.0, 0.0]);
+
+                let vertex = app::VertexData {
+                    pos: self.positions[position],
+                    color,
+                    tex_coord,
+                    normal: normal
+                        .map(|index| self.normals[index])
+                        .unwrap_or(face_normal),
+                };
+
+                let mesh = self.current_mesh();
+                let index = mesh.vertices.len() as u32;
+                mesh.vertices.push(vertex);
+                if shared {
+                    self.corners.insert(corner, index);
+                }
+                index
+            })
+            .collect::<Vec<u32>>();
+
+        let mesh = self.current_mesh();
+        for i in 1..indices.len() - 1 {
+            mesh.indices
+                .extend_from_slice(&[indices[0], indices[i], indices[i + 1]]);
+        }
+    }
+}
+
+impl ObjModel {
+    // Loads the OBJ file along with the MTL files it references
+    pub fn load(path: &Path) -> Result<ObjModel> {
+        let source =
+            fs::read_to_string(path).context(format!("failed to read {}", path.display()))?;
+        let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+        ObjModel::parse(&source, |file| {
+            let mtl_path = base_dir.join(file);
+            let mtl_source = fs::read_to_string(&mtl_path)
+                .context(format!("failed to read {}", mtl_path.display()))?;
+            let mtl_dir = mtl_path.parent().unwrap_or(base_dir);
+
+            parse_mtl(&mtl_source, mtl_dir)
+        })
+        .context(format!("failed to load {}", path.display()))
+    }
+
+    // Parses OBJ source, load_mtl reads the materials of an mtllib statement
+    pub fn parse<F>(source: &str, mut load_mtl: F) -> Result<ObjModel>
+    where
+        F: FnMut(&str) -> Result<Vec<ObjMaterial>>,
+    {
+        let mut parser = Parser {
+            positions: vec![],
+            tex_coords: vec![],
+            normals: vec![],
+            materials: vec![],
+            meshes: vec![],
+            corners: HashMap::new(),
+        };
+
+        for (number, line) in source.lines().enumerate() {
+            let mut tokens = line.split_whitespace();
+            let keyword = match tokens.next() {
+                Some(keyword) if !keyword.starts_with('#') => keyword,
+                _ => continue,
+            };
+
+            let error =
+                |message: String| Error::msg(format!("obj line {}: {}", number + 1, message));
+
+            match keyword {
+                "v" => {
+                    let position = parse_floats(tokens, 3)
+                        .ok_or_else(|| error("invalid position".to_string()))?;
+                    parser
+                        .positions
+                        .push([position[0], position[1], position[2]]);
+                }
+                "vt" => {
+                    let tex_coord = parse_floats(tokens, 2)
+                        .ok_or_else(|| error("invalid texture coordinate".to_string()))?;
+                    parser.tex_coords.push([tex_coord[0], tex_coord[1]]);
+                }
+                "vn" => {
+                    let normal = parse_floats(tokens, 3)
+                        .ok_or_else(|| error("invalid normal".to_string()))?;
+                    parser.normals.push([normal[0], normal[1], normal[2]]);
+                }
+                "f" => {
+                    let corners = tokens
+                        .map(|token| {
+                            parser
+                                .parse_corner(token)
+                                .ok_or_else(|| error(format!("invalid face vertex {}", token)))
+                        })
+                        .collect::<Result<Vec<Corner>>>()?;
+
+                    if corners.len() < 3 {
+                        return Err(error("face with less than 3 vertices".to_string()));
+                    }
+
+                    parser.add_face(&corners);
+                }
+                "mtllib" => {
+                    for file in tokens {
+                        let materials = load_mtl(file)?;
+                        parser.materials.extend(materials);
+                    }
+                }
+                "usemtl" => {
+                    let name = tokens
+                        .next()
+                        .ok_or_else(|| error("usemtl without name".to_string()))?;
+                    parser
+                        .use_material(name)
+                        .map_err(|err| error(err.to_string()))?;
+                }
+                // groups, smoothing groups and lines are not needed for rendering
+                _ => {}
+            }
+        }
+
+        Ok(ObjModel {
+            meshes: parser
+                .meshes
+                .into_iter()
+                .filter(|mesh| !mesh.indices.is_empty())
+                .collect(),
+            materials: parser.materials,
+        })
+    }
+
+    // Every mesh in a single vertex and index buffer, eg. for the scene which is one mesh
+    pub fn merged(&self) -> (Vec<app::VertexData>, Vec<u32>) {
+        let mut vertices = vec![];
+        let mut indices = vec![];
+
+        for mesh in self.meshes.iter() {
+            let base = vertices.len() as u32;
+            vertices.extend_from_slice(&mesh.vertices);
+            indices.extend(mesh.indices.iter().map(|index| base + index));
+        }
+
+        (vertices, indices)
+    }
+
+    // Merges the equal vertices of every mesh and reorders them for the vertex cache, eg.
+    // the corners without a normal, which the parser does not share
+    pub fn optimize(&mut self) {
+        for mesh in self.meshes.iter_mut() {
+            let (vertices, indices) = mesh_processing::optimize(&mesh.vertices, &mesh.indices);
+            mesh.vertices = vertices;
+            mesh.indices = indices;
+        }
+    }
+
+    // Diffuse map of the first mesh which has one
+    pub fn diffuse_texture(&self) -> Option<&Path> {
+        self.meshes
+            .iter()
+            .filter_map(|mesh| mesh.material)
+            .filter_map(|material| self.materials[material].diffuse_texture.as_ref())
+            .map(PathBuf::as_path)
+            .next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUAD: &str = "
+        mtllib quad.mtl
+        v 0 0 0
+        v 1 0 0
+        v 1 1 0
+        v 0 1 0
+        vt 0 0
+        vt 1 0
+        vt 1 1
+        vt 0 1
+        vn 0 0 1
+        usemtl red
+        f 1/1/1 2/2/1 3/3/1 4/4/1
+        usemtl plain
+        f -4 -3 -2
+    ";
+
+    const MATERIALS: &str = "
+        # two materials
+        newmtl red
+        Kd 1.0 0.0 0.0
+        map_Kd -s 1 1 1 red.png
+        newmtl plain
+    ";
+
+    fn load_quad() -> ObjModel {
+        ObjModel::parse(QUAD, |file| {
+            assert_eq!(file, "quad.mtl");
+            parse_mtl(MATERIALS, Path::new("assets"))
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn materials_are_read_from_mtl() {
+        let materials = parse_mtl(MATERIALS, Path::new("assets")).unwrap();
+
+        assert_eq!(materials.len(), 2);
+        assert_eq!(materials[0].diffuse_color, [1.0, 0.0, 0.0]);
+        assert_eq!(
+            materials[0].diffuse_texture,
+            Some(Path::new("assets").join("red.png"))
+        );
+        assert_eq!(materials[1].diffuse_color, [1.0, 1.0, 1.0]);
+        assert_eq!(materials[1].diffuse_texture, None);
+    }
+
+    #[test]
+    fn faces_are_triangulated_per_material() {
+        let model = load_quad();
+
+        assert_eq!(model.meshes.len(), 2);
+
+        let quad = &model.meshes[0];
+        assert_eq!(quad.material, Some(0));
+        assert_eq!(quad.vertices.len(), 4);
+        assert_eq!(quad.indices, vec![0, 1, 2, 0, 2, 3]);
+        assert_eq!(quad.vertices[0].color, [1.0, 0.0, 0.0]);
+        // flipped for vulkan's top left origin
+        assert_eq!(quad.vertices[3].tex_coord, [0.0, 0.0]);
+
+        let triangle = &model.meshes[1];
+        assert_eq!(triangle.material, Some(1));
+        assert_eq!(triangle.indices, vec![0, 1, 2]);
+        // no normals in the file, so the face normal is used
+        assert_eq!(triangle.vertices[0].normal, [0.0, 0.0, 1.0]);
+        assert_eq!(model.diffuse_texture(), Some(Path::new("assets/red.png")));
+    }
+
+    #[test]
+    fn merged_meshes_offset_their_indices() {
+        let (vertices, indices) = load_quad().merged();
+
+        assert_eq!(vertices.len(), 7);
+        assert_eq!(indices, vec![0, 1, 2, 0, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn invalid_faces_are_reported_with_their_line() {
+        let result = ObjModel::parse("v 0 0 0\nf 1 2 3\n", |_| Ok(vec![]));
+
+        let message = result.unwrap_err().to_string();
+        assert!(message.contains("obj line 2"), "{}", message);
+    }
+}
@@ -0,0 +1,131 @@
+use cgmath::{Deg, Matrix4};
+
+// cgmath builds OpenGL projections: y up and a -1..1 depth range. Vulkan's clip space
+// has y pointing down and a 0..1 depth range, so y is flipped and z remapped.
+#[rustfmt::skip]
+pub fn opengl_to_vulkan() -> Matrix4<f32> {
+    Matrix4::new(
+        1.0, 0.0, 0.0, 0.0,
+        0.0, -1.0, 0.0, 0.0,
+        0.0, 0.0, 0.5, 0.0,
+        0.0, 0.0, 0.5, 1.0,
+    )
+}
+
+// How a camera maps view space to Vulkan clip space
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Projection {
+    Perspective { fovy: Deg<f32>, near: f32, far: f32 },
+    // size is the height of the view volume, its width follows the aspect ratio.
+    // Used for 2D, UI and CAD style views where distance does not change the size.
+    Orthographic { size: f32, near: f32, far: f32 },
+    // used as is, has to be in Vulkan clip space already
+    Custom(Matrix4<f32>),
+}
+
+impl Projection {
+    pub fn perspective(fovy: Deg<f32>, near: f32, far: f32) -> Projection {
+        Projection::Perspective { fovy, near, far }
+    }
+
+    pub fn orthographic(size: f32, near: f32, far: f32) -> Projection {
+        Projection::Orthographic { size, near, far }
+    }
+
+    // aspect is width / height of the viewport
+    pub fn matrix(&self, aspect: f32) -> Matrix4<f32> {
+        match *self {
+            Projection::Perspective { fovy, near, far } => {
+                opengl_to_vulkan() * cgmath::perspective(fovy, aspect, near, far)
+            }
+
+            Projection::Orthographic { size, near, far } => {
+                let half_height = size * 0.5;
+                let half_width = half_height * aspect;
+
+                opengl_to_vulkan()
+                    * cgmath::ortho(
+                        -half_width,
+                        half_width,
+                        -half_height,
+                        half_height,
+                        near,
+                        far,
+                    )
+            }
+
+            Projection::Custom(matrix) => matrix,
+        }
+    }
+}
+
+impl Default for Projection {
+    fn default() -> Projection {
+        Projection::perspective(Deg(45.0), 0.1, 10.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use cgmath::{Point3, Vector4};
+
+    fn clip(projection: &Projection, point: Point3<f32>) -> Point3<f32> {
+        Point3::from_homogeneous(
+            projection.matrix(2.0) * Vector4::new(point.x, point.y, point.z, 1.0),
+        )
+    }
+
+    fn assert_near(actual: Point3<f32>, expected: Point3<f32>) {
+        let distance = (actual.x - expected.x)
+            .abs()
+            .max((actual.y - expected.y).abs())
+            .max((actual.z - expected.z).abs());
+        assert!(distance < 1e-5, "{:?} != {:?}", actual, expected);
+    }
+
+    #[test]
+    fn perspective_depth_is_zero_to_one() {
+        let projection = Projection::perspective(Deg(90.0), 1.0, 10.0);
+
+        assert_near(
+            clip(&projection, Point3::new(0.0, 0.0, -1.0)),
+            Point3::new(0.0, 0.0, 0.0),
+        );
+        assert_near(
+            clip(&projection, Point3::new(0.0, 0.0, -10.0)),
+            Point3::new(0.0, 0.0, 1.0),
+        );
+
+        // up in view space is down in vulkan's clip space
+        let top = clip(&projection, Point3::new(0.0, 1.0, -1.0));
+        assert!((top.y + 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn orthographic_size_is_the_view_height() {
+        let projection = Projection::orthographic(4.0, 0.0, 10.0);
+
+        // a width of 8 at an aspect of 2
+        assert_near(
+            clip(&projection, Point3::new(4.0, 2.0, 0.0)),
+            Point3::new(1.0, -1.0, 0.0),
+        );
+        assert_near(
+            clip(&projection, Point3::new(-4.0, -2.0, -10.0)),
+            Point3::new(-1.0, 1.0, 1.0),
+        );
+        // distance does not change the size
+        assert_near(
+            clip(&projection, Point3::new(2.0, 1.0, -5.0)),
+            Point3::new(0.5, -0.5, 0.5),
+        );
+    }
+
+    #[test]
+    fn custom_matrices_are_used_as_is() {
+        let matrix = Matrix4::from_scale(3.0);
+        assert_eq!(Projection::Custom(matrix).matrix(1.5), matrix);
+    }
+}
@@ -0,0 +1,758 @@
+use cgmath::{Deg, Matrix4, One, Point3, Quaternion, Vector3};
+
+use crate::animation;
+use crate::error::{Error, Result};
+use crate::projection;
+use crate::vulkan::{lighting, material, mesh_pool, reflection_probe};
+
+// A handle to an entity of a world. The generation tells a despawned entity apart from
+// a later one reusing its index, so stale handles do not reach the new entity's components.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Entity {
+    index: u32,
+    generation: u32,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Transform {
+    pub position: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+    pub scale: Vector3<f32>,
+}
+
+impl Transform {
+    pub fn at(position: Vector3<f32>) -> Transform {
+        Transform {
+            position,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_rotation(mut self, rotation: Quaternion<f32>) -> Transform {
+        self.rotation = rotation;
+        self
+    }
+
+    pub fn with_scale(mut self, scale: Vector3<f32>) -> Transform {
+        self.scale = scale;
+        self
+    }
+
+    // Scales, then rotates, then translates
+    pub fn matrix(&self) -> Matrix4<f32> {
+        Matrix4::from_translation(self.position)
+            * Matrix4::from(self.rotation)
+            * Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+
+    // Direction the entity faces, -z rotated like the entity
+    pub fn forward(&self) -> Vector3<f32> {
+        self.rotation * Vector3::new(0.0, 0.0, -1.0)
+    }
+}
+
+impl From<animation::JointPose> for Transform {
+    fn from(pose: animation::JointPose) -> Transform {
+        Transform {
+            position: pose.translation,
+            rotation: pose.rotation,
+            scale: pose.scale,
+        }
+    }
+}
+
+impl From<Transform> for animation::JointPose {
+    fn from(transform: Transform) -> animation::JointPose {
+        animation::JointPose {
+            translation: transform.position,
+            rotation: transform.rotation,
+            scale: transform.scale,
+        }
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Transform {
+        Transform {
+            position: Vector3::new(0.0, 0.0, 0.0),
+            rotation: Quaternion::one(),
+            scale: Vector3::new(1.0, 1.0, 1.0),
+        }
+    }
+}
+
+// A range of the scene's index buffer drawn with a material
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct MeshRenderer {
+    pub material: material::MaterialId,
+    pub first_index: u32,
+    pub index_count: u32,
+    pub vertex_offset: i32,
+    pub instance_count: u32,
+}
+
+impl MeshRenderer {
+    pub fn new(first_index: u32, index_count: u32) -> MeshRenderer {
+        MeshRenderer {
+            material: 0,
+            first_index,
+            index_count,
+            vertex_offset: 0,
+            instance_count: 1,
+        }
+    }
+
+    // Draws the whole of a mesh loaded into the scene's mesh pool
+    pub fn from_mesh(mesh: &mesh_pool::MeshAllocation) -> MeshRenderer {
+        MeshRenderer::new(mesh.first_index, mesh.index_count)
+            .with_vertex_offset(mesh.first_vertex as i32)
+    }
+
+    pub fn with_material(mut self, material: material::MaterialId) -> MeshRenderer {
+        self.material = material;
+        self
+    }
+
+    pub fn with_vertex_offset(mut self, vertex_offset: i32) -> MeshRenderer {
+        self.vertex_offset = vertex_offset;
+        self
+    }
+
+    // Draws the range instance_count times, the shaders tell them apart by gl_InstanceIndex
+    pub fn with_instances(mut self, instance_count: u32) -> MeshRenderer {
+        self.instance_count = instance_count;
+        self
+    }
+}
+
+// A camera looking down the -z axis of its transform.
+// The scene is rendered from the first active camera.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Camera {
+    pub projection: projection::Projection,
+    pub active: bool,
+}
+
+impl Camera {
+    pub fn new(projection: projection::Projection) -> Camera {
+        Camera {
+            projection,
+            active: true,
+        }
+    }
+
+    pub fn perspective(fovy: Deg<f32>, near: f32, far: f32) -> Camera {
+        Camera::new(projection::Projection::perspective(fovy, near, far))
+    }
+
+    // size is the height of the visible area in world units
+    pub fn orthographic(size: f32, near: f32, far: f32) -> Camera {
+        Camera::new(projection::Projection::orthographic(size, near, far))
+    }
+
+    // Ignores the scale of the transform
+    pub fn view(&self, transform: &Transform) -> Matrix4<f32> {
+        Matrix4::from(transform.rotation.conjugate())
+            * Matrix4::from_translation(-transform.position)
+    }
+
+    pub fn projection(&self, aspect: f32) -> Matrix4<f32> {
+        self.projection.matrix(aspect)
+    }
+}
+
+impl Default for Camera {
+    fn default() -> Camera {
+        Camera::new(projection::Projection::default())
+    }
+}
+
+// Directional lights shine along the forward direction of their transform,
+// point lights are placed at its position
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Light {
+    Directional {
+        color: [f32; 3],
+        intensity: f32,
+    },
+    Point {
+        color: [f32; 3],
+        intensity: f32,
+        range: f32,
+    },
+}
+
+impl Light {
+    pub fn to_light(&self, transform: &Transform) -> lighting::Light {
+        match *self {
+            Light::Directional { color, intensity } => {
+                lighting::Light::directional(transform.forward().into(), color, intensity)
+            }
+            Light::Point {
+                color,
+                intensity,
+                range,
+            } => lighting::Light::point(transform.position.into(), color, intensity, range),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ProbeRefresh {
+    // when the probe is added and whenever a capture is requested, eg. after the static
+    // parts of the scene were loaded
+    OnDemand,
+    EveryFrame,
+}
+
+// Captures the scene around its entity into a cube map, which the draws within radius
+// are lit with instead of the environment. The renderer keeps a
+// reflection_probe::ReflectionProbe of the size per probe entity.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ReflectionProbe {
+    pub radius: f32,
+    // edge length of the cube's faces
+    pub size: u32,
+    pub refresh: ProbeRefresh,
+    captured: bool,
+}
+
+impl ReflectionProbe {
+    pub fn new(radius: f32, size: u32) -> ReflectionProbe {
+        ReflectionProbe {
+            radius,
+            size,
+            refresh: ProbeRefresh::OnDemand,
+            captured: false,
+        }
+    }
+
+    pub fn with_refresh(mut self, refresh: ProbeRefresh) -> ReflectionProbe {
+        self.refresh = refresh;
+        self
+    }
+
+    // Captured again the next time probes_to_capture is asked
+    pub fn request_capture(&mut self) {
+        self.captured = false;
+    }
+
+    pub fn needs_capture(&self) -> bool {
+        !self.captured || self.refresh == ProbeRefresh::EveryFrame
+    }
+}
+
+// Plays keyframe clips on the entity's transform, which animate advances every frame
+#[derive(Debug, Clone)]
+pub struct Animator {
+    pub animation: animation::NodeAnimation,
+    pub player: animation::AnimationPlayer,
+    skeleton: animation::Skeleton,
+}
+
+impl Animator {
+    pub fn new(animation: animation::NodeAnimation) -> Animator {
+        Animator {
+            skeleton: animation.skeleton(),
+            animation,
+            player: animation::AnimationPlayer::new(),
+        }
+    }
+
+    // Keeps playing the current clip when there is none by that name
+    pub fn play(&mut self, clip: &str) -> bool {
+        match self.animation.find_clip(clip) {
+            Some(clip) => {
+                self.player.play(clip);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn cross_fade(&mut self, clip: &str, duration: f32) -> bool {
+        match self.animation.find_clip(clip) {
+            Some(clip) => {
+                self.player.cross_fade(clip, duration);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn advance(&mut self, delta_time: f32) {
+        self.player.advance(&self.animation.clips, delta_time);
+    }
+
+    // The rest transform while no clip is playing
+    pub fn transform(&self) -> Transform {
+        self.player
+            .pose(&self.skeleton, &self.animation.clips)
+            .joints
+            .get(animation::NODE_JOINT)
+            .map(|&pose| Transform::from(pose))
+            .unwrap_or_else(|| Transform::from(self.animation.rest))
+    }
+}
+
+// Components of one type, indexed by entity index
+#[derive(Debug)]
+pub struct Components<T> {
+    slots: Vec<Option<(u32, T)>>,
+}
+
+impl<T> Default for Components<T> {
+    fn default() -> Components<T> {
+        Components { slots: vec![] }
+    }
+}
+
+impl<T> Components<T> {
+    fn insert(&mut self, entity: Entity, component: T) {
+        let index = entity.index as usize;
+        if self.slots.len() <= index {
+            self.slots.resize_with(index + 1, || None);
+        }
+        self.slots[index] = Some((entity.generation, component));
+    }
+
+    fn remove(&mut self, entity: Entity) -> Option<T> {
+        if self.get(entity).is_none() {
+            return None;
+        }
+
+        self.slots[entity.index as usize]
+            .take()
+            .map(|(_, component)| component)
+    }
+
+    fn get_mut_at(&mut self, index: usize) -> Option<(Entity, &mut T)> {
+        self.slots
+            .get_mut(index)
+            .and_then(|slot| slot.as_mut())
+            .map(|(generation, component)| {
+                (
+                    Entity {
+                        index: index as u32,
+                        generation: *generation,
+                    },
+                    component,
+                )
+            })
+    }
+
+    pub fn get(&self, entity: Entity) -> Option<&T> {
+        match self.slots.get(entity.index as usize) {
+            Some(Some((generation, component))) if *generation == entity.generation => {
+                Some(component)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn get_mut(&mut self, entity: Entity) -> Option<&mut T> {
+        match self.slots.get_mut(entity.index as usize) {
+            Some(Some((generation, component))) if *generation == entity.generation => {
+                Some(component)
+            }
+            _ => None,
+        }
+    }
+
+    // In entity index order
+    pub fn iter(&self) -> impl Iterator<Item = (Entity, &T)> {
+        self.slots.iter().enumerate().filter_map(|(index, slot)| {
+            slot.as_ref().map(|(generation, component)| {
+                (
+                    Entity {
+                        index: index as u32,
+                        generation: *generation,
+                    },
+                    component,
+                )
+            })
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.iter().filter(|slot| slot.is_some()).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+// Implemented by the types a world stores, one storage each
+pub trait Component: Sized {
+    fn storage(world: &World) -> &Components<Self>;
+    fn storage_mut(world: &mut World) -> &mut Components<Self>;
+}
+
+macro_rules! impl_component {
+    ($component:ty, $field:ident) => {
+        impl Component for $component {
+            fn storage(world: &World) -> &Components<Self> {
+                &world.$field
+            }
+
+            fn storage_mut(world: &mut World) -> &mut Components<Self> {
+                &mut world.$field
+            }
+        }
+    };
+}
+
+// Entities and their components. The render systems below turn it into what the
+// scene pass consumes, see Engine::world.
+#[derive(Debug, Default)]
+pub struct World {
+    generations: Vec<u32>,
+    alive: Vec<bool>,
+    free: Vec<u32>,
+
+    transforms: Components<Transform>,
+    mesh_renderers: Components<MeshRenderer>,
+    cameras: Components<Camera>,
+    lights: Components<Light>,
+    animators: Components<Animator>,
+    reflection_probes: Components<ReflectionProbe>,
+}
+
+impl_component!(Transform, transforms);
+impl_component!(MeshRenderer, mesh_renderers);
+impl_component!(Camera, cameras);
+impl_component!(Light, lights);
+impl_component!(Animator, animators);
+impl_component!(ReflectionProbe, reflection_probes);
+
+impl World {
+    pub fn new() -> World {
+        World::default()
+    }
+
+    pub fn spawn(&mut self) -> Entity {
+        match self.free.pop() {
+            Some(index) => {
+                self.alive[index as usize] = true;
+                Entity {
+                    index,
+                    generation: self.generations[index as usize],
+                }
+            }
+            None => {
+                self.generations.push(0);
+                self.alive.push(true);
+                Entity {
+                    index: self.generations.len() as u32 - 1,
+                    generation: 0,
+                }
+            }
+        }
+    }
+
+    pub fn is_alive(&self, entity: Entity) -> bool {
+        let index = entity.index as usize;
+        self.alive.get(index).cloned().unwrap_or(false)
+            && self.generations[index] == entity.generation
+    }
+
+    // Removes the entity with all its components, false if it was already gone
+    pub fn despawn(&mut self, entity: Entity) -> bool {
+        if !self.is_alive(entity) {
+            return false;
+        }
+
+        self.transforms.remove(entity);
+        self.mesh_renderers.remove(entity);
+        self.cameras.remove(entity);
+        self.lights.remove(entity);
+        self.animators.remove(entity);
+        self.reflection_probes.remove(entity);
+
+        let index = entity.index as usize;
+        self.alive[index] = false;
+        self.generations[index] += 1;
+        self.free.push(entity.index);
+        true
+    }
+
+    // Replaces the entity's component of the same type
+    pub fn insert<C: Component>(&mut self, entity: Entity, component: C) -> Result<()> {
+        if !self.is_alive(entity) {
+            return Err(Error::msg(format!("{:?} has been despawned", entity)));
+        }
+
+        C::storage_mut(self).insert(entity, component);
+        Ok(())
+    }
+
+    pub fn remove<C: Component>(&mut self, entity: Entity) -> Option<C> {
+        C::storage_mut(self).remove(entity)
+    }
+
+    pub fn get<C: Component>(&self, entity: Entity) -> Option<&C> {
+        C::storage(self).get(entity)
+    }
+
+    pub fn get_mut<C: Component>(&mut self, entity: Entity) -> Option<&mut C> {
+        C::storage_mut(self).get_mut(entity)
+    }
+
+    pub fn query<C: Component>(&self) -> impl Iterator<Item = (Entity, &C)> {
+        C::storage(self).iter()
+    }
+
+    // Entities without a transform are placed at the origin
+    pub fn transform(&self, entity: Entity) -> Transform {
+        self.get::<Transform>(entity).cloned().unwrap_or_default()
+    }
+}
+
+// One draw per mesh renderer, centered on its entity for sorting
+pub fn draw_list(world: &World) -> Vec<material::Draw> {
+    world
+        .query::<MeshRenderer>()
+        .map(|(entity, renderer)| material::Draw {
+            material: renderer.material,
+            index_count: renderer.index_count,
+            first_index: renderer.first_index,
+            vertex_offset: renderer.vertex_offset,
+            instance_count: renderer.instance_count,
+            center: Point3::new(0.0, 0.0, 0.0) + world.transform(entity).position,
+        })
+        .collect()
+}
+
+// Model matrix of every mesh renderer in draw_list order, eg. set on an
+// object_uniforms::ObjectUniforms each frame
+pub fn model_matrices(world: &World) -> Vec<Matrix4<f32>> {
+    world
+        .query::<MeshRenderer>()
+        .map(|(entity, _)| world.transform(entity).matrix())
+        .collect()
+}
+
+// Advances every animator and moves its entity to the animated transform
+pub fn animate(world: &mut World, delta_time: f32) {
+    for index in 0..world.animators.slots.len() {
+        let (entity, transform) = match world.animators.get_mut_at(index) {
+            Some((entity, animator)) => {
+                animator.advance(delta_time);
+                (entity, animator.transform())
+            }
+            None => continue,
+        };
+
+        world.transforms.insert(entity, transform);
+    }
+}
+
+pub fn active_camera(world: &World) -> Option<(Entity, &Camera)> {
+    world.query::<Camera>().find(|(_, camera)| camera.active)
+}
+
+// View and projection of the active camera
+pub fn camera_matrices(world: &World, aspect: f32) -> Option<(Matrix4<f32>, Matrix4<f32>)> {
+    active_camera(world).map(|(entity, camera)| {
+        (
+            camera.view(&world.transform(entity)),
+            camera.projection(aspect),
+        )
+    })
+}
+
+pub fn lights(world: &World) -> Vec<lighting::Light> {
+    world
+        .query::<Light>()
+        .map(|(entity, light)| light.to_light(&world.transform(entity)))
+        .collect()
+}
+
+// Probes to render this frame and where to render them from, marked as captured. The
+// caller renders each with reflection_probe::ReflectionProbe::capture.
+pub fn probes_to_capture(world: &mut World) -> Vec<(Entity, Point3<f32>)> {
+    let mut entities = vec![];
+    for index in 0..world.reflection_probes.slots.len() {
+        if let Some((entity, probe)) = world.reflection_probes.get_mut_at(index) {
+            if probe.needs_capture() {
+                probe.captured = true;
+                entities.push(entity);
+            }
+        }
+    }
+
+    entities
+        .into_iter()
+        .map(|entity| {
+            (
+                entity,
+                Point3::new(0.0, 0.0, 0.0) + world.transform(entity).position,
+            )
+        })
+        .collect()
+}
+
+// Where each probe is sampled, eg. paired with the probes' sets for
+// material::MaterialLibrary::set_probes
+pub fn probe_regions(world: &World) -> Vec<(Entity, reflection_probe::ProbeRegion)> {
+    world
+        .query::<ReflectionProbe>()
+        .map(|(entity, probe)| {
+            (
+                entity,
+                reflection_probe::ProbeRegion {
+                    center: Point3::new(0.0, 0.0, 0.0) + world.transform(entity).position,
+                    radius: probe.radius,
+                },
+            )
+        })
+        .collect()
+}
+
+// The probe the point is lit by, the closest one whose radius reaches it
+pub fn nearest_probe(world: &World, point: Point3<f32>) -> Option<Entity> {
+    let regions = probe_regions(world);
+    let bounds = regions
+        .iter()
+        .map(|(_, region)| *region)
+        .collect::<Vec<_>>();
+
+    reflection_probe::nearest_region(&bounds, point).map(|index| regions[index].0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn despawned_entities_lose_their_components() {
+        let mut world = World::new();
+        let first = world.spawn();
+        world
+            .insert(first, Transform::at(Vector3::new(1.0, 0.0, 0.0)))
+            .unwrap();
+        world.insert(first, MeshRenderer::new(0, 6)).unwrap();
+
+        assert!(world.despawn(first));
+        assert!(!world.despawn(first));
+
+        // the index is reused by a new generation
+        let second = world.spawn();
+        assert_ne!(first, second);
+        assert!(world.get::<Transform>(second).is_none());
+        assert!(world.insert(first, Camera::default()).is_err());
+        assert_eq!(world.query::<MeshRenderer>().count(), 0);
+    }
+
+    #[test]
+    fn draws_are_built_from_mesh_renderers() {
+        let mut world = World::new();
+        let quad = world.spawn();
+        world
+            .insert(quad, Transform::at(Vector3::new(0.0, 0.0, -2.0)))
+            .unwrap();
+        world
+            .insert(quad, MeshRenderer::new(6, 6).with_material(1))
+            .unwrap();
+        let camera = world.spawn();
+        world.insert(camera, Camera::default()).unwrap();
+
+        let draws = draw_list(&world);
+        assert_eq!(draws.len(), 1);
+        assert_eq!(draws[0].first_index, 6);
+        assert_eq!(draws[0].material, 1);
+        assert_eq!(draws[0].center, Point3::new(0.0, 0.0, -2.0));
+
+        let (view, _) = camera_matrices(&world, 1.0).unwrap();
+        assert_eq!(view, Matrix4::one());
+    }
+
+    #[test]
+    fn probes_are_captured_on_demand() {
+        let mut world = World::new();
+        let hall = world.spawn();
+        world
+            .insert(hall, Transform::at(Vector3::new(0.0, 1.0, 0.0)))
+            .unwrap();
+        world.insert(hall, ReflectionProbe::new(8.0, 128)).unwrap();
+        let mirror = world.spawn();
+        world
+            .insert(mirror, Transform::at(Vector3::new(4.0, 1.0, 0.0)))
+            .unwrap();
+        world
+            .insert(
+                mirror,
+                ReflectionProbe::new(2.0, 64).with_refresh(ProbeRefresh::EveryFrame),
+            )
+            .unwrap();
+
+        let first = probes_to_capture(&mut world);
+        assert_eq!(
+            first,
+            vec![
+                (hall, Point3::new(0.0, 1.0, 0.0)),
+                (mirror, Point3::new(4.0, 1.0, 0.0))
+            ]
+        );
+        // only the probe refreshed every frame is captured again until one is requested
+        assert_eq!(probes_to_capture(&mut world).len(), 1);
+        world
+            .get_mut::<ReflectionProbe>(hall)
+            .unwrap()
+            .request_capture();
+        assert_eq!(probes_to_capture(&mut world).len(), 2);
+
+        // the closer probe wins where both reach
+        assert_eq!(
+            nearest_probe(&world, Point3::new(3.5, 1.0, 0.0)),
+            Some(mirror)
+        );
+        assert_eq!(
+            nearest_probe(&world, Point3::new(-3.0, 1.0, 0.0)),
+            Some(hall)
+        );
+        assert_eq!(nearest_probe(&world, Point3::new(20.0, 0.0, 0.0)), None);
+    }
+
+    #[test]
+    fn animators_move_their_entity() {
+        use cgmath::Rotation3;
+
+        let rest = Transform::at(Vector3::new(0.0, 0.0, -2.0));
+        let spin = animation::AnimationClip::new(
+            "spin",
+            vec![animation::Channel::new(
+                animation::NODE_JOINT,
+                vec![0.0, 2.0],
+                animation::Keyframes::Rotation(vec![
+                    Quaternion::one(),
+                    Quaternion::from_angle_y(Deg(90.0)),
+                ]),
+                animation::Interpolation::Linear,
+            )
+            .unwrap()],
+        );
+
+        let mut world = World::new();
+        let door = world.spawn();
+        world.insert(door, MeshRenderer::new(0, 6)).unwrap();
+        let mut animator =
+            Animator::new(animation::NodeAnimation::new("door", rest.into()).with_clip(spin));
+        assert!(!animator.play("open"));
+        assert!(animator.play("spin"));
+        world.insert(door, animator).unwrap();
+
+        animate(&mut world, 1.0);
+        let transform = world.transform(door);
+        // rotated, the position stays at the rest transform's
+        assert_eq!(transform.position, rest.position);
+        assert!((transform.rotation.s - Quaternion::from_angle_y(Deg(45.0)).s).abs() < 1e-5);
+        assert_eq!(model_matrices(&world), vec![transform.matrix()]);
+
+        // despawning drops the animator with the entity
+        world.despawn(door);
+        animate(&mut world, 1.0);
+        assert_eq!(world.query::<Transform>().count(), 0);
+    }
+}
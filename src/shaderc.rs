@@ -2,19 +2,128 @@ use shaderc;
 
 use std::fs::File;
 use std::io::prelude::*;
+use std::path::{Path, PathBuf};
 
-use anyhow::{Context, Result};
+use crate::error::{Context, Error, Result};
+use crate::vulkan::reflect;
 
+#[derive(Debug, Clone, PartialEq)]
 pub struct ShaderSource {
     pub vertex_shader_file: String,
     pub fragment_shader_file: String,
 }
 
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Optimization {
+    None,
+    Size,
+    Performance,
+}
+
+impl Default for Optimization {
+    fn default() -> Optimization {
+        Optimization::None
+    }
+}
+
+// How shaders are preprocessed and compiled. `#include "file"` is looked up next to the
+// including file and then in the include directories, `#include <file>` only in the latter.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CompileSettings {
+    pub include_dirs: Vec<PathBuf>,
+    pub defines: Vec<(String, Option<String>)>,
+    pub optimization: Optimization,
+}
+
+impl CompileSettings {
+    pub fn with_include_dir<P: Into<PathBuf>>(mut self, dir: P) -> CompileSettings {
+        self.include_dirs.push(dir.into());
+        self
+    }
+
+    pub fn with_define(mut self, name: &str, value: Option<&str>) -> CompileSettings {
+        self.defines
+            .push((name.to_string(), value.map(|value| value.to_string())));
+        self
+    }
+
+    pub fn with_optimization(mut self, optimization: Optimization) -> CompileSettings {
+        self.optimization = optimization;
+        self
+    }
+
+    // Where an include is read from, None when no candidate exists
+    pub fn resolve_include(
+        &self,
+        requested: &str,
+        include_type: shaderc::IncludeType,
+        requesting_source: &str,
+    ) -> Option<PathBuf> {
+        let relative = match include_type {
+            shaderc::IncludeType::Relative => Path::new(requesting_source)
+                .parent()
+                .map(|dir| dir.join(requested)),
+            shaderc::IncludeType::Standard => None,
+        };
+
+        relative
+            .into_iter()
+            .chain(self.include_dirs.iter().map(|dir| dir.join(requested)))
+            .find(|candidate| candidate.is_file())
+    }
+
+    fn options(&self) -> Result<shaderc::CompileOptions> {
+        let mut options =
+            shaderc::CompileOptions::new().context("cannot init shaderc compiler options")?;
+
+        for (name, value) in self.defines.iter() {
+            options.add_macro_definition(name, value.as_ref().map(|value| value.as_str()));
+        }
+
+        options.set_optimization_level(match self.optimization {
+            Optimization::None => shaderc::OptimizationLevel::Zero,
+            Optimization::Size => shaderc::OptimizationLevel::Size,
+            Optimization::Performance => shaderc::OptimizationLevel::Performance,
+        });
+
+        let settings = self.clone();
+        options.set_include_callback(move |requested, include_type, requesting_source, _depth| {
+            let path = settings
+                .resolve_include(requested, include_type, requesting_source)
+                .ok_or_else(|| {
+                    format!(
+                        "cannot find {} included from {}",
+                        requested, requesting_source
+                    )
+                })?;
+
+            let resolved_name = path.to_string_lossy().into_owned();
+            ShaderSource::read_file(&resolved_name)
+                .map(|content| shaderc::ResolvedInclude {
+                    resolved_name,
+                    content,
+                })
+                .map_err(|err| err.to_string())
+        });
+
+        Ok(options)
+    }
+}
+
+#[derive(Clone)]
 pub struct CompiledShader {
     pub vertex: Vec<u8>,
     pub fragment: Vec<u8>,
 }
 
+impl CompiledShader {
+    // Descriptor bindings, push constants and vertex inputs used by both stages
+    pub fn reflect(&self) -> Result<reflect::ShaderReflection> {
+        reflect::ShaderReflection::from_spirv(&self.vertex)?
+            .merge(reflect::ShaderReflection::from_spirv(&self.fragment)?)
+    }
+}
+
 impl ShaderSource {
     fn read_file(filename: &String) -> Result<String> {
         let mut file = File::open(filename).context(format!("cannot open file {}", filename))?;
@@ -25,18 +134,39 @@ impl ShaderSource {
             .context(format!("error reading file to string: {}", filename))
     }
 
+    fn compile_error(file: &String, err: shaderc::Error) -> Error {
+        Error::ShaderCompile {
+            file: file.clone(),
+            message: err.to_string(),
+        }
+    }
+
     pub fn compile(&self) -> Result<CompiledShader> {
+        self.compile_with_settings(&CompileSettings::default())
+    }
+
+    // Compiles a permutation of the shaders, every define is visible to both stages
+    pub fn compile_with_defines(&self, defines: &[(&str, Option<&str>)]) -> Result<CompiledShader> {
+        let settings = defines
+            .iter()
+            .fold(CompileSettings::default(), |settings, (name, value)| {
+                settings.with_define(name, *value)
+            });
+
+        self.compile_with_settings(&settings)
+    }
+
+    pub fn compile_with_settings(&self, settings: &CompileSettings) -> Result<CompiledShader> {
         let vertex_shader = ShaderSource::read_file(&self.vertex_shader_file)?;
         let fragment_shader = ShaderSource::read_file(&self.fragment_shader_file)?;
-        println!(
+        tracing::debug!(
             "shaders: vertex: {} fragment: {}",
-            vertex_shader, fragment_shader
+            vertex_shader,
+            fragment_shader
         );
 
         let mut compiler = shaderc::Compiler::new().context("cannot init shaderc compiler")?;
-
-        let options =
-            shaderc::CompileOptions::new().context("cannot init shaderc compiler options")?;
+        let options = settings.options()?;
 
         let vertex_shader_result = compiler
             .compile_into_spirv(
@@ -46,7 +176,7 @@ impl ShaderSource {
                 "main",
                 Some(&options),
             )
-            .context("failed to compile vertex shader")?;
+            .map_err(|err| ShaderSource::compile_error(&self.vertex_shader_file, err))?;
 
         let fragment_shader_result = compiler
             .compile_into_spirv(
@@ -56,11 +186,39 @@ impl ShaderSource {
                 "main",
                 Some(&options),
             )
-            .context("failed to compile fragment shader")?;
+            .map_err(|err| ShaderSource::compile_error(&self.fragment_shader_file, err))?;
 
         Ok(CompiledShader {
             vertex: vertex_shader_result.as_binary_u8().to_vec(),
             fragment: fragment_shader_result.as_binary_u8().to_vec(),
         })
     }
+
+    pub fn compile_compute(compute_shader_file: &String) -> Result<Vec<u8>> {
+        ShaderSource::compile_compute_with_settings(
+            compute_shader_file,
+            &CompileSettings::default(),
+        )
+    }
+
+    pub fn compile_compute_with_settings(
+        compute_shader_file: &String,
+        settings: &CompileSettings,
+    ) -> Result<Vec<u8>> {
+        let compute_shader = ShaderSource::read_file(compute_shader_file)?;
+
+        let mut compiler = shaderc::Compiler::new().context("cannot init shaderc compiler")?;
+        let options = settings.options()?;
+
+        compiler
+            .compile_into_spirv(
+                &compute_shader,
+                shaderc::ShaderKind::Compute,
+                compute_shader_file,
+                "main",
+                Some(&options),
+            )
+            .map(|result| result.as_binary_u8().to_vec())
+            .map_err(|err| ShaderSource::compile_error(compute_shader_file, err))
+    }
 }
@@ -0,0 +1,341 @@
+// Terrain built from a heightmap image. The grid is split into square chunks, each with
+// its own mesh and bounds so the chunks outside the view can be culled, and is textured
+// by a splat map blending layer textures, see splat_material.
+
+use cgmath::{InnerSpace, Matrix4, Vector3};
+
+use crate::app;
+use crate::error::{Error, Result};
+use crate::shaderc;
+use crate::vulkan::{bounds, material};
+
+use std::path::Path;
+
+pub const TERRAIN_VERTEX_SHADER: &'static str = "shaders/shader.vert";
+pub const TERRAIN_FRAGMENT_SHADER: &'static str = "shaders/terrain.frag";
+
+// The red, green and blue channels of the splat map weigh the three layers, in the order
+// shaders/terrain.frag samples them. Fits the bindless material limit.
+pub const SPLAT_SLOTS: [material::TextureSlot; 4] = [
+    material::TextureSlot::SplatMap,
+    material::TextureSlot::TerrainLayer(0),
+    material::TextureSlot::TerrainLayer(1),
+    material::TextureSlot::TerrainLayer(2),
+];
+
+// The scene vertex shader with shaders/terrain.frag
+pub fn splat_shaders() -> shaderc::ShaderSource {
+    shaderc::ShaderSource {
+        vertex_shader_file: TERRAIN_VERTEX_SHADER.to_string(),
+        fragment_shader_file: TERRAIN_FRAGMENT_SHADER.to_string(),
+    }
+}
+
+// The splat map spans the whole terrain while the layers repeat across it
+pub fn splat_material() -> material::MaterialDescription {
+    material::MaterialDescription::new(splat_shaders(), &SPLAT_SLOTS)
+}
+
+// Heights between 0 and 1, row by row
+#[derive(Debug, Clone, PartialEq)]
+pub struct Heightmap {
+    pub width: u32,
+    pub depth: u32,
+    pub heights: Vec<f32>,
+}
+
+impl Heightmap {
+    pub fn new(width: u32, depth: u32, heights: Vec<f32>) -> Result<Heightmap> {
+        if width < 2 || depth < 2 {
+            return Err(Error::msg(format!(
+                "heightmaps need at least 2x2 samples, got {}x{}",
+                width, depth
+            )));
+        }
+
+        if heights.len() != (width * depth) as usize {
+            return Err(Error::msg(format!(
+                "a {}x{} heightmap needs {} heights, got {}",
+                width,
+                depth,
+                width * depth,
+                heights.len()
+            )));
+        }
+
+        Ok(Heightmap {
+            width,
+            depth,
+            heights,
+        })
+    }
+
+    // Grayscale images keep 16 bit precision, others are converted to 8 bit luma
+    pub fn load(path: &Path) -> Result<Heightmap> {
+        let (width, depth, heights) = match image::open(path)? {
+            image::DynamicImage::ImageLuma16(image) => (
+                image.width(),
+                image.height(),
+                image
+                    .pixels()
+                    .map(|pixel| pixel.0[0] as f32 / std::u16::MAX as f32)
+                    .collect(),
+            ),
+            other => {
+                let image = other.to_luma();
+                (
+                    image.width(),
+                    image.height(),
+                    image
+                        .pixels()
+                        .map(|pixel| pixel.0[0] as f32 / std::u8::MAX as f32)
+                        .collect(),
+                )
+            }
+        };
+
+        Heightmap::new(width, depth, heights)
+    }
+
+    // Samples outside the heightmap repeat its edge
+    pub fn height(&self, x: i64, z: i64) -> f32 {
+        let x = x.max(0).min(self.width as i64 - 1) as usize;
+        let z = z.max(0).min(self.depth as i64 - 1) as usize;
+        self.heights[z * self.width as usize + x]
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TerrainConfig {
+    // quads along each side of a chunk, the chunks at the far edges may be smaller
+    pub chunk_size: u32,
+    // distance between neighbouring samples
+    pub spacing: f32,
+    // height of a sample of 1
+    pub height_scale: f32,
+}
+
+impl Default for TerrainConfig {
+    fn default() -> TerrainConfig {
+        TerrainConfig {
+            chunk_size: 64,
+            spacing: 1.0,
+            height_scale: 16.0,
+        }
+    }
+}
+
+impl TerrainConfig {
+    pub fn with_chunk_size(mut self, chunk_size: u32) -> TerrainConfig {
+        self.chunk_size = chunk_size;
+        self
+    }
+
+    pub fn with_spacing(mut self, spacing: f32) -> TerrainConfig {
+        self.spacing = spacing;
+        self
+    }
+
+    pub fn with_height_scale(mut self, height_scale: f32) -> TerrainConfig {
+        self.height_scale = height_scale;
+        self
+    }
+}
+
+pub struct TerrainChunk {
+    // the first sample of the chunk in the heightmap
+    pub x: u32,
+    pub z: u32,
+    pub vertices: Vec<app::VertexData>,
+    pub indices: Vec<u32>,
+    pub bounds: bounds::MeshBounds,
+}
+
+// Chunks of the heightmap's grid centered on the origin, y up. Texture coordinates span
+// 0 to 1 over the whole terrain so they address the splat map.
+pub struct Terrain {
+    pub config: TerrainConfig,
+    pub heightmap: Heightmap,
+    pub chunks: Vec<TerrainChunk>,
+}
+
+impl Terrain {
+    pub fn new(heightmap: Heightmap, config: TerrainConfig) -> Result<Terrain> {
+        if config.chunk_size == 0 {
+            return Err(Error::msg("terrain chunks need at least one quad"));
+        }
+
+        let mut chunks = vec![];
+        for z in (0..heightmap.depth - 1).step_by(config.chunk_size as usize) {
+            for x in (0..heightmap.width - 1).step_by(config.chunk_size as usize) {
+                chunks.push(Terrain::build_chunk(&heightmap, &config, x, z)?);
+            }
+        }
+
+        Ok(Terrain {
+            config,
+            heightmap,
+            chunks,
+        })
+    }
+
+    pub fn load(path: &Path, config: TerrainConfig) -> Result<Terrain> {
+        Terrain::new(Heightmap::load(path)?, config)
+    }
+
+    fn position(heightmap: &Heightmap, config: &TerrainConfig, x: u32, z: u32) -> [f32; 3] {
+        let half_width = (heightmap.width - 1) as f32 * 0.5;
+        let half_depth = (heightmap.depth - 1) as f32 * 0.5;
+
+        [
+            (x as f32 - half_width) * config.spacing,
+            heightmap.height(x as i64, z as i64) * config.height_scale,
+            (z as f32 - half_depth) * config.spacing,
+        ]
+    }
+
+    // Central differences of the whole heightmap, so the normals match across chunk edges
+    fn normal(heightmap: &Heightmap, config: &TerrainConfig, x: u32, z: u32) -> [f32; 3] {
+        let (x, z) = (x as i64, z as i64);
+        let (left, right) = ((x - 1).max(0), (x + 1).min(heightmap.width as i64 - 1));
+        let (back, front) = ((z - 1).max(0), (z + 1).min(heightmap.depth as i64 - 1));
+
+        let slope_x = (heightmap.height(right, z) - heightmap.height(left, z))
+            * config.height_scale
+            / ((right - left) as f32 * config.spacing);
+        let slope_z = (heightmap.height(x, front) - heightmap.height(x, back))
+            * config.height_scale
+            / ((front - back) as f32 * config.spacing);
+
+        Vector3::new(-slope_x, 1.0, -slope_z).normalize().into()
+    }
+
+    fn build_chunk(
+        heightmap: &Heightmap,
+        config: &TerrainConfig,
+        first_x: u32,
+        first_z: u32,
+    ) -> Result<TerrainChunk> {
+        let last_x = (first_x + config.chunk_size).min(heightmap.width - 1);
+        let last_z = (first_z + config.chunk_size).min(heightmap.depth - 1);
+        let columns = last_x - first_x + 1;
+
+        let mut vertices = Vec::with_capacity((columns * (last_z - first_z + 1)) as usize);
+        for z in first_z..=last_z {
+            for x in first_x..=last_x {
+                vertices.push(app::VertexData {
+                    pos: Terrain::position(heightmap, config, x, z),
+                    color: [1.0, 1.0, 1.0],
+                    tex_coord: [
+                        x as f32 / (heightmap.width - 1) as f32,
+                        z as f32 / (heightmap.depth - 1) as f32,
+                    ],
+                    normal: Terrain::normal(heightmap, config, x, z),
+                });
+            }
+        }
+
+        // two counter clockwise triangles per quad seen from above
+        let at = |x: u32, z: u32| (z - first_z) * columns + x - first_x;
+        let mut indices = Vec::with_capacity(((columns - 1) * (last_z - first_z) * 6) as usize);
+        for z in first_z..last_z {
+            for x in first_x..last_x {
+                indices.extend_from_slice(&[at(x, z), at(x, z + 1), at(x + 1, z)]);
+                indices.extend_from_slice(&[at(x + 1, z), at(x, z + 1), at(x + 1, z + 1)]);
+            }
+        }
+
+        let bounds = bounds::MeshBounds::from_positions(vertices.iter().map(|vertex| vertex.pos))
+            .ok_or_else(|| Error::msg("terrain chunk without vertices"))?;
+
+        Ok(TerrainChunk {
+            x: first_x,
+            z: first_z,
+            vertices,
+            indices,
+            bounds,
+        })
+    }
+
+    // Indices of the chunks in view of the camera, with the terrain placed by model
+    pub fn visible_chunks(&self, view_proj: Matrix4<f32>, model: Matrix4<f32>) -> Vec<usize> {
+        let frustum = bounds::Frustum::from_matrix(view_proj);
+
+        self.chunks
+            .iter()
+            .enumerate()
+            .filter(|(_, chunk)| frustum.is_visible(&chunk.bounds, model))
+            .map(|(index, _)| index)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use cgmath::{Deg, Point3, SquareMatrix};
+
+    // A ramp rising by one sample per column
+    fn ramp(width: u32, depth: u32) -> Heightmap {
+        let heights = (0..depth)
+            .flat_map(|_| (0..width).map(|x| x as f32))
+            .collect();
+        Heightmap::new(width, depth, heights).unwrap()
+    }
+
+    #[test]
+    fn normals_follow_the_slope() {
+        let flat = Heightmap::new(3, 3, vec![0.5; 9]).unwrap();
+        let terrain = Terrain::new(flat, TerrainConfig::default()).unwrap();
+        for vertex in terrain.chunks[0].vertices.iter() {
+            assert_eq!(vertex.normal, [0.0, 1.0, 0.0]);
+        }
+
+        // rises by one per unit along x, so the normals lean back at 45 degrees
+        let config = TerrainConfig::default().with_height_scale(1.0);
+        let terrain = Terrain::new(ramp(4, 4), config).unwrap();
+        let expected = Vector3::new(-1.0, 1.0, 0.0).normalize();
+        for vertex in terrain.chunks[0].vertices.iter() {
+            let normal = Vector3::from(vertex.normal);
+            assert!((normal - expected).magnitude() < 1e-5, "{:?}", normal);
+        }
+
+        assert!(Heightmap::new(1, 4, vec![0.0; 4]).is_err());
+        assert!(Heightmap::new(2, 2, vec![0.0; 3]).is_err());
+    }
+
+    #[test]
+    fn chunks_cover_the_grid_and_are_culled_by_their_bounds() {
+        let config = TerrainConfig::default()
+            .with_chunk_size(4)
+            .with_height_scale(1.0);
+        // 9 quads per side, chunks of 4, 4 and 1
+        let terrain = Terrain::new(ramp(10, 10), config).unwrap();
+        assert_eq!(terrain.chunks.len(), 9);
+
+        let quads: usize = terrain
+            .chunks
+            .iter()
+            .map(|chunk| chunk.indices.len() / 6)
+            .sum();
+        assert_eq!(quads, 81);
+
+        let corner = &terrain.chunks[8];
+        assert_eq!((corner.x, corner.z), (8, 8));
+        assert_eq!(corner.vertices.len(), 4);
+        assert_eq!(corner.bounds.aabb.min, Point3::new(3.5, 8.0, 3.5));
+        assert_eq!(corner.bounds.aabb.max, Point3::new(4.5, 9.0, 4.5));
+
+        // looking down the -x edge only sees the first column of chunks
+        let proj = cgmath::perspective(Deg(10.0), 1.0, 0.1, 100.0);
+        let view = Matrix4::look_at(
+            Point3::new(-4.5, 40.0, 0.0),
+            Point3::new(-4.5, 0.0, 0.0),
+            Vector3::new(0.0, 0.0, -1.0),
+        );
+        let visible = terrain.visible_chunks(proj * view, Matrix4::identity());
+        assert!(!visible.is_empty());
+        assert!(visible.iter().all(|&index| terrain.chunks[index].x == 0));
+    }
+}
@@ -0,0 +1,220 @@
+use ash::version::InstanceV1_0;
+use ash::vk;
+
+use crate::error::{Error, Result};
+
+use serde::Serialize;
+
+use crate::foreign;
+
+use super::device;
+use super::requirements;
+use super::surface;
+
+// Environment variable overriding the gpu choice, either an adapter index or a name substring
+pub const DEVICE_OVERRIDE_ENV: &'static str = "KELSIER_DEVICE";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeviceSelection {
+    // pick the suitable adapter with the highest score
+    Auto,
+    Index(usize),
+    // case insensitive substring of the adapter name
+    Name(String),
+    // the adapter another api has to render with, eg. the one an OpenXR runtime drives
+    Handle(vk::PhysicalDevice),
+}
+
+impl DeviceSelection {
+    pub fn parse(value: &str) -> DeviceSelection {
+        let value = value.trim();
+
+        if value.is_empty() {
+            DeviceSelection::Auto
+        } else {
+            value
+                .parse::<usize>()
+                .map(DeviceSelection::Index)
+                .unwrap_or_else(|_| DeviceSelection::Name(value.to_string()))
+        }
+    }
+
+    pub fn from_env() -> DeviceSelection {
+        std::env::var(DEVICE_OVERRIDE_ENV)
+            .map(|value| DeviceSelection::parse(&value))
+            .unwrap_or(DeviceSelection::Auto)
+    }
+}
+
+impl Default for DeviceSelection {
+    fn default() -> DeviceSelection {
+        DeviceSelection::Auto
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct QueueFamilyInfo {
+    pub index: u32,
+    pub queue_count: u32,
+    pub graphics: bool,
+    pub compute: bool,
+    pub transfer: bool,
+    // can present to the surface the adapters were enumerated with
+    pub present: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AdapterInfo {
+    // position in the list returned by vkEnumeratePhysicalDevices
+    pub index: usize,
+    pub name: String,
+    pub device_type: String,
+    pub vendor_id: u32,
+    pub device_id: u32,
+    pub device_local_memory: vk::DeviceSize,
+    // system memory the gpu can use, all of an integrated gpu's memory may be shared
+    pub shared_memory: vk::DeviceSize,
+    pub queue_families: Vec<QueueFamilyInfo>,
+    // whether the adapter can present to the surface and meets the device requirements
+    pub suitable: bool,
+    pub score: u64,
+
+    #[serde(skip)]
+    pub physical_device: vk::PhysicalDevice,
+}
+
+impl AdapterInfo {
+    // Selects this adapter when set as EngineConfig::device_selection. Adapters are
+    // enumerated in the same order by every instance, unlike their handles.
+    pub fn selection(&self) -> DeviceSelection {
+        DeviceSelection::Index(self.index)
+    }
+}
+
+// Sizes of the device local heaps and of the other heaps
+fn memory_sizes(instance: &ash::Instance, physical_device: vk::PhysicalDevice) -> (u64, u64) {
+    let memory_properties =
+        unsafe { instance.get_physical_device_memory_properties(physical_device) };
+
+    memory_properties.memory_heaps[..memory_properties.memory_heap_count as usize]
+        .iter()
+        .fold((0, 0), |(local, shared), heap| {
+            if heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL) {
+                (local + heap.size, shared)
+            } else {
+                (local, shared + heap.size)
+            }
+        })
+}
+
+fn queue_families(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    surface_info: &surface::SurfaceInfo,
+) -> Vec<QueueFamilyInfo> {
+    let families = unsafe { instance.get_physical_device_queue_family_properties(physical_device) };
+
+    families
+        .iter()
+        .enumerate()
+        .map(|(index, family)| QueueFamilyInfo {
+            index: index as u32,
+            queue_count: family.queue_count,
+            graphics: family.queue_flags.contains(vk::QueueFlags::GRAPHICS),
+            compute: family.queue_flags.contains(vk::QueueFlags::COMPUTE),
+            // graphics and compute queues can always transfer, without reporting it
+            transfer: family.queue_flags.intersects(
+                vk::QueueFlags::TRANSFER | vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE,
+            ),
+            present: unsafe {
+                surface_info.loader.get_physical_device_surface_support(
+                    physical_device,
+                    index as u32,
+                    surface_info.surface,
+                )
+            },
+        })
+        .collect()
+}
+
+// Discrete gpus always win over integrated ones, ties are broken by the amount of device local memory
+pub fn score(device_type: vk::PhysicalDeviceType, device_local_memory: vk::DeviceSize) -> u64 {
+    let type_weight = match device_type {
+        vk::PhysicalDeviceType::DISCRETE_GPU => 4,
+        vk::PhysicalDeviceType::INTEGRATED_GPU => 3,
+        vk::PhysicalDeviceType::VIRTUAL_GPU => 2,
+        vk::PhysicalDeviceType::CPU => 1,
+        _ => 0,
+    };
+
+    type_weight * 1_000_000 + device_local_memory / (1024 * 1024)
+}
+
+pub fn enumerate_adapters(
+    instance: &ash::Instance,
+    surface_info: &surface::SurfaceInfo,
+    requirements: &requirements::DeviceRequirements,
+) -> Result<Vec<AdapterInfo>> {
+    let physical_devices = unsafe { instance.enumerate_physical_devices() }?;
+
+    Ok(physical_devices
+        .into_iter()
+        .enumerate()
+        .map(|(index, physical_device)| {
+            let properties = unsafe { instance.get_physical_device_properties(physical_device) };
+            let (memory, shared_memory) = memory_sizes(instance, physical_device);
+            // failing to query an adapter just rules it out
+            let suitable = device::Device::is_physical_device_suitable(
+                instance,
+                physical_device,
+                surface_info,
+                requirements,
+            )
+            .unwrap_or(false);
+
+            AdapterInfo {
+                index,
+                name: foreign::vk_to_string(&properties.device_name),
+                device_type: format!("{:?}", properties.device_type),
+                vendor_id: properties.vendor_id,
+                device_id: properties.device_id,
+                device_local_memory: memory,
+                shared_memory,
+                queue_families: queue_families(instance, physical_device, surface_info),
+                suitable,
+                score: score(properties.device_type, memory),
+                physical_device,
+            }
+        })
+        .collect())
+}
+
+pub fn select_adapter<'a>(
+    adapters: &'a [AdapterInfo],
+    selection: &DeviceSelection,
+) -> Result<&'a AdapterInfo> {
+    let mut suitable = adapters.iter().filter(|adapter| adapter.suitable);
+
+    match selection {
+        DeviceSelection::Auto => suitable
+            .max_by_key(|adapter| adapter.score)
+            .ok_or_else(|| Error::DeviceNotFound("failed to find a gpu".to_string())),
+
+        DeviceSelection::Index(index) => suitable
+            .find(|adapter| adapter.index == *index)
+            .ok_or_else(|| {
+                Error::DeviceNotFound(format!("gpu {} does not exist or is not suitable", index))
+            }),
+
+        DeviceSelection::Name(name) => {
+            let name = name.to_lowercase();
+            suitable
+                .find(|adapter| adapter.name.to_lowercase().contains(&name))
+                .ok_or_else(|| Error::DeviceNotFound(format!("no suitable gpu matches {:?}", name)))
+        }
+
+        DeviceSelection::Handle(handle) => suitable
+            .find(|adapter| adapter.physical_device == *handle)
+            .ok_or_else(|| Error::DeviceNotFound(format!("gpu {:?} is not suitable", handle))),
+    }
+}
@@ -0,0 +1,186 @@
+// The device, queue and swapchain operations of the frame loop and of draw recording,
+// behind traits so that logic can run against headless::HeadlessDevice on machines
+// without a gpu. The vulkan implementations forward to ash.
+
+use ash::version::DeviceV1_0;
+use ash::vk;
+
+use crate::error::{Context, Error, Result};
+
+use super::queue;
+use super::swapchain;
+
+pub trait FenceOps {
+    fn wait_for_fences(&self, fences: &[vk::Fence], timeout: u64) -> Result<()>;
+    fn reset_fences(&self, fences: &[vk::Fence]) -> Result<()>;
+}
+
+pub trait QueueOps {
+    // Submits to the graphics queue, the fence is signaled once the work completed
+    fn submit(&self, submits: &[vk::SubmitInfo], fence: vk::Fence) -> Result<()>;
+    // Returns whether the swapchain no longer matches the surface exactly
+    fn present(&self, present_info: &vk::PresentInfoKHR) -> Result<bool>;
+}
+
+pub trait SwapchainOps {
+    fn image_count(&self) -> usize;
+    // Fails with Error::SwapchainOutOfDate once the swapchain has to be recreated
+    fn acquire_next_image(&self, semaphore: vk::Semaphore, timeout: u64) -> Result<u32>;
+}
+
+// The commands draw recording uses, see material::MaterialLibrary::record_draws
+pub trait CommandOps {
+    fn bind_pipeline(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        bind_point: vk::PipelineBindPoint,
+        pipeline: vk::Pipeline,
+    );
+
+    fn bind_descriptor_sets(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        bind_point: vk::PipelineBindPoint,
+        layout: vk::PipelineLayout,
+        first_set: u32,
+        descriptor_sets: &[vk::DescriptorSet],
+        dynamic_offsets: &[u32],
+    );
+
+    fn push_constants(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        layout: vk::PipelineLayout,
+        stage_flags: vk::ShaderStageFlags,
+        offset: u32,
+        constants: &[u8],
+    );
+
+    fn draw_indexed(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        index_count: u32,
+        instance_count: u32,
+        first_index: u32,
+        vertex_offset: i32,
+        first_instance: u32,
+    );
+}
+
+impl FenceOps for ash::Device {
+    fn wait_for_fences(&self, fences: &[vk::Fence], timeout: u64) -> Result<()> {
+        unsafe {
+            DeviceV1_0::wait_for_fences(self, fences, true, timeout)
+                .context("failed to wait for fences")
+        }
+    }
+
+    fn reset_fences(&self, fences: &[vk::Fence]) -> Result<()> {
+        unsafe { DeviceV1_0::reset_fences(self, fences).context("failed to reset fences") }
+    }
+}
+
+impl CommandOps for ash::Device {
+    fn bind_pipeline(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        bind_point: vk::PipelineBindPoint,
+        pipeline: vk::Pipeline,
+    ) {
+        unsafe { self.cmd_bind_pipeline(command_buffer, bind_point, pipeline) }
+    }
+
+    fn bind_descriptor_sets(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        bind_point: vk::PipelineBindPoint,
+        layout: vk::PipelineLayout,
+        first_set: u32,
+        descriptor_sets: &[vk::DescriptorSet],
+        dynamic_offsets: &[u32],
+    ) {
+        unsafe {
+            self.cmd_bind_descriptor_sets(
+                command_buffer,
+                bind_point,
+                layout,
+                first_set,
+                descriptor_sets,
+                dynamic_offsets,
+            )
+        }
+    }
+
+    fn push_constants(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        layout: vk::PipelineLayout,
+        stage_flags: vk::ShaderStageFlags,
+        offset: u32,
+        constants: &[u8],
+    ) {
+        unsafe { self.cmd_push_constants(command_buffer, layout, stage_flags, offset, constants) }
+    }
+
+    fn draw_indexed(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        index_count: u32,
+        instance_count: u32,
+        first_index: u32,
+        vertex_offset: i32,
+        first_instance: u32,
+    ) {
+        unsafe {
+            self.cmd_draw_indexed(
+                command_buffer,
+                index_count,
+                instance_count,
+                first_index,
+                vertex_offset,
+                first_instance,
+            )
+        }
+    }
+}
+
+impl SwapchainOps for swapchain::SwapchainDetails {
+    fn image_count(&self) -> usize {
+        self.images.len()
+    }
+
+    fn acquire_next_image(&self, semaphore: vk::Semaphore, timeout: u64) -> Result<u32> {
+        unsafe {
+            self.loader
+                .acquire_next_image(self.swapchain, timeout, semaphore, vk::Fence::null())
+        }
+        .map(|(image_index, _)| image_index)
+        .map_err(|err| match err {
+            vk::Result::ERROR_OUT_OF_DATE_KHR => Error::SwapchainOutOfDate,
+            _ => Error::Vk(err),
+        })
+    }
+}
+
+// The engine's graphics and present queues, presenting to the swapchain of the loader
+pub struct VulkanQueues<'a> {
+    pub device: &'a ash::Device,
+    pub queue: &'a queue::Queue,
+    pub swapchain_loader: &'a ash::extensions::khr::Swapchain,
+}
+
+impl<'a> QueueOps for VulkanQueues<'a> {
+    fn submit(&self, submits: &[vk::SubmitInfo], fence: vk::Fence) -> Result<()> {
+        self.queue
+            .graphics_submit
+            .submit(self.device, submits, fence)
+            .context("failed to submit to graphics queue")
+    }
+
+    fn present(&self, present_info: &vk::PresentInfoKHR) -> Result<bool> {
+        self.queue
+            .present_submit
+            .present(self.swapchain_loader, present_info)
+            .context("could not present to queue")
+    }
+}
@@ -0,0 +1,372 @@
+use ash::version::{DeviceV1_0, InstanceV1_1};
+use ash::vk;
+
+use crate::error::{Context, Error, Result};
+
+use std::cmp;
+use std::os::raw::c_void;
+use std::path::Path;
+
+use super::backend;
+use super::device;
+use super::material;
+use super::texture;
+
+// Upper bound of the texture array, lowered to what the device supports
+pub const MAX_BINDLESS_TEXTURES: u32 = 4096;
+
+// Texture slots a material can index, see MaterialIndices
+pub const MAX_MATERIAL_SLOTS: usize = 4;
+
+// Pushed before the draws of a material, the indices into the texture array of its slots
+// in the order of the material's description. The fragment shader declares it as
+//
+//     layout(push_constant) uniform Material { uint textures[4]; } material;
+//     layout(set = 1, binding = 0) uniform sampler2D textures[];
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct MaterialIndices {
+    pub textures: [u32; MAX_MATERIAL_SLOTS],
+}
+
+impl MaterialIndices {
+    pub fn new(indices: &[u32]) -> Result<MaterialIndices> {
+        if indices.len() > MAX_MATERIAL_SLOTS {
+            return Err(Error::OutOfRange(format!(
+                "bindless materials have up to {} textures, got {}",
+                MAX_MATERIAL_SLOTS,
+                indices.len()
+            )));
+        }
+
+        let mut material = MaterialIndices::default();
+        material.textures[..indices.len()].copy_from_slice(indices);
+        Ok(material)
+    }
+
+    pub fn push_constant_range() -> vk::PushConstantRange {
+        vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::FRAGMENT,
+            offset: 0,
+            size: std::mem::size_of::<MaterialIndices>() as u32,
+        }
+    }
+
+    pub fn push<C: backend::CommandOps>(
+        &self,
+        commands: &C,
+        command_buffer: vk::CommandBuffer,
+        pipeline_layout: vk::PipelineLayout,
+    ) {
+        let bytes = unsafe {
+            std::slice::from_raw_parts(
+                self as *const MaterialIndices as *const u8,
+                std::mem::size_of::<MaterialIndices>(),
+            )
+        };
+
+        commands.push_constants(
+            command_buffer,
+            pipeline_layout,
+            vk::ShaderStageFlags::FRAGMENT,
+            0,
+            bytes,
+        );
+    }
+}
+
+fn supported_features(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+) -> vk::PhysicalDeviceDescriptorIndexingFeaturesEXT {
+    let mut indexing = vk::PhysicalDeviceDescriptorIndexingFeaturesEXT::default();
+    let mut features = vk::PhysicalDeviceFeatures2 {
+        p_next: &mut indexing as *mut vk::PhysicalDeviceDescriptorIndexingFeaturesEXT
+            as *mut c_void,
+        ..Default::default()
+    };
+
+    unsafe { instance.get_physical_device_features2(physical_device, &mut features) };
+    indexing
+}
+
+// Checked when creating the logical device, see device::Device::descriptor_indexing
+pub fn is_supported(instance: &ash::Instance, physical_device: vk::PhysicalDevice) -> Result<bool> {
+    let has_extension = device::Device::check_device_extension_support(
+        instance,
+        physical_device,
+        &material::DESCRIPTOR_INDEXING_EXTENSION,
+    )?;
+
+    if !has_extension {
+        return Ok(false);
+    }
+
+    let features = supported_features(instance, physical_device);
+
+    Ok(
+        features.shader_sampled_image_array_non_uniform_indexing == vk::TRUE
+            && features.descriptor_binding_sampled_image_update_after_bind == vk::TRUE
+            && features.descriptor_binding_partially_bound == vk::TRUE
+            && features.runtime_descriptor_array == vk::TRUE,
+    )
+}
+
+// Chained into vk::DeviceCreateInfo to enable what the texture array relies on
+pub fn device_features() -> vk::PhysicalDeviceDescriptorIndexingFeaturesEXT {
+    vk::PhysicalDeviceDescriptorIndexingFeaturesEXT {
+        shader_sampled_image_array_non_uniform_indexing: vk::TRUE,
+        descriptor_binding_sampled_image_update_after_bind: vk::TRUE,
+        descriptor_binding_partially_bound: vk::TRUE,
+        runtime_descriptor_array: vk::TRUE,
+        ..Default::default()
+    }
+}
+
+// Combined image samplers count against both the sampler and the sampled image limits
+pub fn clamp_capacity(properties: &vk::PhysicalDeviceDescriptorIndexingPropertiesEXT) -> u32 {
+    [
+        properties.max_descriptor_set_update_after_bind_sampled_images,
+        properties.max_descriptor_set_update_after_bind_samplers,
+        properties.max_per_stage_descriptor_update_after_bind_sampled_images,
+        properties.max_per_stage_descriptor_update_after_bind_samplers,
+    ]
+    .iter()
+    .fold(MAX_BINDLESS_TEXTURES, |capacity, &limit| {
+        cmp::min(capacity, limit)
+    })
+}
+
+fn capacity(instance: &ash::Instance, physical_device: vk::PhysicalDevice) -> u32 {
+    let mut indexing = vk::PhysicalDeviceDescriptorIndexingPropertiesEXT::default();
+    let mut properties = vk::PhysicalDeviceProperties2 {
+        p_next: &mut indexing as *mut vk::PhysicalDeviceDescriptorIndexingPropertiesEXT
+            as *mut c_void,
+        ..Default::default()
+    };
+
+    unsafe { instance.get_physical_device_properties2(physical_device, &mut properties) };
+    clamp_capacity(&indexing)
+}
+
+// One large array of textures in a single descriptor set, bound once for all the draws
+// of a scene. Materials refer to their textures by index, pushed as MaterialIndices,
+// so scenes with hundreds of textures need no set per material. Textures can be added
+// while command buffers using the set are pending, the slots they use stay untouched.
+pub struct BindlessTextures {
+    pub layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    pub descriptor_set: vk::DescriptorSet,
+    pub capacity: u32,
+    textures: Vec<texture::Texture>,
+}
+
+impl BindlessTextures {
+    pub fn new(instance: &ash::Instance, device: &device::Device) -> Result<BindlessTextures> {
+        if !device.descriptor_indexing {
+            return Err(Error::Unsupported(
+                "bindless textures need VK_EXT_descriptor_indexing".to_string(),
+            ));
+        }
+
+        let capacity = capacity(instance, device.physical_device);
+        let logical_device = &device.logical_device;
+
+        let binding_flags = [vk::DescriptorBindingFlagsEXT::PARTIALLY_BOUND
+            | vk::DescriptorBindingFlagsEXT::UPDATE_AFTER_BIND];
+        let binding_flags_info = vk::DescriptorSetLayoutBindingFlagsCreateInfoEXT {
+            binding_count: binding_flags.len() as u32,
+            p_binding_flags: binding_flags.as_ptr(),
+            ..Default::default()
+        };
+
+        let binding = vk::DescriptorSetLayoutBinding {
+            binding: 0,
+            descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: capacity,
+            stage_flags: vk::ShaderStageFlags::FRAGMENT,
+            ..Default::default()
+        };
+
+        let layout_info = vk::DescriptorSetLayoutCreateInfo {
+            p_next: &binding_flags_info as *const vk::DescriptorSetLayoutBindingFlagsCreateInfoEXT
+                as *const c_void,
+            flags: vk::DescriptorSetLayoutCreateFlags::UPDATE_AFTER_BIND_POOL_EXT,
+            binding_count: 1,
+            p_bindings: &binding,
+            ..Default::default()
+        };
+
+        let layout = unsafe {
+            logical_device
+                .create_descriptor_set_layout(&layout_info, None)
+                .context("failed to create bindless descriptor set layout")
+        }?;
+
+        let pool_size = vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: capacity,
+        };
+
+        let pool_info = vk::DescriptorPoolCreateInfo {
+            flags: vk::DescriptorPoolCreateFlags::UPDATE_AFTER_BIND_EXT,
+            pool_size_count: 1,
+            p_pool_sizes: &pool_size,
+            max_sets: 1,
+            ..Default::default()
+        };
+
+        let descriptor_pool = unsafe {
+            logical_device
+                .create_descriptor_pool(&pool_info, None)
+                .context("failed to create bindless descriptor pool")
+        }?;
+
+        let layouts = [layout];
+        let alloc_info = vk::DescriptorSetAllocateInfo {
+            descriptor_pool,
+            descriptor_set_count: 1,
+            p_set_layouts: layouts.as_ptr(),
+            ..Default::default()
+        };
+
+        let descriptor_set = unsafe {
+            logical_device
+                .allocate_descriptor_sets(&alloc_info)
+                .context("failed to allocate bindless descriptor set")
+        }?[0];
+
+        tracing::debug!("bindless texture array holds up to {} textures", capacity);
+
+        Ok(BindlessTextures {
+            layout,
+            descriptor_pool,
+            descriptor_set,
+            capacity,
+            textures: vec![],
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.textures.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.textures.is_empty()
+    }
+
+    // Loads the texture into the next free element of the array and returns its index
+    pub fn load(
+        &mut self,
+        device: &device::Device,
+        command_pool: vk::CommandPool,
+        submit_queue: vk::Queue,
+        path: &Path,
+        color_space: texture::ColorSpace,
+    ) -> Result<u32> {
+        let index = self.textures.len() as u32;
+        if index >= self.capacity {
+            return Err(Error::OutOfRange(format!(
+                "the bindless texture array is full, it holds {} textures",
+                self.capacity
+            )));
+        }
+
+        let texture = texture::Texture::with_color_space(
+            device,
+            command_pool,
+            submit_queue,
+            path,
+            color_space,
+        )?;
+
+        let image_info = vk::DescriptorImageInfo {
+            sampler: texture.sampler,
+            image_view: texture.image_data.image_view,
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        };
+
+        let descriptor_write = vk::WriteDescriptorSet {
+            dst_set: self.descriptor_set,
+            dst_binding: 0,
+            dst_array_element: index,
+            descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: 1,
+            p_image_info: &image_info,
+            ..Default::default()
+        };
+
+        unsafe {
+            device
+                .logical_device
+                .update_descriptor_sets(&[descriptor_write], &[])
+        };
+
+        self.textures.push(texture);
+        Ok(index)
+    }
+
+    pub fn bind<C: backend::CommandOps>(
+        &self,
+        commands: &C,
+        command_buffer: vk::CommandBuffer,
+        pipeline_layout: vk::PipelineLayout,
+    ) {
+        commands.bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            pipeline_layout,
+            material::MATERIAL_SET,
+            &[self.descriptor_set],
+            &[],
+        );
+    }
+
+    pub fn destroy(&self, device: &device::Device) {
+        // frees the descriptor set as well
+        unsafe {
+            device
+                .logical_device
+                .destroy_descriptor_pool(self.descriptor_pool, None);
+            device
+                .logical_device
+                .destroy_descriptor_set_layout(self.layout, None);
+        };
+
+        for texture in self.textures.iter() {
+            texture.destroy(device);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn material_indices_fit_the_push_constant_range() {
+        let indices = MaterialIndices::new(&[3, 7]).unwrap();
+        assert_eq!(indices.textures, [3, 7, 0, 0]);
+
+        assert_eq!(MaterialIndices::push_constant_range().size, 16);
+        assert!(MaterialIndices::new(&[0; MAX_MATERIAL_SLOTS + 1]).is_err());
+    }
+
+    #[test]
+    fn capacity_follows_the_smallest_limit() {
+        let properties = vk::PhysicalDeviceDescriptorIndexingPropertiesEXT {
+            max_descriptor_set_update_after_bind_sampled_images: 1_000_000,
+            max_descriptor_set_update_after_bind_samplers: 1_000_000,
+            max_per_stage_descriptor_update_after_bind_sampled_images: 500_000,
+            max_per_stage_descriptor_update_after_bind_samplers: 500_000,
+            ..Default::default()
+        };
+        assert_eq!(clamp_capacity(&properties), MAX_BINDLESS_TEXTURES);
+
+        let properties = vk::PhysicalDeviceDescriptorIndexingPropertiesEXT {
+            max_per_stage_descriptor_update_after_bind_samplers: 1024,
+            ..properties
+        };
+        assert_eq!(clamp_capacity(&properties), 1024);
+    }
+}
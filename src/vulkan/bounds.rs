@@ -0,0 +1,259 @@
+use cgmath::{InnerSpace, Matrix4, Point3, Vector3, Vector4};
+
+// Axis aligned box around a mesh in its model space
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Aabb {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+
+impl Aabb {
+    pub fn from_points<I: IntoIterator<Item = Point3<f32>>>(points: I) -> Option<Aabb> {
+        let mut points = points.into_iter();
+        let first = points.next()?;
+
+        Some(points.fold(
+            Aabb {
+                min: first,
+                max: first,
+            },
+            |aabb, point| Aabb {
+                min: Point3::new(
+                    aabb.min.x.min(point.x),
+                    aabb.min.y.min(point.y),
+                    aabb.min.z.min(point.z),
+                ),
+                max: Point3::new(
+                    aabb.max.x.max(point.x),
+                    aabb.max.y.max(point.y),
+                    aabb.max.z.max(point.z),
+                ),
+            },
+        ))
+    }
+
+    pub fn center(&self) -> Point3<f32> {
+        Point3::new(
+            (self.min.x + self.max.x) * 0.5,
+            (self.min.y + self.max.y) * 0.5,
+            (self.min.z + self.max.z) * 0.5,
+        )
+    }
+
+    pub fn corners(&self) -> [Point3<f32>; 8] {
+        let (min, max) = (self.min, self.max);
+        [
+            Point3::new(min.x, min.y, min.z),
+            Point3::new(max.x, min.y, min.z),
+            Point3::new(max.x, max.y, min.z),
+            Point3::new(min.x, max.y, min.z),
+            Point3::new(min.x, min.y, max.z),
+            Point3::new(max.x, min.y, max.z),
+            Point3::new(max.x, max.y, max.z),
+            Point3::new(min.x, max.y, max.z),
+        ]
+    }
+
+    // The box around the transformed corners, so it stays axis aligned in the new space
+    pub fn transformed(&self, transform: Matrix4<f32>) -> Aabb {
+        let corners = self.corners();
+        let transformed = corners
+            .iter()
+            .map(|corner| Point3::from_homogeneous(transform * corner.to_homogeneous()));
+
+        // a box always has corners
+        Aabb::from_points(transformed).unwrap_or(*self)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BoundingSphere {
+    pub center: Point3<f32>,
+    pub radius: f32,
+}
+
+impl BoundingSphere {
+    // Centered on the box, not the smallest sphere but cheap and good enough for culling
+    pub fn from_aabb(aabb: &Aabb) -> BoundingSphere {
+        let center = aabb.center();
+
+        BoundingSphere {
+            center,
+            radius: (aabb.max - center).magnitude(),
+        }
+    }
+
+    pub fn transformed(&self, transform: Matrix4<f32>) -> BoundingSphere {
+        // non uniform scale stretches the sphere along the largest axis
+        let scale = transform
+            .x
+            .truncate()
+            .magnitude()
+            .max(transform.y.truncate().magnitude())
+            .max(transform.z.truncate().magnitude());
+
+        BoundingSphere {
+            center: Point3::from_homogeneous(transform * self.center.to_homogeneous()),
+            radius: self.radius * scale,
+        }
+    }
+}
+
+// Bounding volumes of a mesh, computed once when its vertices are loaded
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct MeshBounds {
+    pub aabb: Aabb,
+    pub sphere: BoundingSphere,
+}
+
+impl MeshBounds {
+    pub fn from_positions<I: IntoIterator<Item = [f32; 3]>>(positions: I) -> Option<MeshBounds> {
+        let aabb = Aabb::from_points(positions.into_iter().map(Point3::from))?;
+
+        Some(MeshBounds {
+            aabb,
+            sphere: BoundingSphere::from_aabb(&aabb),
+        })
+    }
+}
+
+// Planes of the camera frustum in world space, their normals point inside
+#[derive(Debug, Copy, Clone)]
+pub struct Frustum {
+    planes: [Vector4<f32>; 6],
+}
+
+impl Frustum {
+    // Gribb-Hartmann extraction from the view projection matrix
+    pub fn from_matrix(view_proj: Matrix4<f32>) -> Frustum {
+        let row = |i: usize| {
+            Vector4::new(
+                view_proj.x[i],
+                view_proj.y[i],
+                view_proj.z[i],
+                view_proj.w[i],
+            )
+        };
+        let (x, y, z, w) = (row(0), row(1), row(2), row(3));
+
+        // w + z is the near plane of a -1..1 depth range, which also contains the
+        // 0..1 range vulkan uses, so nothing visible is ever culled
+        let planes = [w + x, w - x, w + y, w - y, w + z, w - z];
+
+        let mut normalized = [Vector4::new(0.0, 0.0, 0.0, 0.0); 6];
+        for (out, plane) in normalized.iter_mut().zip(planes.iter()) {
+            let length = plane.truncate().magnitude();
+            *out = if length > 0.0 { plane / length } else { *plane };
+        }
+
+        Frustum { planes: normalized }
+    }
+
+    // xyz: normal, w: distance, in the order left, right, bottom, top, near, far
+    pub fn planes(&self) -> [Vector4<f32>; 6] {
+        self.planes
+    }
+
+    fn distance(plane: &Vector4<f32>, point: Point3<f32>) -> f32 {
+        plane
+            .truncate()
+            .dot(Vector3::new(point.x, point.y, point.z))
+            + plane.w
+    }
+
+    pub fn intersects_sphere(&self, sphere: &BoundingSphere) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| Frustum::distance(plane, sphere.center) >= -sphere.radius)
+    }
+
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            // the corner furthest along the plane's normal
+            let furthest = |normal: f32, min: f32, max: f32| if normal >= 0.0 { max } else { min };
+            let corner = Point3::new(
+                furthest(plane.x, aabb.min.x, aabb.max.x),
+                furthest(plane.y, aabb.min.y, aabb.max.y),
+                furthest(plane.z, aabb.min.z, aabb.max.z),
+            );
+
+            Frustum::distance(plane, corner) >= 0.0
+        })
+    }
+
+    // Sphere first as it is cheaper and rejects most of the off-screen meshes
+    pub fn is_visible(&self, bounds: &MeshBounds, model: Matrix4<f32>) -> bool {
+        self.intersects_sphere(&bounds.sphere.transformed(model))
+            && self.intersects_aabb(&bounds.aabb.transformed(model))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use cgmath::{Deg, SquareMatrix};
+
+    fn camera() -> Frustum {
+        let proj = cgmath::perspective(Deg(45.0), 1.0, 0.1, 10.0);
+        let view = Matrix4::look_at(
+            Point3::new(0.0, 0.0, 5.0),
+            Point3::new(0.0, 0.0, 0.0),
+            Vector3::new(0.0, 1.0, 0.0),
+        );
+
+        Frustum::from_matrix(proj * view)
+    }
+
+    fn unit_cube() -> MeshBounds {
+        MeshBounds::from_positions(vec![[-0.5, -0.5, -0.5], [0.5, 0.5, 0.5], [0.0, 0.2, 0.1]])
+            .unwrap()
+    }
+
+    #[test]
+    fn bounds_enclose_all_positions() {
+        let bounds = unit_cube();
+
+        assert_eq!(bounds.aabb.min, Point3::new(-0.5, -0.5, -0.5));
+        assert_eq!(bounds.aabb.max, Point3::new(0.5, 0.5, 0.5));
+        assert_eq!(bounds.sphere.center, Point3::new(0.0, 0.0, 0.0));
+        assert!((bounds.sphere.radius - 0.75_f32.sqrt()).abs() < 1e-6);
+        assert_eq!(MeshBounds::from_positions(Vec::new()), None);
+    }
+
+    #[test]
+    fn meshes_in_front_of_the_camera_are_visible() {
+        let frustum = camera();
+
+        assert!(frustum.is_visible(&unit_cube(), Matrix4::identity()));
+        // partly on screen
+        let edge = Matrix4::from_translation(Vector3::new(2.3, 0.0, 0.0));
+        assert!(frustum.is_visible(&unit_cube(), edge));
+    }
+
+    #[test]
+    fn meshes_outside_the_frustum_are_culled() {
+        let frustum = camera();
+        let outside = [
+            Vector3::new(10.0, 0.0, 0.0),
+            Vector3::new(0.0, -10.0, 0.0),
+            // behind the camera and beyond the far plane
+            Vector3::new(0.0, 0.0, 8.0),
+            Vector3::new(0.0, 0.0, -20.0),
+        ];
+
+        for offset in outside.iter() {
+            let model = Matrix4::from_translation(*offset);
+            assert!(!frustum.is_visible(&unit_cube(), model), "{:?}", offset);
+        }
+    }
+
+    #[test]
+    fn scale_grows_the_bounds() {
+        let frustum = camera();
+        let model =
+            Matrix4::from_translation(Vector3::new(4.0, 0.0, 0.0)) * Matrix4::from_scale(4.0);
+
+        assert!(frustum.is_visible(&unit_cube(), model));
+    }
+}
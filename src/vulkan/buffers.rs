@@ -1,17 +1,44 @@
 use ash::version::DeviceV1_0;
 use ash::vk;
+use ash::vk::Handle;
 
-use anyhow::anyhow;
-use anyhow::{Context, Result};
+use crate::error::{Context, Error, Result};
 
+use super::bounds;
+use super::compute_skinning;
+use super::deferred;
+use super::descriptor;
 use super::device;
+use super::dynamic_rendering;
+use super::frame;
+use super::gc;
 use super::image;
+use super::lighting;
+use super::material;
+use super::mesh_pool;
+use super::permutation;
 use super::pipeline;
+use super::preset;
+use super::profiler;
+use super::queue;
+use super::registry;
+use super::render_settings;
+use super::skinning;
 use super::swapchain;
 use super::texture;
+use super::trace;
+use super::typed_buffer;
+use super::uniform_ring;
+use super::upload;
+
+use cgmath::Matrix4;
 
 use std::path::Path;
 
+// Reported when skinned meshes are used before a skinning pass was set, see set_skinning
+pub const SKINNING_DISABLED: &str =
+    "compute skinning is not enabled, see Engine::enable_compute_skinning";
+
 pub struct CommandBuffer {}
 
 impl CommandBuffer {
@@ -65,13 +92,15 @@ impl CommandBuffer {
             ..Default::default()
         }];
 
-        unsafe {
-            device
-                .queue_submit(graphics_queue, &submit_infos, vk::Fence::null())
-                .and_then(|_| device.queue_wait_idle(graphics_queue))
+        let queue = queue::SharedQueue::new(graphics_queue);
+
+        trace::call("vkQueueSubmit", &submit_infos, || {
+            queue
+                .submit(device, &submit_infos, vk::Fence::null())
+                .and_then(|_| queue.wait_idle(device))
                 .context("failed to submit command buffer to graphics queue")
-                .map(|_| device.free_command_buffers(command_pool, &buffers))
-        }
+                .map(|_| unsafe { device.free_command_buffers(command_pool, &buffers) })
+        })
     }
 
     pub fn record_command_to_buffers<F>(
@@ -123,6 +152,29 @@ impl CommandBuffer {
             .collect::<Result<Vec<()>>>()
             .map(|_| command_buffers)
     }
+
+    // Runs secondary command buffers, eg. from `parallel::ParallelRecorder`, in a render pass
+    // of the primary one. The pass has no inline commands, so it is begun and ended here.
+    pub fn execute_in_render_pass(
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        render_pass_begin_info: &vk::RenderPassBeginInfo,
+        secondary_command_buffers: &[vk::CommandBuffer],
+    ) {
+        unsafe {
+            device.cmd_begin_render_pass(
+                command_buffer,
+                render_pass_begin_info,
+                vk::SubpassContents::SECONDARY_COMMAND_BUFFERS,
+            );
+
+            if !secondary_command_buffers.is_empty() {
+                device.cmd_execute_commands(command_buffer, secondary_command_buffers);
+            }
+
+            device.cmd_end_render_pass(command_buffer);
+        }
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -133,10 +185,10 @@ pub struct BufferInfo {
 }
 
 type VertexBuffer = BufferInfo;
-type IndexBuffer = BufferInfo;
+type IndexBuffer = typed_buffer::IndexBuffer<u32>;
 
 impl BufferInfo {
-    fn create(
+    pub fn create(
         device: &device::Device,
         size: vk::DeviceSize,
         usage: vk::BufferUsageFlags,
@@ -149,12 +201,12 @@ impl BufferInfo {
             ..Default::default()
         };
 
-        let buffer = unsafe {
+        let buffer = trace::call("vkCreateBuffer", &buffer_info, || unsafe {
             device
                 .logical_device
                 .create_buffer(&buffer_info, None)
                 .context("failed to create buffer")
-        }?;
+        })?;
 
         let mem_requirements =
             unsafe { device.logical_device.get_buffer_memory_requirements(buffer) };
@@ -169,12 +221,12 @@ impl BufferInfo {
             ..Default::default()
         };
 
-        let buffer_memory = unsafe {
+        let buffer_memory = trace::call("vkAllocateMemory", &allocate_info, || unsafe {
             device
                 .logical_device
                 .allocate_memory(&allocate_info, None)
                 .context("Failed to allocate vertex buffer memory!")
-        }?;
+        })?;
 
         unsafe {
             device
@@ -182,11 +234,264 @@ impl BufferInfo {
                 .bind_buffer_memory(buffer, buffer_memory, 0)
                 .context("Failed to bind buffer")
         }
-        .map(|_| BufferInfo {
-            buffer,
-            device_memory: buffer_memory,
-            size: size,
+        .map(|_| {
+            if let Ok(mut resources) = device.resources.lock() {
+                resources.register_buffer(buffer, mem_requirements.size, usage);
+            }
+
+            BufferInfo {
+                buffer,
+                device_memory: buffer_memory,
+                size: size,
+            }
+        })
+    }
+
+    pub fn set_name(&self, device: &device::Device, name: &str) {
+        device.name_resource(self.buffer, name);
+        device.name_resource(self.device_memory, &format!("{} memory", name));
+    }
+
+    // Maps the whole host visible buffer until unmap is called, eg. for a buffer written
+    // every frame. upload and update_region can't be used while it is mapped.
+    pub fn map(&self, device: &ash::Device) -> Result<*mut u8> {
+        unsafe {
+            device
+                .map_memory(
+                    self.device_memory,
+                    0,
+                    vk::WHOLE_SIZE,
+                    vk::MemoryMapFlags::empty(),
+                )
+                .context("failed to map memory")
+                .map(|data_ptr| data_ptr as *mut u8)
+        }
+    }
+
+    pub fn unmap(&self, device: &ash::Device) {
+        unsafe { device.unmap_memory(self.device_memory) };
+    }
+
+    // Copies data into a host visible buffer
+    pub fn upload<T>(&self, device: &ash::Device, data: &[T]) -> Result<()> {
+        self.update_region(device, 0, data)
+    }
+
+    // Overwrites part of a host visible buffer starting at offset bytes,
+    // the rest of its contents are left untouched
+    pub fn update_region<T>(
+        &self,
+        device: &ash::Device,
+        offset: vk::DeviceSize,
+        data: &[T],
+    ) -> Result<()> {
+        let data_size = ::std::mem::size_of_val(data) as vk::DeviceSize;
+        if offset + data_size > self.size {
+            return Err(Error::OutOfRange(format!(
+                "data of size {} at offset {} does not fit in buffer of size {}",
+                data_size, offset, self.size
+            )));
+        }
+
+        unsafe {
+            let data_ptr = device
+                .map_memory(
+                    self.device_memory,
+                    offset,
+                    data_size,
+                    vk::MemoryMapFlags::empty(),
+                )
+                .context("failed to map memory")? as *mut T;
+
+            data_ptr.copy_from_nonoverlapping(data.as_ptr(), data.len());
+
+            device.unmap_memory(self.device_memory);
+        }
+
+        Ok(())
+    }
+
+    // Copies count elements starting at offset bytes back to the cpu through a host
+    // visible staging buffer. The buffer needs TRANSFER_SRC usage, writes from earlier
+    // submissions on the queue are made visible before the copy.
+    pub fn read_back<T: Copy>(
+        &self,
+        device: &device::Device,
+        command_pool: vk::CommandPool,
+        queue: vk::Queue,
+        offset: vk::DeviceSize,
+        count: usize,
+    ) -> Result<Vec<T>> {
+        let data_size = (count * ::std::mem::size_of::<T>()) as vk::DeviceSize;
+        if offset + data_size > self.size {
+            return Err(Error::OutOfRange(format!(
+                "reading {} bytes at offset {} from buffer of size {}",
+                data_size, offset, self.size
+            )));
+        }
+
+        if data_size == 0 {
+            return Ok(vec![]);
+        }
+
+        let staging_buffer = BufferInfo::create(
+            device,
+            data_size,
+            vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+        staging_buffer.set_name(device, "readback buffer");
+
+        let result = self
+            .copy_to_host(device, command_pool, queue, offset, &staging_buffer)
+            .and_then(|_| staging_buffer.read_mapped(&device.logical_device, count));
+
+        staging_buffer.destroy(device);
+        result
+    }
+
+    fn copy_to_host(
+        &self,
+        device: &device::Device,
+        command_pool: vk::CommandPool,
+        queue: vk::Queue,
+        offset: vk::DeviceSize,
+        dest: &BufferInfo,
+    ) -> Result<()> {
+        let logical_device = &device.logical_device;
+
+        let allocate_info = vk::CommandBufferAllocateInfo {
+            command_buffer_count: 1,
+            command_pool,
+            level: vk::CommandBufferLevel::PRIMARY,
+            ..Default::default()
+        };
+
+        let command_buffers = unsafe {
+            logical_device
+                .allocate_command_buffers(&allocate_info)
+                .context("failed to allocate readback command buffer")
+        }?;
+        let command_buffer = command_buffers[0];
+
+        let begin_info = vk::CommandBufferBeginInfo {
+            flags: vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT,
+            ..Default::default()
+        };
+
+        let copy_regions = [vk::BufferCopy {
+            src_offset: offset,
+            dst_offset: 0,
+            size: dest.size,
+        }];
+
+        let recorded = unsafe {
+            logical_device
+                .begin_command_buffer(command_buffer, &begin_info)
+                .context("failed to begin readback command buffer")
+        }
+        .and_then(|_| unsafe {
+            // whatever wrote the buffer, eg. a compute dispatch, finishes before the copy
+            logical_device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[vk::MemoryBarrier {
+                    src_access_mask: vk::AccessFlags::MEMORY_WRITE,
+                    dst_access_mask: vk::AccessFlags::TRANSFER_READ,
+                    ..Default::default()
+                }],
+                &[],
+                &[],
+            );
+
+            logical_device.cmd_copy_buffer(command_buffer, self.buffer, dest.buffer, &copy_regions);
+
+            logical_device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::HOST,
+                vk::DependencyFlags::empty(),
+                &[vk::MemoryBarrier {
+                    src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+                    dst_access_mask: vk::AccessFlags::HOST_READ,
+                    ..Default::default()
+                }],
+                &[],
+                &[],
+            );
+
+            logical_device
+                .end_command_buffer(command_buffer)
+                .context("failed to end readback command buffer")
         })
+        .and_then(|_| {
+            let fence = unsafe {
+                logical_device
+                    .create_fence(&vk::FenceCreateInfo::default(), None)
+                    .context("failed to create readback fence")
+            }?;
+
+            let submit_infos = [vk::SubmitInfo {
+                command_buffer_count: 1,
+                p_command_buffers: command_buffers.as_ptr(),
+                ..Default::default()
+            }];
+
+            let waited = trace::call("vkQueueSubmit", &submit_infos, || {
+                queue::SharedQueue::new(queue)
+                    .submit(logical_device, &submit_infos, fence)
+                    .and_then(|_| unsafe {
+                        logical_device.wait_for_fences(&[fence], true, std::u64::MAX)
+                    })
+                    .context("failed to submit readback copy")
+            });
+
+            unsafe { logical_device.destroy_fence(fence, None) };
+            waited
+        });
+
+        unsafe { logical_device.free_command_buffers(command_pool, &command_buffers) };
+        recorded
+    }
+
+    // Reads count elements from the start of a host visible buffer
+    fn read_mapped<T: Copy>(&self, device: &ash::Device, count: usize) -> Result<Vec<T>> {
+        let mut data = Vec::with_capacity(count);
+
+        unsafe {
+            let data_ptr = device
+                .map_memory(
+                    self.device_memory,
+                    0,
+                    self.size,
+                    vk::MemoryMapFlags::empty(),
+                )
+                .context("failed to map memory")? as *const T;
+
+            data_ptr.copy_to_nonoverlapping(data.as_mut_ptr(), count);
+            data.set_len(count);
+
+            device.unmap_memory(self.device_memory);
+        }
+
+        Ok(data)
+    }
+
+    pub fn destroy(&self, device: &device::Device) {
+        if let Ok(mut resources) = device.resources.lock() {
+            resources.unregister(self.buffer.as_raw());
+        }
+
+        unsafe {
+            device.logical_device.destroy_buffer(self.buffer, None);
+            device.logical_device.free_memory(self.device_memory, None);
+        }
+    }
+
+    pub fn size(&self) -> vk::DeviceSize {
+        self.size
     }
 
     fn copy_to_gpu(
@@ -229,6 +534,7 @@ impl BufferInfo {
             vk::BufferUsageFlags::TRANSFER_SRC,
             vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
         )?;
+        staging_buffer.set_name(device, "staging buffer");
 
         // copy data from cpu to gpu staging
         unsafe {
@@ -263,7 +569,7 @@ impl BufferInfo {
             &gpu_buffer,
         )?;
 
-        // todo free staging buffer
+        staging_buffer.destroy(device);
 
         Ok(gpu_buffer)
     }
@@ -290,121 +596,130 @@ impl BufferInfo {
         graphics_queue: vk::Queue,
         data: &[u32],
     ) -> Result<IndexBuffer> {
-        BufferInfo::create_gpu_local_buffer(
-            device,
-            command_pool,
-            graphics_queue,
-            vk::BufferUsageFlags::INDEX_BUFFER,
-            data,
-            None,
-        )
+        typed_buffer::IndexBuffer::device_local(device, command_pool, graphics_queue, data)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum UpdatePolicy {
+    // updated and uploaded to the image's buffer every frame, eg. camera matrices
+    PerFrame,
+    // uploaded only after the data has been marked dirty, eg. material constants
+    OnDemand,
+    // uploaded once when the buffers are created, changing the data needs the buffers to
+    // be created again
+    Static,
+}
+
+// Tracks which of the per image uniform buffers still hold stale data
+#[derive(Debug, Clone)]
+pub struct UniformUploads {
+    pub policy: UpdatePolicy,
+    stale: Vec<bool>,
+}
+
+impl UniformUploads {
+    pub fn new(policy: UpdatePolicy, num_buffers: usize) -> UniformUploads {
+        UniformUploads {
+            policy,
+            stale: vec![true; num_buffers],
+        }
+    }
+
+    // Static buffers stay as they were uploaded when they were created
+    pub fn mark_dirty(&mut self) {
+        if self.policy == UpdatePolicy::Static {
+            return;
+        }
+
+        self.stale.iter_mut().for_each(|stale| *stale = true);
+    }
+
+    pub fn needs_upload(&self, index: usize) -> bool {
+        match self.policy {
+            UpdatePolicy::PerFrame => true,
+            UpdatePolicy::OnDemand | UpdatePolicy::Static => {
+                self.stale.get(index).cloned().unwrap_or(false)
+            }
+        }
+    }
+
+    pub fn uploaded(&mut self, index: usize) {
+        if let Some(stale) = self.stale.get_mut(index) {
+            *stale = false;
+        }
     }
 }
 
 pub trait UniformBuffers: Copy {
-    type Data;
+    type Data: Copy;
 
-    fn create(&self, device: &device::Device) -> Result<BufferInfo> {
-        let buffer_size = ::std::mem::size_of::<Self::Data>() as vk::DeviceSize;
-        BufferInfo::create(
-            device,
-            buffer_size as vk::DeviceSize,
-            vk::BufferUsageFlags::UNIFORM_BUFFER,
-            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
-        )
+    fn update_policy(&self) -> UpdatePolicy {
+        UpdatePolicy::PerFrame
     }
 
     fn update(&mut self, delta_time: f32) -> ();
 
     fn get_data(self) -> Self::Data;
 
+    // Model and view projection matrices the mesh is culled with, never culled without them
+    fn transforms(&self) -> Option<(Matrix4<f32>, Matrix4<f32>)> {
+        None
+    }
+
     fn update_buffer(
         &mut self,
-        device: &ash::Device,
-        uniform_buffer: &BufferInfo,
+        ring: &mut uniform_ring::UniformRing<Self::Data>,
+        region: usize,
         delta_time: f32,
     ) -> Result<()> {
-        let buffer_size = ::std::mem::size_of::<Self::Data>() as u64;
-
         self.update(delta_time);
-        let data = [self.get_data()];
-
-        unsafe {
-            let data_ptr = device
-                .map_memory(
-                    uniform_buffer.device_memory,
-                    0,
-                    buffer_size,
-                    vk::MemoryMapFlags::empty(),
-                )
-                .context("failed to map memory")? as *mut Self::Data;
-
-            data_ptr.copy_from_nonoverlapping(data.as_ptr(), data.len());
-
-            device.unmap_memory(uniform_buffer.device_memory);
-        }
+        self.upload_buffer(ring, region)
+    }
 
-        Ok(())
+    // Writes the current data to the ring's region without updating it first
+    fn upload_buffer(
+        &self,
+        ring: &mut uniform_ring::UniformRing<Self::Data>,
+        region: usize,
+    ) -> Result<()> {
+        ring.write(region, &self.get_data())
     }
 
     fn create_descriptor_pool(
         &self,
         device: &ash::Device,
-        pool_size_count: u32,
+        bindings: &[descriptor::Binding],
+        num_sets: u32,
     ) -> Result<vk::DescriptorPool> {
-        let pool_size = vk::DescriptorPoolSize {
-            ty: vk::DescriptorType::UNIFORM_BUFFER,
-            descriptor_count: pool_size_count,
-        };
-
-        let pool_info = vk::DescriptorPoolCreateInfo {
-            pool_size_count: 1,
-            p_pool_sizes: &pool_size,
-            max_sets: pool_size_count,
-            ..Default::default()
-        };
-
-        unsafe {
-            device
-                .create_descriptor_pool(&pool_info, None)
-                .context("failed to create descriptor pool!")
-        }
+        descriptor::create_pool(device, bindings, num_sets)
     }
 
+    // Writes the transform and light uniforms along with the texture to every set, one per
+    // light buffer. The transforms are read from the ring at the image's dynamic offset.
+    // Any other binding is left for the caller, eg. BufferDetails::bind_storage_buffer
     fn create_descriptor_sets(
         &self,
         device: &ash::Device,
         descriptor_layout: vk::DescriptorSetLayout,
-        uniform_buffers: &Vec<BufferInfo>,
-        texture_data: texture::Texture,
-    ) -> Result<Vec<vk::DescriptorSet>> {
-        let num_sets = uniform_buffers.len();
-
-        let pool = self.create_descriptor_pool(device, num_sets as u32)?;
-        let layouts = vec![descriptor_layout; num_sets];
-
-        let alloc_info = vk::DescriptorSetAllocateInfo {
-            descriptor_pool: pool,
-            descriptor_set_count: num_sets as u32,
-            p_set_layouts: layouts.as_ptr(),
-            ..Default::default()
-        };
-
-        let descriptor_sets = unsafe {
-            device
-                .allocate_descriptor_sets(&alloc_info)
-                .context("failed to allocate descriptor sets")
-        }?;
-
-        uniform_buffers
+        bindings: &[descriptor::Binding],
+        uniform_ring: &uniform_ring::UniformRing<Self::Data>,
+        light_buffers: &Vec<typed_buffer::UniformBuffer<lighting::LightBlock>>,
+        texture_data: &texture::Texture,
+    ) -> Result<(vk::DescriptorPool, Vec<vk::DescriptorSet>)> {
+        let num_sets = light_buffers.len();
+
+        let pool = self.create_descriptor_pool(device, bindings, num_sets as u32)?;
+        let descriptor_sets = descriptor::allocate_sets(device, pool, descriptor_layout, num_sets)?;
+        let ring_info = uniform_ring.descriptor_info()?;
+
+        light_buffers
             .iter()
             .zip(descriptor_sets)
-            .map(|(buffer, descriptor_set)| {
-                let buffer_info = [vk::DescriptorBufferInfo {
-                    buffer: buffer.buffer,
-                    offset: 0,
-                    range: ::std::mem::size_of::<Self::Data>() as u64,
-                }];
+            .map(|(light_buffer, descriptor_set)| {
+                let buffer_info = [ring_info];
+
+                let light_info = [light_buffer.descriptor_info(0)?];
 
                 let image_info = [vk::DescriptorImageInfo {
                     sampler: texture_data.sampler,
@@ -417,7 +732,7 @@ pub trait UniformBuffers: Copy {
                         dst_set: descriptor_set,
                         dst_binding: 0,
                         dst_array_element: 0,
-                        descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+                        descriptor_type: vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
                         descriptor_count: 1,
                         p_buffer_info: buffer_info.as_ptr(),
                         ..Default::default()
@@ -431,13 +746,23 @@ pub trait UniformBuffers: Copy {
                         p_image_info: image_info.as_ptr(),
                         ..Default::default()
                     },
+                    vk::WriteDescriptorSet {
+                        dst_set: descriptor_set,
+                        dst_binding: lighting::LIGHTS_BINDING,
+                        dst_array_element: 0,
+                        descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+                        descriptor_count: 1,
+                        p_buffer_info: light_info.as_ptr(),
+                        ..Default::default()
+                    },
                 ];
 
                 unsafe { device.update_descriptor_sets(&descriptor_write_sets, &[]) };
 
                 Ok(descriptor_set)
             })
-            .collect()
+            .collect::<Result<Vec<vk::DescriptorSet>>>()
+            .map(|descriptor_sets| (pool, descriptor_sets))
     }
 }
 
@@ -502,6 +827,8 @@ impl DepthBuffer {
         let depth_property = image::ImagePropertyType::depth_property(swapchain_extent, *format);
 
         image::ImageData::new(&device, command_pool, *graphics_queue, depth_property).map(|image| {
+            image.set_name(device, "depth image");
+
             DepthBuffer {
                 image,
                 format: *format,
@@ -510,27 +837,60 @@ impl DepthBuffer {
     }
 }
 
-pub struct BufferDetails<T: UniformBuffers> {
+// Framebuffers of the scene pass, one per swapchain image over its color view and a
+// depth buffer they share. Created again whenever the swapchain is.
+// A pipeline drawn with dynamic rendering gets render targets over the same images
+// instead, and no framebuffers.
+pub struct FramebufferSet {
     pub framebuffers: Vec<vk::Framebuffer>,
-    pub command_pool: vk::CommandPool,
-    pub command_buffers: Vec<vk::CommandBuffer>,
-    pub vertex_buffer: VertexBuffer,
-    pub index_buffer: IndexBuffer,
-    pub uniform_buffers: Vec<BufferInfo>,
-    pub uniform_buffer_data: T,
+    pub targets: Vec<dynamic_rendering::RenderTarget>,
+    depth_buffer: DepthBuffer,
+    extent: vk::Extent2D,
 }
 
-impl<T: UniformBuffers> BufferDetails<T> {
-    fn create_framebuffers(
-        device: &ash::Device,
-        render_pass: vk::RenderPass,
-        image_views: &Vec<vk::ImageView>,
-        swapchain_extent: vk::Extent2D,
-        depth_buffer: DepthBuffer,
-    ) -> Result<Vec<vk::Framebuffer>> {
+impl FramebufferSet {
+    pub fn new(
+        instance: &ash::Instance,
+        device: &device::Device,
+        command_pool: vk::CommandPool,
+        graphics_queue: vk::Queue,
+        pipeline: &pipeline::PipelineDetail,
+        target: pipeline::ColorTarget,
+        // one per swapchain image, the swapchain's images or offscreen targets
+        color_images: &[vk::Image],
+        color_views: &[vk::ImageView],
+        extent: vk::Extent2D,
+    ) -> Result<FramebufferSet> {
+        let depth_buffer =
+            DepthBuffer::new(instance, device, command_pool, &graphics_queue, extent)?;
         let depth_image_view = depth_buffer.image.image_view;
 
-        image_views
+        if let Some(formats) = pipeline.rendering {
+            let targets = color_images
+                .iter()
+                .zip(color_views.iter())
+                .map(
+                    |(&color_image, &color_view)| dynamic_rendering::RenderTarget {
+                        color_image,
+                        color_view,
+                        depth_image: depth_buffer.image.image,
+                        depth_view: depth_image_view,
+                        formats,
+                        final_layout: target.final_layout,
+                    },
+                )
+                .collect();
+
+            return Ok(FramebufferSet {
+                framebuffers: vec![],
+                targets,
+                depth_buffer,
+                extent,
+            });
+        }
+
+        let render_pass = pipeline.render_pass;
+        let framebuffers = color_views
             .iter()
             .map(|&image_view| {
                 let attachments = [image_view, depth_image_view];
@@ -539,130 +899,517 @@ impl<T: UniformBuffers> BufferDetails<T> {
                     render_pass,
                     attachment_count: attachments.len() as u32,
                     p_attachments: attachments.as_ptr(),
-                    width: swapchain_extent.width,
-                    height: swapchain_extent.height,
+                    width: extent.width,
+                    height: extent.height,
                     layers: 1,
                     ..Default::default()
                 };
 
                 unsafe {
                     device
+                        .logical_device
                         .create_framebuffer(&framebuffer_info, None)
                         .context("failed to create framebuffer")
                 }
             })
-            .collect()
+            .collect::<Result<Vec<vk::Framebuffer>>>()?;
+
+        for (i, &framebuffer) in framebuffers.iter().enumerate() {
+            device.track(registry::ResourceKind::Framebuffer, framebuffer);
+            device.name_resource(framebuffer, &format!("scene framebuffer {}", i));
+        }
+
+        Ok(FramebufferSet {
+            framebuffers,
+            targets: vec![],
+            depth_buffer,
+            extent,
+        })
     }
 
-    fn create_command_pool(device: &device::Device) -> Result<vk::CommandPool> {
-        let device::Device {
-            logical_device,
-            family_indices,
-            ..
-        } = device;
+    // only one of framebuffers and targets is filled
+    pub fn len(&self) -> usize {
+        self.framebuffers.len().max(self.targets.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn extent(&self) -> vk::Extent2D {
+        self.extent
+    }
+
+    pub fn depth_format(&self) -> vk::Format {
+        self.depth_buffer.format
+    }
+
+    pub fn destroy(&self, device: &device::Device) {
+        for &framebuffer in self.framebuffers.iter() {
+            device.untrack(framebuffer);
+            unsafe { device.logical_device.destroy_framebuffer(framebuffer, None) };
+        }
+
+        self.depth_buffer.image.destroy(device);
+    }
+}
+
+// A command pool of the graphics family with one primary command buffer per swapchain
+// image. The buffers start out stale and are recorded before their image is drawn
+// first, eg. by BufferDetails::record_frame_commands.
+pub struct CommandPoolWrapper {
+    pub pool: vk::CommandPool,
+    pub command_buffers: Vec<vk::CommandBuffer>,
+    stale: Vec<bool>,
+}
 
-        let queue_index = family_indices
+impl CommandPoolWrapper {
+    pub fn new(
+        device: &device::Device,
+        num_buffers: usize,
+        name: &str,
+    ) -> Result<CommandPoolWrapper> {
+        let queue_index = device
+            .family_indices
             .graphics
-            .ok_or_else(|| anyhow!("graphics family index not present"))?;
+            .ok_or_else(|| Error::msg("graphics family index not present"))?;
 
         let command_pool_info = vk::CommandPoolCreateInfo {
             queue_family_index: queue_index,
+            // command buffers are re-recorded when the debug view changes
+            flags: vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER,
             ..Default::default()
         };
 
-        unsafe {
-            logical_device
+        let pool = unsafe {
+            device
+                .logical_device
                 .create_command_pool(&command_pool_info, None)
                 .context("Failed to create command pool!")
+        }?;
+        device.track(registry::ResourceKind::CommandPool, pool);
+        device.name_resource(pool, &format!("{} command pool", name));
+
+        let alloc_info = vk::CommandBufferAllocateInfo {
+            command_buffer_count: num_buffers as u32,
+            command_pool: pool,
+            level: vk::CommandBufferLevel::PRIMARY,
+            ..Default::default()
+        };
+
+        let command_buffers = unsafe {
+            device
+                .logical_device
+                .allocate_command_buffers(&alloc_info)
+                .context("failed to allocate command buffers")
+        }?;
+
+        for (i, &command_buffer) in command_buffers.iter().enumerate() {
+            device.name_resource(command_buffer, &format!("{} command buffer {}", name, i));
         }
-    }
 
-    fn create_command_buffers(
-        device: &ash::Device,
-        command_pool: vk::CommandPool,
-        pipeline: pipeline::PipelineDetail,
-        framebuffers: &Vec<vk::Framebuffer>,
-        vertex_buffer: &VertexBuffer,
-        index_buffer: &IndexBuffer,
-        descriptor_sets: Vec<vk::DescriptorSet>,
-        render_pass: vk::RenderPass,
-        surface_extent: vk::Extent2D,
-    ) -> Result<Vec<vk::CommandBuffer>> {
-        // recording command buffers
-        CommandBuffer::record_command_to_buffers(
+        Ok(CommandPoolWrapper {
+            pool,
+            stale: vec![true; command_buffers.len()],
+            command_buffers,
+        })
+    }
+
+    // Every buffer is re-recorded before its image is drawn next
+    pub fn mark_stale(&mut self) {
+        self.stale.iter_mut().for_each(|stale| *stale = true);
+    }
+
+    pub fn is_stale(&self, index: usize) -> bool {
+        self.stale.get(index).cloned().unwrap_or(true)
+    }
+
+    pub fn recorded(&mut self, index: usize) {
+        if let Some(stale) = self.stale.get_mut(index) {
+            *stale = false;
+        }
+    }
+
+    // Frees the command buffers as well
+    pub fn destroy(&self, device: &device::Device) {
+        device.untrack(self.pool);
+        unsafe { device.logical_device.destroy_command_pool(self.pool, None) };
+    }
+}
+
+// The scene's mesh pool along with the ranges of it that are drawn. Nothing in it
+// depends on the swapchain, so it is kept when the swapchain is recreated, see
+// BufferDetails::take_mesh.
+pub struct MeshBuffers {
+    pub pool: mesh_pool::MeshPool,
+    // the mesh of EngineConfig::mesh, drawn while there is no draw list
+    scene_mesh: mesh_pool::MeshAllocation,
+    // see set_draws
+    draws: Vec<material::Draw>,
+    // culled against the camera each frame when set, see with_bounds
+    bounds: Option<bounds::MeshBounds>,
+}
+
+impl MeshBuffers {
+    // The pool holds at least the scene mesh, more meshes are added with load_mesh
+    pub fn new<V: pipeline::VertexData + Copy>(
+        device: &device::Device,
+        uploads: &mut upload::UploadManager,
+        vertex_data: &[V],
+        index_data: &[u32],
+        vertex_capacity: u32,
+        index_capacity: u32,
+    ) -> Result<MeshBuffers> {
+        let mut pool = mesh_pool::MeshPool::new::<V>(
             device,
-            command_pool,
-            framebuffers.len() as u32,
-            |i, command_buffer| {
-                let clear_values = [
-                    vk::ClearValue {
-                        color: vk::ClearColorValue {
-                            float32: [0.0, 0.0, 0.0, 1.0],
-                        },
-                    },
-                    vk::ClearValue {
-                        depth_stencil: vk::ClearDepthStencilValue {
-                            depth: 1.0,
-                            stencil: 0,
-                        },
-                    },
-                ];
+            vertex_capacity.max(vertex_data.len() as u32),
+            index_capacity.max(index_data.len() as u32),
+        )?;
+
+        let scene_mesh = pool.allocate(uploads, vertex_data, index_data)?;
+        uploads.flush()?;
+
+        Ok(MeshBuffers {
+            pool,
+            scene_mesh,
+            draws: vec![scene_mesh.draw(0)],
+            bounds: None,
+        })
+    }
+
+    // Uploads another mesh into the pool and waits for it to be copied. The mesh is
+    // drawn once its ranges are part of the draws, eg. through a scene::MeshRenderer.
+    pub fn load_mesh<V: Copy>(
+        &mut self,
+        uploads: &mut upload::UploadManager,
+        vertex_data: &[V],
+        index_data: &[u32],
+    ) -> Result<mesh_pool::MeshAllocation> {
+        let allocation = self.pool.allocate(uploads, vertex_data, index_data)?;
+        uploads.flush()?;
+
+        Ok(allocation)
+    }
+
+    pub fn scene_mesh(&self) -> mesh_pool::MeshAllocation {
+        self.scene_mesh
+    }
+
+    pub fn with_bounds(mut self, bounds: bounds::MeshBounds) -> MeshBuffers {
+        self.bounds = Some(bounds);
+        self
+    }
+
+    // Never culled without bounds or transforms
+    pub fn is_visible(&self, transforms: Option<(Matrix4<f32>, Matrix4<f32>)>) -> bool {
+        match (self.bounds.as_ref(), transforms) {
+            (Some(bounds), Some((model, view_proj))) => {
+                bounds::Frustum::from_matrix(view_proj).is_visible(bounds, model)
+            }
+            _ => true,
+        }
+    }
 
-                let framebuffer = framebuffers[i];
+    pub fn draws(&self) -> &[material::Draw] {
+        &self.draws
+    }
+
+    // Replaces the ranges of the index buffer that are drawn and returns whether they
+    // changed. An empty list draws the scene mesh again.
+    pub fn set_draws(&mut self, draws: Vec<material::Draw>) -> Result<bool> {
+        let draws = if draws.is_empty() {
+            vec![self.scene_mesh.draw(0)]
+        } else {
+            draws
+        };
+
+        let num_indices = self.pool.index_capacity() as usize;
+        if let Some(draw) = draws
+            .iter()
+            .find(|draw| (draw.first_index + draw.index_count) as usize > num_indices)
+        {
+            return Err(Error::OutOfRange(format!(
+                "draw of indices {}..{} is past the {} indices of the mesh pool",
+                draw.first_index,
+                draw.first_index + draw.index_count,
+                num_indices
+            )));
+        }
+
+        if draws == self.draws {
+            return Ok(false);
+        }
+
+        self.draws = draws;
+        Ok(true)
+    }
+
+    pub fn destroy(&self, device: &device::Device) {
+        self.pool.destroy(device);
+    }
+}
+
+// The scene's uniform data with a region of the ring per swapchain image and the lights
+// with a buffer per image, uploaded according to the data's update policy
+pub struct FrameUniforms<T: UniformBuffers> {
+    pub ring: uniform_ring::UniformRing<T::Data>,
+    pub data: T,
+    pub uploads: UniformUploads,
+    pub lighting: lighting::Lighting,
+    light_buffers: lighting::LightBuffers,
+}
+
+impl<T: UniformBuffers> FrameUniforms<T> {
+    pub fn new(device: &device::Device, data: T, num_images: usize) -> Result<FrameUniforms<T>> {
+        let mut ring = uniform_ring::UniformRing::new(device, num_images)?;
+        ring.set_name(device, "scene uniform ring");
+
+        let mut uploads = UniformUploads::new(data.update_policy(), num_images);
+
+        if uploads.policy == UpdatePolicy::Static {
+            for index in 0..num_images {
+                data.upload_buffer(&mut ring, index)?;
+                uploads.uploaded(index);
+            }
+        }
+
+        Ok(FrameUniforms {
+            ring,
+            data,
+            uploads,
+            lighting: lighting::Lighting::default(),
+            light_buffers: lighting::LightBuffers::new(device, num_images)?,
+        })
+    }
+
+    pub fn light_buffers(&self) -> &lighting::LightBuffers {
+        &self.light_buffers
+    }
+
+    // Uploads the uniform data of the image according to the update policy
+    pub fn update(
+        &mut self,
+        device: &ash::Device,
+        frame: &frame::FrameContext,
+        delta_time: f32,
+    ) -> Result<()> {
+        self.light_buffers.update(device, frame, &self.lighting)?;
+
+        let image_index = frame.image_index() as usize;
+        if !self.uploads.needs_upload(image_index) {
+            return Ok(());
+        }
+
+        match self.uploads.policy {
+            UpdatePolicy::PerFrame => {
+                self.data
+                    .update_buffer(&mut self.ring, image_index, delta_time)?
+            }
+            UpdatePolicy::OnDemand | UpdatePolicy::Static => {
+                self.data.upload_buffer(&mut self.ring, image_index)?
+            }
+        }
+
+        self.uploads.uploaded(image_index);
+        Ok(())
+    }
+
+    // Replaces the uniform data, it is uploaded to each image's region the next time it is
+    // used. Static data is not uploaded again, see UpdatePolicy::Static.
+    pub fn set_data(&mut self, data: T) {
+        self.data = data;
+        self.uploads.mark_dirty();
+    }
+
+    // Replaces the lights of the scene, uploaded the same way as the uniform data
+    pub fn set_lighting(&mut self, lighting: lighting::Lighting) {
+        self.lighting = lighting;
+        self.light_buffers.mark_dirty();
+    }
 
+    pub fn destroy(&self, device: &device::Device) {
+        self.ring.destroy(device);
+        self.light_buffers.destroy(device);
+    }
+}
+
+// The scene pass of the swapchain images: what it draws, where to and with which
+// commands, each part owned by one of the types above
+pub struct BufferDetails<T: UniformBuffers> {
+    pub framebuffers: FramebufferSet,
+    pub commands: CommandPoolWrapper,
+    // only None once taken to be reused, see take_mesh
+    mesh: Option<MeshBuffers>,
+    pub uniforms: FrameUniforms<T>,
+    pub profiler: profiler::Profiler,
+
+    pub pipeline: pipeline::PipelineDetail,
+    pub permutations: Option<permutation::PermutationManager>,
+    // begins the scene pass when the pipeline has no render pass
+    rendering: Option<dynamic_rendering::DynamicRendering>,
+    // drawn instead of the scene pipeline when set, see set_deferred
+    deferred: Option<deferred::DeferredPass>,
+    // skinned before the scene pass and drawn by it, see set_skinning
+    skinning: Option<compute_skinning::ComputeSkinning>,
+    // whether the mesh is drawn by each image's recorded commands
+    recorded_visible: Vec<bool>,
+    descriptor_sets: Vec<vk::DescriptorSet>,
+    // per image, one offset for each dynamic binding of the scene set in binding order
+    dynamic_offsets: Vec<Vec<u32>>,
+    // clear values and viewport of the scene pass, see set_render_settings
+    render_settings: render_settings::RenderSettings,
+    texture: texture::Texture,
+    descriptor_pool: vk::DescriptorPool,
+}
+
+// Where the scene pass of one swapchain image is drawn into
+enum ScenePass<'a> {
+    RenderPass {
+        render_pass: vk::RenderPass,
+        framebuffer: vk::Framebuffer,
+    },
+    Dynamic {
+        rendering: &'a dynamic_rendering::DynamicRendering,
+        target: &'a dynamic_rendering::RenderTarget,
+    },
+}
+
+impl<'a> ScenePass<'a> {
+    fn begin(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        extent: vk::Extent2D,
+        clear_values: &[vk::ClearValue; 2],
+    ) {
+        match *self {
+            ScenePass::RenderPass {
+                render_pass,
+                framebuffer,
+            } => {
                 let render_pass_begin_info = vk::RenderPassBeginInfo {
                     render_pass,
-                    framebuffer: framebuffer,
+                    framebuffer,
                     render_area: vk::Rect2D {
                         offset: vk::Offset2D { x: 0, y: 0 },
-                        extent: surface_extent,
+                        extent,
                     },
                     clear_value_count: clear_values.len() as u32,
                     p_clear_values: clear_values.as_ptr(),
                     ..Default::default()
                 };
 
-                let vertex_buffers = [vertex_buffer.buffer];
-                let offsets = [0_u64];
-                let descriptor_sets = [descriptor_sets[i]];
-
-                // render pass
                 unsafe {
                     device.cmd_begin_render_pass(
                         command_buffer,
                         &render_pass_begin_info,
                         vk::SubpassContents::INLINE,
-                    );
+                    )
+                };
+            }
+            ScenePass::Dynamic { rendering, target } => {
+                rendering.begin(device, command_buffer, target, extent, clear_values)
+            }
+        }
+    }
 
-                    device.cmd_bind_pipeline(
-                        command_buffer,
-                        vk::PipelineBindPoint::GRAPHICS,
-                        pipeline.pipeline,
-                    );
+    fn end(&self, device: &ash::Device, command_buffer: vk::CommandBuffer) {
+        match *self {
+            ScenePass::RenderPass { .. } => unsafe { device.cmd_end_render_pass(command_buffer) },
+            ScenePass::Dynamic { rendering, target } => {
+                rendering.end(device, command_buffer, target)
+            }
+        }
+    }
+}
 
-                    device.cmd_bind_vertex_buffers(command_buffer, 0, &vertex_buffers, &offsets);
-                    device.cmd_bind_index_buffer(
-                        command_buffer,
-                        index_buffer.buffer,
-                        0,
-                        vk::IndexType::UINT32,
-                    );
-                    device.cmd_bind_descriptor_sets(
+impl<T: UniformBuffers> BufferDetails<T> {
+    // Records the scene pass of one swapchain image with the given pipeline
+    fn record_scene_commands(
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        index: u32,
+        pipeline: vk::Pipeline,
+        pipeline_layout: vk::PipelineLayout,
+        pass: ScenePass,
+        vertex_buffer: &VertexBuffer,
+        index_buffer: &IndexBuffer,
+        descriptor_set: vk::DescriptorSet,
+        dynamic_offsets: &[u32],
+        draws: &[material::Draw],
+        skinning: Option<&compute_skinning::ComputeSkinning>,
+        surface_extent: vk::Extent2D,
+        settings: &render_settings::RenderSettings,
+        profiler: &profiler::Profiler,
+        draw_mesh: bool,
+    ) {
+        let clear_values = settings.clear_values();
+
+        let vertex_buffers = [vertex_buffer.buffer];
+        let offsets = [0_u64];
+        let descriptor_sets = [descriptor_set];
+
+        let viewports = [settings.viewport(surface_extent)];
+        let scissors = [settings.scissor(surface_extent)];
+
+        profiler.cmd_begin(device, command_buffer, index);
+
+        pass.begin(device, command_buffer, surface_extent, &clear_values);
+
+        // a culled mesh still needs the pass to clear the image
+        if !draw_mesh && skinning.is_none() {
+            pass.end(device, command_buffer);
+            profiler.cmd_end(device, command_buffer, index);
+            return;
+        }
+
+        unsafe {
+            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+
+            device.cmd_set_viewport(command_buffer, 0, &viewports);
+            device.cmd_set_scissor(command_buffer, 0, &scissors);
+
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                pipeline_layout,
+                0,
+                &descriptor_sets,
+                dynamic_offsets,
+            );
+        }
+
+        if draw_mesh {
+            unsafe {
+                device.cmd_bind_vertex_buffers(command_buffer, 0, &vertex_buffers, &offsets);
+                device.cmd_bind_index_buffer(
+                    command_buffer,
+                    index_buffer.buffer(),
+                    0,
+                    index_buffer.index_type(),
+                );
+
+                for draw in draws.iter() {
+                    device.cmd_draw_indexed(
                         command_buffer,
-                        vk::PipelineBindPoint::GRAPHICS,
-                        pipeline.layout,
+                        draw.index_count,
+                        draw.instance_count,
+                        draw.first_index,
+                        draw.vertex_offset,
                         0,
-                        &descriptor_sets,
-                        &[],
                     );
+                }
+            }
+        }
 
-                    // todo replace hard coded 6 with with index_buffer data size
-                    device.cmd_draw_indexed(command_buffer, 12u32, 1, 0, 0, 0);
+        // the image's frame of skinned vertices, written before the pass began
+        if let Some(skinning) = skinning {
+            skinning.record_draws(device, command_buffer, index);
+        }
 
-                    device.cmd_end_render_pass(command_buffer);
-                }
-            },
-        )
+        pass.end(device, command_buffer);
+
+        profiler.cmd_end(device, command_buffer, index);
     }
 
     pub fn new(
@@ -670,82 +1417,567 @@ impl<T: UniformBuffers> BufferDetails<T> {
         device: &device::Device,
         graphics_queue: vk::Queue,
         pipeline: pipeline::PipelineDetail,
+        target: pipeline::ColorTarget,
         swapchain_details: &swapchain::SwapchainDetails,
-        vertex_data: Vec<impl pipeline::VertexData>,
-        index_data: Vec<u32>,
+        // one per swapchain image, the swapchain's images or offscreen targets
+        color_images: &[vk::Image],
+        color_views: &[vk::ImageView],
+        mesh: MeshBuffers,
         uniform_buffer_data: T,
         texture_image: &Path,
     ) -> Result<BufferDetails<T>> {
         let logical_device = &device.logical_device;
-        let render_pass = pipeline.render_pass;
-
-        println!(
-            "num of swapchain images are: {}",
-            swapchain_details.image_views.len()
-        );
-
-        let command_pool = BufferDetails::<T>::create_command_pool(device)?;
+        let num_images = color_views.len();
 
-        let vertex_buffer =
-            BufferInfo::create_vertex_buffer(device, command_pool, graphics_queue, &vertex_data)?;
+        tracing::debug!("num of swapchain images are: {}", num_images);
 
-        let index_buffer = BufferInfo::create_index_buffer(
-            device,
-            command_pool,
-            graphics_queue,
-            index_data.as_slice(),
-        )?;
+        let commands = CommandPoolWrapper::new(device, num_images, "scene")?;
 
-        let depth_buffer = DepthBuffer::new(
+        let framebuffers = FramebufferSet::new(
             instance,
             device,
-            command_pool,
-            &graphics_queue,
+            commands.pool,
+            graphics_queue,
+            &pipeline,
+            target,
+            color_images,
+            color_views,
             swapchain_details.extent,
         )?;
 
-        let framebuffers = BufferDetails::<T>::create_framebuffers(
-            logical_device,
-            render_pass,
-            &swapchain_details.image_views,
-            swapchain_details.extent,
-            depth_buffer,
-        )?;
+        let rendering = match pipeline.rendering {
+            Some(_) => Some(dynamic_rendering::DynamicRendering::new(instance, device)?),
+            None => None,
+        };
 
-        let uniform_buffers = (0..framebuffers.len())
-            .map(|_| uniform_buffer_data.create(&device))
-            .collect::<Result<Vec<BufferInfo>>>()?;
+        let uniforms = FrameUniforms::new(device, uniform_buffer_data, num_images)?;
 
         let texture_data =
-            texture::Texture::new(device, command_pool, graphics_queue, texture_image)?;
+            texture::Texture::new(device, commands.pool, graphics_queue, texture_image)?;
 
-        let descriptor_sets = uniform_buffer_data.create_descriptor_sets(
+        let (descriptor_pool, descriptor_sets) = uniforms.data.create_descriptor_sets(
             logical_device,
             pipeline.descriptor_set_layout,
-            &uniform_buffers,
-            texture_data,
+            &pipeline.descriptor_bindings,
+            &uniforms.ring,
+            &uniforms.light_buffers().buffers,
+            &texture_data,
         )?;
 
-        let command_buffers = BufferDetails::<T>::create_command_buffers(
+        let profiler = profiler::Profiler::new(
+            instance,
+            device.physical_device,
             logical_device,
-            command_pool,
-            pipeline,
-            &framebuffers,
-            &vertex_buffer,
-            &index_buffer,
-            descriptor_sets,
-            render_pass,
-            swapchain_details.extent,
+            num_images as u32,
         )?;
 
+        // the start of the buffer is valid for any dynamic binding until one is bound,
+        // the transforms are read from the image's region of the ring
+        let dynamic_bindings = descriptor::dynamic_bindings(&pipeline.descriptor_bindings);
+        let ring_position = dynamic_bindings
+            .iter()
+            .position(|dynamic| dynamic.binding == 0)
+            .ok_or_else(|| Error::msg("the scene uniforms are not a dynamic binding"))?;
+
+        let dynamic_offsets = uniforms
+            .ring
+            .region_offsets()?
+            .into_iter()
+            .map(|ring_offset| {
+                let mut offsets = vec![0; dynamic_bindings.len()];
+                offsets[ring_position] = ring_offset;
+                offsets
+            })
+            .collect::<Vec<Vec<u32>>>();
+
+        device.track(registry::ResourceKind::DescriptorPool, descriptor_pool);
+        device.name_resource(descriptor_pool, "scene descriptor pool");
+        texture_data.image_data.set_name(device, "scene texture");
+
         Ok(BufferDetails {
             framebuffers,
-            command_pool,
-            command_buffers,
-            vertex_buffer,
-            index_buffer,
-            uniform_buffers,
-            uniform_buffer_data,
+            commands,
+            mesh: Some(mesh),
+            uniforms,
+            profiler,
+            pipeline,
+            permutations: None,
+            rendering,
+            deferred: None,
+            skinning: None,
+            recorded_visible: vec![true; num_images],
+            descriptor_sets,
+            dynamic_offsets,
+            render_settings: render_settings::RenderSettings::default(),
+            texture: texture_data,
+            descriptor_pool,
+        })
+    }
+
+    pub fn extent(&self) -> vk::Extent2D {
+        self.framebuffers.extent()
+    }
+
+    pub fn mesh(&self) -> Result<&MeshBuffers> {
+        self.mesh
+            .as_ref()
+            .ok_or_else(|| Error::msg("the scene mesh was taken"))
+    }
+
+    pub fn mesh_mut(&mut self) -> Result<&mut MeshBuffers> {
+        self.mesh
+            .as_mut()
+            .ok_or_else(|| Error::msg("the scene mesh was taken"))
+    }
+
+    // Moves the mesh out before the rest is destroyed, to be passed to the BufferDetails
+    // of the recreated swapchain along with every mesh loaded into its pool
+    pub fn take_mesh(&mut self) -> Option<MeshBuffers> {
+        self.mesh.take()
+    }
+
+    pub fn draws(&self) -> &[material::Draw] {
+        self.mesh.as_ref().map_or(&[], |mesh| mesh.draws())
+    }
+
+    // Replaces the ranges of the index buffer the scene pass draws, eg. the draw list of a
+    // scene::World. Every image's commands are re-recorded before it is drawn next if the
+    // draws changed. An empty list draws the whole index buffer again.
+    pub fn set_draws(&mut self, draws: Vec<material::Draw>) -> Result<()> {
+        if self.mesh_mut()?.set_draws(draws)? {
+            self.commands.mark_stale();
+        }
+
+        Ok(())
+    }
+
+    // Shades the scene with the G-buffer subpasses of the pass from now on. Debug views
+    // and the wireframe mode only apply to the forward pipeline. Every image's commands
+    // are re-recorded before it is drawn next.
+    pub fn set_deferred(&mut self, device: &device::Device, pass: deferred::DeferredPass) {
+        if let Some(old) = self.deferred.replace(pass) {
+            old.destroy(device);
+        }
+        self.commands.mark_stale();
+    }
+
+    pub fn is_deferred(&self) -> bool {
+        self.deferred.is_some()
+    }
+
+    // Skins the instances at the start of every image's commands and draws them after the
+    // mesh, with the scene pipeline and one skinning frame per image. Only the forward
+    // pipeline draws them. Every image's commands are re-recorded before it is drawn next.
+    pub fn set_skinning(
+        &mut self,
+        device: &device::Device,
+        skinning: compute_skinning::ComputeSkinning,
+    ) -> Result<()> {
+        let image_count = self.commands.command_buffers.len() as u32;
+        if skinning.frame_count() < image_count {
+            let frame_count = skinning.frame_count();
+            skinning.destroy(device);
+            return Err(Error::OutOfRange(format!(
+                "the skinning pass has {} frames for {} swapchain images",
+                frame_count, image_count
+            )));
+        }
+
+        if let Some(old) = self.skinning.replace(skinning) {
+            old.destroy(device);
+        }
+        self.commands.mark_stale();
+        Ok(())
+    }
+
+    pub fn skinning(&self) -> Option<&compute_skinning::ComputeSkinning> {
+        self.skinning.as_ref()
+    }
+
+    // Every image's commands are re-recorded before it is drawn next, to pick up added or
+    // removed instances
+    pub fn skinning_mut(&mut self) -> Option<&mut compute_skinning::ComputeSkinning> {
+        self.commands.mark_stale();
+        self.skinning.as_mut()
+    }
+
+    // Posing does not change the recorded commands, the poses are uploaded every frame
+    pub fn pose_skinned_instance(
+        &mut self,
+        instance: &compute_skinning::SkinnedInstance,
+        palette: &skinning::BonePalette,
+    ) -> Result<()> {
+        self.skinning
+            .as_mut()
+            .ok_or_else(|| Error::msg(SKINNING_DISABLED))?
+            .pose(instance, palette)
+    }
+
+    // Moves the skinning pass out before the rest is destroyed, see take_mesh
+    pub fn take_skinning(&mut self) -> Option<compute_skinning::ComputeSkinning> {
+        self.skinning.take()
+    }
+
+    // Uploads the poses to the image's palettes once its previous submission completed
+    pub fn update_skinning(&self, device: &ash::Device, frame: &frame::FrameContext) -> Result<()> {
+        match self.skinning.as_ref() {
+            Some(skinning) => skinning.upload_poses(device, frame.image_index()),
+            None => Ok(()),
+        }
+    }
+
+    pub fn render_settings(&self) -> render_settings::RenderSettings {
+        self.render_settings
+    }
+
+    // Every image's commands are re-recorded with the settings before it is drawn next
+    // if they changed, so they can be set every frame
+    pub fn set_render_settings(&mut self, settings: render_settings::RenderSettings) {
+        if settings != self.render_settings {
+            self.render_settings = settings;
+            self.commands.mark_stale();
+        }
+    }
+
+    // Skips drawing the mesh while its bounds are outside of the camera frustum
+    pub fn with_bounds(mut self, bounds: bounds::MeshBounds) -> BufferDetails<T> {
+        self.mesh = self.mesh.map(|mesh| mesh.with_bounds(bounds));
+        self
+    }
+
+    pub fn is_mesh_visible(&self) -> bool {
+        self.mesh.as_ref().map_or(false, |mesh| {
+            mesh.is_visible(self.uniforms.data.transforms())
         })
     }
+
+    // The device must be idle, none of the buffers may be in use anymore
+    pub fn destroy(&mut self, device: &device::Device) {
+        let logical_device = &device.logical_device;
+
+        self.profiler.destroy(logical_device);
+
+        if let Some(permutations) = self.permutations.as_mut() {
+            permutations.destroy(logical_device);
+        }
+
+        if let Some(deferred) = self.deferred.take() {
+            deferred.destroy(device);
+        }
+
+        if let Some(skinning) = self.skinning.take() {
+            skinning.destroy(device);
+        }
+
+        self.commands.destroy(device);
+        self.framebuffers.destroy(device);
+
+        device.untrack(self.descriptor_pool);
+        unsafe { logical_device.destroy_descriptor_pool(self.descriptor_pool, None) };
+
+        self.uniforms.destroy(device);
+        if let Some(mesh) = self.mesh.take() {
+            mesh.destroy(device);
+        }
+
+        self.texture.destroy(device);
+        self.pipeline.destroy(device);
+    }
+
+    // Replaces the uniform data, it is uploaded to each image's buffer the next time it is
+    // used. Static data is not uploaded again, see UpdatePolicy::Static.
+    pub fn set_uniform_data(&mut self, data: T) {
+        self.uniforms.set_data(data);
+    }
+
+    // Switches the shader permutation used for the scene. The pipeline is compiled on
+    // first use and every image's commands are re-recorded before it is drawn next.
+    pub fn set_debug_view(
+        &mut self,
+        device: &ash::Device,
+        view: permutation::DebugView,
+    ) -> Result<()> {
+        let permutations = self
+            .permutations
+            .as_mut()
+            .ok_or_else(|| Error::msg("debug views need a permutation manager"))?;
+
+        if permutations.current != view {
+            permutations.select(device, view)?;
+            self.commands.mark_stale();
+        }
+
+        Ok(())
+    }
+
+    // Switches the scene between filled and wireframe rendering, every image's commands
+    // are re-recorded before it is drawn next
+    pub fn set_polygon_mode(&mut self, polygon_mode: vk::PolygonMode) -> Result<()> {
+        if self.pipeline.polygon_mode != polygon_mode {
+            self.pipeline.set_polygon_mode(polygon_mode)?;
+            self.commands.mark_stale();
+        }
+
+        Ok(())
+    }
+
+    // Recreates the scene pipeline and its debug view permutations with the state,
+    // every image's commands are re-recorded before it is drawn next
+    pub fn set_pipeline_state(
+        &mut self,
+        device: &device::Device,
+        state: preset::FixedFunctionState,
+        garbage: &mut gc::GarbageCollector,
+    ) -> Result<()> {
+        if self.pipeline.state == state.with_polygon_mode(vk::PolygonMode::FILL) {
+            return Ok(());
+        }
+
+        self.pipeline.rebuild(device, state, garbage)?;
+
+        if let Some(permutations) = self.permutations.as_mut() {
+            permutations.rebuild(&device.logical_device, &self.pipeline, garbage)?;
+        }
+
+        self.commands.mark_stale();
+
+        Ok(())
+    }
+
+    // Compiles the scene shaders again and recreates the pipeline and its permutations from
+    // them, eg. after the files changed. On a compile error the current pipeline is kept.
+    pub fn reload_shaders(
+        &mut self,
+        device: &device::Device,
+        garbage: &mut gc::GarbageCollector,
+    ) -> Result<()> {
+        let state = self.pipeline.state;
+        self.pipeline.rebuild(device, state, garbage)?;
+
+        if let Some(permutations) = self.permutations.as_mut() {
+            permutations.rebuild(&device.logical_device, &self.pipeline, garbage)?;
+        }
+
+        self.commands.mark_stale();
+
+        Ok(())
+    }
+
+    // Samples the texture in the scene set from now on and destroys the old one. The sets
+    // are written in place, see write_buffer_binding for when it can be called.
+    pub fn replace_texture(
+        &mut self,
+        device: &device::Device,
+        texture: texture::Texture,
+    ) -> Result<()> {
+        let binding = *descriptor::find(&self.pipeline.descriptor_bindings, 1)?;
+        let image_infos = [vk::DescriptorImageInfo {
+            sampler: texture.sampler,
+            image_view: texture.image_data.image_view,
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        }];
+
+        let descriptor_writes = self
+            .descriptor_sets
+            .iter()
+            .map(|&set| binding.write_images(set, &image_infos))
+            .collect::<Result<Vec<vk::WriteDescriptorSet>>>()?;
+
+        unsafe {
+            device
+                .logical_device
+                .update_descriptor_sets(&descriptor_writes, &[])
+        };
+
+        texture.image_data.set_name(device, "scene texture");
+        std::mem::replace(&mut self.texture, texture).destroy(device);
+        self.commands.mark_stale();
+
+        Ok(())
+    }
+
+    // Re-records the image's scene commands with the current pipeline if they are outdated
+    // or the mesh moved in or out of view. Must only be called once the previous frame
+    // using this image has completed.
+    pub fn record_frame_commands(
+        &mut self,
+        device: &ash::Device,
+        frame: &frame::FrameContext,
+    ) -> Result<()> {
+        let image_index = frame.image_index() as usize;
+        let visible = self.is_mesh_visible();
+        if !self.commands.is_stale(image_index)
+            && *frame.per_image(&self.recorded_visible)? == visible
+        {
+            return Ok(());
+        }
+
+        // the debug view permutations only exist as filled pipelines
+        let pipeline = match (self.pipeline.polygon_mode, self.permutations.as_ref()) {
+            (vk::PolygonMode::FILL, Some(permutations)) => permutations.current_pipeline(),
+            _ => self.pipeline.current_pipeline(),
+        };
+
+        let command_buffer = *frame.per_image(&self.commands.command_buffers)?;
+        let mesh = self.mesh()?;
+
+        let pass = match self.rendering.as_ref() {
+            Some(rendering) => ScenePass::Dynamic {
+                rendering,
+                target: frame.per_image(&self.framebuffers.targets)?,
+            },
+            None => ScenePass::RenderPass {
+                render_pass: self.pipeline.render_pass,
+                framebuffer: *frame.per_image(&self.framebuffers.framebuffers)?,
+            },
+        };
+
+        unsafe {
+            device
+                .begin_command_buffer(command_buffer, &vk::CommandBufferBeginInfo::default())
+                .context("failed to begin recording command buffer")
+        }?;
+
+        // outside of the pass, its barrier keeps the scene pass from reading the vertices
+        // before they are written
+        if let Some(skinning) = self.skinning.as_ref() {
+            skinning.record(device, command_buffer, frame.image_index());
+        }
+
+        match self.deferred.as_ref() {
+            Some(deferred) => deferred.record(
+                device,
+                command_buffer,
+                frame.image_index(),
+                self.pipeline.layout,
+                *frame.per_image(&self.descriptor_sets)?,
+                frame.per_image(&self.dynamic_offsets)?,
+                &mesh.pool,
+                mesh.draws(),
+                self.framebuffers.extent(),
+                &self.render_settings,
+                &self.profiler,
+                visible,
+            )?,
+            None => BufferDetails::<T>::record_scene_commands(
+                device,
+                command_buffer,
+                frame.image_index(),
+                pipeline,
+                self.pipeline.layout,
+                pass,
+                &mesh.pool.vertex_buffer,
+                &mesh.pool.index_buffer,
+                *frame.per_image(&self.descriptor_sets)?,
+                frame.per_image(&self.dynamic_offsets)?,
+                mesh.draws(),
+                self.skinning.as_ref(),
+                self.framebuffers.extent(),
+                &self.render_settings,
+                &self.profiler,
+                visible,
+            ),
+        }
+
+        unsafe {
+            device
+                .end_command_buffer(command_buffer)
+                .context("failed to end command buffer recording")
+        }?;
+
+        self.commands.recorded(image_index);
+        self.recorded_visible[image_index] = visible;
+        Ok(())
+    }
+
+    // Points a binding of the scene set at the buffer range in every set, and returns it
+    // if it has the expected type. The sets are written in place, so no frame may be in
+    // flight, eg. bind the buffers before the first frame or once the device is idle.
+    fn write_buffer_binding(
+        &mut self,
+        device: &ash::Device,
+        binding: u32,
+        descriptor_type: vk::DescriptorType,
+        buffer_info: vk::DescriptorBufferInfo,
+    ) -> Result<descriptor::Binding> {
+        let binding = *descriptor::find(&self.pipeline.descriptor_bindings, binding)?;
+        if binding.descriptor_type != descriptor_type {
+            return Err(Error::msg(format!(
+                "binding {} of the scene set is {:?}, not {:?}",
+                binding.binding, binding.descriptor_type, descriptor_type
+            )));
+        }
+
+        let buffer_infos = [buffer_info];
+        let descriptor_writes = self
+            .descriptor_sets
+            .iter()
+            .map(|&set| binding.write_buffers(set, &buffer_infos))
+            .collect::<Result<Vec<vk::WriteDescriptorSet>>>()?;
+
+        unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+
+        // updating a bound set invalidates the command buffers it was recorded in
+        self.commands.mark_stale();
+
+        Ok(binding)
+    }
+
+    // See write_buffer_binding for when it can be called
+    pub fn bind_storage_buffer(
+        &mut self,
+        device: &ash::Device,
+        binding: u32,
+        buffer_info: vk::DescriptorBufferInfo,
+    ) -> Result<()> {
+        self.write_buffer_binding(
+            device,
+            binding,
+            vk::DescriptorType::STORAGE_BUFFER,
+            buffer_info,
+        )
+        .map(|_| ())
+    }
+
+    // Binds a dynamic uniform buffer, the mesh is drawn with the data at the image's offset,
+    // eg. object_uniforms::ObjectUniforms::image_offsets. See write_buffer_binding for when
+    // it can be called.
+    pub fn bind_dynamic_uniform_buffer(
+        &mut self,
+        device: &ash::Device,
+        binding: u32,
+        buffer_info: vk::DescriptorBufferInfo,
+        image_offsets: &[u32],
+    ) -> Result<()> {
+        if image_offsets.len() != self.dynamic_offsets.len() {
+            return Err(Error::OutOfRange(format!(
+                "got {} dynamic offsets for {} images",
+                image_offsets.len(),
+                self.dynamic_offsets.len()
+            )));
+        }
+
+        let binding = self.write_buffer_binding(
+            device,
+            binding,
+            vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+            buffer_info,
+        )?;
+
+        let position = descriptor::dynamic_bindings(&self.pipeline.descriptor_bindings)
+            .iter()
+            .position(|dynamic| dynamic.binding == binding.binding)
+            .ok_or_else(|| Error::msg("dynamic binding missing from the scene set"))?;
+
+        for (offsets, &offset) in self.dynamic_offsets.iter_mut().zip(image_offsets) {
+            offsets[position] = offset;
+        }
+
+        Ok(())
+    }
+
+    // Replaces the lights of the scene, uploaded the same way as the uniform data
+    pub fn set_lighting(&mut self, lighting: lighting::Lighting) {
+        self.uniforms.set_lighting(lighting);
+    }
 }
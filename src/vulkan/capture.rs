@@ -0,0 +1,97 @@
+// In-application frame captures with RenderDoc.
+// With the `renderdoc-capture` feature the api is picked up when the application was launched
+// or injected from RenderDoc, the frame after a capture was requested is then recorded
+// from the first vulkan call to the present. Without the feature nothing can be captured.
+
+use crate::error::{Error, Result};
+
+#[cfg(feature = "renderdoc-capture")]
+use renderdoc::{RenderDoc, V110};
+
+#[cfg(feature = "renderdoc-capture")]
+pub struct FrameCapture {
+    api: Option<RenderDoc<V110>>,
+    pending: bool,
+    capturing: bool,
+}
+
+#[cfg(feature = "renderdoc-capture")]
+impl FrameCapture {
+    pub fn new() -> FrameCapture {
+        let api = match RenderDoc::<V110>::new() {
+            Ok(api) => Some(api),
+            Err(err) => {
+                tracing::info!(
+                    "renderdoc is not attached, captures are disabled: {:?}",
+                    err
+                );
+                None
+            }
+        };
+
+        FrameCapture {
+            api,
+            pending: false,
+            capturing: false,
+        }
+    }
+
+    pub fn is_available(&self) -> bool {
+        self.api.is_some()
+    }
+
+    // Captures the next frame that is rendered
+    pub fn capture_next_frame(&mut self) -> Result<()> {
+        if self.api.is_none() {
+            return Err(Error::Unsupported(
+                "renderdoc is not attached to the application".to_string(),
+            ));
+        }
+
+        self.pending = true;
+        Ok(())
+    }
+
+    pub fn begin_frame(&mut self) {
+        if let (true, Some(api)) = (self.pending, self.api.as_mut()) {
+            // null device and window capture whichever the frame is presented on
+            api.start_frame_capture(std::ptr::null(), std::ptr::null());
+            self.pending = false;
+            self.capturing = true;
+        }
+    }
+
+    // Has to be called even if the frame failed, otherwise the capture never ends
+    pub fn end_frame(&mut self) {
+        if let (true, Some(api)) = (self.capturing, self.api.as_mut()) {
+            api.end_frame_capture(std::ptr::null(), std::ptr::null());
+            self.capturing = false;
+        }
+    }
+}
+
+#[cfg(not(feature = "renderdoc-capture"))]
+pub struct FrameCapture;
+
+#[cfg(not(feature = "renderdoc-capture"))]
+impl FrameCapture {
+    pub fn new() -> FrameCapture {
+        FrameCapture
+    }
+
+    pub fn is_available(&self) -> bool {
+        false
+    }
+
+    pub fn capture_next_frame(&mut self) -> Result<()> {
+        Err(Error::Unsupported(
+            "captures need kelsier to be built with the renderdoc-capture feature".to_string(),
+        ))
+    }
+
+    #[inline(always)]
+    pub fn begin_frame(&mut self) {}
+
+    #[inline(always)]
+    pub fn end_frame(&mut self) {}
+}
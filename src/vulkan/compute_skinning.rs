@@ -0,0 +1,769 @@
+use ash::version::DeviceV1_0;
+use ash::vk;
+
+use crate::error::{Context, Error, Result};
+
+use std::ffi::CString;
+
+use crate::app;
+use crate::shaderc;
+
+use super::buffers;
+use super::descriptor;
+use super::device;
+use super::pipeline;
+use super::registry;
+use super::skinning;
+use super::typed_buffer;
+
+pub const SKINNING_SHADER_FILE: &'static str = "shaders/skinning.comp";
+
+// local_size_x of the compute shader
+const WORKGROUP_SIZE: u32 = 64;
+
+// size of app::VertexData, the skinned vertices are tightly packed
+const OUTPUT_STRIDE: vk::DeviceSize = std::mem::size_of::<app::VertexData>() as vk::DeviceSize;
+
+// Bind pose vertices and indices of a mesh added with ComputeSkinning::add_mesh. The
+// indices are relative to the mesh's first vertex.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SkinnedMesh {
+    pub first_vertex: u32,
+    pub vertex_count: u32,
+    pub first_index: u32,
+    pub index_count: u32,
+}
+
+// An animated copy of a mesh with a palette of its own. Its skinned vertices start at
+// first_output_vertex of the frame's vertex buffer, the vertex_offset of its draw.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SkinnedInstance {
+    pub index: u32,
+    pub mesh: SkinnedMesh,
+    pub first_output_vertex: u32,
+}
+
+// Laid out to match the Instances buffer of shaders/skinning.comp
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct InstanceData {
+    first_vertex: u32,
+    vertex_count: u32,
+    first_output_vertex: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct PushConstants {
+    instance_count: u32,
+    first_palette: u32,
+    first_output_vertex: u32,
+}
+
+fn as_bytes<T>(value: &T) -> &[u8] {
+    unsafe {
+        ::std::slice::from_raw_parts(value as *const T as *const u8, ::std::mem::size_of::<T>())
+    }
+}
+
+// Joints past the palette would be read from the next instance's
+fn check_joints(vertices: &[skinning::SkinnedVertex]) -> Result<()> {
+    match vertices
+        .iter()
+        .flat_map(|vertex| vertex.joints.iter())
+        .find(|joint| **joint as usize >= skinning::MAX_JOINTS)
+    {
+        Some(joint) => Err(Error::OutOfRange(format!(
+            "skeletons have up to {} joints, a vertex is bound to joint {}",
+            skinning::MAX_JOINTS,
+            joint
+        ))),
+        None => Ok(()),
+    }
+}
+
+// One row of workgroups per instance, as wide as the largest mesh
+fn workgroup_count(max_vertex_count: u32, instance_count: u32) -> (u32, u32) {
+    (
+        (max_vertex_count + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE,
+        instance_count,
+    )
+}
+
+// The first palette and skinned vertex of the frame's region of the buffers, frames past
+// frame_count wrap around
+fn frame_offsets(
+    frame: u32,
+    frame_count: u32,
+    instance_capacity: u32,
+    output_capacity: u32,
+) -> (u32, u32) {
+    let frame = frame % frame_count;
+    (frame * instance_capacity, frame * output_capacity)
+}
+
+// Skinning on the gpu as an alternative to shaders/skinned.vert. A compute pass blends
+// the bind pose of every instance with its palette once per frame and writes
+// app::VertexData into a storage buffer, which is then bound as a plain vertex buffer,
+// eg. with shaders/shader.vert. Shadow and scene passes draw the same skinned vertices
+// instead of skinning them again, and crowds are skinned in a single dispatch.
+// Every frame has its own palettes and vertices, so a frame is skinned while the previous
+// one is still drawn. The scene pass uses one frame per swapchain image, see
+// buffers::BufferDetails::set_skinning.
+pub struct ComputeSkinning {
+    // bind poses and their indices, device local and written through a staging copy
+    source: buffers::BufferInfo,
+    indices: buffers::BufferInfo,
+    instance_data: typed_buffer::StorageBuffer<InstanceData>,
+    palettes: typed_buffer::StorageBuffer<skinning::BonePalette>,
+    output: buffers::BufferInfo,
+
+    vertex_capacity: u32,
+    index_capacity: u32,
+    instance_capacity: u32,
+    // skinned vertices per frame
+    output_capacity: u32,
+    frame_count: u32,
+
+    vertex_count: u32,
+    index_count: u32,
+    output_vertex_count: u32,
+    max_vertex_count: u32,
+    instances: Vec<SkinnedInstance>,
+    // uploaded to the palettes of the frame being drawn, see upload_poses
+    poses: Vec<skinning::BonePalette>,
+
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set: vk::DescriptorSet,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+impl ComputeSkinning {
+    fn bindings() -> [descriptor::Binding; 4] {
+        [
+            descriptor::Binding::storage_buffer(0, vk::ShaderStageFlags::COMPUTE),
+            descriptor::Binding::storage_buffer(1, vk::ShaderStageFlags::COMPUTE),
+            descriptor::Binding::storage_buffer(2, vk::ShaderStageFlags::COMPUTE),
+            descriptor::Binding::storage_buffer(3, vk::ShaderStageFlags::COMPUTE),
+        ]
+    }
+
+    fn create_pipeline(
+        device: &ash::Device,
+        pipeline_layout: vk::PipelineLayout,
+    ) -> Result<vk::Pipeline> {
+        let code = shaderc::ShaderSource::compile_compute(&SKINNING_SHADER_FILE.to_string())?;
+        let shader_module = pipeline::PipelineDetail::create_shader_module(device, code)?;
+
+        let main_function_name = CString::new("main").context("invalid fn name")?;
+
+        let pipeline_info = vk::ComputePipelineCreateInfo {
+            stage: vk::PipelineShaderStageCreateInfo {
+                module: shader_module,
+                p_name: main_function_name.as_ptr(),
+                stage: vk::ShaderStageFlags::COMPUTE,
+                ..Default::default()
+            },
+            layout: pipeline_layout,
+            base_pipeline_index: -1,
+            ..Default::default()
+        };
+
+        let pipelines = unsafe {
+            device.create_compute_pipelines(vk::PipelineCache::null(), &[pipeline_info], None)
+        };
+
+        unsafe { device.destroy_shader_module(shader_module, None) };
+
+        pipelines
+            .map(|pipelines| pipelines[0])
+            .map_err(|(_, err)| err)
+            .context("failed to create skinning pipeline")
+    }
+
+    // Room for `vertex_capacity` bind pose vertices and `index_capacity` indices shared by
+    // the meshes, and for `instance_capacity` instances with up to `output_capacity`
+    // skinned vertices in each of `frame_count` frames, eg. the swapchain images
+    pub fn new(
+        device: &device::Device,
+        vertex_capacity: u32,
+        index_capacity: u32,
+        instance_capacity: u32,
+        output_capacity: u32,
+        frame_count: u32,
+    ) -> Result<ComputeSkinning> {
+        let logical_device = &device.logical_device;
+        let vertex_capacity = vertex_capacity.max(1);
+        let index_capacity = index_capacity.max(1);
+        let instance_capacity = instance_capacity.max(1);
+        let output_capacity = output_capacity.max(1);
+        let frame_count = frame_count.max(1);
+
+        let source = buffers::BufferInfo::create(
+            device,
+            (std::mem::size_of::<skinning::SkinnedVertex>() * vertex_capacity as usize)
+                as vk::DeviceSize,
+            vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+
+        let indices = buffers::BufferInfo::create(
+            device,
+            (std::mem::size_of::<u32>() * index_capacity as usize) as vk::DeviceSize,
+            vk::BufferUsageFlags::INDEX_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+
+        // the shader indexes both tightly packed, so they are not padded to the storage
+        // buffer offset alignment
+        let instance_data = typed_buffer::StorageBuffer::from_packed(
+            buffers::BufferInfo::create(
+                device,
+                (std::mem::size_of::<InstanceData>() * instance_capacity as usize)
+                    as vk::DeviceSize,
+                vk::BufferUsageFlags::STORAGE_BUFFER,
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            )?,
+            instance_capacity as usize,
+            typed_buffer::MemoryLocation::HostVisible,
+        )?;
+
+        let palette_count = (instance_capacity * frame_count) as usize;
+        let palettes = typed_buffer::StorageBuffer::from_packed(
+            buffers::BufferInfo::create(
+                device,
+                (std::mem::size_of::<skinning::BonePalette>() * palette_count) as vk::DeviceSize,
+                vk::BufferUsageFlags::STORAGE_BUFFER,
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            )?,
+            palette_count,
+            typed_buffer::MemoryLocation::HostVisible,
+        )?;
+
+        let output = buffers::BufferInfo::create(
+            device,
+            OUTPUT_STRIDE * (output_capacity * frame_count) as vk::DeviceSize,
+            vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::VERTEX_BUFFER,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+
+        source.set_name(device, "bind pose vertex buffer");
+        indices.set_name(device, "skinned index buffer");
+        device.name_resource(instance_data.buffer(), "skinned instance buffer");
+        device.name_resource(palettes.buffer(), "bone palette storage buffer");
+        output.set_name(device, "skinned vertex buffer");
+
+        let bindings = ComputeSkinning::bindings();
+        let descriptor_set_layout = descriptor::create_set_layout(logical_device, &bindings)?;
+        let descriptor_pool = descriptor::create_pool(logical_device, &bindings, 1)?;
+        let descriptor_set =
+            descriptor::allocate_sets(logical_device, descriptor_pool, descriptor_set_layout, 1)?
+                [0];
+
+        let whole_buffer = |buffer| vk::DescriptorBufferInfo {
+            buffer,
+            offset: 0,
+            range: vk::WHOLE_SIZE,
+        };
+        let buffer_infos = [
+            whole_buffer(source.buffer),
+            whole_buffer(instance_data.buffer()),
+            whole_buffer(palettes.buffer()),
+            whole_buffer(output.buffer),
+        ];
+        let descriptor_writes = [
+            bindings[0].write_buffers(descriptor_set, &buffer_infos[0..1])?,
+            bindings[1].write_buffers(descriptor_set, &buffer_infos[1..2])?,
+            bindings[2].write_buffers(descriptor_set, &buffer_infos[2..3])?,
+            bindings[3].write_buffers(descriptor_set, &buffer_infos[3..4])?,
+        ];
+        unsafe { logical_device.update_descriptor_sets(&descriptor_writes, &[]) };
+
+        let push_constant_ranges = [vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::COMPUTE,
+            offset: 0,
+            size: ::std::mem::size_of::<PushConstants>() as u32,
+        }];
+
+        let set_layouts = [descriptor_set_layout];
+        let layout_info = vk::PipelineLayoutCreateInfo {
+            set_layout_count: set_layouts.len() as u32,
+            p_set_layouts: set_layouts.as_ptr(),
+            push_constant_range_count: push_constant_ranges.len() as u32,
+            p_push_constant_ranges: push_constant_ranges.as_ptr(),
+            ..Default::default()
+        };
+
+        let pipeline_layout = unsafe {
+            logical_device
+                .create_pipeline_layout(&layout_info, None)
+                .context("failed to create skinning pipeline layout")
+        }?;
+
+        let pipeline = ComputeSkinning::create_pipeline(logical_device, pipeline_layout)?;
+
+        device.track(
+            registry::ResourceKind::DescriptorSetLayout,
+            descriptor_set_layout,
+        );
+        device.track(registry::ResourceKind::DescriptorPool, descriptor_pool);
+        device.track(registry::ResourceKind::PipelineLayout, pipeline_layout);
+        device.track(registry::ResourceKind::Pipeline, pipeline);
+
+        Ok(ComputeSkinning {
+            source,
+            indices,
+            instance_data,
+            palettes,
+            output,
+            vertex_capacity,
+            index_capacity,
+            instance_capacity,
+            output_capacity,
+            frame_count,
+            vertex_count: 0,
+            index_count: 0,
+            output_vertex_count: 0,
+            max_vertex_count: 0,
+            instances: vec![],
+            poses: vec![],
+            descriptor_set_layout,
+            descriptor_pool,
+            descriptor_set,
+            pipeline_layout,
+            pipeline,
+        })
+    }
+
+    pub fn instances(&self) -> &[SkinnedInstance] {
+        &self.instances
+    }
+
+    pub fn frame_count(&self) -> u32 {
+        self.frame_count
+    }
+
+    // Copies the data into the buffer at the offset through a staging buffer and waits
+    // for the copy
+    fn upload<D: Copy>(
+        device: &device::Device,
+        command_pool: vk::CommandPool,
+        queue: vk::Queue,
+        buffer: vk::Buffer,
+        offset: vk::DeviceSize,
+        data: &[D],
+    ) -> Result<()> {
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        let size = ::std::mem::size_of_val(data) as vk::DeviceSize;
+        let staging_buffer = buffers::BufferInfo::create(
+            device,
+            size,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+        staging_buffer.set_name(device, "skinning staging buffer");
+
+        let copied = staging_buffer
+            .upload(&device.logical_device, data)
+            .and_then(|_| {
+                let copy_regions = [vk::BufferCopy {
+                    src_offset: 0,
+                    dst_offset: offset,
+                    size,
+                }];
+
+                buffers::CommandBuffer::record_and_submit_single_command(
+                    &device.logical_device,
+                    command_pool,
+                    queue,
+                    |command_buffer| unsafe {
+                        device.logical_device.cmd_copy_buffer(
+                            command_buffer,
+                            staging_buffer.buffer,
+                            buffer,
+                            &copy_regions,
+                        )
+                    },
+                )
+            });
+        staging_buffer.destroy(device);
+        copied
+    }
+
+    // Copies the bind pose and indices after the meshes added before, eg. the vertices and
+    // indices of a gltf::GltfModel. Waits for the copies, so no recorded skinning pass may
+    // still be executing.
+    pub fn add_mesh(
+        &mut self,
+        device: &device::Device,
+        command_pool: vk::CommandPool,
+        queue: vk::Queue,
+        vertices: &[skinning::SkinnedVertex],
+        indices: &[u32],
+    ) -> Result<SkinnedMesh> {
+        check_joints(vertices)?;
+
+        let vertex_count = vertices.len() as u32;
+        if self.vertex_count + vertex_count > self.vertex_capacity {
+            return Err(Error::OutOfRange(format!(
+                "{} more vertices do not fit the {} of {} left for bind poses",
+                vertex_count,
+                self.vertex_capacity - self.vertex_count,
+                self.vertex_capacity
+            )));
+        }
+
+        let index_count = indices.len() as u32;
+        if self.index_count + index_count > self.index_capacity {
+            return Err(Error::OutOfRange(format!(
+                "{} more indices do not fit the {} of {} left",
+                index_count,
+                self.index_capacity - self.index_count,
+                self.index_capacity
+            )));
+        }
+
+        if let Some(index) = indices.iter().find(|index| **index >= vertex_count) {
+            return Err(Error::OutOfRange(format!(
+                "index {} is out of the mesh's {} vertices",
+                index, vertex_count
+            )));
+        }
+
+        let mesh = SkinnedMesh {
+            first_vertex: self.vertex_count,
+            vertex_count,
+            first_index: self.index_count,
+            index_count,
+        };
+
+        ComputeSkinning::upload(
+            device,
+            command_pool,
+            queue,
+            self.source.buffer,
+            (std::mem::size_of::<skinning::SkinnedVertex>() * mesh.first_vertex as usize)
+                as vk::DeviceSize,
+            vertices,
+        )?;
+        ComputeSkinning::upload(
+            device,
+            command_pool,
+            queue,
+            self.indices.buffer,
+            (std::mem::size_of::<u32>() * mesh.first_index as usize) as vk::DeviceSize,
+            indices,
+        )?;
+
+        self.vertex_count += vertex_count;
+        self.index_count += index_count;
+        Ok(mesh)
+    }
+
+    // Adds an instance of the mesh posed at bind pose until it is posed
+    pub fn add_instance(
+        &mut self,
+        device: &device::Device,
+        mesh: SkinnedMesh,
+    ) -> Result<SkinnedInstance> {
+        if self.instances.len() as u32 == self.instance_capacity {
+            return Err(Error::OutOfRange(format!(
+                "the skinning pass was created for {} instances",
+                self.instance_capacity
+            )));
+        }
+
+        if self.output_vertex_count + mesh.vertex_count > self.output_capacity {
+            return Err(Error::OutOfRange(format!(
+                "{} more skinned vertices do not fit the {} left of {} per frame",
+                mesh.vertex_count,
+                self.output_capacity - self.output_vertex_count,
+                self.output_capacity
+            )));
+        }
+
+        let instance = SkinnedInstance {
+            index: self.instances.len() as u32,
+            mesh,
+            first_output_vertex: self.output_vertex_count,
+        };
+
+        self.instance_data.update_at(
+            &device.logical_device,
+            instance.index as usize,
+            &[InstanceData {
+                first_vertex: mesh.first_vertex,
+                vertex_count: mesh.vertex_count,
+                first_output_vertex: instance.first_output_vertex,
+            }],
+        )?;
+
+        self.poses.push(skinning::BonePalette::identity());
+        self.output_vertex_count += mesh.vertex_count;
+        self.max_vertex_count = self.max_vertex_count.max(mesh.vertex_count);
+        self.instances.push(instance);
+
+        Ok(instance)
+    }
+
+    // Removes every instance, the meshes are kept. No recorded skinning pass may still be
+    // executing.
+    pub fn clear_instances(&mut self) {
+        self.instances.clear();
+        self.poses.clear();
+        self.output_vertex_count = 0;
+        self.max_vertex_count = 0;
+    }
+
+    // Poses the instance from the next uploaded frame on, eg. with
+    // animation::AnimationPlayer::joint_matrices
+    pub fn pose(
+        &mut self,
+        instance: &SkinnedInstance,
+        palette: &skinning::BonePalette,
+    ) -> Result<()> {
+        let pose = self
+            .poses
+            .get_mut(instance.index as usize)
+            .ok_or_else(|| Error::OutOfRange(format!("no skinned instance {}", instance.index)))?;
+        *pose = *palette;
+        Ok(())
+    }
+
+    // Writes the poses of every instance to the frame's palettes. The frame's previous
+    // skinning pass has to be completed, eg. once the swapchain image was waited for.
+    pub fn upload_poses(&self, device: &ash::Device, frame: u32) -> Result<()> {
+        if self.poses.is_empty() {
+            return Ok(());
+        }
+
+        let (first_palette, _) = self.frame_offsets(frame);
+        self.palettes
+            .update_at(device, first_palette as usize, &self.poses)
+    }
+
+    fn frame_offsets(&self, frame: u32) -> (u32, u32) {
+        frame_offsets(
+            frame,
+            self.frame_count,
+            self.instance_capacity,
+            self.output_capacity,
+        )
+    }
+
+    fn memory_barrier(
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        src: (vk::PipelineStageFlags, vk::AccessFlags),
+        dst: (vk::PipelineStageFlags, vk::AccessFlags),
+    ) {
+        let barrier = vk::MemoryBarrier {
+            src_access_mask: src.1,
+            dst_access_mask: dst.1,
+            ..Default::default()
+        };
+
+        unsafe {
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                src.0,
+                dst.0,
+                vk::DependencyFlags::empty(),
+                &[barrier],
+                &[],
+                &[],
+            )
+        };
+    }
+
+    // Skins every instance for the frame, recorded outside of a render pass before the
+    // passes drawing the frame's vertex buffer. The barrier after the dispatch makes the
+    // vertices visible to the vertex input of the passes recorded or submitted after it.
+    pub fn record(&self, device: &ash::Device, command_buffer: vk::CommandBuffer, frame: u32) {
+        if self.instances.is_empty() {
+            return;
+        }
+
+        let (first_palette, first_output_vertex) = self.frame_offsets(frame);
+
+        let vertex_read = (
+            vk::PipelineStageFlags::VERTEX_INPUT,
+            vk::AccessFlags::VERTEX_ATTRIBUTE_READ,
+        );
+        let shader_write = (
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::AccessFlags::SHADER_WRITE,
+        );
+
+        // the frame's vertices may still be drawn from by its previous submission
+        ComputeSkinning::memory_barrier(device, command_buffer, vertex_read, shader_write);
+
+        let push_constants = PushConstants {
+            instance_count: self.instances.len() as u32,
+            first_palette,
+            first_output_vertex,
+        };
+        let (x, y) = workgroup_count(self.max_vertex_count, self.instances.len() as u32);
+
+        unsafe {
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline,
+            );
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline_layout,
+                0,
+                &[self.descriptor_set],
+                &[],
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                as_bytes(&push_constants),
+            );
+            device.cmd_dispatch(command_buffer, x, y, 1);
+        }
+
+        ComputeSkinning::memory_barrier(device, command_buffer, shader_write, vertex_read);
+    }
+
+    // The buffer and offset of the frame's skinned vertices
+    pub fn vertex_buffer(&self, frame: u32) -> (vk::Buffer, vk::DeviceSize) {
+        let (_, first_output_vertex) = self.frame_offsets(frame);
+        (
+            self.output.buffer,
+            OUTPUT_STRIDE * first_output_vertex as vk::DeviceSize,
+        )
+    }
+
+    // Binds the frame's skinned vertices for the draws of any pass, eg. the shadow and
+    // scene passes, with the instance's first_output_vertex as their vertex offset
+    pub fn bind_vertex_buffer(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        frame: u32,
+        binding: u32,
+    ) {
+        let (buffer, offset) = self.vertex_buffer(frame);
+
+        unsafe { device.cmd_bind_vertex_buffers(command_buffer, binding, &[buffer], &[offset]) };
+    }
+
+    // Draws every instance with the bound pipeline, which reads app::VertexData from the
+    // first vertex binding, eg. within the scene pass
+    pub fn record_draws(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        frame: u32,
+    ) {
+        if self.instances.is_empty() {
+            return;
+        }
+
+        self.bind_vertex_buffer(device, command_buffer, frame, 0);
+
+        unsafe {
+            device.cmd_bind_index_buffer(
+                command_buffer,
+                self.indices.buffer,
+                0,
+                vk::IndexType::UINT32,
+            );
+
+            for instance in self.instances.iter() {
+                device.cmd_draw_indexed(
+                    command_buffer,
+                    instance.mesh.index_count,
+                    1,
+                    instance.mesh.first_index,
+                    instance.first_output_vertex as i32,
+                    0,
+                );
+            }
+        }
+    }
+
+    // The device must be idle, none of the buffers may be in use anymore
+    pub fn destroy(&self, device: &device::Device) {
+        let logical_device = &device.logical_device;
+
+        device.untrack(self.pipeline);
+        device.untrack(self.pipeline_layout);
+        device.untrack(self.descriptor_pool);
+        device.untrack(self.descriptor_set_layout);
+
+        unsafe {
+            logical_device.destroy_pipeline(self.pipeline, None);
+            logical_device.destroy_pipeline_layout(self.pipeline_layout, None);
+            logical_device.destroy_descriptor_pool(self.descriptor_pool, None);
+            logical_device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+        }
+
+        self.output.destroy(device);
+        self.palettes.destroy(device);
+        self.instance_data.destroy(device);
+        self.indices.destroy(device);
+        self.source.destroy(device);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buffers_match_the_shader_layout() {
+        // SOURCE_STRIDE and OUTPUT_STRIDE words of shaders/skinning.comp
+        assert_eq!(std::mem::size_of::<skinning::SkinnedVertex>(), 19 * 4);
+        assert_eq!(OUTPUT_STRIDE, 11 * 4);
+        assert_eq!(std::mem::size_of::<InstanceData>(), 12);
+        assert_eq!(
+            std::mem::size_of::<skinning::BonePalette>(),
+            skinning::MAX_JOINTS * 64
+        );
+        // every device has at least 128 bytes of push constants
+        assert!(std::mem::size_of::<PushConstants>() <= 128);
+    }
+
+    #[test]
+    fn instances_are_dispatched_in_rows() {
+        assert_eq!(workgroup_count(1, 1), (1, 1));
+        assert_eq!(workgroup_count(64, 3), (1, 3));
+        assert_eq!(workgroup_count(65, 300), (2, 300));
+
+        let vertex = skinning::SkinnedVertex::default();
+        assert!(check_joints(&[vertex]).is_ok());
+
+        let unbound = skinning::SkinnedVertex {
+            joints: [0, skinning::MAX_JOINTS as u32, 0, 0],
+            ..vertex
+        };
+        assert!(check_joints(&[vertex, unbound]).is_err());
+    }
+
+    #[test]
+    fn frames_have_their_own_palettes_and_vertices() {
+        // 4 instances with up to 1000 skinned vertices between them, 3 frames
+        assert_eq!(frame_offsets(0, 3, 4, 1000), (0, 0));
+        assert_eq!(frame_offsets(2, 3, 4, 1000), (8, 2000));
+        // frames past the frame count reuse the regions
+        assert_eq!(frame_offsets(4, 3, 4, 1000), frame_offsets(1, 3, 4, 1000));
+
+        // the last frame's region ends at the end of the buffers
+        let (first_palette, first_output_vertex) = frame_offsets(2, 3, 4, 1000);
+        assert_eq!(first_palette + 4, 3 * 4);
+        assert_eq!(first_output_vertex + 1000, 3 * 1000);
+    }
+}
@@ -52,10 +52,15 @@ pub struct EnabledLayers {
 
 pub const APPLICATION_VERSION: u32 = vk_make_version!(1, 0, 0);
 pub const ENGINE_VERSION: u32 = vk_make_version!(1, 0, 0);
-pub const API_VERSION: u32 = vk_make_version!(1, 0, 92);
+// 1.1 provides the physical device queries optional device extensions depend on,
+// eg. VK_KHR_timeline_semaphore
+pub const API_VERSION: u32 = vk_make_version!(1, 1, 0);
 
 pub const WINDOW_TITLE: &'static str = "Kelsier";
 
+// Max time spent per frame on releasing deferred resources
+pub const GC_TIME_BUDGET_MICROS: u64 = 500;
+
 // Device extensions
 
 pub struct DeviceExtension {
@@ -0,0 +1,413 @@
+use ash::version::DeviceV1_0;
+use ash::vk;
+
+use crate::error::{Context, Error, Result};
+
+use cgmath::Matrix4;
+
+use crate::debug_draw;
+use crate::shaderc;
+
+use super::device;
+use super::dynamic_buffer;
+use super::frame;
+use super::pipeline;
+use super::preset;
+use super::registry;
+use super::swapchain;
+
+pub const DEBUG_LINE_VERTEX_SHADER: &'static str = "shaders/debug_line.vert";
+pub const DEBUG_LINE_FRAGMENT_SHADER: &'static str = "shaders/debug_line.frag";
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct DebugLineVertex {
+    pub pos: [f32; 3],
+    pub color: [f32; 3],
+}
+
+crate::impl_vertex_data!(DebugLineVertex { pos, color });
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct PushConstants {
+    view_proj: [[f32; 4]; 4],
+}
+
+// Draws the lines collected by `debug_draw::DebugDraw` on top of the scene with a
+// line list pipeline. Like the ui overlay it uses its own render pass and command
+// buffers, which are re-recorded every frame.
+pub struct DebugLineRenderer {
+    device: device::Device,
+
+    pub render_pass: vk::RenderPass,
+    pub pipeline: vk::Pipeline,
+    pub layout: vk::PipelineLayout,
+
+    framebuffers: Vec<vk::Framebuffer>,
+    command_pool: vk::CommandPool,
+    command_buffers: Vec<vk::CommandBuffer>,
+
+    // one per swapchain image so that in flight frames are not overwritten
+    vertex_buffers: Vec<dynamic_buffer::DynamicBuffer>,
+
+    extent: vk::Extent2D,
+    vertices: Vec<DebugLineVertex>,
+    view_proj: Matrix4<f32>,
+}
+
+impl DebugLineRenderer {
+    fn create_render_pass(
+        device: &ash::Device,
+        surface_format: vk::Format,
+    ) -> Result<vk::RenderPass> {
+        // drawn over the finished scene, so load instead of clear
+        let color_attachment = vk::AttachmentDescription {
+            format: surface_format,
+            samples: vk::SampleCountFlags::TYPE_1,
+            load_op: vk::AttachmentLoadOp::LOAD,
+            store_op: vk::AttachmentStoreOp::STORE,
+            stencil_load_op: vk::AttachmentLoadOp::DONT_CARE,
+            stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
+            initial_layout: vk::ImageLayout::PRESENT_SRC_KHR,
+            final_layout: vk::ImageLayout::PRESENT_SRC_KHR,
+            ..Default::default()
+        };
+
+        let color_attachment_ref = vk::AttachmentReference {
+            attachment: 0,
+            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        };
+
+        let subpasses = [vk::SubpassDescription {
+            color_attachment_count: 1,
+            p_color_attachments: &color_attachment_ref,
+            pipeline_bind_point: vk::PipelineBindPoint::GRAPHICS,
+            ..Default::default()
+        }];
+
+        let attachments = [color_attachment];
+
+        let subpass_dependencies = [vk::SubpassDependency {
+            src_subpass: vk::SUBPASS_EXTERNAL,
+            src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            dst_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            src_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            dst_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_READ
+                | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            ..Default::default()
+        }];
+
+        let renderpass_create_info = vk::RenderPassCreateInfo {
+            attachment_count: attachments.len() as u32,
+            p_attachments: attachments.as_ptr(),
+            subpass_count: subpasses.len() as u32,
+            p_subpasses: subpasses.as_ptr(),
+            dependency_count: subpass_dependencies.len() as u32,
+            p_dependencies: subpass_dependencies.as_ptr(),
+            ..Default::default()
+        };
+
+        unsafe {
+            device
+                .create_render_pass(&renderpass_create_info, None)
+                .context("failed to create debug line render pass")
+        }
+    }
+
+    fn create_pipeline_layout(device: &ash::Device) -> Result<vk::PipelineLayout> {
+        let push_constant_ranges = [vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::VERTEX,
+            offset: 0,
+            size: ::std::mem::size_of::<PushConstants>() as u32,
+        }];
+
+        let layout_info = vk::PipelineLayoutCreateInfo {
+            push_constant_range_count: push_constant_ranges.len() as u32,
+            p_push_constant_ranges: push_constant_ranges.as_ptr(),
+            ..Default::default()
+        };
+
+        unsafe {
+            device
+                .create_pipeline_layout(&layout_info, None)
+                .context("failed to create debug line pipeline layout")
+        }
+    }
+
+    fn create_framebuffers(
+        device: &ash::Device,
+        render_pass: vk::RenderPass,
+        image_views: &Vec<vk::ImageView>,
+        extent: vk::Extent2D,
+    ) -> Result<Vec<vk::Framebuffer>> {
+        image_views
+            .iter()
+            .map(|&image_view| {
+                let attachments = [image_view];
+
+                let framebuffer_info = vk::FramebufferCreateInfo {
+                    render_pass,
+                    attachment_count: attachments.len() as u32,
+                    p_attachments: attachments.as_ptr(),
+                    width: extent.width,
+                    height: extent.height,
+                    layers: 1,
+                    ..Default::default()
+                };
+
+                unsafe {
+                    device
+                        .create_framebuffer(&framebuffer_info, None)
+                        .context("failed to create debug line framebuffer")
+                }
+            })
+            .collect()
+    }
+
+    fn create_command_buffers(
+        device: &device::Device,
+        count: u32,
+    ) -> Result<(vk::CommandPool, Vec<vk::CommandBuffer>)> {
+        let queue_index = device
+            .family_indices
+            .graphics
+            .ok_or_else(|| Error::msg("graphics family index not present"))?;
+
+        let command_pool_info = vk::CommandPoolCreateInfo {
+            queue_family_index: queue_index,
+            flags: vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER,
+            ..Default::default()
+        };
+
+        let command_pool = unsafe {
+            device
+                .logical_device
+                .create_command_pool(&command_pool_info, None)
+                .context("failed to create debug line command pool")
+        }?;
+
+        let alloc_info = vk::CommandBufferAllocateInfo {
+            command_buffer_count: count,
+            command_pool,
+            level: vk::CommandBufferLevel::PRIMARY,
+            ..Default::default()
+        };
+
+        let command_buffers = unsafe {
+            device
+                .logical_device
+                .allocate_command_buffers(&alloc_info)
+                .context("failed to allocate debug line command buffers")
+        }?;
+
+        Ok((command_pool, command_buffers))
+    }
+
+    pub fn new(
+        device: &device::Device,
+        swapchain: &swapchain::SwapchainDetails,
+    ) -> Result<DebugLineRenderer> {
+        let logical_device = &device.logical_device;
+        let num_images = swapchain.image_views.len();
+
+        let render_pass =
+            DebugLineRenderer::create_render_pass(logical_device, swapchain.format.format)?;
+        let layout = DebugLineRenderer::create_pipeline_layout(logical_device)?;
+
+        let shaders = shaderc::ShaderSource {
+            vertex_shader_file: DEBUG_LINE_VERTEX_SHADER.to_string(),
+            fragment_shader_file: DEBUG_LINE_FRAGMENT_SHADER.to_string(),
+        };
+
+        let pipeline = pipeline::PipelineDetail::create_pipeline(
+            logical_device,
+            shaders,
+            DebugLineVertex {
+                pos: [0.0; 3],
+                color: [0.0; 3],
+            },
+            &preset::FixedFunctionState::from_preset(preset::Preset::DebugLines),
+            layout,
+            render_pass,
+            vk::PipelineCache::null(),
+        )?;
+
+        let framebuffers = DebugLineRenderer::create_framebuffers(
+            logical_device,
+            render_pass,
+            &swapchain.image_views,
+            swapchain.extent,
+        )?;
+
+        let (command_pool, command_buffers) =
+            DebugLineRenderer::create_command_buffers(device, num_images as u32)?;
+
+        device.track(registry::ResourceKind::RenderPass, render_pass);
+        device.track(registry::ResourceKind::Pipeline, pipeline);
+        device.track(registry::ResourceKind::PipelineLayout, layout);
+        device.track(registry::ResourceKind::CommandPool, command_pool);
+        for &framebuffer in framebuffers.iter() {
+            device.track(registry::ResourceKind::Framebuffer, framebuffer);
+        }
+
+        Ok(DebugLineRenderer {
+            device: device.clone(),
+            render_pass,
+            pipeline,
+            layout,
+            framebuffers,
+            command_pool,
+            command_buffers,
+            vertex_buffers: (0..num_images)
+                .map(|_| {
+                    dynamic_buffer::DynamicBuffer::new(
+                        vk::BufferUsageFlags::VERTEX_BUFFER,
+                        dynamic_buffer::DynamicMemory::HostVisible,
+                    )
+                })
+                .collect(),
+            extent: swapchain.extent,
+            vertices: vec![],
+            view_proj: Matrix4::from_scale(1.0),
+        })
+    }
+
+    // Takes the lines to draw in the next frame along with the camera they are seen from
+    pub fn set_lines(&mut self, lines: &[debug_draw::DebugLine], view_proj: Matrix4<f32>) {
+        self.vertices.clear();
+        self.vertices.extend(lines.iter().flat_map(|line| {
+            vec![
+                DebugLineVertex {
+                    pos: line.start,
+                    color: line.color,
+                },
+                DebugLineVertex {
+                    pos: line.end,
+                    color: line.color,
+                },
+            ]
+        }));
+        self.view_proj = view_proj;
+    }
+
+    // Uploads the lines and records the command buffer for the image.
+    // Must only be called once the previous frame using this image has completed.
+    pub fn record(&mut self, frame: &frame::FrameContext) -> Result<vk::CommandBuffer> {
+        let logical_device = &self.device.logical_device;
+
+        let command_buffer = *frame.per_image(&self.command_buffers)?;
+
+        let vertex_buffer = if self.vertices.is_empty() {
+            None
+        } else {
+            let slot = frame.per_image_mut(&mut self.vertex_buffers)?;
+            slot.update(&self.device, &self.vertices)?;
+            slot.buffer()
+        };
+
+        let push_constants = PushConstants {
+            view_proj: self.view_proj.into(),
+        };
+
+        let push_constant_bytes = unsafe {
+            ::std::slice::from_raw_parts(
+                &push_constants as *const PushConstants as *const u8,
+                ::std::mem::size_of::<PushConstants>(),
+            )
+        };
+
+        let render_pass_begin_info = vk::RenderPassBeginInfo {
+            render_pass: self.render_pass,
+            framebuffer: *frame.per_image(&self.framebuffers)?,
+            render_area: vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: self.extent,
+            },
+            ..Default::default()
+        };
+
+        let viewports = [vk::Viewport {
+            x: 0.0,
+            y: 0.0,
+            width: self.extent.width as f32,
+            height: self.extent.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        }];
+
+        let scissors = [vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent: self.extent,
+        }];
+
+        unsafe {
+            logical_device
+                .begin_command_buffer(command_buffer, &vk::CommandBufferBeginInfo::default())
+                .context("failed to begin recording debug line command buffer")?;
+
+            logical_device.cmd_begin_render_pass(
+                command_buffer,
+                &render_pass_begin_info,
+                vk::SubpassContents::INLINE,
+            );
+
+            if let Some(vertex_buffer) = vertex_buffer {
+                logical_device.cmd_bind_pipeline(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    self.pipeline,
+                );
+
+                logical_device.cmd_set_viewport(command_buffer, 0, &viewports);
+                logical_device.cmd_set_scissor(command_buffer, 0, &scissors);
+
+                logical_device.cmd_push_constants(
+                    command_buffer,
+                    self.layout,
+                    vk::ShaderStageFlags::VERTEX,
+                    0,
+                    push_constant_bytes,
+                );
+
+                logical_device.cmd_bind_vertex_buffers(command_buffer, 0, &[vertex_buffer], &[0]);
+                logical_device.cmd_draw(command_buffer, self.vertices.len() as u32, 1, 0, 0);
+            }
+
+            logical_device.cmd_end_render_pass(command_buffer);
+
+            logical_device
+                .end_command_buffer(command_buffer)
+                .context("failed to end debug line command buffer recording")?;
+        }
+
+        Ok(command_buffer)
+    }
+
+    pub fn destroy(&mut self) {
+        for buffer in self.vertex_buffers.iter_mut() {
+            buffer.destroy(&self.device);
+        }
+
+        self.device.untrack(self.command_pool);
+        self.device.untrack(self.pipeline);
+        self.device.untrack(self.layout);
+        self.device.untrack(self.render_pass);
+        for &framebuffer in self.framebuffers.iter() {
+            self.device.untrack(framebuffer);
+        }
+
+        let logical_device = &self.device.logical_device;
+        unsafe {
+            logical_device.destroy_command_pool(self.command_pool, None);
+
+            for &framebuffer in self.framebuffers.iter() {
+                logical_device.destroy_framebuffer(framebuffer, None);
+            }
+
+            logical_device.destroy_pipeline(self.pipeline, None);
+            logical_device.destroy_pipeline_layout(self.layout, None);
+            logical_device.destroy_render_pass(self.render_pass, None);
+        }
+    }
+}
@@ -0,0 +1,287 @@
+use ash::vk;
+
+use std::{
+    ffi::CStr,
+    fmt,
+    os::raw::c_void,
+    panic::{self, AssertUnwindSafe},
+    ptr,
+    sync::{Arc, RwLock},
+};
+
+use super::validation_stats;
+
+// A message of the validation layers or the driver, as passed to a DebugCallback
+#[derive(Debug, Clone, PartialEq)]
+pub struct DebugMessage {
+    pub severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    pub kind: vk::DebugUtilsMessageTypeFlagsEXT,
+    // eg. "VUID-vkCmdDraw-None-02699", empty when the layer gives none
+    pub id_name: String,
+    pub id_number: i32,
+    pub message: String,
+}
+
+// Called on whichever thread made the vulkan call the message is about
+pub type DebugCallback = Arc<dyn Fn(&DebugMessage) + Send + Sync>;
+
+// Which messages of the debug messenger are forwarded, see VulkanInstance::with_debug_messenger.
+// Forwarded messages are logged under the kelsier::vulkan::debug target and passed to
+// the callback.
+#[derive(Clone)]
+pub struct DebugMessengerConfig {
+    // loads the validation layers when vulkan::constants::ENABLE_VALIDATION is set, only
+    // read when the instance is created. Without them nothing is forwarded.
+    pub validation: bool,
+    pub severities: vk::DebugUtilsMessageSeverityFlagsEXT,
+    pub types: vk::DebugUtilsMessageTypeFlagsEXT,
+    // a panic cannot unwind through the vulkan loader, so an ERROR message aborts the
+    // process once it was logged and passed to the callback
+    pub abort_on_error: bool,
+    pub callback: Option<DebugCallback>,
+    // counts the forwarded messages per frame, see vulkan::validation_stats
+    pub stats: Option<validation_stats::ValidationStats>,
+}
+
+impl Default for DebugMessengerConfig {
+    fn default() -> DebugMessengerConfig {
+        DebugMessengerConfig {
+            validation: true,
+            severities: vk::DebugUtilsMessageSeverityFlagsEXT::WARNING
+                | vk::DebugUtilsMessageSeverityFlagsEXT::ERROR,
+            types: vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
+                | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE
+                | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION,
+            abort_on_error: false,
+            callback: None,
+            stats: None,
+        }
+    }
+}
+
+impl fmt::Debug for DebugMessengerConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DebugMessengerConfig")
+            .field("validation", &self.validation)
+            .field("severities", &self.severities)
+            .field("types", &self.types)
+            .field("abort_on_error", &self.abort_on_error)
+            .field("callback", &self.callback.is_some())
+            .field("stats", &self.stats.is_some())
+            .finish()
+    }
+}
+
+impl DebugMessengerConfig {
+    pub fn with_validation(mut self, validation: bool) -> DebugMessengerConfig {
+        self.validation = validation;
+        self
+    }
+
+    pub fn with_severities(
+        mut self,
+        severities: vk::DebugUtilsMessageSeverityFlagsEXT,
+    ) -> DebugMessengerConfig {
+        self.severities = severities;
+        self
+    }
+
+    pub fn with_types(mut self, types: vk::DebugUtilsMessageTypeFlagsEXT) -> DebugMessengerConfig {
+        self.types = types;
+        self
+    }
+
+    pub fn with_abort_on_error(mut self, abort_on_error: bool) -> DebugMessengerConfig {
+        self.abort_on_error = abort_on_error;
+        self
+    }
+
+    pub fn with_callback<F>(mut self, callback: F) -> DebugMessengerConfig
+    where
+        F: Fn(&DebugMessage) + Send + Sync + 'static,
+    {
+        self.callback = Some(Arc::new(callback));
+        self
+    }
+
+    pub fn with_stats(mut self, stats: validation_stats::ValidationStats) -> DebugMessengerConfig {
+        self.stats = Some(stats);
+        self
+    }
+}
+
+// The config a messenger reads its settings from, boxed by the instance so its address
+// stays the same for as long as the messengers using it exist
+pub type DebugMessengerState = RwLock<DebugMessengerConfig>;
+
+pub fn create_info(state: &DebugMessengerState) -> vk::DebugUtilsMessengerCreateInfoEXT {
+    let (severities, types) = match state.read() {
+        Ok(config) => (config.severities, config.types),
+        Err(poisoned) => {
+            let config = poisoned.into_inner();
+            (config.severities, config.types)
+        }
+    };
+
+    vk::DebugUtilsMessengerCreateInfoEXT {
+        s_type: vk::StructureType::DEBUG_UTILS_MESSENGER_CREATE_INFO_EXT,
+        p_next: ptr::null(),
+        flags: vk::DebugUtilsMessengerCreateFlagsEXT::empty(),
+        message_severity: severities,
+        message_type: types,
+        pfn_user_callback: Some(vulkan_debug_utils_callback),
+        p_user_data: state as *const DebugMessengerState as *mut c_void,
+    }
+}
+
+fn kind_name(kind: vk::DebugUtilsMessageTypeFlagsEXT) -> &'static str {
+    match kind {
+        vk::DebugUtilsMessageTypeFlagsEXT::GENERAL => "general",
+        vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE => "performance",
+        vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION => "validation",
+        _ => "unknown",
+    }
+}
+
+fn log_message(message: &DebugMessage) {
+    let kind = kind_name(message.kind);
+    let id = message.id_name.as_str();
+
+    match message.severity {
+        vk::DebugUtilsMessageSeverityFlagsEXT::ERROR => {
+            tracing::error!(target: "kelsier::vulkan::debug", kind, id, "{}", message.message)
+        }
+        vk::DebugUtilsMessageSeverityFlagsEXT::WARNING => {
+            tracing::warn!(target: "kelsier::vulkan::debug", kind, id, "{}", message.message)
+        }
+        vk::DebugUtilsMessageSeverityFlagsEXT::INFO => {
+            tracing::info!(target: "kelsier::vulkan::debug", kind, id, "{}", message.message)
+        }
+        _ => tracing::debug!(target: "kelsier::vulkan::debug", kind, id, "{}", message.message),
+    }
+}
+
+// Logs the message and passes it to the callback, returns whether the process has to
+// abort
+fn forward(config: &DebugMessengerConfig, message: &DebugMessage) -> bool {
+    log_message(message);
+
+    if let Some(stats) = config.stats.as_ref() {
+        stats.record(message);
+    }
+
+    if let Some(callback) = config.callback.as_ref() {
+        // a panicking callback must not unwind into the vulkan loader
+        if panic::catch_unwind(AssertUnwindSafe(|| callback(message))).is_err() {
+            tracing::error!("the debug messenger callback panicked");
+        }
+    }
+
+    config.abort_on_error && message.severity == vk::DebugUtilsMessageSeverityFlagsEXT::ERROR
+}
+
+unsafe fn lossy_string(string: *const std::os::raw::c_char) -> String {
+    if string.is_null() {
+        String::new()
+    } else {
+        CStr::from_ptr(string).to_string_lossy().into_owned()
+    }
+}
+
+unsafe extern "system" fn vulkan_debug_utils_callback(
+    message_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    message_type: vk::DebugUtilsMessageTypeFlagsEXT,
+    p_callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT,
+    p_user_data: *mut c_void,
+) -> vk::Bool32 {
+    let data = &*p_callback_data;
+    let message = DebugMessage {
+        severity: message_severity,
+        kind: message_type,
+        id_name: lossy_string(data.p_message_id_name),
+        id_number: data.message_id_number,
+        message: lossy_string(data.p_message),
+    };
+
+    let state = &*(p_user_data as *const DebugMessengerState);
+    let abort = match state.read() {
+        Ok(config) => forward(&config, &message),
+        Err(poisoned) => forward(&poisoned.into_inner(), &message),
+    };
+
+    if abort {
+        std::process::abort();
+    }
+
+    vk::FALSE
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Mutex;
+
+    fn message(severity: vk::DebugUtilsMessageSeverityFlagsEXT) -> DebugMessage {
+        DebugMessage {
+            severity,
+            kind: vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION,
+            id_name: "VUID-test".to_string(),
+            id_number: 7,
+            message: "test message".to_string(),
+        }
+    }
+
+    #[test]
+    fn forwards_messages_to_the_callback() {
+        let received = Arc::new(Mutex::new(vec![]));
+        let sink = received.clone();
+        let config = DebugMessengerConfig::default()
+            .with_callback(move |message| sink.lock().unwrap().push(message.clone()));
+
+        let warning = message(vk::DebugUtilsMessageSeverityFlagsEXT::WARNING);
+        assert!(!forward(&config, &warning));
+        assert!(!forward(
+            &config,
+            &message(vk::DebugUtilsMessageSeverityFlagsEXT::ERROR)
+        ));
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 2);
+        assert_eq!(received[0], warning);
+    }
+
+    #[test]
+    fn forwarded_messages_are_counted() {
+        let stats = validation_stats::ValidationStats::default();
+        let config = DebugMessengerConfig::default().with_stats(stats.clone());
+
+        forward(
+            &config,
+            &message(vk::DebugUtilsMessageSeverityFlagsEXT::WARNING),
+        );
+        forward(
+            &config,
+            &message(vk::DebugUtilsMessageSeverityFlagsEXT::WARNING),
+        );
+
+        let frame = stats.end_frame();
+        assert_eq!(frame.count(validation_stats::Severity::Warning), 2);
+    }
+
+    #[test]
+    fn only_errors_abort() {
+        let config = DebugMessengerConfig::default()
+            .with_abort_on_error(true)
+            .with_callback(|_| panic!("callback panics are contained"));
+
+        assert!(!forward(
+            &config,
+            &message(vk::DebugUtilsMessageSeverityFlagsEXT::WARNING)
+        ));
+        assert!(forward(
+            &config,
+            &message(vk::DebugUtilsMessageSeverityFlagsEXT::ERROR)
+        ));
+    }
+}
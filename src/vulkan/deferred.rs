@@ -0,0 +1,635 @@
+use ash::version::DeviceV1_0;
+use ash::vk;
+
+use crate::error::{Context, Error, Result};
+
+use crate::shaderc;
+
+use super::buffers;
+use super::descriptor;
+use super::device;
+use super::image;
+use super::lighting;
+use super::material;
+use super::mesh_pool;
+use super::pipeline::{self, PipelineDetail, SubpassAttachments};
+use super::postprocess;
+use super::preset;
+use super::profiler;
+use super::registry;
+use super::render_settings;
+use super::typed_buffer;
+
+pub const GBUFFER_FRAGMENT_SHADER: &'static str = "shaders/gbuffer.frag";
+pub const LIGHTING_FRAGMENT_SHADER: &'static str = "shaders/deferred_lighting.frag";
+
+// Albedo, normal and world position, in the order of the attachments following the
+// color target and of the input attachments of shaders/deferred_lighting.frag
+pub const GBUFFER_FORMATS: [vk::Format; 3] = [
+    vk::Format::R8G8B8A8_UNORM,
+    vk::Format::R16G16B16A16_SFLOAT,
+    vk::Format::R16G16B16A16_SFLOAT,
+];
+
+const COLOR_ATTACHMENT: u32 = 0;
+const DEPTH_ATTACHMENT: u32 = GBUFFER_FORMATS.len() as u32 + 1;
+
+const GEOMETRY_SUBPASS: u32 = 0;
+const LIGHTING_SUBPASS: u32 = 1;
+
+// Binding of the light block in the lighting set, after the input attachments
+const LIGHTS_BINDING: u32 = GBUFFER_FORMATS.len() as u32;
+
+fn gbuffer_attachments() -> Vec<u32> {
+    (1..=GBUFFER_FORMATS.len() as u32).collect()
+}
+
+// The geometry subpass writes the G-buffer and depth, the lighting subpass reads the
+// G-buffer back as input attachments and writes the color target
+pub fn subpasses() -> [SubpassAttachments; 2] {
+    [
+        SubpassAttachments {
+            color: gbuffer_attachments(),
+            input: vec![],
+            depth: Some(DEPTH_ATTACHMENT),
+        },
+        SubpassAttachments {
+            color: vec![COLOR_ATTACHMENT],
+            input: gbuffer_attachments(),
+            depth: None,
+        },
+    ]
+}
+
+fn lighting_bindings() -> Vec<descriptor::Binding> {
+    (0..GBUFFER_FORMATS.len() as u32)
+        .map(descriptor::Binding::input_attachment)
+        .chain(Some(descriptor::Binding::uniform_buffer(
+            LIGHTS_BINDING,
+            vk::ShaderStageFlags::FRAGMENT,
+        )))
+        .collect()
+}
+
+// Shades the scene in two subpasses of one render pass instead of directly. The
+// G-buffer is only read at the pixel it was written, so tiled gpus keep it in tile
+// memory and never write it out, see image::ImagePropertyType::input_attachment_property.
+// Drawn in place of the scene pass by buffers::BufferDetails::record_frame_commands.
+pub struct DeferredPass {
+    render_pass: vk::RenderPass,
+    // draws the scene with the scene pipeline's layout and vertex input
+    geometry_pipeline: vk::Pipeline,
+    lighting_pipeline: vk::Pipeline,
+    lighting_layout: vk::PipelineLayout,
+    lighting_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+
+    // all per swapchain image
+    gbuffers: Vec<Vec<image::ImageData>>,
+    framebuffers: Vec<vk::Framebuffer>,
+    lighting_sets: Vec<vk::DescriptorSet>,
+    // shared like the depth buffer of the forward scene pass
+    depth_buffer: buffers::DepthBuffer,
+}
+
+impl DeferredPass {
+    // The G-buffer is written at once and read back in a single subpass
+    pub fn is_supported(limits: &vk::PhysicalDeviceLimits) -> bool {
+        let count = GBUFFER_FORMATS.len() as u32;
+
+        limits.max_color_attachments >= count
+            && limits.max_per_stage_descriptor_input_attachments >= count
+    }
+
+    fn create_render_pass(
+        instance: &ash::Instance,
+        device: &device::Device,
+        target: pipeline::ColorTarget,
+    ) -> Result<vk::RenderPass> {
+        let color_attachment = vk::AttachmentDescription {
+            format: target.format,
+            samples: vk::SampleCountFlags::TYPE_1,
+            load_op: vk::AttachmentLoadOp::CLEAR,
+            store_op: vk::AttachmentStoreOp::STORE,
+            stencil_load_op: vk::AttachmentLoadOp::DONT_CARE,
+            stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
+            initial_layout: vk::ImageLayout::UNDEFINED,
+            final_layout: target.final_layout,
+            ..Default::default()
+        };
+
+        // nothing reads the G-buffer after the pass, so it is never stored
+        let gbuffer_attachments = GBUFFER_FORMATS
+            .iter()
+            .map(|&format| vk::AttachmentDescription {
+                format,
+                samples: vk::SampleCountFlags::TYPE_1,
+                load_op: vk::AttachmentLoadOp::CLEAR,
+                store_op: vk::AttachmentStoreOp::DONT_CARE,
+                stencil_load_op: vk::AttachmentLoadOp::DONT_CARE,
+                stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
+                initial_layout: vk::ImageLayout::UNDEFINED,
+                final_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                ..Default::default()
+            });
+
+        let (depth_attachment, _) =
+            buffers::DepthBuffer::get_attachment_info(instance, device.physical_device)?;
+
+        let attachments = Some(color_attachment)
+            .into_iter()
+            .chain(gbuffer_attachments)
+            .chain(Some(depth_attachment))
+            .collect::<Vec<vk::AttachmentDescription>>();
+
+        // the color target is first written by the lighting subpass
+        let dependencies = [
+            PipelineDetail::external_dependency(),
+            vk::SubpassDependency {
+                dst_subpass: LIGHTING_SUBPASS,
+                ..PipelineDetail::external_dependency()
+            },
+            PipelineDetail::input_dependency(GEOMETRY_SUBPASS, LIGHTING_SUBPASS),
+        ];
+
+        PipelineDetail::create_render_pass_with_subpasses(
+            &device.logical_device,
+            &attachments,
+            &subpasses(),
+            &dependencies,
+        )
+    }
+
+    fn create_geometry_pipeline(
+        device: &ash::Device,
+        scene: &PipelineDetail,
+        render_pass: vk::RenderPass,
+    ) -> Result<vk::Pipeline> {
+        let shaders = shaderc::ShaderSource {
+            vertex_shader_file: scene.shaders().vertex_shader_file.clone(),
+            fragment_shader_file: GBUFFER_FRAGMENT_SHADER.to_string(),
+        };
+
+        // bound with the scene set, so it has to read from it like the scene shaders
+        let compiled_shaders = shaders.compile()?;
+        let reflection = compiled_shaders.reflect()?;
+        reflection.validate_set(0, &scene.descriptor_bindings)?;
+
+        // the G-buffer holds unblended values
+        let state = preset::FixedFunctionState {
+            blend_mode: preset::BlendMode::Opaque,
+            ..scene.state
+        };
+
+        PipelineDetail::create_subpass_pipeline_from_spirv(
+            device,
+            compiled_shaders,
+            scene.vertex_input(),
+            &state,
+            scene.layout,
+            render_pass,
+            GEOMETRY_SUBPASS,
+            GBUFFER_FORMATS.len() as u32,
+            vk::PipelineCache::null(),
+        )
+    }
+
+    fn create_lighting_pipeline(
+        device: &ash::Device,
+        render_pass: vk::RenderPass,
+        set_layout: vk::DescriptorSetLayout,
+    ) -> Result<(vk::Pipeline, vk::PipelineLayout)> {
+        let shaders = shaderc::ShaderSource {
+            vertex_shader_file: postprocess::FULLSCREEN_VERTEX_SHADER.to_string(),
+            fragment_shader_file: LIGHTING_FRAGMENT_SHADER.to_string(),
+        };
+
+        let compiled_shaders = shaders.compile()?;
+        compiled_shaders
+            .reflect()?
+            .validate_set(0, &lighting_bindings())?;
+
+        let set_layouts = [set_layout];
+        let layout_info = vk::PipelineLayoutCreateInfo {
+            set_layout_count: set_layouts.len() as u32,
+            p_set_layouts: set_layouts.as_ptr(),
+            ..Default::default()
+        };
+
+        let layout = unsafe {
+            device
+                .create_pipeline_layout(&layout_info, None)
+                .context("failed to create deferred lighting pipeline layout")
+        }?;
+
+        let state = preset::FixedFunctionState::from_preset(preset::Preset::Fullscreen);
+
+        match PipelineDetail::create_subpass_pipeline_from_spirv(
+            device,
+            compiled_shaders,
+            postprocess::NoVertices,
+            &state,
+            layout,
+            render_pass,
+            LIGHTING_SUBPASS,
+            1,
+            vk::PipelineCache::null(),
+        ) {
+            Ok(pipeline) => Ok((pipeline, layout)),
+            Err(err) => {
+                unsafe { device.destroy_pipeline_layout(layout, None) };
+                Err(err)
+            }
+        }
+    }
+
+    // Points each image's set at its G-buffer and light block
+    fn write_lighting_sets(
+        device: &ash::Device,
+        sets: &[vk::DescriptorSet],
+        gbuffers: &[Vec<image::ImageData>],
+        light_buffers: &[typed_buffer::UniformBuffer<lighting::LightBlock>],
+    ) -> Result<()> {
+        let bindings = lighting_bindings();
+
+        for ((&set, gbuffer), light_buffer) in
+            sets.iter().zip(gbuffers.iter()).zip(light_buffers.iter())
+        {
+            let image_infos = gbuffer
+                .iter()
+                .map(|image| {
+                    [vk::DescriptorImageInfo {
+                        sampler: vk::Sampler::null(),
+                        image_view: image.image_view,
+                        image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    }]
+                })
+                .collect::<Vec<[vk::DescriptorImageInfo; 1]>>();
+            let buffer_infos = [light_buffer.descriptor_info(0)?];
+
+            let mut writes = image_infos
+                .iter()
+                .zip(bindings.iter())
+                .map(|(image_info, binding)| binding.write_images(set, image_info))
+                .collect::<Result<Vec<vk::WriteDescriptorSet>>>()?;
+            writes.push(
+                descriptor::find(&bindings, LIGHTS_BINDING)?.write_buffers(set, &buffer_infos)?,
+            );
+
+            unsafe { device.update_descriptor_sets(&writes, &[]) };
+        }
+
+        Ok(())
+    }
+
+    pub fn new(
+        instance: &ash::Instance,
+        device: &device::Device,
+        command_pool: vk::CommandPool,
+        graphics_queue: vk::Queue,
+        scene: &PipelineDetail,
+        target: pipeline::ColorTarget,
+        // one per swapchain image, the swapchain's views or offscreen targets
+        color_views: &[vk::ImageView],
+        extent: vk::Extent2D,
+        light_buffers: &[typed_buffer::UniformBuffer<lighting::LightBlock>],
+    ) -> Result<DeferredPass> {
+        if !DeferredPass::is_supported(&device.limits) {
+            return Err(Error::Unsupported(format!(
+                "the deferred path needs {} color and input attachments",
+                GBUFFER_FORMATS.len()
+            )));
+        }
+
+        if light_buffers.len() != color_views.len() {
+            return Err(Error::OutOfRange(format!(
+                "{} light buffers for {} images",
+                light_buffers.len(),
+                color_views.len()
+            )));
+        }
+
+        let logical_device = &device.logical_device;
+        let num_images = color_views.len();
+
+        let gbuffers = (0..num_images)
+            .map(|image_index| {
+                GBUFFER_FORMATS
+                    .iter()
+                    .enumerate()
+                    .map(|(attachment, &format)| {
+                        let property =
+                            image::ImagePropertyType::input_attachment_property(extent, format);
+                        let image =
+                            image::ImageData::new(device, command_pool, graphics_queue, property)?;
+                        image.set_name(device, &format!("g-buffer {}.{}", image_index, attachment));
+
+                        Ok(image)
+                    })
+                    .collect::<Result<Vec<image::ImageData>>>()
+            })
+            .collect::<Result<Vec<Vec<image::ImageData>>>>()?;
+
+        let depth_buffer =
+            buffers::DepthBuffer::new(instance, device, command_pool, &graphics_queue, extent)?;
+
+        let render_pass = DeferredPass::create_render_pass(instance, device, target)?;
+
+        let framebuffers = color_views
+            .iter()
+            .zip(gbuffers.iter())
+            .map(|(&color_view, gbuffer)| {
+                let attachments = Some(color_view)
+                    .into_iter()
+                    .chain(gbuffer.iter().map(|image| image.image_view))
+                    .chain(Some(depth_buffer.image.image_view))
+                    .collect::<Vec<vk::ImageView>>();
+
+                let framebuffer_info = vk::FramebufferCreateInfo {
+                    render_pass,
+                    attachment_count: attachments.len() as u32,
+                    p_attachments: attachments.as_ptr(),
+                    width: extent.width,
+                    height: extent.height,
+                    layers: 1,
+                    ..Default::default()
+                };
+
+                unsafe {
+                    logical_device
+                        .create_framebuffer(&framebuffer_info, None)
+                        .context("failed to create deferred framebuffer")
+                }
+            })
+            .collect::<Result<Vec<vk::Framebuffer>>>()?;
+
+        let geometry_pipeline =
+            DeferredPass::create_geometry_pipeline(logical_device, scene, render_pass)?;
+
+        let bindings = lighting_bindings();
+        let lighting_set_layout = descriptor::create_set_layout(logical_device, &bindings)?;
+        let (lighting_pipeline, lighting_layout) = DeferredPass::create_lighting_pipeline(
+            logical_device,
+            render_pass,
+            lighting_set_layout,
+        )?;
+
+        let descriptor_pool =
+            descriptor::create_pool(logical_device, &bindings, num_images as u32)?;
+        let lighting_sets = descriptor::allocate_sets(
+            logical_device,
+            descriptor_pool,
+            lighting_set_layout,
+            num_images,
+        )?;
+        DeferredPass::write_lighting_sets(
+            logical_device,
+            &lighting_sets,
+            &gbuffers,
+            light_buffers,
+        )?;
+
+        depth_buffer.image.set_name(device, "deferred depth image");
+        device.track(registry::ResourceKind::RenderPass, render_pass);
+        device.name_resource(render_pass, "deferred render pass");
+        for (i, &framebuffer) in framebuffers.iter().enumerate() {
+            device.track(registry::ResourceKind::Framebuffer, framebuffer);
+            device.name_resource(framebuffer, &format!("deferred framebuffer {}", i));
+        }
+        device.track(registry::ResourceKind::Pipeline, geometry_pipeline);
+        device.name_resource(geometry_pipeline, "deferred geometry pipeline");
+        device.track(registry::ResourceKind::Pipeline, lighting_pipeline);
+        device.name_resource(lighting_pipeline, "deferred lighting pipeline");
+        device.track(registry::ResourceKind::PipelineLayout, lighting_layout);
+        device.track(
+            registry::ResourceKind::DescriptorSetLayout,
+            lighting_set_layout,
+        );
+        device.track(registry::ResourceKind::DescriptorPool, descriptor_pool);
+
+        Ok(DeferredPass {
+            render_pass,
+            geometry_pipeline,
+            lighting_pipeline,
+            lighting_layout,
+            lighting_set_layout,
+            descriptor_pool,
+            gbuffers,
+            framebuffers,
+            lighting_sets,
+            depth_buffer,
+        })
+    }
+
+    // Color, then the G-buffer cleared to zero, which marks the pixels nothing was drawn
+    // to, then depth
+    fn clear_values(settings: &render_settings::RenderSettings) -> Vec<vk::ClearValue> {
+        let [color, depth] = settings.clear_values();
+        let gbuffer = vk::ClearValue {
+            color: vk::ClearColorValue {
+                float32: [0.0, 0.0, 0.0, 0.0],
+            },
+        };
+
+        Some(color)
+            .into_iter()
+            .chain(GBUFFER_FORMATS.iter().map(|_| gbuffer))
+            .chain(Some(depth))
+            .collect()
+    }
+
+    // Records both subpasses for the image, drawing the mesh with the scene set the
+    // scene pass would have bound
+    pub fn record(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        index: u32,
+        scene_layout: vk::PipelineLayout,
+        scene_set: vk::DescriptorSet,
+        dynamic_offsets: &[u32],
+        pool: &mesh_pool::MeshPool,
+        draws: &[material::Draw],
+        surface_extent: vk::Extent2D,
+        settings: &render_settings::RenderSettings,
+        profiler: &profiler::Profiler,
+        draw_mesh: bool,
+    ) -> Result<()> {
+        let framebuffer = *self
+            .framebuffers
+            .get(index as usize)
+            .ok_or_else(|| Error::OutOfRange(format!("no deferred framebuffer {}", index)))?;
+        let clear_values = DeferredPass::clear_values(settings);
+
+        let render_pass_begin_info = vk::RenderPassBeginInfo {
+            render_pass: self.render_pass,
+            framebuffer,
+            render_area: vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: surface_extent,
+            },
+            clear_value_count: clear_values.len() as u32,
+            p_clear_values: clear_values.as_ptr(),
+            ..Default::default()
+        };
+
+        let viewports = [settings.viewport(surface_extent)];
+        let scissors = [settings.scissor(surface_extent)];
+
+        profiler.cmd_begin(device, command_buffer, index);
+
+        unsafe {
+            device.cmd_begin_render_pass(
+                command_buffer,
+                &render_pass_begin_info,
+                vk::SubpassContents::INLINE,
+            );
+
+            device.cmd_set_viewport(command_buffer, 0, &viewports);
+            device.cmd_set_scissor(command_buffer, 0, &scissors);
+
+            // a culled mesh leaves the G-buffer cleared, the lighting subpass keeps the
+            // clear color then
+            if draw_mesh {
+                device.cmd_bind_pipeline(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    self.geometry_pipeline,
+                );
+                device.cmd_bind_vertex_buffers(
+                    command_buffer,
+                    0,
+                    &[pool.vertex_buffer.buffer],
+                    &[0],
+                );
+                device.cmd_bind_index_buffer(
+                    command_buffer,
+                    pool.index_buffer.buffer(),
+                    0,
+                    pool.index_buffer.index_type(),
+                );
+                device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    scene_layout,
+                    0,
+                    &[scene_set],
+                    dynamic_offsets,
+                );
+
+                for draw in draws.iter() {
+                    device.cmd_draw_indexed(
+                        command_buffer,
+                        draw.index_count,
+                        draw.instance_count,
+                        draw.first_index,
+                        draw.vertex_offset,
+                        0,
+                    );
+                }
+            }
+
+            device.cmd_next_subpass(command_buffer, vk::SubpassContents::INLINE);
+
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.lighting_pipeline,
+            );
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.lighting_layout,
+                0,
+                &[self.lighting_sets[index as usize]],
+                &[],
+            );
+            device.cmd_draw(command_buffer, 3, 1, 0, 0);
+
+            device.cmd_end_render_pass(command_buffer);
+        }
+
+        profiler.cmd_end(device, command_buffer, index);
+        Ok(())
+    }
+
+    // The device must be idle
+    pub fn destroy(&self, device: &device::Device) {
+        let logical_device = &device.logical_device;
+
+        for &framebuffer in self.framebuffers.iter() {
+            device.untrack(framebuffer);
+            unsafe { logical_device.destroy_framebuffer(framebuffer, None) };
+        }
+
+        for &pipeline in [self.geometry_pipeline, self.lighting_pipeline].iter() {
+            device.untrack(pipeline);
+            unsafe { logical_device.destroy_pipeline(pipeline, None) };
+        }
+
+        device.untrack(self.lighting_layout);
+        device.untrack(self.lighting_set_layout);
+        device.untrack(self.descriptor_pool);
+        device.untrack(self.render_pass);
+        unsafe {
+            logical_device.destroy_pipeline_layout(self.lighting_layout, None);
+            logical_device.destroy_descriptor_set_layout(self.lighting_set_layout, None);
+            logical_device.destroy_descriptor_pool(self.descriptor_pool, None);
+            logical_device.destroy_render_pass(self.render_pass, None);
+        }
+
+        for image in self.gbuffers.iter().flatten() {
+            image.destroy(device);
+        }
+        self.depth_buffer.image.destroy(device);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lighting_reads_what_geometry_writes() {
+        let subpasses = subpasses();
+        let (geometry, lighting) = (&subpasses[0], &subpasses[1]);
+
+        assert_eq!(geometry.color, lighting.input);
+        assert_eq!(lighting.color, vec![COLOR_ATTACHMENT]);
+        assert!(lighting.depth.is_none());
+
+        // color target, G-buffer and depth
+        let attachment_count = GBUFFER_FORMATS.len() + 2;
+        assert!(geometry.validate(attachment_count).is_ok());
+        assert!(lighting.validate(attachment_count).is_ok());
+        assert!(geometry.validate(attachment_count - 1).is_err());
+    }
+
+    #[test]
+    fn input_attachments_are_bound_in_attachment_order() {
+        let bindings = lighting_bindings();
+
+        let inputs = bindings
+            .iter()
+            .filter(|binding| binding.descriptor_type == vk::DescriptorType::INPUT_ATTACHMENT)
+            .map(|binding| binding.binding + 1)
+            .collect::<Vec<u32>>();
+        assert_eq!(inputs, subpasses()[1].input);
+
+        let lights = descriptor::find(&bindings, LIGHTS_BINDING).unwrap();
+        assert_eq!(lights.descriptor_type, vk::DescriptorType::UNIFORM_BUFFER);
+    }
+
+    #[test]
+    fn support_depends_on_attachment_limits() {
+        let mut limits = vk::PhysicalDeviceLimits {
+            max_color_attachments: 8,
+            max_per_stage_descriptor_input_attachments: 4,
+            ..Default::default()
+        };
+        assert!(DeferredPass::is_supported(&limits));
+
+        limits.max_per_stage_descriptor_input_attachments = 2;
+        assert!(!DeferredPass::is_supported(&limits));
+    }
+}
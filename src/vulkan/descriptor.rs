@@ -0,0 +1,356 @@
+use ash::version::DeviceV1_0;
+use ash::vk;
+
+use crate::error::{Context, Error, Result};
+
+// A binding of a descriptor set layout. The layout, the pool sizes and the writes of a
+// set are all derived from the same bindings so they cannot disagree.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Binding {
+    pub binding: u32,
+    pub descriptor_type: vk::DescriptorType,
+    pub count: u32,
+    pub stage_flags: vk::ShaderStageFlags,
+}
+
+impl Binding {
+    pub fn new(
+        binding: u32,
+        descriptor_type: vk::DescriptorType,
+        stage_flags: vk::ShaderStageFlags,
+    ) -> Binding {
+        Binding {
+            binding,
+            descriptor_type,
+            count: 1,
+            stage_flags,
+        }
+    }
+
+    pub fn uniform_buffer(binding: u32, stage_flags: vk::ShaderStageFlags) -> Binding {
+        Binding::new(binding, vk::DescriptorType::UNIFORM_BUFFER, stage_flags)
+    }
+
+    pub fn storage_buffer(binding: u32, stage_flags: vk::ShaderStageFlags) -> Binding {
+        Binding::new(binding, vk::DescriptorType::STORAGE_BUFFER, stage_flags)
+    }
+
+    // Bound with an offset given to cmd_bind_descriptor_sets, see object_uniforms
+    pub fn uniform_buffer_dynamic(binding: u32, stage_flags: vk::ShaderStageFlags) -> Binding {
+        Binding::new(
+            binding,
+            vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+            stage_flags,
+        )
+    }
+
+    pub fn combined_image_sampler(binding: u32, stage_flags: vk::ShaderStageFlags) -> Binding {
+        Binding::new(
+            binding,
+            vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            stage_flags,
+        )
+    }
+
+    // Read with subpassLoad from an attachment of the current render pass
+    pub fn input_attachment(binding: u32) -> Binding {
+        Binding::new(
+            binding,
+            vk::DescriptorType::INPUT_ATTACHMENT,
+            vk::ShaderStageFlags::FRAGMENT,
+        )
+    }
+
+    // Array of descriptors in the binding, indexed in the shader
+    pub fn with_count(mut self, count: u32) -> Binding {
+        self.count = count;
+        self
+    }
+
+    pub fn is_buffer(&self) -> bool {
+        match self.descriptor_type {
+            vk::DescriptorType::UNIFORM_BUFFER
+            | vk::DescriptorType::STORAGE_BUFFER
+            | vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC
+            | vk::DescriptorType::STORAGE_BUFFER_DYNAMIC => true,
+            _ => false,
+        }
+    }
+
+    pub fn is_dynamic(&self) -> bool {
+        self.descriptor_type == vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC
+            || self.descriptor_type == vk::DescriptorType::STORAGE_BUFFER_DYNAMIC
+    }
+
+    pub fn layout_binding(&self) -> vk::DescriptorSetLayoutBinding {
+        vk::DescriptorSetLayoutBinding {
+            binding: self.binding,
+            descriptor_type: self.descriptor_type,
+            descriptor_count: self.count,
+            stage_flags: self.stage_flags,
+            ..Default::default()
+        }
+    }
+
+    // Points the binding at the buffer ranges, starting from its first element.
+    // The write refers to the infos, which have to outlive it.
+    pub fn write_buffers(
+        &self,
+        set: vk::DescriptorSet,
+        buffer_infos: &[vk::DescriptorBufferInfo],
+    ) -> Result<vk::WriteDescriptorSet> {
+        if !self.is_buffer() {
+            return Err(Error::msg(format!(
+                "binding {} holds {:?} descriptors, not buffers",
+                self.binding, self.descriptor_type
+            )));
+        }
+
+        self.check_count(buffer_infos.len())?;
+
+        Ok(vk::WriteDescriptorSet {
+            dst_set: set,
+            dst_binding: self.binding,
+            dst_array_element: 0,
+            descriptor_type: self.descriptor_type,
+            descriptor_count: buffer_infos.len() as u32,
+            p_buffer_info: buffer_infos.as_ptr(),
+            ..Default::default()
+        })
+    }
+
+    pub fn write_images(
+        &self,
+        set: vk::DescriptorSet,
+        image_infos: &[vk::DescriptorImageInfo],
+    ) -> Result<vk::WriteDescriptorSet> {
+        if self.is_buffer() {
+            return Err(Error::msg(format!(
+                "binding {} holds {:?} descriptors, not images",
+                self.binding, self.descriptor_type
+            )));
+        }
+
+        self.check_count(image_infos.len())?;
+
+        Ok(vk::WriteDescriptorSet {
+            dst_set: set,
+            dst_binding: self.binding,
+            dst_array_element: 0,
+            descriptor_type: self.descriptor_type,
+            descriptor_count: image_infos.len() as u32,
+            p_image_info: image_infos.as_ptr(),
+            ..Default::default()
+        })
+    }
+
+    fn check_count(&self, len: usize) -> Result<()> {
+        if len == 0 || len > self.count as usize {
+            return Err(Error::OutOfRange(format!(
+                "cannot write {} descriptors to binding {} of {}",
+                len, self.binding, self.count
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+// Binding numbers have to be unique within a set
+pub fn validate(bindings: &[Binding]) -> Result<()> {
+    for (index, binding) in bindings.iter().enumerate() {
+        if bindings[..index]
+            .iter()
+            .any(|other| other.binding == binding.binding)
+        {
+            return Err(Error::msg(format!(
+                "binding {} is declared twice in the descriptor set",
+                binding.binding
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+pub fn find(bindings: &[Binding], binding: u32) -> Result<&Binding> {
+    bindings
+        .iter()
+        .find(|b| b.binding == binding)
+        .ok_or_else(|| Error::OutOfRange(format!("the set has no binding {}", binding)))
+}
+
+// Bindings taking dynamic offsets, in the order the offsets are passed when binding the set
+pub fn dynamic_bindings(bindings: &[Binding]) -> Vec<Binding> {
+    let mut dynamic = bindings
+        .iter()
+        .filter(|binding| binding.is_dynamic())
+        .cloned()
+        .collect::<Vec<Binding>>();
+
+    dynamic.sort_by_key(|binding| binding.binding);
+    dynamic
+}
+
+pub fn create_set_layout(
+    device: &ash::Device,
+    bindings: &[Binding],
+) -> Result<vk::DescriptorSetLayout> {
+    validate(bindings)?;
+
+    let layout_bindings = bindings
+        .iter()
+        .map(Binding::layout_binding)
+        .collect::<Vec<vk::DescriptorSetLayoutBinding>>();
+
+    let layout_info = vk::DescriptorSetLayoutCreateInfo {
+        binding_count: layout_bindings.len() as u32,
+        p_bindings: layout_bindings.as_ptr(),
+        ..Default::default()
+    };
+
+    unsafe {
+        device
+            .create_descriptor_set_layout(&layout_info, None)
+            .context("failed to create descriptor set layout")
+    }
+}
+
+// Descriptors needed for num_sets sets of the bindings, one pool size per descriptor type
+pub fn pool_sizes(bindings: &[Binding], num_sets: u32) -> Vec<vk::DescriptorPoolSize> {
+    let mut pool_sizes: Vec<vk::DescriptorPoolSize> = vec![];
+
+    for binding in bindings.iter() {
+        match pool_sizes
+            .iter_mut()
+            .find(|size| size.ty == binding.descriptor_type)
+        {
+            Some(size) => size.descriptor_count += binding.count * num_sets,
+            None => pool_sizes.push(vk::DescriptorPoolSize {
+                ty: binding.descriptor_type,
+                descriptor_count: binding.count * num_sets,
+            }),
+        }
+    }
+
+    pool_sizes
+}
+
+pub fn create_pool(
+    device: &ash::Device,
+    bindings: &[Binding],
+    num_sets: u32,
+) -> Result<vk::DescriptorPool> {
+    let pool_sizes = pool_sizes(bindings, num_sets);
+
+    let pool_info = vk::DescriptorPoolCreateInfo {
+        pool_size_count: pool_sizes.len() as u32,
+        p_pool_sizes: pool_sizes.as_ptr(),
+        max_sets: num_sets,
+        ..Default::default()
+    };
+
+    unsafe {
+        device
+            .create_descriptor_pool(&pool_info, None)
+            .context("failed to create descriptor pool")
+    }
+}
+
+pub fn allocate_sets(
+    device: &ash::Device,
+    pool: vk::DescriptorPool,
+    layout: vk::DescriptorSetLayout,
+    num_sets: usize,
+) -> Result<Vec<vk::DescriptorSet>> {
+    let layouts = vec![layout; num_sets];
+
+    let alloc_info = vk::DescriptorSetAllocateInfo {
+        descriptor_pool: pool,
+        descriptor_set_count: num_sets as u32,
+        p_set_layouts: layouts.as_ptr(),
+        ..Default::default()
+    };
+
+    unsafe {
+        device
+            .allocate_descriptor_sets(&alloc_info)
+            .context("failed to allocate descriptor sets")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pool_sizes_are_grouped_by_type() {
+        let bindings = [
+            Binding::uniform_buffer(0, vk::ShaderStageFlags::VERTEX),
+            Binding::combined_image_sampler(1, vk::ShaderStageFlags::FRAGMENT).with_count(3),
+            Binding::uniform_buffer(2, vk::ShaderStageFlags::FRAGMENT),
+            Binding::storage_buffer(3, vk::ShaderStageFlags::VERTEX),
+        ];
+
+        let sizes = pool_sizes(&bindings, 4);
+        let count = |ty| {
+            sizes
+                .iter()
+                .find(|size| size.ty == ty)
+                .map(|size| size.descriptor_count)
+        };
+
+        assert_eq!(sizes.len(), 3);
+        assert_eq!(count(vk::DescriptorType::UNIFORM_BUFFER), Some(8));
+        assert_eq!(count(vk::DescriptorType::COMBINED_IMAGE_SAMPLER), Some(12));
+        assert_eq!(count(vk::DescriptorType::STORAGE_BUFFER), Some(4));
+    }
+
+    #[test]
+    fn duplicate_bindings_are_rejected() {
+        let bindings = [
+            Binding::uniform_buffer(0, vk::ShaderStageFlags::VERTEX),
+            Binding::storage_buffer(0, vk::ShaderStageFlags::VERTEX),
+        ];
+
+        assert!(validate(&bindings).is_err());
+        assert!(validate(&bindings[..1]).is_ok());
+    }
+
+    #[test]
+    fn dynamic_offsets_follow_binding_order() {
+        let bindings = [
+            Binding::uniform_buffer_dynamic(5, vk::ShaderStageFlags::VERTEX),
+            Binding::uniform_buffer(0, vk::ShaderStageFlags::VERTEX),
+            Binding::uniform_buffer_dynamic(3, vk::ShaderStageFlags::FRAGMENT),
+        ];
+
+        let order = dynamic_bindings(&bindings)
+            .iter()
+            .map(|binding| binding.binding)
+            .collect::<Vec<u32>>();
+        assert_eq!(order, vec![3, 5]);
+    }
+
+    #[test]
+    fn writes_match_the_binding_kind() {
+        let storage = Binding::storage_buffer(4, vk::ShaderStageFlags::COMPUTE);
+        let buffer_info = [vk::DescriptorBufferInfo::default()];
+
+        let write = storage
+            .write_buffers(vk::DescriptorSet::null(), &buffer_info)
+            .unwrap();
+        assert_eq!(write.dst_binding, 4);
+        assert_eq!(write.descriptor_type, vk::DescriptorType::STORAGE_BUFFER);
+
+        assert!(storage
+            .write_images(
+                vk::DescriptorSet::null(),
+                &[vk::DescriptorImageInfo::default()]
+            )
+            .is_err());
+        assert!(storage
+            .write_buffers(vk::DescriptorSet::null(), &[buffer_info[0], buffer_info[0]])
+            .is_err());
+    }
+}
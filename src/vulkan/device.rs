@@ -1,25 +1,59 @@
-use ash::version::InstanceV1_0;
+use ash::version::{DeviceV1_0, InstanceV1_0};
 use ash::vk;
 use std::os::raw::c_char;
 
 use crate::foreign;
 
+use super::adapter;
+use super::bindless;
 use super::constants::*;
+use super::dynamic_rendering;
+use super::gpu_culling;
+use super::material;
+use super::portability;
 use super::queue;
+use super::registry;
+use super::requirements;
+use super::stereo;
 use super::surface;
 use super::swapchain;
+use super::timeline;
+use super::trace;
 
-use anyhow::anyhow;
-use anyhow::{Context, Result};
+use crate::error::{Context, Error, Result};
 
 use std::collections::HashSet;
-use std::ffi::CString;
+use std::ffi::{CStr, CString};
+use std::sync::{Arc, Mutex};
 
+#[derive(Clone)]
 pub struct Device {
     pub physical_device: vk::PhysicalDevice,
     pub logical_device: ash::Device,
     pub memory_properties: vk::PhysicalDeviceMemoryProperties,
+    pub limits: vk::PhysicalDeviceLimits,
+    // the features that were enabled on the logical device, see is_feature_enabled
+    pub features: vk::PhysicalDeviceFeatures,
+    // every extension enabled on the logical device
+    pub extensions: Vec<String>,
+    // VK_KHR_timeline_semaphore is enabled, see vulkan::timeline
+    pub timeline_semaphore: bool,
+    // VK_EXT_descriptor_indexing is enabled with the features bindless textures need,
+    // see vulkan::bindless
+    pub descriptor_indexing: bool,
+    // VK_KHR_draw_indirect_count is enabled, see vulkan::gpu_culling
+    pub draw_indirect_count: bool,
+    // VK_KHR_dynamic_rendering is enabled, see vulkan::dynamic_rendering
+    pub dynamic_rendering: bool,
+    // multiview render passes can be created, see vulkan::stereo
+    pub multiview: bool,
+    // set on portability implementations like MoltenVK, with what they lack of vulkan
+    pub portability: Option<portability::PortabilityLimits>,
     pub family_indices: queue::FamilyIndices,
+    // shared between clones so resources created on other threads are tracked too
+    pub resources: Arc<Mutex<registry::ResourceRegistry>>,
+    // names objects for validation messages and captures when set
+    debug_utils: Option<ash::extensions::ext::DebugUtils>,
 }
 
 pub struct DeviceExtension {
@@ -37,24 +71,28 @@ impl DeviceExtension {
 }
 
 impl Device {
-    pub fn check_device_extension_support(
+    pub fn available_extensions(
         instance: &ash::Instance,
         physical_device: vk::PhysicalDevice,
-        device_extensions: &DeviceExtension,
-    ) -> Result<bool> {
+    ) -> Result<HashSet<String>> {
         let available_extensions = unsafe {
             instance
                 .enumerate_device_extension_properties(physical_device)
                 .context("Failed to get device extension properties.")
         }?;
 
-        let mut available_extension_names = HashSet::new();
-
-        for extension in available_extensions.iter() {
-            let extension_name = foreign::vk_to_string(&extension.extension_name);
+        Ok(available_extensions
+            .iter()
+            .map(|extension| foreign::vk_to_string(&extension.extension_name))
+            .collect())
+    }
 
-            available_extension_names.insert(extension_name);
-        }
+    pub fn check_device_extension_support(
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        device_extensions: &DeviceExtension,
+    ) -> Result<bool> {
+        let available_extension_names = Device::available_extensions(instance, physical_device)?;
 
         let mut required_extensions = HashSet::new();
         // can directly convert device_extensions to set and check for subset, but for now it's fine
@@ -65,12 +103,14 @@ impl Device {
         return Ok(available_extension_names.is_superset(&required_extensions));
     }
 
-    fn is_physical_device_suitable(
+    pub fn is_physical_device_suitable(
         instance: &ash::Instance,
         physical_device: vk::PhysicalDevice,
         surface_info: &surface::SurfaceInfo,
+        requirements: &requirements::DeviceRequirements,
     ) -> Result<bool> {
         let device_features = unsafe { instance.get_physical_device_features(physical_device) };
+        let available_extensions = Device::available_extensions(instance, physical_device)?;
 
         let indices = queue::FamilyIndices::new(instance, physical_device, surface_info);
 
@@ -86,43 +126,47 @@ impl Device {
             false
         };
 
-        let is_support_sampler_anisotropy = device_features.sampler_anisotropy == 1;
+        let missing = requirements.missing(&device_features, &available_extensions);
+        if !missing.is_empty() {
+            tracing::debug!(?physical_device, ?missing, "device lacks required features");
+        }
 
         return Ok(is_queue_family_supported
             && is_device_extension_supported
             && is_swapchain_supported
-            && is_support_sampler_anisotropy);
+            && missing.is_empty());
     }
 
     fn pick_physical_device(
         instance: &ash::Instance,
         surface_info: &surface::SurfaceInfo,
+        selection: &adapter::DeviceSelection,
+        requirements: &requirements::DeviceRequirements,
     ) -> Result<vk::PhysicalDevice> {
-        let physical_devices = unsafe { instance.enumerate_physical_devices() }?;
+        let adapters = adapter::enumerate_adapters(instance, surface_info, requirements)?;
+        let selected = adapter::select_adapter(&adapters, selection)?;
 
-        physical_devices
-            .iter()
-            .flat_map(|physical_device| {
-                Device::is_physical_device_suitable(instance, *physical_device, surface_info)
-                    .and_then(|is_suitable| {
-                        if is_suitable {
-                            Ok(physical_device)
-                        } else {
-                            Err(anyhow!("device not suitable"))
-                        }
-                    })
-            })
-            .collect::<Vec<&vk::PhysicalDevice>>()
-            .first()
-            .map(|physical_device| **physical_device)
-            .ok_or(anyhow!("failed to find a gpu"))
+        tracing::info!("using gpu {}: {}", selected.index, selected.name);
+        Ok(selected.physical_device)
     }
 
     fn create_logical_device(
         instance: &ash::Instance,
         physical_device: vk::PhysicalDevice,
         surface_info: &surface::SurfaceInfo,
-    ) -> Result<(ash::Device, queue::FamilyIndices)> {
+        requirements: &requirements::DeviceRequirements,
+    ) -> Result<(
+        ash::Device,
+        queue::FamilyIndices,
+        vk::PhysicalDeviceFeatures,
+        Vec<String>,
+        bool,
+        bool,
+        bool,
+        bool,
+        bool,
+        Option<portability::PortabilityLimits>,
+    )> {
         let indices = queue::FamilyIndices::new(instance, physical_device, surface_info);
         let unique_families = indices.get_unique();
 
@@ -140,12 +184,141 @@ impl Device {
             })
             .collect();
 
-        let physical_device_features = vk::PhysicalDeviceFeatures {
-            sampler_anisotropy: vk::TRUE,
-            ..Default::default()
+        let supported_features = unsafe { instance.get_physical_device_features(physical_device) };
+        let physical_device_features = requirements.features_to_enable(&supported_features);
+
+        let available_extensions = Device::available_extensions(instance, physical_device)?;
+        let extensions = requirements
+            .extensions_to_enable(&available_extensions)
+            .into_iter()
+            .map(|name| CString::new(name).context("invalid extension name"))
+            .collect::<Result<Vec<CString>>>()?;
+
+        // timeline semaphores are enabled when available, frames only use them on request
+        let timeline_semaphore =
+            timeline::TimelineSemaphore::is_supported(instance, physical_device)?;
+        let timeline_extension = CString::new(timeline::TIMELINE_SEMAPHORE_EXTENSION.names[0])
+            .context("invalid extension name")?;
+        let timeline_features = timeline::device_features();
+
+        // enabled when available, only bindless texture arrays use it
+        let descriptor_indexing = bindless::is_supported(instance, physical_device)?;
+        let indexing_extension = CString::new(material::DESCRIPTOR_INDEXING_EXTENSION.names[0])
+            .context("invalid extension name")?;
+        let mut indexing_features = bindless::device_features();
+
+        // without it culled draws are skipped by their instance count instead
+        let draw_indirect_count = gpu_culling::is_supported(instance, physical_device)?;
+        let indirect_count_extension =
+            CString::new(gpu_culling::DRAW_INDIRECT_COUNT_EXTENSION.names[0])
+                .context("invalid extension name")?;
+
+        // scenes are rendered without render pass objects when available
+        let dynamic_rendering = dynamic_rendering::is_supported(instance, physical_device)?;
+        let dynamic_rendering_extensions = dynamic_rendering::DYNAMIC_RENDERING_EXTENSIONS
+            .iter()
+            .map(|extension| CString::new(extension.names[0]).context("invalid extension name"))
+            .collect::<Result<Vec<CString>>>()?;
+        let mut dynamic_rendering_features = dynamic_rendering::device_features();
+
+        // only stereo rendering draws to more than one view
+        let multiview = stereo::is_supported(instance, physical_device);
+        let mut multiview_features = stereo::device_features();
+
+        // the subset has to be enabled when the device exposes it
+        let portability = if portability::is_supported(instance, physical_device)? {
+            Some(portability::PortabilityLimits::query(
+                instance,
+                physical_device,
+            ))
+        } else {
+            None
+        };
+        let portability_extension =
+            CString::new(portability::PORTABILITY_SUBSET_EXTENSION.names[0])
+                .context("invalid extension name")?;
+        let mut portability_features = portability
+            .map(|limits| limits.device_features())
+            .unwrap_or_default();
+
+        let mut extension_names = DEVICE_EXTENSIONS.get_raw_names().to_vec();
+        if timeline_semaphore {
+            extension_names.push(timeline_extension.as_ptr());
+        }
+        if descriptor_indexing {
+            extension_names.push(indexing_extension.as_ptr());
+        }
+        if draw_indirect_count {
+            extension_names.push(indirect_count_extension.as_ptr());
+        }
+        if dynamic_rendering {
+            extension_names.extend(
+                dynamic_rendering_extensions
+                    .iter()
+                    .map(|name| name.as_ptr()),
+            );
+        }
+        if portability.is_some() {
+            extension_names.push(portability_extension.as_ptr());
+        }
+        for extension in extensions.iter() {
+            if !extension_names
+                .iter()
+                .any(|&name| unsafe { CStr::from_ptr(name) } == extension.as_c_str())
+            {
+                extension_names.push(extension.as_ptr());
+            }
+        }
+
+        let enabled_extensions = extension_names
+            .iter()
+            .map(|&name| {
+                unsafe { CStr::from_ptr(name) }
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect::<Vec<String>>();
+
+        // the feature structs of the enabled extensions are chained together
+        let timeline_next = if timeline_semaphore {
+            &timeline_features as *const timeline::PhysicalDeviceTimelineSemaphoreFeatures
+                as *const std::os::raw::c_void
+        } else {
+            std::ptr::null()
+        };
+
+        let features_next = if descriptor_indexing {
+            indexing_features.p_next = timeline_next as *mut std::os::raw::c_void;
+            &indexing_features as *const vk::PhysicalDeviceDescriptorIndexingFeaturesEXT
+                as *const std::os::raw::c_void
+        } else {
+            timeline_next
         };
 
-        let extension_names = &DEVICE_EXTENSIONS.get_raw_names();
+        let features_next = if dynamic_rendering {
+            dynamic_rendering_features.p_next = features_next as *mut std::os::raw::c_void;
+            &dynamic_rendering_features
+                as *const dynamic_rendering::PhysicalDeviceDynamicRenderingFeatures
+                as *const std::os::raw::c_void
+        } else {
+            features_next
+        };
+
+        let features_next = if multiview {
+            multiview_features.p_next = features_next as *mut std::os::raw::c_void;
+            &multiview_features as *const vk::PhysicalDeviceMultiviewFeatures
+                as *const std::os::raw::c_void
+        } else {
+            features_next
+        };
+
+        let features_next = if portability.is_some() {
+            portability_features.p_next = features_next as *mut std::os::raw::c_void;
+            &portability_features as *const portability::PhysicalDevicePortabilitySubsetFeatures
+                as *const std::os::raw::c_void
+        } else {
+            features_next
+        };
 
         // let enabled_layers = EnabledLayers::query();
 
@@ -174,7 +347,7 @@ impl Device {
 
         let device_create_info = vk::DeviceCreateInfo {
             s_type: vk::StructureType::DEVICE_CREATE_INFO,
-            p_next: std::ptr::null(),
+            p_next: features_next,
             flags: vk::DeviceCreateFlags::empty(),
             queue_create_info_count: queue_create_infos.len() as u32,
             p_queue_create_infos: queue_create_infos.as_ptr(),
@@ -185,12 +358,57 @@ impl Device {
             p_enabled_features: &physical_device_features,
         };
 
-        unsafe {
+        trace::call("vkCreateDevice", &device_create_info, || unsafe {
             instance
                 .create_device(physical_device, &device_create_info, None)
                 .context("failed to create logical device")
-        }
-        .map(|device| (device, indices))
+        })
+        .map(|device| {
+            (
+                device,
+                indices,
+                physical_device_features,
+                enabled_extensions,
+                timeline_semaphore,
+                descriptor_indexing,
+                draw_indirect_count,
+                dynamic_rendering,
+                multiview,
+                portability,
+            )
+        })
+    }
+
+    // Compute work is recorded in the graphics command buffers, so no separate queue is needed
+    pub fn graphics_queue_supports_compute(&self, instance: &ash::Instance) -> bool {
+        self.family_indices
+            .graphics
+            .and_then(|index| {
+                unsafe {
+                    instance.get_physical_device_queue_family_properties(self.physical_device)
+                }
+                .get(index as usize)
+                .map(|family| family.queue_flags.contains(vk::QueueFlags::COMPUTE))
+            })
+            .unwrap_or(false)
+    }
+
+    pub fn is_feature_enabled(&self, feature: requirements::DeviceFeature) -> bool {
+        feature.is_set(&self.features)
+    }
+
+    pub fn is_extension_enabled(&self, name: &str) -> bool {
+        self.extensions.iter().any(|extension| extension == name)
+    }
+
+    // Whether vertex buffers can hold attributes of the format
+    pub fn supports_vertex_format(&self, instance: &ash::Instance, format: vk::Format) -> bool {
+        let format_properties =
+            unsafe { instance.get_physical_device_format_properties(self.physical_device, format) };
+
+        format_properties
+            .buffer_features
+            .contains(vk::FormatFeatureFlags::VERTEX_BUFFER)
     }
 
     pub fn are_properties_supported(
@@ -207,7 +425,7 @@ impl Device {
                     && memory_type.property_flags.contains(required_properties)
             })
             .map(|(i, _)| i as u32)
-            .ok_or(anyhow!("failed to find suitable memory type"))
+            .ok_or_else(|| Error::Unsupported("failed to find suitable memory type".to_string()))
     }
 
     pub fn find_supported_format<'a>(
@@ -232,20 +450,155 @@ impl Device {
             .context("could not find supported format")
     }
 
-    pub fn new(instance: &ash::Instance, surface_info: &surface::SurfaceInfo) -> Result<Device> {
-        let physical_device = Device::pick_physical_device(instance, surface_info)?;
+    pub fn new(
+        instance: &ash::Instance,
+        surface_info: &surface::SurfaceInfo,
+        selection: &adapter::DeviceSelection,
+    ) -> Result<Device> {
+        Device::with_requirements(
+            instance,
+            surface_info,
+            selection,
+            &requirements::DeviceRequirements::default(),
+        )
+    }
+
+    // With extensions besides the ones the engine enables, eg. what an OpenXR runtime
+    // requires of the device it renders with
+    pub fn with_extensions(
+        instance: &ash::Instance,
+        surface_info: &surface::SurfaceInfo,
+        selection: &adapter::DeviceSelection,
+        extensions: &[CString],
+    ) -> Result<Device> {
+        let requirements = extensions.iter().fold(
+            requirements::DeviceRequirements::default(),
+            |requirements, extension| {
+                requirements.with_required_extension(&extension.to_string_lossy())
+            },
+        );
+
+        Device::with_requirements(instance, surface_info, selection, &requirements)
+    }
+
+    // Only adapters meeting the requirements are selected
+    pub fn with_requirements(
+        instance: &ash::Instance,
+        surface_info: &surface::SurfaceInfo,
+        selection: &adapter::DeviceSelection,
+        requirements: &requirements::DeviceRequirements,
+    ) -> Result<Device> {
+        let physical_device =
+            Device::pick_physical_device(instance, surface_info, selection, requirements)?;
 
         let memory_properties =
             unsafe { instance.get_physical_device_memory_properties(physical_device) };
+        let limits = unsafe { instance.get_physical_device_properties(physical_device) }.limits;
 
-        let (logical_device, family_indices) =
-            Device::create_logical_device(instance, physical_device, surface_info)?;
+        let (
+            logical_device,
+            family_indices,
+            features,
+            extensions,
+            timeline_semaphore,
+            descriptor_indexing,
+            draw_indirect_count,
+            dynamic_rendering,
+            multiview,
+            portability,
+        ) = Device::create_logical_device(instance, physical_device, surface_info, requirements)?;
 
         Ok(Device {
             physical_device,
             logical_device,
             memory_properties,
+            limits,
+            features,
+            extensions,
+            timeline_semaphore,
+            descriptor_indexing,
+            draw_indirect_count,
+            dynamic_rendering,
+            multiview,
+            portability,
             family_indices,
+            resources: Arc::new(Mutex::new(registry::ResourceRegistry::default())),
+            debug_utils: None,
         })
     }
+
+    // Loads a command of an enabled extension that ash does not wrap
+    pub unsafe fn load_function<F: Copy>(&self, instance: &ash::Instance, name: &str) -> Result<F> {
+        let function_name = CString::new(name).context("invalid fn name")?;
+
+        instance
+            .fp_v1_0()
+            .get_device_proc_addr(self.logical_device.handle(), function_name.as_ptr())
+            .map(|function| ::std::mem::transmute_copy(&function))
+            .ok_or_else(|| Error::Unsupported(format!("{} is not available", name)))
+    }
+
+    pub fn with_debug_utils(mut self, debug_utils: &ash::extensions::ext::DebugUtils) -> Device {
+        self.debug_utils = Some(debug_utils.clone());
+        self
+    }
+
+    pub fn resource_snapshot(&self) -> registry::ResourceSnapshot {
+        self.resources
+            .lock()
+            .map(|resources| resources.snapshot())
+            .unwrap_or_else(|poisoned| poisoned.into_inner().snapshot())
+    }
+
+    // Every object created from the device has to be destroyed before this.
+    // Debug builds report the tracked objects that are still alive.
+    pub fn destroy(&self) {
+        if cfg!(debug_assertions) {
+            if let Some(report) = self.resource_snapshot().leak_report() {
+                tracing::warn!("{}", report);
+            }
+        }
+
+        unsafe { self.logical_device.destroy_device(None) };
+    }
+
+    pub fn track<H: vk::Handle>(&self, kind: registry::ResourceKind, handle: H) {
+        if let Ok(mut resources) = self.resources.lock() {
+            resources.register(kind, handle);
+        }
+    }
+
+    pub fn untrack<H: vk::Handle>(&self, handle: H) {
+        if let Ok(mut resources) = self.resources.lock() {
+            resources.unregister(handle.as_raw());
+        }
+    }
+
+    // Shown instead of the raw handle in validation messages and tools like RenderDoc
+    pub fn name_resource<H: vk::Handle>(&self, handle: H, name: &str) {
+        let raw_handle = handle.as_raw();
+
+        if let Ok(mut resources) = self.resources.lock() {
+            resources.set_name(raw_handle, name);
+        }
+
+        let debug_utils = match self.debug_utils.as_ref() {
+            Some(debug_utils) => debug_utils,
+            None => return,
+        };
+
+        if let Ok(object_name) = CString::new(name) {
+            let name_info = vk::DebugUtilsObjectNameInfoEXT {
+                object_type: H::TYPE,
+                object_handle: raw_handle,
+                p_object_name: object_name.as_ptr(),
+                ..Default::default()
+            };
+
+            // names are only a debugging aid, failing to set one is not an error
+            let _ = unsafe {
+                debug_utils.debug_utils_set_object_name(self.logical_device.handle(), &name_info)
+            };
+        }
+    }
 }
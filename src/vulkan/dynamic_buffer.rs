@@ -0,0 +1,251 @@
+use ash::version::DeviceV1_0;
+use ash::vk;
+
+use crate::error::Result;
+
+use super::buffers;
+use super::device;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DynamicMemory {
+    // written directly by the cpu and read over the bus by the gpu
+    HostVisible,
+    // written to a staging buffer and copied to device local memory by `record_copy`
+    Staged,
+}
+
+// A buffer whose contents are replaced every frame, eg. ui, particle or debug line
+// geometry. It grows to the next power of two when the data does not fit anymore.
+// The gpu must be done with the previous contents before `update` is called, so
+// keep one per frame in flight or swapchain image.
+pub struct DynamicBuffer {
+    usage: vk::BufferUsageFlags,
+    memory: DynamicMemory,
+    buffer: Option<buffers::BufferInfo>,
+    staging: Option<buffers::BufferInfo>,
+    len: vk::DeviceSize,
+    pending_copy: bool,
+}
+
+impl DynamicBuffer {
+    pub fn new(usage: vk::BufferUsageFlags, memory: DynamicMemory) -> DynamicBuffer {
+        DynamicBuffer {
+            usage,
+            memory,
+            buffer: None,
+            staging: None,
+            len: 0,
+            pending_copy: false,
+        }
+    }
+
+    pub fn with_capacity(
+        device: &device::Device,
+        usage: vk::BufferUsageFlags,
+        memory: DynamicMemory,
+        capacity: vk::DeviceSize,
+    ) -> Result<DynamicBuffer> {
+        let mut buffer = DynamicBuffer::new(usage, memory);
+        buffer.reserve(device, capacity)?;
+        Ok(buffer)
+    }
+
+    fn host_visible_buffer(
+        device: &device::Device,
+        size: vk::DeviceSize,
+        usage: vk::BufferUsageFlags,
+    ) -> Result<buffers::BufferInfo> {
+        buffers::BufferInfo::create(
+            device,
+            size,
+            usage,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )
+    }
+
+    // Makes sure at least `size` bytes fit, the previous contents are discarded when growing
+    pub fn reserve(&mut self, device: &device::Device, size: vk::DeviceSize) -> Result<()> {
+        if size <= self.capacity() {
+            return Ok(());
+        }
+
+        self.release(device);
+
+        let capacity = size.next_power_of_two();
+        match self.memory {
+            DynamicMemory::HostVisible => {
+                self.buffer = Some(DynamicBuffer::host_visible_buffer(
+                    device, capacity, self.usage,
+                )?);
+            }
+            DynamicMemory::Staged => {
+                self.staging = Some(DynamicBuffer::host_visible_buffer(
+                    device,
+                    capacity,
+                    vk::BufferUsageFlags::TRANSFER_SRC,
+                )?);
+                self.buffer = Some(buffers::BufferInfo::create(
+                    device,
+                    capacity,
+                    vk::BufferUsageFlags::TRANSFER_DST | self.usage,
+                    vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                )?);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Replaces the contents with data, growing the buffer if needed
+    pub fn update<T>(&mut self, device: &device::Device, data: &[T]) -> Result<()> {
+        let size = ::std::mem::size_of_val(data) as vk::DeviceSize;
+        self.reserve(device, size)?;
+
+        self.len = size;
+        if size == 0 {
+            return Ok(());
+        }
+
+        match (self.memory, self.staging.as_ref(), self.buffer.as_ref()) {
+            (DynamicMemory::Staged, Some(staging), _) => {
+                staging.upload(&device.logical_device, data)?;
+                self.pending_copy = true;
+            }
+            (_, _, Some(buffer)) => buffer.upload(&device.logical_device, data)?,
+            _ => (),
+        }
+
+        Ok(())
+    }
+
+    // Overwrites part of the contents, the region has to lie within the current length
+    pub fn update_region<T>(
+        &mut self,
+        device: &ash::Device,
+        offset: vk::DeviceSize,
+        data: &[T],
+    ) -> Result<()> {
+        let target = match self.memory {
+            DynamicMemory::HostVisible => self.buffer.as_ref(),
+            DynamicMemory::Staged => self.staging.as_ref(),
+        };
+
+        if let Some(target) = target {
+            target.update_region(device, offset, data)?;
+            self.pending_copy = self.memory == DynamicMemory::Staged;
+        }
+
+        Ok(())
+    }
+
+    // Records the staging copy if the contents changed, has to be recorded before the
+    // commands reading the buffer. Does nothing for host visible buffers.
+    pub fn record_copy(&mut self, device: &ash::Device, command_buffer: vk::CommandBuffer) {
+        let (staging, buffer) = match (self.pending_copy, &self.staging, &self.buffer) {
+            (true, Some(staging), Some(buffer)) => (staging, buffer),
+            _ => return,
+        };
+
+        let copy_regions = [vk::BufferCopy {
+            src_offset: 0,
+            dst_offset: 0,
+            size: self.len,
+        }];
+
+        let (dst_access_mask, dst_stage) = DynamicBuffer::read_access(self.usage);
+        let barrier = vk::BufferMemoryBarrier {
+            src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+            dst_access_mask,
+            src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            buffer: buffer.buffer,
+            offset: 0,
+            size: self.len,
+            ..Default::default()
+        };
+
+        unsafe {
+            device.cmd_copy_buffer(command_buffer, staging.buffer, buffer.buffer, &copy_regions);
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                dst_stage,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[barrier],
+                &[],
+            );
+        }
+
+        self.pending_copy = false;
+    }
+
+    // How the gpu reads a buffer bound with the usage
+    fn read_access(usage: vk::BufferUsageFlags) -> (vk::AccessFlags, vk::PipelineStageFlags) {
+        let mut access = vk::AccessFlags::empty();
+        let mut stages = vk::PipelineStageFlags::empty();
+
+        if usage.contains(vk::BufferUsageFlags::VERTEX_BUFFER) {
+            access |= vk::AccessFlags::VERTEX_ATTRIBUTE_READ;
+            stages |= vk::PipelineStageFlags::VERTEX_INPUT;
+        }
+        if usage.contains(vk::BufferUsageFlags::INDEX_BUFFER) {
+            access |= vk::AccessFlags::INDEX_READ;
+            stages |= vk::PipelineStageFlags::VERTEX_INPUT;
+        }
+        if usage.contains(vk::BufferUsageFlags::INDIRECT_BUFFER) {
+            access |= vk::AccessFlags::INDIRECT_COMMAND_READ;
+            stages |= vk::PipelineStageFlags::DRAW_INDIRECT;
+        }
+        if usage
+            .intersects(vk::BufferUsageFlags::UNIFORM_BUFFER | vk::BufferUsageFlags::STORAGE_BUFFER)
+        {
+            access |= vk::AccessFlags::UNIFORM_READ | vk::AccessFlags::SHADER_READ;
+            stages |= vk::PipelineStageFlags::VERTEX_SHADER
+                | vk::PipelineStageFlags::FRAGMENT_SHADER
+                | vk::PipelineStageFlags::COMPUTE_SHADER;
+        }
+
+        if stages.is_empty() {
+            (
+                vk::AccessFlags::MEMORY_READ,
+                vk::PipelineStageFlags::ALL_COMMANDS,
+            )
+        } else {
+            (access, stages)
+        }
+    }
+
+    // The buffer to bind, none until data has been written
+    pub fn buffer(&self) -> Option<vk::Buffer> {
+        self.buffer.as_ref().map(|buffer| buffer.buffer)
+    }
+
+    // Bytes written by the last update
+    pub fn len(&self) -> vk::DeviceSize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn capacity(&self) -> vk::DeviceSize {
+        self.buffer
+            .as_ref()
+            .map(|buffer| buffer.size())
+            .unwrap_or(0)
+    }
+
+    fn release(&mut self, device: &device::Device) {
+        for buffer in self.buffer.take().into_iter().chain(self.staging.take()) {
+            buffer.destroy(device);
+        }
+        self.len = 0;
+        self.pending_copy = false;
+    }
+
+    pub fn destroy(&mut self, device: &device::Device) {
+        self.release(device);
+    }
+}
@@ -0,0 +1,405 @@
+use ash::version::{DeviceV1_0, InstanceV1_1};
+use ash::vk;
+
+use crate::error::{Error, Result};
+
+use std::os::raw::c_void;
+
+use super::device;
+use super::image;
+
+// VK_KHR_dynamic_rendering and what it depends on beyond vulkan 1.1
+pub const DYNAMIC_RENDERING_EXTENSIONS: [device::DeviceExtension; 3] = [
+    device::DeviceExtension {
+        names: ["VK_KHR_dynamic_rendering"],
+    },
+    device::DeviceExtension {
+        names: ["VK_KHR_depth_stencil_resolve"],
+    },
+    device::DeviceExtension {
+        names: ["VK_KHR_create_renderpass2"],
+    },
+];
+
+// ash 0.29 predates VK_KHR_dynamic_rendering, so the parts of it used here are
+// declared by hand following vulkan_core.h
+const RENDERING_INFO: i32 = 1_000_044_000;
+const RENDERING_ATTACHMENT_INFO: i32 = 1_000_044_001;
+const PIPELINE_RENDERING_CREATE_INFO: i32 = 1_000_044_002;
+const PHYSICAL_DEVICE_DYNAMIC_RENDERING_FEATURES: i32 = 1_000_044_003;
+const RESOLVE_MODE_NONE: u32 = 0;
+
+#[repr(C)]
+pub struct PhysicalDeviceDynamicRenderingFeatures {
+    s_type: vk::StructureType,
+    pub p_next: *mut c_void,
+    dynamic_rendering: vk::Bool32,
+}
+
+#[repr(C)]
+pub struct PipelineRenderingCreateInfo {
+    s_type: vk::StructureType,
+    p_next: *const c_void,
+    view_mask: u32,
+    color_attachment_count: u32,
+    p_color_attachment_formats: *const vk::Format,
+    depth_attachment_format: vk::Format,
+    stencil_attachment_format: vk::Format,
+}
+
+#[repr(C)]
+struct RenderingAttachmentInfo {
+    s_type: vk::StructureType,
+    p_next: *const c_void,
+    image_view: vk::ImageView,
+    image_layout: vk::ImageLayout,
+    resolve_mode: u32,
+    resolve_image_view: vk::ImageView,
+    resolve_image_layout: vk::ImageLayout,
+    load_op: vk::AttachmentLoadOp,
+    store_op: vk::AttachmentStoreOp,
+    clear_value: vk::ClearValue,
+}
+
+#[repr(C)]
+struct RenderingInfo {
+    s_type: vk::StructureType,
+    p_next: *const c_void,
+    flags: u32,
+    render_area: vk::Rect2D,
+    layer_count: u32,
+    view_mask: u32,
+    color_attachment_count: u32,
+    p_color_attachments: *const RenderingAttachmentInfo,
+    p_depth_attachment: *const RenderingAttachmentInfo,
+    p_stencil_attachment: *const RenderingAttachmentInfo,
+}
+
+type CmdBeginRendering = unsafe extern "system" fn(vk::CommandBuffer, *const RenderingInfo);
+type CmdEndRendering = unsafe extern "system" fn(vk::CommandBuffer);
+
+fn supported_features(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+) -> PhysicalDeviceDynamicRenderingFeatures {
+    let mut dynamic_rendering = PhysicalDeviceDynamicRenderingFeatures {
+        dynamic_rendering: vk::FALSE,
+        ..device_features()
+    };
+    let mut features = vk::PhysicalDeviceFeatures2 {
+        p_next: &mut dynamic_rendering as *mut PhysicalDeviceDynamicRenderingFeatures
+            as *mut c_void,
+        ..Default::default()
+    };
+
+    unsafe { instance.get_physical_device_features2(physical_device, &mut features) };
+    dynamic_rendering
+}
+
+// Checked when creating the logical device, see device::Device::dynamic_rendering
+pub fn is_supported(instance: &ash::Instance, physical_device: vk::PhysicalDevice) -> Result<bool> {
+    for extension in DYNAMIC_RENDERING_EXTENSIONS.iter() {
+        if !device::Device::check_device_extension_support(instance, physical_device, extension)? {
+            return Ok(false);
+        }
+    }
+
+    Ok(supported_features(instance, physical_device).dynamic_rendering == vk::TRUE)
+}
+
+// Chained into vk::DeviceCreateInfo to enable dynamic rendering
+pub fn device_features() -> PhysicalDeviceDynamicRenderingFeatures {
+    PhysicalDeviceDynamicRenderingFeatures {
+        s_type: vk::StructureType::from_raw(PHYSICAL_DEVICE_DYNAMIC_RENDERING_FEATURES),
+        p_next: ::std::ptr::null_mut(),
+        dynamic_rendering: vk::TRUE,
+    }
+}
+
+// Formats of the attachments a pipeline without a render pass is drawn into
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RenderingFormats {
+    pub color: vk::Format,
+    pub depth: vk::Format,
+}
+
+impl RenderingFormats {
+    // Chained into vk::GraphicsPipelineCreateInfo in place of the render pass. The info
+    // refers to the formats, which have to outlive it.
+    pub fn pipeline_info(&self) -> PipelineRenderingCreateInfo {
+        PipelineRenderingCreateInfo {
+            s_type: vk::StructureType::from_raw(PIPELINE_RENDERING_CREATE_INFO),
+            p_next: ::std::ptr::null(),
+            view_mask: 0,
+            color_attachment_count: 1,
+            p_color_attachment_formats: &self.color,
+            depth_attachment_format: self.depth,
+            stencil_attachment_format: self.stencil(),
+        }
+    }
+
+    // the depth buffer's stencil aspect, if its format has one
+    fn stencil(&self) -> vk::Format {
+        if image::ImageData::has_stencil_component(self.depth) {
+            self.depth
+        } else {
+            vk::Format::UNDEFINED
+        }
+    }
+}
+
+// The images one frame is rendered into. The color image starts out in any layout and
+// is left in final_layout like the color attachment of a render pass would be.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RenderTarget {
+    pub color_image: vk::Image,
+    pub color_view: vk::ImageView,
+    pub depth_image: vk::Image,
+    pub depth_view: vk::ImageView,
+    pub formats: RenderingFormats,
+    pub final_layout: vk::ImageLayout,
+}
+
+impl RenderTarget {
+    fn depth_aspect(&self) -> vk::ImageAspectFlags {
+        if self.formats.stencil() != vk::Format::UNDEFINED {
+            vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+        } else {
+            vk::ImageAspectFlags::DEPTH
+        }
+    }
+}
+
+fn image_barrier(
+    image: vk::Image,
+    aspect_mask: vk::ImageAspectFlags,
+    layouts: (vk::ImageLayout, vk::ImageLayout),
+    access: (vk::AccessFlags, vk::AccessFlags),
+) -> vk::ImageMemoryBarrier {
+    vk::ImageMemoryBarrier {
+        src_access_mask: access.0,
+        dst_access_mask: access.1,
+        old_layout: layouts.0,
+        new_layout: layouts.1,
+        src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+        dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+        image,
+        subresource_range: vk::ImageSubresourceRange {
+            aspect_mask,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        },
+        ..Default::default()
+    }
+}
+
+// Renders into image views directly instead of through render pass and framebuffer
+// objects, so nothing but the images has to be created again with the swapchain. The
+// layout transitions a render pass would do are recorded as barriers around it.
+pub struct DynamicRendering {
+    begin_rendering: CmdBeginRendering,
+    end_rendering: CmdEndRendering,
+}
+
+impl DynamicRendering {
+    pub fn new(instance: &ash::Instance, device: &device::Device) -> Result<DynamicRendering> {
+        if !device.dynamic_rendering {
+            return Err(Error::Unsupported(
+                "dynamic rendering is not enabled on the device".to_string(),
+            ));
+        }
+
+        let (begin_rendering, end_rendering) = unsafe {
+            (
+                device.load_function::<CmdBeginRendering>(instance, "vkCmdBeginRenderingKHR")?,
+                device.load_function::<CmdEndRendering>(instance, "vkCmdEndRenderingKHR")?,
+            )
+        };
+
+        Ok(DynamicRendering {
+            begin_rendering,
+            end_rendering,
+        })
+    }
+
+    // Clears both attachments with the values, color first, like the scene render pass.
+    // The previous contents of the target are discarded.
+    pub fn begin(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        target: &RenderTarget,
+        extent: vk::Extent2D,
+        clear_values: &[vk::ClearValue; 2],
+    ) {
+        // the color image may still be presented or sampled by the previous frame, the
+        // depth image may still be tested against
+        let barriers = [
+            image_barrier(
+                target.color_image,
+                vk::ImageAspectFlags::COLOR,
+                (
+                    vk::ImageLayout::UNDEFINED,
+                    vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                ),
+                (
+                    vk::AccessFlags::empty(),
+                    vk::AccessFlags::COLOR_ATTACHMENT_READ
+                        | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                ),
+            ),
+            image_barrier(
+                target.depth_image,
+                target.depth_aspect(),
+                (
+                    vk::ImageLayout::UNDEFINED,
+                    vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                ),
+                (
+                    vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                    vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                        | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                ),
+            ),
+        ];
+
+        unsafe {
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &barriers,
+            )
+        };
+
+        let color_attachment = RenderingAttachmentInfo {
+            s_type: vk::StructureType::from_raw(RENDERING_ATTACHMENT_INFO),
+            p_next: ::std::ptr::null(),
+            image_view: target.color_view,
+            image_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            resolve_mode: RESOLVE_MODE_NONE,
+            resolve_image_view: vk::ImageView::null(),
+            resolve_image_layout: vk::ImageLayout::UNDEFINED,
+            load_op: vk::AttachmentLoadOp::CLEAR,
+            store_op: vk::AttachmentStoreOp::STORE,
+            clear_value: clear_values[0],
+        };
+
+        // nothing reads the depth buffer after the pass
+        let depth_attachment = RenderingAttachmentInfo {
+            image_view: target.depth_view,
+            image_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            store_op: vk::AttachmentStoreOp::DONT_CARE,
+            clear_value: clear_values[1],
+            ..color_attachment
+        };
+
+        let stencil_attachment = RenderingAttachmentInfo {
+            load_op: vk::AttachmentLoadOp::DONT_CARE,
+            ..depth_attachment
+        };
+
+        let rendering_info = RenderingInfo {
+            s_type: vk::StructureType::from_raw(RENDERING_INFO),
+            p_next: ::std::ptr::null(),
+            flags: 0,
+            render_area: vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent,
+            },
+            layer_count: 1,
+            view_mask: 0,
+            color_attachment_count: 1,
+            p_color_attachments: &color_attachment,
+            p_depth_attachment: &depth_attachment,
+            p_stencil_attachment: if target.formats.stencil() != vk::Format::UNDEFINED {
+                &stencil_attachment
+            } else {
+                ::std::ptr::null()
+            },
+        };
+
+        unsafe { (self.begin_rendering)(command_buffer, &rendering_info) };
+    }
+
+    // Moves the color image into the target's final layout for whatever comes next
+    pub fn end(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        target: &RenderTarget,
+    ) {
+        unsafe { (self.end_rendering)(command_buffer) };
+
+        let barrier = image::TransitionBarrier::from_layout(
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            target.final_layout,
+        )
+        // only layouts a render pass could leave the image in are used as final layouts
+        .unwrap_or(image::TransitionBarrier {
+            src_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            dst_access_mask: vk::AccessFlags::empty(),
+            source_stage: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            destination_stage: vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+        });
+
+        let image_barriers = [image_barrier(
+            target.color_image,
+            vk::ImageAspectFlags::COLOR,
+            (
+                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                target.final_layout,
+            ),
+            (barrier.src_access_mask, barrier.dst_access_mask),
+        )];
+
+        unsafe {
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                barrier.source_stage,
+                barrier.destination_stage,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &image_barriers,
+            )
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stencil_formats_render_to_the_stencil_aspect() {
+        let depth_only = RenderingFormats {
+            color: vk::Format::B8G8R8A8_SRGB,
+            depth: vk::Format::D32_SFLOAT,
+        };
+        let with_stencil = RenderingFormats {
+            depth: vk::Format::D24_UNORM_S8_UINT,
+            ..depth_only
+        };
+
+        assert_eq!(depth_only.stencil(), vk::Format::UNDEFINED);
+        assert_eq!(with_stencil.stencil(), vk::Format::D24_UNORM_S8_UINT);
+
+        let info = with_stencil.pipeline_info();
+        assert_eq!(info.color_attachment_count, 1);
+        assert_eq!(
+            unsafe { *info.p_color_attachment_formats },
+            with_stencil.color
+        );
+        assert_eq!(info.depth_attachment_format, vk::Format::D24_UNORM_S8_UINT);
+    }
+}
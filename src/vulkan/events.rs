@@ -0,0 +1,108 @@
+use ash::vk;
+
+use std::sync::mpsc;
+
+use super::frame;
+use super::profiler;
+use super::swapchain;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Pass {
+    Scene,
+    // replaces the scene pass, see vulkan::present
+    ComputePresent,
+    PostProcess,
+    // see vulkan::image_effects
+    ImageEffects,
+    Particles,
+    DebugLines,
+    UiOverlay,
+}
+
+// Lifecycle of the renderer as seen from the cpu. Pass events bracket the recording
+// of the pass's commands for the frame, not their execution on the gpu.
+#[derive(Debug, Clone)]
+pub enum RenderEvent {
+    FrameBegin(frame::FrameContext),
+    PassBegin(frame::FrameContext, Pass),
+    PassEnd(frame::FrameContext, Pass),
+    // sent after the frame was submitted and presented
+    FrameEnd(frame::FrameContext, profiler::FrameStats),
+    SwapchainCreated {
+        extent: vk::Extent2D,
+        format: vk::Format,
+        image_count: usize,
+    },
+    // acquire or present reported that the swapchain no longer matches the surface
+    SwapchainOutOfDate,
+}
+
+impl RenderEvent {
+    pub fn swapchain_created(swapchain: &swapchain::SwapchainDetails) -> RenderEvent {
+        RenderEvent::SwapchainCreated {
+            extent: swapchain.extent,
+            format: swapchain.format.format,
+            image_count: swapchain.images.len(),
+        }
+    }
+}
+
+enum Observer {
+    Callback(Box<dyn FnMut(&RenderEvent) + Send>),
+    Channel(mpsc::Sender<RenderEvent>),
+}
+
+// Read-only observation of the renderer, eg. for telemetry, captures or editors.
+// Observers are called on the render thread, so callbacks should return quickly.
+#[derive(Default)]
+pub struct RenderEvents {
+    observers: Vec<Observer>,
+    // replayed to late observers so they know the swapchain in use
+    swapchain: Option<RenderEvent>,
+}
+
+impl RenderEvents {
+    pub fn subscribe<F>(&mut self, mut callback: F)
+    where
+        F: FnMut(&RenderEvent) + Send + 'static,
+    {
+        if let Some(event) = self.swapchain.as_ref() {
+            callback(event);
+        }
+
+        self.observers.push(Observer::Callback(Box::new(callback)));
+    }
+
+    // Events are buffered until received, the sender is dropped once the receiver is
+    pub fn channel(&mut self) -> mpsc::Receiver<RenderEvent> {
+        let (sender, receiver) = mpsc::channel();
+
+        if let Some(event) = self.swapchain.as_ref() {
+            let _ = sender.send(event.clone());
+        }
+
+        self.observers.push(Observer::Channel(sender));
+        receiver
+    }
+
+    pub fn has_observers(&self) -> bool {
+        !self.observers.is_empty()
+    }
+
+    pub fn emit(&mut self, event: RenderEvent) {
+        if let RenderEvent::SwapchainCreated { .. } = event {
+            self.swapchain = Some(event.clone());
+        }
+
+        self.observers.retain(|observer| match observer {
+            Observer::Callback(_) => true,
+            Observer::Channel(sender) => sender.send(event.clone()).is_ok(),
+        });
+
+        for observer in self.observers.iter_mut() {
+            if let Observer::Callback(callback) = observer {
+                callback(&event);
+            }
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use crate::error::{Error, Result};
+
+// Indices of the resources used by the frame currently being recorded.
+// Resources are duplicated either per frame in flight (fences, semaphores) or
+// per swapchain image (command buffers, uniform buffers, timestamp queries),
+// looking them up through the context keeps the two from being mixed up.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct FrameContext {
+    frame_index: usize,
+    image_index: u32,
+    view_index: usize,
+    view_count: usize,
+}
+
+impl FrameContext {
+    pub fn new(frame_index: usize, image_index: u32) -> FrameContext {
+        FrameContext {
+            frame_index,
+            image_index,
+            view_index: 0,
+            view_count: 1,
+        }
+    }
+
+    pub fn with_views(mut self, view_count: usize) -> FrameContext {
+        self.view_count = view_count.max(1);
+        self
+    }
+
+    // Context for another view (viewport, eye) of the same frame
+    pub fn for_view(&self, view_index: usize) -> Result<FrameContext> {
+        if view_index >= self.view_count {
+            return Err(Error::OutOfRange(format!(
+                "view {} is out of range, the frame has {} views",
+                view_index, self.view_count
+            )));
+        }
+
+        Ok(FrameContext {
+            view_index,
+            ..*self
+        })
+    }
+
+    pub fn views(&self) -> impl Iterator<Item = FrameContext> {
+        let frame = *self;
+        (0..self.view_count).map(move |view_index| FrameContext {
+            view_index,
+            ..frame
+        })
+    }
+
+    pub fn frame_index(&self) -> usize {
+        self.frame_index
+    }
+
+    pub fn image_index(&self) -> u32 {
+        self.image_index
+    }
+
+    pub fn view_index(&self) -> usize {
+        self.view_index
+    }
+
+    pub fn view_count(&self) -> usize {
+        self.view_count
+    }
+
+    // Slot of the current view in resources holding one entry per view of every image
+    pub fn view_slot(&self) -> usize {
+        self.image_index as usize * self.view_count + self.view_index
+    }
+
+    pub fn per_frame<'a, T>(&self, resources: &'a [T]) -> Result<&'a T> {
+        resources.get(self.frame_index).ok_or_else(|| {
+            Error::OutOfRange(format!(
+                "no resource for frame in flight {}, found {}",
+                self.frame_index,
+                resources.len()
+            ))
+        })
+    }
+
+    pub fn per_image<'a, T>(&self, resources: &'a [T]) -> Result<&'a T> {
+        resources.get(self.image_index as usize).ok_or_else(|| {
+            Error::OutOfRange(format!(
+                "no resource for swapchain image {}, found {}",
+                self.image_index,
+                resources.len()
+            ))
+        })
+    }
+
+    pub fn per_image_mut<'a, T>(&self, resources: &'a mut [T]) -> Result<&'a mut T> {
+        let len = resources.len();
+        resources.get_mut(self.image_index as usize).ok_or_else(|| {
+            Error::OutOfRange(format!(
+                "no resource for swapchain image {}, found {}",
+                self.image_index, len
+            ))
+        })
+    }
+
+    // Resources with one entry per view of every swapchain image, stored image major
+    pub fn per_view<'a, T>(&self, resources: &'a [T]) -> Result<&'a T> {
+        resources.get(self.view_slot()).ok_or_else(|| {
+            Error::OutOfRange(format!(
+                "no resource for view {} of swapchain image {}, found {}",
+                self.view_index,
+                self.image_index,
+                resources.len()
+            ))
+        })
+    }
+}
@@ -0,0 +1,150 @@
+use ash::version::DeviceV1_0;
+use ash::vk;
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+// Vulkan objects whose destruction is deferred until the gpu can no longer be using them
+#[derive(Debug, Copy, Clone)]
+pub enum PendingDestruction {
+    Buffer {
+        buffer: vk::Buffer,
+        memory: vk::DeviceMemory,
+    },
+    Image {
+        image: vk::Image,
+        view: vk::ImageView,
+        memory: vk::DeviceMemory,
+    },
+    Sampler(vk::Sampler),
+    Framebuffer(vk::Framebuffer),
+    Pipeline(vk::Pipeline),
+    PipelineLayout(vk::PipelineLayout),
+    RenderPass(vk::RenderPass),
+    DescriptorPool(vk::DescriptorPool),
+    DescriptorSetLayout(vk::DescriptorSetLayout),
+}
+
+impl PendingDestruction {
+    unsafe fn destroy(self, device: &ash::Device) {
+        match self {
+            PendingDestruction::Buffer { buffer, memory } => {
+                device.destroy_buffer(buffer, None);
+                device.free_memory(memory, None);
+            }
+            PendingDestruction::Image {
+                image,
+                view,
+                memory,
+            } => {
+                device.destroy_image_view(view, None);
+                device.destroy_image(image, None);
+                device.free_memory(memory, None);
+            }
+            PendingDestruction::Sampler(sampler) => device.destroy_sampler(sampler, None),
+            PendingDestruction::Framebuffer(framebuffer) => {
+                device.destroy_framebuffer(framebuffer, None)
+            }
+            PendingDestruction::Pipeline(pipeline) => device.destroy_pipeline(pipeline, None),
+            PendingDestruction::PipelineLayout(layout) => {
+                device.destroy_pipeline_layout(layout, None)
+            }
+            PendingDestruction::RenderPass(render_pass) => {
+                device.destroy_render_pass(render_pass, None)
+            }
+            PendingDestruction::DescriptorPool(pool) => device.destroy_descriptor_pool(pool, None),
+            PendingDestruction::DescriptorSetLayout(layout) => {
+                device.destroy_descriptor_set_layout(layout, None)
+            }
+        }
+    }
+}
+
+// Implemented by caches which can give up entries incrementally, eg. texture::TextureCache.
+// `evict_next` releases a single entry and returns false once the cache wants to keep the
+// rest, `evict_all` releases every entry and returns how many there were.
+pub trait Evictable {
+    fn evict_next(&mut self, device: &ash::Device) -> bool;
+    fn evict_all(&mut self, device: &ash::Device) -> usize;
+}
+
+pub struct GarbageCollector {
+    pending: VecDeque<(u64, PendingDestruction)>,
+    current_frame: u64,
+    frames_in_flight: u64,
+    pub budget: Duration,
+}
+
+impl GarbageCollector {
+    pub fn new(frames_in_flight: u32, budget: Duration) -> GarbageCollector {
+        GarbageCollector {
+            pending: VecDeque::new(),
+            current_frame: 0,
+            frames_in_flight: frames_in_flight as u64,
+            budget,
+        }
+    }
+
+    pub fn defer(&mut self, resource: PendingDestruction) {
+        self.pending.push_back((self.current_frame, resource));
+    }
+
+    pub fn pending_count(&self) -> usize {
+        self.pending.len()
+    }
+
+    fn is_retired(&self, queued_frame: u64) -> bool {
+        queued_frame + self.frames_in_flight <= self.current_frame
+    }
+
+    // Processes retired destructions and then cache evictions until the time budget runs out.
+    // Returns the number of objects released in this step.
+    pub fn step(&mut self, device: &ash::Device, caches: &mut [&mut dyn Evictable]) -> usize {
+        let start = Instant::now();
+        let mut released = 0;
+
+        while start.elapsed() < self.budget {
+            match self.pending.front() {
+                Some((queued_frame, _)) if self.is_retired(*queued_frame) => {
+                    if let Some((_, resource)) = self.pending.pop_front() {
+                        unsafe { resource.destroy(device) };
+                        released += 1;
+                    }
+                }
+                _ => break,
+            }
+        }
+
+        for cache in caches.iter_mut() {
+            while start.elapsed() < self.budget {
+                if !cache.evict_next(device) {
+                    break;
+                }
+                released += 1;
+            }
+        }
+
+        released
+    }
+
+    pub fn end_frame(&mut self) {
+        self.current_frame += 1;
+    }
+
+    // Destroys everything immediately, eg. on scene unload.
+    // The caller must make sure the device is idle before flushing.
+    pub fn flush(&mut self, device: &ash::Device, caches: &mut [&mut dyn Evictable]) -> usize {
+        let mut released = 0;
+
+        while let Some((_, resource)) = self.pending.pop_front() {
+            unsafe { resource.destroy(device) };
+            released += 1;
+        }
+
+        for cache in caches.iter_mut() {
+            released += cache.evict_all(device);
+        }
+
+        released
+    }
+}
@@ -0,0 +1,604 @@
+use ash::version::DeviceV1_0;
+use ash::vk;
+
+use cgmath::Matrix4;
+
+use crate::error::{Context, Error, Result};
+
+use std::ffi::CString;
+
+use crate::shaderc;
+
+use super::bounds;
+use super::buffers;
+use super::descriptor;
+use super::device;
+use super::material;
+use super::pipeline;
+use super::registry;
+use super::typed_buffer;
+
+pub const CULL_SHADER_FILE: &'static str = "shaders/cull.comp";
+
+pub const DRAW_INDIRECT_COUNT_EXTENSION: device::DeviceExtension = device::DeviceExtension {
+    names: ["VK_KHR_draw_indirect_count"],
+};
+
+// local_size_x of the compute shader
+const WORKGROUP_SIZE: u32 = 64;
+
+// size of a vk::DrawIndexedIndirectCommand, the draws are tightly packed
+const DRAW_STRIDE: u32 = std::mem::size_of::<vk::DrawIndexedIndirectCommand>() as u32;
+
+type CmdDrawIndexedIndirectCount = unsafe extern "system" fn(
+    vk::CommandBuffer,
+    vk::Buffer,
+    vk::DeviceSize,
+    vk::Buffer,
+    vk::DeviceSize,
+    u32,
+    u32,
+);
+
+// Checked when creating the logical device, see device::Device::draw_indirect_count
+pub fn is_supported(instance: &ash::Instance, physical_device: vk::PhysicalDevice) -> Result<bool> {
+    device::Device::check_device_extension_support(
+        instance,
+        physical_device,
+        &DRAW_INDIRECT_COUNT_EXTENSION,
+    )
+}
+
+// An object the compute shader tests against the frustum, with the draw it turns into
+// when visible. Laid out to match the Objects buffer of shaders/cull.comp.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct CullObject {
+    // model space bounds, w of the sphere is its radius
+    pub sphere: [f32; 4],
+    pub aabb_min: [f32; 4],
+    pub aabb_max: [f32; 4],
+    pub model: [[f32; 4]; 4],
+    pub index_count: u32,
+    pub first_index: u32,
+    pub vertex_offset: i32,
+    // passed on to the draw, eg. to look up per object data with gl_InstanceIndex
+    pub first_instance: u32,
+}
+
+impl CullObject {
+    pub fn new(
+        draw: &material::Draw,
+        bounds: &bounds::MeshBounds,
+        model: Matrix4<f32>,
+    ) -> CullObject {
+        let (aabb, sphere) = (&bounds.aabb, &bounds.sphere);
+
+        CullObject {
+            sphere: [
+                sphere.center.x,
+                sphere.center.y,
+                sphere.center.z,
+                sphere.radius,
+            ],
+            aabb_min: [aabb.min.x, aabb.min.y, aabb.min.z, 1.0],
+            aabb_max: [aabb.max.x, aabb.max.y, aabb.max.z, 1.0],
+            model: model.into(),
+            index_count: draw.index_count,
+            first_index: draw.first_index,
+            vertex_offset: draw.vertex_offset,
+            first_instance: 0,
+        }
+    }
+
+    pub fn with_first_instance(mut self, first_instance: u32) -> CullObject {
+        self.first_instance = first_instance;
+        self
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct PushConstants {
+    planes: [[f32; 4]; 6],
+    object_count: u32,
+    compact: u32,
+}
+
+impl PushConstants {
+    fn new(frustum: &bounds::Frustum, object_count: u32, compact: bool) -> PushConstants {
+        let mut planes = [[0.0; 4]; 6];
+        for (out, plane) in planes.iter_mut().zip(frustum.planes().iter()) {
+            *out = (*plane).into();
+        }
+
+        PushConstants {
+            planes,
+            object_count,
+            compact: compact as u32,
+        }
+    }
+}
+
+fn as_bytes<T>(value: &T) -> &[u8] {
+    unsafe {
+        ::std::slice::from_raw_parts(value as *const T as *const u8, ::std::mem::size_of::<T>())
+    }
+}
+
+// Frustum culling on the gpu. The bounds of every object live in a storage buffer, a
+// compute pass writes the draws of the visible ones into an indirect buffer and a single
+// indirect draw consumes them, so the cpu never walks the draw list.
+// With VK_KHR_draw_indirect_count the visible draws are packed and counted on the gpu,
+// otherwise every object keeps its draw and the culled ones draw no instances.
+// All objects are drawn with the pipeline, descriptor sets and mesh pool buffers bound
+// before record_draw, so per object materials have to be looked up in the shaders.
+pub struct GpuCulling {
+    objects: typed_buffer::StorageBuffer<CullObject>,
+    draws: typed_buffer::IndirectBuffer,
+    count: buffers::BufferInfo,
+    object_count: u32,
+
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set: vk::DescriptorSet,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+
+    // loaded when the device can draw a gpu written count of draws
+    draw_indexed_indirect_count: Option<CmdDrawIndexedIndirectCount>,
+}
+
+impl GpuCulling {
+    fn bindings() -> [descriptor::Binding; 3] {
+        [
+            descriptor::Binding::storage_buffer(0, vk::ShaderStageFlags::COMPUTE),
+            descriptor::Binding::storage_buffer(1, vk::ShaderStageFlags::COMPUTE),
+            descriptor::Binding::storage_buffer(2, vk::ShaderStageFlags::COMPUTE),
+        ]
+    }
+
+    fn create_pipeline(
+        device: &ash::Device,
+        pipeline_layout: vk::PipelineLayout,
+    ) -> Result<vk::Pipeline> {
+        let code = shaderc::ShaderSource::compile_compute(&CULL_SHADER_FILE.to_string())?;
+        let shader_module = pipeline::PipelineDetail::create_shader_module(device, code)?;
+
+        let main_function_name = CString::new("main").context("invalid fn name")?;
+
+        let pipeline_info = vk::ComputePipelineCreateInfo {
+            stage: vk::PipelineShaderStageCreateInfo {
+                module: shader_module,
+                p_name: main_function_name.as_ptr(),
+                stage: vk::ShaderStageFlags::COMPUTE,
+                ..Default::default()
+            },
+            layout: pipeline_layout,
+            base_pipeline_index: -1,
+            ..Default::default()
+        };
+
+        let pipelines = unsafe {
+            device.create_compute_pipelines(vk::PipelineCache::null(), &[pipeline_info], None)
+        };
+
+        unsafe { device.destroy_shader_module(shader_module, None) };
+
+        pipelines
+            .map(|pipelines| pipelines[0])
+            .map_err(|(_, err)| err)
+            .context("failed to create culling pipeline")
+    }
+
+    // Room for `capacity` objects, none of which are drawn until set_objects
+    pub fn new(
+        instance: &ash::Instance,
+        device: &device::Device,
+        capacity: u32,
+    ) -> Result<GpuCulling> {
+        let logical_device = &device.logical_device;
+        let capacity = capacity.max(1);
+
+        // the shader indexes the objects tightly packed, so they are not padded to the
+        // storage buffer offset alignment
+        let objects = typed_buffer::StorageBuffer::from_packed(
+            buffers::BufferInfo::create(
+                device,
+                (std::mem::size_of::<CullObject>() * capacity as usize) as vk::DeviceSize,
+                vk::BufferUsageFlags::STORAGE_BUFFER,
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            )?,
+            capacity as usize,
+            typed_buffer::MemoryLocation::HostVisible,
+        )?;
+
+        let draws = typed_buffer::IndirectBuffer::from_packed(
+            buffers::BufferInfo::create(
+                device,
+                (DRAW_STRIDE * capacity) as vk::DeviceSize,
+                vk::BufferUsageFlags::INDIRECT_BUFFER | vk::BufferUsageFlags::STORAGE_BUFFER,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            )?,
+            capacity as usize,
+            typed_buffer::MemoryLocation::DeviceLocal,
+        )?;
+
+        let count = buffers::BufferInfo::create(
+            device,
+            std::mem::size_of::<u32>() as vk::DeviceSize,
+            vk::BufferUsageFlags::INDIRECT_BUFFER
+                | vk::BufferUsageFlags::STORAGE_BUFFER
+                | vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+
+        device.name_resource(objects.buffer(), "culling object buffer");
+        device.name_resource(draws.buffer(), "culled draw buffer");
+        count.set_name(device, "culled draw count buffer");
+
+        let bindings = GpuCulling::bindings();
+        let descriptor_set_layout = descriptor::create_set_layout(logical_device, &bindings)?;
+        let descriptor_pool = descriptor::create_pool(logical_device, &bindings, 1)?;
+        let descriptor_set =
+            descriptor::allocate_sets(logical_device, descriptor_pool, descriptor_set_layout, 1)?
+                [0];
+
+        let whole_buffer = |buffer| vk::DescriptorBufferInfo {
+            buffer,
+            offset: 0,
+            range: vk::WHOLE_SIZE,
+        };
+        let buffer_infos = [
+            whole_buffer(objects.buffer()),
+            whole_buffer(draws.buffer()),
+            whole_buffer(count.buffer),
+        ];
+        let descriptor_writes = [
+            bindings[0].write_buffers(descriptor_set, &buffer_infos[0..1])?,
+            bindings[1].write_buffers(descriptor_set, &buffer_infos[1..2])?,
+            bindings[2].write_buffers(descriptor_set, &buffer_infos[2..3])?,
+        ];
+        unsafe { logical_device.update_descriptor_sets(&descriptor_writes, &[]) };
+
+        let push_constant_ranges = [vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::COMPUTE,
+            offset: 0,
+            size: ::std::mem::size_of::<PushConstants>() as u32,
+        }];
+
+        let set_layouts = [descriptor_set_layout];
+        let layout_info = vk::PipelineLayoutCreateInfo {
+            set_layout_count: set_layouts.len() as u32,
+            p_set_layouts: set_layouts.as_ptr(),
+            push_constant_range_count: push_constant_ranges.len() as u32,
+            p_push_constant_ranges: push_constant_ranges.as_ptr(),
+            ..Default::default()
+        };
+
+        let pipeline_layout = unsafe {
+            logical_device
+                .create_pipeline_layout(&layout_info, None)
+                .context("failed to create culling pipeline layout")
+        }?;
+
+        let pipeline = GpuCulling::create_pipeline(logical_device, pipeline_layout)?;
+
+        // more than one draw per indirect call needs the multi draw feature as well
+        let draw_indexed_indirect_count =
+            if device.draw_indirect_count && device.features.multi_draw_indirect == vk::TRUE {
+                Some(unsafe {
+                    device.load_function::<CmdDrawIndexedIndirectCount>(
+                        instance,
+                        "vkCmdDrawIndexedIndirectCountKHR",
+                    )?
+                })
+            } else {
+                None
+            };
+
+        device.track(
+            registry::ResourceKind::DescriptorSetLayout,
+            descriptor_set_layout,
+        );
+        device.track(registry::ResourceKind::DescriptorPool, descriptor_pool);
+        device.track(registry::ResourceKind::PipelineLayout, pipeline_layout);
+        device.track(registry::ResourceKind::Pipeline, pipeline);
+
+        Ok(GpuCulling {
+            objects,
+            draws,
+            count,
+            object_count: 0,
+            descriptor_set_layout,
+            descriptor_pool,
+            descriptor_set,
+            pipeline_layout,
+            pipeline,
+            draw_indexed_indirect_count,
+        })
+    }
+
+    // Whether the visible draws are packed and counted on the gpu
+    pub fn is_compacting(&self) -> bool {
+        self.draw_indexed_indirect_count.is_some()
+    }
+
+    pub fn capacity(&self) -> u32 {
+        self.objects.len() as u32
+    }
+
+    pub fn object_count(&self) -> u32 {
+        self.object_count
+    }
+
+    // Replaces the objects that are culled and drawn. The buffer is written right away,
+    // so no recorded culling pass may still be executing, eg. after Engine::wait_idle.
+    pub fn set_objects(&mut self, device: &device::Device, objects: &[CullObject]) -> Result<()> {
+        if objects.len() > self.objects.len() {
+            return Err(Error::OutOfRange(format!(
+                "{} objects do not fit the {} the culling pass was created for",
+                objects.len(),
+                self.objects.len()
+            )));
+        }
+
+        self.objects.update(&device.logical_device, objects)?;
+        self.object_count = objects.len() as u32;
+
+        Ok(())
+    }
+
+    fn memory_barrier(
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        src: (vk::PipelineStageFlags, vk::AccessFlags),
+        dst: (vk::PipelineStageFlags, vk::AccessFlags),
+    ) {
+        let barrier = vk::MemoryBarrier {
+            src_access_mask: src.1,
+            dst_access_mask: dst.1,
+            ..Default::default()
+        };
+
+        unsafe {
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                src.0,
+                dst.0,
+                vk::DependencyFlags::empty(),
+                &[barrier],
+                &[],
+                &[],
+            )
+        };
+    }
+
+    // Writes the draws of the objects inside the frustum, recorded outside of a render
+    // pass before the one record_draw is recorded in
+    pub fn record_cull(
+        &self,
+        device: &device::Device,
+        command_buffer: vk::CommandBuffer,
+        frustum: &bounds::Frustum,
+    ) {
+        let logical_device = &device.logical_device;
+
+        // the draws of the previous frame may still be read
+        let indirect_read = (
+            vk::PipelineStageFlags::DRAW_INDIRECT,
+            vk::AccessFlags::INDIRECT_COMMAND_READ,
+        );
+
+        if self.is_compacting() {
+            GpuCulling::memory_barrier(
+                logical_device,
+                command_buffer,
+                indirect_read,
+                (
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::AccessFlags::TRANSFER_WRITE,
+                ),
+            );
+
+            unsafe {
+                logical_device.cmd_fill_buffer(
+                    command_buffer,
+                    self.count.buffer,
+                    0,
+                    vk::WHOLE_SIZE,
+                    0,
+                )
+            };
+
+            GpuCulling::memory_barrier(
+                logical_device,
+                command_buffer,
+                (
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::AccessFlags::TRANSFER_WRITE,
+                ),
+                (
+                    vk::PipelineStageFlags::COMPUTE_SHADER,
+                    vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+                ),
+            );
+        }
+
+        GpuCulling::memory_barrier(
+            logical_device,
+            command_buffer,
+            indirect_read,
+            (
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::AccessFlags::SHADER_WRITE,
+            ),
+        );
+
+        let push_constants = PushConstants::new(frustum, self.object_count, self.is_compacting());
+
+        unsafe {
+            logical_device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline,
+            );
+            logical_device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline_layout,
+                0,
+                &[self.descriptor_set],
+                &[],
+            );
+            logical_device.cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                as_bytes(&push_constants),
+            );
+            logical_device.cmd_dispatch(
+                command_buffer,
+                (self.object_count + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE,
+                1,
+                1,
+            );
+        }
+
+        GpuCulling::memory_barrier(
+            logical_device,
+            command_buffer,
+            (
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::AccessFlags::SHADER_WRITE,
+            ),
+            indirect_read,
+        );
+    }
+
+    // Draws what the last record_cull left visible with whatever is bound
+    pub fn record_draw(&self, device: &device::Device, command_buffer: vk::CommandBuffer) {
+        if self.object_count == 0 {
+            return;
+        }
+
+        let logical_device = &device.logical_device;
+        let draws = self.draws.buffer();
+
+        match self.draw_indexed_indirect_count {
+            Some(draw_indexed_indirect_count) => unsafe {
+                draw_indexed_indirect_count(
+                    command_buffer,
+                    draws,
+                    0,
+                    self.count.buffer,
+                    0,
+                    self.object_count,
+                    DRAW_STRIDE,
+                )
+            },
+            None if device.features.multi_draw_indirect == vk::TRUE => unsafe {
+                logical_device.cmd_draw_indexed_indirect(
+                    command_buffer,
+                    draws,
+                    0,
+                    self.object_count,
+                    DRAW_STRIDE,
+                )
+            },
+            // one draw per call without the multi draw feature
+            None => {
+                for index in 0..self.object_count {
+                    unsafe {
+                        logical_device.cmd_draw_indexed_indirect(
+                            command_buffer,
+                            draws,
+                            (index * DRAW_STRIDE) as vk::DeviceSize,
+                            1,
+                            DRAW_STRIDE,
+                        )
+                    };
+                }
+            }
+        }
+    }
+
+    // The device must be idle, none of the buffers may be in use anymore
+    pub fn destroy(&self, device: &device::Device) {
+        let logical_device = &device.logical_device;
+
+        device.untrack(self.pipeline);
+        device.untrack(self.pipeline_layout);
+        device.untrack(self.descriptor_pool);
+        device.untrack(self.descriptor_set_layout);
+
+        unsafe {
+            logical_device.destroy_pipeline(self.pipeline, None);
+            logical_device.destroy_pipeline_layout(self.pipeline_layout, None);
+            logical_device.destroy_descriptor_pool(self.descriptor_pool, None);
+            logical_device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+        }
+
+        self.count.destroy(device);
+        self.draws.destroy(device);
+        self.objects.destroy(device);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use cgmath::{Deg, Point3, Vector3};
+
+    #[test]
+    fn objects_match_the_shader_layout() {
+        // three vec4, a mat4 and four scalars in the std430 Objects buffer
+        assert_eq!(std::mem::size_of::<CullObject>(), 128);
+        // five scalars per VkDrawIndexedIndirectCommand
+        assert_eq!(DRAW_STRIDE, 20);
+        // every device has at least 128 bytes of push constants
+        assert!(std::mem::size_of::<PushConstants>() <= 128);
+    }
+
+    #[test]
+    fn objects_carry_their_draw_and_bounds() {
+        let draw = material::Draw {
+            material: 3,
+            index_count: 36,
+            first_index: 120,
+            vertex_offset: 48,
+            instance_count: 1,
+            center: Point3::new(0.0, 0.0, 0.0),
+        };
+        let bounds =
+            bounds::MeshBounds::from_positions(vec![[-1.0, 0.0, -1.0], [1.0, 2.0, 1.0]]).unwrap();
+        let model = Matrix4::from_translation(Vector3::new(5.0, 0.0, 0.0));
+
+        let object = CullObject::new(&draw, &bounds, model).with_first_instance(7);
+
+        assert_eq!((object.index_count, object.first_index), (36, 120));
+        assert_eq!((object.vertex_offset, object.first_instance), (48, 7));
+        assert_eq!(object.sphere, [0.0, 1.0, 0.0, 3.0_f32.sqrt()]);
+        assert_eq!((object.aabb_min[1], object.aabb_max[1]), (0.0, 2.0));
+        assert_eq!(object.model[3], [5.0, 0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn push_constants_hold_the_frustum_planes() {
+        let proj = cgmath::perspective(Deg(60.0), 1.0, 0.1, 100.0);
+        let frustum = bounds::Frustum::from_matrix(proj);
+
+        let push_constants = PushConstants::new(&frustum, 12, true);
+
+        for (pushed, plane) in push_constants.planes.iter().zip(frustum.planes().iter()) {
+            let plane: [f32; 4] = (*plane).into();
+            assert_eq!(*pushed, plane);
+        }
+        assert_eq!(
+            (push_constants.object_count, push_constants.compact),
+            (12, 1)
+        );
+    }
+}
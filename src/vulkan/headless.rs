@@ -0,0 +1,267 @@
+// A software stand-in for the device, queues and swapchain, see vulkan::backend. Work
+// completes the moment it is submitted unless submissions are held, and every call is
+// recorded, so tests can check what the frame loop and draw recording did without a gpu.
+
+use ash::vk;
+use ash::vk::Handle;
+
+use crate::error::{Error, Result};
+
+use super::backend;
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Call {
+    WaitForFences(Vec<vk::Fence>),
+    ResetFences(Vec<vk::Fence>),
+    AcquireNextImage(u32),
+    Submit {
+        command_buffers: Vec<vk::CommandBuffer>,
+        fence: vk::Fence,
+    },
+    Present(Vec<u32>),
+    BindPipeline(vk::Pipeline),
+    BindDescriptorSets {
+        first_set: u32,
+        descriptor_sets: Vec<vk::DescriptorSet>,
+    },
+    PushConstants {
+        offset: u32,
+        constants: Vec<u8>,
+    },
+    DrawIndexed {
+        index_count: u32,
+        instance_count: u32,
+        first_index: u32,
+    },
+}
+
+pub struct HeadlessDevice {
+    calls: RefCell<Vec<Call>>,
+    next_handle: Cell<u64>,
+    signaled: RefCell<HashSet<vk::Fence>>,
+    // fences of submissions that did not complete yet, see hold_submissions
+    pending: RefCell<Vec<vk::Fence>>,
+    hold_submissions: Cell<bool>,
+    image_count: u32,
+    next_image: Cell<u32>,
+    out_of_date: Cell<bool>,
+    suboptimal: Cell<bool>,
+}
+
+impl HeadlessDevice {
+    // A device presenting to a swapchain of image_count images, acquired in turn
+    pub fn new(image_count: u32) -> HeadlessDevice {
+        HeadlessDevice {
+            calls: RefCell::new(vec![]),
+            next_handle: Cell::new(1),
+            signaled: RefCell::new(HashSet::new()),
+            pending: RefCell::new(vec![]),
+            hold_submissions: Cell::new(false),
+            image_count: image_count.max(1),
+            next_image: Cell::new(0),
+            out_of_date: Cell::new(false),
+            suboptimal: Cell::new(false),
+        }
+    }
+
+    fn next_raw_handle(&self) -> u64 {
+        let handle = self.next_handle.get();
+        self.next_handle.set(handle + 1);
+        handle
+    }
+
+    pub fn create_fence(&self, signaled: bool) -> vk::Fence {
+        let fence = vk::Fence::from_raw(self.next_raw_handle());
+        if signaled {
+            self.signaled.borrow_mut().insert(fence);
+        }
+        fence
+    }
+
+    pub fn create_semaphore(&self) -> vk::Semaphore {
+        vk::Semaphore::from_raw(self.next_raw_handle())
+    }
+
+    // Distinct handles for objects the device does not track, eg. pipelines or sets
+    pub fn create_handle<H: Handle>(&self) -> H {
+        H::from_raw(self.next_raw_handle())
+    }
+
+    pub fn is_signaled(&self, fence: vk::Fence) -> bool {
+        self.signaled.borrow().contains(&fence)
+    }
+
+    // Later submissions stay in flight until complete_submissions
+    pub fn hold_submissions(&self, hold: bool) {
+        self.hold_submissions.set(hold);
+    }
+
+    // Completes the held submissions, signaling their fences
+    pub fn complete_submissions(&self) {
+        let pending = self.pending.replace(vec![]);
+        self.signaled.borrow_mut().extend(pending);
+    }
+
+    // Acquiring fails until set_out_of_date(false), like after a resize
+    pub fn set_out_of_date(&self, out_of_date: bool) {
+        self.out_of_date.set(out_of_date);
+    }
+
+    // Presenting reports a suboptimal swapchain
+    pub fn set_suboptimal(&self, suboptimal: bool) {
+        self.suboptimal.set(suboptimal);
+    }
+
+    pub fn calls(&self) -> Vec<Call> {
+        self.calls.borrow().clone()
+    }
+
+    pub fn take_calls(&self) -> Vec<Call> {
+        self.calls.replace(vec![])
+    }
+
+    fn record(&self, call: Call) {
+        self.calls.borrow_mut().push(call);
+    }
+}
+
+impl backend::FenceOps for HeadlessDevice {
+    // Nothing else could signal the fences, so waiting for an unsignaled one times out
+    // instead of blocking
+    fn wait_for_fences(&self, fences: &[vk::Fence], _timeout: u64) -> Result<()> {
+        self.record(Call::WaitForFences(fences.to_vec()));
+
+        let signaled = self.signaled.borrow();
+        match fences.iter().find(|fence| !signaled.contains(fence)) {
+            Some(_) => Err(Error::Vk(vk::Result::TIMEOUT)),
+            None => Ok(()),
+        }
+    }
+
+    fn reset_fences(&self, fences: &[vk::Fence]) -> Result<()> {
+        self.record(Call::ResetFences(fences.to_vec()));
+
+        let mut signaled = self.signaled.borrow_mut();
+        for fence in fences.iter() {
+            signaled.remove(fence);
+        }
+        Ok(())
+    }
+}
+
+impl backend::QueueOps for HeadlessDevice {
+    fn submit(&self, submits: &[vk::SubmitInfo], fence: vk::Fence) -> Result<()> {
+        let command_buffers = submits
+            .iter()
+            .flat_map(|submit| unsafe {
+                std::slice::from_raw_parts(
+                    submit.p_command_buffers,
+                    submit.command_buffer_count as usize,
+                )
+            })
+            .cloned()
+            .collect();
+        self.record(Call::Submit {
+            command_buffers,
+            fence,
+        });
+
+        if fence != vk::Fence::null() {
+            if self.hold_submissions.get() {
+                self.pending.borrow_mut().push(fence);
+            } else {
+                self.signaled.borrow_mut().insert(fence);
+            }
+        }
+        Ok(())
+    }
+
+    fn present(&self, present_info: &vk::PresentInfoKHR) -> Result<bool> {
+        let image_indices = unsafe {
+            std::slice::from_raw_parts(
+                present_info.p_image_indices,
+                present_info.swapchain_count as usize,
+            )
+        };
+        self.record(Call::Present(image_indices.to_vec()));
+
+        Ok(self.suboptimal.get())
+    }
+}
+
+impl backend::SwapchainOps for HeadlessDevice {
+    fn image_count(&self) -> usize {
+        self.image_count as usize
+    }
+
+    fn acquire_next_image(&self, _semaphore: vk::Semaphore, _timeout: u64) -> Result<u32> {
+        if self.out_of_date.get() {
+            return Err(Error::SwapchainOutOfDate);
+        }
+
+        let image_index = self.next_image.get();
+        self.next_image.set((image_index + 1) % self.image_count);
+        self.record(Call::AcquireNextImage(image_index));
+
+        Ok(image_index)
+    }
+}
+
+impl backend::CommandOps for HeadlessDevice {
+    fn bind_pipeline(
+        &self,
+        _command_buffer: vk::CommandBuffer,
+        _bind_point: vk::PipelineBindPoint,
+        pipeline: vk::Pipeline,
+    ) {
+        self.record(Call::BindPipeline(pipeline));
+    }
+
+    fn bind_descriptor_sets(
+        &self,
+        _command_buffer: vk::CommandBuffer,
+        _bind_point: vk::PipelineBindPoint,
+        _layout: vk::PipelineLayout,
+        first_set: u32,
+        descriptor_sets: &[vk::DescriptorSet],
+        _dynamic_offsets: &[u32],
+    ) {
+        self.record(Call::BindDescriptorSets {
+            first_set,
+            descriptor_sets: descriptor_sets.to_vec(),
+        });
+    }
+
+    fn push_constants(
+        &self,
+        _command_buffer: vk::CommandBuffer,
+        _layout: vk::PipelineLayout,
+        _stage_flags: vk::ShaderStageFlags,
+        offset: u32,
+        constants: &[u8],
+    ) {
+        self.record(Call::PushConstants {
+            offset,
+            constants: constants.to_vec(),
+        });
+    }
+
+    fn draw_indexed(
+        &self,
+        _command_buffer: vk::CommandBuffer,
+        index_count: u32,
+        instance_count: u32,
+        first_index: u32,
+        _vertex_offset: i32,
+        _first_instance: u32,
+    ) {
+        self.record(Call::DrawIndexed {
+            index_count,
+            instance_count,
+            first_index,
+        });
+    }
+}
@@ -0,0 +1,734 @@
+use ash::version::DeviceV1_0;
+use ash::vk;
+
+use ::image::hdr::HdrDecoder;
+
+use crate::error::{Context, Error, Result};
+
+use std::ffi::CString;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use crate::shaderc;
+
+use super::buffers;
+use super::descriptor;
+use super::device;
+use super::image;
+use super::pbr;
+use super::pipeline;
+use super::registry;
+use super::texgen;
+use super::texture;
+
+pub const IBL_SHADER_FILE: &'static str = "shaders/ibl.comp";
+pub const IBL_FRAGMENT_SHADER: &'static str = "shaders/pbr_ibl.frag";
+
+// Matches the image format declared in shaders/ibl.comp
+pub const IBL_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+// Set of the environment in shaders/pbr_ibl.frag, after the scene and material sets
+pub const ENVIRONMENT_SET: u32 = 2;
+
+const WORKGROUP_SIZE: u32 = 8;
+
+const MODE_IRRADIANCE: u32 = 0;
+const MODE_PREFILTER: u32 = 1;
+
+// Sizes and sample counts of the precomputed maps. Irradiance varies slowly so a
+// small cube holds it, the prefiltered cube gets sharper towards its first level.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct IblSettings {
+    pub irradiance_size: u32,
+    pub prefiltered_size: u32,
+    // clamped to the levels the prefiltered size has, see mip_levels
+    pub prefiltered_levels: u32,
+    pub samples: u32,
+    pub brdf_lut_size: u32,
+}
+
+impl Default for IblSettings {
+    fn default() -> IblSettings {
+        IblSettings {
+            irradiance_size: 32,
+            prefiltered_size: 256,
+            prefiltered_levels: 6,
+            samples: 1024,
+            brdf_lut_size: 512,
+        }
+    }
+}
+
+impl IblSettings {
+    pub fn with_prefiltered(mut self, size: u32, levels: u32) -> IblSettings {
+        self.prefiltered_size = size;
+        self.prefiltered_levels = levels;
+        self
+    }
+
+    pub fn with_samples(mut self, samples: u32) -> IblSettings {
+        self.samples = samples;
+        self
+    }
+
+    // Levels of the prefiltered cube, at least one and no smaller than a texel
+    pub fn mip_levels(&self) -> u32 {
+        let full_chain = 32 - self.prefiltered_size.max(1).leading_zeros();
+        self.prefiltered_levels.max(1).min(full_chain)
+    }
+}
+
+// Roughness the level of the prefiltered cube is convolved for, shaders/pbr.glsl picks
+// the level the same way
+pub fn roughness_of_level(level: u32, levels: u32) -> f32 {
+    if levels <= 1 {
+        return 0.0;
+    }
+
+    level as f32 / (levels - 1) as f32
+}
+
+// The bits of the nearest half float towards zero, the format hdr images are uploaded in
+pub fn half_bits(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32 - 127 + 15;
+    let mantissa = bits & 0x7f_ffff;
+
+    if value.is_nan() {
+        return sign | 0x7e00;
+    }
+    // too large for a half, including infinity
+    if exponent >= 31 {
+        return sign | 0x7c00;
+    }
+    if exponent <= 0 {
+        if exponent < -10 {
+            return sign;
+        }
+        // subnormal, the implicit leading bit becomes part of the mantissa
+        return sign | ((mantissa | 0x80_0000) >> (14 - exponent)) as u16;
+    }
+
+    sign | ((exponent as u16) << 10) | (mantissa >> 13) as u16
+}
+
+// Loads a Radiance .hdr image as an RGBA half float texture, eg. an equirectangular
+// environment map
+pub fn load_hdr_texture(
+    device: &device::Device,
+    command_pool: vk::CommandPool,
+    submit_queue: vk::Queue,
+    path: &Path,
+) -> Result<texture::Texture> {
+    let file = File::open(path).context(format!("cannot open {}", path.display()))?;
+    let decoder = HdrDecoder::new(BufReader::new(file))?;
+    let metadata = decoder.metadata();
+    let pixels = decoder.read_image_hdr()?;
+
+    let data = pixels
+        .iter()
+        .flat_map(|pixel| {
+            let [r, g, b] = pixel.0;
+            vec![half_bits(r), half_bits(g), half_bits(b), half_bits(1.0)]
+        })
+        .flat_map(|half| half.to_le_bytes().to_vec())
+        .collect::<Vec<u8>>();
+
+    let property = image::ImagePropertyType::texture_property_from_data(
+        device,
+        command_pool,
+        submit_queue,
+        vk::Extent2D {
+            width: metadata.width,
+            height: metadata.height,
+        },
+        &data,
+        vk::Format::R16G16B16A16_SFLOAT,
+    )?;
+    let image_data = image::ImageData::new(device, command_pool, submit_queue, property)?;
+
+    let sampler = texture::Texture::create_texture_sampler(device)?;
+    device.track(registry::ResourceKind::Sampler, sampler);
+
+    Ok(texture::Texture {
+        image_data,
+        sampler,
+        color_space: texture::ColorSpace::Linear,
+    })
+}
+
+// The scene vertex shader with pbr.frag lit by the environment instead of the uniform
+// ambient color, for MaterialDescription::pbr in a library with_environment
+pub fn environment_shaders() -> shaderc::ShaderSource {
+    shaderc::ShaderSource {
+        vertex_shader_file: pbr::PBR_VERTEX_SHADER.to_string(),
+        fragment_shader_file: IBL_FRAGMENT_SHADER.to_string(),
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct PushConstants {
+    mode: u32,
+    samples: u32,
+    roughness: f32,
+    size: u32,
+}
+
+// The maps image based lighting samples, bound as one set next to the material's,
+// see material::MaterialLibrary::with_environment
+pub struct EnvironmentLighting {
+    pub irradiance: image::CubeImage,
+    pub prefiltered: image::CubeImage,
+    pub brdf_lut: texture::Texture,
+    sampler: vk::Sampler,
+    pub layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    pub descriptor_set: vk::DescriptorSet,
+}
+
+impl EnvironmentLighting {
+    // irradiance, prefiltered, brdf lut in the order of shaders/pbr.glsl
+    pub fn bindings() -> [descriptor::Binding; 3] {
+        [
+            descriptor::Binding::combined_image_sampler(0, vk::ShaderStageFlags::FRAGMENT),
+            descriptor::Binding::combined_image_sampler(1, vk::ShaderStageFlags::FRAGMENT),
+            descriptor::Binding::combined_image_sampler(2, vk::ShaderStageFlags::FRAGMENT),
+        ]
+    }
+
+    // Clamped so the lookup table and cube seams do not wrap, sampling every level
+    fn create_sampler(device: &ash::Device, mip_levels: u32) -> Result<vk::Sampler> {
+        let sampler_info = vk::SamplerCreateInfo {
+            mag_filter: vk::Filter::LINEAR,
+            min_filter: vk::Filter::LINEAR,
+            mipmap_mode: vk::SamplerMipmapMode::LINEAR,
+            address_mode_u: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            address_mode_v: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            address_mode_w: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            max_lod: mip_levels as f32,
+            border_color: vk::BorderColor::FLOAT_OPAQUE_BLACK,
+            ..Default::default()
+        };
+
+        unsafe {
+            device
+                .create_sampler(&sampler_info, None)
+                .context("failed to create environment sampler")
+        }
+    }
+
+    fn new(
+        device: &device::Device,
+        irradiance: image::CubeImage,
+        prefiltered: image::CubeImage,
+        brdf_lut: texture::Texture,
+    ) -> Result<EnvironmentLighting> {
+        let logical_device = &device.logical_device;
+        let bindings = EnvironmentLighting::bindings();
+
+        let sampler = EnvironmentLighting::create_sampler(logical_device, prefiltered.mip_levels)?;
+        device.track(registry::ResourceKind::Sampler, sampler);
+
+        let layout = descriptor::create_set_layout(logical_device, &bindings)?;
+        let descriptor_pool = descriptor::create_pool(logical_device, &bindings, 1)?;
+        let descriptor_set =
+            descriptor::allocate_sets(logical_device, descriptor_pool, layout, 1)?[0];
+
+        write_set(
+            logical_device,
+            descriptor_set,
+            sampler,
+            [
+                irradiance.image_view,
+                prefiltered.image_view,
+                brdf_lut.image_data.image_view,
+            ],
+        )?;
+
+        irradiance.set_name(device, "irradiance cube");
+        prefiltered.set_name(device, "prefiltered environment cube");
+        brdf_lut.image_data.set_name(device, "brdf lut");
+
+        Ok(EnvironmentLighting {
+            irradiance,
+            prefiltered,
+            brdf_lut,
+            sampler,
+            layout,
+            descriptor_pool,
+            descriptor_set,
+        })
+    }
+
+    // Precomputes the maps from a Radiance .hdr equirectangular environment, waiting for
+    // the gpu to finish. Done once at startup, the generators are not kept.
+    pub fn from_hdr(
+        device: &device::Device,
+        command_pool: vk::CommandPool,
+        queue: vk::Queue,
+        path: &Path,
+        settings: IblSettings,
+    ) -> Result<EnvironmentLighting> {
+        let environment = load_hdr_texture(device, command_pool, queue, path)?;
+
+        let generator = IblGenerator::new(&device.logical_device)?;
+        let lighting = generator.generate(device, command_pool, queue, &environment, settings);
+        generator.destroy(&device.logical_device);
+        environment.destroy(device);
+
+        lighting
+    }
+
+    // Writes a set of the environment's layout that samples another prefiltered cube,
+    // eg. the capture of a reflection_probe::ReflectionProbe
+    pub fn write_set_with_prefiltered(
+        &self,
+        device: &ash::Device,
+        set: vk::DescriptorSet,
+        prefiltered: vk::ImageView,
+    ) -> Result<()> {
+        write_set(
+            device,
+            set,
+            self.sampler,
+            [
+                self.irradiance.image_view,
+                prefiltered,
+                self.brdf_lut.image_data.image_view,
+            ],
+        )
+    }
+
+    pub fn bind(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        pipeline_layout: vk::PipelineLayout,
+    ) {
+        unsafe {
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                pipeline_layout,
+                ENVIRONMENT_SET,
+                &[self.descriptor_set],
+                &[],
+            )
+        };
+    }
+
+    pub fn destroy(&self, device: &device::Device) {
+        unsafe {
+            device
+                .logical_device
+                .destroy_descriptor_pool(self.descriptor_pool, None);
+            device
+                .logical_device
+                .destroy_descriptor_set_layout(self.layout, None);
+        }
+
+        device.untrack(self.sampler);
+        unsafe { device.logical_device.destroy_sampler(self.sampler, None) };
+
+        self.irradiance.destroy(device);
+        self.prefiltered.destroy(device);
+        self.brdf_lut.destroy(device);
+    }
+}
+
+// Points the bindings of an environment set at the views, in the order of bindings()
+fn write_set(
+    device: &ash::Device,
+    set: vk::DescriptorSet,
+    sampler: vk::Sampler,
+    image_views: [vk::ImageView; 3],
+) -> Result<()> {
+    let image_infos = image_views
+        .iter()
+        .map(|&image_view| {
+            [vk::DescriptorImageInfo {
+                sampler,
+                image_view,
+                image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            }]
+        })
+        .collect::<Vec<_>>();
+
+    let writes = EnvironmentLighting::bindings()
+        .iter()
+        .zip(image_infos.iter())
+        .map(|(binding, info)| binding.write_images(set, info))
+        .collect::<Result<Vec<_>>>()?;
+
+    unsafe { device.update_descriptor_sets(&writes, &[]) };
+    Ok(())
+}
+
+// Convolves an environment into the irradiance and prefiltered cubes with a compute
+// shader, one dispatch per cube level. The brdf lut is left to texgen.
+pub struct IblGenerator {
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+impl IblGenerator {
+    fn bindings() -> [descriptor::Binding; 2] {
+        [
+            descriptor::Binding::combined_image_sampler(0, vk::ShaderStageFlags::COMPUTE),
+            descriptor::Binding::new(
+                1,
+                vk::DescriptorType::STORAGE_IMAGE,
+                vk::ShaderStageFlags::COMPUTE,
+            ),
+        ]
+    }
+
+    fn create_pipeline(
+        device: &ash::Device,
+        pipeline_layout: vk::PipelineLayout,
+    ) -> Result<vk::Pipeline> {
+        let code = shaderc::ShaderSource::compile_compute(&IBL_SHADER_FILE.to_string())?;
+        let shader_module = pipeline::PipelineDetail::create_shader_module(device, code)?;
+
+        let main_function_name = CString::new("main").unwrap();
+
+        let pipeline_info = vk::ComputePipelineCreateInfo {
+            stage: vk::PipelineShaderStageCreateInfo {
+                module: shader_module,
+                p_name: main_function_name.as_ptr(),
+                stage: vk::ShaderStageFlags::COMPUTE,
+                ..Default::default()
+            },
+            layout: pipeline_layout,
+            base_pipeline_index: -1,
+            ..Default::default()
+        };
+
+        let pipelines = unsafe {
+            device.create_compute_pipelines(vk::PipelineCache::null(), &[pipeline_info], None)
+        };
+
+        unsafe { device.destroy_shader_module(shader_module, None) };
+
+        pipelines
+            .map(|pipelines| pipelines[0])
+            .map_err(|(_, err)| err)
+            .context("failed to create ibl pipeline")
+    }
+
+    pub fn new(device: &ash::Device) -> Result<IblGenerator> {
+        let descriptor_set_layout =
+            descriptor::create_set_layout(device, &IblGenerator::bindings())?;
+
+        let push_constant_ranges = [vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::COMPUTE,
+            offset: 0,
+            size: ::std::mem::size_of::<PushConstants>() as u32,
+        }];
+
+        let set_layouts = [descriptor_set_layout];
+        let layout_info = vk::PipelineLayoutCreateInfo {
+            set_layout_count: set_layouts.len() as u32,
+            p_set_layouts: set_layouts.as_ptr(),
+            push_constant_range_count: push_constant_ranges.len() as u32,
+            p_push_constant_ranges: push_constant_ranges.as_ptr(),
+            ..Default::default()
+        };
+
+        let pipeline_layout = unsafe {
+            device
+                .create_pipeline_layout(&layout_info, None)
+                .context("failed to create ibl pipeline layout")
+        }?;
+
+        let pipeline = IblGenerator::create_pipeline(device, pipeline_layout)?;
+
+        Ok(IblGenerator {
+            descriptor_set_layout,
+            pipeline_layout,
+            pipeline,
+        })
+    }
+
+    // Moves every level of the cube between the compute writes and sampling
+    fn cube_barrier(
+        cube: &image::CubeImage,
+        old_layout: vk::ImageLayout,
+        new_layout: vk::ImageLayout,
+    ) -> Result<(image::TransitionBarrier, vk::ImageMemoryBarrier)> {
+        let barrier = image::TransitionBarrier::from_layout(old_layout, new_layout)?;
+
+        let image_barrier = vk::ImageMemoryBarrier {
+            src_access_mask: barrier.src_access_mask,
+            dst_access_mask: barrier.dst_access_mask,
+            old_layout,
+            new_layout,
+            src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            image: cube.image,
+            subresource_range: cube.subresource_range(),
+            ..Default::default()
+        };
+
+        Ok((barrier, image_barrier))
+    }
+
+    // Convolves the environment, which has to be ready for sampling, and waits for the
+    // maps to be ready for sampling as well
+    pub fn generate(
+        &self,
+        device: &device::Device,
+        command_pool: vk::CommandPool,
+        queue: vk::Queue,
+        environment: &texture::Texture,
+        settings: IblSettings,
+    ) -> Result<EnvironmentLighting> {
+        if settings.samples == 0 {
+            return Err(Error::OutOfRange(
+                "image based lighting needs at least one sample".to_string(),
+            ));
+        }
+
+        let logical_device = &device.logical_device;
+
+        let irradiance = image::CubeImage::new(device, settings.irradiance_size, IBL_FORMAT, 1)?;
+        let prefiltered = image::CubeImage::new(
+            device,
+            settings.prefiltered_size,
+            IBL_FORMAT,
+            settings.mip_levels(),
+        )?;
+
+        // one dispatch per written level, the irradiance first
+        let mut targets = vec![(
+            &irradiance,
+            0,
+            PushConstants {
+                mode: MODE_IRRADIANCE,
+                samples: settings.samples,
+                roughness: 0.0,
+                size: irradiance.size,
+            },
+        )];
+        targets.extend((0..prefiltered.mip_levels).map(|level| {
+            (
+                &prefiltered,
+                level,
+                PushConstants {
+                    mode: MODE_PREFILTER,
+                    samples: settings.samples,
+                    roughness: roughness_of_level(level, prefiltered.mip_levels),
+                    size: prefiltered.mip_size(level),
+                },
+            )
+        }));
+
+        let bindings = IblGenerator::bindings();
+        let descriptor_pool =
+            descriptor::create_pool(logical_device, &bindings, targets.len() as u32)?;
+        let descriptor_sets = descriptor::allocate_sets(
+            logical_device,
+            descriptor_pool,
+            self.descriptor_set_layout,
+            targets.len(),
+        )?;
+
+        let environment_info = [vk::DescriptorImageInfo {
+            sampler: environment.sampler,
+            image_view: environment.image_data.image_view,
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        }];
+        let target_infos = targets
+            .iter()
+            .map(|(cube, level, _)| {
+                [vk::DescriptorImageInfo {
+                    sampler: vk::Sampler::null(),
+                    image_view: cube.mip_views[*level as usize],
+                    image_layout: vk::ImageLayout::GENERAL,
+                }]
+            })
+            .collect::<Vec<_>>();
+
+        let mut writes = vec![];
+        for (set, target_info) in descriptor_sets.iter().zip(target_infos.iter()) {
+            writes.push(bindings[0].write_images(*set, &environment_info)?);
+            writes.push(bindings[1].write_images(*set, target_info)?);
+        }
+        unsafe { logical_device.update_descriptor_sets(&writes, &[]) };
+
+        let to_general = [&irradiance, &prefiltered]
+            .iter()
+            .map(|cube| {
+                IblGenerator::cube_barrier(
+                    cube,
+                    vk::ImageLayout::UNDEFINED,
+                    vk::ImageLayout::GENERAL,
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let to_sampled = [&irradiance, &prefiltered]
+            .iter()
+            .map(|cube| {
+                IblGenerator::cube_barrier(
+                    cube,
+                    vk::ImageLayout::GENERAL,
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let submitted = buffers::CommandBuffer::record_and_submit_single_command(
+            logical_device,
+            command_pool,
+            queue,
+            |command_buffer| unsafe {
+                let (barrier, _) = to_general[0];
+                logical_device.cmd_pipeline_barrier(
+                    command_buffer,
+                    barrier.source_stage,
+                    barrier.destination_stage,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &to_general.iter().map(|(_, b)| *b).collect::<Vec<_>>(),
+                );
+
+                logical_device.cmd_bind_pipeline(
+                    command_buffer,
+                    vk::PipelineBindPoint::COMPUTE,
+                    self.pipeline,
+                );
+
+                // the levels are written independently, so the dispatches need no barriers
+                for ((_, _, push_constants), set) in targets.iter().zip(descriptor_sets.iter()) {
+                    logical_device.cmd_bind_descriptor_sets(
+                        command_buffer,
+                        vk::PipelineBindPoint::COMPUTE,
+                        self.pipeline_layout,
+                        0,
+                        &[*set],
+                        &[],
+                    );
+                    logical_device.cmd_push_constants(
+                        command_buffer,
+                        self.pipeline_layout,
+                        vk::ShaderStageFlags::COMPUTE,
+                        0,
+                        std::slice::from_raw_parts(
+                            push_constants as *const PushConstants as *const u8,
+                            ::std::mem::size_of::<PushConstants>(),
+                        ),
+                    );
+
+                    let groups = (push_constants.size + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE;
+                    logical_device.cmd_dispatch(
+                        command_buffer,
+                        groups,
+                        groups,
+                        image::CubeImage::FACES,
+                    );
+                }
+
+                let (barrier, _) = to_sampled[0];
+                logical_device.cmd_pipeline_barrier(
+                    command_buffer,
+                    barrier.source_stage,
+                    barrier.destination_stage,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &to_sampled.iter().map(|(_, b)| *b).collect::<Vec<_>>(),
+                );
+            },
+        );
+
+        // the submission has completed, so the sets are not in use anymore
+        unsafe { logical_device.destroy_descriptor_pool(descriptor_pool, None) };
+
+        let brdf_lut = submitted.and_then(|_| {
+            let texgen = texgen::TextureGenerator::new(logical_device)?;
+            let brdf_lut = texgen.generate(
+                device,
+                command_pool,
+                queue,
+                texgen::ProceduralTexture::BrdfLut {
+                    samples: settings.samples,
+                },
+                vk::Extent2D {
+                    width: settings.brdf_lut_size,
+                    height: settings.brdf_lut_size,
+                },
+            );
+            texgen.destroy(logical_device);
+            brdf_lut
+        });
+
+        match brdf_lut {
+            Ok(brdf_lut) => EnvironmentLighting::new(device, irradiance, prefiltered, brdf_lut),
+            Err(err) => {
+                irradiance.destroy(device);
+                prefiltered.destroy(device);
+                Err(err)
+            }
+        }
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        unsafe {
+            device.destroy_pipeline(self.pipeline, None);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels_span_the_roughness_range() {
+        let settings = IblSettings::default().with_prefiltered(128, 5);
+        let levels = settings.mip_levels();
+
+        assert_eq!(levels, 5);
+        assert_eq!(roughness_of_level(0, levels), 0.0);
+        assert_eq!(roughness_of_level(2, levels), 0.5);
+        assert_eq!(roughness_of_level(levels - 1, levels), 1.0);
+
+        // a single level holds the mirror reflection
+        assert_eq!(roughness_of_level(0, 1), 0.0);
+    }
+
+    #[test]
+    fn levels_stop_at_one_texel() {
+        assert_eq!(
+            IblSettings::default().with_prefiltered(16, 10).mip_levels(),
+            5
+        );
+        assert_eq!(
+            IblSettings::default().with_prefiltered(1, 4).mip_levels(),
+            1
+        );
+        assert_eq!(
+            IblSettings::default().with_prefiltered(64, 0).mip_levels(),
+            1
+        );
+    }
+
+    #[test]
+    fn floats_are_converted_to_halves() {
+        assert_eq!(half_bits(0.0), 0x0000);
+        assert_eq!(half_bits(1.0), 0x3c00);
+        assert_eq!(half_bits(0.5), 0x3800);
+        assert_eq!(half_bits(-2.0), 0xc000);
+        // hdr values past the half range saturate to infinity
+        assert_eq!(half_bits(1.0e6), 0x7c00);
+        // smallest subnormal half
+        assert_eq!(half_bits(5.960_464_5e-8), 0x0001);
+    }
+}
@@ -1,66 +1,238 @@
 use ash::version::DeviceV1_0;
 use ash::vk;
+use ash::vk::Handle;
 
-use anyhow::anyhow;
-use anyhow::{Context, Result};
+use crate::error::{Context, Error, Result};
 
-use super::{buffers, device, texture};
+use super::{buffers, device, registry, texture, trace};
 
 use image;
 use image::GenericImageView;
 
+use std::fmt;
+
+// Returned for layout transitions the engine has no barrier for, so callers
+// can tell them apart from vulkan failures
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TransitionError {
+    UnsupportedTransition {
+        old_layout: vk::ImageLayout,
+        new_layout: vk::ImageLayout,
+    },
+}
+
+impl fmt::Display for TransitionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TransitionError::UnsupportedTransition {
+                old_layout,
+                new_layout,
+            } => write!(
+                f,
+                "unsupported layout transition from {:?} to {:?}",
+                old_layout, new_layout
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TransitionError {}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub struct TransitionBarrier {
-    src_access_mask: vk::AccessFlags,
-    dst_access_mask: vk::AccessFlags,
-    source_stage: vk::PipelineStageFlags,
-    destination_stage: vk::PipelineStageFlags,
+    pub src_access_mask: vk::AccessFlags,
+    pub dst_access_mask: vk::AccessFlags,
+    pub source_stage: vk::PipelineStageFlags,
+    pub destination_stage: vk::PipelineStageFlags,
+    // both QUEUE_FAMILY_IGNORED unless the barrier moves the image between queue families
+    pub src_queue_family_index: u32,
+    pub dst_queue_family_index: u32,
+}
+
+// Accesses and stages an image in a given layout is used with. The source side is
+// what has to finish before the image leaves the layout, the destination side is
+// what has to wait before it is used in the new one.
+struct LayoutUsage {
+    access_mask: vk::AccessFlags,
+    stage: vk::PipelineStageFlags,
+}
+
+impl LayoutUsage {
+    fn new(access_mask: vk::AccessFlags, stage: vk::PipelineStageFlags) -> Option<LayoutUsage> {
+        Some(LayoutUsage { access_mask, stage })
+    }
+
+    fn source(layout: vk::ImageLayout) -> Option<LayoutUsage> {
+        match layout {
+            // old contents are discarded so there is nothing to wait for
+            vk::ImageLayout::UNDEFINED => LayoutUsage::new(
+                vk::AccessFlags::empty(),
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+            ),
+
+            vk::ImageLayout::PREINITIALIZED => {
+                LayoutUsage::new(vk::AccessFlags::HOST_WRITE, vk::PipelineStageFlags::HOST)
+            }
+
+            vk::ImageLayout::GENERAL => LayoutUsage::new(
+                vk::AccessFlags::SHADER_WRITE,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+            ),
+
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL => LayoutUsage::new(
+                vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            ),
+
+            vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL => LayoutUsage::new(
+                vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            ),
+
+            // read only layouts have no writes to make available, only the reads to wait on
+            vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL => LayoutUsage::new(
+                vk::AccessFlags::empty(),
+                vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                    | vk::PipelineStageFlags::FRAGMENT_SHADER,
+            ),
+
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL => LayoutUsage::new(
+                vk::AccessFlags::empty(),
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+            ),
+
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL => {
+                LayoutUsage::new(vk::AccessFlags::empty(), vk::PipelineStageFlags::TRANSFER)
+            }
+
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL => LayoutUsage::new(
+                vk::AccessFlags::TRANSFER_WRITE,
+                vk::PipelineStageFlags::TRANSFER,
+            ),
+
+            // chains with the image available semaphore, which is waited on at this stage
+            vk::ImageLayout::PRESENT_SRC_KHR => LayoutUsage::new(
+                vk::AccessFlags::empty(),
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            ),
+
+            _ => None,
+        }
+    }
+
+    fn destination(layout: vk::ImageLayout) -> Option<LayoutUsage> {
+        match layout {
+            vk::ImageLayout::GENERAL => LayoutUsage::new(
+                vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+            ),
+
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL => LayoutUsage::new(
+                vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            ),
+
+            vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL => LayoutUsage::new(
+                vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                    | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+            ),
+
+            vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL => LayoutUsage::new(
+                vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ | vk::AccessFlags::SHADER_READ,
+                vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                    | vk::PipelineStageFlags::FRAGMENT_SHADER,
+            ),
+
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL => LayoutUsage::new(
+                vk::AccessFlags::SHADER_READ,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+            ),
+
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL => LayoutUsage::new(
+                vk::AccessFlags::TRANSFER_READ,
+                vk::PipelineStageFlags::TRANSFER,
+            ),
+
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL => LayoutUsage::new(
+                vk::AccessFlags::TRANSFER_WRITE,
+                vk::PipelineStageFlags::TRANSFER,
+            ),
+
+            // the presentation engine reads through the semaphore, no access to make visible
+            vk::ImageLayout::PRESENT_SRC_KHR => LayoutUsage::new(
+                vk::AccessFlags::empty(),
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            ),
+
+            // images can never be transitioned back into these
+            _ => None,
+        }
+    }
 }
 
 impl TransitionBarrier {
+    // Infers the barrier for any pair of known layouts from how each of them is used.
+    // Errors for transitions into UNDEFINED or PREINITIALIZED and for unknown layouts.
     pub fn from_layout(
         old_layout: vk::ImageLayout,
         new_layout: vk::ImageLayout,
-    ) -> Result<TransitionBarrier> {
-        match old_layout {
-            vk::ImageLayout::UNDEFINED => match new_layout {
-                vk::ImageLayout::TRANSFER_DST_OPTIMAL => Ok(TransitionBarrier {
-                    src_access_mask: vk::AccessFlags::empty(),
-                    dst_access_mask: vk::AccessFlags::TRANSFER_WRITE,
-                    source_stage: vk::PipelineStageFlags::TOP_OF_PIPE,
-                    destination_stage: vk::PipelineStageFlags::TRANSFER,
-                }),
+    ) -> std::result::Result<TransitionBarrier, TransitionError> {
+        match (
+            LayoutUsage::source(old_layout),
+            LayoutUsage::destination(new_layout),
+        ) {
+            (Some(source), Some(destination)) => Ok(TransitionBarrier {
+                src_access_mask: source.access_mask,
+                dst_access_mask: destination.access_mask,
+                source_stage: source.stage,
+                destination_stage: destination.stage,
+                src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            }),
 
-                vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL => Ok(TransitionBarrier {
-                    src_access_mask: vk::AccessFlags::empty(),
-                    dst_access_mask: vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
-                        | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
-                    source_stage: vk::PipelineStageFlags::TOP_OF_PIPE,
-                    destination_stage: vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
-                }),
+            _ => Err(TransitionError::UnsupportedTransition {
+                old_layout,
+                new_layout,
+            }),
+        }
+    }
 
-                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL => Ok(TransitionBarrier {
-                    src_access_mask: vk::AccessFlags::empty(),
-                    dst_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_READ
-                        | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
-                    source_stage: vk::PipelineStageFlags::TOP_OF_PIPE,
-                    destination_stage: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
-                }),
+    // Moves ownership of the image from one queue family to another along with
+    // the layout change. Same family transfers are left as a plain barrier.
+    pub fn with_queue_transfer(self, src_family: u32, dst_family: u32) -> TransitionBarrier {
+        if src_family == dst_family {
+            return self;
+        }
 
-                _ => Err(anyhow!("unsupported new_layout for transition")),
-            },
+        TransitionBarrier {
+            src_queue_family_index: src_family,
+            dst_queue_family_index: dst_family,
+            ..self
+        }
+    }
 
-            vk::ImageLayout::TRANSFER_DST_OPTIMAL
-                if new_layout == vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL =>
-            {
-                Ok(TransitionBarrier {
-                    src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
-                    dst_access_mask: vk::AccessFlags::SHADER_READ,
-                    source_stage: vk::PipelineStageFlags::TRANSFER,
-                    destination_stage: vk::PipelineStageFlags::FRAGMENT_SHADER,
-                })
-            }
+    pub fn is_queue_transfer(&self) -> bool {
+        self.src_queue_family_index != self.dst_queue_family_index
+    }
 
-            _ => Err(anyhow!("unsupported old_layout for transition")),
+    // Half of an ownership transfer recorded on the source queue. Access on the
+    // destination side is meaningless here, the acquire barrier makes it visible.
+    pub fn release(self) -> TransitionBarrier {
+        TransitionBarrier {
+            dst_access_mask: vk::AccessFlags::empty(),
+            destination_stage: vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            ..self
+        }
+    }
+
+    // Half of an ownership transfer recorded on the destination queue, the writes
+    // were already made available by the matching release
+    pub fn acquire(self) -> TransitionBarrier {
+        TransitionBarrier {
+            src_access_mask: vk::AccessFlags::empty(),
+            source_stage: vk::PipelineStageFlags::TOP_OF_PIPE,
+            ..self
         }
     }
 }
@@ -96,10 +268,30 @@ impl ImageData {
         image_properties: &ImageProperties,
         required_memory_properties: vk::MemoryPropertyFlags,
     ) -> Result<(vk::Image, vk::DeviceMemory)> {
+        ImageData::create_layered_image(
+            device,
+            image_properties,
+            required_memory_properties,
+            vk::ImageCreateFlags::empty(),
+            1,
+            1,
+        )
+    }
+
+    // The image without memory bound to it yet
+    pub(super) fn create_image_handle(
+        device: &device::Device,
+        image_properties: &ImageProperties,
+        flags: vk::ImageCreateFlags,
+        mip_levels: u32,
+        array_layers: u32,
+    ) -> Result<vk::Image> {
         let image_create_info = vk::ImageCreateInfo {
+            flags,
             image_type: vk::ImageType::TYPE_2D,
             format: image_properties.format,
-            array_layers: 1,
+            mip_levels,
+            array_layers,
             samples: vk::SampleCountFlags::TYPE_1,
             tiling: vk::ImageTiling::OPTIMAL,
             usage: image_properties.usage_flags,
@@ -113,30 +305,77 @@ impl ImageData {
             ..Default::default()
         };
 
-        let image = unsafe {
+        trace::call("vkCreateImage", &image_create_info, || unsafe {
             device
                 .logical_device
                 .create_image(&image_create_info, None)
                 .context("Failed to create texture image!")
-        }?;
+        })
+    }
+
+    // lazily allocated memory is only preferred, not every device has it
+    pub(super) fn memory_type_for(
+        device: &device::Device,
+        memory_type_bits: u32,
+        required_memory_properties: vk::MemoryPropertyFlags,
+    ) -> Result<u32> {
+        match device.are_properties_supported(memory_type_bits, required_memory_properties) {
+            Err(_)
+                if required_memory_properties
+                    .contains(vk::MemoryPropertyFlags::LAZILY_ALLOCATED) =>
+            {
+                device.are_properties_supported(
+                    memory_type_bits,
+                    required_memory_properties & !vk::MemoryPropertyFlags::LAZILY_ALLOCATED,
+                )
+            }
+            found => found,
+        }
+    }
+
+    fn create_layered_image(
+        device: &device::Device,
+        image_properties: &ImageProperties,
+        required_memory_properties: vk::MemoryPropertyFlags,
+        flags: vk::ImageCreateFlags,
+        mip_levels: u32,
+        array_layers: u32,
+    ) -> Result<(vk::Image, vk::DeviceMemory)> {
+        let image = ImageData::create_image_handle(
+            device,
+            image_properties,
+            flags,
+            mip_levels,
+            array_layers,
+        )?;
 
         let image_memory_requirement =
             unsafe { device.logical_device.get_image_memory_requirements(image) };
+
+        let memory_type_index = match ImageData::memory_type_for(
+            device,
+            image_memory_requirement.memory_type_bits,
+            required_memory_properties,
+        ) {
+            Ok(index) => index,
+            Err(err) => {
+                unsafe { device.logical_device.destroy_image(image, None) };
+                return Err(err);
+            }
+        };
+
         let memory_allocate_info = vk::MemoryAllocateInfo {
             allocation_size: image_memory_requirement.size,
-            memory_type_index: device.are_properties_supported(
-                image_memory_requirement.memory_type_bits,
-                required_memory_properties,
-            )?,
+            memory_type_index,
             ..Default::default()
         };
 
-        let image_memory = unsafe {
+        let image_memory = trace::call("vkAllocateMemory", &memory_allocate_info, || unsafe {
             device
                 .logical_device
                 .allocate_memory(&memory_allocate_info, None)
                 .context("failed to allocate texture image memory!")
-        }?;
+        })?;
 
         unsafe {
             device
@@ -145,6 +384,19 @@ impl ImageData {
                 .context("Failed to bind image memory!")
         }?;
 
+        if let Ok(mut resources) = device.resources.lock() {
+            resources.register_image(
+                image,
+                image_memory_requirement.size,
+                image_properties.usage_flags,
+                image_properties.format,
+                vk::Extent2D {
+                    width: image_properties.width,
+                    height: image_properties.height,
+                },
+            );
+        }
+
         Ok((image, image_memory))
     }
 
@@ -163,7 +415,98 @@ impl ImageData {
         mip_levels: u32,
     ) -> Result<()> {
         let transition_barrier_info = TransitionBarrier::from_layout(old_layout, new_layout)?;
+        let image_barriers = [ImageData::image_memory_barrier(
+            &transition_barrier_info,
+            image,
+            format,
+            old_layout,
+            new_layout,
+            mip_levels,
+        )];
 
+        buffers::CommandBuffer::record_and_submit_single_command(
+            device,
+            command_pool,
+            submit_queue,
+            |command_buffer| unsafe {
+                device.cmd_pipeline_barrier(
+                    command_buffer,
+                    transition_barrier_info.source_stage,
+                    transition_barrier_info.destination_stage,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &image_barriers,
+                )
+            },
+        )
+    }
+
+    // Transitions the image while handing it over between queue families, eg. from
+    // the transfer queue that uploaded it to the graphics queue sampling it.
+    // The release is submitted on the source queue and the acquire on the destination
+    // one, each from a pool created for that queue's family.
+    pub fn transfer_image_ownership(
+        device: &ash::Device,
+        src: (vk::CommandPool, vk::Queue, u32),
+        dst: (vk::CommandPool, vk::Queue, u32),
+        image: vk::Image,
+        format: vk::Format,
+        old_layout: vk::ImageLayout,
+        new_layout: vk::ImageLayout,
+        mip_levels: u32,
+    ) -> Result<()> {
+        let (src_pool, src_queue, src_family) = src;
+        let (dst_pool, dst_queue, dst_family) = dst;
+
+        let barrier = TransitionBarrier::from_layout(old_layout, new_layout)?
+            .with_queue_transfer(src_family, dst_family);
+
+        if !barrier.is_queue_transfer() {
+            return ImageData::transition_image_layout(
+                device, dst_pool, dst_queue, image, format, old_layout, new_layout, mip_levels,
+            );
+        }
+
+        for (barrier, command_pool, queue) in [
+            (barrier.release(), src_pool, src_queue),
+            (barrier.acquire(), dst_pool, dst_queue),
+        ]
+        .iter()
+        {
+            let image_barriers = [ImageData::image_memory_barrier(
+                barrier, image, format, old_layout, new_layout, mip_levels,
+            )];
+
+            buffers::CommandBuffer::record_and_submit_single_command(
+                device,
+                *command_pool,
+                *queue,
+                |command_buffer| unsafe {
+                    device.cmd_pipeline_barrier(
+                        command_buffer,
+                        barrier.source_stage,
+                        barrier.destination_stage,
+                        vk::DependencyFlags::empty(),
+                        &[],
+                        &[],
+                        &image_barriers,
+                    )
+                },
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn image_memory_barrier(
+        transition_barrier_info: &TransitionBarrier,
+        image: vk::Image,
+        format: vk::Format,
+        old_layout: vk::ImageLayout,
+        new_layout: vk::ImageLayout,
+        mip_levels: u32,
+    ) -> vk::ImageMemoryBarrier {
         let aspect_mask = match new_layout {
             vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL => {
                 if ImageData::has_stencil_component(format) {
@@ -176,13 +519,13 @@ impl ImageData {
             _ => vk::ImageAspectFlags::COLOR,
         };
 
-        let image_barriers = [vk::ImageMemoryBarrier {
+        vk::ImageMemoryBarrier {
             src_access_mask: transition_barrier_info.src_access_mask,
             dst_access_mask: transition_barrier_info.dst_access_mask,
             old_layout: old_layout,
             new_layout: new_layout,
-            src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
-            dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            src_queue_family_index: transition_barrier_info.src_queue_family_index,
+            dst_queue_family_index: transition_barrier_info.dst_queue_family_index,
             image,
             subresource_range: vk::ImageSubresourceRange {
                 aspect_mask,
@@ -192,24 +535,7 @@ impl ImageData {
                 layer_count: 1,
             },
             ..Default::default()
-        }];
-
-        buffers::CommandBuffer::record_and_submit_single_command(
-            device,
-            command_pool,
-            submit_queue,
-            |command_buffer| unsafe {
-                device.cmd_pipeline_barrier(
-                    command_buffer,
-                    transition_barrier_info.source_stage,
-                    transition_barrier_info.destination_stage,
-                    vk::DependencyFlags::empty(),
-                    &[],
-                    &[],
-                    &image_barriers,
-                )
-            },
-        )
+        }
     }
 
     pub fn create_image_view(
@@ -251,11 +577,20 @@ impl ImageData {
         graphics_queue: vk::Queue,
         image_type: T,
     ) -> Result<ImageData> {
-        let (image, memory) = ImageData::create_image(
-            device,
-            image_type.get_property(),
-            vk::MemoryPropertyFlags::DEVICE_LOCAL,
-        )?;
+        // transient attachments never leave the tile memory of tiled gpus, which back
+        // them lazily if at all
+        let memory_properties = if image_type
+            .get_property()
+            .usage_flags
+            .contains(vk::ImageUsageFlags::TRANSIENT_ATTACHMENT)
+        {
+            vk::MemoryPropertyFlags::DEVICE_LOCAL | vk::MemoryPropertyFlags::LAZILY_ALLOCATED
+        } else {
+            vk::MemoryPropertyFlags::DEVICE_LOCAL
+        };
+
+        let (image, memory) =
+            ImageData::create_image(device, image_type.get_property(), memory_properties)?;
 
         image_type.perform_transition(
             &device.logical_device,
@@ -270,6 +605,7 @@ impl ImageData {
             &image_type.get_property(),
             0,
         )?;
+        device.track(registry::ResourceKind::ImageView, image_view);
 
         Ok(ImageData {
             image,
@@ -277,6 +613,329 @@ impl ImageData {
             memory,
         })
     }
+
+    pub fn set_name(&self, device: &device::Device, name: &str) {
+        device.name_resource(self.image, name);
+        device.name_resource(self.image_view, &format!("{} view", name));
+        device.name_resource(self.memory, &format!("{} memory", name));
+    }
+
+    pub fn destroy(&self, device: &device::Device) {
+        if let Ok(mut resources) = device.resources.lock() {
+            resources.unregister(self.image.as_raw());
+            resources.unregister(self.image_view.as_raw());
+        }
+
+        unsafe {
+            device
+                .logical_device
+                .destroy_image_view(self.image_view, None);
+            device.logical_device.destroy_image(self.image, None);
+            device.logical_device.free_memory(self.memory, None);
+        }
+    }
+}
+
+// Six square layers of mip levels, sampled through a cube view and written by compute
+// shaders through a 2d array view of each level, see ibl::IblGenerator. Created in the
+// UNDEFINED layout, whoever writes it transitions it.
+pub struct CubeImage {
+    pub image: vk::Image,
+    pub image_view: vk::ImageView,
+    pub mip_views: Vec<vk::ImageView>,
+    pub memory: vk::DeviceMemory,
+    pub format: vk::Format,
+    pub size: u32,
+    pub mip_levels: u32,
+}
+
+impl CubeImage {
+    pub const FACES: u32 = 6;
+
+    fn create_view(
+        device: &ash::Device,
+        image: vk::Image,
+        format: vk::Format,
+        view_type: vk::ImageViewType,
+        base_mip_level: u32,
+        level_count: u32,
+        base_array_layer: u32,
+        layer_count: u32,
+    ) -> Result<vk::ImageView> {
+        let imageview_create_info = vk::ImageViewCreateInfo {
+            view_type,
+            format,
+            components: vk::ComponentMapping {
+                r: vk::ComponentSwizzle::IDENTITY,
+                g: vk::ComponentSwizzle::IDENTITY,
+                b: vk::ComponentSwizzle::IDENTITY,
+                a: vk::ComponentSwizzle::IDENTITY,
+            },
+            subresource_range: vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                base_mip_level,
+                level_count,
+                base_array_layer,
+                layer_count,
+            },
+            image,
+            ..Default::default()
+        };
+
+        unsafe {
+            device
+                .create_image_view(&imageview_create_info, None)
+                .context("failed to create cube image view")
+        }
+    }
+
+    pub fn new(
+        device: &device::Device,
+        size: u32,
+        format: vk::Format,
+        mip_levels: u32,
+    ) -> Result<CubeImage> {
+        CubeImage::with_usage(
+            device,
+            size,
+            format,
+            mip_levels,
+            vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
+        )
+    }
+
+    // eg. a color attachment the faces are rendered to, see reflection_probe
+    pub fn with_usage(
+        device: &device::Device,
+        size: u32,
+        format: vk::Format,
+        mip_levels: u32,
+        usage_flags: vk::ImageUsageFlags,
+    ) -> Result<CubeImage> {
+        let property = ImageProperties {
+            width: size,
+            height: size,
+            format,
+            usage_flags,
+            aspect_flag: vk::ImageAspectFlags::COLOR,
+        };
+
+        let (image, memory) = ImageData::create_layered_image(
+            device,
+            &property,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            vk::ImageCreateFlags::CUBE_COMPATIBLE,
+            mip_levels,
+            CubeImage::FACES,
+        )?;
+
+        let logical_device = &device.logical_device;
+        let image_view = CubeImage::create_view(
+            logical_device,
+            image,
+            format,
+            vk::ImageViewType::CUBE,
+            0,
+            mip_levels,
+            0,
+            CubeImage::FACES,
+        )?;
+        device.track(registry::ResourceKind::ImageView, image_view);
+
+        let mip_views = (0..mip_levels)
+            .map(|level| {
+                CubeImage::create_view(
+                    logical_device,
+                    image,
+                    format,
+                    vk::ImageViewType::TYPE_2D_ARRAY,
+                    level,
+                    1,
+                    0,
+                    CubeImage::FACES,
+                )
+                .map(|view| {
+                    device.track(registry::ResourceKind::ImageView, view);
+                    view
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(CubeImage {
+            image,
+            image_view,
+            mip_views,
+            memory,
+            format,
+            size,
+            mip_levels,
+        })
+    }
+
+    // A 2d view of the first level of one face, owned by the caller, eg. to attach it to
+    // a framebuffer
+    pub fn face_view(&self, device: &ash::Device, face: u32) -> Result<vk::ImageView> {
+        if face >= CubeImage::FACES {
+            return Err(Error::OutOfRange(format!("a cube has no face {}", face)));
+        }
+
+        CubeImage::create_view(
+            device,
+            self.image,
+            self.format,
+            vk::ImageViewType::TYPE_2D,
+            0,
+            1,
+            face,
+            1,
+        )
+    }
+
+    // Edge length of the faces of the level
+    pub fn mip_size(&self, level: u32) -> u32 {
+        (self.size >> level).max(1)
+    }
+
+    // Every level of every face
+    pub fn subresource_range(&self) -> vk::ImageSubresourceRange {
+        vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: self.mip_levels,
+            base_array_layer: 0,
+            layer_count: CubeImage::FACES,
+        }
+    }
+
+    pub fn set_name(&self, device: &device::Device, name: &str) {
+        device.name_resource(self.image, name);
+        device.name_resource(self.image_view, &format!("{} view", name));
+        device.name_resource(self.memory, &format!("{} memory", name));
+    }
+
+    pub fn destroy(&self, device: &device::Device) {
+        if let Ok(mut resources) = device.resources.lock() {
+            resources.unregister(self.image.as_raw());
+        }
+
+        for view in std::iter::once(&self.image_view).chain(self.mip_views.iter()) {
+            device.untrack(*view);
+            unsafe { device.logical_device.destroy_image_view(*view, None) };
+        }
+
+        unsafe {
+            device.logical_device.destroy_image(self.image, None);
+            device.logical_device.free_memory(self.memory, None);
+        }
+    }
+}
+
+// Layers of one level rendered to together through a 2d array view, eg. the eyes of a
+// stereo::StereoPass. Created in the UNDEFINED layout, whoever writes it transitions it.
+pub struct ArrayImage {
+    pub image: vk::Image,
+    pub image_view: vk::ImageView,
+    pub memory: vk::DeviceMemory,
+    pub format: vk::Format,
+    pub extent: vk::Extent2D,
+    pub layers: u32,
+}
+
+impl ArrayImage {
+    pub fn new(
+        device: &device::Device,
+        extent: vk::Extent2D,
+        format: vk::Format,
+        layers: u32,
+        usage_flags: vk::ImageUsageFlags,
+        aspect_flag: vk::ImageAspectFlags,
+    ) -> Result<ArrayImage> {
+        let property = ImageProperties {
+            width: extent.width,
+            height: extent.height,
+            format,
+            usage_flags,
+            aspect_flag,
+        };
+
+        let (image, memory) = ImageData::create_layered_image(
+            device,
+            &property,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            vk::ImageCreateFlags::empty(),
+            1,
+            layers,
+        )?;
+
+        let imageview_create_info = vk::ImageViewCreateInfo {
+            view_type: vk::ImageViewType::TYPE_2D_ARRAY,
+            format,
+            subresource_range: vk::ImageSubresourceRange {
+                aspect_mask: aspect_flag,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: layers,
+            },
+            image,
+            ..Default::default()
+        };
+
+        let image_view = unsafe {
+            device
+                .logical_device
+                .create_image_view(&imageview_create_info, None)
+                .context("failed to create array image view")
+        }?;
+        device.track(registry::ResourceKind::ImageView, image_view);
+
+        Ok(ArrayImage {
+            image,
+            image_view,
+            memory,
+            format,
+            extent,
+            layers,
+        })
+    }
+
+    // The color aspect of one layer, eg. to copy it out
+    pub fn layer(&self, layer: u32) -> Result<vk::ImageSubresourceLayers> {
+        if layer >= self.layers {
+            return Err(Error::OutOfRange(format!(
+                "the image has no layer {}, found {}",
+                layer, self.layers
+            )));
+        }
+
+        Ok(vk::ImageSubresourceLayers {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            mip_level: 0,
+            base_array_layer: layer,
+            layer_count: 1,
+        })
+    }
+
+    pub fn set_name(&self, device: &device::Device, name: &str) {
+        device.name_resource(self.image, name);
+        device.name_resource(self.image_view, &format!("{} view", name));
+        device.name_resource(self.memory, &format!("{} memory", name));
+    }
+
+    pub fn destroy(&self, device: &device::Device) {
+        if let Ok(mut resources) = device.resources.lock() {
+            resources.unregister(self.image.as_raw());
+        }
+
+        device.untrack(self.image_view);
+        unsafe {
+            device
+                .logical_device
+                .destroy_image_view(self.image_view, None);
+            device.logical_device.destroy_image(self.image, None);
+            device.logical_device.free_memory(self.memory, None);
+        }
+    }
 }
 
 pub struct TextureImageProperty {
@@ -287,6 +946,10 @@ pub struct TextureImageProperty {
 pub enum ImagePropertyType {
     TextureImage(TextureImageProperty),
     DepthImage(ImageProperties),
+    ColorImage(ImageProperties),
+    StorageImage(ImageProperties),
+    InputAttachment(ImageProperties),
+    CopyDestination(ImageProperties),
 }
 
 impl ImagePropertyType {
@@ -298,14 +961,19 @@ impl ImagePropertyType {
         image: vk::Image,
     ) -> Result<()> {
         let TextureImageProperty { property, buffer } = texture_image_property;
-        let ImageProperties { width, height, .. } = *property;
+        let ImageProperties {
+            width,
+            height,
+            format,
+            ..
+        } = *property;
 
         ImageData::transition_image_layout(
             device,
             command_pool,
             submit_queue,
             image,
-            vk::Format::R8G8B8A8_SNORM,
+            format,
             vk::ImageLayout::UNDEFINED,
             vk::ImageLayout::TRANSFER_DST_OPTIMAL,
             1,
@@ -346,7 +1014,7 @@ impl ImagePropertyType {
             command_pool,
             submit_queue,
             image,
-            vk::Format::R8G8B8A8_SNORM,
+            format,
             vk::ImageLayout::TRANSFER_DST_OPTIMAL,
             vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
             1,
@@ -358,14 +1026,37 @@ impl ImagePropertyType {
         command_pool: vk::CommandPool,
         submit_queue: vk::Queue,
         image: texture::RawImage,
+        format: vk::Format,
     ) -> Result<ImagePropertyType> {
-        let width = image.object.width();
-        let height = image.object.height();
+        let extent = vk::Extent2D {
+            width: image.object.width(),
+            height: image.object.height(),
+        };
 
+        // RGBA8 texels, see texture::ColorSpace::format
+        ImagePropertyType::texture_property_from_data(
+            device,
+            command_pool,
+            submit_queue,
+            extent,
+            &image.data,
+            format,
+        )
+    }
+
+    // Texels already packed in the format, eg. half floats of an hdr image
+    pub fn texture_property_from_data(
+        device: &device::Device,
+        command_pool: vk::CommandPool,
+        submit_queue: vk::Queue,
+        extent: vk::Extent2D,
+        data: &[u8],
+        format: vk::Format,
+    ) -> Result<ImagePropertyType> {
         let property = ImageProperties {
-            width,
-            height,
-            format: vk::Format::R8G8B8A8_SRGB,
+            width: extent.width,
+            height: extent.height,
+            format,
             usage_flags: vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
             aspect_flag: vk::ImageAspectFlags::COLOR,
         };
@@ -375,8 +1066,8 @@ impl ImagePropertyType {
             command_pool,
             submit_queue,
             vk::BufferUsageFlags::TRANSFER_SRC,
-            &image.data,
-            Some(image.size),
+            data,
+            Some(data.len() as vk::DeviceSize),
         )
         .map(|buffer_info| {
             ImagePropertyType::TextureImage(TextureImageProperty {
@@ -395,6 +1086,57 @@ impl ImagePropertyType {
             aspect_flag: vk::ImageAspectFlags::DEPTH,
         })
     }
+
+    // Offscreen color target which is rendered into and later sampled by another pass
+    pub fn color_property(extent: vk::Extent2D, format: vk::Format) -> ImagePropertyType {
+        ImagePropertyType::ColorImage(ImageProperties {
+            width: extent.width,
+            height: extent.height,
+            format,
+            usage_flags: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+            aspect_flag: vk::ImageAspectFlags::COLOR,
+        })
+    }
+
+    // Written by compute shaders and later sampled
+    pub fn storage_property(extent: vk::Extent2D, format: vk::Format) -> ImagePropertyType {
+        ImagePropertyType::StorageImage(ImageProperties {
+            width: extent.width,
+            height: extent.height,
+            format,
+            usage_flags: vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
+            aspect_flag: vk::ImageAspectFlags::COLOR,
+        })
+    }
+
+    // Receives a copy of another image every frame which is then sampled, see
+    // image_effects::ComputeImageEffects
+    pub fn copy_property(extent: vk::Extent2D, format: vk::Format) -> ImagePropertyType {
+        ImagePropertyType::CopyDestination(ImageProperties {
+            width: extent.width,
+            height: extent.height,
+            format,
+            usage_flags: vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
+            aspect_flag: vk::ImageAspectFlags::COLOR,
+        })
+    }
+
+    // Written by one subpass and read by a later one of the same render pass, its
+    // contents never leave the pass, see deferred::DeferredPass
+    pub fn input_attachment_property(
+        extent: vk::Extent2D,
+        format: vk::Format,
+    ) -> ImagePropertyType {
+        ImagePropertyType::InputAttachment(ImageProperties {
+            width: extent.width,
+            height: extent.height,
+            format,
+            usage_flags: vk::ImageUsageFlags::COLOR_ATTACHMENT
+                | vk::ImageUsageFlags::INPUT_ATTACHMENT
+                | vk::ImageUsageFlags::TRANSIENT_ATTACHMENT,
+            aspect_flag: vk::ImageAspectFlags::COLOR,
+        })
+    }
 }
 
 impl ImageType for ImagePropertyType {
@@ -402,6 +1144,10 @@ impl ImageType for ImagePropertyType {
         match self {
             ImagePropertyType::TextureImage(p) => &p.property,
             ImagePropertyType::DepthImage(p) => p,
+            ImagePropertyType::ColorImage(p) => p,
+            ImagePropertyType::StorageImage(p) => p,
+            ImagePropertyType::InputAttachment(p) => p,
+            ImagePropertyType::CopyDestination(p) => p,
         }
     }
 
@@ -430,6 +1176,322 @@ impl ImageType for ImagePropertyType {
                 vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
                 0,
             ),
+            ImagePropertyType::ColorImage(prop) => ImageData::transition_image_layout(
+                device,
+                command_pool,
+                graphics_queue,
+                image,
+                prop.format,
+                vk::ImageLayout::UNDEFINED,
+                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                1,
+            ),
+            ImagePropertyType::StorageImage(prop) => ImageData::transition_image_layout(
+                device,
+                command_pool,
+                graphics_queue,
+                image,
+                prop.format,
+                vk::ImageLayout::UNDEFINED,
+                vk::ImageLayout::GENERAL,
+                1,
+            ),
+            // the render pass starts from UNDEFINED and discards the contents anyway
+            ImagePropertyType::InputAttachment(_) => Ok(()),
+            // every copy transitions it from UNDEFINED as its contents are replaced
+            ImagePropertyType::CopyDestination(_) => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Expected {
+        usage: &'static str,
+        old_layout: vk::ImageLayout,
+        new_layout: vk::ImageLayout,
+        barrier: TransitionBarrier,
+    }
+
+    fn supported_transitions() -> Vec<Expected> {
+        vec![
+            Expected {
+                usage: "upload destination",
+                old_layout: vk::ImageLayout::UNDEFINED,
+                new_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                barrier: TransitionBarrier {
+                    src_access_mask: vk::AccessFlags::empty(),
+                    dst_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+                    source_stage: vk::PipelineStageFlags::TOP_OF_PIPE,
+                    destination_stage: vk::PipelineStageFlags::TRANSFER,
+                    src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                    dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                },
+            },
+            Expected {
+                usage: "depth attachment",
+                old_layout: vk::ImageLayout::UNDEFINED,
+                new_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                barrier: TransitionBarrier {
+                    src_access_mask: vk::AccessFlags::empty(),
+                    dst_access_mask: vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                        | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                    source_stage: vk::PipelineStageFlags::TOP_OF_PIPE,
+                    destination_stage: vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+                    src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                    dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                },
+            },
+            Expected {
+                usage: "color attachment",
+                old_layout: vk::ImageLayout::UNDEFINED,
+                new_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                barrier: TransitionBarrier {
+                    src_access_mask: vk::AccessFlags::empty(),
+                    dst_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_READ
+                        | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                    source_stage: vk::PipelineStageFlags::TOP_OF_PIPE,
+                    destination_stage: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                    src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                    dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                },
+            },
+            Expected {
+                usage: "compute storage image",
+                old_layout: vk::ImageLayout::UNDEFINED,
+                new_layout: vk::ImageLayout::GENERAL,
+                barrier: TransitionBarrier {
+                    src_access_mask: vk::AccessFlags::empty(),
+                    dst_access_mask: vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+                    source_stage: vk::PipelineStageFlags::TOP_OF_PIPE,
+                    destination_stage: vk::PipelineStageFlags::COMPUTE_SHADER,
+                    src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                    dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                },
+            },
+            Expected {
+                usage: "sampling an uploaded texture",
+                old_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                new_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                barrier: TransitionBarrier {
+                    src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+                    dst_access_mask: vk::AccessFlags::SHADER_READ,
+                    source_stage: vk::PipelineStageFlags::TRANSFER,
+                    destination_stage: vk::PipelineStageFlags::FRAGMENT_SHADER,
+                    src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                    dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                },
+            },
+            Expected {
+                usage: "sampling a compute generated texture",
+                old_layout: vk::ImageLayout::GENERAL,
+                new_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                barrier: TransitionBarrier {
+                    src_access_mask: vk::AccessFlags::SHADER_WRITE,
+                    dst_access_mask: vk::AccessFlags::SHADER_READ,
+                    source_stage: vk::PipelineStageFlags::COMPUTE_SHADER,
+                    destination_stage: vk::PipelineStageFlags::FRAGMENT_SHADER,
+                    src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                    dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                },
+            },
+            Expected {
+                usage: "sampling an offscreen target",
+                old_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                new_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                barrier: TransitionBarrier {
+                    src_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                    dst_access_mask: vk::AccessFlags::SHADER_READ,
+                    source_stage: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                    destination_stage: vk::PipelineStageFlags::FRAGMENT_SHADER,
+                    src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                    dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                },
+            },
+            Expected {
+                usage: "reading back a sampled image",
+                old_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                new_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                barrier: TransitionBarrier {
+                    src_access_mask: vk::AccessFlags::empty(),
+                    dst_access_mask: vk::AccessFlags::TRANSFER_READ,
+                    source_stage: vk::PipelineStageFlags::FRAGMENT_SHADER,
+                    destination_stage: vk::PipelineStageFlags::TRANSFER,
+                    src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                    dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                },
+            },
+            Expected {
+                usage: "rendering into an acquired swapchain image",
+                old_layout: vk::ImageLayout::PRESENT_SRC_KHR,
+                new_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                barrier: TransitionBarrier {
+                    src_access_mask: vk::AccessFlags::empty(),
+                    dst_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_READ
+                        | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                    source_stage: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                    destination_stage: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                    src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                    dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                },
+            },
+            Expected {
+                usage: "presenting a rendered image",
+                old_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                new_layout: vk::ImageLayout::PRESENT_SRC_KHR,
+                barrier: TransitionBarrier {
+                    src_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                    dst_access_mask: vk::AccessFlags::empty(),
+                    source_stage: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                    destination_stage: vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                    src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                    dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                },
+            },
+        ]
+    }
+
+    // Every core layout along with the presentable one
+    const ALL_LAYOUTS: [vk::ImageLayout; 10] = [
+        vk::ImageLayout::UNDEFINED,
+        vk::ImageLayout::GENERAL,
+        vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+        vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        vk::ImageLayout::PREINITIALIZED,
+        vk::ImageLayout::PRESENT_SRC_KHR,
+    ];
+
+    #[test]
+    fn supported_transitions_have_expected_barriers() {
+        for expected in supported_transitions() {
+            let barrier = TransitionBarrier::from_layout(expected.old_layout, expected.new_layout)
+                .unwrap_or_else(|err| panic!("{}: {}", expected.usage, err));
+
+            assert_eq!(barrier, expected.barrier, "{}", expected.usage);
+        }
+    }
+
+    #[test]
+    fn every_known_layout_pair_has_a_barrier() {
+        for &old_layout in ALL_LAYOUTS.iter() {
+            for &new_layout in ALL_LAYOUTS.iter() {
+                let into_initial = new_layout == vk::ImageLayout::UNDEFINED
+                    || new_layout == vk::ImageLayout::PREINITIALIZED;
+
+                let barrier = TransitionBarrier::from_layout(old_layout, new_layout);
+
+                if into_initial {
+                    assert_eq!(
+                        barrier,
+                        Err(TransitionError::UnsupportedTransition {
+                            old_layout,
+                            new_layout,
+                        }),
+                        "{:?} -> {:?} should not be supported",
+                        old_layout,
+                        new_layout
+                    );
+                } else {
+                    assert!(
+                        barrier.is_ok(),
+                        "{:?} -> {:?} should be supported",
+                        old_layout,
+                        new_layout
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_layout_values_are_rejected() {
+        // raw values no layout is defined for, eg. from a corrupted state tracker
+        let unknown = [
+            vk::ImageLayout::from_raw(-1),
+            vk::ImageLayout::from_raw(9),
+            vk::ImageLayout::from_raw(i32::max_value()),
+        ];
+
+        for &layout in unknown.iter() {
+            for &other in ALL_LAYOUTS.iter().chain(unknown.iter()) {
+                assert!(TransitionBarrier::from_layout(layout, other).is_err());
+                assert!(TransitionBarrier::from_layout(other, layout).is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn queue_transfer_sets_both_family_indices() {
+        let barrier = TransitionBarrier::from_layout(
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        )
+        .unwrap();
+
+        assert!(!barrier.is_queue_transfer());
+
+        let transfer = barrier.with_queue_transfer(1, 0);
+        assert!(transfer.is_queue_transfer());
+
+        for half in [transfer.release(), transfer.acquire()].iter() {
+            assert_eq!(half.src_queue_family_index, 1);
+            assert_eq!(half.dst_queue_family_index, 0);
+        }
+
+        // a transfer within one family is just the layout transition
+        assert_eq!(barrier.with_queue_transfer(2, 2), barrier);
+    }
+
+    #[test]
+    fn release_and_acquire_split_the_access_masks() {
+        let transfer = TransitionBarrier::from_layout(
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        )
+        .unwrap()
+        .with_queue_transfer(1, 0);
+
+        let release = transfer.release();
+        assert_eq!(release.src_access_mask, vk::AccessFlags::TRANSFER_WRITE);
+        assert_eq!(release.source_stage, vk::PipelineStageFlags::TRANSFER);
+        assert!(release.dst_access_mask.is_empty());
+        assert_eq!(
+            release.destination_stage,
+            vk::PipelineStageFlags::BOTTOM_OF_PIPE
+        );
+
+        let acquire = transfer.acquire();
+        assert!(acquire.src_access_mask.is_empty());
+        assert_eq!(acquire.source_stage, vk::PipelineStageFlags::TOP_OF_PIPE);
+        assert_eq!(acquire.dst_access_mask, vk::AccessFlags::SHADER_READ);
+        assert_eq!(
+            acquire.destination_stage,
+            vk::PipelineStageFlags::FRAGMENT_SHADER
+        );
+    }
+
+    #[test]
+    fn transitions_block_a_real_stage() {
+        for expected in supported_transitions() {
+            let barrier = expected.barrier;
+
+            assert_ne!(
+                barrier.destination_stage,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                "{}",
+                expected.usage
+            );
+
+            // nothing has to be made visible when the old contents are discarded
+            if expected.old_layout == vk::ImageLayout::UNDEFINED {
+                assert!(barrier.src_access_mask.is_empty(), "{}", expected.usage);
+            }
         }
     }
 }
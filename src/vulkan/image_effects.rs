@@ -0,0 +1,844 @@
+use ash::version::{DeviceV1_0, InstanceV1_0};
+use ash::vk;
+
+use crate::error::{Context, Error, Result};
+
+use std::ffi::CString;
+
+use crate::shaderc;
+
+use super::device;
+use super::frame;
+use super::image;
+use super::pipeline;
+use super::registry;
+use super::swapchain;
+
+pub const IMAGE_EFFECT_SHADER_FILE: &'static str = "shaders/image_effect.comp";
+
+// Matches the local size declared in shaders/image_effect.comp
+const WORKGROUP_SIZE: u32 = 8;
+
+// EFFECT_* of shaders/image_effect.comp
+const EFFECT_SHARPEN: u32 = 0;
+const EFFECT_COLOR_GRADE: u32 = 1;
+
+// Lift, gamma and gain per channel followed by a saturation adjustment, applied to the
+// values as they are stored in the target
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ColorGrade {
+    // raises the blacks, 0 leaves them unchanged
+    pub lift: [f32; 3],
+    pub gamma: [f32; 3],
+    // scales the whites, 1 leaves them unchanged
+    pub gain: [f32; 3],
+    // 0 is grayscale
+    pub saturation: f32,
+}
+
+impl ColorGrade {
+    pub fn with_lift(mut self, lift: [f32; 3]) -> ColorGrade {
+        self.lift = lift;
+        self
+    }
+
+    pub fn with_gamma(mut self, gamma: [f32; 3]) -> ColorGrade {
+        self.gamma = gamma;
+        self
+    }
+
+    pub fn with_gain(mut self, gain: [f32; 3]) -> ColorGrade {
+        self.gain = gain;
+        self
+    }
+
+    pub fn with_saturation(mut self, saturation: f32) -> ColorGrade {
+        self.saturation = saturation;
+        self
+    }
+}
+
+// Leaves the image unchanged
+impl Default for ColorGrade {
+    fn default() -> ColorGrade {
+        ColorGrade {
+            lift: [0.0; 3],
+            gamma: [1.0; 3],
+            gain: [1.0; 3],
+            saturation: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ImageEffect {
+    // unsharp mask, 0 leaves the image unchanged
+    Sharpen { strength: f32 },
+    ColorGrade(ColorGrade),
+}
+
+impl ImageEffect {
+    fn validate(&self) -> Result<()> {
+        match self {
+            ImageEffect::ColorGrade(grade) if grade.gamma.iter().any(|&gamma| gamma <= 0.0) => {
+                Err(Error::OutOfRange(format!(
+                    "color grade gamma has to be positive, found {:?}",
+                    grade.gamma
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+// See shaders/image_effect.comp
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct PushConstants {
+    extent: [u32; 2],
+    effect: u32,
+    strength: f32,
+    lift: [f32; 4],
+    gamma: [f32; 4],
+    gain: [f32; 4],
+    saturation: f32,
+}
+
+impl PushConstants {
+    fn new(effect: &ImageEffect, extent: vk::Extent2D) -> PushConstants {
+        let extend = |v: [f32; 3]| [v[0], v[1], v[2], 0.0];
+        let identity = ColorGrade::default();
+
+        let (effect, strength, grade) = match *effect {
+            ImageEffect::Sharpen { strength } => (EFFECT_SHARPEN, strength, identity),
+            ImageEffect::ColorGrade(grade) => (EFFECT_COLOR_GRADE, 0.0, grade),
+        };
+
+        PushConstants {
+            extent: [extent.width, extent.height],
+            effect,
+            strength,
+            lift: extend(grade.lift),
+            gamma: extend(grade.gamma),
+            gain: extend(grade.gain),
+            saturation: grade.saturation,
+        }
+    }
+}
+
+// Images the effects run on in place, eg. the swapchain images or offscreen targets
+// created with STORAGE and TRANSFER_SRC usage. They are expected in `layout` when the
+// effects start and are left in it.
+pub struct EffectTargets {
+    pub images: Vec<vk::Image>,
+    pub views: Vec<vk::ImageView>,
+    pub format: vk::Format,
+    pub extent: vk::Extent2D,
+    pub layout: vk::ImageLayout,
+}
+
+impl EffectTargets {
+    // Runs after the passes drawing the image, which leave it ready to present
+    pub fn swapchain(swapchain: &swapchain::SwapchainDetails) -> EffectTargets {
+        EffectTargets {
+            images: swapchain.images.clone(),
+            views: swapchain.image_views.clone(),
+            format: swapchain.format.format,
+            extent: swapchain.extent,
+            layout: vk::ImageLayout::PRESENT_SRC_KHR,
+        }
+    }
+}
+
+// Runs compute effects such as sharpening or color grading on finished images without a
+// graphics pass. Every effect copies the target into a sampled image, then writes the
+// target through a storage view while reading the copy. Command buffers are re-recorded
+// every frame, so the effects can be changed at any time.
+pub struct ComputeImageEffects {
+    device: device::Device,
+    effects: Vec<ImageEffect>,
+
+    targets: EffectTargets,
+    // one per target, holds the contents before the running effect
+    copies: Vec<image::ImageData>,
+    sampler: vk::Sampler,
+
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_sets: Vec<vk::DescriptorSet>,
+    pub pipeline_layout: vk::PipelineLayout,
+    pub pipeline: vk::Pipeline,
+
+    command_pool: vk::CommandPool,
+    command_buffers: Vec<vk::CommandBuffer>,
+}
+
+impl ComputeImageEffects {
+    // The targets are copied out and sampled, then written without a format qualifier by
+    // the graphics queue, which has to run compute work
+    pub fn is_supported(
+        instance: &ash::Instance,
+        device: &device::Device,
+        format: vk::Format,
+    ) -> bool {
+        let format_properties = unsafe {
+            instance.get_physical_device_format_properties(device.physical_device, format)
+        };
+
+        format_properties.optimal_tiling_features.contains(
+            vk::FormatFeatureFlags::STORAGE_IMAGE
+                | vk::FormatFeatureFlags::SAMPLED_IMAGE
+                | vk::FormatFeatureFlags::TRANSFER_SRC
+                | vk::FormatFeatureFlags::TRANSFER_DST,
+        ) && device.features.shader_storage_image_write_without_format == vk::TRUE
+            && device.graphics_queue_supports_compute(instance)
+    }
+
+    // The swapchain images additionally need to have been created with the usages
+    pub fn is_supported_by_swapchain(
+        instance: &ash::Instance,
+        device: &device::Device,
+        swapchain: &swapchain::SwapchainDetails,
+    ) -> bool {
+        swapchain.supports_storage()
+            && swapchain.supports_transfer_src()
+            && ComputeImageEffects::is_supported(instance, device, swapchain.format.format)
+    }
+
+    fn create_descriptor_set_layout(device: &ash::Device) -> Result<vk::DescriptorSetLayout> {
+        let bindings = [
+            vk::DescriptorSetLayoutBinding {
+                binding: 0,
+                descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: 1,
+                stage_flags: vk::ShaderStageFlags::COMPUTE,
+                ..Default::default()
+            },
+            vk::DescriptorSetLayoutBinding {
+                binding: 1,
+                descriptor_type: vk::DescriptorType::STORAGE_IMAGE,
+                descriptor_count: 1,
+                stage_flags: vk::ShaderStageFlags::COMPUTE,
+                ..Default::default()
+            },
+        ];
+
+        let layout_info = vk::DescriptorSetLayoutCreateInfo {
+            binding_count: bindings.len() as u32,
+            p_bindings: bindings.as_ptr(),
+            ..Default::default()
+        };
+
+        unsafe {
+            device
+                .create_descriptor_set_layout(&layout_info, None)
+                .context("failed to create image effect descriptor set layout")
+        }
+    }
+
+    // The shader fetches texels, no filtering is done
+    fn create_sampler(device: &ash::Device) -> Result<vk::Sampler> {
+        let sampler_info = vk::SamplerCreateInfo {
+            mag_filter: vk::Filter::NEAREST,
+            min_filter: vk::Filter::NEAREST,
+            address_mode_u: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            address_mode_v: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            address_mode_w: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            compare_enable: vk::FALSE,
+            compare_op: vk::CompareOp::ALWAYS,
+            mipmap_mode: vk::SamplerMipmapMode::NEAREST,
+            border_color: vk::BorderColor::INT_OPAQUE_BLACK,
+            anisotropy_enable: vk::FALSE,
+            unnormalized_coordinates: vk::FALSE,
+            ..Default::default()
+        };
+
+        unsafe {
+            device
+                .create_sampler(&sampler_info, None)
+                .context("failed to create image effect sampler")
+        }
+    }
+
+    // One set per target, reading its copy and writing its view
+    fn create_descriptor_sets(
+        device: &ash::Device,
+        layout: vk::DescriptorSetLayout,
+        sampler: vk::Sampler,
+        copy_views: &[vk::ImageView],
+        target_views: &[vk::ImageView],
+    ) -> Result<(vk::DescriptorPool, Vec<vk::DescriptorSet>)> {
+        let count = target_views.len() as u32;
+        let pool_sizes = [
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: count,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::STORAGE_IMAGE,
+                descriptor_count: count,
+            },
+        ];
+
+        let pool_info = vk::DescriptorPoolCreateInfo {
+            pool_size_count: pool_sizes.len() as u32,
+            p_pool_sizes: pool_sizes.as_ptr(),
+            max_sets: count,
+            ..Default::default()
+        };
+
+        let pool = unsafe {
+            device
+                .create_descriptor_pool(&pool_info, None)
+                .context("failed to create image effect descriptor pool")
+        }?;
+
+        let layouts = vec![layout; target_views.len()];
+        let alloc_info = vk::DescriptorSetAllocateInfo {
+            descriptor_pool: pool,
+            descriptor_set_count: layouts.len() as u32,
+            p_set_layouts: layouts.as_ptr(),
+            ..Default::default()
+        };
+
+        let descriptor_sets = unsafe {
+            device
+                .allocate_descriptor_sets(&alloc_info)
+                .context("failed to allocate image effect descriptor sets")
+        }?;
+
+        let image_infos = copy_views
+            .iter()
+            .zip(target_views.iter())
+            .map(|(&copy_view, &target_view)| {
+                [
+                    vk::DescriptorImageInfo {
+                        sampler,
+                        image_view: copy_view,
+                        image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    },
+                    vk::DescriptorImageInfo {
+                        sampler: vk::Sampler::null(),
+                        image_view: target_view,
+                        image_layout: vk::ImageLayout::GENERAL,
+                    },
+                ]
+            })
+            .collect::<Vec<[vk::DescriptorImageInfo; 2]>>();
+
+        let descriptor_writes = descriptor_sets
+            .iter()
+            .zip(image_infos.iter())
+            .flat_map(|(&dst_set, infos)| {
+                vec![
+                    vk::WriteDescriptorSet {
+                        dst_set,
+                        dst_binding: 0,
+                        descriptor_count: 1,
+                        descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                        p_image_info: &infos[0],
+                        ..Default::default()
+                    },
+                    vk::WriteDescriptorSet {
+                        dst_set,
+                        dst_binding: 1,
+                        descriptor_count: 1,
+                        descriptor_type: vk::DescriptorType::STORAGE_IMAGE,
+                        p_image_info: &infos[1],
+                        ..Default::default()
+                    },
+                ]
+            })
+            .collect::<Vec<vk::WriteDescriptorSet>>();
+
+        unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+
+        Ok((pool, descriptor_sets))
+    }
+
+    fn create_pipeline(
+        device: &ash::Device,
+        pipeline_layout: vk::PipelineLayout,
+    ) -> Result<vk::Pipeline> {
+        let code = shaderc::ShaderSource::compile_compute(&IMAGE_EFFECT_SHADER_FILE.to_string())?;
+        let shader_module = pipeline::PipelineDetail::create_shader_module(device, code)?;
+
+        let main_function_name = CString::new("main").context("invalid fn name")?;
+
+        let pipeline_info = vk::ComputePipelineCreateInfo {
+            stage: vk::PipelineShaderStageCreateInfo {
+                module: shader_module,
+                p_name: main_function_name.as_ptr(),
+                stage: vk::ShaderStageFlags::COMPUTE,
+                ..Default::default()
+            },
+            layout: pipeline_layout,
+            base_pipeline_index: -1,
+            ..Default::default()
+        };
+
+        let pipelines = unsafe {
+            device.create_compute_pipelines(vk::PipelineCache::null(), &[pipeline_info], None)
+        };
+
+        unsafe { device.destroy_shader_module(shader_module, None) };
+
+        pipelines
+            .map(|pipelines| pipelines[0])
+            .map_err(|(_, err)| err)
+            .context("failed to create image effect pipeline")
+    }
+
+    fn create_command_buffers(
+        device: &device::Device,
+        count: u32,
+    ) -> Result<(vk::CommandPool, Vec<vk::CommandBuffer>)> {
+        let queue_index = device
+            .family_indices
+            .graphics
+            .ok_or_else(|| Error::msg("graphics family index not present"))?;
+
+        // buffers are reset and recorded again every frame
+        let command_pool_info = vk::CommandPoolCreateInfo {
+            queue_family_index: queue_index,
+            flags: vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER,
+            ..Default::default()
+        };
+
+        let command_pool = unsafe {
+            device
+                .logical_device
+                .create_command_pool(&command_pool_info, None)
+                .context("failed to create image effect command pool")
+        }?;
+
+        let alloc_info = vk::CommandBufferAllocateInfo {
+            command_buffer_count: count,
+            command_pool,
+            level: vk::CommandBufferLevel::PRIMARY,
+            ..Default::default()
+        };
+
+        let command_buffers = unsafe {
+            device
+                .logical_device
+                .allocate_command_buffers(&alloc_info)
+                .context("failed to allocate image effect command buffers")
+        }?;
+
+        Ok((command_pool, command_buffers))
+    }
+
+    pub fn new(
+        instance: &ash::Instance,
+        device: &device::Device,
+        graphics_queue: vk::Queue,
+        targets: EffectTargets,
+        effects: Vec<ImageEffect>,
+    ) -> Result<ComputeImageEffects> {
+        if !ComputeImageEffects::is_supported(instance, device, targets.format) {
+            return Err(Error::Unsupported(format!(
+                "format {:?} cannot be written by compute effects",
+                targets.format
+            )));
+        }
+
+        for effect in effects.iter() {
+            effect.validate()?;
+        }
+
+        let logical_device = &device.logical_device;
+
+        let (command_pool, command_buffers) =
+            ComputeImageEffects::create_command_buffers(device, targets.images.len() as u32)?;
+
+        let copies = (0..targets.images.len())
+            .map(|index| {
+                let property =
+                    image::ImagePropertyType::copy_property(targets.extent, targets.format);
+                let copy = image::ImageData::new(device, command_pool, graphics_queue, property)?;
+                copy.set_name(device, &format!("image effect copy {}", index));
+
+                Ok(copy)
+            })
+            .collect::<Result<Vec<image::ImageData>>>()?;
+        let copy_views = copies
+            .iter()
+            .map(|copy| copy.image_view)
+            .collect::<Vec<vk::ImageView>>();
+
+        let sampler = ComputeImageEffects::create_sampler(logical_device)?;
+        let descriptor_set_layout =
+            ComputeImageEffects::create_descriptor_set_layout(logical_device)?;
+        let (descriptor_pool, descriptor_sets) = ComputeImageEffects::create_descriptor_sets(
+            logical_device,
+            descriptor_set_layout,
+            sampler,
+            &copy_views,
+            &targets.views,
+        )?;
+
+        let push_constant_ranges = [vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::COMPUTE,
+            offset: 0,
+            size: ::std::mem::size_of::<PushConstants>() as u32,
+        }];
+
+        let set_layouts = [descriptor_set_layout];
+        let layout_info = vk::PipelineLayoutCreateInfo {
+            set_layout_count: set_layouts.len() as u32,
+            p_set_layouts: set_layouts.as_ptr(),
+            push_constant_range_count: push_constant_ranges.len() as u32,
+            p_push_constant_ranges: push_constant_ranges.as_ptr(),
+            ..Default::default()
+        };
+
+        let pipeline_layout = unsafe {
+            logical_device
+                .create_pipeline_layout(&layout_info, None)
+                .context("failed to create image effect pipeline layout")
+        }?;
+
+        let pipeline = ComputeImageEffects::create_pipeline(logical_device, pipeline_layout)?;
+
+        device.track(registry::ResourceKind::Sampler, sampler);
+        device.track(
+            registry::ResourceKind::DescriptorSetLayout,
+            descriptor_set_layout,
+        );
+        device.track(registry::ResourceKind::DescriptorPool, descriptor_pool);
+        device.track(registry::ResourceKind::PipelineLayout, pipeline_layout);
+        device.track(registry::ResourceKind::Pipeline, pipeline);
+        device.track(registry::ResourceKind::CommandPool, command_pool);
+
+        Ok(ComputeImageEffects {
+            device: device.clone(),
+            effects,
+            targets,
+            copies,
+            sampler,
+            descriptor_set_layout,
+            descriptor_pool,
+            descriptor_sets,
+            pipeline_layout,
+            pipeline,
+            command_pool,
+            command_buffers,
+        })
+    }
+
+    pub fn effects(&self) -> &[ImageEffect] {
+        &self.effects
+    }
+
+    // Picked up the next time the effects are recorded
+    pub fn set_effects(&mut self, effects: Vec<ImageEffect>) -> Result<()> {
+        for effect in effects.iter() {
+            effect.validate()?;
+        }
+
+        self.effects = effects;
+        Ok(())
+    }
+
+    fn layout_barrier(
+        image: vk::Image,
+        old_layout: vk::ImageLayout,
+        new_layout: vk::ImageLayout,
+        src_access_mask: vk::AccessFlags,
+        dst_access_mask: vk::AccessFlags,
+    ) -> vk::ImageMemoryBarrier {
+        vk::ImageMemoryBarrier {
+            src_access_mask,
+            dst_access_mask,
+            old_layout,
+            new_layout,
+            src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            image,
+            subresource_range: vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: 1,
+            },
+            ..Default::default()
+        }
+    }
+
+    // Records the effects on the target into a command buffer that is being recorded, eg.
+    // one finishing an offscreen image. Nothing is recorded without effects.
+    pub fn record_effects(&self, command_buffer: vk::CommandBuffer, target: usize) -> Result<()> {
+        if self.effects.is_empty() {
+            return Ok(());
+        }
+
+        let logical_device = &self.device.logical_device;
+
+        let image = *self.targets.images.get(target).ok_or_else(|| {
+            Error::OutOfRange(format!(
+                "no effect target {}, found {}",
+                target,
+                self.targets.images.len()
+            ))
+        })?;
+        let copy = self.copies[target].image;
+        let descriptor_set = self.descriptor_sets[target];
+        let extent = self.targets.extent;
+
+        let subresource = vk::ImageSubresourceLayers {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            mip_level: 0,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        let copy_region = vk::ImageCopy {
+            src_subresource: subresource,
+            src_offset: vk::Offset3D { x: 0, y: 0, z: 0 },
+            dst_subresource: subresource,
+            dst_offset: vk::Offset3D { x: 0, y: 0, z: 0 },
+            extent: vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            },
+        };
+
+        // whatever wrote the target before the effects, including the previous effect
+        let writes = vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+            | vk::AccessFlags::SHADER_WRITE
+            | vk::AccessFlags::TRANSFER_WRITE;
+        let writers = vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+            | vk::PipelineStageFlags::COMPUTE_SHADER
+            | vk::PipelineStageFlags::TRANSFER;
+
+        let mut layout = self.targets.layout;
+
+        for effect in self.effects.iter() {
+            let push_constants = PushConstants::new(effect, extent);
+            let push_constant_bytes = unsafe {
+                ::std::slice::from_raw_parts(
+                    &push_constants as *const PushConstants as *const u8,
+                    ::std::mem::size_of::<PushConstants>(),
+                )
+            };
+
+            // the copy is overwritten, the previous effect may still be sampling it
+            let to_copy = [
+                ComputeImageEffects::layout_barrier(
+                    image,
+                    layout,
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    writes,
+                    vk::AccessFlags::TRANSFER_READ,
+                ),
+                ComputeImageEffects::layout_barrier(
+                    copy,
+                    vk::ImageLayout::UNDEFINED,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    vk::AccessFlags::empty(),
+                    vk::AccessFlags::TRANSFER_WRITE,
+                ),
+            ];
+
+            let to_dispatch = [
+                ComputeImageEffects::layout_barrier(
+                    image,
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    vk::ImageLayout::GENERAL,
+                    vk::AccessFlags::empty(),
+                    vk::AccessFlags::SHADER_WRITE,
+                ),
+                ComputeImageEffects::layout_barrier(
+                    copy,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    vk::AccessFlags::TRANSFER_WRITE,
+                    vk::AccessFlags::SHADER_READ,
+                ),
+            ];
+
+            unsafe {
+                logical_device.cmd_pipeline_barrier(
+                    command_buffer,
+                    writers,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &to_copy,
+                );
+
+                logical_device.cmd_copy_image(
+                    command_buffer,
+                    image,
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    copy,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    &[copy_region],
+                );
+
+                logical_device.cmd_pipeline_barrier(
+                    command_buffer,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::PipelineStageFlags::COMPUTE_SHADER,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &to_dispatch,
+                );
+
+                logical_device.cmd_bind_pipeline(
+                    command_buffer,
+                    vk::PipelineBindPoint::COMPUTE,
+                    self.pipeline,
+                );
+                logical_device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::COMPUTE,
+                    self.pipeline_layout,
+                    0,
+                    &[descriptor_set],
+                    &[],
+                );
+                logical_device.cmd_push_constants(
+                    command_buffer,
+                    self.pipeline_layout,
+                    vk::ShaderStageFlags::COMPUTE,
+                    0,
+                    push_constant_bytes,
+                );
+                logical_device.cmd_dispatch(
+                    command_buffer,
+                    (extent.width + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE,
+                    (extent.height + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE,
+                    1,
+                );
+            }
+
+            layout = vk::ImageLayout::GENERAL;
+        }
+
+        // overlays drawn afterwards load the target, offscreen ones may be sampled
+        let to_target_layout = ComputeImageEffects::layout_barrier(
+            image,
+            vk::ImageLayout::GENERAL,
+            self.targets.layout,
+            vk::AccessFlags::SHADER_WRITE,
+            vk::AccessFlags::COLOR_ATTACHMENT_READ
+                | vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                | vk::AccessFlags::SHADER_READ,
+        );
+
+        unsafe {
+            logical_device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags::FRAGMENT_SHADER
+                    | vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[to_target_layout],
+            );
+        }
+
+        Ok(())
+    }
+
+    // Records the effects on the swapchain image of the frame, which is left ready to
+    // present. Must only be called once the previous frame using this image has completed.
+    pub fn record(&mut self, frame: &frame::FrameContext) -> Result<vk::CommandBuffer> {
+        let command_buffer = *frame.per_image(&self.command_buffers)?;
+
+        unsafe {
+            let logical_device = &self.device.logical_device;
+
+            logical_device
+                .reset_command_buffer(command_buffer, vk::CommandBufferResetFlags::empty())
+                .context("failed to reset image effect command buffer")?;
+
+            logical_device
+                .begin_command_buffer(command_buffer, &vk::CommandBufferBeginInfo::default())
+                .context("failed to begin recording image effect command buffer")?;
+        }
+
+        self.record_effects(command_buffer, frame.image_index() as usize)?;
+
+        unsafe {
+            self.device
+                .logical_device
+                .end_command_buffer(command_buffer)
+                .context("failed to end image effect command buffer recording")?;
+        }
+
+        Ok(command_buffer)
+    }
+
+    // The device has to be idle
+    pub fn destroy(&mut self) {
+        for copy in self.copies.iter() {
+            copy.destroy(&self.device);
+        }
+
+        self.device.untrack(self.command_pool);
+        self.device.untrack(self.pipeline);
+        self.device.untrack(self.pipeline_layout);
+        self.device.untrack(self.descriptor_pool);
+        self.device.untrack(self.descriptor_set_layout);
+        self.device.untrack(self.sampler);
+
+        let logical_device = &self.device.logical_device;
+        unsafe {
+            // frees the command buffers and descriptor sets as well
+            logical_device.destroy_command_pool(self.command_pool, None);
+            logical_device.destroy_descriptor_pool(self.descriptor_pool, None);
+
+            logical_device.destroy_pipeline(self.pipeline, None);
+            logical_device.destroy_pipeline_layout(self.pipeline_layout, None);
+            logical_device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+            logical_device.destroy_sampler(self.sampler, None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn push_constants_match_the_shader_block() {
+        // vec4 members start at 16 bytes in the push constant block
+        assert_eq!(::std::mem::size_of::<PushConstants>(), 68);
+
+        let extent = vk::Extent2D {
+            width: 640,
+            height: 480,
+        };
+        let sharpen = PushConstants::new(&ImageEffect::Sharpen { strength: 0.5 }, extent);
+        assert_eq!(sharpen.effect, EFFECT_SHARPEN);
+        assert_eq!(sharpen.strength, 0.5);
+        assert_eq!(sharpen.extent, [640, 480]);
+
+        let grade = ColorGrade::default().with_saturation(0.0);
+        let graded = PushConstants::new(&ImageEffect::ColorGrade(grade), extent);
+        assert_eq!(graded.effect, EFFECT_COLOR_GRADE);
+        assert_eq!(graded.gamma, [1.0, 1.0, 1.0, 0.0]);
+        assert_eq!(graded.saturation, 0.0);
+    }
+
+    #[test]
+    fn color_grades_need_a_positive_gamma() {
+        assert!(ImageEffect::ColorGrade(ColorGrade::default())
+            .validate()
+            .is_ok());
+        assert!(
+            ImageEffect::ColorGrade(ColorGrade::default().with_gamma([1.0, 0.0, 1.0]))
+                .validate()
+                .is_err()
+        );
+        assert!(ImageEffect::Sharpen { strength: -1.0 }.validate().is_ok());
+    }
+}
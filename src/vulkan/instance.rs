@@ -7,40 +7,17 @@ use std::{
     ffi::{CStr, CString},
     os::raw::c_void,
     ptr,
+    sync::RwLock,
 };
 
 use crate::foreign;
 use crate::platforms;
 use crate::vulkan::constants::*;
+use crate::vulkan::debug_messenger;
+use crate::vulkan::portability;
+use crate::vulkan::trace;
 
-use anyhow::{Context, Result};
-
-unsafe extern "system" fn vulkan_debug_utils_callback(
-    message_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
-    message_type: vk::DebugUtilsMessageTypeFlagsEXT,
-    p_callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT,
-    _user_data: *mut c_void,
-) -> vk::Bool32 {
-    let severity = match message_severity {
-        vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE => "[Verbose]",
-        vk::DebugUtilsMessageSeverityFlagsEXT::WARNING => "[Warning]",
-        vk::DebugUtilsMessageSeverityFlagsEXT::ERROR => "[Error]",
-        vk::DebugUtilsMessageSeverityFlagsEXT::INFO => "[Info]",
-        _ => "[Unknown]",
-    };
-
-    let types = match message_type {
-        vk::DebugUtilsMessageTypeFlagsEXT::GENERAL => "[General]",
-        vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE => "[Performance]",
-        vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION => "[Validation]",
-        _ => "[Unknown]",
-    };
-
-    let message = CStr::from_ptr((*p_callback_data).p_message);
-    println!("[Debug]{}{}{:?}", severity, types, message);
-
-    vk::FALSE
-}
+use crate::error::{Context, Result};
 
 // Vulkan Instance
 pub struct VulkanInstance {
@@ -48,6 +25,12 @@ pub struct VulkanInstance {
     pub instance: ash::Instance,
     debug_utils_loader: ash::extensions::ext::DebugUtils,
     debug_messenger: vk::DebugUtilsMessengerEXT,
+    // the validation layers are loaded and the messenger created, see
+    // DebugMessengerConfig::validation
+    validation: bool,
+    // read by the messengers' callback, has to outlive the instance since the messenger
+    // chained to the instance create info reports its destruction too
+    debug_state: Box<debug_messenger::DebugMessengerState>,
 }
 
 impl VulkanInstance {
@@ -60,13 +43,13 @@ impl VulkanInstance {
             .expect("Failed to enumerate Instance Layers Properties!");
 
         if layer_properties.len() <= 0 {
-            eprintln!("No available layers.");
+            tracing::warn!("No available layers.");
             return false;
         } else {
-            println!("Instance Available Layers: ");
+            tracing::debug!("Instance Available Layers: ");
             for layer in layer_properties.iter() {
                 let layer_name = foreign::vk_to_string(&layer.layer_name);
-                println!("\t{}", layer_name);
+                tracing::debug!("\t{}", layer_name);
             }
         }
 
@@ -85,25 +68,13 @@ impl VulkanInstance {
         true
     }
 
-    fn populate_debug_messenger_create_info() -> vk::DebugUtilsMessengerCreateInfoEXT {
-        vk::DebugUtilsMessengerCreateInfoEXT {
-            s_type: vk::StructureType::DEBUG_UTILS_MESSENGER_CREATE_INFO_EXT,
-            p_next: ptr::null(),
-            flags: vk::DebugUtilsMessengerCreateFlagsEXT::empty(),
-            message_severity: vk::DebugUtilsMessageSeverityFlagsEXT::WARNING |
-            // vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE |
-            // vk::DebugUtilsMessageSeverityFlagsEXT::INFO |
-            vk::DebugUtilsMessageSeverityFlagsEXT::ERROR,
-            message_type: vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
-                | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE
-                | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION,
-            pfn_user_callback: Some(vulkan_debug_utils_callback),
-            p_user_data: ptr::null_mut(),
-        }
-    }
-
-    fn create_instance(entry: &ash::Entry) -> Result<ash::Instance> {
-        if ENABLE_VALIDATION && VulkanInstance::check_validation_layer_support(entry) == false {
+    fn create_instance(
+        entry: &ash::Entry,
+        extensions: &[CString],
+        debug_state: &debug_messenger::DebugMessengerState,
+        validation: bool,
+    ) -> Result<ash::Instance> {
+        if validation && VulkanInstance::check_validation_layer_support(entry) == false {
             panic!("Validation layers requested, but not available");
         }
 
@@ -120,12 +91,35 @@ impl VulkanInstance {
             api_version: API_VERSION,
         };
 
-        let debug_utils_create_info = VulkanInstance::populate_debug_messenger_create_info();
+        let debug_utils_create_info = debug_messenger::create_info(debug_state);
 
         // Debug utils extension also requested here
-        let extension_names = platforms::required_extension_names();
+        let mut extension_names = platforms::required_extension_names();
+
+        // needed for the HDR color spaces of the swapchain, optional elsewhere
+        if VulkanInstance::is_extension_supported(entry, vk::ExtSwapchainColorspaceFn::name()) {
+            extension_names.push(vk::ExtSwapchainColorspaceFn::name().as_ptr());
+        }
+
+        // lists portability implementations like MoltenVK, the loader hides them otherwise
+        let portability_extension = CString::new(portability::PORTABILITY_ENUMERATION_EXTENSION)
+            .context("invalid extension name")?;
+        let portability_enumeration =
+            VulkanInstance::is_extension_supported(entry, portability_extension.as_c_str());
+        if portability_enumeration {
+            extension_names.push(portability_extension.as_ptr());
+        }
+
+        for extension in extensions.iter() {
+            if !extension_names
+                .iter()
+                .any(|&name| unsafe { CStr::from_ptr(name) } == extension.as_c_str())
+            {
+                extension_names.push(extension.as_ptr());
+            }
+        }
 
-        println!("enabled layer {:?}", VALIDATION_LAYER);
+        tracing::info!("enabled layer {:?}", VALIDATION_LAYER);
 
         // let enabled_layers = EnabledLayers::query();
 
@@ -140,12 +134,12 @@ impl VulkanInstance {
             .collect();
 
         let layers = EnabledLayers {
-            count: if ENABLE_VALIDATION {
+            count: if validation {
                 enabled_layer_names.len()
             } else {
                 0
             } as u32,
-            names: if ENABLE_VALIDATION {
+            names: if validation {
                 enabled_layer_names.as_ptr()
             } else {
                 &std::ptr::null()
@@ -154,14 +148,18 @@ impl VulkanInstance {
 
         let create_info = vk::InstanceCreateInfo {
             s_type: vk::StructureType::INSTANCE_CREATE_INFO,
-            p_next: if ENABLE_VALIDATION {
+            p_next: if validation {
                 &debug_utils_create_info as *const vk::DebugUtilsMessengerCreateInfoEXT
                     as *const c_void
             } else {
                 std::ptr::null()
             },
 
-            flags: vk::InstanceCreateFlags::empty(),
+            flags: if portability_enumeration {
+                portability::instance_create_flags()
+            } else {
+                vk::InstanceCreateFlags::empty()
+            },
             p_application_info: &app_info,
             pp_enabled_layer_names: layers.names,
             enabled_layer_count: layers.count,
@@ -169,54 +167,137 @@ impl VulkanInstance {
             enabled_extension_count: extension_names.len() as u32,
         };
 
-        unsafe {
+        trace::call("vkCreateInstance", &create_info, || unsafe {
             entry
                 .create_instance(&create_info, None)
                 .context("failed to create instance")
-        }
+        })
+    }
+
+    fn is_extension_supported(entry: &ash::Entry, name: &CStr) -> bool {
+        entry
+            .enumerate_instance_extension_properties()
+            .map(|properties| {
+                properties.iter().any(|extension| {
+                    let extension_name =
+                        unsafe { CStr::from_ptr(extension.extension_name.as_ptr()) };
+                    extension_name == name
+                })
+            })
+            .unwrap_or(false)
     }
 
     fn setup_debug_utils(
         entry: &ash::Entry,
         instance: &ash::Instance,
-    ) -> (ash::extensions::ext::DebugUtils, vk::DebugUtilsMessengerEXT) {
+        debug_state: &debug_messenger::DebugMessengerState,
+        validation: bool,
+    ) -> Result<(ash::extensions::ext::DebugUtils, vk::DebugUtilsMessengerEXT)> {
         let debug_utils_loader = ash::extensions::ext::DebugUtils::new(entry, instance);
 
-        if ENABLE_VALIDATION {
-            (debug_utils_loader, ash::vk::DebugUtilsMessengerEXT::null())
-        } else {
-            let messenger_info = VulkanInstance::populate_debug_messenger_create_info();
+        if !validation {
+            return Ok((debug_utils_loader, vk::DebugUtilsMessengerEXT::null()));
+        }
 
-            let utils_messenger = unsafe {
-                debug_utils_loader
-                    .create_debug_utils_messenger(&messenger_info, None)
-                    .expect("Debug utils callback")
-            };
+        let messenger_info = debug_messenger::create_info(debug_state);
+        let utils_messenger = unsafe {
+            debug_utils_loader
+                .create_debug_utils_messenger(&messenger_info, None)
+                .context("cannot create the debug messenger")?
+        };
 
-            (debug_utils_loader, utils_messenger)
-        }
+        Ok((debug_utils_loader, utils_messenger))
     }
 
     pub fn new() -> Result<VulkanInstance> {
+        VulkanInstance::with_extensions(&[])
+    }
+
+    // With extensions besides the ones the engine needs, eg. what an OpenXR runtime requires
+    pub fn with_extensions(extensions: &[CString]) -> Result<VulkanInstance> {
+        VulkanInstance::with_debug_messenger(extensions, Default::default())
+    }
+
+    // Forwards the validation messages the config selects, see vulkan::debug_messenger
+    pub fn with_debug_messenger(
+        extensions: &[CString],
+        debug_config: debug_messenger::DebugMessengerConfig,
+    ) -> Result<VulkanInstance> {
+        let validation = ENABLE_VALIDATION && debug_config.validation;
+        let debug_state = Box::new(RwLock::new(debug_config));
+
         let entry = ash::Entry::new().context("cannot load ash entry")?;
-        let instance = VulkanInstance::create_instance(&entry)?;
+        let instance =
+            VulkanInstance::create_instance(&entry, extensions, &debug_state, validation)?;
 
         let (debug_utils_loader, debug_messenger) =
-            VulkanInstance::setup_debug_utils(&entry, &instance);
+            match VulkanInstance::setup_debug_utils(&entry, &instance, &debug_state, validation) {
+                Ok(debug_utils) => debug_utils,
+                Err(err) => {
+                    unsafe { instance.destroy_instance(None) };
+                    return Err(err);
+                }
+            };
 
         Ok(VulkanInstance {
             entry,
             instance,
             debug_utils_loader,
             debug_messenger,
+            validation,
+            debug_state,
         })
     }
+
+    // Changes which messages are forwarded from now on. The messenger is created again
+    // with the new severities and types, messages of the instance's own creation and
+    // destruction keep the ones it was created with. Validation cannot be turned on or
+    // off once the instance exists.
+    pub fn set_debug_messenger(
+        &mut self,
+        debug_config: debug_messenger::DebugMessengerConfig,
+    ) -> Result<()> {
+        match self.debug_state.write() {
+            Ok(mut config) => *config = debug_config,
+            Err(poisoned) => *poisoned.into_inner() = debug_config,
+        }
+
+        if !self.validation {
+            return Ok(());
+        }
+
+        let messenger_info = debug_messenger::create_info(&self.debug_state);
+        let debug_messenger = unsafe {
+            self.debug_utils_loader
+                .create_debug_utils_messenger(&messenger_info, None)
+                .context("cannot create the debug messenger")?
+        };
+
+        unsafe {
+            self.debug_utils_loader
+                .destroy_debug_utils_messenger(self.debug_messenger, None);
+        }
+        self.debug_messenger = debug_messenger;
+
+        Ok(())
+    }
+
+    pub fn debug_messenger_config(&self) -> debug_messenger::DebugMessengerConfig {
+        match self.debug_state.read() {
+            Ok(config) => config.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+
+    pub fn debug_utils(&self) -> &ash::extensions::ext::DebugUtils {
+        &self.debug_utils_loader
+    }
 }
 
 impl Drop for VulkanInstance {
     fn drop(&mut self) {
         unsafe {
-            if ENABLE_VALIDATION {
+            if self.validation {
                 self.debug_utils_loader
                     .destroy_debug_utils_messenger(self.debug_messenger, None);
             }
@@ -0,0 +1,181 @@
+use crate::error::{Error, Result};
+
+use super::buffers;
+use super::device;
+use super::frame;
+use super::typed_buffer;
+
+// Binding of the light block in the per frame descriptor set, see shaders/shader.frag
+pub const LIGHTS_BINDING: u32 = 2;
+
+// Size of the light array in the shader, extra lights are rejected
+pub const MAX_LIGHTS: usize = 8;
+
+// Laid out to match the std140 `Light` struct in shaders/shader.frag
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Light {
+    // xyz: direction the light travels in for directional lights, position for point lights
+    // w: 0 for directional, 1 for point lights
+    pub position: [f32; 4],
+    // rgb: color, a: intensity
+    pub color: [f32; 4],
+    // constant, linear and quadratic attenuation followed by the range of a point light
+    pub attenuation: [f32; 4],
+}
+
+impl Light {
+    pub fn directional(direction: [f32; 3], color: [f32; 3], intensity: f32) -> Light {
+        Light {
+            position: [direction[0], direction[1], direction[2], 0.0],
+            color: [color[0], color[1], color[2], intensity],
+            attenuation: [1.0, 0.0, 0.0, 0.0],
+        }
+    }
+
+    pub fn point(position: [f32; 3], color: [f32; 3], intensity: f32, range: f32) -> Light {
+        Light {
+            position: [position[0], position[1], position[2], 1.0],
+            color: [color[0], color[1], color[2], intensity],
+            attenuation: [1.0, 4.5 / range, 75.0 / (range * range), range],
+        }
+    }
+
+    pub fn with_attenuation(mut self, constant: f32, linear: f32, quadratic: f32) -> Light {
+        self.attenuation = [constant, linear, quadratic, self.attenuation[3]];
+        self
+    }
+
+    pub fn is_directional(&self) -> bool {
+        self.position[3] == 0.0
+    }
+}
+
+// Contents of the light uniform buffer
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct LightBlock {
+    // rgb: ambient color, a: shininess used for the specular highlight
+    pub ambient: [f32; 4],
+    pub camera_position: [f32; 4],
+    // x: number of lights in use, padded to a vec4
+    pub light_count: [u32; 4],
+    pub lights: [Light; MAX_LIGHTS],
+}
+
+// Lights of the scene along with the parameters shared by all of them
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lighting {
+    pub ambient: [f32; 3],
+    pub shininess: f32,
+    pub camera_position: [f32; 3],
+    pub lights: Vec<Light>,
+}
+
+impl Lighting {
+    pub fn new(camera_position: [f32; 3]) -> Lighting {
+        Lighting {
+            ambient: [0.1, 0.1, 0.1],
+            shininess: 32.0,
+            camera_position,
+            lights: vec![],
+        }
+    }
+
+    pub fn with_ambient(mut self, ambient: [f32; 3]) -> Lighting {
+        self.ambient = ambient;
+        self
+    }
+
+    pub fn with_shininess(mut self, shininess: f32) -> Lighting {
+        self.shininess = shininess;
+        self
+    }
+
+    pub fn with_light(mut self, light: Light) -> Lighting {
+        self.lights.push(light);
+        self
+    }
+
+    pub fn block(&self) -> Result<LightBlock> {
+        if self.lights.len() > MAX_LIGHTS {
+            return Err(Error::OutOfRange(format!(
+                "{} lights exceed the maximum of {}",
+                self.lights.len(),
+                MAX_LIGHTS
+            )));
+        }
+
+        let mut lights = [Light::directional([0.0, 0.0, -1.0], [0.0; 3], 0.0); MAX_LIGHTS];
+        lights[..self.lights.len()].copy_from_slice(&self.lights);
+
+        let [r, g, b] = self.ambient;
+        let [x, y, z] = self.camera_position;
+
+        Ok(LightBlock {
+            ambient: [r, g, b, self.shininess],
+            camera_position: [x, y, z, 1.0],
+            light_count: [self.lights.len() as u32, 0, 0, 0],
+            lights,
+        })
+    }
+}
+
+impl Default for Lighting {
+    // a single white light shining down, seen from the default camera of the app
+    fn default() -> Lighting {
+        Lighting::new([2.0, 2.0, 2.0]).with_light(Light::directional(
+            [-0.5, -0.5, -1.0],
+            [1.0, 1.0, 1.0],
+            1.0,
+        ))
+    }
+}
+
+// One light uniform buffer per swapchain image, rewritten only when the lighting changes
+pub struct LightBuffers {
+    pub buffers: Vec<typed_buffer::UniformBuffer<LightBlock>>,
+    uploads: buffers::UniformUploads,
+}
+
+impl LightBuffers {
+    pub fn new(device: &device::Device, num_images: usize) -> Result<LightBuffers> {
+        let buffers = (0..num_images)
+            .map(|_| typed_buffer::UniformBuffer::host_visible(device, 1))
+            .collect::<Result<Vec<typed_buffer::UniformBuffer<LightBlock>>>>()?;
+
+        Ok(LightBuffers {
+            buffers,
+            uploads: buffers::UniformUploads::new(buffers::UpdatePolicy::OnDemand, num_images),
+        })
+    }
+
+    pub fn mark_dirty(&mut self) {
+        self.uploads.mark_dirty();
+    }
+
+    pub fn update(
+        &mut self,
+        device: &ash::Device,
+        frame: &frame::FrameContext,
+        lighting: &Lighting,
+    ) -> Result<()> {
+        let image_index = frame.image_index() as usize;
+        if !self.uploads.needs_upload(image_index) {
+            return Ok(());
+        }
+
+        frame
+            .per_image(&self.buffers)?
+            .update(device, &[lighting.block()?])?;
+
+        self.uploads.uploaded(image_index);
+        Ok(())
+    }
+
+    pub fn destroy(&self, device: &device::Device) {
+        for buffer in self.buffers.iter() {
+            buffer.destroy(device);
+        }
+    }
+}
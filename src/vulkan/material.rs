@@ -0,0 +1,1147 @@
+use ash::version::DeviceV1_0;
+use ash::vk;
+
+use crate::error::{Context, Error, Result};
+
+use std::cmp::Ordering;
+use std::path::Path;
+
+use cgmath::{Matrix4, Point3};
+
+use crate::shaderc;
+
+use super::backend;
+use super::bindless;
+use super::descriptor;
+use super::device;
+use super::ibl;
+use super::pbr;
+use super::permutation;
+use super::pipeline;
+use super::preset;
+use super::reflection_probe;
+use super::texture;
+
+// Materials are bound to the second descriptor set, the first one holds the per frame uniforms
+pub const MATERIAL_SET: u32 = 1;
+
+pub const DESCRIPTOR_INDEXING_EXTENSION: device::DeviceExtension = device::DeviceExtension {
+    names: ["VK_EXT_descriptor_indexing"],
+};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TextureSlot {
+    // the albedo of PBR materials
+    Diffuse,
+    Normal,
+    Specular,
+    // metallic in blue and roughness in green, see pbr::PBR_SLOTS
+    MetallicRoughness,
+    // ambient occlusion in red
+    Occlusion,
+    // weights of the terrain layers, see terrain::SPLAT_SLOTS
+    SplatMap,
+    TerrainLayer(u8),
+}
+
+impl TextureSlot {
+    // Only the diffuse map and terrain layers hold colors, the other slots hold data
+    pub fn color_space(&self) -> texture::ColorSpace {
+        match self {
+            TextureSlot::Diffuse | TextureSlot::TerrainLayer(_) => texture::ColorSpace::Srgb,
+            TextureSlot::Normal
+            | TextureSlot::Specular
+            | TextureSlot::MetallicRoughness
+            | TextureSlot::Occlusion
+            | TextureSlot::SplatMap => texture::ColorSpace::Linear,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MaterialLayoutKind {
+    // a single binding holding an array of samplers, indexed by slot in the shader
+    TextureArray,
+    // one binding per slot, used when descriptor indexing is not available
+    PerTexture,
+}
+
+pub struct MaterialLayout {
+    pub layout: vk::DescriptorSetLayout,
+    pub kind: MaterialLayoutKind,
+    pub slots: Vec<TextureSlot>,
+}
+
+impl MaterialLayout {
+    pub fn new(
+        instance: &ash::Instance,
+        device: &device::Device,
+        slots: &[TextureSlot],
+    ) -> Result<MaterialLayout> {
+        let supports_indexing = device::Device::check_device_extension_support(
+            instance,
+            device.physical_device,
+            &DESCRIPTOR_INDEXING_EXTENSION,
+        )?;
+
+        let kind = if supports_indexing {
+            MaterialLayoutKind::TextureArray
+        } else {
+            MaterialLayoutKind::PerTexture
+        };
+
+        MaterialLayout::with_kind(&device.logical_device, slots, kind)
+    }
+
+    pub fn with_kind(
+        device: &ash::Device,
+        slots: &[TextureSlot],
+        kind: MaterialLayoutKind,
+    ) -> Result<MaterialLayout> {
+        let binding = |binding, descriptor_count| vk::DescriptorSetLayoutBinding {
+            binding,
+            descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count,
+            stage_flags: vk::ShaderStageFlags::FRAGMENT,
+            ..Default::default()
+        };
+
+        let bindings = match kind {
+            MaterialLayoutKind::TextureArray => vec![binding(0, slots.len() as u32)],
+            MaterialLayoutKind::PerTexture => (0..slots.len() as u32)
+                .map(|index| binding(index, 1))
+                .collect(),
+        };
+
+        let layout_info = vk::DescriptorSetLayoutCreateInfo {
+            binding_count: bindings.len() as u32,
+            p_bindings: bindings.as_ptr(),
+            ..Default::default()
+        };
+
+        let layout = unsafe {
+            device
+                .create_descriptor_set_layout(&layout_info, None)
+                .context("failed to create material descriptor set layout")
+        }?;
+
+        Ok(MaterialLayout {
+            layout,
+            kind,
+            slots: slots.to_vec(),
+        })
+    }
+
+    // Index of the slot in the sampler array or its binding, depending on the layout kind
+    pub fn slot_index(&self, slot: TextureSlot) -> Option<u32> {
+        self.slots
+            .iter()
+            .position(|&s| s == slot)
+            .map(|index| index as u32)
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        unsafe { device.destroy_descriptor_set_layout(self.layout, None) };
+    }
+}
+
+// Set of textures sampled together by a mesh, eg. diffuse + normal + specular maps
+pub struct Material {
+    pub textures: Vec<texture::Texture>,
+    descriptor_pool: vk::DescriptorPool,
+    pub descriptor_set: vk::DescriptorSet,
+}
+
+impl Material {
+    fn create_descriptor_pool(
+        device: &ash::Device,
+        num_textures: u32,
+    ) -> Result<vk::DescriptorPool> {
+        let pool_size = vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: num_textures,
+        };
+
+        let pool_info = vk::DescriptorPoolCreateInfo {
+            pool_size_count: 1,
+            p_pool_sizes: &pool_size,
+            max_sets: 1,
+            ..Default::default()
+        };
+
+        unsafe {
+            device
+                .create_descriptor_pool(&pool_info, None)
+                .context("failed to create material descriptor pool")
+        }
+    }
+
+    // Every slot of the layout needs a texture
+    pub fn new(
+        device: &device::Device,
+        command_pool: vk::CommandPool,
+        submit_queue: vk::Queue,
+        layout: &MaterialLayout,
+        textures: &[(TextureSlot, &Path)],
+    ) -> Result<Material> {
+        let textures = layout
+            .slots
+            .iter()
+            .map(|slot| {
+                textures
+                    .iter()
+                    .find(|(s, _)| s == slot)
+                    .ok_or_else(|| Error::msg(format!("material is missing a {:?} texture", slot)))
+                    .and_then(|(_, path)| {
+                        texture::Texture::with_color_space(
+                            device,
+                            command_pool,
+                            submit_queue,
+                            path,
+                            slot.color_space(),
+                        )
+                    })
+            })
+            .collect::<Result<Vec<texture::Texture>>>()?;
+
+        let logical_device = &device.logical_device;
+        let descriptor_pool =
+            Material::create_descriptor_pool(logical_device, textures.len() as u32)?;
+
+        let layouts = [layout.layout];
+        let alloc_info = vk::DescriptorSetAllocateInfo {
+            descriptor_pool,
+            descriptor_set_count: 1,
+            p_set_layouts: layouts.as_ptr(),
+            ..Default::default()
+        };
+
+        let descriptor_set = unsafe {
+            logical_device
+                .allocate_descriptor_sets(&alloc_info)
+                .context("failed to allocate material descriptor set")
+        }?[0];
+
+        let image_infos = textures
+            .iter()
+            .map(|texture| vk::DescriptorImageInfo {
+                sampler: texture.sampler,
+                image_view: texture.image_data.image_view,
+                image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            })
+            .collect::<Vec<vk::DescriptorImageInfo>>();
+
+        let descriptor_writes = match layout.kind {
+            MaterialLayoutKind::TextureArray => vec![vk::WriteDescriptorSet {
+                dst_set: descriptor_set,
+                dst_binding: 0,
+                dst_array_element: 0,
+                descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: image_infos.len() as u32,
+                p_image_info: image_infos.as_ptr(),
+                ..Default::default()
+            }],
+            MaterialLayoutKind::PerTexture => image_infos
+                .iter()
+                .enumerate()
+                .map(|(index, image_info)| vk::WriteDescriptorSet {
+                    dst_set: descriptor_set,
+                    dst_binding: index as u32,
+                    dst_array_element: 0,
+                    descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                    descriptor_count: 1,
+                    p_image_info: image_info,
+                    ..Default::default()
+                })
+                .collect(),
+        };
+
+        unsafe { logical_device.update_descriptor_sets(&descriptor_writes, &[]) };
+
+        Ok(Material {
+            textures,
+            descriptor_pool,
+            descriptor_set,
+        })
+    }
+
+    pub fn bind<C: backend::CommandOps>(
+        &self,
+        commands: &C,
+        command_buffer: vk::CommandBuffer,
+        pipeline_layout: vk::PipelineLayout,
+    ) {
+        commands.bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            pipeline_layout,
+            MATERIAL_SET,
+            &[self.descriptor_set],
+            &[],
+        );
+    }
+
+    pub fn destroy(&self, device: &device::Device) {
+        // frees the descriptor set as well
+        unsafe {
+            device
+                .logical_device
+                .destroy_descriptor_pool(self.descriptor_pool, None)
+        };
+
+        for texture in self.textures.iter() {
+            texture.destroy(device);
+        }
+    }
+}
+
+// What a material is drawn with: the shader pair, its blend and cull state and the
+// texture slots it samples. Materials with equal descriptions share one pipeline.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MaterialDescription {
+    pub shaders: shaderc::ShaderSource,
+    pub state: preset::FixedFunctionState,
+    pub slots: Vec<TextureSlot>,
+    // the pipeline takes pbr::PbrParameters as push constants, see MaterialLibrary::add_pbr
+    pub pbr_parameters: bool,
+}
+
+impl MaterialDescription {
+    pub fn new(shaders: shaderc::ShaderSource, slots: &[TextureSlot]) -> MaterialDescription {
+        MaterialDescription {
+            shaders,
+            state: preset::FixedFunctionState::from_preset(preset::Preset::Opaque3d),
+            slots: slots.to_vec(),
+            pbr_parameters: false,
+        }
+    }
+
+    // Metallic-roughness material sampling pbr::PBR_SLOTS, eg. with pbr::reference_shaders
+    pub fn pbr(shaders: shaderc::ShaderSource) -> MaterialDescription {
+        MaterialDescription {
+            pbr_parameters: true,
+            ..MaterialDescription::new(shaders, &pbr::PBR_SLOTS)
+        }
+    }
+
+    // Alpha blended, tested against depth without writing it. Drawn after the opaque
+    // materials, back to front.
+    pub fn transparent(
+        shaders: shaderc::ShaderSource,
+        slots: &[TextureSlot],
+    ) -> MaterialDescription {
+        MaterialDescription::new(shaders, slots).with_state(
+            preset::FixedFunctionState::from_preset(preset::Preset::AlphaBlended),
+        )
+    }
+
+    pub fn is_transparent(&self) -> bool {
+        self.state.blend_mode != preset::BlendMode::Opaque
+    }
+
+    pub fn with_state(mut self, state: preset::FixedFunctionState) -> MaterialDescription {
+        self.state = state;
+        self
+    }
+
+    pub fn with_blend_mode(mut self, blend_mode: preset::BlendMode) -> MaterialDescription {
+        self.state = self.state.with_blend_mode(blend_mode);
+        self
+    }
+
+    pub fn with_cull_mode(mut self, cull_mode: vk::CullModeFlags) -> MaterialDescription {
+        self.state = self.state.with_cull_mode(cull_mode);
+        self
+    }
+}
+
+pub type MaterialId = usize;
+
+// An indexed draw of part of the scene's vertex and index buffers
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Draw {
+    pub material: MaterialId,
+    pub index_count: u32,
+    pub first_index: u32,
+    pub vertex_offset: i32,
+    // instances drawn with gl_InstanceIndex counting from 0, eg. for shaders/benchmark.vert
+    pub instance_count: u32,
+    // world space, transparent draws are sorted by its distance to the camera
+    pub center: Point3<f32>,
+}
+
+// Draws in recording order, the opaque ones grouped by pipeline and material followed
+// by the transparent ones from back to front
+#[derive(Debug, Clone, PartialEq)]
+pub struct SortedDraws {
+    draws: Vec<Draw>,
+    first_transparent: usize,
+}
+
+impl SortedDraws {
+    pub fn opaque(&self) -> &[Draw] {
+        &self.draws[..self.first_transparent]
+    }
+
+    pub fn transparent(&self) -> &[Draw] {
+        &self.draws[self.first_transparent..]
+    }
+}
+
+// How the textures of a material reach its shaders
+enum MaterialTextures {
+    // a descriptor set of its own, bound before the material's draws
+    Set(Material),
+    // indices into the bindless texture array, pushed before the material's draws
+    Bindless(bindless::MaterialIndices),
+}
+
+// Pipeline and material set last bound while recording
+#[derive(Default)]
+struct Bound {
+    variant: Option<usize>,
+    material: Option<MaterialId>,
+    environment: Option<vk::DescriptorSet>,
+}
+
+struct PipelineVariant {
+    description: MaterialDescription,
+    detail: pipeline::PipelineDetail,
+}
+
+// Owns the materials of a scene together with the pipelines and set layouts they are
+// drawn with. A pipeline variant is created the first time a description is added and
+// shared by every later material with the same description, layouts are shared by
+// materials sampling the same slots. With bindless textures every material samples the
+// one texture array instead, see with_bindless.
+pub struct MaterialLibrary {
+    target: pipeline::ColorTarget,
+    vertex_input: permutation::VertexInput,
+    scene_bindings: Vec<descriptor::Binding>,
+    pipeline_cache: vk::PipelineCache,
+
+    layouts: Vec<MaterialLayout>,
+    variants: Vec<PipelineVariant>,
+    // the textures of the material and the index of its pipeline variant
+    materials: Vec<(MaterialTextures, usize)>,
+    // by material id like materials, set for the materials added with add_pbr
+    parameters: Vec<Option<pbr::PbrParameters>>,
+    bindless: Option<bindless::BindlessTextures>,
+    // bound as ibl::ENVIRONMENT_SET for the pbr materials
+    environment: Option<ibl::EnvironmentLighting>,
+    // sets of the environment's layout sampling a probe's capture, see set_probes
+    probes: Vec<(reflection_probe::ProbeRegion, vk::DescriptorSet)>,
+}
+
+impl MaterialLibrary {
+    pub fn new(
+        target: pipeline::ColorTarget,
+        vertex_data: impl pipeline::VertexData,
+        scene_bindings: &[descriptor::Binding],
+        pipeline_cache: vk::PipelineCache,
+    ) -> MaterialLibrary {
+        MaterialLibrary {
+            target,
+            vertex_input: permutation::VertexInput::capture(vertex_data),
+            scene_bindings: scene_bindings.to_vec(),
+            pipeline_cache,
+            layouts: vec![],
+            variants: vec![],
+            materials: vec![],
+            parameters: vec![],
+            bindless: None,
+            environment: None,
+            probes: vec![],
+        }
+    }
+
+    // Draws the materials with their textures in the array, selected with push constants.
+    // Has to be set before the first material is added.
+    pub fn with_bindless(mut self, textures: bindless::BindlessTextures) -> MaterialLibrary {
+        self.bindless = Some(textures);
+        self
+    }
+
+    pub fn is_bindless(&self) -> bool {
+        self.bindless.is_some()
+    }
+
+    // Lights the pbr materials with the environment, their shaders have to declare its
+    // set, eg. ibl::environment_shaders. Has to be set before the first material is added.
+    pub fn with_environment(mut self, environment: ibl::EnvironmentLighting) -> MaterialLibrary {
+        self.environment = Some(environment);
+        self
+    }
+
+    pub fn has_environment(&self) -> bool {
+        self.environment.is_some()
+    }
+
+    // The pbr materials drawn within the region of a probe sample its capture instead of
+    // the environment's prefiltered cube, see reflection_probe::ReflectionProbe::bind_environment.
+    // Commands recorded earlier keep the sets they were recorded with.
+    pub fn set_probes(&mut self, probes: Vec<(reflection_probe::ProbeRegion, vk::DescriptorSet)>) {
+        self.probes = probes;
+    }
+
+    // The set the pbr materials at the point are lit with, None without an environment
+    fn environment_set(&self, point: Point3<f32>) -> Option<vk::DescriptorSet> {
+        let environment = self.environment.as_ref()?;
+        let regions = self
+            .probes
+            .iter()
+            .map(|(region, _)| *region)
+            .collect::<Vec<_>>();
+
+        Some(
+            reflection_probe::nearest_region(&regions, point)
+                .map(|index| self.probes[index].1)
+                .unwrap_or(environment.descriptor_set),
+        )
+    }
+
+    fn layout_for(
+        &mut self,
+        instance: &ash::Instance,
+        device: &device::Device,
+        slots: &[TextureSlot],
+    ) -> Result<usize> {
+        if let Some(index) = self.layouts.iter().position(|layout| layout.slots == slots) {
+            return Ok(index);
+        }
+
+        self.layouts
+            .push(MaterialLayout::new(instance, device, slots)?);
+        Ok(self.layouts.len() - 1)
+    }
+
+    fn variant_for(
+        &mut self,
+        instance: &ash::Instance,
+        device: &device::Device,
+        description: &MaterialDescription,
+        layout: Option<usize>,
+    ) -> Result<usize> {
+        if let Some(index) = self
+            .variants
+            .iter()
+            .position(|variant| &variant.description == description)
+        {
+            return Ok(index);
+        }
+
+        // bindless variants share the texture array and the push constant range, so their
+        // layouts stay compatible for the material set as well
+        let (set_layout, mut push_constant_ranges) = match (self.bindless.as_ref(), layout) {
+            (Some(textures), _) => (
+                textures.layout,
+                vec![bindless::MaterialIndices::push_constant_range()],
+            ),
+            (None, Some(layout)) => (self.layouts[layout].layout, vec![]),
+            (None, None) => return Err(Error::msg("material variant needs a set layout")),
+        };
+        let mut set_layouts = vec![set_layout];
+        if description.pbr_parameters {
+            push_constant_ranges.push(pbr::PbrParameters::push_constant_range());
+            set_layouts.extend(
+                self.environment
+                    .as_ref()
+                    .map(|environment| environment.layout),
+            );
+        }
+
+        tracing::debug!("creating material pipeline variant {}", self.variants.len());
+        let detail = pipeline::PipelineDetail::create_graphics_pipeline_with_push_constants(
+            instance,
+            device,
+            self.target,
+            description.shaders.clone(),
+            self.vertex_input.clone(),
+            &description.state,
+            self.pipeline_cache,
+            &self.scene_bindings,
+            &set_layouts,
+            &push_constant_ranges,
+        )?;
+        detail.set_name(device, &format!("material variant {}", self.variants.len()));
+
+        self.variants.push(PipelineVariant {
+            description: description.clone(),
+            detail,
+        });
+        Ok(self.variants.len() - 1)
+    }
+
+    // Every slot of the description needs a texture
+    pub fn add(
+        &mut self,
+        instance: &ash::Instance,
+        device: &device::Device,
+        command_pool: vk::CommandPool,
+        submit_queue: vk::Queue,
+        description: &MaterialDescription,
+        textures: &[(TextureSlot, &Path)],
+    ) -> Result<MaterialId> {
+        if self.bindless.is_some() {
+            return self.add_bindless(
+                instance,
+                device,
+                command_pool,
+                submit_queue,
+                description,
+                textures,
+            );
+        }
+
+        let layout = self.layout_for(instance, device, &description.slots)?;
+        let variant = self.variant_for(instance, device, description, Some(layout))?;
+
+        let material = Material::new(
+            device,
+            command_pool,
+            submit_queue,
+            &self.layouts[layout],
+            textures,
+        )?;
+
+        self.materials
+            .push((MaterialTextures::Set(material), variant));
+        self.parameters.push(None);
+        Ok(self.materials.len() - 1)
+    }
+
+    // The textures are appended to the array, their indices follow the description's slots
+    fn add_bindless(
+        &mut self,
+        instance: &ash::Instance,
+        device: &device::Device,
+        command_pool: vk::CommandPool,
+        submit_queue: vk::Queue,
+        description: &MaterialDescription,
+        textures: &[(TextureSlot, &Path)],
+    ) -> Result<MaterialId> {
+        if description.slots.len() > bindless::MAX_MATERIAL_SLOTS {
+            return Err(Error::OutOfRange(format!(
+                "bindless materials sample up to {} slots, the description has {}",
+                bindless::MAX_MATERIAL_SLOTS,
+                description.slots.len()
+            )));
+        }
+
+        let paths = description
+            .slots
+            .iter()
+            .map(|slot| {
+                textures
+                    .iter()
+                    .find(|(s, _)| s == slot)
+                    .map(|&(_, path)| (*slot, path))
+                    .ok_or_else(|| Error::msg(format!("material is missing a {:?} texture", slot)))
+            })
+            .collect::<Result<Vec<(TextureSlot, &Path)>>>()?;
+
+        let variant = self.variant_for(instance, device, description, None)?;
+
+        let array = self
+            .bindless
+            .as_mut()
+            .ok_or_else(|| Error::msg("the material library has no bindless textures"))?;
+        let indices = paths
+            .into_iter()
+            .map(|(slot, path)| {
+                array.load(device, command_pool, submit_queue, path, slot.color_space())
+            })
+            .collect::<Result<Vec<u32>>>()?;
+
+        self.materials.push((
+            MaterialTextures::Bindless(bindless::MaterialIndices::new(&indices)?),
+            variant,
+        ));
+        self.parameters.push(None);
+        Ok(self.materials.len() - 1)
+    }
+
+    // Like add, the parameters are pushed before the draws of the material
+    pub fn add_pbr(
+        &mut self,
+        instance: &ash::Instance,
+        device: &device::Device,
+        command_pool: vk::CommandPool,
+        submit_queue: vk::Queue,
+        description: &MaterialDescription,
+        textures: &[(TextureSlot, &Path)],
+        parameters: pbr::PbrParameters,
+    ) -> Result<MaterialId> {
+        if !description.pbr_parameters {
+            return Err(Error::Unsupported(
+                "the material description takes no PBR parameters, see MaterialDescription::pbr"
+                    .to_string(),
+            ));
+        }
+
+        let material = self.add(
+            instance,
+            device,
+            command_pool,
+            submit_queue,
+            description,
+            textures,
+        )?;
+        self.parameters[material] = Some(parameters);
+        Ok(material)
+    }
+
+    pub fn len(&self) -> usize {
+        self.materials.len()
+    }
+
+    pub fn parameters(&self, material: MaterialId) -> Option<pbr::PbrParameters> {
+        self.parameters
+            .get(material)
+            .and_then(|parameters| *parameters)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.materials.is_empty()
+    }
+
+    pub fn variant_count(&self) -> usize {
+        self.variants.len()
+    }
+
+    fn entry(&self, material: MaterialId) -> Result<&(MaterialTextures, usize)> {
+        self.materials.get(material).ok_or_else(|| {
+            Error::OutOfRange(format!(
+                "no material {}, the library holds {}",
+                material,
+                self.materials.len()
+            ))
+        })
+    }
+
+    // Materials drawn with bindless textures have no set of their own, see material_indices
+    pub fn material(&self, material: MaterialId) -> Result<&Material> {
+        match self.entry(material)? {
+            (MaterialTextures::Set(material), _) => Ok(material),
+            (MaterialTextures::Bindless(_), _) => Err(Error::Unsupported(format!(
+                "material {} samples the bindless texture array",
+                material
+            ))),
+        }
+    }
+
+    pub fn material_indices(&self, material: MaterialId) -> Result<bindless::MaterialIndices> {
+        match self.entry(material)? {
+            (MaterialTextures::Bindless(indices), _) => Ok(*indices),
+            (MaterialTextures::Set(_), _) => Err(Error::Unsupported(format!(
+                "material {} has a descriptor set of its own",
+                material
+            ))),
+        }
+    }
+
+    pub fn pipeline(&self, material: MaterialId) -> Result<&pipeline::PipelineDetail> {
+        self.entry(material)
+            .map(|&(_, variant)| &self.variants[variant].detail)
+    }
+
+    // Unknown materials sort last, recording them fails
+    fn variant_of(&self, material: MaterialId) -> usize {
+        self.materials
+            .get(material)
+            .map(|&(_, variant)| variant)
+            .unwrap_or(usize::max_value())
+    }
+
+    pub fn is_transparent(&self, material: MaterialId) -> bool {
+        self.materials
+            .get(material)
+            .map(|&(_, variant)| self.variants[variant].description.is_transparent())
+            .unwrap_or(false)
+    }
+
+    // Orders the draws for recording, the view is the camera's view matrix
+    pub fn sort_draws(&self, draws: Vec<Draw>, view: Matrix4<f32>) -> SortedDraws {
+        sort_draws(
+            draws,
+            |material| self.variant_of(material),
+            |material| self.is_transparent(material),
+            view,
+        )
+    }
+
+    // Switches every variant that has the polygon mode, like PipelineDetail::set_polygon_mode
+    pub fn set_polygon_mode(&mut self, polygon_mode: vk::PolygonMode) -> Result<()> {
+        if let Some(variant) = self
+            .variants
+            .iter()
+            .find(|variant| !variant.detail.supports_polygon_mode(polygon_mode))
+        {
+            return Err(Error::Unsupported(format!(
+                "material pipeline {:?} has no {:?} variant",
+                variant.description.shaders.fragment_shader_file, polygon_mode
+            )));
+        }
+
+        for variant in self.variants.iter_mut() {
+            variant.detail.set_polygon_mode(polygon_mode)?;
+        }
+
+        Ok(())
+    }
+
+    // Records the draws inside a begun scene render pass with the vertex and index
+    // buffers bound, the opaque pass first so the transparent pass blends over its depth
+    // tested result. The scene set is bound once, the layouts of all the variants are
+    // created from the same bindings and so are compatible for set 0. So is the bindless
+    // texture array, which is bound along with it.
+    pub fn record_draws<C: backend::CommandOps>(
+        &self,
+        commands: &C,
+        command_buffer: vk::CommandBuffer,
+        draws: &SortedDraws,
+        scene_set: vk::DescriptorSet,
+        dynamic_offsets: &[u32],
+    ) -> Result<()> {
+        let mut bound = Bound::default();
+
+        self.record_pass(
+            commands,
+            command_buffer,
+            draws.opaque(),
+            scene_set,
+            dynamic_offsets,
+            &mut bound,
+        )?;
+        self.record_pass(
+            commands,
+            command_buffer,
+            draws.transparent(),
+            scene_set,
+            dynamic_offsets,
+            &mut bound,
+        )
+    }
+
+    // Pipelines and material sets are only bound when they change
+    fn record_pass<C: backend::CommandOps>(
+        &self,
+        commands: &C,
+        command_buffer: vk::CommandBuffer,
+        draws: &[Draw],
+        scene_set: vk::DescriptorSet,
+        dynamic_offsets: &[u32],
+        bound: &mut Bound,
+    ) -> Result<()> {
+        for draw in draws.iter() {
+            let (material, variant) = self.entry(draw.material)?;
+            let detail = &self.variants[*variant].detail;
+            let variant_changed = bound.variant != Some(*variant);
+
+            if variant_changed {
+                commands.bind_pipeline(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    detail.current_pipeline(),
+                );
+
+                if bound.variant.is_none() {
+                    commands.bind_descriptor_sets(
+                        command_buffer,
+                        vk::PipelineBindPoint::GRAPHICS,
+                        detail.layout,
+                        0,
+                        &[scene_set],
+                        dynamic_offsets,
+                    );
+
+                    if let Some(textures) = self.bindless.as_ref() {
+                        textures.bind(commands, command_buffer, detail.layout);
+                    }
+                }
+                bound.variant = Some(*variant);
+            }
+
+            let environment_set = if self.variants[*variant].description.pbr_parameters {
+                self.environment_set(draw.center)
+            } else {
+                None
+            };
+            // the material sets of earlier variants may have had other layouts, which
+            // disturbs the environment set after them
+            if let Some(set) = environment_set {
+                if variant_changed || bound.environment != Some(set) {
+                    commands.bind_descriptor_sets(
+                        command_buffer,
+                        vk::PipelineBindPoint::GRAPHICS,
+                        detail.layout,
+                        ibl::ENVIRONMENT_SET,
+                        &[set],
+                        &[],
+                    );
+                    bound.environment = Some(set);
+                }
+            }
+
+            if bound.material != Some(draw.material) {
+                match material {
+                    MaterialTextures::Set(material) => {
+                        material.bind(commands, command_buffer, detail.layout)
+                    }
+                    MaterialTextures::Bindless(indices) => {
+                        indices.push(commands, command_buffer, detail.layout)
+                    }
+                }
+                if let Some(parameters) = self.parameters(draw.material) {
+                    parameters.push(commands, command_buffer, detail.layout);
+                }
+                bound.material = Some(draw.material);
+            }
+
+            commands.draw_indexed(
+                command_buffer,
+                draw.index_count,
+                draw.instance_count,
+                draw.first_index,
+                draw.vertex_offset,
+                0,
+            );
+        }
+
+        Ok(())
+    }
+
+    pub fn destroy(&self, device: &device::Device) {
+        for (material, _) in self.materials.iter() {
+            if let MaterialTextures::Set(material) = material {
+                material.destroy(device);
+            }
+        }
+
+        if let Some(textures) = self.bindless.as_ref() {
+            textures.destroy(device);
+        }
+
+        if let Some(environment) = self.environment.as_ref() {
+            environment.destroy(device);
+        }
+
+        for variant in self.variants.iter() {
+            variant.detail.destroy(device);
+        }
+
+        for layout in self.layouts.iter() {
+            layout.destroy(&device.logical_device);
+        }
+    }
+}
+
+// Sorts by pipeline variant then by material, keeping the submitted order otherwise
+pub fn group_draws(draws: &mut [Draw], variant_of: impl Fn(MaterialId) -> usize) {
+    draws.sort_by_key(|draw| (variant_of(draw.material), draw.material));
+}
+
+// Depth of the point along the camera's view direction, the camera looks down -z
+fn view_depth(view: Matrix4<f32>, point: Point3<f32>) -> f32 {
+    -(view * point.to_homogeneous()).z
+}
+
+// Opaque draws first, grouped to minimize binds, then the transparent ones from the
+// farthest to the nearest so each blends over what is behind it
+pub fn sort_draws(
+    mut draws: Vec<Draw>,
+    variant_of: impl Fn(MaterialId) -> usize,
+    is_transparent: impl Fn(MaterialId) -> bool,
+    view: Matrix4<f32>,
+) -> SortedDraws {
+    let mut transparent = draws
+        .iter()
+        .filter(|draw| is_transparent(draw.material))
+        .cloned()
+        .collect::<Vec<Draw>>();
+    draws.retain(|draw| !is_transparent(draw.material));
+
+    group_draws(&mut draws, variant_of);
+    transparent.sort_by(|a, b| {
+        view_depth(view, b.center)
+            .partial_cmp(&view_depth(view, a.center))
+            .unwrap_or(Ordering::Equal)
+    });
+
+    let first_transparent = draws.len();
+    draws.extend(transparent);
+
+    SortedDraws {
+        draws,
+        first_transparent,
+    }
+}
+
+// Number of pipeline binds recording the draws in their current order takes
+pub fn pipeline_binds(draws: &[Draw], variant_of: impl Fn(MaterialId) -> usize) -> usize {
+    let mut binds = 0;
+    let mut bound = None;
+
+    for draw in draws.iter() {
+        let variant = variant_of(draw.material);
+        if bound != Some(variant) {
+            binds += 1;
+            bound = Some(variant);
+        }
+    }
+
+    binds
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn draw(material: MaterialId, first_index: u32) -> Draw {
+        Draw {
+            material,
+            index_count: 3,
+            first_index,
+            vertex_offset: 0,
+            instance_count: 1,
+            center: Point3::new(0.0, 0.0, 0.0),
+        }
+    }
+
+    #[test]
+    fn transparent_draws_follow_opaque_ones_back_to_front() {
+        use cgmath::Vector3;
+
+        // material 1 is transparent, the camera sits at z = 5 looking down -z
+        let view = Matrix4::from_translation(Vector3::new(0.0, 0.0, -5.0));
+        let at = |material, first_index, z| Draw {
+            center: Point3::new(0.0, 0.0, z),
+            ..draw(material, first_index)
+        };
+        let draws = vec![at(1, 0, 2.0), at(0, 3, 0.0), at(1, 6, -3.0), at(1, 9, 4.0)];
+
+        let sorted = sort_draws(draws, |material| material, |material| material == 1, view);
+
+        assert_eq!(sorted.opaque().len(), 1);
+        let order = sorted
+            .transparent()
+            .iter()
+            .map(|draw| draw.first_index)
+            .collect::<Vec<u32>>();
+        assert_eq!(order, vec![6, 0, 9]);
+    }
+
+    #[test]
+    fn draws_are_grouped_by_pipeline_then_material() {
+        // materials 0 and 2 share a pipeline variant
+        let variant_of = |material: MaterialId| [0, 1, 0][material];
+        let mut draws = vec![draw(1, 0), draw(2, 3), draw(0, 6), draw(1, 9), draw(0, 12)];
+
+        assert_eq!(pipeline_binds(&draws, variant_of), 5);
+        group_draws(&mut draws, variant_of);
+
+        let order = draws
+            .iter()
+            .map(|draw| (draw.material, draw.first_index))
+            .collect::<Vec<(MaterialId, u32)>>();
+        assert_eq!(order, vec![(0, 6), (0, 12), (2, 3), (1, 0), (1, 9)]);
+        assert_eq!(pipeline_binds(&draws, variant_of), 2);
+    }
+
+    #[test]
+    fn pipelines_are_bound_when_the_variant_changes() {
+        use crate::vulkan::headless::{Call, HeadlessDevice};
+        use crate::vulkan::postprocess::NoVertices;
+
+        let device = HeadlessDevice::new(1);
+        let shaders = shaderc::ShaderSource {
+            vertex_shader_file: "shaders/shader.vert".to_string(),
+            fragment_shader_file: "shaders/shader.frag".to_string(),
+        };
+
+        let mut library = MaterialLibrary::new(
+            pipeline::ColorTarget::swapchain(vk::Format::B8G8R8A8_SRGB),
+            NoVertices,
+            &[],
+            vk::PipelineCache::null(),
+        );
+        let pipelines: Vec<vk::Pipeline> = (0..2).map(|_| device.create_handle()).collect();
+        for &pipeline in pipelines.iter() {
+            library.variants.push(PipelineVariant {
+                description: MaterialDescription::new(shaders.clone(), &[TextureSlot::Diffuse]),
+                detail: pipeline::PipelineDetail::from_handles(
+                    shaders.clone(),
+                    NoVertices,
+                    pipeline,
+                    device.create_handle(),
+                ),
+            });
+        }
+        // materials 0 and 1 share the first variant
+        for (material, &variant) in [0, 0, 1].iter().enumerate() {
+            let indices = bindless::MaterialIndices::new(&[material as u32]).unwrap();
+            library
+                .materials
+                .push((MaterialTextures::Bindless(indices), variant));
+            library.parameters.push(None);
+        }
+
+        let draws = library.sort_draws(
+            vec![draw(2, 0), draw(0, 3), draw(1, 6), draw(0, 9)],
+            Matrix4::from_scale(1.0),
+        );
+        let scene_set = device.create_handle();
+        library
+            .record_draws(&device, vk::CommandBuffer::null(), &draws, scene_set, &[])
+            .unwrap();
+
+        let binds = device
+            .calls()
+            .into_iter()
+            .filter(|call| match call {
+                Call::BindPipeline(_) | Call::BindDescriptorSets { .. } => true,
+                _ => false,
+            })
+            .collect::<Vec<Call>>();
+        assert_eq!(
+            binds,
+            vec![
+                Call::BindPipeline(pipelines[0]),
+                Call::BindDescriptorSets {
+                    first_set: 0,
+                    descriptor_sets: vec![scene_set],
+                },
+                Call::BindPipeline(pipelines[1]),
+            ]
+        );
+
+        let draws = device
+            .calls()
+            .into_iter()
+            .filter_map(|call| match call {
+                Call::DrawIndexed { first_index, .. } => Some(first_index),
+                _ => None,
+            })
+            .collect::<Vec<u32>>();
+        assert_eq!(draws, vec![3, 9, 6, 0]);
+    }
+
+    #[test]
+    fn descriptions_differing_in_state_are_separate_variants() {
+        let shaders = shaderc::ShaderSource {
+            vertex_shader_file: "shaders/shader.vert".to_string(),
+            fragment_shader_file: "shaders/shader.frag".to_string(),
+        };
+        let opaque = MaterialDescription::new(shaders, &[TextureSlot::Diffuse]);
+
+        assert_eq!(opaque, opaque.clone());
+        assert_ne!(
+            opaque,
+            opaque.clone().with_blend_mode(preset::BlendMode::Alpha)
+        );
+        assert_ne!(
+            opaque,
+            opaque.clone().with_cull_mode(vk::CullModeFlags::NONE)
+        );
+    }
+}
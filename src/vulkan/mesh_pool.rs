@@ -0,0 +1,302 @@
+use ash::vk;
+
+use cgmath::Point3;
+
+use crate::error::{Error, Result};
+
+use super::buffers;
+use super::device;
+use super::material;
+use super::typed_buffer;
+use super::upload;
+
+use std::ops::Range;
+
+// Default capacity of the scene's pool, grown to fit the scene mesh if it is larger
+pub const DEFAULT_VERTEX_CAPACITY: u32 = 1 << 18;
+pub const DEFAULT_INDEX_CAPACITY: u32 = 1 << 20;
+
+// Hands out ranges of elements first fit. Freed ranges are merged with their free
+// neighbours, so freeing everything gives back the whole capacity in one range.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RangeAllocator {
+    capacity: u32,
+    // sorted by start and never adjacent to each other
+    free: Vec<Range<u32>>,
+}
+
+impl RangeAllocator {
+    pub fn new(capacity: u32) -> RangeAllocator {
+        RangeAllocator {
+            capacity,
+            free: if capacity > 0 {
+                vec![0..capacity]
+            } else {
+                vec![]
+            },
+        }
+    }
+
+    // None when no free range is large enough
+    pub fn allocate(&mut self, count: u32) -> Option<Range<u32>> {
+        let index = self
+            .free
+            .iter()
+            .position(|range| range.end - range.start >= count)?;
+
+        let start = self.free[index].start;
+        self.free[index].start += count;
+        if self.free[index].start == self.free[index].end {
+            self.free.remove(index);
+        }
+
+        Some(start..start + count)
+    }
+
+    pub fn free(&mut self, range: Range<u32>) {
+        if range.start >= range.end {
+            return;
+        }
+
+        let index = self
+            .free
+            .iter()
+            .position(|free| free.start > range.start)
+            .unwrap_or_else(|| self.free.len());
+        self.free.insert(index, range);
+
+        // merge with the following range, then with the preceding one
+        if index + 1 < self.free.len() && self.free[index].end == self.free[index + 1].start {
+            self.free[index].end = self.free.remove(index + 1).end;
+        }
+        if index > 0 && self.free[index - 1].end == self.free[index].start {
+            self.free[index - 1].end = self.free.remove(index).end;
+        }
+    }
+
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+
+    pub fn free_count(&self) -> u32 {
+        self.free.iter().map(|range| range.end - range.start).sum()
+    }
+}
+
+// Where a mesh ended up in the pool. Its indices are relative to its first vertex,
+// which is passed as the vertex offset of its draws.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct MeshAllocation {
+    pub first_vertex: u32,
+    pub vertex_count: u32,
+    pub first_index: u32,
+    pub index_count: u32,
+}
+
+impl MeshAllocation {
+    pub fn draw(&self, material: material::MaterialId) -> material::Draw {
+        material::Draw {
+            material,
+            index_count: self.index_count,
+            first_index: self.first_index,
+            vertex_offset: self.first_vertex as i32,
+            instance_count: 1,
+            center: Point3::new(0.0, 0.0, 0.0),
+        }
+    }
+
+    fn vertices(&self) -> Range<u32> {
+        self.first_vertex..self.first_vertex + self.vertex_count
+    }
+
+    fn indices(&self) -> Range<u32> {
+        self.first_index..self.first_index + self.index_count
+    }
+}
+
+// One device local vertex buffer and one index buffer that every mesh is suballocated
+// from, so all meshes are drawn with the same buffers bound and only their offsets
+// differ. All vertices have the same type and size.
+pub struct MeshPool {
+    pub vertex_buffer: buffers::BufferInfo,
+    pub index_buffer: typed_buffer::IndexBuffer<u32>,
+    vertex_size: usize,
+    vertices: RangeAllocator,
+    indices: RangeAllocator,
+}
+
+impl MeshPool {
+    pub fn new<V: Copy>(
+        device: &device::Device,
+        vertex_capacity: u32,
+        index_capacity: u32,
+    ) -> Result<MeshPool> {
+        let vertex_size = std::mem::size_of::<V>();
+
+        let vertex_buffer = buffers::BufferInfo::create(
+            device,
+            (vertex_size * vertex_capacity.max(1) as usize) as vk::DeviceSize,
+            vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+
+        let index_buffer = typed_buffer::IndexBuffer::from_packed(
+            buffers::BufferInfo::create(
+                device,
+                (std::mem::size_of::<u32>() * index_capacity.max(1) as usize) as vk::DeviceSize,
+                vk::BufferUsageFlags::INDEX_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+                vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            )?,
+            index_capacity as usize,
+            typed_buffer::MemoryLocation::DeviceLocal,
+        )?;
+
+        vertex_buffer.set_name(device, "mesh pool vertex buffer");
+        device.name_resource(index_buffer.buffer(), "mesh pool index buffer");
+
+        Ok(MeshPool {
+            vertex_buffer,
+            index_buffer,
+            vertex_size,
+            vertices: RangeAllocator::new(vertex_capacity),
+            indices: RangeAllocator::new(index_capacity),
+        })
+    }
+
+    // Reserves room for the mesh and queues the copies of its data, which are done once
+    // the upload batch is submitted
+    pub fn allocate<V: Copy>(
+        &mut self,
+        uploads: &mut upload::UploadManager,
+        vertices: &[V],
+        indices: &[u32],
+    ) -> Result<MeshAllocation> {
+        if std::mem::size_of::<V>() != self.vertex_size {
+            return Err(Error::Unsupported(format!(
+                "the pool holds vertices of {} bytes, got {}",
+                self.vertex_size,
+                std::mem::size_of::<V>()
+            )));
+        }
+
+        if let Some(&index) = indices
+            .iter()
+            .find(|&&index| index as usize >= vertices.len())
+        {
+            return Err(Error::OutOfRange(format!(
+                "index {} is past the {} vertices of the mesh",
+                index,
+                vertices.len()
+            )));
+        }
+
+        let vertex_range = self
+            .vertices
+            .allocate(vertices.len() as u32)
+            .ok_or_else(|| {
+                Error::OutOfRange(format!(
+                    "no room for {} vertices in the mesh pool, {} of {} are free",
+                    vertices.len(),
+                    self.vertices.free_count(),
+                    self.vertices.capacity()
+                ))
+            })?;
+
+        let index_range = match self.indices.allocate(indices.len() as u32) {
+            Some(range) => range,
+            None => {
+                self.vertices.free(vertex_range);
+                return Err(Error::OutOfRange(format!(
+                    "no room for {} indices in the mesh pool, {} of {} are free",
+                    indices.len(),
+                    self.indices.free_count(),
+                    self.indices.capacity()
+                )));
+            }
+        };
+
+        let allocation = MeshAllocation {
+            first_vertex: vertex_range.start,
+            vertex_count: vertices.len() as u32,
+            first_index: index_range.start,
+            index_count: indices.len() as u32,
+        };
+
+        if !vertices.is_empty() {
+            uploads.upload_to_buffer(
+                self.vertex_buffer.buffer,
+                (vertex_range.start as usize * self.vertex_size) as vk::DeviceSize,
+                vertices,
+            )?;
+        }
+        if !indices.is_empty() {
+            uploads.upload_to_buffer(
+                self.index_buffer.buffer(),
+                self.index_buffer.offset_of(index_range.start as usize)?,
+                indices,
+            )?;
+        }
+
+        Ok(allocation)
+    }
+
+    // The ranges can be handed out again right away, so the gpu must be done drawing
+    // the mesh, eg. after Engine::wait_idle
+    pub fn free(&mut self, allocation: MeshAllocation) {
+        self.vertices.free(allocation.vertices());
+        self.indices.free(allocation.indices());
+    }
+
+    pub fn vertex_capacity(&self) -> u32 {
+        self.vertices.capacity()
+    }
+
+    pub fn index_capacity(&self) -> u32 {
+        self.indices.capacity()
+    }
+
+    pub fn destroy(&self, device: &device::Device) {
+        self.vertex_buffer.destroy(device);
+        self.index_buffer.destroy(device);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn freed_ranges_are_merged_and_reused() {
+        let mut ranges = RangeAllocator::new(10);
+
+        let first = ranges.allocate(4).unwrap();
+        let second = ranges.allocate(4).unwrap();
+        assert_eq!((first.clone(), second.clone()), (0..4, 4..8));
+        assert!(ranges.allocate(3).is_none());
+
+        // the hole at the start is reused first fit
+        ranges.free(first);
+        assert_eq!(ranges.allocate(2), Some(0..2));
+        assert_eq!(ranges.free_count(), 4);
+
+        ranges.free(0..2);
+        ranges.free(second);
+        assert_eq!(ranges.free_count(), 10);
+        assert_eq!(ranges.allocate(10), Some(0..10));
+    }
+
+    #[test]
+    fn allocations_draw_their_own_range() {
+        let allocation = MeshAllocation {
+            first_vertex: 8,
+            vertex_count: 4,
+            first_index: 12,
+            index_count: 6,
+        };
+
+        let draw = allocation.draw(2);
+        assert_eq!((draw.first_index, draw.index_count), (12, 6));
+        assert_eq!((draw.vertex_offset, draw.material), (8, 2));
+        assert_eq!(allocation.indices(), 12..18);
+    }
+}
@@ -1,11 +1,65 @@
+pub mod adapter;
+pub mod backend;
+pub mod bindless;
+pub mod bounds;
 pub mod buffers;
+pub mod capture;
+pub mod compute_skinning;
 pub mod constants;
+pub mod debug_lines;
+pub mod debug_messenger;
+pub mod deferred;
+pub mod descriptor;
 pub mod device;
+pub mod dynamic_buffer;
+pub mod dynamic_rendering;
+pub mod events;
+pub mod frame;
+pub mod gc;
+pub mod gpu_culling;
+pub mod headless;
+pub mod ibl;
 pub mod image;
+pub mod image_effects;
 pub mod instance;
+pub mod lighting;
+pub mod material;
+pub mod mesh_pool;
+pub mod object_uniforms;
+pub mod parallel;
+pub mod particles;
+pub mod pbr;
+pub mod permutation;
+pub mod picking;
 pub mod pipeline;
+pub mod portability;
+pub mod postprocess;
+pub mod present;
+pub mod preset;
+pub mod probe;
+pub mod profiler;
 pub mod queue;
+pub mod reflect;
+pub mod reflection_probe;
+pub mod registry;
+pub mod render_settings;
+pub mod requirements;
+pub mod scheduler;
+pub mod skinning;
+pub mod stereo;
 pub mod surface;
 pub mod swapchain;
 pub mod sync;
+pub mod texgen;
 pub mod texture;
+pub mod timeline;
+pub mod trace;
+pub mod transient;
+pub mod typed_buffer;
+pub mod ui;
+pub mod uniform_ring;
+pub mod upload;
+pub mod validation_stats;
+pub mod vertex;
+pub mod viewport;
+pub mod warmup;
@@ -0,0 +1,132 @@
+use ash::vk;
+
+use crate::error::{Error, Result};
+
+use super::buffers;
+use super::descriptor;
+use super::device;
+use super::frame;
+use super::typed_buffer;
+
+// Uniform data of many objects in one buffer, bound once as a UNIFORM_BUFFER_DYNAMIC
+// descriptor. Every object's slice is aligned to minUniformBufferOffsetAlignment, so
+// an object is selected with a dynamic offset when binding the set instead of needing
+// a buffer and descriptor set of its own.
+// Each swapchain image has its own region so that in flight frames are not overwritten.
+pub struct ObjectUniforms<T: Copy> {
+    buffer: typed_buffer::UniformBuffer<T>,
+    objects: Vec<T>,
+    num_images: usize,
+    uploads: buffers::UniformUploads,
+}
+
+impl<T: Copy> ObjectUniforms<T> {
+    pub fn new(
+        device: &device::Device,
+        objects: Vec<T>,
+        num_images: usize,
+    ) -> Result<ObjectUniforms<T>> {
+        let object_size = ::std::mem::size_of::<T>() as u32;
+        if object_size > device.limits.max_uniform_buffer_range {
+            return Err(Error::Unsupported(format!(
+                "objects of {} bytes exceed the uniform buffer range of {}",
+                object_size, device.limits.max_uniform_buffer_range
+            )));
+        }
+
+        let buffer = typed_buffer::UniformBuffer::host_visible(device, objects.len() * num_images)?;
+
+        Ok(ObjectUniforms {
+            buffer,
+            objects,
+            num_images,
+            uploads: buffers::UniformUploads::new(buffers::UpdatePolicy::OnDemand, num_images),
+        })
+    }
+
+    // Layout binding for the shaders, which declare a single object's block
+    pub fn binding(binding: u32, stage_flags: vk::ShaderStageFlags) -> descriptor::Binding {
+        descriptor::Binding::uniform_buffer_dynamic(binding, stage_flags)
+    }
+
+    // The range covers one object, the dynamic offset moves it over the buffer
+    pub fn descriptor_info(&self) -> Result<vk::DescriptorBufferInfo> {
+        self.buffer.descriptor_info(0)
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    pub fn objects(&self) -> &[T] {
+        &self.objects
+    }
+
+    // Changes the data of one object, uploaded to each image's region before it is drawn
+    pub fn set(&mut self, object: usize, data: T) -> Result<()> {
+        let len = self.objects.len();
+        let slot = self.objects.get_mut(object).ok_or_else(|| {
+            Error::OutOfRange(format!(
+                "no object {} to update, the buffer holds {}",
+                object, len
+            ))
+        })?;
+
+        *slot = data;
+        self.uploads.mark_dirty();
+        Ok(())
+    }
+
+    // Copies the objects to the image's region if they changed since its last upload.
+    // Must only be called once the previous frame using the image has completed.
+    pub fn upload(&mut self, device: &ash::Device, frame: &frame::FrameContext) -> Result<()> {
+        let image_index = frame.image_index() as usize;
+        if image_index >= self.num_images {
+            return Err(Error::OutOfRange(format!(
+                "no region for image {}, the buffer has {}",
+                image_index, self.num_images
+            )));
+        }
+
+        if !self.uploads.needs_upload(image_index) {
+            return Ok(());
+        }
+
+        self.buffer
+            .update_at(device, image_index * self.objects.len(), &self.objects)?;
+        self.uploads.uploaded(image_index);
+        Ok(())
+    }
+
+    // Offset passed to cmd_bind_descriptor_sets to select the object in the image's region
+    pub fn dynamic_offset(&self, image_index: usize, object: usize) -> Result<u32> {
+        if object >= self.objects.len() || image_index >= self.num_images {
+            return Err(Error::OutOfRange(format!(
+                "no object {} for image {}, the buffer holds {} objects for {} images",
+                object,
+                image_index,
+                self.objects.len(),
+                self.num_images
+            )));
+        }
+
+        self.buffer
+            .offset_of(image_index * self.objects.len() + object)
+            .map(|offset| offset as u32)
+    }
+
+    // Offsets of the object in every image's region, indexed by image
+    pub fn image_offsets(&self, object: usize) -> Result<Vec<u32>> {
+        (0..self.num_images)
+            .map(|image_index| self.dynamic_offset(image_index, object))
+            .collect()
+    }
+
+    pub fn destroy(&self, device: &device::Device) {
+        self.buffer.destroy(device);
+    }
+}
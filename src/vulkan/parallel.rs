@@ -0,0 +1,200 @@
+use ash::version::DeviceV1_0;
+use ash::vk;
+
+use crate::error::{Context, Error, Result};
+
+use super::device;
+use super::registry;
+
+use std::ops::Range;
+use std::sync::Arc;
+use std::thread;
+
+// The render pass and framebuffer the secondary command buffers are executed in
+#[derive(Debug, Copy, Clone)]
+pub struct Inheritance {
+    pub render_pass: vk::RenderPass,
+    pub subpass: u32,
+    pub framebuffer: vk::Framebuffer,
+}
+
+// Records secondary command buffers on multiple threads, to be run from a primary one with
+// `CommandBuffer::execute_in_render_pass`. A command pool must only be used by one thread
+// at a time, so every thread records into a buffer allocated from its own pool.
+// The buffers are reused by the next recording, so a recorder is needed per image in flight.
+pub struct ParallelRecorder {
+    pools: Vec<vk::CommandPool>,
+    command_buffers: Vec<vk::CommandBuffer>,
+}
+
+impl ParallelRecorder {
+    pub fn new(device: &device::Device, num_threads: usize) -> Result<ParallelRecorder> {
+        let queue_index = device
+            .family_indices
+            .graphics
+            .ok_or_else(|| Error::msg("graphics family index not present"))?;
+
+        let mut pools = Vec::with_capacity(num_threads.max(1));
+        let mut command_buffers = Vec::with_capacity(num_threads.max(1));
+
+        for _ in 0..num_threads.max(1) {
+            let command_pool_info = vk::CommandPoolCreateInfo {
+                queue_family_index: queue_index,
+                // the whole pool is reset before every recording
+                flags: vk::CommandPoolCreateFlags::TRANSIENT,
+                ..Default::default()
+            };
+
+            let pool = unsafe {
+                device
+                    .logical_device
+                    .create_command_pool(&command_pool_info, None)
+                    .context("failed to create command pool")
+            }?;
+            device.track(registry::ResourceKind::CommandPool, pool);
+
+            let command_buffer_alloc_info = vk::CommandBufferAllocateInfo {
+                command_buffer_count: 1,
+                command_pool: pool,
+                level: vk::CommandBufferLevel::SECONDARY,
+                ..Default::default()
+            };
+
+            let command_buffer = unsafe {
+                device
+                    .logical_device
+                    .allocate_command_buffers(&command_buffer_alloc_info)
+                    .context("failed to allocate secondary command buffer")
+            }?[0];
+
+            pools.push(pool);
+            command_buffers.push(command_buffer);
+        }
+
+        Ok(ParallelRecorder {
+            pools,
+            command_buffers,
+        })
+    }
+
+    pub fn num_threads(&self) -> usize {
+        self.pools.len()
+    }
+
+    // Splits the items into a contiguous range per thread and calls `record` for every item
+    // with the buffer of its thread. Returns the recorded buffers in the order of the items.
+    // The previous recording must have completed on the gpu, its buffers are overwritten.
+    pub fn record<F>(
+        &self,
+        device: &ash::Device,
+        inheritance: Inheritance,
+        num_items: usize,
+        record: F,
+    ) -> Result<Vec<vk::CommandBuffer>>
+    where
+        F: Fn(usize, vk::CommandBuffer) + Send + Sync + 'static,
+    {
+        if num_items == 0 {
+            return Ok(Vec::new());
+        }
+
+        let record = Arc::new(record);
+        let chunk_size = (num_items + self.pools.len() - 1) / self.pools.len();
+
+        let handles = self
+            .pools
+            .iter()
+            .zip(self.command_buffers.iter())
+            .enumerate()
+            .map(|(thread_index, (&pool, &command_buffer))| {
+                (thread_index * chunk_size, pool, command_buffer)
+            })
+            .take_while(|(start, _, _)| *start < num_items)
+            .map(|(start, pool, command_buffer)| {
+                let items = start..(start + chunk_size).min(num_items);
+                let device = device.clone();
+                let record = Arc::clone(&record);
+
+                thread::spawn(move || {
+                    ParallelRecorder::record_items(
+                        &device,
+                        pool,
+                        command_buffer,
+                        inheritance,
+                        items,
+                        &*record,
+                    )
+                })
+            })
+            .collect::<Vec<_>>();
+
+        // join every thread before returning, even if one of them failed
+        handles
+            .into_iter()
+            .map(|handle| {
+                handle
+                    .join()
+                    .unwrap_or_else(|_| Err(Error::msg("command recording thread panicked")))
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .collect()
+    }
+
+    fn record_items<F>(
+        device: &ash::Device,
+        pool: vk::CommandPool,
+        command_buffer: vk::CommandBuffer,
+        inheritance: Inheritance,
+        items: Range<usize>,
+        record: &F,
+    ) -> Result<vk::CommandBuffer>
+    where
+        F: Fn(usize, vk::CommandBuffer),
+    {
+        unsafe {
+            device
+                .reset_command_pool(pool, vk::CommandPoolResetFlags::empty())
+                .context("failed to reset command pool")
+        }?;
+
+        let inheritance_info = vk::CommandBufferInheritanceInfo {
+            render_pass: inheritance.render_pass,
+            subpass: inheritance.subpass,
+            framebuffer: inheritance.framebuffer,
+            ..Default::default()
+        };
+
+        let begin_info = vk::CommandBufferBeginInfo {
+            flags: vk::CommandBufferUsageFlags::RENDER_PASS_CONTINUE,
+            p_inheritance_info: &inheritance_info,
+            ..Default::default()
+        };
+
+        unsafe {
+            device
+                .begin_command_buffer(command_buffer, &begin_info)
+                .context("failed to begin recording secondary command buffer")
+        }?;
+
+        for item in items {
+            record(item, command_buffer);
+        }
+
+        unsafe {
+            device
+                .end_command_buffer(command_buffer)
+                .context("failed to end secondary command buffer recording")
+        }?;
+
+        Ok(command_buffer)
+    }
+
+    // Also frees the command buffers, none of them may be in use anymore
+    pub fn destroy(&self, device: &device::Device) {
+        for &pool in self.pools.iter() {
+            device.untrack(pool);
+            unsafe { device.logical_device.destroy_command_pool(pool, None) };
+        }
+    }
+}
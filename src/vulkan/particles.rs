@@ -0,0 +1,861 @@
+use ash::version::DeviceV1_0;
+use ash::vk;
+
+use crate::error::{Context, Error, Result};
+
+use cgmath::{Matrix4, Point3, Vector3};
+
+use std::ffi::CString;
+use std::time::Instant;
+
+use crate::shaderc;
+
+use super::buffers;
+use super::descriptor;
+use super::device;
+use super::frame;
+use super::pipeline;
+use super::preset;
+use super::registry;
+use super::swapchain;
+use super::vertex;
+
+pub const PARTICLE_COMPUTE_SHADER: &'static str = "shaders/particle.comp";
+pub const PARTICLE_VERTEX_SHADER: &'static str = "shaders/particle.vert";
+pub const PARTICLE_FRAGMENT_SHADER: &'static str = "shaders/particle.frag";
+
+// the particle storage buffer the compute shader updates
+const PARTICLES_BINDING: descriptor::Binding = descriptor::Binding {
+    binding: 0,
+    descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+    count: 1,
+    stage_flags: vk::ShaderStageFlags::COMPUTE,
+};
+
+// local_size_x of the compute shader
+const WORKGROUP_SIZE: u32 = 64;
+// a long stall, eg. while the window is dragged, should not throw every particle away
+const MAX_DELTA_TIME: f32 = 0.1;
+
+// Where particles come from and how they move, changes take effect for the particles
+// spawned afterwards
+#[derive(Debug, Copy, Clone)]
+pub struct EmitterConfig {
+    pub position: Point3<f32>,
+    // particles per second
+    pub spawn_rate: f32,
+    // seconds before a particle disappears
+    pub lifetime: f32,
+    pub velocity: Vector3<f32>,
+    // random offset added to the velocity on every axis
+    pub velocity_spread: f32,
+    pub gravity: Vector3<f32>,
+    pub size: f32,
+    pub color: [f32; 4],
+    // size of the particle buffer, the oldest particles are replaced once it is full
+    pub max_particles: u32,
+}
+
+impl Default for EmitterConfig {
+    fn default() -> EmitterConfig {
+        EmitterConfig {
+            position: Point3::new(0.0, 0.0, 0.0),
+            spawn_rate: 200.0,
+            lifetime: 2.0,
+            velocity: Vector3::new(0.0, 0.0, 1.0),
+            velocity_spread: 0.3,
+            gravity: Vector3::new(0.0, 0.0, -0.5),
+            size: 0.05,
+            color: [1.0, 0.6, 0.2, 1.0],
+            max_particles: 1024,
+        }
+    }
+}
+
+impl EmitterConfig {
+    pub fn with_position(mut self, position: Point3<f32>) -> EmitterConfig {
+        self.position = position;
+        self
+    }
+
+    pub fn with_spawn_rate(mut self, spawn_rate: f32) -> EmitterConfig {
+        self.spawn_rate = spawn_rate;
+        self
+    }
+
+    pub fn with_lifetime(mut self, lifetime: f32) -> EmitterConfig {
+        self.lifetime = lifetime;
+        self
+    }
+
+    pub fn with_velocity(mut self, velocity: Vector3<f32>, spread: f32) -> EmitterConfig {
+        self.velocity = velocity;
+        self.velocity_spread = spread;
+        self
+    }
+
+    pub fn with_gravity(mut self, gravity: Vector3<f32>) -> EmitterConfig {
+        self.gravity = gravity;
+        self
+    }
+
+    pub fn with_size(mut self, size: f32) -> EmitterConfig {
+        self.size = size;
+        self
+    }
+
+    pub fn with_color(mut self, color: [f32; 4]) -> EmitterConfig {
+        self.color = color;
+        self
+    }
+
+    pub fn with_max_particles(mut self, max_particles: u32) -> EmitterConfig {
+        self.max_particles = max_particles;
+        self
+    }
+}
+
+// Layout of a particle in the storage buffer, matches the std430 struct in particle.comp
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct Particle {
+    // w is the remaining lifetime, the particle is dead once it reaches 0
+    pub position: [f32; 4],
+    // w is the size of the quad
+    pub velocity: [f32; 4],
+    pub color: [f32; 4],
+}
+
+// The storage buffer is bound as a per instance vertex buffer, every particle is a quad
+impl pipeline::VertexData for Particle {
+    fn get_input_binding_description(&self) -> Vec<vk::VertexInputBindingDescription> {
+        vertex::VertexLayoutBuilder::<Particle>::new()
+            .per_instance()
+            .binding_descriptions()
+    }
+
+    fn get_attribute_description(&self) -> Vec<vk::VertexInputAttributeDescription> {
+        vertex::VertexLayoutBuilder::<Particle>::new()
+            .per_instance()
+            .field(memoffset::offset_of!(Particle, position), |p| &p.position)
+            .field(memoffset::offset_of!(Particle, velocity), |p| &p.velocity)
+            .field(memoffset::offset_of!(Particle, color), |p| &p.color)
+            .attribute_descriptions()
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct SimulateConstants {
+    emitter_position: [f32; 4],
+    velocity: [f32; 4],
+    color: [f32; 4],
+    gravity: [f32; 4],
+    delta_time: f32,
+    spawn_start: u32,
+    spawn_count: u32,
+    particle_count: u32,
+    seed: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct DrawConstants {
+    view_proj: [[f32; 4]; 4],
+    camera_right: [f32; 4],
+    camera_up: [f32; 4],
+}
+
+fn as_bytes<T>(value: &T) -> &[u8] {
+    unsafe {
+        ::std::slice::from_raw_parts(value as *const T as *const u8, ::std::mem::size_of::<T>())
+    }
+}
+
+// Particles simulated by a compute shader and drawn as camera facing quads on top of the
+// scene. The state lives in a single device local buffer which the compute pass updates
+// in place every frame, so the frames are serialized by barriers in the command buffers.
+pub struct ParticleSystem {
+    device: device::Device,
+    emitter: EmitterConfig,
+
+    particles: buffers::BufferInfo,
+    // the buffer starts out with garbage and is cleared by the first recording
+    cleared: bool,
+
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set: vk::DescriptorSet,
+    compute_layout: vk::PipelineLayout,
+    compute_pipeline: vk::Pipeline,
+
+    pub render_pass: vk::RenderPass,
+    pub pipeline: vk::Pipeline,
+    pub layout: vk::PipelineLayout,
+
+    framebuffers: Vec<vk::Framebuffer>,
+    command_pool: vk::CommandPool,
+    command_buffers: Vec<vk::CommandBuffer>,
+
+    extent: vk::Extent2D,
+    view_proj: Matrix4<f32>,
+    view: Matrix4<f32>,
+
+    last_update: Instant,
+    spawn_accumulator: f32,
+    next_spawn: u32,
+    seed: u32,
+}
+
+impl ParticleSystem {
+    pub fn is_supported(instance: &ash::Instance, device: &device::Device) -> bool {
+        device.graphics_queue_supports_compute(instance)
+    }
+
+    fn create_descriptor_set(
+        device: &ash::Device,
+        layout: vk::DescriptorSetLayout,
+        particles: &buffers::BufferInfo,
+    ) -> Result<(vk::DescriptorPool, vk::DescriptorSet)> {
+        let bindings = [PARTICLES_BINDING];
+        let pool = descriptor::create_pool(device, &bindings, 1)?;
+        let descriptor_set = descriptor::allocate_sets(device, pool, layout, 1)?[0];
+
+        let buffer_infos = [vk::DescriptorBufferInfo {
+            buffer: particles.buffer,
+            offset: 0,
+            range: vk::WHOLE_SIZE,
+        }];
+
+        let descriptor_writes = [PARTICLES_BINDING.write_buffers(descriptor_set, &buffer_infos)?];
+        unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+
+        Ok((pool, descriptor_set))
+    }
+
+    fn create_pipeline_layout(
+        device: &ash::Device,
+        set_layouts: &[vk::DescriptorSetLayout],
+        stage_flags: vk::ShaderStageFlags,
+        push_constants_size: usize,
+    ) -> Result<vk::PipelineLayout> {
+        let push_constant_ranges = [vk::PushConstantRange {
+            stage_flags,
+            offset: 0,
+            size: push_constants_size as u32,
+        }];
+
+        let layout_info = vk::PipelineLayoutCreateInfo {
+            set_layout_count: set_layouts.len() as u32,
+            p_set_layouts: set_layouts.as_ptr(),
+            push_constant_range_count: push_constant_ranges.len() as u32,
+            p_push_constant_ranges: push_constant_ranges.as_ptr(),
+            ..Default::default()
+        };
+
+        unsafe {
+            device
+                .create_pipeline_layout(&layout_info, None)
+                .context("failed to create particle pipeline layout")
+        }
+    }
+
+    fn create_compute_pipeline(
+        device: &ash::Device,
+        shader_file: &String,
+        pipeline_layout: vk::PipelineLayout,
+    ) -> Result<vk::Pipeline> {
+        let code = shaderc::ShaderSource::compile_compute(shader_file)?;
+        let shader_module = pipeline::PipelineDetail::create_shader_module(device, code)?;
+
+        let main_function_name = CString::new("main").context("invalid fn name")?;
+
+        let pipeline_info = vk::ComputePipelineCreateInfo {
+            stage: vk::PipelineShaderStageCreateInfo {
+                module: shader_module,
+                p_name: main_function_name.as_ptr(),
+                stage: vk::ShaderStageFlags::COMPUTE,
+                ..Default::default()
+            },
+            layout: pipeline_layout,
+            base_pipeline_index: -1,
+            ..Default::default()
+        };
+
+        let pipelines = unsafe {
+            device.create_compute_pipelines(vk::PipelineCache::null(), &[pipeline_info], None)
+        };
+
+        unsafe { device.destroy_shader_module(shader_module, None) };
+
+        pipelines
+            .map(|pipelines| pipelines[0])
+            .map_err(|(_, err)| err)
+            .context("failed to create particle compute pipeline")
+    }
+
+    fn create_render_pass(
+        device: &ash::Device,
+        surface_format: vk::Format,
+    ) -> Result<vk::RenderPass> {
+        // drawn over the finished scene, so load instead of clear
+        let color_attachment = vk::AttachmentDescription {
+            format: surface_format,
+            samples: vk::SampleCountFlags::TYPE_1,
+            load_op: vk::AttachmentLoadOp::LOAD,
+            store_op: vk::AttachmentStoreOp::STORE,
+            stencil_load_op: vk::AttachmentLoadOp::DONT_CARE,
+            stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
+            initial_layout: vk::ImageLayout::PRESENT_SRC_KHR,
+            final_layout: vk::ImageLayout::PRESENT_SRC_KHR,
+            ..Default::default()
+        };
+
+        let color_attachment_ref = vk::AttachmentReference {
+            attachment: 0,
+            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        };
+
+        let subpasses = [vk::SubpassDescription {
+            color_attachment_count: 1,
+            p_color_attachments: &color_attachment_ref,
+            pipeline_bind_point: vk::PipelineBindPoint::GRAPHICS,
+            ..Default::default()
+        }];
+
+        let attachments = [color_attachment];
+
+        let subpass_dependencies = [vk::SubpassDependency {
+            src_subpass: vk::SUBPASS_EXTERNAL,
+            src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            dst_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            src_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            dst_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_READ
+                | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            ..Default::default()
+        }];
+
+        let renderpass_create_info = vk::RenderPassCreateInfo {
+            attachment_count: attachments.len() as u32,
+            p_attachments: attachments.as_ptr(),
+            subpass_count: subpasses.len() as u32,
+            p_subpasses: subpasses.as_ptr(),
+            dependency_count: subpass_dependencies.len() as u32,
+            p_dependencies: subpass_dependencies.as_ptr(),
+            ..Default::default()
+        };
+
+        unsafe {
+            device
+                .create_render_pass(&renderpass_create_info, None)
+                .context("failed to create particle render pass")
+        }
+    }
+
+    fn create_framebuffers(
+        device: &ash::Device,
+        render_pass: vk::RenderPass,
+        image_views: &Vec<vk::ImageView>,
+        extent: vk::Extent2D,
+    ) -> Result<Vec<vk::Framebuffer>> {
+        image_views
+            .iter()
+            .map(|&image_view| {
+                let attachments = [image_view];
+
+                let framebuffer_info = vk::FramebufferCreateInfo {
+                    render_pass,
+                    attachment_count: attachments.len() as u32,
+                    p_attachments: attachments.as_ptr(),
+                    width: extent.width,
+                    height: extent.height,
+                    layers: 1,
+                    ..Default::default()
+                };
+
+                unsafe {
+                    device
+                        .create_framebuffer(&framebuffer_info, None)
+                        .context("failed to create particle framebuffer")
+                }
+            })
+            .collect()
+    }
+
+    fn create_command_buffers(
+        device: &device::Device,
+        count: u32,
+    ) -> Result<(vk::CommandPool, Vec<vk::CommandBuffer>)> {
+        let queue_index = device
+            .family_indices
+            .graphics
+            .ok_or_else(|| Error::msg("graphics family index not present"))?;
+
+        let command_pool_info = vk::CommandPoolCreateInfo {
+            queue_family_index: queue_index,
+            flags: vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER,
+            ..Default::default()
+        };
+
+        let command_pool = unsafe {
+            device
+                .logical_device
+                .create_command_pool(&command_pool_info, None)
+                .context("failed to create particle command pool")
+        }?;
+
+        let alloc_info = vk::CommandBufferAllocateInfo {
+            command_buffer_count: count,
+            command_pool,
+            level: vk::CommandBufferLevel::PRIMARY,
+            ..Default::default()
+        };
+
+        let command_buffers = unsafe {
+            device
+                .logical_device
+                .allocate_command_buffers(&alloc_info)
+                .context("failed to allocate particle command buffers")
+        }?;
+
+        Ok((command_pool, command_buffers))
+    }
+
+    pub fn new(
+        instance: &ash::Instance,
+        device: &device::Device,
+        swapchain: &swapchain::SwapchainDetails,
+        emitter: EmitterConfig,
+    ) -> Result<ParticleSystem> {
+        if !ParticleSystem::is_supported(instance, device) {
+            return Err(Error::Unsupported(
+                "the graphics queue cannot run the particle compute shader".to_string(),
+            ));
+        }
+
+        if emitter.max_particles == 0 {
+            return Err(Error::OutOfRange(
+                "an emitter needs room for at least one particle".to_string(),
+            ));
+        }
+
+        let logical_device = &device.logical_device;
+        let num_images = swapchain.image_views.len();
+
+        let particles = buffers::BufferInfo::create(
+            device,
+            (emitter.max_particles as usize * ::std::mem::size_of::<Particle>()) as vk::DeviceSize,
+            vk::BufferUsageFlags::STORAGE_BUFFER
+                | vk::BufferUsageFlags::VERTEX_BUFFER
+                | vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+        particles.set_name(device, "particles");
+
+        let descriptor_set_layout =
+            descriptor::create_set_layout(logical_device, &[PARTICLES_BINDING])?;
+        let (descriptor_pool, descriptor_set) = ParticleSystem::create_descriptor_set(
+            logical_device,
+            descriptor_set_layout,
+            &particles,
+        )?;
+
+        let compute_layout = ParticleSystem::create_pipeline_layout(
+            logical_device,
+            &[descriptor_set_layout],
+            vk::ShaderStageFlags::COMPUTE,
+            ::std::mem::size_of::<SimulateConstants>(),
+        )?;
+        let compute_pipeline = ParticleSystem::create_compute_pipeline(
+            logical_device,
+            &PARTICLE_COMPUTE_SHADER.to_string(),
+            compute_layout,
+        )?;
+
+        let render_pass =
+            ParticleSystem::create_render_pass(logical_device, swapchain.format.format)?;
+        let layout = ParticleSystem::create_pipeline_layout(
+            logical_device,
+            &[],
+            vk::ShaderStageFlags::VERTEX,
+            ::std::mem::size_of::<DrawConstants>(),
+        )?;
+
+        let shaders = shaderc::ShaderSource {
+            vertex_shader_file: PARTICLE_VERTEX_SHADER.to_string(),
+            fragment_shader_file: PARTICLE_FRAGMENT_SHADER.to_string(),
+        };
+
+        // additive blending does not depend on the draw order, so particles are not sorted
+        let pipeline = pipeline::PipelineDetail::create_pipeline(
+            logical_device,
+            shaders,
+            Particle {
+                position: [0.0; 4],
+                velocity: [0.0; 4],
+                color: [0.0; 4],
+            },
+            &preset::FixedFunctionState::from_preset(preset::Preset::Additive).with_depth(
+                false,
+                false,
+                vk::CompareOp::ALWAYS,
+            ),
+            layout,
+            render_pass,
+            vk::PipelineCache::null(),
+        )?;
+
+        let framebuffers = ParticleSystem::create_framebuffers(
+            logical_device,
+            render_pass,
+            &swapchain.image_views,
+            swapchain.extent,
+        )?;
+
+        let (command_pool, command_buffers) =
+            ParticleSystem::create_command_buffers(device, num_images as u32)?;
+
+        device.track(
+            registry::ResourceKind::DescriptorSetLayout,
+            descriptor_set_layout,
+        );
+        device.track(registry::ResourceKind::DescriptorPool, descriptor_pool);
+        device.track(registry::ResourceKind::PipelineLayout, compute_layout);
+        device.track(registry::ResourceKind::Pipeline, compute_pipeline);
+        device.track(registry::ResourceKind::RenderPass, render_pass);
+        device.track(registry::ResourceKind::Pipeline, pipeline);
+        device.track(registry::ResourceKind::PipelineLayout, layout);
+        device.track(registry::ResourceKind::CommandPool, command_pool);
+        for &framebuffer in framebuffers.iter() {
+            device.track(registry::ResourceKind::Framebuffer, framebuffer);
+        }
+
+        Ok(ParticleSystem {
+            device: device.clone(),
+            emitter,
+            particles,
+            cleared: false,
+            descriptor_set_layout,
+            descriptor_pool,
+            descriptor_set,
+            compute_layout,
+            compute_pipeline,
+            render_pass,
+            pipeline,
+            layout,
+            framebuffers,
+            command_pool,
+            command_buffers,
+            extent: swapchain.extent,
+            view_proj: Matrix4::from_scale(1.0),
+            view: Matrix4::from_scale(1.0),
+            last_update: Instant::now(),
+            spawn_accumulator: 0.0,
+            next_spawn: 0,
+            seed: 0,
+        })
+    }
+
+    pub fn emitter(&self) -> &EmitterConfig {
+        &self.emitter
+    }
+
+    // The particle buffer is not resized, so the new config must keep max_particles
+    pub fn set_emitter(&mut self, emitter: EmitterConfig) -> Result<()> {
+        if emitter.max_particles != self.emitter.max_particles {
+            return Err(Error::Unsupported(format!(
+                "cannot resize the particle buffer from {} to {} particles",
+                self.emitter.max_particles, emitter.max_particles
+            )));
+        }
+
+        self.emitter = emitter;
+        Ok(())
+    }
+
+    // The quads are turned to face the camera of the view matrix
+    pub fn set_camera(&mut self, view: Matrix4<f32>, proj: Matrix4<f32>) {
+        self.view = view;
+        self.view_proj = proj * view;
+    }
+
+    // Number of particles to spawn this frame, the fractional rest is carried to the next
+    fn take_spawn_count(&mut self, delta_time: f32) -> u32 {
+        self.spawn_accumulator += self.emitter.spawn_rate.max(0.0) * delta_time;
+
+        let spawn_count = self.spawn_accumulator.floor();
+        self.spawn_accumulator -= spawn_count;
+
+        (spawn_count as u32).min(self.emitter.max_particles)
+    }
+
+    fn buffer_barrier(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        src: (vk::PipelineStageFlags, vk::AccessFlags),
+        dst: (vk::PipelineStageFlags, vk::AccessFlags),
+    ) {
+        let barrier = vk::BufferMemoryBarrier {
+            src_access_mask: src.1,
+            dst_access_mask: dst.1,
+            src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            buffer: self.particles.buffer,
+            offset: 0,
+            size: vk::WHOLE_SIZE,
+            ..Default::default()
+        };
+
+        unsafe {
+            self.device.logical_device.cmd_pipeline_barrier(
+                command_buffer,
+                src.0,
+                dst.0,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[barrier],
+                &[],
+            )
+        };
+    }
+
+    // Records the simulation step followed by the draw for the image, the step covers
+    // the time since the previous recording.
+    // Must only be called once the previous frame using this image has completed.
+    pub fn record(&mut self, frame: &frame::FrameContext) -> Result<vk::CommandBuffer> {
+        let delta_time = self.last_update.elapsed().as_secs_f32().min(MAX_DELTA_TIME);
+        self.last_update = Instant::now();
+
+        let particle_count = self.emitter.max_particles;
+        let spawn_count = self.take_spawn_count(delta_time);
+        let spawn_start = self.next_spawn;
+        self.next_spawn = (self.next_spawn + spawn_count) % particle_count;
+        self.seed = self.seed.wrapping_add(1);
+
+        let emitter = &self.emitter;
+        let simulate_constants = SimulateConstants {
+            emitter_position: [
+                emitter.position.x,
+                emitter.position.y,
+                emitter.position.z,
+                emitter.lifetime,
+            ],
+            velocity: [
+                emitter.velocity.x,
+                emitter.velocity.y,
+                emitter.velocity.z,
+                emitter.velocity_spread,
+            ],
+            color: emitter.color,
+            gravity: [
+                emitter.gravity.x,
+                emitter.gravity.y,
+                emitter.gravity.z,
+                emitter.size,
+            ],
+            delta_time,
+            spawn_start,
+            spawn_count,
+            particle_count,
+            // spreads the seeds so consecutive frames do not spawn correlated particles
+            seed: self.seed.wrapping_mul(0x9e37_79b9),
+        };
+
+        // the rows of the view matrix are the camera axes in world space
+        let draw_constants = DrawConstants {
+            view_proj: self.view_proj.into(),
+            camera_right: [self.view.x.x, self.view.y.x, self.view.z.x, 0.0],
+            camera_up: [self.view.x.y, self.view.y.y, self.view.z.y, 0.0],
+        };
+
+        let command_buffer = *frame.per_image(&self.command_buffers)?;
+
+        let render_pass_begin_info = vk::RenderPassBeginInfo {
+            render_pass: self.render_pass,
+            framebuffer: *frame.per_image(&self.framebuffers)?,
+            render_area: vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: self.extent,
+            },
+            ..Default::default()
+        };
+
+        let viewports = [vk::Viewport {
+            x: 0.0,
+            y: 0.0,
+            width: self.extent.width as f32,
+            height: self.extent.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        }];
+
+        let scissors = [vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent: self.extent,
+        }];
+
+        let logical_device = &self.device.logical_device;
+
+        unsafe {
+            logical_device
+                .begin_command_buffer(command_buffer, &vk::CommandBufferBeginInfo::default())
+                .context("failed to begin recording particle command buffer")?;
+        }
+
+        if self.cleared {
+            // the previous frame may still be drawing from the buffer
+            self.buffer_barrier(
+                command_buffer,
+                (
+                    vk::PipelineStageFlags::VERTEX_INPUT,
+                    vk::AccessFlags::VERTEX_ATTRIBUTE_READ,
+                ),
+                (
+                    vk::PipelineStageFlags::COMPUTE_SHADER,
+                    vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+                ),
+            );
+        } else {
+            // every particle starts out dead
+            unsafe {
+                logical_device.cmd_fill_buffer(
+                    command_buffer,
+                    self.particles.buffer,
+                    0,
+                    vk::WHOLE_SIZE,
+                    0,
+                )
+            };
+
+            self.buffer_barrier(
+                command_buffer,
+                (
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::AccessFlags::TRANSFER_WRITE,
+                ),
+                (
+                    vk::PipelineStageFlags::COMPUTE_SHADER,
+                    vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+                ),
+            );
+            self.cleared = true;
+        }
+
+        unsafe {
+            logical_device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.compute_pipeline,
+            );
+            logical_device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.compute_layout,
+                0,
+                &[self.descriptor_set],
+                &[],
+            );
+            logical_device.cmd_push_constants(
+                command_buffer,
+                self.compute_layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                as_bytes(&simulate_constants),
+            );
+            logical_device.cmd_dispatch(
+                command_buffer,
+                (particle_count + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE,
+                1,
+                1,
+            );
+        }
+
+        self.buffer_barrier(
+            command_buffer,
+            (
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::AccessFlags::SHADER_WRITE,
+            ),
+            (
+                vk::PipelineStageFlags::VERTEX_INPUT,
+                vk::AccessFlags::VERTEX_ATTRIBUTE_READ,
+            ),
+        );
+
+        unsafe {
+            logical_device.cmd_begin_render_pass(
+                command_buffer,
+                &render_pass_begin_info,
+                vk::SubpassContents::INLINE,
+            );
+
+            logical_device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline,
+            );
+
+            logical_device.cmd_set_viewport(command_buffer, 0, &viewports);
+            logical_device.cmd_set_scissor(command_buffer, 0, &scissors);
+
+            logical_device.cmd_push_constants(
+                command_buffer,
+                self.layout,
+                vk::ShaderStageFlags::VERTEX,
+                0,
+                as_bytes(&draw_constants),
+            );
+
+            logical_device.cmd_bind_vertex_buffers(
+                command_buffer,
+                0,
+                &[self.particles.buffer],
+                &[0],
+            );
+            // a quad of two triangles per particle, dead ones are collapsed by the vertex shader
+            logical_device.cmd_draw(command_buffer, 6, particle_count, 0, 0);
+
+            logical_device.cmd_end_render_pass(command_buffer);
+
+            logical_device
+                .end_command_buffer(command_buffer)
+                .context("failed to end particle command buffer recording")?;
+        }
+
+        Ok(command_buffer)
+    }
+
+    pub fn destroy(&mut self) {
+        self.particles.destroy(&self.device);
+
+        self.device.untrack(self.command_pool);
+        self.device.untrack(self.pipeline);
+        self.device.untrack(self.layout);
+        self.device.untrack(self.render_pass);
+        self.device.untrack(self.compute_pipeline);
+        self.device.untrack(self.compute_layout);
+        self.device.untrack(self.descriptor_pool);
+        self.device.untrack(self.descriptor_set_layout);
+        for &framebuffer in self.framebuffers.iter() {
+            self.device.untrack(framebuffer);
+        }
+
+        let logical_device = &self.device.logical_device;
+        unsafe {
+            logical_device.destroy_command_pool(self.command_pool, None);
+
+            for &framebuffer in self.framebuffers.iter() {
+                logical_device.destroy_framebuffer(framebuffer, None);
+            }
+
+            logical_device.destroy_pipeline(self.pipeline, None);
+            logical_device.destroy_pipeline_layout(self.layout, None);
+            logical_device.destroy_render_pass(self.render_pass, None);
+
+            logical_device.destroy_pipeline(self.compute_pipeline, None);
+            logical_device.destroy_pipeline_layout(self.compute_layout, None);
+            logical_device.destroy_descriptor_pool(self.descriptor_pool, None);
+            logical_device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use ash::vk;
+
+use crate::shaderc;
+
+use super::backend;
+use super::bindless;
+use super::material::TextureSlot;
+
+pub const PBR_VERTEX_SHADER: &'static str = "shaders/shader.vert";
+pub const PBR_FRAGMENT_SHADER: &'static str = "shaders/pbr.frag";
+
+// Textures of a metallic-roughness material in the order shaders/pbr.frag samples them.
+// Metallic is read from the blue and roughness from the green channel like in glTF.
+pub const PBR_SLOTS: [TextureSlot; 4] = [
+    TextureSlot::Diffuse,
+    TextureSlot::Normal,
+    TextureSlot::MetallicRoughness,
+    TextureSlot::Occlusion,
+];
+
+// Placed after the bindless material indices, so a material can push both
+pub const PARAMETERS_OFFSET: u32 = std::mem::size_of::<bindless::MaterialIndices>() as u32;
+
+// Factors the textures of a material are multiplied with, pushed before its draws. The
+// defaults leave the textures as they are, like a glTF material without factors.
+// Laid out to match the push constant block of shaders/pbr.frag.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PbrParameters {
+    // linear rgb and alpha
+    pub base_color: [f32; 4],
+    pub metallic: f32,
+    pub roughness: f32,
+    // scales the xy of the tangent space normal
+    pub normal_scale: f32,
+    // 0 ignores the occlusion map
+    pub occlusion_strength: f32,
+}
+
+impl Default for PbrParameters {
+    fn default() -> PbrParameters {
+        PbrParameters {
+            base_color: [1.0, 1.0, 1.0, 1.0],
+            metallic: 1.0,
+            roughness: 1.0,
+            normal_scale: 1.0,
+            occlusion_strength: 1.0,
+        }
+    }
+}
+
+impl PbrParameters {
+    pub fn with_base_color(mut self, base_color: [f32; 4]) -> PbrParameters {
+        self.base_color = base_color;
+        self
+    }
+
+    pub fn with_metallic_roughness(mut self, metallic: f32, roughness: f32) -> PbrParameters {
+        self.metallic = metallic.max(0.0).min(1.0);
+        self.roughness = roughness.max(0.0).min(1.0);
+        self
+    }
+
+    pub fn with_normal_scale(mut self, normal_scale: f32) -> PbrParameters {
+        self.normal_scale = normal_scale;
+        self
+    }
+
+    pub fn with_occlusion_strength(mut self, occlusion_strength: f32) -> PbrParameters {
+        self.occlusion_strength = occlusion_strength.max(0.0).min(1.0);
+        self
+    }
+
+    pub fn push_constant_range() -> vk::PushConstantRange {
+        vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::FRAGMENT,
+            offset: PARAMETERS_OFFSET,
+            size: std::mem::size_of::<PbrParameters>() as u32,
+        }
+    }
+
+    pub fn push<C: backend::CommandOps>(
+        &self,
+        commands: &C,
+        command_buffer: vk::CommandBuffer,
+        pipeline_layout: vk::PipelineLayout,
+    ) {
+        let bytes = unsafe {
+            std::slice::from_raw_parts(
+                self as *const PbrParameters as *const u8,
+                std::mem::size_of::<PbrParameters>(),
+            )
+        };
+
+        commands.push_constants(
+            command_buffer,
+            pipeline_layout,
+            vk::ShaderStageFlags::FRAGMENT,
+            PARAMETERS_OFFSET,
+            bytes,
+        );
+    }
+}
+
+// The scene vertex shader with shaders/pbr.frag, which samples the PBR_SLOTS from the
+// material set's texture array
+pub fn reference_shaders() -> shaderc::ShaderSource {
+    shaderc::ShaderSource {
+        vertex_shader_file: PBR_VERTEX_SHADER.to_string(),
+        fragment_shader_file: PBR_FRAGMENT_SHADER.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parameters_follow_the_bindless_indices() {
+        let indices = bindless::MaterialIndices::push_constant_range();
+        let parameters = PbrParameters::push_constant_range();
+
+        assert_eq!(indices.offset + indices.size, parameters.offset);
+        // a vec4 and four floats in the std430 push constant block
+        assert_eq!(parameters.size, 32);
+        // every device has at least 128 bytes of push constants
+        assert!(parameters.offset + parameters.size <= 128);
+    }
+
+    #[test]
+    fn factors_are_clamped() {
+        let parameters = PbrParameters::default()
+            .with_metallic_roughness(1.5, -0.5)
+            .with_occlusion_strength(2.0);
+
+        assert_eq!((parameters.metallic, parameters.roughness), (1.0, 0.0));
+        assert_eq!(parameters.occlusion_strength, 1.0);
+        assert_eq!(parameters.normal_scale, 1.0);
+    }
+}
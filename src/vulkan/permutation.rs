@@ -0,0 +1,202 @@
+use ash::version::DeviceV1_0;
+use ash::vk;
+
+use crate::error::Result;
+
+use std::collections::HashMap;
+
+use crate::shaderc;
+
+use super::dynamic_rendering;
+use super::gc;
+use super::pipeline;
+use super::preset;
+
+// What the scene shader writes to the color attachment, each mode other than
+// `Lit` is a separate permutation of shaders/shader.frag
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum DebugView {
+    Lit,
+    Albedo,
+    Normals,
+    TexCoords,
+    VertexColor,
+    // lighting applied to a white surface
+    LightingOnly,
+}
+
+impl DebugView {
+    pub const ALL: [DebugView; 6] = [
+        DebugView::Lit,
+        DebugView::Albedo,
+        DebugView::Normals,
+        DebugView::TexCoords,
+        DebugView::VertexColor,
+        DebugView::LightingOnly,
+    ];
+
+    // Define the shaders are compiled with for this mode
+    pub fn define(&self) -> Option<&'static str> {
+        match self {
+            DebugView::Lit => None,
+            DebugView::Albedo => Some("DEBUG_VIEW_ALBEDO"),
+            DebugView::Normals => Some("DEBUG_VIEW_NORMALS"),
+            DebugView::TexCoords => Some("DEBUG_VIEW_TEX_COORDS"),
+            DebugView::VertexColor => Some("DEBUG_VIEW_VERTEX_COLOR"),
+            DebugView::LightingOnly => Some("DEBUG_VIEW_LIGHTING_ONLY"),
+        }
+    }
+
+    // The mode after this one, wrapping around, eg. to cycle through them with a key
+    pub fn next(&self) -> DebugView {
+        let index = DebugView::ALL
+            .iter()
+            .position(|view| view == self)
+            .unwrap_or(0);
+        DebugView::ALL[(index + 1) % DebugView::ALL.len()]
+    }
+}
+
+impl Default for DebugView {
+    fn default() -> DebugView {
+        DebugView::Lit
+    }
+}
+
+// Vertex layout captured from the vertex type the base pipeline was created with
+#[derive(Debug, Clone)]
+pub(super) struct VertexInput {
+    bindings: Vec<vk::VertexInputBindingDescription>,
+    attributes: Vec<vk::VertexInputAttributeDescription>,
+}
+
+impl VertexInput {
+    pub(super) fn capture(vertex_data: impl pipeline::VertexData) -> VertexInput {
+        VertexInput {
+            bindings: vertex_data.get_input_binding_description(),
+            attributes: vertex_data.get_attribute_description(),
+        }
+    }
+}
+
+impl pipeline::VertexData for VertexInput {
+    fn get_input_binding_description(&self) -> Vec<vk::VertexInputBindingDescription> {
+        self.bindings.clone()
+    }
+
+    fn get_attribute_description(&self) -> Vec<vk::VertexInputAttributeDescription> {
+        self.attributes.clone()
+    }
+}
+
+// Compiles and caches the debug view permutations of the scene pipeline.
+// Variants share the layout and render pass of the base pipeline, so switching
+// only requires rebinding the pipeline in the command buffers.
+pub struct PermutationManager {
+    shaders: shaderc::ShaderSource,
+    vertex_input: VertexInput,
+    state: preset::FixedFunctionState,
+    layout: vk::PipelineLayout,
+    render_pass: vk::RenderPass,
+    rendering: Option<dynamic_rendering::RenderingFormats>,
+    pipeline_cache: vk::PipelineCache,
+
+    // owned by the pipeline detail, not destroyed here
+    base_pipeline: vk::Pipeline,
+    variants: HashMap<DebugView, vk::Pipeline>,
+    pub current: DebugView,
+}
+
+impl PermutationManager {
+    pub fn new(
+        shaders: shaderc::ShaderSource,
+        vertex_data: impl pipeline::VertexData,
+        state: preset::FixedFunctionState,
+        base: &pipeline::PipelineDetail,
+        pipeline_cache: vk::PipelineCache,
+    ) -> PermutationManager {
+        PermutationManager {
+            shaders,
+            vertex_input: VertexInput::capture(vertex_data),
+            state,
+            layout: base.layout,
+            render_pass: base.render_pass,
+            rendering: base.rendering,
+            pipeline_cache,
+            base_pipeline: base.pipeline,
+            variants: HashMap::new(),
+            current: DebugView::Lit,
+        }
+    }
+
+    // Returns the pipeline of the view, compiling it the first time it is requested
+    pub fn pipeline(&mut self, device: &ash::Device, view: DebugView) -> Result<vk::Pipeline> {
+        let define = match view.define() {
+            Some(define) => define,
+            None => return Ok(self.base_pipeline),
+        };
+
+        if let Some(&pipeline) = self.variants.get(&view) {
+            return Ok(pipeline);
+        }
+
+        tracing::info!("compiling {:?} debug view permutation", view);
+        let compiled_shaders = self.shaders.compile_with_defines(&[(define, None)])?;
+
+        let pipeline = pipeline::PipelineDetail::create_pass_pipeline_from_spirv(
+            device,
+            compiled_shaders,
+            self.vertex_input.clone(),
+            &self.state,
+            self.layout,
+            self.render_pass,
+            self.rendering,
+            self.pipeline_cache,
+        )?;
+
+        self.variants.insert(view, pipeline);
+        Ok(pipeline)
+    }
+
+    // Makes the view current, returns the pipeline draw recording should bind
+    pub fn select(&mut self, device: &ash::Device, view: DebugView) -> Result<vk::Pipeline> {
+        let pipeline = self.pipeline(device, view)?;
+        self.current = view;
+        Ok(pipeline)
+    }
+
+    // Follows a rebuild of the base pipeline, the variants are created again from its state.
+    // The current view is compiled right away, the others once they are selected.
+    pub fn rebuild(
+        &mut self,
+        device: &ash::Device,
+        base: &pipeline::PipelineDetail,
+        garbage: &mut gc::GarbageCollector,
+    ) -> Result<()> {
+        self.base_pipeline = base.pipeline;
+        self.state = base.state;
+
+        for (_, pipeline) in self.variants.drain() {
+            garbage.defer(gc::PendingDestruction::Pipeline(pipeline));
+        }
+
+        self.pipeline(device, self.current).map(|_| ())
+    }
+
+    pub fn current_pipeline(&self) -> vk::Pipeline {
+        self.variants
+            .get(&self.current)
+            .cloned()
+            .unwrap_or(self.base_pipeline)
+    }
+
+    pub fn is_compiled(&self, view: DebugView) -> bool {
+        view.define().is_none() || self.variants.contains_key(&view)
+    }
+
+    pub fn destroy(&mut self, device: &ash::Device) {
+        for (_, pipeline) in self.variants.drain() {
+            unsafe { device.destroy_pipeline(pipeline, None) };
+        }
+    }
+}
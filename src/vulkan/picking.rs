@@ -0,0 +1,448 @@
+use ash::version::DeviceV1_0;
+use ash::vk;
+
+use cgmath::Matrix4;
+
+use crate::app;
+use crate::error::{Context, Error, Result};
+use crate::shaderc;
+
+use super::buffers;
+use super::device;
+use super::image;
+use super::material;
+use super::pipeline;
+use super::preset;
+use super::registry;
+use super::typed_buffer;
+
+pub const PICKING_VERTEX_SHADER: &str = "shaders/picking.vert";
+pub const PICKING_FRAGMENT_SHADER: &str = "shaders/picking.frag";
+
+pub const ID_FORMAT: vk::Format = vk::Format::R32_UINT;
+
+// Cleared to this, draws are numbered from 1
+pub const NO_OBJECT: u32 = 0;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct PushConstants {
+    mvp: [[f32; 4]; 4],
+    id: u32,
+}
+
+// Turns the id read back from the target into the index of the draw it belongs to
+pub fn draw_index(id: u32) -> Option<usize> {
+    match id {
+        NO_OBJECT => None,
+        id => Some(id as usize - 1),
+    }
+}
+
+pub fn in_extent(x: u32, y: u32, extent: vk::Extent2D) -> bool {
+    x < extent.width && y < extent.height
+}
+
+// Renders the id of every draw into an offscreen R32_UINT target on demand and reads
+// back the one under a pixel, for editor style selection. Only runs when something is
+// picked, the frame does not pay for it otherwise.
+pub struct PickingPass {
+    device: device::Device,
+
+    render_pass: vk::RenderPass,
+    pipeline: vk::Pipeline,
+    layout: vk::PipelineLayout,
+    framebuffer: vk::Framebuffer,
+
+    id_image: image::ImageData,
+    depth: buffers::DepthBuffer,
+    // holds the picked pixel until it is read back
+    readback: buffers::BufferInfo,
+
+    extent: vk::Extent2D,
+}
+
+impl PickingPass {
+    fn create_render_pass(
+        device: &ash::Device,
+        depth_format: vk::Format,
+    ) -> Result<vk::RenderPass> {
+        let attachments = [
+            vk::AttachmentDescription {
+                format: ID_FORMAT,
+                samples: vk::SampleCountFlags::TYPE_1,
+                load_op: vk::AttachmentLoadOp::CLEAR,
+                store_op: vk::AttachmentStoreOp::STORE,
+                stencil_load_op: vk::AttachmentLoadOp::DONT_CARE,
+                stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
+                initial_layout: vk::ImageLayout::UNDEFINED,
+                // ready for the copy of the picked pixel
+                final_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                ..Default::default()
+            },
+            vk::AttachmentDescription {
+                format: depth_format,
+                samples: vk::SampleCountFlags::TYPE_1,
+                load_op: vk::AttachmentLoadOp::CLEAR,
+                store_op: vk::AttachmentStoreOp::DONT_CARE,
+                stencil_load_op: vk::AttachmentLoadOp::DONT_CARE,
+                stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
+                initial_layout: vk::ImageLayout::UNDEFINED,
+                final_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                ..Default::default()
+            },
+        ];
+
+        let color_attachment_ref = vk::AttachmentReference {
+            attachment: 0,
+            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        };
+
+        let depth_attachment_ref = vk::AttachmentReference {
+            attachment: 1,
+            layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+        };
+
+        let subpasses = [vk::SubpassDescription {
+            color_attachment_count: 1,
+            p_color_attachments: &color_attachment_ref,
+            p_depth_stencil_attachment: &depth_attachment_ref,
+            pipeline_bind_point: vk::PipelineBindPoint::GRAPHICS,
+            ..Default::default()
+        }];
+
+        // the copy waits for the ids to be written
+        let subpass_dependencies = [vk::SubpassDependency {
+            src_subpass: 0,
+            dst_subpass: vk::SUBPASS_EXTERNAL,
+            src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            dst_stage_mask: vk::PipelineStageFlags::TRANSFER,
+            src_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            dst_access_mask: vk::AccessFlags::TRANSFER_READ,
+            ..Default::default()
+        }];
+
+        let renderpass_create_info = vk::RenderPassCreateInfo {
+            attachment_count: attachments.len() as u32,
+            p_attachments: attachments.as_ptr(),
+            subpass_count: subpasses.len() as u32,
+            p_subpasses: subpasses.as_ptr(),
+            dependency_count: subpass_dependencies.len() as u32,
+            p_dependencies: subpass_dependencies.as_ptr(),
+            ..Default::default()
+        };
+
+        unsafe {
+            device
+                .create_render_pass(&renderpass_create_info, None)
+                .context("failed to create picking render pass")
+        }
+    }
+
+    fn create_pipeline_layout(device: &ash::Device) -> Result<vk::PipelineLayout> {
+        let push_constant_ranges = [vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+            offset: 0,
+            size: ::std::mem::size_of::<PushConstants>() as u32,
+        }];
+
+        let layout_info = vk::PipelineLayoutCreateInfo {
+            push_constant_range_count: push_constant_ranges.len() as u32,
+            p_push_constant_ranges: push_constant_ranges.as_ptr(),
+            ..Default::default()
+        };
+
+        unsafe {
+            device
+                .create_pipeline_layout(&layout_info, None)
+                .context("failed to create picking pipeline layout")
+        }
+    }
+
+    pub fn new(
+        instance: &ash::Instance,
+        device: &device::Device,
+        command_pool: vk::CommandPool,
+        graphics_queue: vk::Queue,
+        extent: vk::Extent2D,
+        vertex_format: app::VertexFormat,
+    ) -> Result<PickingPass> {
+        let logical_device = &device.logical_device;
+
+        let id_image = image::ImageData::new(
+            device,
+            command_pool,
+            graphics_queue,
+            image::ImagePropertyType::ColorImage(image::ImageProperties {
+                width: extent.width,
+                height: extent.height,
+                format: ID_FORMAT,
+                usage_flags: vk::ImageUsageFlags::COLOR_ATTACHMENT
+                    | vk::ImageUsageFlags::TRANSFER_SRC,
+                aspect_flag: vk::ImageAspectFlags::COLOR,
+            }),
+        )?;
+        id_image.set_name(device, "picking id image");
+
+        let depth =
+            buffers::DepthBuffer::new(instance, device, command_pool, &graphics_queue, extent)?;
+
+        let readback = buffers::BufferInfo::create(
+            device,
+            ::std::mem::size_of::<u32>() as vk::DeviceSize,
+            vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::TRANSFER_SRC,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+        readback.set_name(device, "picking readback buffer");
+
+        let render_pass = PickingPass::create_render_pass(logical_device, depth.format)?;
+        let layout = PickingPass::create_pipeline_layout(logical_device)?;
+
+        let shaders = shaderc::ShaderSource {
+            vertex_shader_file: PICKING_VERTEX_SHADER.to_string(),
+            fragment_shader_file: PICKING_FRAGMENT_SHADER.to_string(),
+        };
+
+        // the scene's vertices, only their positions are read
+        let pipeline = pipeline::PipelineDetail::create_pipeline(
+            logical_device,
+            shaders,
+            vertex_format,
+            &preset::FixedFunctionState::from_preset(preset::Preset::Opaque3d),
+            layout,
+            render_pass,
+            vk::PipelineCache::null(),
+        )?;
+
+        let attachments = [id_image.image_view, depth.image.image_view];
+        let framebuffer_info = vk::FramebufferCreateInfo {
+            render_pass,
+            attachment_count: attachments.len() as u32,
+            p_attachments: attachments.as_ptr(),
+            width: extent.width,
+            height: extent.height,
+            layers: 1,
+            ..Default::default()
+        };
+
+        let framebuffer = unsafe {
+            logical_device
+                .create_framebuffer(&framebuffer_info, None)
+                .context("failed to create picking framebuffer")
+        }?;
+
+        device.track(registry::ResourceKind::RenderPass, render_pass);
+        device.track(registry::ResourceKind::Pipeline, pipeline);
+        device.track(registry::ResourceKind::PipelineLayout, layout);
+        device.track(registry::ResourceKind::Framebuffer, framebuffer);
+
+        Ok(PickingPass {
+            device: device.clone(),
+            render_pass,
+            pipeline,
+            layout,
+            framebuffer,
+            id_image,
+            depth,
+            readback,
+            extent,
+        })
+    }
+
+    pub fn extent(&self) -> vk::Extent2D {
+        self.extent
+    }
+
+    // Draws the ids of the draws seen with mvp and returns the id at the pixel, NO_OBJECT
+    // where nothing was drawn. Waits for the gpu, so it is meant for clicks rather than
+    // every frame. Pixels outside of the target are NO_OBJECT.
+    pub fn pick(
+        &self,
+        command_pool: vk::CommandPool,
+        queue: vk::Queue,
+        vertex_buffer: vk::Buffer,
+        index_buffer: &typed_buffer::IndexBuffer<u32>,
+        draws: &[material::Draw],
+        mvp: Matrix4<f32>,
+        (x, y): (u32, u32),
+    ) -> Result<u32> {
+        if !in_extent(x, y, self.extent) {
+            return Ok(NO_OBJECT);
+        }
+
+        let logical_device = &self.device.logical_device;
+
+        let clear_values = [
+            vk::ClearValue {
+                color: vk::ClearColorValue {
+                    uint32: [NO_OBJECT, 0, 0, 0],
+                },
+            },
+            vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue {
+                    depth: 1.0,
+                    stencil: 0,
+                },
+            },
+        ];
+
+        let render_area = vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent: self.extent,
+        };
+
+        let render_pass_begin_info = vk::RenderPassBeginInfo {
+            render_pass: self.render_pass,
+            framebuffer: self.framebuffer,
+            render_area,
+            clear_value_count: clear_values.len() as u32,
+            p_clear_values: clear_values.as_ptr(),
+            ..Default::default()
+        };
+
+        let viewports = [vk::Viewport {
+            x: 0.0,
+            y: 0.0,
+            width: self.extent.width as f32,
+            height: self.extent.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        }];
+
+        let copy_regions = [vk::BufferImageCopy {
+            image_subresource: vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: 0,
+                base_array_layer: 0,
+                layer_count: 1,
+            },
+            image_offset: vk::Offset3D {
+                x: x as i32,
+                y: y as i32,
+                z: 0,
+            },
+            image_extent: vk::Extent3D {
+                width: 1,
+                height: 1,
+                depth: 1,
+            },
+            ..Default::default()
+        }];
+
+        buffers::CommandBuffer::record_and_submit_single_command(
+            logical_device,
+            command_pool,
+            queue,
+            |command_buffer| unsafe {
+                logical_device.cmd_begin_render_pass(
+                    command_buffer,
+                    &render_pass_begin_info,
+                    vk::SubpassContents::INLINE,
+                );
+
+                logical_device.cmd_bind_pipeline(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    self.pipeline,
+                );
+                logical_device.cmd_set_viewport(command_buffer, 0, &viewports);
+                logical_device.cmd_set_scissor(command_buffer, 0, &[render_area]);
+
+                logical_device.cmd_bind_vertex_buffers(command_buffer, 0, &[vertex_buffer], &[0]);
+                logical_device.cmd_bind_index_buffer(
+                    command_buffer,
+                    index_buffer.buffer(),
+                    0,
+                    index_buffer.index_type(),
+                );
+
+                for (index, draw) in draws.iter().enumerate() {
+                    let push_constants = PushConstants {
+                        mvp: mvp.into(),
+                        id: index as u32 + 1,
+                    };
+
+                    let push_constant_bytes = ::std::slice::from_raw_parts(
+                        &push_constants as *const PushConstants as *const u8,
+                        ::std::mem::size_of::<PushConstants>(),
+                    );
+
+                    logical_device.cmd_push_constants(
+                        command_buffer,
+                        self.layout,
+                        vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                        0,
+                        push_constant_bytes,
+                    );
+
+                    logical_device.cmd_draw_indexed(
+                        command_buffer,
+                        draw.index_count,
+                        1,
+                        draw.first_index,
+                        draw.vertex_offset,
+                        0,
+                    );
+                }
+
+                logical_device.cmd_end_render_pass(command_buffer);
+
+                logical_device.cmd_copy_image_to_buffer(
+                    command_buffer,
+                    self.id_image.image,
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    self.readback.buffer,
+                    &copy_regions,
+                );
+            },
+        )?;
+
+        self.readback
+            .read_back::<u32>(&self.device, command_pool, queue, 0, 1)?
+            .first()
+            .cloned()
+            .ok_or_else(|| Error::msg("picking readback returned no id"))
+    }
+
+    pub fn destroy(&mut self) {
+        self.device.untrack(self.pipeline);
+        self.device.untrack(self.layout);
+        self.device.untrack(self.render_pass);
+        self.device.untrack(self.framebuffer);
+
+        let logical_device = &self.device.logical_device;
+        unsafe {
+            logical_device.destroy_framebuffer(self.framebuffer, None);
+            logical_device.destroy_pipeline(self.pipeline, None);
+            logical_device.destroy_pipeline_layout(self.layout, None);
+            logical_device.destroy_render_pass(self.render_pass, None);
+        }
+
+        self.readback.destroy(&self.device);
+        self.depth.image.destroy(&self.device);
+        self.id_image.destroy(&self.device);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids_map_to_draws() {
+        assert_eq!(draw_index(NO_OBJECT), None);
+        assert_eq!(draw_index(1), Some(0));
+        assert_eq!(draw_index(7), Some(6));
+
+        let extent = vk::Extent2D {
+            width: 800,
+            height: 600,
+        };
+        assert!(in_extent(799, 599, extent));
+        assert!(!in_extent(800, 10, extent));
+        assert!(!in_extent(10, 600, extent));
+
+        // a mat4 followed by the id, within the 128 bytes every device supports
+        assert_eq!(::std::mem::size_of::<PushConstants>(), 68);
+    }
+}
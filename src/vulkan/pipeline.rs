@@ -1,21 +1,45 @@
 use std::ffi::CString;
+use std::os::raw::c_void;
 
 use ash::version::DeviceV1_0;
 use ash::vk;
 
-use anyhow::{Context, Result};
+use crate::error::{Context, Error, Result};
 
+use crate::foreign;
 use crate::shaderc;
 
 use super::buffers;
+use super::descriptor;
 use super::device;
+use super::dynamic_rendering;
+use super::gc;
+use super::lighting;
+use super::permutation;
+use super::preset;
+use super::registry;
 use super::swapchain;
+use super::trace;
 
 pub struct PipelineDetail {
+    // the FILL variant
     pub pipeline: vk::Pipeline,
+    // the LINE variant, None when the device does not support non solid fill modes
+    pub wireframe: Option<vk::Pipeline>,
+    pub polygon_mode: vk::PolygonMode,
     pub layout: vk::PipelineLayout,
     pub descriptor_set_layout: vk::DescriptorSetLayout,
+    // what the descriptor set layout was created from
+    pub descriptor_bindings: Vec<descriptor::Binding>,
+    // null when the pipeline is drawn with dynamic rendering into these formats instead
     pub render_pass: vk::RenderPass,
+    pub rendering: Option<dynamic_rendering::RenderingFormats>,
+    // state the current variants were created with, see rebuild
+    pub state: preset::FixedFunctionState,
+
+    shaders: shaderc::ShaderSource,
+    vertex_input: permutation::VertexInput,
+    pipeline_cache: vk::PipelineCache,
 }
 
 pub trait VertexData<T = Self> {
@@ -23,8 +47,109 @@ pub trait VertexData<T = Self> {
     fn get_attribute_description(&self) -> Vec<vk::VertexInputAttributeDescription>;
 }
 
+// Lets several pipelines be created from the same vertex data
+impl<'a, V: VertexData> VertexData for &'a V {
+    fn get_input_binding_description(&self) -> Vec<vk::VertexInputBindingDescription> {
+        (*self).get_input_binding_description()
+    }
+
+    fn get_attribute_description(&self) -> Vec<vk::VertexInputAttributeDescription> {
+        (*self).get_attribute_description()
+    }
+}
+
+// Color attachment the scene is rendered into
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ColorTarget {
+    pub format: vk::Format,
+    // layout the image is left in at the end of the render pass
+    pub final_layout: vk::ImageLayout,
+    // drawn without a render pass when the device supports it, see vulkan::dynamic_rendering
+    pub dynamic_rendering: bool,
+}
+
+impl ColorTarget {
+    pub fn swapchain(format: vk::Format) -> ColorTarget {
+        ColorTarget {
+            format,
+            final_layout: vk::ImageLayout::PRESENT_SRC_KHR,
+            dynamic_rendering: false,
+        }
+    }
+
+    // Sampled by a later pass, eg. the post process chain
+    pub fn offscreen(format: vk::Format) -> ColorTarget {
+        ColorTarget {
+            format,
+            final_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            dynamic_rendering: false,
+        }
+    }
+
+    pub fn with_dynamic_rendering(self) -> ColorTarget {
+        ColorTarget {
+            dynamic_rendering: true,
+            ..self
+        }
+    }
+}
+
+// Attachments one subpass uses, by their index in the render pass
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SubpassAttachments {
+    pub color: Vec<u32>,
+    // written by an earlier subpass, see PipelineDetail::input_dependency
+    pub input: Vec<u32>,
+    pub depth: Option<u32>,
+}
+
+impl SubpassAttachments {
+    pub fn validate(&self, attachment_count: usize) -> Result<()> {
+        let mut used = self
+            .color
+            .iter()
+            .chain(self.input.iter())
+            .chain(self.depth.iter());
+
+        match used.find(|&&index| index as usize >= attachment_count) {
+            Some(index) => Err(Error::OutOfRange(format!(
+                "attachment {} of a subpass is past the {} attachments of the render pass",
+                index, attachment_count
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    fn references(
+        &self,
+    ) -> (
+        Vec<vk::AttachmentReference>,
+        Vec<vk::AttachmentReference>,
+        Option<vk::AttachmentReference>,
+    ) {
+        let reference = |layout: vk::ImageLayout| {
+            move |&attachment: &u32| vk::AttachmentReference { attachment, layout }
+        };
+
+        (
+            self.color
+                .iter()
+                .map(reference(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL))
+                .collect(),
+            self.input
+                .iter()
+                .map(reference(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL))
+                .collect(),
+            self.depth
+                .iter()
+                .map(reference(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL))
+                .next(),
+        )
+    }
+}
+
 impl PipelineDetail {
-    fn create_shader_module(device: &ash::Device, code: Vec<u8>) -> Result<vk::ShaderModule> {
+    pub fn create_shader_module(device: &ash::Device, code: Vec<u8>) -> Result<vk::ShaderModule> {
         let shader_module_info = vk::ShaderModuleCreateInfo {
             code_size: code.len(),
             p_code: code.as_ptr() as *const u32,
@@ -38,122 +163,554 @@ impl PipelineDetail {
         }
     }
 
-    fn create_render_pass(
+    pub(super) fn create_render_pass(
         instance: &ash::Instance,
         device: &device::Device,
-        surface_format: vk::Format,
+        target: ColorTarget,
     ) -> Result<vk::RenderPass> {
         let color_attachment = vk::AttachmentDescription {
-            format: surface_format,
+            format: target.format,
             samples: vk::SampleCountFlags::TYPE_1,
             load_op: vk::AttachmentLoadOp::CLEAR,
             store_op: vk::AttachmentStoreOp::STORE,
             stencil_load_op: vk::AttachmentLoadOp::CLEAR,
             stencil_store_op: vk::AttachmentStoreOp::STORE,
             initial_layout: vk::ImageLayout::UNDEFINED,
-            final_layout: vk::ImageLayout::PRESENT_SRC_KHR,
+            final_layout: target.final_layout,
             ..Default::default()
         };
 
-        let color_attachment_ref = vk::AttachmentReference {
-            attachment: 0,
-            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-        };
-
         let (depth_buffer_attachment, depth_buffer_attachment_ref) =
             buffers::DepthBuffer::get_attachment_info(instance, device.physical_device)?;
 
-        let subpasses = [vk::SubpassDescription {
-            color_attachment_count: 1,
-            p_color_attachments: &color_attachment_ref,
-            p_depth_stencil_attachment: &depth_buffer_attachment_ref,
-            pipeline_bind_point: vk::PipelineBindPoint::GRAPHICS,
-            ..Default::default()
+        let subpasses = [SubpassAttachments {
+            color: vec![0],
+            input: vec![],
+            depth: Some(depth_buffer_attachment_ref.attachment),
         }];
 
-        let render_pass_attachments = [color_attachment, depth_buffer_attachment];
+        PipelineDetail::create_render_pass_with_subpasses(
+            &device.logical_device,
+            &[color_attachment, depth_buffer_attachment],
+            &subpasses,
+            &[PipelineDetail::external_dependency()],
+        )
+    }
 
-        let subpass_dependencies = [vk::SubpassDependency {
+    // Makes the previous use of the color attachments finish before the pass writes them
+    pub fn external_dependency() -> vk::SubpassDependency {
+        vk::SubpassDependency {
             src_subpass: vk::SUBPASS_EXTERNAL,
             src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
             dst_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
             dst_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_READ
                 | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
             ..Default::default()
-        }];
+        }
+    }
 
-        let renderpass_create_info = vk::RenderPassCreateInfo {
-            attachment_count: render_pass_attachments.len() as u32,
-            p_attachments: render_pass_attachments.as_ptr(),
-            subpass_count: subpasses.len() as u32,
-            p_subpasses: subpasses.as_ptr(),
-            dependency_count: subpass_dependencies.len() as u32,
-            p_dependencies: subpass_dependencies.as_ptr(),
+    // Makes the color and depth writes of src visible to the input attachment reads of
+    // dst. Both only touch the pixel they shade, so it is by region, which lets tiled
+    // gpus keep the attachments in tile memory between the subpasses.
+    pub fn input_dependency(src_subpass: u32, dst_subpass: u32) -> vk::SubpassDependency {
+        vk::SubpassDependency {
+            src_subpass,
+            dst_subpass,
+            src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            dst_stage_mask: vk::PipelineStageFlags::FRAGMENT_SHADER,
+            src_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            dst_access_mask: vk::AccessFlags::INPUT_ATTACHMENT_READ,
+            dependency_flags: vk::DependencyFlags::BY_REGION,
+        }
+    }
+
+    // Render pass of several subpasses over the attachments. Color attachments are
+    // written in COLOR_ATTACHMENT_OPTIMAL, input attachments are read in
+    // SHADER_READ_ONLY_OPTIMAL and the depth attachment is used in
+    // DEPTH_STENCIL_ATTACHMENT_OPTIMAL.
+    pub fn create_render_pass_with_subpasses(
+        device: &ash::Device,
+        attachments: &[vk::AttachmentDescription],
+        subpasses: &[SubpassAttachments],
+        dependencies: &[vk::SubpassDependency],
+    ) -> Result<vk::RenderPass> {
+        PipelineDetail::create_render_pass_with_next(
+            device,
+            attachments,
+            subpasses,
+            dependencies,
+            ::std::ptr::null(),
+        )
+    }
+
+    // Like create_render_pass_with_subpasses with every subpass drawing once to each
+    // layer of the view mask, the layer is gl_ViewIndex in the shaders. The views are
+    // rendered together, so they are all marked as correlated.
+    pub fn create_multiview_render_pass(
+        device: &ash::Device,
+        attachments: &[vk::AttachmentDescription],
+        subpasses: &[SubpassAttachments],
+        dependencies: &[vk::SubpassDependency],
+        view_mask: u32,
+    ) -> Result<vk::RenderPass> {
+        let view_masks = vec![view_mask; subpasses.len()];
+        let correlation_masks = [view_mask];
+
+        let multiview_info = vk::RenderPassMultiviewCreateInfo {
+            subpass_count: view_masks.len() as u32,
+            p_view_masks: view_masks.as_ptr(),
+            correlation_mask_count: correlation_masks.len() as u32,
+            p_correlation_masks: correlation_masks.as_ptr(),
             ..Default::default()
         };
 
-        unsafe {
-            device
-                .logical_device
-                .create_render_pass(&renderpass_create_info, None)
-                .context("failed to create render pass!")
-        }
+        PipelineDetail::create_render_pass_with_next(
+            device,
+            attachments,
+            subpasses,
+            dependencies,
+            &multiview_info as *const vk::RenderPassMultiviewCreateInfo as *const c_void,
+        )
     }
 
-    fn create_descriptor_set_layout(device: &ash::Device) -> Result<vk::DescriptorSetLayout> {
-        let binding = [
-            vk::DescriptorSetLayoutBinding {
-                //transform uniform
-                binding: 0,
-                descriptor_count: 1,
-                descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
-                stage_flags: vk::ShaderStageFlags::VERTEX,
-                ..Default::default()
-            },
-            vk::DescriptorSetLayoutBinding {
-                // combined image sampler uniform (used for texture mapping)
-                binding: 1,
-                descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
-                descriptor_count: 1,
-                stage_flags: vk::ShaderStageFlags::FRAGMENT,
+    fn create_render_pass_with_next(
+        device: &ash::Device,
+        attachments: &[vk::AttachmentDescription],
+        subpasses: &[SubpassAttachments],
+        dependencies: &[vk::SubpassDependency],
+        p_next: *const c_void,
+    ) -> Result<vk::RenderPass> {
+        for subpass in subpasses.iter() {
+            subpass.validate(attachments.len())?;
+        }
+
+        // the descriptions point into these, they have to outlive the create call
+        let references: Vec<_> = subpasses
+            .iter()
+            .map(SubpassAttachments::references)
+            .collect();
+
+        let descriptions: Vec<vk::SubpassDescription> = references
+            .iter()
+            .map(|(color, input, depth)| vk::SubpassDescription {
+                color_attachment_count: color.len() as u32,
+                p_color_attachments: color.as_ptr(),
+                input_attachment_count: input.len() as u32,
+                p_input_attachments: input.as_ptr(),
+                p_depth_stencil_attachment: depth
+                    .as_ref()
+                    .map_or(std::ptr::null(), |depth| depth as *const _),
+                pipeline_bind_point: vk::PipelineBindPoint::GRAPHICS,
                 ..Default::default()
-            },
-        ];
+            })
+            .collect();
 
-        let layout_info = vk::DescriptorSetLayoutCreateInfo {
-            binding_count: binding.len() as u32,
-            p_bindings: binding.as_ptr(),
+        let renderpass_create_info = vk::RenderPassCreateInfo {
+            p_next,
+            attachment_count: attachments.len() as u32,
+            p_attachments: attachments.as_ptr(),
+            subpass_count: descriptions.len() as u32,
+            p_subpasses: descriptions.as_ptr(),
+            dependency_count: dependencies.len() as u32,
+            p_dependencies: dependencies.as_ptr(),
             ..Default::default()
         };
 
         unsafe {
             device
-                .create_descriptor_set_layout(&layout_info, None)
-                .context("failed to create descriptor set layout")
+                .create_render_pass(&renderpass_create_info, None)
+                .context("failed to create render pass!")
         }
     }
 
+    // Bindings every scene shader can use, extra ones are added after them
+    pub fn scene_bindings() -> Vec<descriptor::Binding> {
+        vec![
+            // transform uniform, the image's region of buffers::FrameUniforms::ring
+            descriptor::Binding::uniform_buffer_dynamic(0, vk::ShaderStageFlags::VERTEX),
+            // combined image sampler uniform (used for texture mapping)
+            descriptor::Binding::combined_image_sampler(1, vk::ShaderStageFlags::FRAGMENT),
+            // lights used for shading the scene
+            descriptor::Binding::uniform_buffer(
+                lighting::LIGHTS_BINDING,
+                vk::ShaderStageFlags::FRAGMENT,
+            ),
+        ]
+    }
+
     pub fn create_graphics_pipeline(
         instance: &ash::Instance,
         device: &device::Device,
         swapchain: &swapchain::SwapchainDetails,
         shaders: shaderc::ShaderSource,
         vertex_data: impl VertexData,
+        state: &preset::FixedFunctionState,
     ) -> Result<PipelineDetail> {
-        let extent = swapchain.extent;
-        let surface_format = swapchain.format.format;
+        PipelineDetail::create_graphics_pipeline_with_cache(
+            instance,
+            device,
+            ColorTarget::swapchain(swapchain.format.format),
+            shaders,
+            vertex_data,
+            state,
+            vk::PipelineCache::null(),
+        )
+    }
 
-        println!("compiling shaders..");
+    pub fn create_graphics_pipeline_with_cache(
+        instance: &ash::Instance,
+        device: &device::Device,
+        target: ColorTarget,
+        shaders: shaderc::ShaderSource,
+        vertex_data: impl VertexData,
+        state: &preset::FixedFunctionState,
+        pipeline_cache: vk::PipelineCache,
+    ) -> Result<PipelineDetail> {
+        PipelineDetail::create_graphics_pipeline_with_bindings(
+            instance,
+            device,
+            target,
+            shaders,
+            vertex_data,
+            state,
+            pipeline_cache,
+            &PipelineDetail::scene_bindings(),
+        )
+    }
+
+    // Like create_graphics_pipeline_with_cache with the scene set laid out from the
+    // bindings, eg. the scene bindings followed by storage buffers the shaders read
+    pub fn create_graphics_pipeline_with_bindings(
+        instance: &ash::Instance,
+        device: &device::Device,
+        target: ColorTarget,
+        shaders: shaderc::ShaderSource,
+        vertex_data: impl VertexData,
+        state: &preset::FixedFunctionState,
+        pipeline_cache: vk::PipelineCache,
+        bindings: &[descriptor::Binding],
+    ) -> Result<PipelineDetail> {
+        PipelineDetail::create_graphics_pipeline_with_sets(
+            instance,
+            device,
+            target,
+            shaders,
+            vertex_data,
+            state,
+            pipeline_cache,
+            bindings,
+            &[],
+        )
+    }
+
+    // The scene set is followed by the extra set layouts, eg. the material set.
+    // They are owned by the caller and are not destroyed with the pipeline.
+    pub fn create_graphics_pipeline_with_sets(
+        instance: &ash::Instance,
+        device: &device::Device,
+        target: ColorTarget,
+        shaders: shaderc::ShaderSource,
+        vertex_data: impl VertexData,
+        state: &preset::FixedFunctionState,
+        pipeline_cache: vk::PipelineCache,
+        bindings: &[descriptor::Binding],
+        extra_set_layouts: &[vk::DescriptorSetLayout],
+    ) -> Result<PipelineDetail> {
+        PipelineDetail::create_graphics_pipeline_with_push_constants(
+            instance,
+            device,
+            target,
+            shaders,
+            vertex_data,
+            state,
+            pipeline_cache,
+            bindings,
+            extra_set_layouts,
+            &[],
+        )
+    }
+
+    // Like create_graphics_pipeline_with_sets with push constants in the layout,
+    // eg. the material indices of bindless textures
+    pub fn create_graphics_pipeline_with_push_constants(
+        instance: &ash::Instance,
+        device: &device::Device,
+        target: ColorTarget,
+        shaders: shaderc::ShaderSource,
+        vertex_data: impl VertexData,
+        state: &preset::FixedFunctionState,
+        pipeline_cache: vk::PipelineCache,
+        bindings: &[descriptor::Binding],
+        extra_set_layouts: &[vk::DescriptorSetLayout],
+        push_constant_ranges: &[vk::PushConstantRange],
+    ) -> Result<PipelineDetail> {
+        // both variants are created from the same spirv, so shaders are compiled once
         let compiled_shaders = shaders.compile()?;
-        println!("shaders compiled");
 
-        let vert_shader_module =
-            PipelineDetail::create_shader_module(&device.logical_device, compiled_shaders.vertex)?;
-        let frag_shader_module = PipelineDetail::create_shader_module(
+        // the scene set and vertex data have to provide what the shaders read,
+        // the extra sets are checked by whoever owns them
+        let reflection = compiled_shaders.reflect()?;
+        reflection.validate_set(0, bindings)?;
+        reflection.validate_vertex_input(&vertex_data.get_attribute_description())?;
+        reflection.validate_push_constants(push_constant_ranges)?;
+
+        // portability implementations like MoltenVK cannot draw with some of the state
+        if let Some(portability) = device.portability {
+            portability.check_state(state)?;
+            portability.check_vertex_input(&vertex_data.get_input_binding_description())?;
+        }
+
+        let descriptor_set_layout: vk::DescriptorSetLayout =
+            descriptor::create_set_layout(&device.logical_device, bindings)?;
+        let mut set_layouts = vec![descriptor_set_layout];
+        set_layouts.extend_from_slice(extra_set_layouts);
+
+        let pipeline_layout_info = vk::PipelineLayoutCreateInfo {
+            set_layout_count: set_layouts.len() as u32,
+            p_set_layouts: set_layouts.as_ptr(),
+            push_constant_range_count: push_constant_ranges.len() as u32,
+            p_push_constant_ranges: push_constant_ranges.as_ptr(),
+            ..Default::default()
+        };
+
+        let pipeline_layout = unsafe {
+            device
+                .logical_device
+                .create_pipeline_layout(&pipeline_layout_info, None)
+                .context("failed to create pipeline layout")
+        }?;
+
+        let (render_pass, rendering) = if target.dynamic_rendering && device.dynamic_rendering {
+            let depth = *buffers::DepthBuffer::find_depth_format(instance, device.physical_device)?;
+            let formats = dynamic_rendering::RenderingFormats {
+                color: target.format,
+                depth,
+            };
+
+            (vk::RenderPass::null(), Some(formats))
+        } else {
+            (
+                PipelineDetail::create_render_pass(instance, &device, target)?,
+                None,
+            )
+        };
+
+        let pipeline = PipelineDetail::create_pass_pipeline_from_spirv(
             &device.logical_device,
-            compiled_shaders.fragment,
+            compiled_shaders.clone(),
+            &vertex_data,
+            &state.with_polygon_mode(vk::PolygonMode::FILL),
+            pipeline_layout,
+            render_pass,
+            rendering,
+            pipeline_cache,
         )?;
 
+        let wireframe = if device.features.fill_mode_non_solid == vk::TRUE {
+            Some(PipelineDetail::create_pass_pipeline_from_spirv(
+                &device.logical_device,
+                compiled_shaders,
+                &vertex_data,
+                &state.with_polygon_mode(vk::PolygonMode::LINE),
+                pipeline_layout,
+                render_pass,
+                rendering,
+                pipeline_cache,
+            )?)
+        } else {
+            None
+        };
+
+        device.track(registry::ResourceKind::Pipeline, pipeline);
+        if let Some(wireframe) = wireframe {
+            device.track(registry::ResourceKind::Pipeline, wireframe);
+        }
+        device.track(registry::ResourceKind::PipelineLayout, pipeline_layout);
+        device.track(
+            registry::ResourceKind::DescriptorSetLayout,
+            descriptor_set_layout,
+        );
+        if rendering.is_none() {
+            device.track(registry::ResourceKind::RenderPass, render_pass);
+        }
+
+        Ok(PipelineDetail {
+            pipeline,
+            wireframe,
+            polygon_mode: vk::PolygonMode::FILL,
+            layout: pipeline_layout,
+            descriptor_set_layout,
+            descriptor_bindings: bindings.to_vec(),
+            render_pass,
+            rendering,
+            state: state.with_polygon_mode(vk::PolygonMode::FILL),
+            shaders,
+            vertex_input: permutation::VertexInput::capture(&vertex_data),
+            pipeline_cache,
+        })
+    }
+
+    // A pipeline made of existing handles, without a render pass or set layout of its
+    // own, eg. for recording against a headless::HeadlessDevice
+    pub fn from_handles(
+        shaders: shaderc::ShaderSource,
+        vertex_data: impl VertexData,
+        pipeline: vk::Pipeline,
+        layout: vk::PipelineLayout,
+    ) -> PipelineDetail {
+        PipelineDetail {
+            pipeline,
+            wireframe: None,
+            polygon_mode: vk::PolygonMode::FILL,
+            layout,
+            descriptor_set_layout: vk::DescriptorSetLayout::null(),
+            descriptor_bindings: vec![],
+            render_pass: vk::RenderPass::null(),
+            rendering: None,
+            state: preset::FixedFunctionState::from_preset(preset::Preset::Opaque3d),
+            shaders,
+            vertex_input: permutation::VertexInput::capture(vertex_data),
+            pipeline_cache: vk::PipelineCache::null(),
+        }
+    }
+
+    // Creates a pipeline for an existing layout and render pass, shared by all the passes
+    pub fn create_pipeline(
+        device: &ash::Device,
+        shaders: shaderc::ShaderSource,
+        vertex_data: impl VertexData,
+        state: &preset::FixedFunctionState,
+        pipeline_layout: vk::PipelineLayout,
+        render_pass: vk::RenderPass,
+        pipeline_cache: vk::PipelineCache,
+    ) -> Result<vk::Pipeline> {
+        tracing::debug!("compiling shaders..");
+        let compiled_shaders = shaders.compile()?;
+        tracing::debug!("shaders compiled");
+
+        PipelineDetail::create_pipeline_from_spirv(
+            device,
+            compiled_shaders,
+            vertex_data,
+            state,
+            pipeline_layout,
+            render_pass,
+            pipeline_cache,
+        )
+    }
+
+    pub fn create_pipeline_from_spirv(
+        device: &ash::Device,
+        compiled_shaders: shaderc::CompiledShader,
+        vertex_data: impl VertexData,
+        state: &preset::FixedFunctionState,
+        pipeline_layout: vk::PipelineLayout,
+        render_pass: vk::RenderPass,
+        pipeline_cache: vk::PipelineCache,
+    ) -> Result<vk::Pipeline> {
+        PipelineDetail::create_subpass_pipeline_from_spirv(
+            device,
+            compiled_shaders,
+            vertex_data,
+            state,
+            pipeline_layout,
+            render_pass,
+            0,
+            1,
+            pipeline_cache,
+        )
+    }
+
+    // Like create_pipeline_from_spirv, drawn with dynamic rendering into the formats
+    // instead of the render pass when they are given
+    pub fn create_pass_pipeline_from_spirv(
+        device: &ash::Device,
+        compiled_shaders: shaderc::CompiledShader,
+        vertex_data: impl VertexData,
+        state: &preset::FixedFunctionState,
+        pipeline_layout: vk::PipelineLayout,
+        render_pass: vk::RenderPass,
+        rendering: Option<dynamic_rendering::RenderingFormats>,
+        pipeline_cache: vk::PipelineCache,
+    ) -> Result<vk::Pipeline> {
+        let formats = match rendering {
+            Some(formats) => formats,
+            None => {
+                return PipelineDetail::create_pipeline_from_spirv(
+                    device,
+                    compiled_shaders,
+                    vertex_data,
+                    state,
+                    pipeline_layout,
+                    render_pass,
+                    pipeline_cache,
+                )
+            }
+        };
+
+        let rendering_info = formats.pipeline_info();
+        PipelineDetail::create_pipeline_with_next(
+            device,
+            compiled_shaders,
+            vertex_data,
+            state,
+            pipeline_layout,
+            vk::RenderPass::null(),
+            0,
+            1,
+            &rendering_info as *const dynamic_rendering::PipelineRenderingCreateInfo
+                as *const c_void,
+            pipeline_cache,
+        )
+    }
+
+    // For any subpass of the render pass, every one of its color attachments is blended
+    // with the state's blend mode
+    pub fn create_subpass_pipeline_from_spirv(
+        device: &ash::Device,
+        compiled_shaders: shaderc::CompiledShader,
+        vertex_data: impl VertexData,
+        state: &preset::FixedFunctionState,
+        pipeline_layout: vk::PipelineLayout,
+        render_pass: vk::RenderPass,
+        subpass: u32,
+        color_attachment_count: u32,
+        pipeline_cache: vk::PipelineCache,
+    ) -> Result<vk::Pipeline> {
+        PipelineDetail::create_pipeline_with_next(
+            device,
+            compiled_shaders,
+            vertex_data,
+            state,
+            pipeline_layout,
+            render_pass,
+            subpass,
+            color_attachment_count,
+            ::std::ptr::null(),
+            pipeline_cache,
+        )
+    }
+
+    // p_next of the create info, eg. what replaces the render pass with dynamic rendering
+    fn create_pipeline_with_next(
+        device: &ash::Device,
+        compiled_shaders: shaderc::CompiledShader,
+        vertex_data: impl VertexData,
+        state: &preset::FixedFunctionState,
+        pipeline_layout: vk::PipelineLayout,
+        render_pass: vk::RenderPass,
+        subpass: u32,
+        color_attachment_count: u32,
+        p_next: *const c_void,
+        pipeline_cache: vk::PipelineCache,
+    ) -> Result<vk::Pipeline> {
+        let vert_shader_module =
+            PipelineDetail::create_shader_module(device, compiled_shaders.vertex)?;
+        let frag_shader_module =
+            PipelineDetail::create_shader_module(device, compiled_shaders.fragment)?;
+
         let main_function_name = CString::new("main").context("invalid fn name")?;
 
         let shader_stages = [
@@ -174,9 +731,10 @@ impl PipelineDetail {
         // ..enter
         let binding_description = vertex_data.get_input_binding_description();
         let attribute_description = vertex_data.get_attribute_description();
-        println!(
+        tracing::debug!(
             "descriptions {:?} {:?}",
-            binding_description, attribute_description
+            binding_description,
+            attribute_description
         );
 
         let vertex_input_info = vk::PipelineVertexInputStateCreateInfo {
@@ -187,49 +745,28 @@ impl PipelineDetail {
             ..Default::default()
         };
 
-        let vertex_input_assembly_state_info = vk::PipelineInputAssemblyStateCreateInfo {
-            primitive_restart_enable: vk::FALSE,
-            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
-            ..Default::default()
-        };
-
-        let viewport = [vk::Viewport {
-            width: extent.width as f32,
-            height: extent.height as f32,
-            max_depth: 1.0,
-            ..Default::default()
-        }];
-
-        let scissors = [vk::Rect2D {
-            offset: vk::Offset2D { x: 0, y: 0 },
-            extent: extent,
-        }];
+        let vertex_input_assembly_state_info = state.input_assembly_state();
 
+        // viewport and scissor are set while recording command buffers, so a resize
+        // only needs the command buffers to be re-recorded
         let viewport_state = vk::PipelineViewportStateCreateInfo {
-            viewport_count: viewport.len() as u32,
-            p_viewports: viewport.as_ptr(),
-            scissor_count: scissors.len() as u32,
-            p_scissors: scissors.as_ptr(),
+            viewport_count: 1,
+            scissor_count: 1,
             ..Default::default()
         };
 
-        let rasterizer = vk::PipelineRasterizationStateCreateInfo {
-            depth_clamp_enable: vk::FALSE,
-            rasterizer_discard_enable: vk::FALSE,
-            polygon_mode: vk::PolygonMode::FILL,
-            line_width: 1.0,
-            cull_mode: vk::CullModeFlags::BACK,
-            front_face: vk::FrontFace::COUNTER_CLOCKWISE,
-            depth_bias_enable: vk::FALSE,
-            ..Default::default()
-        };
+        let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
 
-        let multisampling = vk::PipelineMultisampleStateCreateInfo {
-            sample_shading_enable: vk::FALSE,
-            rasterization_samples: vk::SampleCountFlags::TYPE_1,
+        let dynamic_state = vk::PipelineDynamicStateCreateInfo {
+            dynamic_state_count: dynamic_states.len() as u32,
+            p_dynamic_states: dynamic_states.as_ptr(),
             ..Default::default()
         };
 
+        let rasterizer = state.rasterization_state();
+
+        let multisampling = state.multisample_state();
+
         let stencil_state = vk::StencilOpState {
             fail_op: vk::StencilOp::KEEP,
             pass_op: vk::StencilOp::KEEP,
@@ -244,9 +781,9 @@ impl PipelineDetail {
             s_type: vk::StructureType::PIPELINE_DEPTH_STENCIL_STATE_CREATE_INFO,
             p_next: ::std::ptr::null(),
             flags: vk::PipelineDepthStencilStateCreateFlags::empty(),
-            depth_test_enable: vk::TRUE,
-            depth_write_enable: vk::TRUE,
-            depth_compare_op: vk::CompareOp::LESS,
+            depth_test_enable: foreign::to_vk_bool(state.depth_test),
+            depth_write_enable: foreign::to_vk_bool(state.depth_write),
+            depth_compare_op: state.depth_compare_op,
             depth_bounds_test_enable: vk::TRUE,
             stencil_test_enable: vk::TRUE,
             front: stencil_state,
@@ -255,44 +792,20 @@ impl PipelineDetail {
             min_depth_bounds: 0.0,
         };
 
-        let color_blend_attachment_states = [vk::PipelineColorBlendAttachmentState {
-            blend_enable: vk::FALSE,
-            color_write_mask: vk::ColorComponentFlags::all(),
-            src_color_blend_factor: vk::BlendFactor::ONE,
-            dst_color_blend_factor: vk::BlendFactor::ZERO,
-            color_blend_op: vk::BlendOp::ADD,
-            src_alpha_blend_factor: vk::BlendFactor::ONE,
-            dst_alpha_blend_factor: vk::BlendFactor::ZERO,
-            alpha_blend_op: vk::BlendOp::ADD,
-        }];
+        let color_blend_attachment_states =
+            vec![state.color_blend_attachment_state(); color_attachment_count as usize];
 
         let color_blending = vk::PipelineColorBlendStateCreateInfo {
             logic_op_enable: vk::FALSE,
             logic_op: vk::LogicOp::COPY,
-            attachment_count: 1,
+            attachment_count: color_blend_attachment_states.len() as u32,
             p_attachments: color_blend_attachment_states.as_ptr(),
             blend_constants: [0.0, 0.0, 0.0, 0.0],
             ..Default::default()
         };
 
-        let descriptor_set_layout: vk::DescriptorSetLayout =
-            PipelineDetail::create_descriptor_set_layout(&device.logical_device)?;
-        let pipeline_layout_info = vk::PipelineLayoutCreateInfo {
-            set_layout_count: 1,
-            p_set_layouts: [descriptor_set_layout].as_ptr(),
-            ..Default::default()
-        };
-
-        let pipeline_layout = unsafe {
-            device
-                .logical_device
-                .create_pipeline_layout(&pipeline_layout_info, None)
-                .context("failed to create pipeline layout")
-        }?;
-
-        let render_pass = PipelineDetail::create_render_pass(instance, &device, surface_format)?;
-
         let pipeline_info = vk::GraphicsPipelineCreateInfo {
+            p_next,
             stage_count: shader_stages.len() as u32,
             p_stages: shader_stages.as_ptr(),
             p_vertex_input_state: &vertex_input_info,
@@ -302,35 +815,181 @@ impl PipelineDetail {
             p_multisample_state: &multisampling,
             p_depth_stencil_state: &depth_state_create_info,
             p_color_blend_state: &color_blending,
+            p_dynamic_state: &dynamic_state,
             layout: pipeline_layout,
             base_pipeline_index: -1,
             render_pass,
+            subpass,
             ..Default::default()
         };
 
-        println!("going to create pipelines");
-        let pipelines = unsafe {
-            device
-                .logical_device
-                .create_graphics_pipelines(vk::PipelineCache::null(), &[pipeline_info], None)
-                //todo handle this with anyhow! somehow
-                .expect("failed to create pipelines")
-        };
+        tracing::debug!("going to create pipelines");
+        let pipelines = trace::call("vkCreateGraphicsPipelines", &pipeline_info, || unsafe {
+            device.create_graphics_pipelines(pipeline_cache, &[pipeline_info], None)
+        });
 
         unsafe {
-            device
-                .logical_device
-                .destroy_shader_module(vert_shader_module, None);
-            device
-                .logical_device
-                .destroy_shader_module(frag_shader_module, None);
+            device.destroy_shader_module(vert_shader_module, None);
+            device.destroy_shader_module(frag_shader_module, None);
         }
 
-        Ok(PipelineDetail {
-            pipeline: pipelines[0],
-            layout: pipeline_layout,
-            descriptor_set_layout,
-            render_pass,
-        })
+        pipelines
+            .map(|pipelines| pipelines[0])
+            .map_err(|(_, err)| err)
+            .context("failed to create pipelines")
+    }
+
+    // Replaces the variants with ones created from the state, eg. after a settings change.
+    // The layout and render pass are kept, so the sample count cannot change here.
+    // The old pipelines are destroyed by the garbage collector once no frame in flight
+    // can use them, command buffers have to be recorded again to bind the new ones.
+    pub fn rebuild(
+        &mut self,
+        device: &device::Device,
+        state: preset::FixedFunctionState,
+        garbage: &mut gc::GarbageCollector,
+    ) -> Result<()> {
+        if state.samples != self.state.samples {
+            return Err(Error::Unsupported(format!(
+                "cannot change the sample count from {:?} to {:?} without a new render pass",
+                self.state.samples, state.samples
+            )));
+        }
+
+        if let Some(portability) = device.portability {
+            portability.check_state(&state)?;
+        }
+
+        let compiled_shaders = self.shaders.compile()?;
+
+        let pipeline = PipelineDetail::create_pass_pipeline_from_spirv(
+            &device.logical_device,
+            compiled_shaders.clone(),
+            self.vertex_input.clone(),
+            &state.with_polygon_mode(vk::PolygonMode::FILL),
+            self.layout,
+            self.render_pass,
+            self.rendering,
+            self.pipeline_cache,
+        )?;
+
+        let wireframe = match self.wireframe {
+            Some(_) => {
+                match PipelineDetail::create_pass_pipeline_from_spirv(
+                    &device.logical_device,
+                    compiled_shaders,
+                    self.vertex_input.clone(),
+                    &state.with_polygon_mode(vk::PolygonMode::LINE),
+                    self.layout,
+                    self.render_pass,
+                    self.rendering,
+                    self.pipeline_cache,
+                ) {
+                    Ok(wireframe) => Some(wireframe),
+                    Err(err) => {
+                        unsafe { device.logical_device.destroy_pipeline(pipeline, None) };
+                        return Err(err);
+                    }
+                }
+            }
+            None => None,
+        };
+
+        for old in Some(self.pipeline).into_iter().chain(self.wireframe) {
+            device.untrack(old);
+            garbage.defer(gc::PendingDestruction::Pipeline(old));
+        }
+
+        device.track(registry::ResourceKind::Pipeline, pipeline);
+        if let Some(wireframe) = wireframe {
+            device.track(registry::ResourceKind::Pipeline, wireframe);
+        }
+
+        self.pipeline = pipeline;
+        self.wireframe = wireframe;
+        self.state = state.with_polygon_mode(vk::PolygonMode::FILL);
+
+        Ok(())
+    }
+
+    pub fn shaders(&self) -> &shaderc::ShaderSource {
+        &self.shaders
+    }
+
+    // Vertex layout the pipeline was created with, eg. for other pipelines drawing the
+    // same vertex buffers
+    pub(super) fn vertex_input(&self) -> permutation::VertexInput {
+        self.vertex_input.clone()
+    }
+
+    pub fn supports_polygon_mode(&self, polygon_mode: vk::PolygonMode) -> bool {
+        self.variant(polygon_mode).is_some()
+    }
+
+    fn variant(&self, polygon_mode: vk::PolygonMode) -> Option<vk::Pipeline> {
+        match polygon_mode {
+            vk::PolygonMode::FILL => Some(self.pipeline),
+            vk::PolygonMode::LINE => self.wireframe,
+            _ => None,
+        }
+    }
+
+    // Selects the variant bound by `current_pipeline`, command buffers that were
+    // already recorded keep the previous one until they are recorded again
+    pub fn set_polygon_mode(&mut self, polygon_mode: vk::PolygonMode) -> Result<()> {
+        self.variant(polygon_mode).ok_or_else(|| {
+            Error::Unsupported(format!(
+                "no pipeline variant for polygon mode {:?}",
+                polygon_mode
+            ))
+        })?;
+
+        self.polygon_mode = polygon_mode;
+        Ok(())
+    }
+
+    pub fn current_pipeline(&self) -> vk::Pipeline {
+        self.variant(self.polygon_mode).unwrap_or(self.pipeline)
+    }
+
+    // Names every object of the pipeline after the pass it is used for
+    pub fn set_name(&self, device: &device::Device, name: &str) {
+        device.name_resource(self.pipeline, &format!("{} pipeline", name));
+        if let Some(wireframe) = self.wireframe {
+            device.name_resource(wireframe, &format!("{} wireframe pipeline", name));
+        }
+        device.name_resource(self.layout, &format!("{} pipeline layout", name));
+        device.name_resource(
+            self.descriptor_set_layout,
+            &format!("{} descriptor set layout", name),
+        );
+        if self.rendering.is_none() {
+            device.name_resource(self.render_pass, &format!("{} render pass", name));
+        }
+    }
+
+    pub fn destroy(&self, device: &device::Device) {
+        device.untrack(self.pipeline);
+        if let Some(wireframe) = self.wireframe {
+            device.untrack(wireframe);
+        }
+        device.untrack(self.layout);
+        device.untrack(self.descriptor_set_layout);
+        if self.rendering.is_none() {
+            device.untrack(self.render_pass);
+        }
+
+        let device = &device.logical_device;
+        unsafe {
+            device.destroy_pipeline(self.pipeline, None);
+            if let Some(wireframe) = self.wireframe {
+                device.destroy_pipeline(wireframe, None);
+            }
+            device.destroy_pipeline_layout(self.layout, None);
+            device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+            if self.rendering.is_none() {
+                device.destroy_render_pass(self.render_pass, None);
+            }
+        }
     }
 }
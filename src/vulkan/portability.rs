@@ -0,0 +1,290 @@
+use ash::version::InstanceV1_1;
+use ash::vk;
+
+use crate::error::{Error, Result};
+use crate::foreign;
+
+use std::os::raw::c_void;
+
+use super::device;
+use super::preset;
+use super::swapchain;
+
+// Lists implementations layered on other apis, eg. MoltenVK on Metal, which the loader
+// hides from instances created without it
+pub const PORTABILITY_ENUMERATION_EXTENSION: &str = "VK_KHR_portability_enumeration";
+
+// Has to be enabled on devices exposing it, they are not fully conformant and report
+// what they lack through its features and properties
+pub const PORTABILITY_SUBSET_EXTENSION: device::DeviceExtension = device::DeviceExtension {
+    names: ["VK_KHR_portability_subset"],
+};
+
+// Metal layers hand out at most 3 drawables, more images or frames only add latency
+pub const PORTABILITY_IMAGE_COUNT: u32 = 3;
+
+// ash 0.29 predates both extensions, so the parts of them used here are declared by hand
+// following vulkan_core.h and vulkan_beta.h
+const PHYSICAL_DEVICE_PORTABILITY_SUBSET_FEATURES: i32 = 1_000_163_000;
+const PHYSICAL_DEVICE_PORTABILITY_SUBSET_PROPERTIES: i32 = 1_000_163_001;
+const INSTANCE_CREATE_ENUMERATE_PORTABILITY: u32 = 0x1;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct PhysicalDevicePortabilitySubsetFeatures {
+    s_type: vk::StructureType,
+    pub p_next: *mut c_void,
+    constant_alpha_color_blend_factors: vk::Bool32,
+    events: vk::Bool32,
+    image_view_format_reinterpretation: vk::Bool32,
+    image_view_format_swizzle: vk::Bool32,
+    image_view_2d_on_3d_image: vk::Bool32,
+    multisample_array_image: vk::Bool32,
+    mutable_comparison_samplers: vk::Bool32,
+    point_polygons: vk::Bool32,
+    sampler_mip_lod_bias: vk::Bool32,
+    separate_stencil_mask_ref: vk::Bool32,
+    shader_sample_rate_interpolation_functions: vk::Bool32,
+    tessellation_isolines: vk::Bool32,
+    tessellation_point_mode: vk::Bool32,
+    triangle_fans: vk::Bool32,
+    vertex_attribute_access_beyond_stride: vk::Bool32,
+}
+
+impl Default for PhysicalDevicePortabilitySubsetFeatures {
+    fn default() -> PhysicalDevicePortabilitySubsetFeatures {
+        PhysicalDevicePortabilitySubsetFeatures {
+            s_type: vk::StructureType::from_raw(PHYSICAL_DEVICE_PORTABILITY_SUBSET_FEATURES),
+            p_next: ::std::ptr::null_mut(),
+            constant_alpha_color_blend_factors: vk::FALSE,
+            events: vk::FALSE,
+            image_view_format_reinterpretation: vk::FALSE,
+            image_view_format_swizzle: vk::FALSE,
+            image_view_2d_on_3d_image: vk::FALSE,
+            multisample_array_image: vk::FALSE,
+            mutable_comparison_samplers: vk::FALSE,
+            point_polygons: vk::FALSE,
+            sampler_mip_lod_bias: vk::FALSE,
+            separate_stencil_mask_ref: vk::FALSE,
+            shader_sample_rate_interpolation_functions: vk::FALSE,
+            tessellation_isolines: vk::FALSE,
+            tessellation_point_mode: vk::FALSE,
+            triangle_fans: vk::FALSE,
+            vertex_attribute_access_beyond_stride: vk::FALSE,
+        }
+    }
+}
+
+#[repr(C)]
+struct PhysicalDevicePortabilitySubsetProperties {
+    s_type: vk::StructureType,
+    p_next: *mut c_void,
+    min_vertex_input_binding_stride_alignment: u32,
+}
+
+// Chained into vk::InstanceCreateInfo along with the enumeration extension
+pub fn instance_create_flags() -> vk::InstanceCreateFlags {
+    vk::InstanceCreateFlags::from_raw(INSTANCE_CREATE_ENUMERATE_PORTABILITY)
+}
+
+pub fn is_supported(instance: &ash::Instance, physical_device: vk::PhysicalDevice) -> Result<bool> {
+    device::Device::check_device_extension_support(
+        instance,
+        physical_device,
+        &PORTABILITY_SUBSET_EXTENSION,
+    )
+}
+
+// What a portability implementation supports of the pipeline state, see
+// device::Device::portability. Conformant devices have no limits to respect.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PortabilityLimits {
+    pub triangle_fans: bool,
+    pub point_polygons: bool,
+    pub constant_alpha_color_blend_factors: bool,
+    pub separate_stencil_mask_ref: bool,
+    pub sampler_mip_lod_bias: bool,
+    pub image_view_format_swizzle: bool,
+    pub vertex_attribute_access_beyond_stride: bool,
+    pub min_vertex_input_binding_stride_alignment: u32,
+}
+
+impl PortabilityLimits {
+    pub fn query(
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+    ) -> PortabilityLimits {
+        let mut subset_features = PhysicalDevicePortabilitySubsetFeatures::default();
+        let mut features = vk::PhysicalDeviceFeatures2 {
+            p_next: &mut subset_features as *mut PhysicalDevicePortabilitySubsetFeatures
+                as *mut c_void,
+            ..Default::default()
+        };
+
+        let mut subset_properties = PhysicalDevicePortabilitySubsetProperties {
+            s_type: vk::StructureType::from_raw(PHYSICAL_DEVICE_PORTABILITY_SUBSET_PROPERTIES),
+            p_next: ::std::ptr::null_mut(),
+            min_vertex_input_binding_stride_alignment: 1,
+        };
+        let mut properties = vk::PhysicalDeviceProperties2 {
+            p_next: &mut subset_properties as *mut PhysicalDevicePortabilitySubsetProperties
+                as *mut c_void,
+            ..Default::default()
+        };
+
+        unsafe {
+            instance.get_physical_device_features2(physical_device, &mut features);
+            instance.get_physical_device_properties2(physical_device, &mut properties);
+        }
+
+        PortabilityLimits::from_features(
+            subset_features,
+            subset_properties.min_vertex_input_binding_stride_alignment,
+        )
+    }
+
+    fn from_features(
+        features: PhysicalDevicePortabilitySubsetFeatures,
+        min_vertex_input_binding_stride_alignment: u32,
+    ) -> PortabilityLimits {
+        PortabilityLimits {
+            triangle_fans: features.triangle_fans == vk::TRUE,
+            point_polygons: features.point_polygons == vk::TRUE,
+            constant_alpha_color_blend_factors: features.constant_alpha_color_blend_factors
+                == vk::TRUE,
+            separate_stencil_mask_ref: features.separate_stencil_mask_ref == vk::TRUE,
+            sampler_mip_lod_bias: features.sampler_mip_lod_bias == vk::TRUE,
+            image_view_format_swizzle: features.image_view_format_swizzle == vk::TRUE,
+            vertex_attribute_access_beyond_stride: features.vertex_attribute_access_beyond_stride
+                == vk::TRUE,
+            min_vertex_input_binding_stride_alignment: min_vertex_input_binding_stride_alignment
+                .max(1),
+        }
+    }
+
+    // Chained into vk::DeviceCreateInfo to enable the supported features the engine uses
+    pub fn device_features(&self) -> PhysicalDevicePortabilitySubsetFeatures {
+        PhysicalDevicePortabilitySubsetFeatures {
+            constant_alpha_color_blend_factors: foreign::to_vk_bool(
+                self.constant_alpha_color_blend_factors,
+            ),
+            image_view_format_swizzle: foreign::to_vk_bool(self.image_view_format_swizzle),
+            point_polygons: foreign::to_vk_bool(self.point_polygons),
+            sampler_mip_lod_bias: foreign::to_vk_bool(self.sampler_mip_lod_bias),
+            separate_stencil_mask_ref: foreign::to_vk_bool(self.separate_stencil_mask_ref),
+            triangle_fans: foreign::to_vk_bool(self.triangle_fans),
+            vertex_attribute_access_beyond_stride: foreign::to_vk_bool(
+                self.vertex_attribute_access_beyond_stride,
+            ),
+            ..Default::default()
+        }
+    }
+
+    // Fails for state the implementation cannot draw with, instead of leaving it to the
+    // driver to reject the pipeline or draw something else
+    pub fn check_state(&self, state: &preset::FixedFunctionState) -> Result<()> {
+        if state.topology == vk::PrimitiveTopology::TRIANGLE_FAN && !self.triangle_fans {
+            return Err(Error::Unsupported(
+                "triangle fans are not supported by the portability implementation".to_string(),
+            ));
+        }
+
+        if state.polygon_mode == vk::PolygonMode::POINT && !self.point_polygons {
+            return Err(Error::Unsupported(
+                "point polygon mode is not supported by the portability implementation".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn check_vertex_input(&self, bindings: &[vk::VertexInputBindingDescription]) -> Result<()> {
+        let alignment = self.min_vertex_input_binding_stride_alignment;
+
+        match bindings
+            .iter()
+            .find(|binding| binding.stride % alignment != 0)
+        {
+            Some(binding) => Err(Error::Unsupported(format!(
+                "vertex binding {} has a stride of {} bytes, the portability implementation \
+                 needs a multiple of {}",
+                binding.binding, binding.stride, alignment
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    // The surface's limits still apply, see swapchain::SwapchainDetails::choose_image_count
+    pub fn swapchain_config(
+        &self,
+        config: &swapchain::SwapchainConfig,
+    ) -> swapchain::SwapchainConfig {
+        let mut config = config.clone();
+        if config.image_count.is_none() {
+            config.image_count = Some(PORTABILITY_IMAGE_COUNT);
+        }
+        config
+    }
+
+    // Frames in flight beyond the swapchain's images would only wait for a drawable
+    pub fn frames_in_flight(&self, requested: u32, image_count: u32) -> u32 {
+        requested.min(image_count).max(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn moltenvk() -> PortabilityLimits {
+        let features = PhysicalDevicePortabilitySubsetFeatures {
+            point_polygons: vk::FALSE,
+            triangle_fans: vk::FALSE,
+            image_view_format_swizzle: vk::TRUE,
+            ..Default::default()
+        };
+
+        PortabilityLimits::from_features(features, 4)
+    }
+
+    #[test]
+    fn unsupported_state_is_rejected() {
+        let limits = moltenvk();
+        assert!(limits.image_view_format_swizzle);
+
+        let state = preset::FixedFunctionState::from_preset(preset::Preset::Opaque3d);
+        assert!(limits.check_state(&state).is_ok());
+        assert!(limits
+            .check_state(&state.with_polygon_mode(vk::PolygonMode::LINE))
+            .is_ok());
+        assert!(limits
+            .check_state(&state.with_polygon_mode(vk::PolygonMode::POINT))
+            .is_err());
+        assert!(limits
+            .check_state(&state.with_topology(vk::PrimitiveTopology::TRIANGLE_FAN))
+            .is_err());
+
+        let binding = |stride| vk::VertexInputBindingDescription {
+            binding: 0,
+            stride,
+            input_rate: vk::VertexInputRate::VERTEX,
+        };
+        assert!(limits.check_vertex_input(&[binding(44)]).is_ok());
+        assert!(limits.check_vertex_input(&[binding(42)]).is_err());
+    }
+
+    #[test]
+    fn frame_counts_follow_the_drawables() {
+        let limits = moltenvk();
+
+        let config = limits.swapchain_config(&swapchain::SwapchainConfig::default());
+        assert_eq!(config.image_count, Some(PORTABILITY_IMAGE_COUNT));
+        let config =
+            limits.swapchain_config(&swapchain::SwapchainConfig::default().with_image_count(2));
+        assert_eq!(config.image_count, Some(2));
+
+        assert_eq!(limits.frames_in_flight(10, 3), 3);
+        assert_eq!(limits.frames_in_flight(2, 3), 2);
+        assert_eq!(limits.frames_in_flight(0, 3), 1);
+    }
+}
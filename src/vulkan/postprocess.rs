@@ -0,0 +1,815 @@
+use ash::version::DeviceV1_0;
+use ash::vk;
+
+use crate::error::{Context, Error, Result};
+
+use crate::shaderc;
+
+use super::device;
+use super::frame;
+use super::image;
+use super::pipeline;
+use super::preset;
+use super::registry;
+use super::swapchain;
+use super::viewport::{PostProcessConfig, PostProcessEffect, TonemapOperator, ViewportConfig};
+
+pub const FULLSCREEN_VERTEX_SHADER: &'static str = "shaders/fullscreen.vert";
+pub const POST_PROCESS_FRAGMENT_SHADER: &'static str = "shaders/postprocess.frag";
+
+// Keeps the scene's values above 1.0 until the tonemap pass
+pub const HDR_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+// How the last pass encodes the image for the swapchain's color space. The values match
+// OUTPUT_* of shaders/postprocess.frag.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum OutputEncoding {
+    // gamma is applied by the gamma effect or the sRGB format of the swapchain
+    Sdr = 0,
+    // linear with 1.0 at 80 nits
+    ScRgb = 1,
+    // rec. 2020 primaries and the ST 2084 perceptual quantizer curve
+    Hdr10 = 2,
+}
+
+impl OutputEncoding {
+    pub fn of_color_space(color_space: vk::ColorSpaceKHR) -> OutputEncoding {
+        match color_space {
+            vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT => OutputEncoding::ScRgb,
+            vk::ColorSpaceKHR::HDR10_ST2084_EXT => OutputEncoding::Hdr10,
+            _ => OutputEncoding::Sdr,
+        }
+    }
+
+    // The display maps the HDR values itself, so the tonemap effect only applies the
+    // exposure and gamma is left to the encoding
+    pub fn is_hdr(self) -> bool {
+        self != OutputEncoding::Sdr
+    }
+}
+
+// TONEMAP_NONE of shaders/postprocess.frag, used in place of the operator on an HDR swapchain
+const TONEMAP_NONE: u32 = 3;
+
+// See shaders/postprocess.frag
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct PushConstants {
+    inverse_extent: [f32; 2],
+    exposure: f32,
+    gamma: f32,
+    paper_white: f32,
+    tonemap: u32,
+    // OutputEncoding of the pass, Sdr for all but the last one
+    output: u32,
+}
+
+// The fullscreen triangle is generated from the vertex index, no vertex buffer is bound
+#[derive(Debug, Copy, Clone)]
+pub(super) struct NoVertices;
+
+impl pipeline::VertexData for NoVertices {
+    fn get_input_binding_description(&self) -> Vec<vk::VertexInputBindingDescription> {
+        vec![]
+    }
+
+    fn get_attribute_description(&self) -> Vec<vk::VertexInputAttributeDescription> {
+        vec![]
+    }
+}
+
+// One effect of the chain drawn as a fullscreen triangle, a copy of the input without one
+struct Pass {
+    effect: Option<PostProcessEffect>,
+    pipeline: vk::Pipeline,
+    render_pass: vk::RenderPass,
+    // both per swapchain image
+    framebuffers: Vec<vk::Framebuffer>,
+    descriptor_sets: Vec<vk::DescriptorSet>,
+}
+
+// Runs the effects of a viewport's config on the offscreen HDR scene target in the order
+// they were added, the last one writing the viewport's area of the swapchain image. Every
+// pass samples the output of the previous one, so each swapchain image has its own
+// targets. They have the extent of the swapchain, so the passes sample the pixels they
+// write. Overlays are drawn on top of the result. Command buffers are re-recorded every
+// frame.
+pub struct PostProcessChain {
+    device: device::Device,
+    config: PostProcessConfig,
+    passes: Vec<Pass>,
+    // targets[image][i] is written by pass i and read by the next one
+    targets: Vec<Vec<image::ImageData>>,
+
+    intermediate_render_pass: vk::RenderPass,
+    present_render_pass: vk::RenderPass,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    pipeline_layout: vk::PipelineLayout,
+    sampler: vk::Sampler,
+
+    command_pool: vk::CommandPool,
+    command_buffers: Vec<vk::CommandBuffer>,
+    extent: vk::Extent2D,
+    // the viewport's pixels of the swapchain image
+    area: vk::Rect2D,
+    // the swapchain encodes its writes, the gamma pass must not encode them again
+    srgb_output: bool,
+    output: OutputEncoding,
+}
+
+impl PostProcessChain {
+    // Name of the define selecting the effect in shaders/postprocess.frag
+    fn shader_define(effect: PostProcessEffect) -> Result<&'static str> {
+        match effect {
+            PostProcessEffect::Tonemap => Ok("TONEMAP"),
+            PostProcessEffect::Gamma => Ok("GAMMA"),
+            PostProcessEffect::Fxaa => Ok("FXAA"),
+            PostProcessEffect::Bloom => Err(Error::Unsupported(
+                "bloom is not supported by the post process chain".to_string(),
+            )),
+        }
+    }
+
+    // The previous pass or the scene has written the input by the time it is sampled.
+    // Pixels outside of the render area are only kept with a defined initial layout.
+    fn create_render_pass(
+        device: &ash::Device,
+        format: vk::Format,
+        initial_layout: vk::ImageLayout,
+        final_layout: vk::ImageLayout,
+    ) -> Result<vk::RenderPass> {
+        // every pixel of the render area is overwritten by the fullscreen triangle
+        let color_attachment = vk::AttachmentDescription {
+            format,
+            samples: vk::SampleCountFlags::TYPE_1,
+            load_op: vk::AttachmentLoadOp::DONT_CARE,
+            store_op: vk::AttachmentStoreOp::STORE,
+            stencil_load_op: vk::AttachmentLoadOp::DONT_CARE,
+            stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
+            initial_layout,
+            final_layout,
+            ..Default::default()
+        };
+
+        let color_attachment_ref = vk::AttachmentReference {
+            attachment: 0,
+            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        };
+
+        let subpasses = [vk::SubpassDescription {
+            color_attachment_count: 1,
+            p_color_attachments: &color_attachment_ref,
+            pipeline_bind_point: vk::PipelineBindPoint::GRAPHICS,
+            ..Default::default()
+        }];
+
+        let attachments = [color_attachment];
+
+        // also keeps the output from being overwritten while an earlier pass still reads it
+        let subpass_dependencies = [vk::SubpassDependency {
+            src_subpass: vk::SUBPASS_EXTERNAL,
+            src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                | vk::PipelineStageFlags::FRAGMENT_SHADER,
+            dst_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                | vk::PipelineStageFlags::FRAGMENT_SHADER,
+            src_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            dst_access_mask: vk::AccessFlags::SHADER_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            ..Default::default()
+        }];
+
+        let renderpass_create_info = vk::RenderPassCreateInfo {
+            attachment_count: attachments.len() as u32,
+            p_attachments: attachments.as_ptr(),
+            subpass_count: subpasses.len() as u32,
+            p_subpasses: subpasses.as_ptr(),
+            dependency_count: subpass_dependencies.len() as u32,
+            p_dependencies: subpass_dependencies.as_ptr(),
+            ..Default::default()
+        };
+
+        unsafe {
+            device
+                .create_render_pass(&renderpass_create_info, None)
+                .context("failed to create post process render pass")
+        }
+    }
+
+    fn create_descriptor_set_layout(device: &ash::Device) -> Result<vk::DescriptorSetLayout> {
+        let bindings = [vk::DescriptorSetLayoutBinding {
+            binding: 0,
+            descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: 1,
+            stage_flags: vk::ShaderStageFlags::FRAGMENT,
+            ..Default::default()
+        }];
+
+        let layout_info = vk::DescriptorSetLayoutCreateInfo {
+            binding_count: bindings.len() as u32,
+            p_bindings: bindings.as_ptr(),
+            ..Default::default()
+        };
+
+        unsafe {
+            device
+                .create_descriptor_set_layout(&layout_info, None)
+                .context("failed to create post process descriptor set layout")
+        }
+    }
+
+    fn create_pipeline_layout(
+        device: &ash::Device,
+        descriptor_set_layout: vk::DescriptorSetLayout,
+    ) -> Result<vk::PipelineLayout> {
+        let push_constant_ranges = [vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::FRAGMENT,
+            offset: 0,
+            size: ::std::mem::size_of::<PushConstants>() as u32,
+        }];
+
+        let set_layouts = [descriptor_set_layout];
+        let layout_info = vk::PipelineLayoutCreateInfo {
+            set_layout_count: set_layouts.len() as u32,
+            p_set_layouts: set_layouts.as_ptr(),
+            push_constant_range_count: push_constant_ranges.len() as u32,
+            p_push_constant_ranges: push_constant_ranges.as_ptr(),
+            ..Default::default()
+        };
+
+        unsafe {
+            device
+                .create_pipeline_layout(&layout_info, None)
+                .context("failed to create post process pipeline layout")
+        }
+    }
+
+    // Linear filtering is needed by FXAA, which samples between texels
+    fn create_sampler(device: &ash::Device) -> Result<vk::Sampler> {
+        let sampler_info = vk::SamplerCreateInfo {
+            mag_filter: vk::Filter::LINEAR,
+            min_filter: vk::Filter::LINEAR,
+            address_mode_u: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            address_mode_v: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            address_mode_w: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            compare_enable: vk::FALSE,
+            compare_op: vk::CompareOp::ALWAYS,
+            mipmap_mode: vk::SamplerMipmapMode::NEAREST,
+            border_color: vk::BorderColor::INT_OPAQUE_BLACK,
+            anisotropy_enable: vk::FALSE,
+            unnormalized_coordinates: vk::FALSE,
+            ..Default::default()
+        };
+
+        unsafe {
+            device
+                .create_sampler(&sampler_info, None)
+                .context("failed to create post process sampler")
+        }
+    }
+
+    fn create_descriptor_pool(device: &ash::Device, count: u32) -> Result<vk::DescriptorPool> {
+        let pool_size = vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: count,
+        };
+
+        let pool_info = vk::DescriptorPoolCreateInfo {
+            pool_size_count: 1,
+            p_pool_sizes: &pool_size,
+            max_sets: count,
+            ..Default::default()
+        };
+
+        unsafe {
+            device
+                .create_descriptor_pool(&pool_info, None)
+                .context("failed to create post process descriptor pool")
+        }
+    }
+
+    // One set per input view, sampled in the layout the render passes leave it in
+    fn create_descriptor_sets(
+        device: &ash::Device,
+        pool: vk::DescriptorPool,
+        layout: vk::DescriptorSetLayout,
+        sampler: vk::Sampler,
+        input_views: &[vk::ImageView],
+    ) -> Result<Vec<vk::DescriptorSet>> {
+        let layouts = vec![layout; input_views.len()];
+        let alloc_info = vk::DescriptorSetAllocateInfo {
+            descriptor_pool: pool,
+            descriptor_set_count: layouts.len() as u32,
+            p_set_layouts: layouts.as_ptr(),
+            ..Default::default()
+        };
+
+        let descriptor_sets = unsafe {
+            device
+                .allocate_descriptor_sets(&alloc_info)
+                .context("failed to allocate post process descriptor sets")
+        }?;
+
+        let image_infos = input_views
+            .iter()
+            .map(|&image_view| vk::DescriptorImageInfo {
+                sampler,
+                image_view,
+                image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            })
+            .collect::<Vec<vk::DescriptorImageInfo>>();
+
+        let descriptor_writes = descriptor_sets
+            .iter()
+            .zip(image_infos.iter())
+            .map(|(&dst_set, image_info)| vk::WriteDescriptorSet {
+                dst_set,
+                dst_binding: 0,
+                descriptor_count: 1,
+                descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                p_image_info: image_info,
+                ..Default::default()
+            })
+            .collect::<Vec<vk::WriteDescriptorSet>>();
+
+        unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+
+        Ok(descriptor_sets)
+    }
+
+    fn create_framebuffers(
+        device: &ash::Device,
+        render_pass: vk::RenderPass,
+        image_views: &[vk::ImageView],
+        extent: vk::Extent2D,
+    ) -> Result<Vec<vk::Framebuffer>> {
+        image_views
+            .iter()
+            .map(|&image_view| {
+                let attachments = [image_view];
+
+                let framebuffer_info = vk::FramebufferCreateInfo {
+                    render_pass,
+                    attachment_count: attachments.len() as u32,
+                    p_attachments: attachments.as_ptr(),
+                    width: extent.width,
+                    height: extent.height,
+                    layers: 1,
+                    ..Default::default()
+                };
+
+                unsafe {
+                    device
+                        .create_framebuffer(&framebuffer_info, None)
+                        .context("failed to create post process framebuffer")
+                }
+            })
+            .collect()
+    }
+
+    fn create_command_buffers(
+        device: &device::Device,
+        count: u32,
+    ) -> Result<(vk::CommandPool, Vec<vk::CommandBuffer>)> {
+        let queue_index = device
+            .family_indices
+            .graphics
+            .ok_or_else(|| Error::msg("graphics family index not present"))?;
+
+        // buffers are reset and recorded again every frame
+        let command_pool_info = vk::CommandPoolCreateInfo {
+            queue_family_index: queue_index,
+            flags: vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER,
+            ..Default::default()
+        };
+
+        let command_pool = unsafe {
+            device
+                .logical_device
+                .create_command_pool(&command_pool_info, None)
+                .context("failed to create post process command pool")
+        }?;
+
+        let alloc_info = vk::CommandBufferAllocateInfo {
+            command_buffer_count: count,
+            command_pool,
+            level: vk::CommandBufferLevel::PRIMARY,
+            ..Default::default()
+        };
+
+        let command_buffers = unsafe {
+            device
+                .logical_device
+                .allocate_command_buffers(&alloc_info)
+                .context("failed to allocate post process command buffers")
+        }?;
+
+        Ok((command_pool, command_buffers))
+    }
+
+    // Reads the scene from input_views, one per swapchain image. A viewport without
+    // effects copies its area. keep_output keeps what the chains of earlier viewports
+    // wrote to the swapchain image, the first one starts from an undefined image.
+    pub fn new(
+        device: &device::Device,
+        graphics_queue: vk::Queue,
+        swapchain: &swapchain::SwapchainDetails,
+        viewport: &ViewportConfig,
+        input_views: &[vk::ImageView],
+        keep_output: bool,
+    ) -> Result<PostProcessChain> {
+        let config = viewport.post_process.clone();
+
+        let defines = if config.is_empty() {
+            vec![None]
+        } else {
+            config
+                .effects
+                .iter()
+                .map(|&effect| PostProcessChain::shader_define(effect).map(Some))
+                .collect::<Result<Vec<Option<&'static str>>>>()?
+        };
+
+        let logical_device = &device.logical_device;
+        let num_images = swapchain.image_views.len();
+        let extent = swapchain.extent;
+
+        if input_views.len() != num_images {
+            return Err(Error::msg(format!(
+                "the post process chain needs one input per swapchain image, got {} for {}",
+                input_views.len(),
+                num_images
+            )));
+        }
+
+        let (command_pool, command_buffers) =
+            PostProcessChain::create_command_buffers(device, num_images as u32)?;
+
+        // the output of every pass but the last
+        let targets = (0..num_images)
+            .map(|image_index| {
+                (0..defines.len() - 1)
+                    .map(|target_index| {
+                        let property = image::ImagePropertyType::color_property(extent, HDR_FORMAT);
+                        let target =
+                            image::ImageData::new(device, command_pool, graphics_queue, property)?;
+                        target.set_name(
+                            device,
+                            &format!(
+                                "{} post process target {}.{}",
+                                viewport.name, image_index, target_index
+                            ),
+                        );
+
+                        Ok(target)
+                    })
+                    .collect::<Result<Vec<image::ImageData>>>()
+            })
+            .collect::<Result<Vec<Vec<image::ImageData>>>>()?;
+
+        let intermediate_render_pass = PostProcessChain::create_render_pass(
+            logical_device,
+            HDR_FORMAT,
+            vk::ImageLayout::UNDEFINED,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        )?;
+        // overlays drawn afterwards load the image in the present layout
+        let present_render_pass = PostProcessChain::create_render_pass(
+            logical_device,
+            swapchain.format.format,
+            if keep_output {
+                vk::ImageLayout::PRESENT_SRC_KHR
+            } else {
+                vk::ImageLayout::UNDEFINED
+            },
+            vk::ImageLayout::PRESENT_SRC_KHR,
+        )?;
+
+        let descriptor_set_layout = PostProcessChain::create_descriptor_set_layout(logical_device)?;
+        let pipeline_layout =
+            PostProcessChain::create_pipeline_layout(logical_device, descriptor_set_layout)?;
+        let sampler = PostProcessChain::create_sampler(logical_device)?;
+        let descriptor_pool = PostProcessChain::create_descriptor_pool(
+            logical_device,
+            (num_images * defines.len()) as u32,
+        )?;
+
+        let shaders = shaderc::ShaderSource {
+            vertex_shader_file: FULLSCREEN_VERTEX_SHADER.to_string(),
+            fragment_shader_file: POST_PROCESS_FRAGMENT_SHADER.to_string(),
+        };
+        let state = preset::FixedFunctionState::from_preset(preset::Preset::Fullscreen);
+
+        let passes = defines
+            .iter()
+            .enumerate()
+            .map(|(index, &define)| {
+                let effect = config.effects.get(index).cloned();
+                let is_last = index + 1 == defines.len();
+                let render_pass = if is_last {
+                    present_render_pass
+                } else {
+                    intermediate_render_pass
+                };
+
+                // without an effect the shader copies its input
+                let shader_defines = define
+                    .map(|define| vec![(define, None)])
+                    .unwrap_or_default();
+                let pipeline = pipeline::PipelineDetail::create_pipeline_from_spirv(
+                    logical_device,
+                    shaders.compile_with_defines(&shader_defines)?,
+                    NoVertices,
+                    &state,
+                    pipeline_layout,
+                    render_pass,
+                    vk::PipelineCache::null(),
+                )?;
+
+                let pass_inputs = if index == 0 {
+                    input_views.to_vec()
+                } else {
+                    targets
+                        .iter()
+                        .map(|image_targets| image_targets[index - 1].image_view)
+                        .collect::<Vec<vk::ImageView>>()
+                };
+                let output_views = if is_last {
+                    swapchain.image_views.clone()
+                } else {
+                    targets
+                        .iter()
+                        .map(|image_targets| image_targets[index].image_view)
+                        .collect()
+                };
+
+                let descriptor_sets = PostProcessChain::create_descriptor_sets(
+                    logical_device,
+                    descriptor_pool,
+                    descriptor_set_layout,
+                    sampler,
+                    &pass_inputs,
+                )?;
+                let framebuffers = PostProcessChain::create_framebuffers(
+                    logical_device,
+                    render_pass,
+                    &output_views,
+                    extent,
+                )?;
+
+                device.track(registry::ResourceKind::Pipeline, pipeline);
+                let pipeline_name = match effect {
+                    Some(effect) => format!("{} post process {:?} pipeline", viewport.name, effect),
+                    None => format!("{} post process copy pipeline", viewport.name),
+                };
+                device.name_resource(pipeline, &pipeline_name);
+                for &framebuffer in framebuffers.iter() {
+                    device.track(registry::ResourceKind::Framebuffer, framebuffer);
+                }
+
+                Ok(Pass {
+                    effect,
+                    pipeline,
+                    render_pass,
+                    framebuffers,
+                    descriptor_sets,
+                })
+            })
+            .collect::<Result<Vec<Pass>>>()?;
+
+        device.track(registry::ResourceKind::RenderPass, intermediate_render_pass);
+        device.track(registry::ResourceKind::RenderPass, present_render_pass);
+        device.track(
+            registry::ResourceKind::DescriptorSetLayout,
+            descriptor_set_layout,
+        );
+        device.track(registry::ResourceKind::DescriptorPool, descriptor_pool);
+        device.track(registry::ResourceKind::PipelineLayout, pipeline_layout);
+        device.track(registry::ResourceKind::Sampler, sampler);
+        device.track(registry::ResourceKind::CommandPool, command_pool);
+
+        Ok(PostProcessChain {
+            device: device.clone(),
+            config,
+            passes,
+            targets,
+            intermediate_render_pass,
+            present_render_pass,
+            descriptor_set_layout,
+            descriptor_pool,
+            pipeline_layout,
+            sampler,
+            command_pool,
+            command_buffers,
+            extent,
+            area: viewport.get_scissor(extent),
+            srgb_output: swapchain.is_srgb(),
+            output: OutputEncoding::of_color_space(swapchain.format.color_space),
+        })
+    }
+
+    pub fn effects(&self) -> Vec<PostProcessEffect> {
+        self.passes.iter().filter_map(|pass| pass.effect).collect()
+    }
+
+    pub fn config(&self) -> &PostProcessConfig {
+        &self.config
+    }
+
+    pub fn output_encoding(&self) -> OutputEncoding {
+        self.output
+    }
+
+    // Picked up by the next recorded frame
+    pub fn set_exposure(&mut self, exposure: f32) {
+        self.config.exposure = exposure;
+    }
+
+    pub fn set_tonemap(&mut self, tonemap: TonemapOperator) {
+        self.config.tonemap = tonemap;
+    }
+
+    pub fn set_gamma(&mut self, gamma: f32) {
+        self.config.gamma = gamma;
+    }
+
+    // Records every pass of the chain for the viewport's area of the image, which is left
+    // ready to present.
+    // Must only be called once the previous frame using this image has completed.
+    pub fn record(&mut self, frame: &frame::FrameContext) -> Result<vk::CommandBuffer> {
+        let logical_device = &self.device.logical_device;
+        let command_buffer = *frame.per_image(&self.command_buffers)?;
+
+        let push_constants = PushConstants {
+            inverse_extent: [
+                1.0 / self.extent.width as f32,
+                1.0 / self.extent.height as f32,
+            ],
+            exposure: self.config.exposure,
+            gamma: if self.srgb_output || self.output.is_hdr() {
+                1.0
+            } else {
+                self.config.gamma
+            },
+            paper_white: self.config.paper_white,
+            tonemap: if self.output.is_hdr() {
+                TONEMAP_NONE
+            } else {
+                self.config.tonemap as u32
+            },
+            output: OutputEncoding::Sdr as u32,
+        };
+        // only the pass writing the swapchain image encodes for its color space
+        let last_push_constants = PushConstants {
+            output: self.output as u32,
+            ..push_constants
+        };
+
+        // the whole extent, so the fullscreen triangle samples the pixel it writes
+        let viewports = [vk::Viewport {
+            x: 0.0,
+            y: 0.0,
+            width: self.extent.width as f32,
+            height: self.extent.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        }];
+
+        let scissors = [self.area];
+
+        unsafe {
+            logical_device
+                .reset_command_buffer(command_buffer, vk::CommandBufferResetFlags::empty())
+                .context("failed to reset post process command buffer")?;
+
+            logical_device
+                .begin_command_buffer(command_buffer, &vk::CommandBufferBeginInfo::default())
+                .context("failed to begin recording post process command buffer")?;
+        }
+
+        for (index, pass) in self.passes.iter().enumerate() {
+            let push_constants = if index + 1 == self.passes.len() {
+                &last_push_constants
+            } else {
+                &push_constants
+            };
+            let push_constant_bytes = unsafe {
+                ::std::slice::from_raw_parts(
+                    push_constants as *const PushConstants as *const u8,
+                    ::std::mem::size_of::<PushConstants>(),
+                )
+            };
+
+            let render_pass_begin_info = vk::RenderPassBeginInfo {
+                render_pass: pass.render_pass,
+                framebuffer: *frame.per_image(&pass.framebuffers)?,
+                render_area: scissors[0],
+                ..Default::default()
+            };
+
+            unsafe {
+                logical_device.cmd_begin_render_pass(
+                    command_buffer,
+                    &render_pass_begin_info,
+                    vk::SubpassContents::INLINE,
+                );
+
+                logical_device.cmd_bind_pipeline(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    pass.pipeline,
+                );
+                logical_device.cmd_set_viewport(command_buffer, 0, &viewports);
+                logical_device.cmd_set_scissor(command_buffer, 0, &scissors);
+                logical_device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    self.pipeline_layout,
+                    0,
+                    &[*frame.per_image(&pass.descriptor_sets)?],
+                    &[],
+                );
+                logical_device.cmd_push_constants(
+                    command_buffer,
+                    self.pipeline_layout,
+                    vk::ShaderStageFlags::FRAGMENT,
+                    0,
+                    push_constant_bytes,
+                );
+
+                logical_device.cmd_draw(command_buffer, 3, 1, 0, 0);
+
+                logical_device.cmd_end_render_pass(command_buffer);
+            }
+        }
+
+        unsafe {
+            logical_device
+                .end_command_buffer(command_buffer)
+                .context("failed to end post process command buffer recording")?;
+        }
+
+        Ok(command_buffer)
+    }
+
+    // The device has to be idle
+    pub fn destroy(&mut self) {
+        let logical_device = &self.device.logical_device;
+
+        for pass in self.passes.iter() {
+            self.device.untrack(pass.pipeline);
+            unsafe { logical_device.destroy_pipeline(pass.pipeline, None) };
+
+            for &framebuffer in pass.framebuffers.iter() {
+                self.device.untrack(framebuffer);
+                unsafe { logical_device.destroy_framebuffer(framebuffer, None) };
+            }
+        }
+
+        for target in self.targets.iter().flatten() {
+            target.destroy(&self.device);
+        }
+
+        self.device.untrack(self.command_pool);
+        self.device.untrack(self.sampler);
+        self.device.untrack(self.pipeline_layout);
+        self.device.untrack(self.descriptor_pool);
+        self.device.untrack(self.descriptor_set_layout);
+        self.device.untrack(self.present_render_pass);
+        self.device.untrack(self.intermediate_render_pass);
+
+        unsafe {
+            // frees the command buffers and descriptor sets as well
+            logical_device.destroy_command_pool(self.command_pool, None);
+            logical_device.destroy_descriptor_pool(self.descriptor_pool, None);
+
+            logical_device.destroy_sampler(self.sampler, None);
+            logical_device.destroy_pipeline_layout(self.pipeline_layout, None);
+            logical_device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+            logical_device.destroy_render_pass(self.present_render_pass, None);
+            logical_device.destroy_render_pass(self.intermediate_render_pass, None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hdr_color_spaces_are_encoded_by_the_last_pass() {
+        assert_eq!(
+            OutputEncoding::of_color_space(vk::ColorSpaceKHR::SRGB_NONLINEAR),
+            OutputEncoding::Sdr
+        );
+        assert_eq!(
+            OutputEncoding::of_color_space(vk::ColorSpaceKHR::HDR10_ST2084_EXT),
+            OutputEncoding::Hdr10
+        );
+        assert!(
+            OutputEncoding::of_color_space(vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT).is_hdr()
+        );
+
+        // matches the push constant block of shaders/postprocess.frag
+        assert_eq!(::std::mem::size_of::<PushConstants>(), 28);
+    }
+}
@@ -0,0 +1,447 @@
+use ash::version::DeviceV1_0;
+use ash::vk;
+
+use crate::error::{Context, Error, Result};
+
+use std::ffi::CString;
+use std::time::Instant;
+
+use crate::shaderc;
+
+use super::device;
+use super::frame;
+use super::pipeline;
+use super::registry;
+use super::swapchain;
+
+pub const PRESENT_SHADER_FILE: &'static str = "shaders/present.comp";
+
+// Matches the local size declared in shaders/present.comp
+const WORKGROUP_SIZE: u32 = 8;
+
+// See shaders/present.comp
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct PushConstants {
+    extent: [u32; 2],
+    time: f32,
+}
+
+// Produces the final image with a compute shader writing straight into the swapchain
+// image, skipping the scene render pass entirely. Overlays are still drawn on top.
+// Command buffers are re-recorded every frame as the push constants change.
+pub struct ComputePresenter {
+    device: device::Device,
+
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_sets: Vec<vk::DescriptorSet>,
+    pub pipeline_layout: vk::PipelineLayout,
+    pub pipeline: vk::Pipeline,
+
+    command_pool: vk::CommandPool,
+    command_buffers: Vec<vk::CommandBuffer>,
+
+    // owned by the swapchain
+    images: Vec<vk::Image>,
+    extent: vk::Extent2D,
+    start_time: Instant,
+}
+
+impl ComputePresenter {
+    // The swapchain images need storage usage, the shader writes them without a format
+    // qualifier and the graphics queue, which also presents, has to run compute work
+    pub fn is_supported(
+        instance: &ash::Instance,
+        device: &device::Device,
+        swapchain: &swapchain::SwapchainDetails,
+    ) -> bool {
+        swapchain.supports_storage()
+            && device.features.shader_storage_image_write_without_format == vk::TRUE
+            && device.graphics_queue_supports_compute(instance)
+    }
+
+    fn create_descriptor_set_layout(device: &ash::Device) -> Result<vk::DescriptorSetLayout> {
+        let bindings = [vk::DescriptorSetLayoutBinding {
+            binding: 0,
+            descriptor_type: vk::DescriptorType::STORAGE_IMAGE,
+            descriptor_count: 1,
+            stage_flags: vk::ShaderStageFlags::COMPUTE,
+            ..Default::default()
+        }];
+
+        let layout_info = vk::DescriptorSetLayoutCreateInfo {
+            binding_count: bindings.len() as u32,
+            p_bindings: bindings.as_ptr(),
+            ..Default::default()
+        };
+
+        unsafe {
+            device
+                .create_descriptor_set_layout(&layout_info, None)
+                .context("failed to create present descriptor set layout")
+        }
+    }
+
+    // One set per swapchain image, each pointing at the image's view
+    fn create_descriptor_sets(
+        device: &ash::Device,
+        layout: vk::DescriptorSetLayout,
+        image_views: &[vk::ImageView],
+    ) -> Result<(vk::DescriptorPool, Vec<vk::DescriptorSet>)> {
+        let pool_size = vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::STORAGE_IMAGE,
+            descriptor_count: image_views.len() as u32,
+        };
+
+        let pool_info = vk::DescriptorPoolCreateInfo {
+            pool_size_count: 1,
+            p_pool_sizes: &pool_size,
+            max_sets: image_views.len() as u32,
+            ..Default::default()
+        };
+
+        let pool = unsafe {
+            device
+                .create_descriptor_pool(&pool_info, None)
+                .context("failed to create present descriptor pool")
+        }?;
+
+        let layouts = vec![layout; image_views.len()];
+        let alloc_info = vk::DescriptorSetAllocateInfo {
+            descriptor_pool: pool,
+            descriptor_set_count: layouts.len() as u32,
+            p_set_layouts: layouts.as_ptr(),
+            ..Default::default()
+        };
+
+        let descriptor_sets = unsafe {
+            device
+                .allocate_descriptor_sets(&alloc_info)
+                .context("failed to allocate present descriptor sets")
+        }?;
+
+        let image_infos = image_views
+            .iter()
+            .map(|&image_view| vk::DescriptorImageInfo {
+                sampler: vk::Sampler::null(),
+                image_view,
+                image_layout: vk::ImageLayout::GENERAL,
+            })
+            .collect::<Vec<vk::DescriptorImageInfo>>();
+
+        let descriptor_writes = descriptor_sets
+            .iter()
+            .zip(image_infos.iter())
+            .map(|(&dst_set, image_info)| vk::WriteDescriptorSet {
+                dst_set,
+                dst_binding: 0,
+                descriptor_count: 1,
+                descriptor_type: vk::DescriptorType::STORAGE_IMAGE,
+                p_image_info: image_info,
+                ..Default::default()
+            })
+            .collect::<Vec<vk::WriteDescriptorSet>>();
+
+        unsafe { device.update_descriptor_sets(&descriptor_writes, &[]) };
+
+        Ok((pool, descriptor_sets))
+    }
+
+    fn create_pipeline(
+        device: &ash::Device,
+        shader_file: &String,
+        pipeline_layout: vk::PipelineLayout,
+    ) -> Result<vk::Pipeline> {
+        let code = shaderc::ShaderSource::compile_compute(shader_file)?;
+        let shader_module = pipeline::PipelineDetail::create_shader_module(device, code)?;
+
+        let main_function_name = CString::new("main").context("invalid fn name")?;
+
+        let pipeline_info = vk::ComputePipelineCreateInfo {
+            stage: vk::PipelineShaderStageCreateInfo {
+                module: shader_module,
+                p_name: main_function_name.as_ptr(),
+                stage: vk::ShaderStageFlags::COMPUTE,
+                ..Default::default()
+            },
+            layout: pipeline_layout,
+            base_pipeline_index: -1,
+            ..Default::default()
+        };
+
+        let pipelines = unsafe {
+            device.create_compute_pipelines(vk::PipelineCache::null(), &[pipeline_info], None)
+        };
+
+        unsafe { device.destroy_shader_module(shader_module, None) };
+
+        pipelines
+            .map(|pipelines| pipelines[0])
+            .map_err(|(_, err)| err)
+            .context("failed to create present pipeline")
+    }
+
+    fn create_command_buffers(
+        device: &device::Device,
+        count: u32,
+    ) -> Result<(vk::CommandPool, Vec<vk::CommandBuffer>)> {
+        let queue_index = device
+            .family_indices
+            .graphics
+            .ok_or_else(|| Error::msg("graphics family index not present"))?;
+
+        // buffers are reset and recorded again every frame
+        let command_pool_info = vk::CommandPoolCreateInfo {
+            queue_family_index: queue_index,
+            flags: vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER,
+            ..Default::default()
+        };
+
+        let command_pool = unsafe {
+            device
+                .logical_device
+                .create_command_pool(&command_pool_info, None)
+                .context("failed to create present command pool")
+        }?;
+
+        let alloc_info = vk::CommandBufferAllocateInfo {
+            command_buffer_count: count,
+            command_pool,
+            level: vk::CommandBufferLevel::PRIMARY,
+            ..Default::default()
+        };
+
+        let command_buffers = unsafe {
+            device
+                .logical_device
+                .allocate_command_buffers(&alloc_info)
+                .context("failed to allocate present command buffers")
+        }?;
+
+        Ok((command_pool, command_buffers))
+    }
+
+    pub fn new(
+        instance: &ash::Instance,
+        device: &device::Device,
+        swapchain: &swapchain::SwapchainDetails,
+        shader_file: &String,
+    ) -> Result<ComputePresenter> {
+        if !ComputePresenter::is_supported(instance, device, swapchain) {
+            return Err(Error::Unsupported(format!(
+                "swapchain format {:?} cannot be written by a compute shader",
+                swapchain.format.format
+            )));
+        }
+
+        let logical_device = &device.logical_device;
+
+        let descriptor_set_layout = ComputePresenter::create_descriptor_set_layout(logical_device)?;
+        let (descriptor_pool, descriptor_sets) = ComputePresenter::create_descriptor_sets(
+            logical_device,
+            descriptor_set_layout,
+            &swapchain.image_views,
+        )?;
+
+        let push_constant_ranges = [vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::COMPUTE,
+            offset: 0,
+            size: ::std::mem::size_of::<PushConstants>() as u32,
+        }];
+
+        let set_layouts = [descriptor_set_layout];
+        let layout_info = vk::PipelineLayoutCreateInfo {
+            set_layout_count: set_layouts.len() as u32,
+            p_set_layouts: set_layouts.as_ptr(),
+            push_constant_range_count: push_constant_ranges.len() as u32,
+            p_push_constant_ranges: push_constant_ranges.as_ptr(),
+            ..Default::default()
+        };
+
+        let pipeline_layout = unsafe {
+            logical_device
+                .create_pipeline_layout(&layout_info, None)
+                .context("failed to create present pipeline layout")
+        }?;
+
+        let pipeline =
+            ComputePresenter::create_pipeline(logical_device, shader_file, pipeline_layout)?;
+
+        let (command_pool, command_buffers) =
+            ComputePresenter::create_command_buffers(device, swapchain.images.len() as u32)?;
+
+        device.track(
+            registry::ResourceKind::DescriptorSetLayout,
+            descriptor_set_layout,
+        );
+        device.track(registry::ResourceKind::DescriptorPool, descriptor_pool);
+        device.track(registry::ResourceKind::PipelineLayout, pipeline_layout);
+        device.track(registry::ResourceKind::Pipeline, pipeline);
+        device.track(registry::ResourceKind::CommandPool, command_pool);
+
+        Ok(ComputePresenter {
+            device: device.clone(),
+            descriptor_set_layout,
+            descriptor_pool,
+            descriptor_sets,
+            pipeline_layout,
+            pipeline,
+            command_pool,
+            command_buffers,
+            images: swapchain.images.clone(),
+            extent: swapchain.extent,
+            start_time: Instant::now(),
+        })
+    }
+
+    fn layout_barrier(
+        &self,
+        image: vk::Image,
+        old_layout: vk::ImageLayout,
+        new_layout: vk::ImageLayout,
+        src_access_mask: vk::AccessFlags,
+        dst_access_mask: vk::AccessFlags,
+    ) -> vk::ImageMemoryBarrier {
+        vk::ImageMemoryBarrier {
+            src_access_mask,
+            dst_access_mask,
+            old_layout,
+            new_layout,
+            src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            image,
+            subresource_range: vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: 1,
+            },
+            ..Default::default()
+        }
+    }
+
+    // Records the dispatch writing the whole swapchain image, which is left ready to
+    // present. Must only be called once the previous frame using this image has completed.
+    pub fn record(&mut self, frame: &frame::FrameContext) -> Result<vk::CommandBuffer> {
+        let logical_device = &self.device.logical_device;
+
+        let command_buffer = *frame.per_image(&self.command_buffers)?;
+        let descriptor_set = *frame.per_image(&self.descriptor_sets)?;
+        let image = *frame.per_image(&self.images)?;
+
+        let push_constants = PushConstants {
+            extent: [self.extent.width, self.extent.height],
+            time: self.start_time.elapsed().as_secs_f32(),
+        };
+
+        let push_constant_bytes = unsafe {
+            ::std::slice::from_raw_parts(
+                &push_constants as *const PushConstants as *const u8,
+                ::std::mem::size_of::<PushConstants>(),
+            )
+        };
+
+        // the previous contents are overwritten, so they can be discarded
+        let to_general = self.layout_barrier(
+            image,
+            vk::ImageLayout::UNDEFINED,
+            vk::ImageLayout::GENERAL,
+            vk::AccessFlags::empty(),
+            vk::AccessFlags::SHADER_WRITE,
+        );
+
+        // overlays drawn afterwards load the image in the present layout
+        let to_present = self.layout_barrier(
+            image,
+            vk::ImageLayout::GENERAL,
+            vk::ImageLayout::PRESENT_SRC_KHR,
+            vk::AccessFlags::SHADER_WRITE,
+            vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+        );
+
+        unsafe {
+            logical_device
+                .reset_command_buffer(command_buffer, vk::CommandBufferResetFlags::empty())
+                .context("failed to reset present command buffer")?;
+
+            logical_device
+                .begin_command_buffer(command_buffer, &vk::CommandBufferBeginInfo::default())
+                .context("failed to begin recording present command buffer")?;
+
+            logical_device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[to_general],
+            );
+
+            logical_device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline,
+            );
+            logical_device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline_layout,
+                0,
+                &[descriptor_set],
+                &[],
+            );
+            logical_device.cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                push_constant_bytes,
+            );
+            logical_device.cmd_dispatch(
+                command_buffer,
+                (self.extent.width + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE,
+                (self.extent.height + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE,
+                1,
+            );
+
+            logical_device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[to_present],
+            );
+
+            logical_device
+                .end_command_buffer(command_buffer)
+                .context("failed to end present command buffer recording")?;
+        }
+
+        Ok(command_buffer)
+    }
+
+    // The device has to be idle
+    pub fn destroy(&mut self) {
+        self.device.untrack(self.command_pool);
+        self.device.untrack(self.pipeline);
+        self.device.untrack(self.pipeline_layout);
+        self.device.untrack(self.descriptor_pool);
+        self.device.untrack(self.descriptor_set_layout);
+
+        let logical_device = &self.device.logical_device;
+        unsafe {
+            // frees the command buffers and descriptor sets as well
+            logical_device.destroy_command_pool(self.command_pool, None);
+            logical_device.destroy_descriptor_pool(self.descriptor_pool, None);
+
+            logical_device.destroy_pipeline(self.pipeline, None);
+            logical_device.destroy_pipeline_layout(self.pipeline_layout, None);
+            logical_device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+        }
+    }
+}
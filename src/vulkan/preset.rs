@@ -0,0 +1,222 @@
+use ash::vk;
+
+use serde::{Deserialize, Serialize};
+
+use crate::foreign;
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum BlendMode {
+    Opaque,
+    Alpha,
+    Additive,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Preset {
+    Opaque3d,
+    AlphaBlended,
+    Additive,
+    Ui2d,
+    WireframeDebug,
+    DebugLines,
+    ShadowDepth,
+    Fullscreen,
+}
+
+// Rasterizer, depth and blend state of a graphics pipeline.
+// Start from a preset and override individual fields with the `with_*` functions.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct FixedFunctionState {
+    pub topology: vk::PrimitiveTopology,
+    pub polygon_mode: vk::PolygonMode,
+    pub cull_mode: vk::CullModeFlags,
+    pub front_face: vk::FrontFace,
+    pub line_width: f32,
+    pub depth_bias: bool,
+    pub depth_test: bool,
+    pub depth_write: bool,
+    pub depth_compare_op: vk::CompareOp,
+    pub blend_mode: BlendMode,
+    pub color_write_mask: vk::ColorComponentFlags,
+    // has to match the samples of the render pass attachments
+    pub samples: vk::SampleCountFlags,
+}
+
+impl FixedFunctionState {
+    pub fn from_preset(preset: Preset) -> FixedFunctionState {
+        let opaque = FixedFunctionState {
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            polygon_mode: vk::PolygonMode::FILL,
+            cull_mode: vk::CullModeFlags::BACK,
+            front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+            line_width: 1.0,
+            depth_bias: false,
+            depth_test: true,
+            depth_write: true,
+            depth_compare_op: vk::CompareOp::LESS,
+            blend_mode: BlendMode::Opaque,
+            color_write_mask: vk::ColorComponentFlags::all(),
+            samples: vk::SampleCountFlags::TYPE_1,
+        };
+
+        match preset {
+            Preset::Opaque3d => opaque,
+
+            // transparent geometry is tested against depth but does not write it
+            Preset::AlphaBlended => FixedFunctionState {
+                depth_write: false,
+                blend_mode: BlendMode::Alpha,
+                ..opaque
+            },
+
+            Preset::Additive => FixedFunctionState {
+                cull_mode: vk::CullModeFlags::NONE,
+                depth_write: false,
+                blend_mode: BlendMode::Additive,
+                ..opaque
+            },
+
+            Preset::Ui2d => FixedFunctionState {
+                cull_mode: vk::CullModeFlags::NONE,
+                depth_test: false,
+                depth_write: false,
+                blend_mode: BlendMode::Alpha,
+                ..opaque
+            },
+
+            Preset::WireframeDebug => FixedFunctionState {
+                polygon_mode: vk::PolygonMode::LINE,
+                cull_mode: vk::CullModeFlags::NONE,
+                depth_compare_op: vk::CompareOp::LESS_OR_EQUAL,
+                ..opaque
+            },
+
+            // line lists drawn on top of the scene
+            Preset::DebugLines => FixedFunctionState {
+                topology: vk::PrimitiveTopology::LINE_LIST,
+                cull_mode: vk::CullModeFlags::NONE,
+                depth_test: false,
+                depth_write: false,
+                ..opaque
+            },
+
+            // only depth is written, front faces are culled to reduce shadow acne
+            Preset::ShadowDepth => FixedFunctionState {
+                cull_mode: vk::CullModeFlags::FRONT,
+                depth_bias: true,
+                color_write_mask: vk::ColorComponentFlags::empty(),
+                ..opaque
+            },
+
+            // a single triangle covering the screen, eg. post process passes
+            Preset::Fullscreen => FixedFunctionState {
+                cull_mode: vk::CullModeFlags::NONE,
+                depth_test: false,
+                depth_write: false,
+                ..opaque
+            },
+        }
+    }
+
+    pub fn with_topology(mut self, topology: vk::PrimitiveTopology) -> FixedFunctionState {
+        self.topology = topology;
+        self
+    }
+
+    pub fn with_polygon_mode(mut self, polygon_mode: vk::PolygonMode) -> FixedFunctionState {
+        self.polygon_mode = polygon_mode;
+        self
+    }
+
+    pub fn with_cull_mode(mut self, cull_mode: vk::CullModeFlags) -> FixedFunctionState {
+        self.cull_mode = cull_mode;
+        self
+    }
+
+    pub fn with_front_face(mut self, front_face: vk::FrontFace) -> FixedFunctionState {
+        self.front_face = front_face;
+        self
+    }
+
+    pub fn with_depth(
+        mut self,
+        test: bool,
+        write: bool,
+        compare_op: vk::CompareOp,
+    ) -> FixedFunctionState {
+        self.depth_test = test;
+        self.depth_write = write;
+        self.depth_compare_op = compare_op;
+        self
+    }
+
+    pub fn with_blend_mode(mut self, blend_mode: BlendMode) -> FixedFunctionState {
+        self.blend_mode = blend_mode;
+        self
+    }
+
+    pub fn with_samples(mut self, samples: vk::SampleCountFlags) -> FixedFunctionState {
+        self.samples = samples;
+        self
+    }
+
+    pub fn input_assembly_state(&self) -> vk::PipelineInputAssemblyStateCreateInfo {
+        vk::PipelineInputAssemblyStateCreateInfo {
+            primitive_restart_enable: vk::FALSE,
+            topology: self.topology,
+            ..Default::default()
+        }
+    }
+
+    pub fn rasterization_state(&self) -> vk::PipelineRasterizationStateCreateInfo {
+        vk::PipelineRasterizationStateCreateInfo {
+            depth_clamp_enable: vk::FALSE,
+            rasterizer_discard_enable: vk::FALSE,
+            polygon_mode: self.polygon_mode,
+            line_width: self.line_width,
+            cull_mode: self.cull_mode,
+            front_face: self.front_face,
+            depth_bias_enable: foreign::to_vk_bool(self.depth_bias),
+            depth_bias_constant_factor: if self.depth_bias { 1.25 } else { 0.0 },
+            depth_bias_slope_factor: if self.depth_bias { 1.75 } else { 0.0 },
+            ..Default::default()
+        }
+    }
+
+    pub fn multisample_state(&self) -> vk::PipelineMultisampleStateCreateInfo {
+        vk::PipelineMultisampleStateCreateInfo {
+            sample_shading_enable: vk::FALSE,
+            rasterization_samples: self.samples,
+            ..Default::default()
+        }
+    }
+
+    pub fn color_blend_attachment_state(&self) -> vk::PipelineColorBlendAttachmentState {
+        let (blend_enable, src_color_blend_factor, dst_color_blend_factor) = match self.blend_mode {
+            BlendMode::Opaque => (vk::FALSE, vk::BlendFactor::ONE, vk::BlendFactor::ZERO),
+            BlendMode::Alpha => (
+                vk::TRUE,
+                vk::BlendFactor::SRC_ALPHA,
+                vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+            ),
+            BlendMode::Additive => (vk::TRUE, vk::BlendFactor::SRC_ALPHA, vk::BlendFactor::ONE),
+        };
+
+        vk::PipelineColorBlendAttachmentState {
+            blend_enable,
+            color_write_mask: self.color_write_mask,
+            src_color_blend_factor,
+            dst_color_blend_factor,
+            color_blend_op: vk::BlendOp::ADD,
+            src_alpha_blend_factor: vk::BlendFactor::ONE,
+            dst_alpha_blend_factor: vk::BlendFactor::ZERO,
+            alpha_blend_op: vk::BlendOp::ADD,
+        }
+    }
+}
+
+impl Default for FixedFunctionState {
+    fn default() -> FixedFunctionState {
+        FixedFunctionState::from_preset(Preset::Opaque3d)
+    }
+}
@@ -0,0 +1,187 @@
+use ash::version::InstanceV1_0;
+use ash::vk;
+use ash::{vk_version_major, vk_version_minor, vk_version_patch};
+
+use crate::error::{Context, Result};
+
+use serde::Serialize;
+
+use crate::foreign;
+
+use super::instance::VulkanInstance;
+use super::surface;
+use super::swapchain;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SurfaceFormatReport {
+    pub format: String,
+    pub color_space: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SurfaceReport {
+    pub formats: Vec<SurfaceFormatReport>,
+    pub present_modes: Vec<String>,
+    pub min_image_count: u32,
+    pub max_image_count: u32,
+    pub current_extent: (u32, u32),
+    pub min_image_extent: (u32, u32),
+    pub max_image_extent: (u32, u32),
+    pub supported_usage: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LimitsReport {
+    pub max_image_dimension_2d: u32,
+    pub max_framebuffer_width: u32,
+    pub max_framebuffer_height: u32,
+    pub max_bound_descriptor_sets: u32,
+    pub max_per_stage_descriptor_samplers: u32,
+    pub max_uniform_buffer_range: u32,
+    pub max_storage_buffer_range: u32,
+    pub max_push_constants_size: u32,
+    pub max_sampler_anisotropy: f32,
+    pub min_uniform_buffer_offset_alignment: u64,
+    pub timestamp_period: f32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceReport {
+    pub name: String,
+    pub device_type: String,
+    pub vendor_id: u32,
+    pub device_id: u32,
+    pub api_version: String,
+    pub driver_version: u32,
+    pub limits: LimitsReport,
+    pub extensions: Vec<String>,
+    // only present when probed against a surface
+    pub surface: Option<SurfaceReport>,
+}
+
+fn version_to_string(version: u32) -> String {
+    format!(
+        "{}.{}.{}",
+        vk_version_major!(version),
+        vk_version_minor!(version),
+        vk_version_patch!(version)
+    )
+}
+
+fn extent_tuple(extent: vk::Extent2D) -> (u32, u32) {
+    (extent.width, extent.height)
+}
+
+impl LimitsReport {
+    fn from_limits(limits: &vk::PhysicalDeviceLimits) -> LimitsReport {
+        LimitsReport {
+            max_image_dimension_2d: limits.max_image_dimension2_d,
+            max_framebuffer_width: limits.max_framebuffer_width,
+            max_framebuffer_height: limits.max_framebuffer_height,
+            max_bound_descriptor_sets: limits.max_bound_descriptor_sets,
+            max_per_stage_descriptor_samplers: limits.max_per_stage_descriptor_samplers,
+            max_uniform_buffer_range: limits.max_uniform_buffer_range,
+            max_storage_buffer_range: limits.max_storage_buffer_range,
+            max_push_constants_size: limits.max_push_constants_size,
+            max_sampler_anisotropy: limits.max_sampler_anisotropy,
+            min_uniform_buffer_offset_alignment: limits.min_uniform_buffer_offset_alignment,
+            timestamp_period: limits.timestamp_period,
+        }
+    }
+}
+
+impl SurfaceReport {
+    fn query(
+        physical_device: vk::PhysicalDevice,
+        surface_info: &surface::SurfaceInfo,
+    ) -> Result<SurfaceReport> {
+        let support = swapchain::SupportDetail::query(physical_device, surface_info)?;
+        let capabilities = support.capabilities;
+
+        Ok(SurfaceReport {
+            formats: support
+                .formats
+                .iter()
+                .map(|format| SurfaceFormatReport {
+                    format: format!("{:?}", format.format),
+                    color_space: format!("{:?}", format.color_space),
+                })
+                .collect(),
+            present_modes: support
+                .present_modes
+                .iter()
+                .map(|mode| format!("{:?}", mode))
+                .collect(),
+            min_image_count: capabilities.min_image_count,
+            max_image_count: capabilities.max_image_count,
+            current_extent: extent_tuple(capabilities.current_extent),
+            min_image_extent: extent_tuple(capabilities.min_image_extent),
+            max_image_extent: extent_tuple(capabilities.max_image_extent),
+            supported_usage: format!("{:?}", capabilities.supported_usage_flags),
+        })
+    }
+}
+
+impl DeviceReport {
+    fn query(
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        surface_info: Option<&surface::SurfaceInfo>,
+    ) -> Result<DeviceReport> {
+        let properties = unsafe { instance.get_physical_device_properties(physical_device) };
+
+        let extensions = unsafe {
+            instance
+                .enumerate_device_extension_properties(physical_device)
+                .context("failed to get device extension properties")
+        }?
+        .iter()
+        .map(|extension| foreign::vk_to_string(&extension.extension_name))
+        .collect();
+
+        let surface = surface_info
+            .map(|surface_info| SurfaceReport::query(physical_device, surface_info))
+            .transpose()?;
+
+        Ok(DeviceReport {
+            name: foreign::vk_to_string(&properties.device_name),
+            device_type: format!("{:?}", properties.device_type),
+            vendor_id: properties.vendor_id,
+            device_id: properties.device_id,
+            api_version: version_to_string(properties.api_version),
+            driver_version: properties.driver_version,
+            limits: LimitsReport::from_limits(&properties.limits),
+            extensions,
+            surface,
+        })
+    }
+}
+
+// Collects a capability report for every physical device without creating a
+// logical device or swapchain. Surface support is only reported when a surface is given.
+pub fn probe(
+    instance: &VulkanInstance,
+    surface_info: Option<&surface::SurfaceInfo>,
+) -> Result<Vec<DeviceReport>> {
+    let physical_devices = unsafe {
+        instance
+            .instance
+            .enumerate_physical_devices()
+            .context("failed to enumerate physical devices")
+    }?;
+
+    physical_devices
+        .iter()
+        .map(|physical_device| {
+            DeviceReport::query(&instance.instance, *physical_device, surface_info)
+        })
+        .collect()
+}
+
+pub fn probe_to_json(
+    instance: &VulkanInstance,
+    surface_info: Option<&surface::SurfaceInfo>,
+) -> Result<String> {
+    let reports = probe(instance, surface_info)?;
+    serde_json::to_string_pretty(&reports).context("failed to serialize probe report")
+}
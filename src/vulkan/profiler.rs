@@ -0,0 +1,255 @@
+use ash::version::{DeviceV1_0, InstanceV1_0};
+use ash::vk;
+
+use crate::error::{Context, Result};
+
+use std::time::{Duration, Instant};
+
+use super::frame;
+
+// Two timestamps are written per command buffer, around the render pass
+const QUERIES_PER_BUFFER: u32 = 2;
+
+// Names of the timed gpu workloads that budgets can refer to
+pub const MAIN_PASS: &'static str = "main";
+pub const WHOLE_FRAME: &'static str = "frame";
+
+#[derive(Debug, Copy, Clone, Default)]
+pub struct FrameStats {
+    pub gpu_ms: f32,
+    pub cpu_ms: f32,
+    pub fps: f32,
+    // number of gpu budgets exceeded for long enough to be reported
+    pub budgets_exceeded: u32,
+}
+
+// Upper bound of the gpu time of a pass. A warning is emitted once the budget
+// has been exceeded for `frames` consecutive frames.
+#[derive(Debug, Clone)]
+pub struct GpuBudget {
+    pub pass: String,
+    pub max_ms: f32,
+    pub frames: u32,
+    exceeded_frames: u32,
+}
+
+impl GpuBudget {
+    pub fn new(pass: &str, max_ms: f32, frames: u32) -> GpuBudget {
+        GpuBudget {
+            pass: pass.to_string(),
+            max_ms,
+            frames,
+            exceeded_frames: 0,
+        }
+    }
+
+    pub fn is_exceeded(&self) -> bool {
+        self.exceeded_frames >= self.frames.max(1)
+    }
+
+    // Returns true on the frame the budget becomes exceeded so that it is reported only once
+    fn check(&mut self, elapsed_ms: f32) -> bool {
+        let was_exceeded = self.is_exceeded();
+
+        if elapsed_ms > self.max_ms {
+            self.exceeded_frames += 1;
+        } else {
+            self.exceeded_frames = 0;
+        }
+
+        !was_exceeded && self.is_exceeded()
+    }
+}
+
+pub struct Profiler {
+    // None when the device does not support timestamps on the graphics queue
+    query_pool: Option<vk::QueryPool>,
+    timestamp_period: f32,
+
+    cpu_frame_start: Instant,
+    last_frame_end: Instant,
+    stats: FrameStats,
+
+    // print stats to the console every n frames, disabled when None
+    pub print_interval: Option<u32>,
+    frame_count: u32,
+
+    pub budgets: Vec<GpuBudget>,
+}
+
+impl Profiler {
+    pub fn new(
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &ash::Device,
+        num_buffers: u32,
+    ) -> Result<Profiler> {
+        let properties = unsafe { instance.get_physical_device_properties(physical_device) };
+
+        let query_pool = if properties.limits.timestamp_compute_and_graphics == vk::TRUE {
+            let pool_info = vk::QueryPoolCreateInfo {
+                query_type: vk::QueryType::TIMESTAMP,
+                query_count: num_buffers * QUERIES_PER_BUFFER,
+                ..Default::default()
+            };
+
+            Some(unsafe {
+                device
+                    .create_query_pool(&pool_info, None)
+                    .context("failed to create timestamp query pool")
+            }?)
+        } else {
+            tracing::warn!("timestamp queries not supported, gpu timings are disabled");
+            None
+        };
+
+        Ok(Profiler {
+            query_pool,
+            timestamp_period: properties.limits.timestamp_period,
+            cpu_frame_start: Instant::now(),
+            last_frame_end: Instant::now(),
+            stats: FrameStats::default(),
+            print_interval: None,
+            frame_count: 0,
+            budgets: vec![],
+        })
+    }
+
+    pub fn cmd_begin(&self, device: &ash::Device, command_buffer: vk::CommandBuffer, index: u32) {
+        if let Some(pool) = self.query_pool {
+            let first_query = index * QUERIES_PER_BUFFER;
+            unsafe {
+                device.cmd_reset_query_pool(command_buffer, pool, first_query, QUERIES_PER_BUFFER);
+                device.cmd_write_timestamp(
+                    command_buffer,
+                    vk::PipelineStageFlags::TOP_OF_PIPE,
+                    pool,
+                    first_query,
+                );
+            }
+        }
+    }
+
+    pub fn cmd_end(&self, device: &ash::Device, command_buffer: vk::CommandBuffer, index: u32) {
+        if let Some(pool) = self.query_pool {
+            unsafe {
+                device.cmd_write_timestamp(
+                    command_buffer,
+                    vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                    pool,
+                    index * QUERIES_PER_BUFFER + 1,
+                );
+            }
+        }
+    }
+
+    pub fn begin_cpu_frame(&mut self) {
+        self.cpu_frame_start = Instant::now();
+    }
+
+    pub fn end_cpu_frame(&mut self) {
+        let now = Instant::now();
+        let frame_time = now.duration_since(self.last_frame_end);
+
+        self.stats.cpu_ms = duration_ms(now.duration_since(self.cpu_frame_start));
+        self.stats.fps = if frame_time.as_secs_f32() > 0.0 {
+            1.0 / frame_time.as_secs_f32()
+        } else {
+            0.0
+        };
+        self.last_frame_end = now;
+
+        self.frame_count += 1;
+        if let Some(interval) = self.print_interval {
+            if interval > 0 && self.frame_count % interval == 0 {
+                tracing::info!(
+                    "[Profiler] gpu: {:.3} ms cpu: {:.3} ms fps: {:.1}",
+                    self.stats.gpu_ms,
+                    self.stats.cpu_ms,
+                    self.stats.fps
+                );
+            }
+        }
+    }
+
+    // Reads back the timestamps of a command buffer. Must only be called once the
+    // fence of the submission that used the command buffer has been waited upon.
+    pub fn collect_gpu_time(
+        &mut self,
+        device: &ash::Device,
+        frame: &frame::FrameContext,
+    ) -> Result<()> {
+        let index = frame.image_index();
+        if let Some(pool) = self.query_pool {
+            let mut timestamps = [0u64; QUERIES_PER_BUFFER as usize];
+
+            unsafe {
+                device
+                    .get_query_pool_results(
+                        pool,
+                        index * QUERIES_PER_BUFFER,
+                        QUERIES_PER_BUFFER,
+                        &mut timestamps,
+                        vk::QueryResultFlags::TYPE_64,
+                    )
+                    .context("failed to get timestamp query results")
+            }?;
+
+            let ticks = timestamps[1].saturating_sub(timestamps[0]);
+            self.stats.gpu_ms = ticks as f32 * self.timestamp_period / 1_000_000.0;
+
+            self.check_budgets();
+        }
+
+        Ok(())
+    }
+
+    // Gpu time of every timed workload of the last collected frame
+    pub fn pass_times(&self) -> Vec<(&'static str, f32)> {
+        // the main pass is the only timed pass so far, so it makes up the whole frame
+        vec![
+            (MAIN_PASS, self.stats.gpu_ms),
+            (WHOLE_FRAME, self.stats.gpu_ms),
+        ]
+    }
+
+    fn check_budgets(&mut self) {
+        let pass_times = self.pass_times();
+
+        for budget in self.budgets.iter_mut() {
+            let elapsed_ms = pass_times
+                .iter()
+                .find(|(pass, _)| *pass == budget.pass)
+                .map(|(_, elapsed_ms)| *elapsed_ms);
+
+            if let Some(elapsed_ms) = elapsed_ms {
+                if budget.check(elapsed_ms) {
+                    tracing::warn!(
+                        "[Profiler] warning: {} pass took {:.3} ms, over its budget of {:.3} ms for {} frames",
+                        budget.pass, elapsed_ms, budget.max_ms, budget.frames
+                    );
+                }
+            }
+        }
+
+        self.stats.budgets_exceeded = self
+            .budgets
+            .iter()
+            .filter(|budget| budget.is_exceeded())
+            .count() as u32;
+    }
+
+    pub fn stats(&self) -> FrameStats {
+        self.stats
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        if let Some(pool) = self.query_pool {
+            unsafe { device.destroy_query_pool(pool, None) };
+        }
+    }
+}
+
+fn duration_ms(duration: Duration) -> f32 {
+    duration.as_secs_f32() * 1000.0
+}
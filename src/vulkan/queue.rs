@@ -1,11 +1,15 @@
 use super::device;
 use super::surface;
 
+use ash::prelude::VkResult;
 use ash::vk;
 
 use ash::version::DeviceV1_0;
 use ash::version::InstanceV1_0;
 
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone)]
 pub struct FamilyIndices {
     pub graphics: Option<u32>,
     pub present: Option<u32>,
@@ -70,6 +74,9 @@ impl FamilyIndices {
 pub struct Queue {
     pub graphics: vk::Queue,
     pub present: vk::Queue,
+    // submissions to the queues go through these, see SharedQueue
+    pub graphics_submit: SharedQueue,
+    pub present_submit: SharedQueue,
 }
 
 impl Queue {
@@ -86,6 +93,102 @@ impl Queue {
                 .get_device_queue(device.family_indices.present.unwrap(), 0)
         };
 
-        Queue { graphics, present }
+        Queue {
+            graphics,
+            present,
+            graphics_submit: SharedQueue::new(graphics),
+            present_submit: SharedQueue::new(present),
+        }
+    }
+}
+
+// Locks of the queues that were wrapped, by handle, so every wrapper of a queue shares one
+static QUEUE_LOCKS: Mutex<Vec<(vk::Queue, Arc<Mutex<()>>)>> = Mutex::new(Vec::new());
+
+// A queue that can be submitted to from any thread. vkQueueSubmit, vkQueueWaitIdle and
+// vkQueuePresentKHR need the queue to be externally synchronized, so they are called
+// with the queue's lock held. Wrapping the same handle twice gives the same lock, eg.
+// when the graphics and present queue are one queue.
+#[derive(Clone)]
+pub struct SharedQueue {
+    pub handle: vk::Queue,
+    lock: Arc<Mutex<()>>,
+}
+
+impl SharedQueue {
+    pub fn new(handle: vk::Queue) -> SharedQueue {
+        // a thread that panicked while holding a lock leaves nothing inconsistent behind
+        let mut locks = QUEUE_LOCKS.lock().unwrap_or_else(|e| e.into_inner());
+
+        let lock = match locks.iter().find(|(queue, _)| *queue == handle) {
+            Some((_, lock)) => lock.clone(),
+            None => {
+                let lock = Arc::new(Mutex::new(()));
+                locks.push((handle, lock.clone()));
+                lock
+            }
+        };
+
+        SharedQueue { handle, lock }
+    }
+
+    // Runs f with exclusive access to the queue
+    pub fn with_lock<T, F>(&self, f: F) -> T
+    where
+        F: FnOnce(vk::Queue) -> T,
+    {
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        f(self.handle)
+    }
+
+    pub fn submit(
+        &self,
+        device: &ash::Device,
+        submits: &[vk::SubmitInfo],
+        fence: vk::Fence,
+    ) -> VkResult<()> {
+        self.with_lock(|queue| unsafe { device.queue_submit(queue, submits, fence) })
+    }
+
+    pub fn wait_idle(&self, device: &ash::Device) -> VkResult<()> {
+        self.with_lock(|queue| unsafe { device.queue_wait_idle(queue) })
+    }
+
+    // Returns whether the swapchain is suboptimal
+    pub fn present(
+        &self,
+        loader: &ash::extensions::khr::Swapchain,
+        present_info: &vk::PresentInfoKHR,
+    ) -> VkResult<bool> {
+        self.with_lock(|queue| unsafe { loader.queue_present(queue, present_info) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ash::vk::Handle;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn wrappers_of_a_queue_share_its_lock() {
+        assert_send_sync::<SharedQueue>();
+        assert_send_sync::<device::Device>();
+
+        let graphics = SharedQueue::new(vk::Queue::from_raw(0x1000));
+        let present = SharedQueue::new(vk::Queue::from_raw(0x1000));
+        let other = SharedQueue::new(vk::Queue::from_raw(0x2000));
+
+        assert!(Arc::ptr_eq(&graphics.lock, &present.lock));
+        assert!(!Arc::ptr_eq(&graphics.lock, &other.lock));
+
+        // other queues can be used while one is locked
+        graphics.with_lock(|_| {
+            assert!(present.lock.try_lock().is_err());
+            assert!(other.lock.try_lock().is_ok());
+        });
+        assert!(present.lock.try_lock().is_ok());
     }
 }
@@ -0,0 +1,917 @@
+use ash::vk;
+
+use crate::error::{Error, Result};
+
+use super::descriptor;
+
+use std::collections::HashMap;
+
+// Reads the resource interface of compiled SPIR-V, so descriptor set layouts, push
+// constant ranges and vertex inputs can be generated from or checked against the shaders.
+// Only the handful of instructions describing the interface are decoded.
+
+const MAGIC: u32 = 0x0723_0203;
+const HEADER_WORDS: usize = 5;
+
+mod op {
+    pub const ENTRY_POINT: u32 = 15;
+    pub const TYPE_INT: u32 = 21;
+    pub const TYPE_FLOAT: u32 = 22;
+    pub const TYPE_VECTOR: u32 = 23;
+    pub const TYPE_MATRIX: u32 = 24;
+    pub const TYPE_IMAGE: u32 = 25;
+    pub const TYPE_SAMPLER: u32 = 26;
+    pub const TYPE_SAMPLED_IMAGE: u32 = 27;
+    pub const TYPE_ARRAY: u32 = 28;
+    pub const TYPE_RUNTIME_ARRAY: u32 = 29;
+    pub const TYPE_STRUCT: u32 = 30;
+    pub const TYPE_POINTER: u32 = 32;
+    pub const CONSTANT: u32 = 43;
+    pub const VARIABLE: u32 = 59;
+    pub const DECORATE: u32 = 71;
+    pub const MEMBER_DECORATE: u32 = 72;
+}
+
+mod decoration {
+    pub const BUFFER_BLOCK: u32 = 3;
+    pub const ARRAY_STRIDE: u32 = 6;
+    pub const MATRIX_STRIDE: u32 = 7;
+    pub const BUILT_IN: u32 = 11;
+    pub const LOCATION: u32 = 30;
+    pub const BINDING: u32 = 33;
+    pub const DESCRIPTOR_SET: u32 = 34;
+    pub const OFFSET: u32 = 35;
+}
+
+mod storage_class {
+    pub const UNIFORM_CONSTANT: u32 = 0;
+    pub const INPUT: u32 = 1;
+    pub const UNIFORM: u32 = 2;
+    pub const PUSH_CONSTANT: u32 = 9;
+    pub const STORAGE_BUFFER: u32 = 12;
+}
+
+const DIM_BUFFER: u32 = 5;
+const DIM_SUBPASS_DATA: u32 = 6;
+// the image is used without a sampler, ie. a storage image
+const IMAGE_NOT_SAMPLED: u32 = 2;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Numeric {
+    Float,
+    Int,
+    Uint,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Type {
+    Scalar(Numeric, u32),
+    Vector(u32, u32),
+    Matrix(u32, u32),
+    Image { dim: u32, sampled: u32 },
+    Sampler,
+    SampledImage,
+    Array(u32, u32),
+    RuntimeArray(u32),
+    Struct(Vec<u32>),
+    Pointer(u32, u32),
+}
+
+// A binding of a descriptor set as the shaders declare it. The count is 0 for
+// runtime sized arrays.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SetBinding {
+    pub set: u32,
+    pub binding: descriptor::Binding,
+}
+
+// An input of the vertex stage, the format is the one matching the shader type exactly
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct VertexInput {
+    pub location: u32,
+    pub numeric: Numeric,
+    pub components: u32,
+}
+
+// What a shader, or every stage of a pipeline once merged, expects to be bound
+#[derive(Debug, Clone, Default)]
+pub struct ShaderReflection {
+    pub stage: vk::ShaderStageFlags,
+    pub bindings: Vec<SetBinding>,
+    pub push_constants: Option<vk::PushConstantRange>,
+    pub vertex_inputs: Vec<VertexInput>,
+}
+
+fn invalid(message: &str) -> Error {
+    Error::msg(format!("invalid spirv: {}", message))
+}
+
+fn mismatch(message: String) -> Error {
+    Error::InterfaceMismatch(message)
+}
+
+struct Module {
+    stage: vk::ShaderStageFlags,
+    types: HashMap<u32, Type>,
+    constants: HashMap<u32, u32>,
+    decorations: HashMap<(u32, u32), u32>,
+    member_decorations: HashMap<(u32, u32, u32), u32>,
+    // (id, pointer type, storage class)
+    variables: Vec<(u32, u32, u32)>,
+}
+
+impl Module {
+    fn parse(code: &[u8]) -> Result<Module> {
+        if code.len() % 4 != 0 || code.len() < HEADER_WORDS * 4 {
+            return Err(invalid("code is not a whole number of words"));
+        }
+
+        let words = code
+            .chunks(4)
+            .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+            .collect::<Vec<u32>>();
+
+        if words[0] != MAGIC {
+            return Err(invalid("bad magic number"));
+        }
+
+        let mut module = Module {
+            stage: vk::ShaderStageFlags::empty(),
+            types: HashMap::new(),
+            constants: HashMap::new(),
+            decorations: HashMap::new(),
+            member_decorations: HashMap::new(),
+            variables: vec![],
+        };
+
+        let mut index = HEADER_WORDS;
+        while index < words.len() {
+            let word_count = (words[index] >> 16) as usize;
+            let opcode = words[index] & 0xffff;
+
+            if word_count == 0 || index + word_count > words.len() {
+                return Err(invalid("truncated instruction"));
+            }
+
+            module.instruction(opcode, &words[index + 1..index + word_count])?;
+            index += word_count;
+        }
+
+        Ok(module)
+    }
+
+    fn instruction(&mut self, opcode: u32, operands: &[u32]) -> Result<()> {
+        let operand = |i: usize| {
+            operands
+                .get(i)
+                .cloned()
+                .ok_or_else(|| invalid(&format!("missing operand of opcode {}", opcode)))
+        };
+
+        match opcode {
+            op::ENTRY_POINT => {
+                // the first entry point decides the stage, the engine uses one per module
+                if self.stage.is_empty() {
+                    self.stage = execution_stage(operand(0)?)?;
+                }
+            }
+
+            op::TYPE_INT => {
+                let numeric = if operand(2)? == 1 {
+                    Numeric::Int
+                } else {
+                    Numeric::Uint
+                };
+                self.types
+                    .insert(operand(0)?, Type::Scalar(numeric, operand(1)?));
+            }
+
+            op::TYPE_FLOAT => {
+                self.types
+                    .insert(operand(0)?, Type::Scalar(Numeric::Float, operand(1)?));
+            }
+
+            op::TYPE_VECTOR => {
+                self.types
+                    .insert(operand(0)?, Type::Vector(operand(1)?, operand(2)?));
+            }
+
+            op::TYPE_MATRIX => {
+                self.types
+                    .insert(operand(0)?, Type::Matrix(operand(1)?, operand(2)?));
+            }
+
+            op::TYPE_IMAGE => {
+                self.types.insert(
+                    operand(0)?,
+                    Type::Image {
+                        dim: operand(2)?,
+                        sampled: operand(6)?,
+                    },
+                );
+            }
+
+            op::TYPE_SAMPLER => {
+                self.types.insert(operand(0)?, Type::Sampler);
+            }
+
+            op::TYPE_SAMPLED_IMAGE => {
+                self.types.insert(operand(0)?, Type::SampledImage);
+            }
+
+            op::TYPE_ARRAY => {
+                self.types
+                    .insert(operand(0)?, Type::Array(operand(1)?, operand(2)?));
+            }
+
+            op::TYPE_RUNTIME_ARRAY => {
+                self.types
+                    .insert(operand(0)?, Type::RuntimeArray(operand(1)?));
+            }
+
+            op::TYPE_STRUCT => {
+                self.types
+                    .insert(operand(0)?, Type::Struct(operands[1..].to_vec()));
+            }
+
+            op::TYPE_POINTER => {
+                self.types
+                    .insert(operand(0)?, Type::Pointer(operand(1)?, operand(2)?));
+            }
+
+            // only the low word matters, array lengths never need more
+            op::CONSTANT => {
+                self.constants.insert(operand(1)?, operand(2)?);
+            }
+
+            op::VARIABLE => {
+                self.variables.push((operand(1)?, operand(0)?, operand(2)?));
+            }
+
+            op::DECORATE => {
+                let value = operands.get(2).cloned().unwrap_or(0);
+                self.decorations.insert((operand(0)?, operand(1)?), value);
+            }
+
+            op::MEMBER_DECORATE => {
+                let value = operands.get(3).cloned().unwrap_or(0);
+                self.member_decorations
+                    .insert((operand(0)?, operand(1)?, operand(2)?), value);
+            }
+
+            _ => (),
+        }
+
+        Ok(())
+    }
+
+    fn get_type(&self, id: u32) -> Result<&Type> {
+        self.types
+            .get(&id)
+            .ok_or_else(|| invalid(&format!("unknown type %{}", id)))
+    }
+
+    fn decoration(&self, id: u32, decoration: u32) -> Option<u32> {
+        self.decorations.get(&(id, decoration)).cloned()
+    }
+
+    fn array_length(&self, length_id: u32) -> Result<u32> {
+        self.constants
+            .get(&length_id)
+            .cloned()
+            .ok_or_else(|| invalid(&format!("array length %{} is not a constant", length_id)))
+    }
+
+    // Size in bytes of a type laid out by its offset and stride decorations
+    fn size_of(&self, id: u32, matrix_stride: Option<u32>) -> Result<u32> {
+        match self.get_type(id)? {
+            Type::Scalar(_, width) => Ok(width / 8),
+            Type::Vector(component, count) => Ok(self.size_of(*component, None)? * count),
+            Type::Matrix(column, count) => match matrix_stride {
+                Some(stride) => Ok(stride * count),
+                None => Ok(self.size_of(*column, None)? * count),
+            },
+            Type::Array(element, length) => {
+                let length = self.array_length(*length)?;
+                match self.decoration(id, decoration::ARRAY_STRIDE) {
+                    Some(stride) => Ok(stride * length),
+                    None => Ok(self.size_of(*element, None)? * length),
+                }
+            }
+            // only the fixed part counts, the runtime array is sized by the bound range
+            Type::RuntimeArray(_) => Ok(0),
+            Type::Struct(members) => members
+                .iter()
+                .enumerate()
+                .map(|(index, member)| {
+                    let key = |decoration| (id, index as u32, decoration);
+                    let offset = self
+                        .member_decorations
+                        .get(&key(decoration::OFFSET))
+                        .cloned()
+                        .unwrap_or(0);
+                    let stride = self
+                        .member_decorations
+                        .get(&key(decoration::MATRIX_STRIDE))
+                        .cloned();
+
+                    self.size_of(*member, stride).map(|size| offset + size)
+                })
+                .collect::<Result<Vec<u32>>>()
+                .map(|ends| ends.into_iter().max().unwrap_or(0)),
+            other => Err(invalid(&format!("{:?} has no size", other))),
+        }
+    }
+
+    fn descriptor_type(&self, id: u32, storage_class: u32) -> Result<vk::DescriptorType> {
+        match (storage_class, self.get_type(id)?) {
+            (storage_class::UNIFORM_CONSTANT, Type::Sampler) => Ok(vk::DescriptorType::SAMPLER),
+            (storage_class::UNIFORM_CONSTANT, Type::SampledImage) => {
+                Ok(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            }
+            (storage_class::UNIFORM_CONSTANT, Type::Image { dim, sampled }) => {
+                Ok(match (*dim, *sampled) {
+                    (DIM_BUFFER, IMAGE_NOT_SAMPLED) => vk::DescriptorType::STORAGE_TEXEL_BUFFER,
+                    (DIM_BUFFER, _) => vk::DescriptorType::UNIFORM_TEXEL_BUFFER,
+                    (DIM_SUBPASS_DATA, _) => vk::DescriptorType::INPUT_ATTACHMENT,
+                    (_, IMAGE_NOT_SAMPLED) => vk::DescriptorType::STORAGE_IMAGE,
+                    _ => vk::DescriptorType::SAMPLED_IMAGE,
+                })
+            }
+            // before SPIR-V 1.3 storage buffers are uniform blocks decorated BufferBlock
+            (storage_class::UNIFORM, Type::Struct(_)) => {
+                match self.decoration(id, decoration::BUFFER_BLOCK) {
+                    Some(_) => Ok(vk::DescriptorType::STORAGE_BUFFER),
+                    None => Ok(vk::DescriptorType::UNIFORM_BUFFER),
+                }
+            }
+            (storage_class::STORAGE_BUFFER, Type::Struct(_)) => {
+                Ok(vk::DescriptorType::STORAGE_BUFFER)
+            }
+            (_, other) => Err(Error::Unsupported(format!(
+                "no descriptor type for {:?} in storage class {}",
+                other, storage_class
+            ))),
+        }
+    }
+
+    // The descriptor type and count of a variable, unwrapping arrays of descriptors
+    fn descriptor(&self, pointee: u32, storage_class: u32) -> Result<(vk::DescriptorType, u32)> {
+        match self.get_type(pointee)? {
+            Type::Array(element, length) => Ok((
+                self.descriptor_type(*element, storage_class)?,
+                self.array_length(*length)?,
+            )),
+            Type::RuntimeArray(element) => Ok((self.descriptor_type(*element, storage_class)?, 0)),
+            _ => Ok((self.descriptor_type(pointee, storage_class)?, 1)),
+        }
+    }
+
+    fn vertex_input(&self, location: u32, pointee: u32) -> Result<Option<VertexInput>> {
+        let (component, components) = match self.get_type(pointee)? {
+            Type::Vector(component, count) => (*component, *count),
+            Type::Scalar(..) => (pointee, 1),
+            // matrices and arrays span several locations, not used by the engine
+            _ => return Ok(None),
+        };
+
+        match self.get_type(component)? {
+            Type::Scalar(numeric, 32) => Ok(Some(VertexInput {
+                location,
+                numeric: *numeric,
+                components,
+            })),
+            _ => Ok(None),
+        }
+    }
+}
+
+fn execution_stage(model: u32) -> Result<vk::ShaderStageFlags> {
+    match model {
+        0 => Ok(vk::ShaderStageFlags::VERTEX),
+        1 => Ok(vk::ShaderStageFlags::TESSELLATION_CONTROL),
+        2 => Ok(vk::ShaderStageFlags::TESSELLATION_EVALUATION),
+        3 => Ok(vk::ShaderStageFlags::GEOMETRY),
+        4 => Ok(vk::ShaderStageFlags::FRAGMENT),
+        5 => Ok(vk::ShaderStageFlags::COMPUTE),
+        _ => Err(Error::Unsupported(format!(
+            "execution model {} is not supported",
+            model
+        ))),
+    }
+}
+
+// Whether a binding declared on the rust side can hold what the shader reads through it.
+// Dynamic buffers look like plain ones to the shader.
+fn is_compatible(declared: vk::DescriptorType, reflected: vk::DescriptorType) -> bool {
+    declared == reflected
+        || (declared == vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC
+            && reflected == vk::DescriptorType::UNIFORM_BUFFER)
+        || (declared == vk::DescriptorType::STORAGE_BUFFER_DYNAMIC
+            && reflected == vk::DescriptorType::STORAGE_BUFFER)
+}
+
+// How a vertex attribute format is read by the shader, None for formats not known here
+fn format_numeric(format: vk::Format) -> Option<Numeric> {
+    match format {
+        vk::Format::R32_SFLOAT
+        | vk::Format::R32G32_SFLOAT
+        | vk::Format::R32G32B32_SFLOAT
+        | vk::Format::R32G32B32A32_SFLOAT
+        | vk::Format::R16G16_SFLOAT
+        | vk::Format::R16G16B16A16_SFLOAT
+        | vk::Format::R8G8B8A8_UNORM
+        | vk::Format::R8G8B8A8_SNORM
+        | vk::Format::R16G16_UNORM
+        | vk::Format::R16G16_SNORM
+        | vk::Format::R16G16B16A16_SNORM
+        | vk::Format::A2B10G10R10_SNORM_PACK32 => Some(Numeric::Float),
+
+        vk::Format::R32_UINT
+        | vk::Format::R32G32_UINT
+        | vk::Format::R32G32B32_UINT
+        | vk::Format::R32G32B32A32_UINT
+        | vk::Format::R8G8B8A8_UINT
+        | vk::Format::R16G16B16A16_UINT => Some(Numeric::Uint),
+
+        vk::Format::R32_SINT
+        | vk::Format::R32G32_SINT
+        | vk::Format::R32G32B32_SINT
+        | vk::Format::R32G32B32A32_SINT => Some(Numeric::Int),
+
+        _ => None,
+    }
+}
+
+impl ShaderReflection {
+    pub fn from_spirv(code: &[u8]) -> Result<ShaderReflection> {
+        let module = Module::parse(code)?;
+        let stage = module.stage;
+
+        let mut reflection = ShaderReflection {
+            stage,
+            ..Default::default()
+        };
+
+        for &(id, pointer, class) in module.variables.iter() {
+            let pointee = match module.get_type(pointer)? {
+                Type::Pointer(_, pointee) => *pointee,
+                _ => return Err(invalid(&format!("variable %{} is not a pointer", id))),
+            };
+
+            match class {
+                storage_class::UNIFORM_CONSTANT
+                | storage_class::UNIFORM
+                | storage_class::STORAGE_BUFFER => {
+                    let (descriptor_type, count) = module.descriptor(pointee, class)?;
+
+                    reflection.bindings.push(SetBinding {
+                        set: module
+                            .decoration(id, decoration::DESCRIPTOR_SET)
+                            .unwrap_or(0),
+                        binding: descriptor::Binding::new(
+                            module.decoration(id, decoration::BINDING).unwrap_or(0),
+                            descriptor_type,
+                            stage,
+                        )
+                        .with_count(count),
+                    });
+                }
+
+                storage_class::PUSH_CONSTANT => {
+                    reflection.push_constants = Some(vk::PushConstantRange {
+                        stage_flags: stage,
+                        offset: 0,
+                        size: module.size_of(pointee, None)?,
+                    });
+                }
+
+                storage_class::INPUT if stage == vk::ShaderStageFlags::VERTEX => {
+                    if module.decoration(id, decoration::BUILT_IN).is_some() {
+                        continue;
+                    }
+
+                    if let Some(location) = module.decoration(id, decoration::LOCATION) {
+                        if let Some(input) = module.vertex_input(location, pointee)? {
+                            reflection.vertex_inputs.push(input);
+                        }
+                    }
+                }
+
+                _ => (),
+            }
+        }
+
+        reflection
+            .bindings
+            .sort_by_key(|b| (b.set, b.binding.binding));
+        reflection.vertex_inputs.sort_by_key(|input| input.location);
+
+        Ok(reflection)
+    }
+
+    // Combines the stages of a pipeline, bindings used by several stages are visible to all of them
+    pub fn merge(mut self, other: ShaderReflection) -> Result<ShaderReflection> {
+        self.stage |= other.stage;
+
+        for theirs in other.bindings.into_iter() {
+            match self.bindings.iter_mut().find(|ours| {
+                ours.set == theirs.set && ours.binding.binding == theirs.binding.binding
+            }) {
+                Some(ours) => {
+                    if ours.binding.descriptor_type != theirs.binding.descriptor_type
+                        || ours.binding.count != theirs.binding.count
+                    {
+                        return Err(mismatch(format!(
+                            "set {} binding {} is declared as {:?} and {:?} by different stages",
+                            theirs.set,
+                            theirs.binding.binding,
+                            ours.binding.descriptor_type,
+                            theirs.binding.descriptor_type
+                        )));
+                    }
+
+                    ours.binding.stage_flags |= theirs.binding.stage_flags;
+                }
+                None => self.bindings.push(theirs),
+            }
+        }
+        self.bindings.sort_by_key(|b| (b.set, b.binding.binding));
+
+        self.push_constants = match (self.push_constants, other.push_constants) {
+            (Some(ours), Some(theirs)) => Some(vk::PushConstantRange {
+                stage_flags: ours.stage_flags | theirs.stage_flags,
+                offset: ours.offset.min(theirs.offset),
+                size: ours.size.max(theirs.size),
+            }),
+            (ours, theirs) => ours.or(theirs),
+        };
+
+        self.vertex_inputs.extend(other.vertex_inputs);
+        self.vertex_inputs.sort_by_key(|input| input.location);
+
+        Ok(self)
+    }
+
+    // The layout of a set as the shaders use it, eg. for `descriptor::create_set_layout`
+    pub fn set_bindings(&self, set: u32) -> Vec<descriptor::Binding> {
+        self.bindings
+            .iter()
+            .filter(|b| b.set == set)
+            .map(|b| b.binding)
+            .collect()
+    }
+
+    pub fn push_constant_ranges(&self) -> Vec<vk::PushConstantRange> {
+        self.push_constants.into_iter().collect()
+    }
+
+    // Every binding the shaders read from the set has to be declared with a compatible
+    // type, enough descriptors and the stages using it. Unused declarations are fine.
+    pub fn validate_set(&self, set: u32, bindings: &[descriptor::Binding]) -> Result<()> {
+        for reflected in self.bindings.iter().filter(|b| b.set == set) {
+            let reflected = reflected.binding;
+
+            let declared = descriptor::find(bindings, reflected.binding).map_err(|_| {
+                mismatch(format!(
+                    "set {} binding {} ({:?}) is used by the shaders but not declared",
+                    set, reflected.binding, reflected.descriptor_type
+                ))
+            })?;
+
+            if !is_compatible(declared.descriptor_type, reflected.descriptor_type) {
+                return Err(mismatch(format!(
+                    "set {} binding {} is declared as {:?} but the shaders use {:?}",
+                    set, reflected.binding, declared.descriptor_type, reflected.descriptor_type
+                )));
+            }
+
+            if declared.count < reflected.count {
+                return Err(mismatch(format!(
+                    "set {} binding {} declares {} descriptors but the shaders use {}",
+                    set, reflected.binding, declared.count, reflected.count
+                )));
+            }
+
+            if !declared.stage_flags.contains(reflected.stage_flags) {
+                return Err(mismatch(format!(
+                    "set {} binding {} is visible to {:?} but used by {:?}",
+                    set, reflected.binding, declared.stage_flags, reflected.stage_flags
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    // The push constant block has to lie within a range visible to the stages using it
+    pub fn validate_push_constants(&self, ranges: &[vk::PushConstantRange]) -> Result<()> {
+        let reflected = match self.push_constants {
+            Some(reflected) => reflected,
+            None => return Ok(()),
+        };
+
+        let covered = ranges.iter().any(|range| {
+            range.stage_flags.contains(reflected.stage_flags)
+                && range.offset <= reflected.offset
+                && range.offset + range.size >= reflected.offset + reflected.size
+        });
+
+        if covered {
+            Ok(())
+        } else {
+            Err(mismatch(format!(
+                "push constants of {} bytes used by {:?} are not covered by {:?}",
+                reflected.size, reflected.stage_flags, ranges
+            )))
+        }
+    }
+
+    // Every vertex shader input needs an attribute at its location. Formats the engine
+    // does not know are accepted, known ones have to be read as the same kind of number.
+    pub fn validate_vertex_input(
+        &self,
+        attributes: &[vk::VertexInputAttributeDescription],
+    ) -> Result<()> {
+        for input in self.vertex_inputs.iter() {
+            let attribute = attributes
+                .iter()
+                .find(|attribute| attribute.location == input.location)
+                .ok_or_else(|| {
+                    mismatch(format!(
+                        "vertex input at location {} has no attribute",
+                        input.location
+                    ))
+                })?;
+
+            match format_numeric(attribute.format) {
+                Some(numeric) if numeric != input.numeric => {
+                    return Err(mismatch(format!(
+                        "vertex input at location {} is read as {:?} but the attribute is {:?}",
+                        input.location, input.numeric, attribute.format
+                    )))
+                }
+                _ => (),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Assembles a module from (opcode, operands) pairs
+    fn assemble(instructions: &[(u32, &[u32])]) -> Vec<u8> {
+        let mut words = vec![MAGIC, 0x0001_0000, 0, 100, 0];
+
+        for (opcode, operands) in instructions.iter() {
+            words.push(((operands.len() as u32 + 1) << 16) | opcode);
+            words.extend_from_slice(operands);
+        }
+
+        words
+            .iter()
+            .flat_map(|word| word.to_le_bytes().to_vec())
+            .collect()
+    }
+
+    // layout(set = 0, binding = 0) uniform Ubo { mat4 model; } ubo;
+    // layout(set = 1, binding = 2) uniform sampler2D textures[4];
+    // layout(push_constant) uniform Pc { vec4 color; float scale; } pc;
+    // layout(location = 0) in vec3 position;
+    // layout(location = 1) in uvec2 ids;
+    // in gl_VertexIndex
+    fn vertex_module() -> Vec<u8> {
+        assemble(&[
+            (op::ENTRY_POINT, &[0, 1, 0x6e69_616d, 0]),
+            (op::TYPE_FLOAT, &[10, 32]),
+            (op::TYPE_VECTOR, &[11, 10, 4]),
+            (op::TYPE_MATRIX, &[12, 11, 4]),
+            (op::TYPE_STRUCT, &[13, 12]),
+            (op::TYPE_POINTER, &[14, storage_class::UNIFORM, 13]),
+            (op::VARIABLE, &[14, 15, storage_class::UNIFORM]),
+            (op::DECORATE, &[15, decoration::DESCRIPTOR_SET, 0]),
+            (op::DECORATE, &[15, decoration::BINDING, 0]),
+            (op::MEMBER_DECORATE, &[13, 0, decoration::OFFSET, 0]),
+            (op::MEMBER_DECORATE, &[13, 0, decoration::MATRIX_STRIDE, 16]),
+            (op::TYPE_IMAGE, &[20, 10, 1, 0, 0, 0, 1, 0]),
+            (op::TYPE_SAMPLED_IMAGE, &[21, 20]),
+            (op::TYPE_INT, &[22, 32, 0]),
+            (op::CONSTANT, &[22, 23, 4]),
+            (op::TYPE_ARRAY, &[24, 21, 23]),
+            (op::TYPE_POINTER, &[25, storage_class::UNIFORM_CONSTANT, 24]),
+            (op::VARIABLE, &[25, 26, storage_class::UNIFORM_CONSTANT]),
+            (op::DECORATE, &[26, decoration::DESCRIPTOR_SET, 1]),
+            (op::DECORATE, &[26, decoration::BINDING, 2]),
+            (op::TYPE_STRUCT, &[30, 11, 10]),
+            (op::MEMBER_DECORATE, &[30, 0, decoration::OFFSET, 0]),
+            (op::MEMBER_DECORATE, &[30, 1, decoration::OFFSET, 16]),
+            (op::TYPE_POINTER, &[31, storage_class::PUSH_CONSTANT, 30]),
+            (op::VARIABLE, &[31, 32, storage_class::PUSH_CONSTANT]),
+            (op::TYPE_VECTOR, &[40, 10, 3]),
+            (op::TYPE_POINTER, &[41, storage_class::INPUT, 40]),
+            (op::VARIABLE, &[41, 42, storage_class::INPUT]),
+            (op::DECORATE, &[42, decoration::LOCATION, 0]),
+            (op::TYPE_VECTOR, &[43, 22, 2]),
+            (op::TYPE_POINTER, &[44, storage_class::INPUT, 43]),
+            (op::VARIABLE, &[44, 45, storage_class::INPUT]),
+            (op::DECORATE, &[45, decoration::LOCATION, 1]),
+            (op::TYPE_INT, &[46, 32, 1]),
+            (op::TYPE_POINTER, &[47, storage_class::INPUT, 46]),
+            (op::VARIABLE, &[47, 48, storage_class::INPUT]),
+            (op::DECORATE, &[48, decoration::BUILT_IN, 42]),
+        ])
+    }
+
+    // layout(set = 0, binding = 0) uniform Ubo { mat4 model; } ubo;
+    // layout(set = 0, binding = 3) buffer Lights { vec4 lights[]; };
+    fn fragment_module() -> Vec<u8> {
+        assemble(&[
+            (op::ENTRY_POINT, &[4, 1, 0x6e69_616d, 0]),
+            (op::TYPE_FLOAT, &[10, 32]),
+            (op::TYPE_VECTOR, &[11, 10, 4]),
+            (op::TYPE_MATRIX, &[12, 11, 4]),
+            (op::TYPE_STRUCT, &[13, 12]),
+            (op::TYPE_POINTER, &[14, storage_class::UNIFORM, 13]),
+            (op::VARIABLE, &[14, 15, storage_class::UNIFORM]),
+            (op::DECORATE, &[15, decoration::DESCRIPTOR_SET, 0]),
+            (op::DECORATE, &[15, decoration::BINDING, 0]),
+            (op::TYPE_RUNTIME_ARRAY, &[20, 11]),
+            (op::TYPE_STRUCT, &[21, 20]),
+            (op::DECORATE, &[21, decoration::BUFFER_BLOCK]),
+            (op::TYPE_POINTER, &[22, storage_class::UNIFORM, 21]),
+            (op::VARIABLE, &[22, 23, storage_class::UNIFORM]),
+            (op::DECORATE, &[23, decoration::DESCRIPTOR_SET, 0]),
+            (op::DECORATE, &[23, decoration::BINDING, 3]),
+        ])
+    }
+
+    fn pipeline_reflection() -> ShaderReflection {
+        let vertex = ShaderReflection::from_spirv(&vertex_module()).unwrap();
+        let fragment = ShaderReflection::from_spirv(&fragment_module()).unwrap();
+
+        vertex.merge(fragment).unwrap()
+    }
+
+    #[test]
+    fn descriptors_are_read_per_set() {
+        let reflection = pipeline_reflection();
+        let all_stages = vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT;
+
+        assert_eq!(reflection.stage, all_stages);
+        assert_eq!(
+            reflection.set_bindings(0),
+            vec![
+                descriptor::Binding::uniform_buffer(0, all_stages),
+                descriptor::Binding::storage_buffer(3, vk::ShaderStageFlags::FRAGMENT),
+            ]
+        );
+        assert_eq!(
+            reflection.set_bindings(1),
+            vec![
+                descriptor::Binding::combined_image_sampler(2, vk::ShaderStageFlags::VERTEX)
+                    .with_count(4)
+            ]
+        );
+    }
+
+    #[test]
+    fn push_constant_size_follows_member_offsets() {
+        let reflection = pipeline_reflection();
+
+        let ranges = reflection.push_constant_ranges();
+
+        assert_eq!(ranges.len(), 1);
+        assert_eq!(ranges[0].stage_flags, vk::ShaderStageFlags::VERTEX);
+        assert_eq!(ranges[0].offset, 0);
+        assert_eq!(ranges[0].size, 20);
+    }
+
+    #[test]
+    fn vertex_inputs_skip_builtins() {
+        let reflection = pipeline_reflection();
+
+        assert_eq!(
+            reflection.vertex_inputs,
+            vec![
+                VertexInput {
+                    location: 0,
+                    numeric: Numeric::Float,
+                    components: 3,
+                },
+                VertexInput {
+                    location: 1,
+                    numeric: Numeric::Uint,
+                    components: 2,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn declared_sets_are_validated() {
+        let reflection = pipeline_reflection();
+        let all_stages = vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT;
+
+        let declared = [
+            descriptor::Binding::uniform_buffer_dynamic(0, all_stages),
+            descriptor::Binding::storage_buffer(3, vk::ShaderStageFlags::FRAGMENT),
+            // not used by the shaders
+            descriptor::Binding::uniform_buffer(7, vk::ShaderStageFlags::VERTEX),
+        ];
+        assert!(reflection.validate_set(0, &declared).is_ok());
+
+        // binding 3 missing
+        assert!(reflection.validate_set(0, &declared[..1]).is_err());
+
+        // the vertex stage cannot see binding 0
+        let fragment_only = [
+            descriptor::Binding::uniform_buffer(0, vk::ShaderStageFlags::FRAGMENT),
+            declared[1],
+        ];
+        assert!(reflection.validate_set(0, &fragment_only).is_err());
+
+        // wrong type
+        let sampled =
+            [
+                descriptor::Binding::combined_image_sampler(2, vk::ShaderStageFlags::VERTEX)
+                    .with_count(4),
+            ];
+        assert!(reflection.validate_set(1, &sampled).is_ok());
+        assert!(reflection
+            .validate_set(1, &[sampled[0].with_count(2)])
+            .is_err());
+        assert!(reflection
+            .validate_set(
+                1,
+                &[
+                    descriptor::Binding::uniform_buffer(2, vk::ShaderStageFlags::VERTEX)
+                        .with_count(4)
+                ]
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn vertex_attributes_are_validated() {
+        let reflection = pipeline_reflection();
+        let attribute = |location, format| vk::VertexInputAttributeDescription {
+            location,
+            binding: 0,
+            format,
+            offset: 0,
+        };
+
+        assert!(reflection
+            .validate_vertex_input(&[
+                attribute(0, vk::Format::R32G32B32_SFLOAT),
+                attribute(1, vk::Format::R32G32_UINT),
+            ])
+            .is_ok());
+
+        assert!(reflection
+            .validate_vertex_input(&[attribute(0, vk::Format::R32G32B32_SFLOAT)])
+            .is_err());
+
+        assert!(reflection
+            .validate_vertex_input(&[
+                attribute(0, vk::Format::R32G32B32_SINT),
+                attribute(1, vk::Format::R32G32_UINT),
+            ])
+            .is_err());
+    }
+
+    #[test]
+    fn push_constant_ranges_are_validated() {
+        let reflection = pipeline_reflection();
+        let range = |stage_flags, size| vk::PushConstantRange {
+            stage_flags,
+            offset: 0,
+            size,
+        };
+
+        assert!(reflection
+            .validate_push_constants(&[range(vk::ShaderStageFlags::VERTEX, 64)])
+            .is_ok());
+        assert!(reflection
+            .validate_push_constants(&[range(vk::ShaderStageFlags::VERTEX, 16)])
+            .is_err());
+        assert!(reflection
+            .validate_push_constants(&[range(vk::ShaderStageFlags::FRAGMENT, 64)])
+            .is_err());
+        assert!(reflection.validate_push_constants(&[]).is_err());
+    }
+
+    #[test]
+    fn malformed_code_is_rejected() {
+        assert!(ShaderReflection::from_spirv(&[0, 1, 2]).is_err());
+        assert!(ShaderReflection::from_spirv(&[0; 20]).is_err());
+
+        let mut truncated = vertex_module();
+        truncated.truncate(truncated.len() - 4);
+        assert!(ShaderReflection::from_spirv(&truncated).is_err());
+    }
+}
@@ -0,0 +1,408 @@
+use ash::version::DeviceV1_0;
+use ash::vk;
+
+use cgmath::{Deg, InnerSpace, Matrix4, MetricSpace, Point3, Vector3};
+
+use crate::error::{Context, Result};
+use crate::projection;
+
+use super::buffers;
+use super::descriptor;
+use super::device;
+use super::ibl;
+use super::image;
+use super::pipeline;
+use super::registry;
+use super::render_settings;
+
+// Forward and up direction of the camera rendering each face, in the cube face order
+// +x -x +y -y +z -z. The faces are proper rotations, so the captured cube holds the world
+// mirrored in z, see cube_direction.
+const FACE_CAMERAS: [([f32; 3], [f32; 3]); 6] = [
+    ([1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+    ([-1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+    ([0.0, 1.0, 0.0], [0.0, 0.0, 1.0]),
+    ([0.0, -1.0, 0.0], [0.0, 0.0, -1.0]),
+    ([0.0, 0.0, -1.0], [0.0, 1.0, 0.0]),
+    ([0.0, 0.0, 1.0], [0.0, 1.0, 0.0]),
+];
+
+// The world direction a cube map holds at the face's coordinates, -1..1 from the left
+// and top edge. Cube maps are addressed in a left handed space, so the right handed world
+// is stored mirrored in z and sampled with z negated, like shaders/pbr.glsl does.
+pub fn cube_direction(face: u32, u: f32, v: f32) -> Vector3<f32> {
+    let direction = match face {
+        0 => Vector3::new(1.0, -v, -u),
+        1 => Vector3::new(-1.0, -v, u),
+        2 => Vector3::new(u, 1.0, v),
+        3 => Vector3::new(u, -1.0, -v),
+        4 => Vector3::new(u, -v, 1.0),
+        _ => Vector3::new(-u, -v, -1.0),
+    };
+
+    Vector3::new(direction.x, direction.y, -direction.z).normalize()
+}
+
+// The camera a face of a probe is rendered with
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ProbeFace {
+    pub index: u32,
+    pub view: Matrix4<f32>,
+    pub projection: Matrix4<f32>,
+}
+
+impl ProbeFace {
+    pub fn view_projection(&self) -> Matrix4<f32> {
+        self.projection * self.view
+    }
+}
+
+// The six cameras at the position, each covering a quarter turn
+pub fn probe_faces(position: Point3<f32>, near: f32, far: f32) -> Vec<ProbeFace> {
+    let projection = projection::Projection::perspective(Deg(90.0), near, far).matrix(1.0);
+
+    FACE_CAMERAS
+        .iter()
+        .enumerate()
+        .map(|(index, (forward, up))| ProbeFace {
+            index: index as u32,
+            view: Matrix4::look_at_dir(position, Vector3::from(*forward), Vector3::from(*up)),
+            projection,
+        })
+        .collect()
+}
+
+// Where a probe is sampled instead of the environment
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ProbeRegion {
+    pub center: Point3<f32>,
+    pub radius: f32,
+}
+
+impl ProbeRegion {
+    pub fn contains(&self, point: Point3<f32>) -> bool {
+        self.center.distance2(point) <= self.radius * self.radius
+    }
+}
+
+// Index of the region with the closest center among those containing the point
+pub fn nearest_region(regions: &[ProbeRegion], point: Point3<f32>) -> Option<usize> {
+    regions
+        .iter()
+        .enumerate()
+        .filter(|(_, region)| region.contains(point))
+        .min_by(|(_, a), (_, b)| {
+            a.center
+                .distance2(point)
+                .partial_cmp(&b.center.distance2(point))
+                .unwrap_or(std::cmp::Ordering::Equal)
+        })
+        .map(|(index, _)| index)
+}
+
+// A cube map the scene is rendered into from the probe's position, one render pass per
+// face. The render pass matches the scene pass of a color target with the same format, so
+// the pipelines of a material::MaterialLibrary drawing to it can draw the faces as well.
+// Sampled as the prefiltered environment of the draws near it, see
+// material::MaterialLibrary::set_probes.
+pub struct ReflectionProbe {
+    pub cube: image::CubeImage,
+    face_views: Vec<vk::ImageView>,
+    depth_buffer: buffers::DepthBuffer,
+    render_pass: vk::RenderPass,
+    framebuffers: Vec<vk::Framebuffer>,
+    descriptor_pool: Option<vk::DescriptorPool>,
+    // the environment's set with the cube as its prefiltered map, see bind_environment
+    pub descriptor_set: Option<vk::DescriptorSet>,
+    pub near: f32,
+    pub far: f32,
+}
+
+impl ReflectionProbe {
+    pub fn new(
+        instance: &ash::Instance,
+        device: &device::Device,
+        command_pool: vk::CommandPool,
+        queue: vk::Queue,
+        format: vk::Format,
+        size: u32,
+    ) -> Result<ReflectionProbe> {
+        let logical_device = &device.logical_device;
+        let extent = vk::Extent2D {
+            width: size,
+            height: size,
+        };
+
+        let cube = image::CubeImage::with_usage(
+            device,
+            size,
+            format,
+            1,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+        )?;
+        cube.set_name(device, "reflection probe cube");
+
+        let face_views = (0..image::CubeImage::FACES)
+            .map(|face| {
+                cube.face_view(logical_device, face).map(|view| {
+                    device.track(registry::ResourceKind::ImageView, view);
+                    view
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        // every face is cleared, so the depth is shared between them
+        let depth_buffer =
+            buffers::DepthBuffer::new(instance, device, command_pool, &queue, extent)?;
+
+        let render_pass = pipeline::PipelineDetail::create_render_pass(
+            instance,
+            device,
+            pipeline::ColorTarget::offscreen(format),
+        )?;
+        device.track(registry::ResourceKind::RenderPass, render_pass);
+
+        let framebuffers = face_views
+            .iter()
+            .map(|&face_view| {
+                let attachments = [face_view, depth_buffer.image.image_view];
+                let framebuffer_info = vk::FramebufferCreateInfo {
+                    render_pass,
+                    attachment_count: attachments.len() as u32,
+                    p_attachments: attachments.as_ptr(),
+                    width: size,
+                    height: size,
+                    layers: 1,
+                    ..Default::default()
+                };
+
+                let framebuffer = unsafe {
+                    logical_device
+                        .create_framebuffer(&framebuffer_info, None)
+                        .context("failed to create reflection probe framebuffer")
+                }?;
+                device.track(registry::ResourceKind::Framebuffer, framebuffer);
+                Ok(framebuffer)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(ReflectionProbe {
+            cube,
+            face_views,
+            depth_buffer,
+            render_pass,
+            framebuffers,
+            descriptor_pool: None,
+            descriptor_set: None,
+            near: 0.1,
+            far: 100.0,
+        })
+    }
+
+    pub fn with_depth_range(mut self, near: f32, far: f32) -> ReflectionProbe {
+        self.near = near;
+        self.far = far;
+        self
+    }
+
+    // Creates the set the draws near the probe are lit with, the environment's irradiance
+    // and lookup table with the probe's cube as the prefiltered map. The cube only has its
+    // first level, so rough surfaces reflect it as sharply as smooth ones.
+    pub fn bind_environment(
+        &mut self,
+        device: &device::Device,
+        environment: &ibl::EnvironmentLighting,
+    ) -> Result<vk::DescriptorSet> {
+        if let Some(set) = self.descriptor_set {
+            return Ok(set);
+        }
+
+        let logical_device = &device.logical_device;
+        let descriptor_pool =
+            descriptor::create_pool(logical_device, &ibl::EnvironmentLighting::bindings(), 1)?;
+        self.descriptor_pool = Some(descriptor_pool);
+
+        let set =
+            descriptor::allocate_sets(logical_device, descriptor_pool, environment.layout, 1)?[0];
+        environment.write_set_with_prefiltered(logical_device, set, self.cube.image_view)?;
+
+        self.descriptor_set = Some(set);
+        Ok(set)
+    }
+
+    // Renders the faces at the position one after the other, waiting for each. prepare is
+    // called before a face is recorded, eg. to upload its view and projection to the
+    // uniforms the draws read, then draw records the face's draws inside the render pass.
+    // The cube is ready for sampling afterwards.
+    pub fn capture<P, D>(
+        &self,
+        device: &device::Device,
+        command_pool: vk::CommandPool,
+        queue: vk::Queue,
+        position: Point3<f32>,
+        settings: &render_settings::RenderSettings,
+        mut prepare: P,
+        draw: D,
+    ) -> Result<()>
+    where
+        P: FnMut(&ProbeFace) -> Result<()>,
+        D: Fn(vk::CommandBuffer, &ProbeFace),
+    {
+        let logical_device = &device.logical_device;
+        let extent = vk::Extent2D {
+            width: self.cube.size,
+            height: self.cube.size,
+        };
+        let clear_values = settings.clear_values();
+
+        for face in probe_faces(position, self.near, self.far) {
+            prepare(&face)?;
+
+            let render_pass_begin_info = vk::RenderPassBeginInfo {
+                render_pass: self.render_pass,
+                framebuffer: self.framebuffers[face.index as usize],
+                render_area: vk::Rect2D {
+                    offset: vk::Offset2D { x: 0, y: 0 },
+                    extent,
+                },
+                clear_value_count: clear_values.len() as u32,
+                p_clear_values: clear_values.as_ptr(),
+                ..Default::default()
+            };
+
+            buffers::CommandBuffer::record_and_submit_single_command(
+                logical_device,
+                command_pool,
+                queue,
+                |command_buffer| unsafe {
+                    logical_device.cmd_begin_render_pass(
+                        command_buffer,
+                        &render_pass_begin_info,
+                        vk::SubpassContents::INLINE,
+                    );
+                    // the whole face regardless of the settings' viewport
+                    logical_device.cmd_set_viewport(
+                        command_buffer,
+                        0,
+                        &[vk::Viewport {
+                            x: 0.0,
+                            y: 0.0,
+                            width: extent.width as f32,
+                            height: extent.height as f32,
+                            min_depth: 0.0,
+                            max_depth: 1.0,
+                        }],
+                    );
+                    logical_device.cmd_set_scissor(
+                        command_buffer,
+                        0,
+                        &[render_pass_begin_info.render_area],
+                    );
+
+                    draw(command_buffer, &face);
+
+                    logical_device.cmd_end_render_pass(command_buffer);
+                },
+            )?;
+        }
+
+        Ok(())
+    }
+
+    pub fn destroy(&self, device: &device::Device) {
+        if let Some(pool) = self.descriptor_pool {
+            unsafe { device.logical_device.destroy_descriptor_pool(pool, None) };
+        }
+
+        for &framebuffer in self.framebuffers.iter() {
+            device.untrack(framebuffer);
+            unsafe { device.logical_device.destroy_framebuffer(framebuffer, None) };
+        }
+
+        device.untrack(self.render_pass);
+        unsafe {
+            device
+                .logical_device
+                .destroy_render_pass(self.render_pass, None)
+        };
+
+        for &view in self.face_views.iter() {
+            device.untrack(view);
+            unsafe { device.logical_device.destroy_image_view(view, None) };
+        }
+
+        self.depth_buffer.image.destroy(device);
+        self.cube.destroy(device);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use cgmath::Vector4;
+
+    fn ndc(face: &ProbeFace, direction: Vector3<f32>) -> Vector3<f32> {
+        let clip = face.view_projection() * direction.extend(1.0);
+        clip.truncate() / clip.w
+    }
+
+    fn assert_near(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-4,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn faces_render_what_the_cube_is_sampled_with() {
+        let faces = probe_faces(Point3::new(0.0, 0.0, 0.0), 0.1, 10.0);
+        assert_eq!(faces.len(), 6);
+
+        for face in faces.iter() {
+            // the center, right edge and bottom edge of the face, vulkan's y points down
+            for &(u, v) in [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0), (-0.5, -0.5)].iter() {
+                let point = ndc(face, cube_direction(face.index, u, v));
+                assert_near(point.x, u);
+                assert_near(point.y, v);
+                assert!(point.z > 0.0 && point.z < 1.0);
+            }
+        }
+    }
+
+    #[test]
+    fn faces_are_not_mirrored() {
+        // mirrored faces would turn the winding of every triangle around
+        for face in probe_faces(Point3::new(1.0, 2.0, 3.0), 0.1, 10.0) {
+            let view = face.view;
+            let basis = Matrix4::from_cols(view.x, view.y, view.z, Vector4::unit_w());
+            assert_near(cgmath::SquareMatrix::determinant(&basis), 1.0);
+        }
+    }
+
+    #[test]
+    fn the_nearest_containing_region_wins() {
+        let regions = [
+            ProbeRegion {
+                center: Point3::new(0.0, 0.0, 0.0),
+                radius: 10.0,
+            },
+            ProbeRegion {
+                center: Point3::new(4.0, 0.0, 0.0),
+                radius: 2.0,
+            },
+        ];
+
+        assert_eq!(
+            nearest_region(&regions, Point3::new(3.0, 0.0, 0.0)),
+            Some(1)
+        );
+        assert_eq!(
+            nearest_region(&regions, Point3::new(-3.0, 0.0, 0.0)),
+            Some(0)
+        );
+        assert_eq!(nearest_region(&regions, Point3::new(20.0, 0.0, 0.0)), None);
+    }
+}
@@ -0,0 +1,614 @@
+use ash::vk;
+use ash::vk::Handle as VkHandle;
+
+use crate::error::{Context, Error, Result};
+
+use serde::Serialize;
+
+use std::backtrace::{Backtrace, BacktraceStatus};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+use super::buffers;
+use super::device;
+use super::pipeline;
+use super::texture;
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize)]
+pub enum ResourceKind {
+    Buffer,
+    Image,
+    ImageView,
+    Sampler,
+    Pipeline,
+    PipelineLayout,
+    DescriptorSetLayout,
+    DescriptorPool,
+    RenderPass,
+    Framebuffer,
+    CommandPool,
+    Fence,
+    QueryPool,
+}
+
+// Only captured in debug builds, and only resolved when RUST_BACKTRACE is set
+fn capture_backtrace() -> Option<Arc<Backtrace>> {
+    if cfg!(debug_assertions) {
+        Some(Arc::new(Backtrace::capture()))
+    } else {
+        None
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ResourceRecord {
+    pub handle: u64,
+    pub kind: ResourceKind,
+    pub name: String,
+    // size of the memory allocation backing the resource
+    pub size: vk::DeviceSize,
+    pub usage: String,
+    pub format: Option<String>,
+    pub extent: Option<(u32, u32)>,
+    #[serde(skip)]
+    pub backtrace: Option<Arc<Backtrace>>,
+}
+
+// Book-keeping of every live vulkan object created through the crate's wrappers
+#[derive(Debug, Default)]
+pub struct ResourceRegistry {
+    resources: BTreeMap<u64, ResourceRecord>,
+}
+
+impl ResourceRegistry {
+    pub fn register_buffer(
+        &mut self,
+        buffer: vk::Buffer,
+        size: vk::DeviceSize,
+        usage: vk::BufferUsageFlags,
+    ) {
+        let handle = buffer.as_raw();
+
+        self.resources.insert(
+            handle,
+            ResourceRecord {
+                handle,
+                kind: ResourceKind::Buffer,
+                name: format!("buffer {:#x}", handle),
+                size,
+                usage: format!("{:?}", usage),
+                format: None,
+                extent: None,
+                backtrace: capture_backtrace(),
+            },
+        );
+    }
+
+    pub fn register_image(
+        &mut self,
+        image: vk::Image,
+        size: vk::DeviceSize,
+        usage: vk::ImageUsageFlags,
+        format: vk::Format,
+        extent: vk::Extent2D,
+    ) {
+        let handle = image.as_raw();
+
+        self.resources.insert(
+            handle,
+            ResourceRecord {
+                handle,
+                kind: ResourceKind::Image,
+                name: format!("image {:#x}", handle),
+                size,
+                usage: format!("{:?}", usage),
+                format: Some(format!("{:?}", format)),
+                extent: Some((extent.width, extent.height)),
+                backtrace: capture_backtrace(),
+            },
+        );
+    }
+
+    // Objects without memory of their own, eg. pipelines or samplers
+    pub fn register<H: VkHandle>(&mut self, kind: ResourceKind, handle: H) {
+        let handle = handle.as_raw();
+
+        self.resources.insert(
+            handle,
+            ResourceRecord {
+                handle,
+                kind,
+                name: format!("{:?} {:#x}", kind, handle),
+                size: 0,
+                usage: String::new(),
+                format: None,
+                extent: None,
+                backtrace: capture_backtrace(),
+            },
+        );
+    }
+
+    pub fn set_name(&mut self, handle: u64, name: &str) {
+        if let Some(record) = self.resources.get_mut(&handle) {
+            record.name = name.to_string();
+        }
+    }
+
+    pub fn unregister(&mut self, handle: u64) {
+        self.resources.remove(&handle);
+    }
+
+    pub fn snapshot(&self) -> ResourceSnapshot {
+        ResourceSnapshot {
+            resources: self.resources.values().cloned().collect(),
+        }
+    }
+}
+
+// Point in time copy of the registry, eg. for asserting on resource usage in tests
+#[derive(Debug, Clone, Serialize)]
+pub struct ResourceSnapshot {
+    pub resources: Vec<ResourceRecord>,
+}
+
+impl ResourceSnapshot {
+    pub fn count(&self, kind: ResourceKind) -> usize {
+        self.resources.iter().filter(|r| r.kind == kind).count()
+    }
+
+    pub fn total_size(&self, kind: ResourceKind) -> vk::DeviceSize {
+        self.resources
+            .iter()
+            .filter(|r| r.kind == kind)
+            .map(|r| r.size)
+            .sum()
+    }
+
+    pub fn total_bytes(&self) -> vk::DeviceSize {
+        self.resources.iter().map(|r| r.size).sum()
+    }
+
+    pub fn find(&self, name: &str) -> Option<&ResourceRecord> {
+        self.resources.iter().find(|r| r.name == name)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.resources.is_empty()
+    }
+
+    // Lists the objects that are still alive, None when everything was destroyed
+    pub fn leak_report(&self) -> Option<String> {
+        if self.resources.is_empty() {
+            return None;
+        }
+
+        let mut report = format!(
+            "{} vulkan objects were never destroyed:\n",
+            self.resources.len()
+        );
+        for record in self.resources.iter() {
+            let _ = writeln!(
+                report,
+                "  {:?} {} ({} bytes)",
+                record.kind, record.name, record.size
+            );
+
+            match record.backtrace.as_ref() {
+                Some(backtrace) if backtrace.status() == BacktraceStatus::Captured => {
+                    let _ = writeln!(report, "    created at:\n{}", backtrace);
+                }
+                _ => (),
+            }
+        }
+
+        if cfg!(debug_assertions) && std::env::var_os("RUST_BACKTRACE").is_none() {
+            report.push_str("set RUST_BACKTRACE=1 to see where they were created\n");
+        }
+
+        Some(report)
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(self).context("failed to serialize resource snapshot")
+    }
+}
+
+// Index of a resource in a HandlePool along with the generation of its slot when the
+// handle was given out. Removing the resource bumps the generation, so a copy of the
+// handle kept around afterwards is caught instead of reaching a destroyed vulkan object.
+pub struct Handle<T> {
+    index: u32,
+    generation: u32,
+    kind: PhantomData<fn() -> T>,
+}
+
+pub type BufferHandle = Handle<buffers::BufferInfo>;
+pub type TextureHandle = Handle<texture::Texture>;
+pub type PipelineHandle = Handle<pipeline::PipelineDetail>;
+
+impl<T> Handle<T> {
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+}
+
+// Not derived, which would require T to implement the traits as well
+impl<T> Copy for Handle<T> {}
+
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Handle<T> {
+        *self
+    }
+}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Handle<T>) -> bool {
+        self.index == other.index && self.generation == other.generation
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> Hash for Handle<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.index.hash(state);
+        self.generation.hash(state);
+    }
+}
+
+impl<T> std::fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Handle({}v{})", self.index, self.generation)
+    }
+}
+
+struct Slot<T> {
+    generation: u32,
+    value: Option<T>,
+    // where the last resource of the slot was removed, debug builds only
+    removed_at: Option<Arc<Backtrace>>,
+}
+
+// Resources of one type addressed by generational handles. Slots are reused once their
+// resource is removed, the handles given out for the old resource no longer resolve.
+pub struct HandlePool<T> {
+    kind: &'static str,
+    slots: Vec<Slot<T>>,
+    free: Vec<u32>,
+}
+
+impl<T> HandlePool<T> {
+    pub fn new(kind: &'static str) -> HandlePool<T> {
+        HandlePool {
+            kind,
+            slots: vec![],
+            free: vec![],
+        }
+    }
+
+    pub fn insert(&mut self, value: T) -> Handle<T> {
+        let index = match self.free.pop() {
+            Some(index) => {
+                self.slots[index as usize].value = Some(value);
+                index
+            }
+            None => {
+                self.slots.push(Slot {
+                    generation: 0,
+                    value: Some(value),
+                    removed_at: None,
+                });
+                self.slots.len() as u32 - 1
+            }
+        };
+
+        Handle {
+            index,
+            generation: self.slots[index as usize].generation,
+            kind: PhantomData,
+        }
+    }
+
+    fn stale(&self, handle: Handle<T>) -> Error {
+        let slot = match self.slots.get(handle.index as usize) {
+            Some(slot) => slot,
+            None => {
+                return Error::StaleHandle(format!(
+                    "{} handle {:?} was not given out by this pool",
+                    self.kind, handle
+                ))
+            }
+        };
+
+        let mut message = format!(
+            "{} handle {:?} is stale, the resource was destroyed",
+            self.kind, handle
+        );
+        match slot.removed_at.as_ref() {
+            Some(backtrace) if backtrace.status() == BacktraceStatus::Captured => {
+                let _ = write!(message, " at:\n{}", backtrace);
+            }
+            _ => (),
+        }
+
+        Error::StaleHandle(message)
+    }
+
+    pub fn get(&self, handle: Handle<T>) -> Result<&T> {
+        match self.slots.get(handle.index as usize) {
+            Some(Slot {
+                generation,
+                value: Some(value),
+                ..
+            }) if *generation == handle.generation => Ok(value),
+            _ => Err(self.stale(handle)),
+        }
+    }
+
+    pub fn get_mut(&mut self, handle: Handle<T>) -> Result<&mut T> {
+        if !self.contains(handle) {
+            return Err(self.stale(handle));
+        }
+
+        match self.slots[handle.index as usize].value.as_mut() {
+            Some(value) => Ok(value),
+            None => unreachable!("live slots hold a value"),
+        }
+    }
+
+    // Swaps the resource behind the handle, eg. after reloading it. The handle stays valid
+    // and the old resource is returned to be destroyed.
+    pub fn replace(&mut self, handle: Handle<T>, value: T) -> Result<T> {
+        let current = self.get_mut(handle)?;
+        Ok(std::mem::replace(current, value))
+    }
+
+    pub fn contains(&self, handle: Handle<T>) -> bool {
+        self.get(handle).is_ok()
+    }
+
+    // Takes the resource out of the pool for it to be destroyed, every copy of the
+    // handle is stale afterwards
+    pub fn remove(&mut self, handle: Handle<T>) -> Result<T> {
+        if !self.contains(handle) {
+            return Err(self.stale(handle));
+        }
+
+        let slot = &mut self.slots[handle.index as usize];
+        slot.generation = slot.generation.wrapping_add(1);
+        slot.removed_at = capture_backtrace();
+        self.free.push(handle.index);
+
+        slot.value
+            .take()
+            .ok_or_else(|| Error::msg("live slots hold a value"))
+    }
+
+    // Takes every resource out of the pool, eg. to destroy them on shutdown
+    pub fn drain(&mut self) -> Vec<T> {
+        let handles = self.handles();
+        handles
+            .into_iter()
+            .filter_map(|handle| self.remove(handle).ok())
+            .collect()
+    }
+
+    pub fn handles(&self) -> Vec<Handle<T>> {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter(|(_, slot)| slot.value.is_some())
+            .map(|(index, slot)| Handle {
+                index: index as u32,
+                generation: slot.generation,
+                kind: PhantomData,
+            })
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len() - self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+// The buffers, textures and pipelines handed out to applications by handle, see
+// Engine::create_buffer. The vulkan objects stay in here, so none of them can be reached
+// through a handle once it was destroyed.
+pub struct ResourceHandles {
+    buffers: HandlePool<buffers::BufferInfo>,
+    textures: HandlePool<texture::Texture>,
+    pipelines: HandlePool<pipeline::PipelineDetail>,
+}
+
+impl ResourceHandles {
+    pub fn new() -> ResourceHandles {
+        ResourceHandles {
+            buffers: HandlePool::new("buffer"),
+            textures: HandlePool::new("texture"),
+            pipelines: HandlePool::new("pipeline"),
+        }
+    }
+
+    pub fn add_buffer(&mut self, buffer: buffers::BufferInfo) -> BufferHandle {
+        self.buffers.insert(buffer)
+    }
+
+    pub fn buffer(&self, handle: BufferHandle) -> Result<&buffers::BufferInfo> {
+        self.buffers.get(handle)
+    }
+
+    // The whole buffer, eg. for Engine::bind_storage_buffer
+    pub fn buffer_descriptor(&self, handle: BufferHandle) -> Result<vk::DescriptorBufferInfo> {
+        let buffer = self.buffers.get(handle)?;
+
+        Ok(vk::DescriptorBufferInfo {
+            buffer: buffer.buffer,
+            offset: 0,
+            range: buffer.size(),
+        })
+    }
+
+    pub fn add_texture(&mut self, texture: texture::Texture) -> TextureHandle {
+        self.textures.insert(texture)
+    }
+
+    pub fn texture(&self, handle: TextureHandle) -> Result<&texture::Texture> {
+        self.textures.get(handle)
+    }
+
+    // Destroys the buffer the handle referred to, the gpu has to be done with it
+    pub fn replace_buffer(
+        &mut self,
+        device: &device::Device,
+        handle: BufferHandle,
+        buffer: buffers::BufferInfo,
+    ) -> Result<()> {
+        self.buffers
+            .replace(handle, buffer)
+            .map(|old| old.destroy(device))
+    }
+
+    // Destroys the texture the handle referred to, the gpu has to be done with it
+    pub fn replace_texture(
+        &mut self,
+        device: &device::Device,
+        handle: TextureHandle,
+        texture: texture::Texture,
+    ) -> Result<()> {
+        self.textures
+            .replace(handle, texture)
+            .map(|old| old.destroy(device))
+    }
+
+    pub fn add_pipeline(&mut self, pipeline: pipeline::PipelineDetail) -> PipelineHandle {
+        self.pipelines.insert(pipeline)
+    }
+
+    pub fn pipeline(&self, handle: PipelineHandle) -> Result<&pipeline::PipelineDetail> {
+        self.pipelines.get(handle)
+    }
+
+    pub fn pipeline_mut(
+        &mut self,
+        handle: PipelineHandle,
+    ) -> Result<&mut pipeline::PipelineDetail> {
+        self.pipelines.get_mut(handle)
+    }
+
+    // The destroy functions need the gpu to be done with the resource
+    pub fn destroy_buffer(&mut self, device: &device::Device, handle: BufferHandle) -> Result<()> {
+        self.buffers
+            .remove(handle)
+            .map(|buffer| buffer.destroy(device))
+    }
+
+    // The texture is the caller's to destroy, eg. once it is evicted from a texture::TextureCache
+    pub fn remove_texture(&mut self, handle: TextureHandle) -> Result<texture::Texture> {
+        self.textures.remove(handle)
+    }
+
+    pub fn destroy_texture(
+        &mut self,
+        device: &device::Device,
+        handle: TextureHandle,
+    ) -> Result<()> {
+        self.textures
+            .remove(handle)
+            .map(|texture| texture.destroy(device))
+    }
+
+    pub fn destroy_pipeline(
+        &mut self,
+        device: &device::Device,
+        handle: PipelineHandle,
+    ) -> Result<()> {
+        self.pipelines
+            .remove(handle)
+            .map(|pipeline| pipeline.destroy(device))
+    }
+
+    // Destroys every pipeline and the buffers and textures not kept, their handles become
+    // stale. The kept ones stay, eg. to be replaced with ones created on a new device.
+    pub fn destroy_except(
+        &mut self,
+        device: &device::Device,
+        kept_buffers: &[BufferHandle],
+        kept_textures: &[TextureHandle],
+    ) {
+        for pipeline in self.pipelines.drain() {
+            pipeline.destroy(device);
+        }
+
+        for handle in self.textures.handles() {
+            if !kept_textures.contains(&handle) {
+                let _ = self.destroy_texture(device, handle);
+            }
+        }
+        for handle in self.buffers.handles() {
+            if !kept_buffers.contains(&handle) {
+                let _ = self.destroy_buffer(device, handle);
+            }
+        }
+    }
+
+    // Destroys whatever the application did not, the device has to be idle
+    pub fn destroy(&mut self, device: &device::Device) {
+        for pipeline in self.pipelines.drain() {
+            pipeline.destroy(device);
+        }
+        for texture in self.textures.drain() {
+            texture.destroy(device);
+        }
+        for buffer in self.buffers.drain() {
+            buffer.destroy(device);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removed_handles_go_stale_when_the_slot_is_reused() {
+        let mut pool = HandlePool::new("number");
+
+        let first = pool.insert(1);
+        assert_eq!(*pool.get(first).unwrap(), 1);
+        assert_eq!(pool.remove(first).unwrap(), 1);
+
+        let second = pool.insert(2);
+        assert_eq!(second.index(), first.index());
+        assert_ne!(second, first);
+
+        assert!(match pool.get(first) {
+            Err(Error::StaleHandle(_)) => true,
+            _ => false,
+        });
+        assert!(pool.remove(first).is_err());
+        assert_eq!(*pool.get(second).unwrap(), 2);
+        assert_eq!(pool.len(), 1);
+    }
+
+    #[test]
+    fn draining_empties_the_pool() {
+        let mut pool = HandlePool::new("number");
+        let handles = (0..3).map(|n| pool.insert(n)).collect::<Vec<_>>();
+        pool.remove(handles[1]).unwrap();
+
+        assert_eq!(pool.drain(), vec![0, 2]);
+        assert!(pool.is_empty());
+        assert!(handles.iter().all(|&handle| !pool.contains(handle)));
+    }
+}
@@ -0,0 +1,182 @@
+use ash::vk;
+
+// Region of the surface the scene is drawn to, in pixels. Drawing outside of the
+// surface is cut off by the scissor.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ViewportRect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+    pub min_depth: f32,
+    pub max_depth: f32,
+}
+
+impl ViewportRect {
+    pub fn new(x: f32, y: f32, width: f32, height: f32) -> ViewportRect {
+        ViewportRect {
+            x,
+            y,
+            width,
+            height,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        }
+    }
+
+    pub fn with_depth_range(mut self, min_depth: f32, max_depth: f32) -> ViewportRect {
+        self.min_depth = min_depth;
+        self.max_depth = max_depth;
+        self
+    }
+
+    pub fn viewport(&self) -> vk::Viewport {
+        vk::Viewport {
+            x: self.x,
+            y: self.y,
+            width: self.width,
+            height: self.height,
+            min_depth: self.min_depth,
+            max_depth: self.max_depth,
+        }
+    }
+
+    // The part of the rect inside of the surface
+    pub fn scissor(&self, surface_extent: vk::Extent2D) -> vk::Rect2D {
+        let clamp_x = |x: f32| (x.max(0.0) as u32).min(surface_extent.width);
+        let clamp_y = |y: f32| (y.max(0.0) as u32).min(surface_extent.height);
+
+        let (left, right) = (clamp_x(self.x), clamp_x(self.x + self.width));
+        let (top, bottom) = (clamp_y(self.y), clamp_y(self.y + self.height));
+
+        vk::Rect2D {
+            offset: vk::Offset2D {
+                x: left as i32,
+                y: top as i32,
+            },
+            extent: vk::Extent2D {
+                width: right - left,
+                height: bottom - top,
+            },
+        }
+    }
+}
+
+// How the scene pass starts and where it draws. The values end up in the recorded
+// command buffers, so changing them re-records every image's commands, see
+// buffers::BufferDetails::set_render_settings.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RenderSettings {
+    pub clear_color: [f32; 4],
+    // eg. 0.0 with a reversed depth range
+    pub clear_depth: f32,
+    pub clear_stencil: u32,
+    // the whole surface when not set
+    pub viewport: Option<ViewportRect>,
+}
+
+impl Default for RenderSettings {
+    fn default() -> RenderSettings {
+        RenderSettings {
+            clear_color: [0.0, 0.0, 0.0, 1.0],
+            clear_depth: 1.0,
+            clear_stencil: 0,
+            viewport: None,
+        }
+    }
+}
+
+impl RenderSettings {
+    pub fn with_clear_color(mut self, clear_color: [f32; 4]) -> RenderSettings {
+        self.clear_color = clear_color;
+        self
+    }
+
+    pub fn with_clear_depth(mut self, clear_depth: f32) -> RenderSettings {
+        self.clear_depth = clear_depth;
+        self
+    }
+
+    pub fn with_viewport(mut self, viewport: Option<ViewportRect>) -> RenderSettings {
+        self.viewport = viewport;
+        self
+    }
+
+    // Color then depth, in the order of the scene render pass attachments
+    pub fn clear_values(&self) -> [vk::ClearValue; 2] {
+        [
+            vk::ClearValue {
+                color: vk::ClearColorValue {
+                    float32: self.clear_color,
+                },
+            },
+            vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue {
+                    depth: self.clear_depth,
+                    stencil: self.clear_stencil,
+                },
+            },
+        ]
+    }
+
+    pub fn viewport(&self, surface_extent: vk::Extent2D) -> vk::Viewport {
+        self.viewport
+            .unwrap_or_else(|| {
+                ViewportRect::new(
+                    0.0,
+                    0.0,
+                    surface_extent.width as f32,
+                    surface_extent.height as f32,
+                )
+            })
+            .viewport()
+    }
+
+    pub fn scissor(&self, surface_extent: vk::Extent2D) -> vk::Rect2D {
+        match self.viewport {
+            Some(viewport) => viewport.scissor(surface_extent),
+            None => vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: surface_extent,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXTENT: vk::Extent2D = vk::Extent2D {
+        width: 800,
+        height: 600,
+    };
+
+    #[test]
+    fn defaults_cover_the_surface() {
+        let settings = RenderSettings::default();
+
+        let viewport = settings.viewport(EXTENT);
+        assert_eq!((viewport.width, viewport.height), (800.0, 600.0));
+        assert_eq!(settings.scissor(EXTENT).extent.width, 800);
+
+        let clear_values = settings.clear_values();
+        unsafe {
+            assert_eq!(clear_values[0].color.float32, [0.0, 0.0, 0.0, 1.0]);
+            assert_eq!(clear_values[1].depth_stencil.depth, 1.0);
+        }
+    }
+
+    #[test]
+    fn scissor_is_clamped_to_the_surface() {
+        let settings = RenderSettings::default()
+            .with_viewport(Some(ViewportRect::new(-100.0, 400.0, 500.0, 400.0)));
+
+        let scissor = settings.scissor(EXTENT);
+        assert_eq!((scissor.offset.x, scissor.offset.y), (0, 400));
+        assert_eq!((scissor.extent.width, scissor.extent.height), (400, 200));
+
+        // the viewport itself is used as given
+        assert_eq!(settings.viewport(EXTENT).x, -100.0);
+    }
+}
@@ -0,0 +1,218 @@
+use ash::vk;
+
+use std::collections::HashSet;
+
+// Core features of vk::PhysicalDeviceFeatures an application can require or ask for
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum DeviceFeature {
+    SamplerAnisotropy,
+    // line and point polygon modes, eg. the wireframe toggle
+    FillModeNonSolid,
+    WideLines,
+    LargePoints,
+    DepthClamp,
+    DepthBiasClamp,
+    GeometryShader,
+    TessellationShader,
+    MultiDrawIndirect,
+    ShaderStorageImageWriteWithoutFormat,
+    ShaderFloat64,
+    ShaderInt64,
+    ShaderInt16,
+    TextureCompressionBc,
+    TextureCompressionEtc2,
+    TextureCompressionAstcLdr,
+    PipelineStatisticsQuery,
+}
+
+impl DeviceFeature {
+    fn flag(&self, features: &mut vk::PhysicalDeviceFeatures) -> &mut vk::Bool32 {
+        match self {
+            DeviceFeature::SamplerAnisotropy => &mut features.sampler_anisotropy,
+            DeviceFeature::FillModeNonSolid => &mut features.fill_mode_non_solid,
+            DeviceFeature::WideLines => &mut features.wide_lines,
+            DeviceFeature::LargePoints => &mut features.large_points,
+            DeviceFeature::DepthClamp => &mut features.depth_clamp,
+            DeviceFeature::DepthBiasClamp => &mut features.depth_bias_clamp,
+            DeviceFeature::GeometryShader => &mut features.geometry_shader,
+            DeviceFeature::TessellationShader => &mut features.tessellation_shader,
+            DeviceFeature::MultiDrawIndirect => &mut features.multi_draw_indirect,
+            DeviceFeature::ShaderStorageImageWriteWithoutFormat => {
+                &mut features.shader_storage_image_write_without_format
+            }
+            DeviceFeature::ShaderFloat64 => &mut features.shader_float64,
+            DeviceFeature::ShaderInt64 => &mut features.shader_int64,
+            DeviceFeature::ShaderInt16 => &mut features.shader_int16,
+            DeviceFeature::TextureCompressionBc => &mut features.texture_compression_bc,
+            DeviceFeature::TextureCompressionEtc2 => &mut features.texture_compression_etc2,
+            DeviceFeature::TextureCompressionAstcLdr => &mut features.texture_compression_astc_ldr,
+            DeviceFeature::PipelineStatisticsQuery => &mut features.pipeline_statistics_query,
+        }
+    }
+
+    pub fn is_set(&self, features: &vk::PhysicalDeviceFeatures) -> bool {
+        let mut features = *features;
+        *self.flag(&mut features) == vk::TRUE
+    }
+
+    pub fn set(&self, features: &mut vk::PhysicalDeviceFeatures) {
+        *self.flag(features) = vk::TRUE;
+    }
+}
+
+// What an application needs of the gpu, see EngineConfig::device_requirements. Adapters
+// missing a required feature or extension are not suitable, optional ones are enabled
+// when available and reported by Device::is_feature_enabled and is_extension_enabled.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceRequirements {
+    pub required_features: Vec<DeviceFeature>,
+    pub optional_features: Vec<DeviceFeature>,
+    pub required_extensions: Vec<String>,
+    pub optional_extensions: Vec<String>,
+}
+
+impl Default for DeviceRequirements {
+    // Textures are sampled anisotropically. Line polygon mode is only used for the
+    // wireframe toggle, format-less storage writes for compute presentation and multiple
+    // indirect draws per call for gpu culling.
+    fn default() -> DeviceRequirements {
+        DeviceRequirements {
+            required_features: vec![DeviceFeature::SamplerAnisotropy],
+            optional_features: vec![
+                DeviceFeature::FillModeNonSolid,
+                DeviceFeature::ShaderStorageImageWriteWithoutFormat,
+                DeviceFeature::MultiDrawIndirect,
+            ],
+            required_extensions: vec![],
+            optional_extensions: vec![],
+        }
+    }
+}
+
+impl DeviceRequirements {
+    pub fn with_required_feature(mut self, feature: DeviceFeature) -> DeviceRequirements {
+        self.required_features.push(feature);
+        self
+    }
+
+    pub fn with_optional_feature(mut self, feature: DeviceFeature) -> DeviceRequirements {
+        self.optional_features.push(feature);
+        self
+    }
+
+    pub fn with_required_extension(mut self, name: &str) -> DeviceRequirements {
+        self.required_extensions.push(name.to_string());
+        self
+    }
+
+    pub fn with_optional_extension(mut self, name: &str) -> DeviceRequirements {
+        self.optional_extensions.push(name.to_string());
+        self
+    }
+
+    // The required features and extensions the adapter lacks, empty when it is suitable
+    pub fn missing(
+        &self,
+        supported_features: &vk::PhysicalDeviceFeatures,
+        available_extensions: &HashSet<String>,
+    ) -> Vec<String> {
+        let features = self
+            .required_features
+            .iter()
+            .filter(|feature| !feature.is_set(supported_features))
+            .map(|feature| format!("{:?}", feature));
+        let extensions = self
+            .required_extensions
+            .iter()
+            .filter(|name| !available_extensions.contains(*name))
+            .cloned();
+
+        features.chain(extensions).collect()
+    }
+
+    // The required features along with the supported optional ones
+    pub fn features_to_enable(
+        &self,
+        supported_features: &vk::PhysicalDeviceFeatures,
+    ) -> vk::PhysicalDeviceFeatures {
+        let mut features = vk::PhysicalDeviceFeatures::default();
+
+        let optional = self
+            .optional_features
+            .iter()
+            .filter(|feature| feature.is_set(supported_features));
+        for feature in self.required_features.iter().chain(optional) {
+            feature.set(&mut features);
+        }
+
+        features
+    }
+
+    // The required extensions along with the available optional ones
+    pub fn extensions_to_enable(&self, available_extensions: &HashSet<String>) -> Vec<String> {
+        let optional = self
+            .optional_extensions
+            .iter()
+            .filter(|name| available_extensions.contains(*name));
+
+        let mut extensions: Vec<String> = vec![];
+        for name in self.required_extensions.iter().chain(optional) {
+            if !extensions.contains(name) {
+                extensions.push(name.clone());
+            }
+        }
+
+        extensions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn supported() -> vk::PhysicalDeviceFeatures {
+        vk::PhysicalDeviceFeatures {
+            sampler_anisotropy: vk::TRUE,
+            fill_mode_non_solid: vk::TRUE,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn missing_requirements_rule_adapters_out() {
+        let available = vec!["VK_KHR_swapchain".to_string()].into_iter().collect();
+
+        let requirements = DeviceRequirements::default();
+        assert!(requirements.missing(&supported(), &available).is_empty());
+
+        let requirements = requirements
+            .with_required_feature(DeviceFeature::GeometryShader)
+            .with_required_extension("VK_KHR_ray_query")
+            .with_optional_extension("VK_EXT_memory_budget");
+        assert_eq!(
+            requirements.missing(&supported(), &available),
+            vec!["GeometryShader".to_string(), "VK_KHR_ray_query".to_string()]
+        );
+    }
+
+    #[test]
+    fn only_supported_optional_features_are_enabled() {
+        let requirements = DeviceRequirements::default()
+            .with_optional_feature(DeviceFeature::WideLines)
+            .with_optional_extension("VK_EXT_memory_budget")
+            .with_optional_extension("VK_KHR_swapchain")
+            .with_required_extension("VK_KHR_swapchain");
+
+        let enabled = requirements.features_to_enable(&supported());
+        assert!(DeviceFeature::SamplerAnisotropy.is_set(&enabled));
+        assert!(DeviceFeature::FillModeNonSolid.is_set(&enabled));
+        assert!(!DeviceFeature::MultiDrawIndirect.is_set(&enabled));
+        assert!(!DeviceFeature::WideLines.is_set(&enabled));
+
+        let available = vec!["VK_KHR_swapchain".to_string()].into_iter().collect();
+        assert_eq!(
+            requirements.extensions_to_enable(&available),
+            vec!["VK_KHR_swapchain".to_string()]
+        );
+    }
+}
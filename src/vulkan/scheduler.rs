@@ -0,0 +1,429 @@
+use ash::version::DeviceV1_0;
+use ash::vk;
+
+use crate::error::{Context, Error, Result};
+
+use std::collections::{HashMap, VecDeque};
+
+use super::device;
+use super::queue;
+use super::registry;
+use super::trace;
+
+// Gpu time per frame given to background work when nothing else is configured
+pub const DEFAULT_BACKGROUND_BUDGET_MS: f32 = 2.0;
+
+// Number of background batches that can be in flight at once
+const SLOT_COUNT: usize = 3;
+
+// Cost assumed for a kind of job until one has been measured
+const INITIAL_COST_MS: f32 = 0.5;
+
+// Weight of the latest measurement in the running cost estimate of a kind
+const COST_SMOOTHING: f32 = 0.25;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum JobKind {
+    Upload,
+    MipGeneration,
+    Blit,
+    AccelerationStructureBuild,
+    Defragmentation,
+    Other,
+}
+
+// Gpu work that is not needed by the current frame, recorded once it gets scheduled
+pub struct GpuJob {
+    pub name: String,
+    pub kind: JobKind,
+    record: Box<dyn FnOnce(&ash::Device, vk::CommandBuffer) + Send>,
+}
+
+impl GpuJob {
+    pub fn new<F>(name: &str, kind: JobKind, record: F) -> GpuJob
+    where
+        F: FnOnce(&ash::Device, vk::CommandBuffer) + Send + 'static,
+    {
+        GpuJob {
+            name: name.to_string(),
+            kind,
+            record: Box::new(record),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+pub struct SchedulerStats {
+    pub pending: usize,
+    pub submitted_last_frame: usize,
+    // measured gpu time of the last completed batch
+    pub last_batch_ms: f32,
+    pub budget_ms: f32,
+}
+
+struct Slot {
+    command_buffer: vk::CommandBuffer,
+    fence: vk::Fence,
+    // kinds of the jobs in the batch, none when the slot is free
+    batch: Option<Vec<JobKind>>,
+}
+
+// Queues background gpu jobs (uploads, blits, acceleration structure builds, ..)
+// and submits only as many per frame as are estimated to fit in the budget.
+// Estimates come from timestamps written around every batch.
+pub struct GpuScheduler {
+    device: device::Device,
+    queue: queue::SharedQueue,
+    command_pool: vk::CommandPool,
+    slots: Vec<Slot>,
+    next_slot: usize,
+    // None when the device does not support timestamps, jobs are then scheduled on estimates only
+    query_pool: Option<vk::QueryPool>,
+    timestamp_period: f32,
+
+    jobs: VecDeque<GpuJob>,
+    costs: HashMap<JobKind, f32>,
+    pub budget_ms: f32,
+    stats: SchedulerStats,
+}
+
+impl GpuScheduler {
+    pub fn new(device: &device::Device, queue: vk::Queue, budget_ms: f32) -> Result<GpuScheduler> {
+        let logical_device = &device.logical_device;
+
+        let queue_index = device
+            .family_indices
+            .graphics
+            .ok_or_else(|| Error::msg("graphics family index not present"))?;
+
+        let pool_info = vk::CommandPoolCreateInfo {
+            queue_family_index: queue_index,
+            flags: vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER,
+            ..Default::default()
+        };
+
+        let command_pool = unsafe {
+            logical_device
+                .create_command_pool(&pool_info, None)
+                .context("failed to create scheduler command pool")
+        }?;
+
+        let allocate_info = vk::CommandBufferAllocateInfo {
+            command_pool,
+            level: vk::CommandBufferLevel::PRIMARY,
+            command_buffer_count: SLOT_COUNT as u32,
+            ..Default::default()
+        };
+
+        let command_buffers = unsafe {
+            logical_device
+                .allocate_command_buffers(&allocate_info)
+                .context("failed to allocate scheduler command buffers")
+        }?;
+
+        let slots = command_buffers
+            .into_iter()
+            .map(|command_buffer| {
+                let fence = unsafe {
+                    logical_device
+                        .create_fence(&vk::FenceCreateInfo::default(), None)
+                        .context("failed to create scheduler fence")
+                }?;
+                device.track(registry::ResourceKind::Fence, fence);
+
+                Ok(Slot {
+                    command_buffer,
+                    fence,
+                    batch: None,
+                })
+            })
+            .collect::<Result<Vec<Slot>>>()?;
+
+        let query_pool = if device.limits.timestamp_compute_and_graphics == vk::TRUE {
+            let pool_info = vk::QueryPoolCreateInfo {
+                query_type: vk::QueryType::TIMESTAMP,
+                query_count: 2 * SLOT_COUNT as u32,
+                ..Default::default()
+            };
+
+            Some(unsafe {
+                logical_device
+                    .create_query_pool(&pool_info, None)
+                    .context("failed to create scheduler query pool")
+            }?)
+        } else {
+            None
+        };
+
+        device.track(registry::ResourceKind::CommandPool, command_pool);
+        if let Some(pool) = query_pool {
+            device.track(registry::ResourceKind::QueryPool, pool);
+        }
+
+        Ok(GpuScheduler {
+            device: device.clone(),
+            queue: queue::SharedQueue::new(queue),
+            command_pool,
+            slots,
+            next_slot: 0,
+            query_pool,
+            timestamp_period: device.limits.timestamp_period,
+            jobs: VecDeque::new(),
+            costs: HashMap::new(),
+            budget_ms,
+            stats: SchedulerStats {
+                budget_ms,
+                ..Default::default()
+            },
+        })
+    }
+
+    pub fn schedule(&mut self, job: GpuJob) {
+        self.jobs.push_back(job);
+    }
+
+    pub fn pending(&self) -> usize {
+        self.jobs.len()
+    }
+
+    pub fn stats(&self) -> SchedulerStats {
+        SchedulerStats {
+            pending: self.jobs.len(),
+            budget_ms: self.budget_ms,
+            ..self.stats
+        }
+    }
+
+    pub fn estimated_cost(&self, kind: JobKind) -> f32 {
+        self.costs.get(&kind).cloned().unwrap_or(INITIAL_COST_MS)
+    }
+
+    // Folds the measured time of a batch into the estimates of the kinds it contained,
+    // splitting it according to the current estimates
+    fn record_cost(&mut self, kinds: &[JobKind], measured_ms: f32) {
+        let estimated: f32 = kinds.iter().map(|&kind| self.estimated_cost(kind)).sum();
+        if estimated <= 0.0 {
+            return;
+        }
+
+        for &kind in kinds {
+            let share = measured_ms * self.estimated_cost(kind) / estimated;
+            let cost = self.estimated_cost(kind);
+            self.costs
+                .insert(kind, cost + (share - cost) * COST_SMOOTHING);
+        }
+    }
+
+    fn is_signaled(&self, fence: vk::Fence) -> Result<bool> {
+        match unsafe {
+            self.device
+                .logical_device
+                .wait_for_fences(&[fence], true, 0)
+        } {
+            Ok(_) => Ok(true),
+            Err(vk::Result::TIMEOUT) => Ok(false),
+            Err(err) => Err(err).context("failed to query scheduler fence"),
+        }
+    }
+
+    fn read_batch_time(&self, slot: usize) -> Result<Option<f32>> {
+        let pool = match self.query_pool {
+            Some(pool) => pool,
+            None => return Ok(None),
+        };
+
+        let mut timestamps = [0u64; 2];
+        unsafe {
+            self.device
+                .logical_device
+                .get_query_pool_results(
+                    pool,
+                    2 * slot as u32,
+                    2,
+                    &mut timestamps,
+                    vk::QueryResultFlags::TYPE_64,
+                )
+                .context("failed to read scheduler timestamps")
+        }?;
+
+        let ticks = timestamps[1].saturating_sub(timestamps[0]);
+        Ok(Some(ticks as f32 * self.timestamp_period / 1_000_000.0))
+    }
+
+    // Frees the slots whose batches have completed and updates the cost estimates
+    fn collect(&mut self) -> Result<()> {
+        for index in 0..self.slots.len() {
+            if self.slots[index].batch.is_none() || !self.is_signaled(self.slots[index].fence)? {
+                continue;
+            }
+
+            let kinds = self.slots[index].batch.take().unwrap_or_default();
+            if let Some(measured_ms) = self.read_batch_time(index)? {
+                self.stats.last_batch_ms = measured_ms;
+                self.record_cost(&kinds, measured_ms);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Takes the jobs that fit in the budget, at least one so a job estimated over
+    // budget still makes progress
+    fn take_jobs(&mut self) -> Vec<GpuJob> {
+        let mut spent_ms = 0.0;
+        let mut batch = vec![];
+
+        while let Some(job) = self.jobs.front() {
+            let cost = self.estimated_cost(job.kind);
+            if !batch.is_empty() && spent_ms + cost > self.budget_ms {
+                break;
+            }
+
+            spent_ms += cost;
+            batch.extend(self.jobs.pop_front());
+        }
+
+        batch
+    }
+
+    // Submits this frame's share of background work, returns the number of jobs submitted
+    pub fn submit_frame(&mut self) -> Result<usize> {
+        self.collect()?;
+        self.stats.submitted_last_frame = 0;
+
+        // all slots are busy, the gpu is behind on background work already
+        if self.jobs.is_empty() || self.slots[self.next_slot].batch.is_some() {
+            return Ok(0);
+        }
+
+        let batch = self.take_jobs();
+        let slot = self.next_slot;
+        let command_buffer = self.slots[slot].command_buffer;
+        let fence = self.slots[slot].fence;
+        let logical_device = &self.device.logical_device;
+
+        let begin_info = vk::CommandBufferBeginInfo {
+            flags: vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT,
+            ..Default::default()
+        };
+
+        unsafe {
+            logical_device
+                .begin_command_buffer(command_buffer, &begin_info)
+                .context("failed to begin scheduler command buffer")?;
+
+            if let Some(pool) = self.query_pool {
+                logical_device.cmd_reset_query_pool(command_buffer, pool, 2 * slot as u32, 2);
+                logical_device.cmd_write_timestamp(
+                    command_buffer,
+                    vk::PipelineStageFlags::TOP_OF_PIPE,
+                    pool,
+                    2 * slot as u32,
+                );
+            }
+        }
+
+        let kinds = batch.iter().map(|job| job.kind).collect::<Vec<JobKind>>();
+        for job in batch {
+            tracing::debug!("scheduling background job {} ({:?})", job.name, job.kind);
+            (job.record)(logical_device, command_buffer);
+        }
+
+        unsafe {
+            if let Some(pool) = self.query_pool {
+                logical_device.cmd_write_timestamp(
+                    command_buffer,
+                    vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                    pool,
+                    2 * slot as u32 + 1,
+                );
+            }
+
+            logical_device
+                .end_command_buffer(command_buffer)
+                .context("failed to end scheduler command buffer")?;
+
+            logical_device
+                .reset_fences(&[fence])
+                .context("failed to reset scheduler fence")?;
+        }
+
+        let command_buffers = [command_buffer];
+        let submit_info = vk::SubmitInfo {
+            command_buffer_count: command_buffers.len() as u32,
+            p_command_buffers: command_buffers.as_ptr(),
+            ..Default::default()
+        };
+
+        trace::call("vkQueueSubmit", &submit_info, || {
+            self.queue
+                .submit(logical_device, &[submit_info], fence)
+                .context("failed to submit background jobs")
+        })?;
+
+        self.stats.submitted_last_frame = kinds.len();
+        self.slots[slot].batch = Some(kinds);
+        self.next_slot = (slot + 1) % self.slots.len();
+
+        Ok(self.stats.submitted_last_frame)
+    }
+
+    // Submits every queued job regardless of the budget and waits for all of them
+    pub fn flush(&mut self) -> Result<()> {
+        let budget_ms = self.budget_ms;
+        self.budget_ms = std::f32::INFINITY;
+
+        let result = (|| {
+            while !self.jobs.is_empty() {
+                self.wait_idle()?;
+                self.submit_frame()?;
+            }
+            self.wait_idle()
+        })();
+
+        self.budget_ms = budget_ms;
+        result
+    }
+
+    pub fn wait_idle(&mut self) -> Result<()> {
+        let fences = self
+            .slots
+            .iter()
+            .filter(|slot| slot.batch.is_some())
+            .map(|slot| slot.fence)
+            .collect::<Vec<vk::Fence>>();
+
+        if !fences.is_empty() {
+            unsafe {
+                self.device
+                    .logical_device
+                    .wait_for_fences(&fences, true, std::u64::MAX)
+                    .context("failed to wait for background jobs")
+            }?;
+        }
+
+        self.collect()
+    }
+
+    // Queued jobs that were never submitted are dropped
+    pub fn destroy(&mut self) {
+        let logical_device = &self.device.logical_device;
+        self.jobs.clear();
+
+        unsafe {
+            // frees the command buffers as well
+            self.device.untrack(self.command_pool);
+            logical_device.destroy_command_pool(self.command_pool, None);
+
+            for slot in self.slots.iter() {
+                self.device.untrack(slot.fence);
+                logical_device.destroy_fence(slot.fence, None);
+            }
+
+            if let Some(pool) = self.query_pool.take() {
+                self.device.untrack(pool);
+                logical_device.destroy_query_pool(pool, None);
+            }
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use ash::vk;
+
+use cgmath::Matrix4;
+
+use crate::error::{Error, Result};
+
+use super::descriptor;
+use super::object_uniforms;
+
+// Must match MAX_JOINTS of shaders/skinned.vert
+pub const MAX_JOINTS: usize = 64;
+
+pub const SKINNED_VERTEX_SHADER_FILE: &str = "shaders/skinned.vert";
+
+// The first binding after pipeline::PipelineDetail::scene_bindings, where
+// shaders/skinned.vert reads the palette
+pub const BONES_BINDING: u32 = 3;
+
+// app::VertexData with the joints that move the vertex and their weights, which add up
+// to 1. Unused joints have a weight of 0. Drawn with shaders/skinned.vert, which feeds
+// the same outputs as shaders/shader.vert to the fragment shader, or skinned ahead of the
+// passes by compute_skinning::ComputeSkinning.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SkinnedVertex {
+    pub pos: [f32; 3],
+    pub color: [f32; 3],
+    pub tex_coord: [f32; 2],
+    pub normal: [f32; 3],
+    pub joints: [u32; 4],
+    pub weights: [f32; 4],
+}
+
+impl Default for SkinnedVertex {
+    // fully bound to the first joint
+    fn default() -> SkinnedVertex {
+        SkinnedVertex {
+            pos: [0.0, 0.0, 0.0],
+            color: [1.0, 1.0, 1.0],
+            tex_coord: [0.0, 0.0],
+            normal: [0.0, 0.0, 1.0],
+            joints: [0, 0, 0, 0],
+            weights: [1.0, 0.0, 0.0, 0.0],
+        }
+    }
+}
+
+crate::impl_vertex_data!(SkinnedVertex {
+    pos,
+    color,
+    tex_coord,
+    normal,
+    joints,
+    weights
+});
+
+// Joint matrices of one skinned mesh, eg. from animation::AnimationPlayer::joint_matrices.
+// Joints past the skeleton's are left at identity.
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct BonePalette {
+    pub joints: [[[f32; 4]; 4]; MAX_JOINTS],
+}
+
+impl BonePalette {
+    pub fn identity() -> BonePalette {
+        let identity: [[f32; 4]; 4] = Matrix4::from_scale(1.0).into();
+
+        BonePalette {
+            joints: [identity; MAX_JOINTS],
+        }
+    }
+
+    pub fn from_matrices(matrices: &[Matrix4<f32>]) -> Result<BonePalette> {
+        if matrices.len() > MAX_JOINTS {
+            return Err(Error::OutOfRange(format!(
+                "skeletons have up to {} joints, got {}",
+                MAX_JOINTS,
+                matrices.len()
+            )));
+        }
+
+        let mut palette = BonePalette::identity();
+        for (joint, matrix) in palette.joints.iter_mut().zip(matrices.iter()) {
+            *joint = (*matrix).into();
+        }
+
+        Ok(palette)
+    }
+}
+
+// One palette per skinned mesh and swapchain image, the mesh's palette is selected with
+// a dynamic offset like any object_uniforms::ObjectUniforms
+pub type BoneBuffer = object_uniforms::ObjectUniforms<BonePalette>;
+
+// Layout binding of the palette in the scene set, eg. added to EngineConfig::scene_buffers
+pub fn bone_binding() -> descriptor::Binding {
+    BoneBuffer::binding(BONES_BINDING, vk::ShaderStageFlags::VERTEX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use cgmath::Vector3;
+
+    #[test]
+    fn palette_fills_missing_joints_with_identity() {
+        let moved = Matrix4::from_translation(Vector3::new(1.0, 2.0, 3.0));
+        let palette = BonePalette::from_matrices(&[moved]).unwrap();
+
+        assert_eq!(palette.joints[0], <[[f32; 4]; 4]>::from(moved));
+        assert_eq!(palette.joints[1], BonePalette::identity().joints[0]);
+        assert_eq!(palette.joints[1][3], [0.0, 0.0, 0.0, 1.0]);
+
+        assert!(BonePalette::from_matrices(&vec![moved; MAX_JOINTS + 1]).is_err());
+        // a whole palette fits the smallest uniform buffer range vulkan allows
+        assert!(std::mem::size_of::<BonePalette>() <= 16384);
+    }
+}
@@ -0,0 +1,642 @@
+use ash::version::{DeviceV1_0, InstanceV1_1};
+use ash::vk;
+
+use cgmath::{Deg, Matrix4, Rad, Vector3};
+
+use crate::error::{Context, Error, Result};
+use crate::projection;
+use crate::shaderc;
+
+use std::os::raw::c_void;
+
+use super::buffers;
+use super::device;
+use super::image;
+use super::pipeline;
+use super::registry;
+use super::render_settings;
+
+pub const STEREO_VERTEX_SHADER_FILE: &str = "shaders/stereo.vert";
+
+// both eyes are drawn by every draw, eye i to layer i
+const EYE_COUNT: u32 = 2;
+const VIEW_MASK: u32 = 0b11;
+
+// VK_KHR_multiview is core in vulkan 1.1, only its feature has to be enabled
+pub fn is_supported(instance: &ash::Instance, physical_device: vk::PhysicalDevice) -> bool {
+    let mut multiview = vk::PhysicalDeviceMultiviewFeatures::default();
+    let mut features = vk::PhysicalDeviceFeatures2 {
+        p_next: &mut multiview as *mut vk::PhysicalDeviceMultiviewFeatures as *mut c_void,
+        ..Default::default()
+    };
+
+    unsafe { instance.get_physical_device_features2(physical_device, &mut features) };
+    multiview.multiview == vk::TRUE
+}
+
+// Chained into vk::DeviceCreateInfo to enable multiview render passes
+pub fn device_features() -> vk::PhysicalDeviceMultiviewFeatures {
+    vk::PhysicalDeviceMultiviewFeatures {
+        multiview: vk::TRUE,
+        ..Default::default()
+    }
+}
+
+// Angles of the edges of an eye's view from its forward direction in radians, left and
+// down are negative. Headsets usually have asymmetric ones, eg. OpenXR's XrFovf.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Fov {
+    pub angle_left: f32,
+    pub angle_right: f32,
+    pub angle_up: f32,
+    pub angle_down: f32,
+}
+
+impl Fov {
+    // The view of a regular camera, aspect is width / height
+    pub fn symmetric(fovy: Deg<f32>, aspect: f32) -> Fov {
+        let half_y = Rad::from(fovy).0 * 0.5;
+        let half_x = (half_y.tan() * aspect).atan();
+
+        Fov {
+            angle_left: -half_x,
+            angle_right: half_x,
+            angle_up: half_y,
+            angle_down: -half_y,
+        }
+    }
+
+    pub fn projection(&self, near: f32, far: f32) -> Matrix4<f32> {
+        projection::opengl_to_vulkan()
+            * cgmath::frustum(
+                near * self.angle_left.tan(),
+                near * self.angle_right.tan(),
+                near * self.angle_down.tan(),
+                near * self.angle_up.tan(),
+                near,
+                far,
+            )
+    }
+}
+
+// The camera one eye is rendered with
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct EyeCamera {
+    pub view: Matrix4<f32>,
+    pub projection: Matrix4<f32>,
+}
+
+impl EyeCamera {
+    pub fn view_projection(&self) -> Matrix4<f32> {
+        self.projection * self.view
+    }
+}
+
+// Two eyes apart by the interpupillary distance, level with the camera they replace
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct StereoRig {
+    // in world units, usually meters
+    pub ipd: f32,
+    // left then right
+    pub fov: [Fov; 2],
+    pub near: f32,
+    pub far: f32,
+}
+
+impl StereoRig {
+    pub fn new(ipd: f32, fov: Fov) -> StereoRig {
+        StereoRig {
+            ipd,
+            fov: [fov, fov],
+            near: 0.1,
+            far: 100.0,
+        }
+    }
+
+    pub fn with_depth_range(mut self, near: f32, far: f32) -> StereoRig {
+        self.near = near;
+        self.far = far;
+        self
+    }
+
+    // The eyes of the camera with the view matrix, eg. the scene's uniform view or the
+    // inverse of a tracked head pose. Left then right.
+    pub fn eyes(&self, view: Matrix4<f32>) -> [EyeCamera; 2] {
+        let eye = |index: usize, offset: f32| EyeCamera {
+            // the eye sits at offset along the camera's x axis, so the world moves the
+            // other way
+            view: Matrix4::from_translation(Vector3::new(-offset, 0.0, 0.0)) * view,
+            projection: self.fov[index].projection(self.near, self.far),
+        };
+
+        [eye(0, -self.ipd * 0.5), eye(1, self.ipd * 0.5)]
+    }
+}
+
+// Push constants of shaders/stereo.vert
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct EyeConstants {
+    view_projection: [Matrix4<f32>; 2],
+}
+
+impl EyeConstants {
+    fn new(eyes: &[EyeCamera; 2]) -> EyeConstants {
+        EyeConstants {
+            view_projection: [eyes[0].view_projection(), eyes[1].view_projection()],
+        }
+    }
+
+    fn push_constant_range() -> vk::PushConstantRange {
+        vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::VERTEX,
+            offset: 0,
+            size: ::std::mem::size_of::<EyeConstants>() as u32,
+        }
+    }
+}
+
+// An image an eye is handed off to instead of a window, eg. from an OpenXR swapchain.
+// Its contents are replaced and it is left in layout.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct EyeTarget {
+    pub image: vk::Image,
+    pub layer: u32,
+    pub extent: vk::Extent2D,
+    pub layout: vk::ImageLayout,
+}
+
+// Where the eyes of a StereoPass are presented, implemented by whatever drives the
+// headset, eg. an OpenXR session
+pub trait EyeSink {
+    // The images this frame's eyes are copied into, left then right
+    fn acquire_eyes(&mut self) -> Result<[EyeTarget; 2]>;
+
+    // Called once the commands copying into the acquired images are submitted
+    fn release_eyes(&mut self) -> Result<()>;
+}
+
+fn layer_barrier(
+    image: vk::Image,
+    layer: u32,
+    layouts: (vk::ImageLayout, vk::ImageLayout),
+    access: (vk::AccessFlags, vk::AccessFlags),
+) -> vk::ImageMemoryBarrier {
+    vk::ImageMemoryBarrier {
+        src_access_mask: access.0,
+        dst_access_mask: access.1,
+        old_layout: layouts.0,
+        new_layout: layouts.1,
+        src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+        dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+        image,
+        subresource_range: vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: layer,
+            layer_count: 1,
+        },
+        ..Default::default()
+    }
+}
+
+// Renders the scene for both eyes of a headset at once with a multiview render pass, each
+// draw is broadcast to the two layers of the color image. The pipeline shares the scene
+// pipeline's descriptor set layout and fragment shader, so the scene's sets and vertex
+// buffers are bound as they are. The eyes are copied out with record_handoff afterwards.
+pub struct StereoPass {
+    pub color: image::ArrayImage,
+    depth: image::ArrayImage,
+    render_pass: vk::RenderPass,
+    framebuffer: vk::Framebuffer,
+    pub layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+impl StereoPass {
+    pub fn new(
+        instance: &ash::Instance,
+        device: &device::Device,
+        scene: &pipeline::PipelineDetail,
+        format: vk::Format,
+        eye_extent: vk::Extent2D,
+        pipeline_cache: vk::PipelineCache,
+    ) -> Result<StereoPass> {
+        if !device.multiview {
+            return Err(Error::Unsupported(
+                "multiview is not enabled on the device".to_string(),
+            ));
+        }
+
+        let logical_device = &device.logical_device;
+
+        let shaders = shaderc::ShaderSource {
+            vertex_shader_file: STEREO_VERTEX_SHADER_FILE.to_string(),
+            fragment_shader_file: scene.shaders().fragment_shader_file.clone(),
+        };
+        let compiled_shaders = shaders.compile()?;
+
+        let reflection = compiled_shaders.reflect()?;
+        reflection.validate_set(0, &scene.descriptor_bindings)?;
+        reflection.validate_push_constants(&[EyeConstants::push_constant_range()])?;
+
+        let color = image::ArrayImage::new(
+            device,
+            eye_extent,
+            format,
+            EYE_COUNT,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
+            vk::ImageAspectFlags::COLOR,
+        )?;
+        color.set_name(device, "stereo color");
+
+        let (depth_attachment, depth_reference) =
+            buffers::DepthBuffer::get_attachment_info(instance, device.physical_device)?;
+        let depth_aspect = if image::ImageData::has_stencil_component(depth_attachment.format) {
+            vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+        } else {
+            vk::ImageAspectFlags::DEPTH
+        };
+        let depth = image::ArrayImage::new(
+            device,
+            eye_extent,
+            depth_attachment.format,
+            EYE_COUNT,
+            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+            depth_aspect,
+        )?;
+        depth.set_name(device, "stereo depth");
+
+        let color_attachment = vk::AttachmentDescription {
+            format,
+            samples: vk::SampleCountFlags::TYPE_1,
+            load_op: vk::AttachmentLoadOp::CLEAR,
+            store_op: vk::AttachmentStoreOp::STORE,
+            stencil_load_op: vk::AttachmentLoadOp::DONT_CARE,
+            stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
+            initial_layout: vk::ImageLayout::UNDEFINED,
+            final_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            ..Default::default()
+        };
+
+        // the eyes are copied out right after the pass
+        let handoff_dependency = vk::SubpassDependency {
+            src_subpass: 0,
+            dst_subpass: vk::SUBPASS_EXTERNAL,
+            src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            dst_stage_mask: vk::PipelineStageFlags::TRANSFER,
+            src_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            dst_access_mask: vk::AccessFlags::TRANSFER_READ,
+            ..Default::default()
+        };
+
+        let render_pass = pipeline::PipelineDetail::create_multiview_render_pass(
+            logical_device,
+            &[color_attachment, depth_attachment],
+            &[pipeline::SubpassAttachments {
+                color: vec![0],
+                input: vec![],
+                depth: Some(depth_reference.attachment),
+            }],
+            &[
+                pipeline::PipelineDetail::external_dependency(),
+                handoff_dependency,
+            ],
+            VIEW_MASK,
+        )?;
+        device.track(registry::ResourceKind::RenderPass, render_pass);
+
+        // with multiview the framebuffer has one layer, the views hold the eyes
+        let attachments = [color.image_view, depth.image_view];
+        let framebuffer_info = vk::FramebufferCreateInfo {
+            render_pass,
+            attachment_count: attachments.len() as u32,
+            p_attachments: attachments.as_ptr(),
+            width: eye_extent.width,
+            height: eye_extent.height,
+            layers: 1,
+            ..Default::default()
+        };
+
+        let framebuffer = unsafe {
+            logical_device
+                .create_framebuffer(&framebuffer_info, None)
+                .context("failed to create stereo framebuffer")
+        }?;
+        device.track(registry::ResourceKind::Framebuffer, framebuffer);
+
+        let set_layouts = [scene.descriptor_set_layout];
+        let push_constant_ranges = [EyeConstants::push_constant_range()];
+        let pipeline_layout_info = vk::PipelineLayoutCreateInfo {
+            set_layout_count: set_layouts.len() as u32,
+            p_set_layouts: set_layouts.as_ptr(),
+            push_constant_range_count: push_constant_ranges.len() as u32,
+            p_push_constant_ranges: push_constant_ranges.as_ptr(),
+            ..Default::default()
+        };
+
+        let layout = unsafe {
+            logical_device
+                .create_pipeline_layout(&pipeline_layout_info, None)
+                .context("failed to create stereo pipeline layout")
+        }?;
+        device.track(registry::ResourceKind::PipelineLayout, layout);
+
+        // the eyes are not multisampled
+        let state = scene
+            .state
+            .with_polygon_mode(vk::PolygonMode::FILL)
+            .with_samples(vk::SampleCountFlags::TYPE_1);
+
+        let pipeline = pipeline::PipelineDetail::create_pipeline_from_spirv(
+            logical_device,
+            compiled_shaders,
+            scene.vertex_input(),
+            &state,
+            layout,
+            render_pass,
+            pipeline_cache,
+        )?;
+        device.track(registry::ResourceKind::Pipeline, pipeline);
+
+        let stereo = StereoPass {
+            color,
+            depth,
+            render_pass,
+            framebuffer,
+            layout,
+            pipeline,
+        };
+        stereo.set_name(device, "stereo");
+
+        Ok(stereo)
+    }
+
+    pub fn eye_extent(&self) -> vk::Extent2D {
+        self.color.extent
+    }
+
+    // Records the pass with the eyes' cameras. draw is called inside it with the pipeline
+    // bound, eg. to bind the scene's set with the layout and record its draws.
+    pub fn record<D>(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        eyes: &[EyeCamera; 2],
+        settings: &render_settings::RenderSettings,
+        draw: D,
+    ) where
+        D: FnOnce(vk::CommandBuffer, vk::PipelineLayout),
+    {
+        let extent = self.eye_extent();
+        let clear_values = settings.clear_values();
+
+        let render_pass_begin_info = vk::RenderPassBeginInfo {
+            render_pass: self.render_pass,
+            framebuffer: self.framebuffer,
+            render_area: vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent,
+            },
+            clear_value_count: clear_values.len() as u32,
+            p_clear_values: clear_values.as_ptr(),
+            ..Default::default()
+        };
+
+        let constants = EyeConstants::new(eyes);
+        let constant_bytes = unsafe {
+            ::std::slice::from_raw_parts(
+                &constants as *const EyeConstants as *const u8,
+                ::std::mem::size_of::<EyeConstants>(),
+            )
+        };
+
+        unsafe {
+            device.cmd_begin_render_pass(
+                command_buffer,
+                &render_pass_begin_info,
+                vk::SubpassContents::INLINE,
+            );
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline,
+            );
+            // the whole eye, a window's viewport settings do not apply to the headset
+            device.cmd_set_viewport(
+                command_buffer,
+                0,
+                &[vk::Viewport {
+                    x: 0.0,
+                    y: 0.0,
+                    width: extent.width as f32,
+                    height: extent.height as f32,
+                    min_depth: 0.0,
+                    max_depth: 1.0,
+                }],
+            );
+            device.cmd_set_scissor(command_buffer, 0, &[render_pass_begin_info.render_area]);
+            device.cmd_push_constants(
+                command_buffer,
+                self.layout,
+                vk::ShaderStageFlags::VERTEX,
+                0,
+                constant_bytes,
+            );
+        }
+
+        draw(command_buffer, self.layout);
+
+        unsafe { device.cmd_end_render_pass(command_buffer) };
+    }
+
+    // Copies the eyes into the targets, scaled to their extent, after record
+    pub fn record_handoff(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        targets: &[EyeTarget; 2],
+    ) -> Result<()> {
+        let extent = self.eye_extent();
+
+        let to_transfer: Vec<vk::ImageMemoryBarrier> = targets
+            .iter()
+            .map(|target| {
+                layer_barrier(
+                    target.image,
+                    target.layer,
+                    (
+                        vk::ImageLayout::UNDEFINED,
+                        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    ),
+                    (vk::AccessFlags::empty(), vk::AccessFlags::TRANSFER_WRITE),
+                )
+            })
+            .collect();
+
+        let regions = targets
+            .iter()
+            .enumerate()
+            .map(|(eye, target)| {
+                let corner = |extent: vk::Extent2D| vk::Offset3D {
+                    x: extent.width as i32,
+                    y: extent.height as i32,
+                    z: 1,
+                };
+
+                Ok(vk::ImageBlit {
+                    src_subresource: self.color.layer(eye as u32)?,
+                    src_offsets: [vk::Offset3D::default(), corner(extent)],
+                    dst_subresource: vk::ImageSubresourceLayers {
+                        base_array_layer: target.layer,
+                        ..self.color.layer(0)?
+                    },
+                    dst_offsets: [vk::Offset3D::default(), corner(target.extent)],
+                })
+            })
+            .collect::<Result<Vec<vk::ImageBlit>>>()?;
+
+        let to_target: Vec<vk::ImageMemoryBarrier> = targets
+            .iter()
+            .map(|target| {
+                layer_barrier(
+                    target.image,
+                    target.layer,
+                    (vk::ImageLayout::TRANSFER_DST_OPTIMAL, target.layout),
+                    (vk::AccessFlags::TRANSFER_WRITE, vk::AccessFlags::empty()),
+                )
+            })
+            .collect();
+
+        unsafe {
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &to_transfer,
+            );
+
+            for (target, region) in targets.iter().zip(regions.iter()) {
+                device.cmd_blit_image(
+                    command_buffer,
+                    self.color.image,
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    target.image,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    &[*region],
+                    vk::Filter::LINEAR,
+                );
+            }
+
+            // whoever uses the targets next waits for the submission
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &to_target,
+            );
+        }
+
+        Ok(())
+    }
+
+    pub fn set_name(&self, device: &device::Device, name: &str) {
+        device.name_resource(self.render_pass, &format!("{} render pass", name));
+        device.name_resource(self.framebuffer, &format!("{} framebuffer", name));
+        device.name_resource(self.layout, &format!("{} pipeline layout", name));
+        device.name_resource(self.pipeline, &format!("{} pipeline", name));
+    }
+
+    pub fn destroy(&self, device: &device::Device) {
+        device.untrack(self.pipeline);
+        device.untrack(self.layout);
+        device.untrack(self.framebuffer);
+        device.untrack(self.render_pass);
+
+        let logical_device = &device.logical_device;
+        unsafe {
+            logical_device.destroy_pipeline(self.pipeline, None);
+            logical_device.destroy_pipeline_layout(self.layout, None);
+            logical_device.destroy_framebuffer(self.framebuffer, None);
+            logical_device.destroy_render_pass(self.render_pass, None);
+        }
+
+        self.depth.destroy(device);
+        self.color.destroy(device);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use cgmath::{Point3, SquareMatrix, Vector4};
+
+    fn assert_near(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-5,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn eyes_are_apart_by_the_ipd() {
+        let rig = StereoRig::new(0.064, Fov::symmetric(Deg(90.0), 1.0));
+        let [left, right] = rig.eyes(Matrix4::identity());
+
+        // the camera's origin is right of the left eye and left of the right eye
+        let origin = Vector4::new(0.0, 0.0, 0.0, 1.0);
+        assert_near((left.view * origin).x, 0.032);
+        assert_near((right.view * origin).x, -0.032);
+    }
+
+    #[test]
+    fn symmetric_fov_matches_the_mono_projection() {
+        let fov = Fov::symmetric(Deg(60.0), 1.5);
+        let stereo = fov.projection(0.1, 10.0);
+        let mono = projection::Projection::perspective(Deg(60.0), 0.1, 10.0).matrix(1.5);
+
+        for (column, expected) in [stereo.x, stereo.y, stereo.z, stereo.w]
+            .iter()
+            .zip([mono.x, mono.y, mono.z, mono.w].iter())
+        {
+            for i in 0..4 {
+                assert_near(column[i], expected[i]);
+            }
+        }
+    }
+
+    #[test]
+    fn asymmetric_fov_edges_land_on_the_clip_edges() {
+        let fov = Fov {
+            angle_left: -0.9,
+            angle_right: 0.7,
+            angle_up: 0.8,
+            angle_down: -0.6,
+        };
+        let projection = fov.projection(0.1, 10.0);
+
+        let ndc =
+            |direction: Vector3<f32>| Point3::from_homogeneous(projection * direction.extend(1.0));
+
+        assert_near(ndc(Vector3::new(-(0.9_f32.tan()), 0.0, -1.0)).x, -1.0);
+        assert_near(ndc(Vector3::new(0.7_f32.tan(), 0.0, -1.0)).x, 1.0);
+        // up in view space is down in vulkan's clip space
+        assert_near(ndc(Vector3::new(0.0, 0.8_f32.tan(), -1.0)).y, -1.0);
+        assert_near(ndc(Vector3::new(0.0, -(0.6_f32.tan()), -1.0)).y, 1.0);
+    }
+
+    #[test]
+    fn eye_constants_fit_the_guaranteed_push_constant_size() {
+        assert_eq!(EyeConstants::push_constant_range().size, 128);
+    }
+}
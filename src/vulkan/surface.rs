@@ -2,24 +2,21 @@ use ash::vk;
 
 use super::instance::VulkanInstance;
 
+use crate::error::{Context, Result};
 use crate::platforms;
-use anyhow::{Context, Result};
 
 pub struct SurfaceInfo {
     pub loader: ash::extensions::khr::Surface,
     pub surface: vk::SurfaceKHR,
 
-    screen_width: u32,
-    screen_height: u32,
+    // size of the window's framebuffer in physical pixels
+    pub extent: vk::Extent2D,
+    // ratio of physical to logical pixels, > 1.0 on HiDPI displays
+    pub scale_factor: f64,
 }
 
 impl SurfaceInfo {
-    pub fn new(
-        instance: &VulkanInstance,
-        window: &winit::window::Window,
-        screen_width: u32,
-        screen_height: u32,
-    ) -> Result<SurfaceInfo> {
+    pub fn new(instance: &VulkanInstance, window: &winit::window::Window) -> Result<SurfaceInfo> {
         let loader = ash::extensions::khr::Surface::new(&instance.entry, &instance.instance);
         unsafe {
             platforms::create_surface(&instance.entry, &instance.instance, window)
@@ -28,8 +25,33 @@ impl SurfaceInfo {
         .map(|surface| SurfaceInfo {
             loader,
             surface,
-            screen_width,
-            screen_height,
+            extent: SurfaceInfo::window_extent(window),
+            scale_factor: window.scale_factor(),
         })
     }
+
+    fn window_extent(window: &winit::window::Window) -> vk::Extent2D {
+        let size = window.inner_size();
+
+        vk::Extent2D {
+            width: size.width,
+            height: size.height,
+        }
+    }
+
+    // To be called when the window is resized or moved to a display with a different scale factor
+    pub fn update_size(&mut self, window: &winit::window::Window) {
+        self.extent = SurfaceInfo::window_extent(window);
+        self.scale_factor = window.scale_factor();
+    }
+
+    // No swapchain can be created while the window has no area, eg. when it is minimized
+    pub fn is_minimized(&self) -> bool {
+        self.extent.width == 0 || self.extent.height == 0
+    }
+
+    // Must be called after the swapchain using the surface has been destroyed
+    pub fn destroy(&self) {
+        unsafe { self.loader.destroy_surface(self.surface, None) };
+    }
 }
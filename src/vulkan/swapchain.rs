@@ -1,14 +1,14 @@
 use ash::extensions::khr::Swapchain;
-use ash::version::DeviceV1_0;
+use ash::version::{DeviceV1_0, InstanceV1_0};
 use ash::vk;
 
-use super::constants::*;
 use super::device;
 use super::surface;
+use super::texture;
+use super::trace;
 use std::cmp;
 
-use anyhow::anyhow;
-use anyhow::{Context, Result};
+use crate::error::{Context, Error, Result};
 use ash::vk::Extent2D;
 
 pub struct SupportDetail {
@@ -46,61 +46,269 @@ impl SupportDetail {
     }
 }
 
+// What the swapchain is created with, the surface decides what is actually used
+#[derive(Debug, Clone)]
+pub struct SwapchainConfig {
+    // images to request, clamped to the surface limits. None asks for one more than the minimum.
+    pub image_count: Option<u32>,
+    // tried in order, the first one the surface supports is used and its first format otherwise
+    pub formats: Vec<vk::SurfaceFormatKHR>,
+    // tried in order like the formats, see with_vsync
+    pub present_modes: Vec<vk::PresentModeKHR>,
+}
+
+impl SwapchainConfig {
+    fn surface_format(format: vk::Format, color_space: vk::ColorSpaceKHR) -> vk::SurfaceFormatKHR {
+        vk::SurfaceFormatKHR {
+            format,
+            color_space,
+        }
+    }
+
+    // An sRGB format encodes the linear shader output on write, with a UNORM one
+    // the shaders have to apply gamma themselves
+    pub fn sdr_formats() -> Vec<vk::SurfaceFormatKHR> {
+        vec![
+            SwapchainConfig::surface_format(
+                vk::Format::B8G8R8A8_SRGB,
+                vk::ColorSpaceKHR::SRGB_NONLINEAR,
+            ),
+            SwapchainConfig::surface_format(
+                vk::Format::R8G8B8A8_SRGB,
+                vk::ColorSpaceKHR::SRGB_NONLINEAR,
+            ),
+            SwapchainConfig::surface_format(
+                vk::Format::B8G8R8A8_UNORM,
+                vk::ColorSpaceKHR::SRGB_NONLINEAR,
+            ),
+        ]
+    }
+
+    // HDR10 and scRGB output, falling back to the sdr formats. The HDR color spaces are
+    // only reported when the instance enables VK_EXT_swapchain_colorspace.
+    pub fn hdr_formats() -> Vec<vk::SurfaceFormatKHR> {
+        let mut formats = vec![
+            SwapchainConfig::surface_format(
+                vk::Format::A2B10G10R10_UNORM_PACK32,
+                vk::ColorSpaceKHR::HDR10_ST2084_EXT,
+            ),
+            SwapchainConfig::surface_format(
+                vk::Format::R16G16B16A16_SFLOAT,
+                vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT,
+            ),
+        ];
+
+        formats.extend(SwapchainConfig::sdr_formats());
+        formats
+    }
+
+    pub fn with_image_count(mut self, image_count: u32) -> SwapchainConfig {
+        self.image_count = Some(image_count);
+        self
+    }
+
+    pub fn with_formats(mut self, formats: Vec<vk::SurfaceFormatKHR>) -> SwapchainConfig {
+        self.formats = formats;
+        self
+    }
+
+    // FIFO waits for the vertical blank and is always supported. Without vsync frames are
+    // presented right away, mailbox at least replaces queued images instead of tearing.
+    pub fn with_vsync(mut self, vsync: bool) -> SwapchainConfig {
+        self.present_modes = if vsync {
+            vec![vk::PresentModeKHR::FIFO]
+        } else {
+            vec![
+                vk::PresentModeKHR::IMMEDIATE,
+                vk::PresentModeKHR::MAILBOX,
+                vk::PresentModeKHR::FIFO,
+            ]
+        };
+        self
+    }
+}
+
+impl Default for SwapchainConfig {
+    fn default() -> SwapchainConfig {
+        SwapchainConfig {
+            image_count: None,
+            formats: SwapchainConfig::sdr_formats(),
+            present_modes: vec![vk::PresentModeKHR::MAILBOX],
+        }
+    }
+}
+
 pub struct SwapchainDetails {
     pub loader: ash::extensions::khr::Swapchain,
     pub swapchain: vk::SwapchainKHR,
     pub images: Vec<vk::Image>,
     pub format: vk::SurfaceFormatKHR,
     pub extent: vk::Extent2D,
+    pub scale_factor: f64,
     pub image_views: Vec<vk::ImageView>,
+    pub image_usage: vk::ImageUsageFlags,
 }
 
 impl SwapchainDetails {
-    fn choose_format(support_detail: &SupportDetail) -> Result<vk::SurfaceFormatKHR> {
-        support_detail
-            .formats
+    pub fn choose_format(
+        supported: &[vk::SurfaceFormatKHR],
+        preferred: &[vk::SurfaceFormatKHR],
+    ) -> Result<vk::SurfaceFormatKHR> {
+        // a single undefined format means the surface takes any format
+        if supported.len() == 1 && supported[0].format == vk::Format::UNDEFINED {
+            if let Some(&format) = preferred.first() {
+                return Ok(format);
+            }
+        }
+
+        preferred
             .iter()
-            .find(|format| {
-                format.format == vk::Format::B8G8R8A8_UNORM
-                    && format.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
+            .filter_map(|preferred| {
+                supported.iter().find(|format| {
+                    format.format == preferred.format && format.color_space == preferred.color_space
+                })
             })
-            .or(support_detail.formats.first())
+            .next()
+            .or(supported.first())
             .cloned()
-            .ok_or(anyhow!("cannot find suitable swapchain format"))
+            .ok_or_else(|| Error::Unsupported("cannot find suitable swapchain format".to_string()))
     }
 
-    fn choose_present_mode(support_detail: &SupportDetail) -> Result<vk::PresentModeKHR> {
-        support_detail
-            .present_modes
+    // The first preferred mode the surface supports, its first mode otherwise
+    fn choose_present_mode(
+        supported: &[vk::PresentModeKHR],
+        preferred: &[vk::PresentModeKHR],
+    ) -> Result<vk::PresentModeKHR> {
+        preferred
             .iter()
-            .find(|mode| **mode == vk::PresentModeKHR::MAILBOX)
-            .or(support_detail.present_modes.first())
+            .find(|mode| supported.contains(mode))
+            .or(supported.first())
             .cloned()
-            .ok_or(anyhow!("cannot find suitable present mode"))
+            .ok_or_else(|| Error::Unsupported("cannot find suitable present mode".to_string()))
     }
 
-    fn choose_swap_extent(support_detail: &SupportDetail) -> vk::Extent2D {
+    fn choose_swap_extent(
+        support_detail: &SupportDetail,
+        window_extent: vk::Extent2D,
+    ) -> vk::Extent2D {
         /*
         Vulkan tells us to match the resolution of the window by setting the width and height in the currentExtent member.
         However, some window managers do allow us to differ here and this is indicated by setting the width
         and height in currentExtent to a special value: the maximum value of uint32_t.
-        In that case we'll pick the resolution that best matches the window within the minImageExtent and maxImageExtent bounds.
-        But somehow in either cases same resolution is being picked up {1600, 1200}...strange
+        In that case we'll pick the physical size of the window within the minImageExtent and maxImageExtent bounds.
         */
         if support_detail.capabilities.current_extent.width != std::u32::MAX {
-            println!("Current extent {:?}",support_detail.capabilities.current_extent);
+            tracing::debug!(
+                "Current extent {:?}",
+                support_detail.capabilities.current_extent
+            );
             support_detail.capabilities.current_extent
         } else {
-            let mut actual_extent: vk::Extent2D = Extent2D { width: WINDOW_WIDTH, height: WINDOW_HEIGHT };
-            actual_extent.width = cmp::max(
-                support_detail.capabilities.min_image_extent.width,
-                cmp::min(support_detail.capabilities.min_image_extent.width, actual_extent.width));
-            actual_extent.height = cmp::max(
-                support_detail.capabilities.min_image_extent.height,
-                cmp::min(support_detail.capabilities.min_image_extent.height, actual_extent.height));
-
-            actual_extent
+            let capabilities = &support_detail.capabilities;
+
+            Extent2D {
+                width: cmp::max(
+                    capabilities.min_image_extent.width,
+                    cmp::min(capabilities.max_image_extent.width, window_extent.width),
+                ),
+                height: cmp::max(
+                    capabilities.min_image_extent.height,
+                    cmp::min(capabilities.max_image_extent.height, window_extent.height),
+                ),
+            }
+        }
+    }
+
+    // Extent a swapchain created now would have, zero while the window is minimized. Some
+    // platforms report the minimized surface this way even though the window keeps its size.
+    pub fn surface_extent(
+        device: &device::Device,
+        surface_info: &surface::SurfaceInfo,
+    ) -> Result<vk::Extent2D> {
+        let support = SupportDetail::query(device.physical_device, surface_info)?;
+        Ok(SwapchainDetails::choose_swap_extent(
+            &support,
+            surface_info.extent,
+        ))
+    }
+
+    // One image more than the minimum lets the application render the next frame while
+    // the driver holds on to the others. A max_image_count of 0 means there is no limit.
+    pub fn choose_image_count(
+        capabilities: &vk::SurfaceCapabilitiesKHR,
+        desired: Option<u32>,
+    ) -> u32 {
+        let count = cmp::max(
+            desired.unwrap_or(capabilities.min_image_count + 1),
+            capabilities.min_image_count,
+        );
+
+        if capabilities.max_image_count > 0 {
+            cmp::min(count, capabilities.max_image_count)
+        } else {
+            count
+        }
+    }
+
+    // Storage usage lets a compute shader write the final image, see vulkan::present.
+    // Copying it out lets compute effects read the previous contents, see image_effects.
+    fn choose_image_usage(
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        support_detail: &SupportDetail,
+        format: vk::Format,
+    ) -> vk::ImageUsageFlags {
+        let format_properties =
+            unsafe { instance.get_physical_device_format_properties(physical_device, format) };
+
+        let supports_storage = support_detail
+            .capabilities
+            .supported_usage_flags
+            .contains(vk::ImageUsageFlags::STORAGE)
+            && format_properties
+                .optimal_tiling_features
+                .contains(vk::FormatFeatureFlags::STORAGE_IMAGE);
+
+        let supports_transfer_src = support_detail
+            .capabilities
+            .supported_usage_flags
+            .contains(vk::ImageUsageFlags::TRANSFER_SRC)
+            && format_properties
+                .optimal_tiling_features
+                .contains(vk::FormatFeatureFlags::TRANSFER_SRC);
+
+        let mut usage = vk::ImageUsageFlags::COLOR_ATTACHMENT;
+        if supports_storage {
+            usage |= vk::ImageUsageFlags::STORAGE;
         }
+        if supports_transfer_src {
+            usage |= vk::ImageUsageFlags::TRANSFER_SRC;
+        }
+
+        usage
+    }
+
+    // The number of images actually created, which can exceed the requested count
+    pub fn image_count(&self) -> u32 {
+        self.images.len() as u32
+    }
+
+    pub fn supports_storage(&self) -> bool {
+        self.image_usage.contains(vk::ImageUsageFlags::STORAGE)
+    }
+
+    pub fn supports_transfer_src(&self) -> bool {
+        self.image_usage.contains(vk::ImageUsageFlags::TRANSFER_SRC)
+    }
+
+    // Whether the images are presented in an HDR color space, eg. HDR10 or scRGB
+    pub fn is_hdr(&self) -> bool {
+        self.format.color_space != vk::ColorSpaceKHR::SRGB_NONLINEAR
+    }
+
+    // Whether writes to the images are gamma encoded by the hardware
+    pub fn is_srgb(&self) -> bool {
+        texture::ColorSpace::of_format(self.format.format) == Some(texture::ColorSpace::Srgb)
     }
 
     fn create_image_view(
@@ -143,15 +351,27 @@ impl SwapchainDetails {
         _window: &winit::window::Window,
         family_indices: &super::queue::FamilyIndices,
         surface_info: &surface::SurfaceInfo,
+        config: &SwapchainConfig,
     ) -> Result<SwapchainDetails> {
         let support = &SupportDetail::query(device.physical_device, surface_info)?;
 
-        let surface_format = SwapchainDetails::choose_format(support)?;
-        let present_mode = SwapchainDetails::choose_present_mode(support)?;
-        let extent = SwapchainDetails::choose_swap_extent(support);
+        let surface_format = SwapchainDetails::choose_format(&support.formats, &config.formats)?;
+        tracing::info!("swapchain format: {:?}", surface_format);
+        let present_mode =
+            SwapchainDetails::choose_present_mode(&support.present_modes, &config.present_modes)?;
+        let extent = SwapchainDetails::choose_swap_extent(support, surface_info.extent);
+        if extent.width == 0 || extent.height == 0 {
+            return Err(Error::SurfaceMinimized);
+        }
+        let image_usage = SwapchainDetails::choose_image_usage(
+            instance,
+            device.physical_device,
+            support,
+            surface_format.format,
+        );
 
-        let image_count = support.capabilities.max_image_count;
-        println!("swapchain image count: {}", image_count);
+        let image_count =
+            SwapchainDetails::choose_image_count(&support.capabilities, config.image_count);
 
         let (image_sharing_mode, queue_family_index_count, queue_family_indices) =
             if family_indices.graphics != family_indices.present {
@@ -173,7 +393,7 @@ impl SwapchainDetails {
             image_color_space: surface_format.color_space,
             image_format: surface_format.format,
             image_extent: extent,
-            image_usage: vk::ImageUsageFlags::COLOR_ATTACHMENT,
+            image_usage,
             image_sharing_mode: image_sharing_mode,
             p_queue_family_indices: queue_family_indices.as_ptr(),
             queue_family_index_count: queue_family_index_count,
@@ -187,17 +407,23 @@ impl SwapchainDetails {
         };
 
         let swapchain_loader = Swapchain::new(instance, &device.logical_device);
-        let swapchain = unsafe {
+        let swapchain = trace::call("vkCreateSwapchainKHR", &swapchain_info, || unsafe {
             swapchain_loader
                 .create_swapchain(&swapchain_info, None)
                 .context("failed to create swapchain")
-        }?;
+        })?;
 
         let images = unsafe {
             swapchain_loader
                 .get_swapchain_images(swapchain)
                 .context("failed to get swapchain images")
         }?;
+        // the driver may create more images than requested
+        tracing::info!(
+            "swapchain image count: {} (requested {})",
+            images.len(),
+            image_count
+        );
 
         let image_views = images
             .iter()
@@ -218,7 +444,160 @@ impl SwapchainDetails {
             images,
             format: surface_format,
             extent,
+            scale_factor: surface_info.scale_factor,
             image_views,
+            image_usage,
         })
     }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        unsafe {
+            for &image_view in self.image_views.iter() {
+                device.destroy_image_view(image_view, None);
+            }
+
+            // the swapchain images are owned by the swapchain
+            self.loader.destroy_swapchain(self.swapchain, None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn capabilities(min_image_count: u32, max_image_count: u32) -> vk::SurfaceCapabilitiesKHR {
+        vk::SurfaceCapabilitiesKHR {
+            min_image_count,
+            max_image_count,
+            ..Default::default()
+        }
+    }
+
+    fn surface_format(format: vk::Format, color_space: vk::ColorSpaceKHR) -> vk::SurfaceFormatKHR {
+        vk::SurfaceFormatKHR {
+            format,
+            color_space,
+        }
+    }
+
+    #[test]
+    fn first_supported_preference_is_chosen() {
+        let supported = [
+            surface_format(
+                vk::Format::B8G8R8A8_UNORM,
+                vk::ColorSpaceKHR::SRGB_NONLINEAR,
+            ),
+            surface_format(vk::Format::B8G8R8A8_SRGB, vk::ColorSpaceKHR::SRGB_NONLINEAR),
+            surface_format(
+                vk::Format::R16G16B16A16_SFLOAT,
+                vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT,
+            ),
+        ];
+
+        let chosen = |preferred: &[vk::SurfaceFormatKHR]| {
+            let format = SwapchainDetails::choose_format(&supported, preferred).unwrap();
+            (format.format, format.color_space)
+        };
+
+        assert_eq!(
+            chosen(&SwapchainConfig::sdr_formats()),
+            (vk::Format::B8G8R8A8_SRGB, vk::ColorSpaceKHR::SRGB_NONLINEAR)
+        );
+        assert_eq!(
+            chosen(&SwapchainConfig::hdr_formats()),
+            (
+                vk::Format::R16G16B16A16_SFLOAT,
+                vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT
+            )
+        );
+
+        // the color space has to match as well
+        assert_eq!(
+            chosen(&[surface_format(
+                vk::Format::B8G8R8A8_SRGB,
+                vk::ColorSpaceKHR::HDR10_ST2084_EXT
+            )]),
+            (
+                vk::Format::B8G8R8A8_UNORM,
+                vk::ColorSpaceKHR::SRGB_NONLINEAR
+            )
+        );
+    }
+
+    #[test]
+    fn undefined_surface_format_takes_the_first_preference() {
+        let supported = [surface_format(
+            vk::Format::UNDEFINED,
+            vk::ColorSpaceKHR::SRGB_NONLINEAR,
+        )];
+
+        let format =
+            SwapchainDetails::choose_format(&supported, &SwapchainConfig::hdr_formats()).unwrap();
+        assert_eq!(format.format, vk::Format::A2B10G10R10_UNORM_PACK32);
+
+        assert!(SwapchainDetails::choose_format(&[], &SwapchainConfig::sdr_formats()).is_err());
+    }
+
+    #[test]
+    fn one_image_more_than_the_minimum_by_default() {
+        assert_eq!(
+            SwapchainDetails::choose_image_count(&capabilities(2, 8), None),
+            3
+        );
+        // no upper limit
+        assert_eq!(
+            SwapchainDetails::choose_image_count(&capabilities(2, 0), None),
+            3
+        );
+        assert_eq!(
+            SwapchainDetails::choose_image_count(&capabilities(3, 3), None),
+            3
+        );
+    }
+
+    #[test]
+    fn desired_count_is_clamped_to_the_surface_limits() {
+        assert_eq!(
+            SwapchainDetails::choose_image_count(&capabilities(2, 8), Some(4)),
+            4
+        );
+        assert_eq!(
+            SwapchainDetails::choose_image_count(&capabilities(2, 8), Some(1)),
+            2
+        );
+        assert_eq!(
+            SwapchainDetails::choose_image_count(&capabilities(2, 3), Some(16)),
+            3
+        );
+        assert_eq!(
+            SwapchainDetails::choose_image_count(&capabilities(2, 0), Some(16)),
+            16
+        );
+    }
+
+    #[test]
+    fn vsync_selects_the_present_mode() {
+        let supported = [vk::PresentModeKHR::FIFO, vk::PresentModeKHR::MAILBOX];
+
+        let config = SwapchainConfig::default();
+        assert_eq!(
+            SwapchainDetails::choose_present_mode(&supported, &config.present_modes).unwrap(),
+            vk::PresentModeKHR::MAILBOX
+        );
+
+        let config = config.with_vsync(true);
+        assert_eq!(
+            SwapchainDetails::choose_present_mode(&supported, &config.present_modes).unwrap(),
+            vk::PresentModeKHR::FIFO
+        );
+
+        // without immediate presentation mailbox is the next best
+        let config = config.with_vsync(false);
+        assert_eq!(
+            SwapchainDetails::choose_present_mode(&supported, &config.present_modes).unwrap(),
+            vk::PresentModeKHR::MAILBOX
+        );
+        assert!(SwapchainDetails::choose_present_mode(&[], &config.present_modes).is_err());
+    }
 }
@@ -1,34 +1,117 @@
 use ash::version::DeviceV1_0;
 use ash::vk;
 
-use anyhow::anyhow;
-use anyhow::{Context, Result};
+use crate::error::{Context, Error, Result};
 
+use super::backend::{self, SwapchainOps};
 use super::buffers;
+use super::constants::*;
+use super::debug_lines;
+use super::device;
+use super::events;
+use super::frame;
+use super::gc;
+use super::image_effects;
+use super::particles;
+use super::picking;
+use super::present;
 use super::queue;
 use super::swapchain;
+use super::timeline;
+use super::trace;
+use super::ui;
+use super::viewport;
 
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 pub struct FrameState {
-    swapchain_image_index: u32,
     current_frame: usize,
     images_in_flight: Vec<Option<vk::Fence>>,
+    // with a timeline: the value signaled by the last submission of each frame and image,
+    // 0 before the first one
+    frame_values: Vec<u64>,
+    image_values: Vec<u64>,
+    submitted_value: u64,
 }
 
 impl FrameState {
-    pub fn default(num_swapchain_images: u32) -> FrameState {
+    pub fn default(num_swapchain_images: u32, frames_in_flight: u32) -> FrameState {
         let images_in_flight = (0..num_swapchain_images)
             .into_iter()
             .map(|_| None)
             .collect();
 
         FrameState {
-            swapchain_image_index: 0,
             current_frame: 0,
             images_in_flight,
+            frame_values: vec![0; frames_in_flight as usize],
+            image_values: vec![0; num_swapchain_images as usize],
+            submitted_value: 0,
         }
     }
+
+    pub fn current_frame(&self) -> usize {
+        self.current_frame
+    }
+
+    // Waits for the fence of the last submission that used the image, then marks the
+    // frame's fence as the image's. Returns whether the image had been submitted before.
+    pub fn wait_for_image_fence<D: backend::FenceOps>(
+        &mut self,
+        device: &D,
+        frame: &frame::FrameContext,
+        in_flight_fence: vk::Fence,
+    ) -> Result<bool> {
+        let image_in_flight = *frame.per_image(&self.images_in_flight)?;
+
+        if let Some(image_in_flight) = image_in_flight {
+            tracing::trace!("waiting for fence of image {}", frame.image_index());
+            device
+                .wait_for_fences(&[image_in_flight], std::u64::MAX)
+                .context("failed to wait for in flight fence")?;
+        }
+
+        *frame.per_image_mut(&mut self.images_in_flight)? = Some(in_flight_fence);
+        Ok(image_in_flight.is_some())
+    }
+
+    pub fn advance(&mut self, frames_in_flight: u32) {
+        self.current_frame = (self.current_frame + 1) % frames_in_flight as usize;
+    }
+}
+
+// Submits the frame's commands and presents its image, a suboptimal swapchain is reported
+// as out of date. A fence that is not null is reset before the submission signals it.
+pub fn submit_and_present<D, Q>(
+    device: &D,
+    queues: &Q,
+    submit_info: &vk::SubmitInfo,
+    fence: vk::Fence,
+    present_info: &vk::PresentInfoKHR,
+) -> Result<()>
+where
+    D: backend::FenceOps,
+    Q: backend::QueueOps,
+{
+    if fence != vk::Fence::null() {
+        device.reset_fences(&[fence])?;
+    }
+
+    trace::call("vkQueueSubmit", submit_info, || {
+        queues.submit(std::slice::from_ref(submit_info), fence)
+    })?;
+    tracing::trace!("buffer submitted to graphics queue");
+
+    let is_swapchain_suboptimal = trace::call("vkQueuePresentKHR", present_info, || {
+        queues.present(present_info)
+    })?;
+
+    if is_swapchain_suboptimal {
+        // recreate swapchain
+        Err(Error::SwapchainOutOfDate)
+    } else {
+        Ok(())
+    }
 }
 
 pub struct Objects<T: buffers::UniformBuffers> {
@@ -43,9 +126,28 @@ pub struct Objects<T: buffers::UniformBuffers> {
     pub render_finished_semaphores: Vec<vk::Semaphore>,
 
     pub in_flight_fences: Vec<vk::Fence>,
+    // replaces the in flight fences when set, see with_timeline
+    pub timeline: Option<timeline::TimelineSemaphore>,
     pub start_time: Instant,
 
     pub frame_state: FrameState,
+
+    pub garbage: gc::GarbageCollector,
+
+    pub debug_lines: Option<debug_lines::DebugLineRenderer>,
+    pub overlay: Option<ui::UiOverlay>,
+    // replaces the scene render pass when set
+    pub compute_present: Option<present::ComputePresenter>,
+    // the post process chains of the viewports, reading the scene's offscreen target and
+    // writing the swapchain image when set
+    pub viewports: Option<viewport::ViewportTargets>,
+    // runs on the finished swapchain image, before the overlays are drawn
+    pub image_effects: Option<image_effects::ComputeImageEffects>,
+    pub particles: Option<particles::ParticleSystem>,
+    // created by the first Engine::pick
+    pub picking: Option<picking::PickingPass>,
+
+    pub events: events::RenderEvents,
 }
 
 impl<T: buffers::UniformBuffers> Objects<T> {
@@ -98,7 +200,16 @@ impl<T: buffers::UniformBuffers> Objects<T> {
 
         let start_time = Instant::now();
 
-        let frame_state = FrameState::default(swapchain_details.images.len() as u32);
+        let frame_state =
+            FrameState::default(swapchain_details.images.len() as u32, frames_in_flight);
+
+        let garbage = gc::GarbageCollector::new(
+            frames_in_flight,
+            Duration::from_micros(GC_TIME_BUDGET_MICROS),
+        );
+
+        let mut events = events::RenderEvents::default();
+        events.emit(events::RenderEvent::swapchain_created(&swapchain_details));
 
         Ok(Objects {
             device: device,
@@ -109,188 +220,544 @@ impl<T: buffers::UniformBuffers> Objects<T> {
             image_available_semaphores,
             render_finished_semaphores,
             in_flight_fences,
+            timeline: None,
             start_time,
             frame_state: frame_state,
+            garbage,
+            debug_lines: None,
+            overlay: None,
+            compute_present: None,
+            viewports: None,
+            image_effects: None,
+            particles: None,
+            picking: None,
+            events,
         })
     }
 
-    fn submit_buffers_to_queue(sync_objects: &Objects<T>, acquired_image_index: u32) -> Result<()> {
-        let current_frame = sync_objects.frame_state.current_frame as usize;
-        println!("submitting buffer for frame: {}", current_frame);
+    // Frames wait for the values the timeline semaphore reaches instead of fences. Binary
+    // semaphores are still used with the swapchain, which cannot wait on timelines.
+    pub fn with_timeline(mut self, timeline: timeline::TimelineSemaphore) -> Objects<T> {
+        self.timeline = Some(timeline);
+        self
+    }
 
-        let command_buffer = sync_objects
-            .buffers
-            .command_buffers
-            .get(acquired_image_index as usize)
-            .ok_or(anyhow!("could not find buffer for current frame"))?;
+    // The timeline and the value the last submitted frame signals, eg. for work on other
+    // queues that has to wait for the frame
+    pub fn gpu_timeline(&self) -> Option<(vk::Semaphore, u64)> {
+        self.timeline
+            .as_ref()
+            .map(|timeline| (timeline.semaphore, self.frame_state.submitted_value))
+    }
 
-        let in_flight_fence = sync_objects
-            .in_flight_fences
-            .get(current_frame)
-            .ok_or(anyhow!(
-                "could not find find flight fence for current frame"
-            ))?;
+    fn submit_buffers_to_queue(
+        sync_objects: &Objects<T>,
+        frame: &frame::FrameContext,
+        command_buffer: vk::CommandBuffer,
+        wait_stage: vk::PipelineStageFlags,
+        overlay_command_buffers: &[vk::CommandBuffer],
+        // signaled on the timeline when it is used
+        timeline_value: u64,
+    ) -> Result<()> {
+        tracing::trace!("submitting buffer for frame: {}", frame.frame_index());
+
+        // overlays are drawn after the scene in the same submission
+        let command_buffers: Vec<vk::CommandBuffer> = std::iter::once(command_buffer)
+            .chain(overlay_command_buffers.iter().cloned())
+            .collect();
 
-        let img_semaphore = sync_objects
-            .image_available_semaphores
-            .get(current_frame)
-            .ok_or(anyhow!("count not find image available semaphore"))?;
+        let in_flight_fence = frame.per_frame(&sync_objects.in_flight_fences)?;
+
+        let img_semaphore = frame.per_frame(&sync_objects.image_available_semaphores)?;
         let wait_semaphores = [*img_semaphore];
+        let wait_stages = [wait_stage];
 
-        let render_semaphore = sync_objects
-            .render_finished_semaphores
-            .get(current_frame)
-            .ok_or(anyhow!(
-                "coult not find render finished semaphore for current frame"
-            ))?;
-        let signal_semaphores = [*render_semaphore];
+        let render_semaphore = frame.per_frame(&sync_objects.render_finished_semaphores)?;
+        let present_semaphores = [*render_semaphore];
+
+        let signal_semaphores = match sync_objects.timeline.as_ref() {
+            Some(timeline) => vec![*render_semaphore, timeline.semaphore],
+            None => vec![*render_semaphore],
+        };
+        let wait_values = [0];
+        let signal_values = [0, timeline_value];
+        let timeline_info = timeline::submit_info(&wait_values, &signal_values);
 
         let submit_info = vk::SubmitInfo {
+            p_next: match sync_objects.timeline {
+                Some(_) => {
+                    &timeline_info as *const timeline::TimelineSemaphoreSubmitInfo
+                        as *const std::os::raw::c_void
+                }
+                None => std::ptr::null(),
+            },
+
             wait_semaphore_count: wait_semaphores.len() as u32,
             p_wait_semaphores: wait_semaphores.as_ptr(),
-            p_wait_dst_stage_mask: [vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT].as_ptr(),
+            p_wait_dst_stage_mask: wait_stages.as_ptr(),
 
-            command_buffer_count: 1u32,
-            p_command_buffers: command_buffer,
+            command_buffer_count: command_buffers.len() as u32,
+            p_command_buffers: command_buffers.as_ptr(),
 
             signal_semaphore_count: signal_semaphores.len() as u32,
             p_signal_semaphores: signal_semaphores.as_ptr(),
             ..Default::default()
         };
 
-        // Submit to graphics queue
-        unsafe {
-            sync_objects.device.reset_fences(&[*in_flight_fence])?;
-            sync_objects
-                .device
-                .queue_submit(
-                    sync_objects.queue.graphics,
-                    &[submit_info],
-                    *in_flight_fence,
-                )
-                .context("failed to submit to graphics queue")
-        }?;
-        println!("buffer submitted to graphics queue");
-
         let swapchains = [sync_objects.swapchain_details.swapchain];
+        let image_index = frame.image_index();
 
         let present_info = vk::PresentInfoKHR {
-            wait_semaphore_count: signal_semaphores.len() as u32,
-            p_wait_semaphores: signal_semaphores.as_ptr(),
+            wait_semaphore_count: present_semaphores.len() as u32,
+            p_wait_semaphores: present_semaphores.as_ptr(),
             swapchain_count: 1u32,
             p_swapchains: swapchains.as_ptr(),
-            p_image_indices: &acquired_image_index,
+            p_image_indices: &image_index,
             ..Default::default()
         };
 
-        // Submit to presentation queue
-        unsafe {
-            sync_objects
-                .swapchain_details
-                .loader
-                .queue_present(sync_objects.queue.present, &present_info)
-                .context("could not present to queue")
-        }
-        .and_then(|is_swapchain_suboptimal| {
-            if is_swapchain_suboptimal {
-                // recreate swapchain
-                Err(anyhow!("swapchain is invalid"))
-            } else {
-                Ok(())
+        let queues = backend::VulkanQueues {
+            device: &sync_objects.device,
+            queue: &sync_objects.queue,
+            swapchain_loader: &sync_objects.swapchain_details.loader,
+        };
+
+        // the timeline takes the place of the fence
+        let in_flight_fence = match sync_objects.timeline {
+            Some(_) => vk::Fence::null(),
+            None => *in_flight_fence,
+        };
+
+        submit_and_present(
+            &sync_objects.device,
+            &queues,
+            &submit_info,
+            in_flight_fence,
+            &present_info,
+        )
+    }
+
+    // Lets observers know the swapchain has to be recreated
+    fn observe_out_of_date<V>(&mut self, result: Result<V>) -> Result<V> {
+        if let Err(err) = result.as_ref() {
+            if err.is_swapchain_out_of_date() {
+                self.events.emit(events::RenderEvent::SwapchainOutOfDate);
             }
-        })
+        }
+
+        result
     }
 
-    pub fn draw_next_frame(&mut self) -> Result<()> {
-        println!("drawing frame");
+    // The caches give up entries within the garbage collector's time budget, see gc::Evictable
+    pub fn draw_next_frame(&mut self, caches: &mut [&mut dyn gc::Evictable]) -> Result<()> {
+        tracing::trace!("drawing frame");
+        self.buffers.profiler.begin_cpu_frame();
 
-        let in_flight_fence = self
-            .in_flight_fences
-            .get(self.frame_state.current_frame)
-            .ok_or(anyhow!("could not find fence for current frame"))?;
+        let current_frame = self.frame_state.current_frame;
+        let in_flight_fence = *self.in_flight_fences.get(current_frame).ok_or_else(|| {
+            Error::OutOfRange("could not find fence for current frame".to_string())
+        })?;
 
-        unsafe {
-            self.device
-                .wait_for_fences(&[*in_flight_fence], true, std::u64::MAX)?;
+        match self.timeline.as_ref() {
+            Some(timeline) => {
+                let value = self.frame_state.frame_values[current_frame];
+                trace::call("vkWaitSemaphoresKHR", &value, || {
+                    timeline.wait(value, std::u64::MAX)
+                })?
+            }
+            None => trace::call("vkWaitForFences", &in_flight_fence, || {
+                backend::FenceOps::wait_for_fences(&self.device, &[in_flight_fence], std::u64::MAX)
+            })?,
         }
 
         let image_available_semaphore = self
             .image_available_semaphores
-            .get(self.frame_state.current_frame)
-            .ok_or(anyhow!("could not find semaphore for current frame"))?;
-
-        let (acquired_image_index, _) = unsafe {
-            self.swapchain_details.loader.acquire_next_image(
-                self.swapchain_details.swapchain,
-                std::u64::MAX,
-                *image_available_semaphore,
-                vk::Fence::null(),
-            )
-        }
-        .map_err(|err| {
-            match err {
-                vk::Result::ERROR_OUT_OF_DATE_KHR => {
-                    // recreate swapchain
-                    anyhow!("swapchain is out of date")
-                }
-                _ => anyhow!(format!("failed to acquire swapchain images: {}", err)),
-            }
-        })?;
-        println!(
-            "acquired image index is {}, current_frame is {}",
-            acquired_image_index, self.frame_state.current_frame,
-        );
+            .get(current_frame)
+            .ok_or_else(|| {
+                Error::OutOfRange("could not find semaphore for current frame".to_string())
+            })?;
+
+        let acquired = trace::call("vkAcquireNextImageKHR", image_available_semaphore, || {
+            self.swapchain_details
+                .acquire_next_image(*image_available_semaphore, std::u64::MAX)
+        });
+        let acquired_image_index = self
+            .observe_out_of_date(acquired)
+            .context("failed to acquire swapchain images")?;
 
-        println!("images in flight: {:?}", self.frame_state.images_in_flight);
+        // every per frame or per image lookup below goes through the context
+        let frame = frame::FrameContext::new(current_frame, acquired_image_index);
+        self.events.emit(events::RenderEvent::FrameBegin(frame));
+        tracing::trace!("recording {:?}", frame);
 
-        // updating uniform buffers
+        tracing::trace!("images in flight: {:?}", self.frame_state.images_in_flight);
+
+        let image_was_in_flight = self.wait_for_image(&frame, in_flight_fence)?;
+
+        // updating uniform buffers, the image's region of the ring is no longer read now
+        // that the previous frame using the image has completed
         let delta_time = self.start_time.elapsed();
         self.start_time = Instant::now();
 
-        let uniform_buffer = self
-            .buffers
-            .uniform_buffers
-            .get(acquired_image_index as usize)
-            .ok_or(anyhow!("could not find uniform buffer for the image"))?;
-
-        self.buffers.uniform_buffer_data.update_buffer(
+        self.buffers.uniforms.update(
             &self.device,
-            uniform_buffer,
+            &frame,
             delta_time.subsec_micros() as f32 / 1000_000.0_f32,
         )?;
+        self.buffers.update_skinning(&self.device, &frame)?;
+
+        // the previous submission using this image has completed, so its timestamps are
+        // available. The compute path does not submit the timed scene commands.
+        if image_was_in_flight && self.compute_present.is_none() {
+            self.buffers
+                .profiler
+                .collect_gpu_time(&self.device, &frame)?;
+        }
 
-        let image_in_flight = self
-            .frame_state
-            .images_in_flight
-            .get(acquired_image_index as usize)
-            .ok_or(anyhow!("in flight image fence not found"))?;
-
-        image_in_flight
-            .map(|image_in_flight| unsafe {
-                println!(
-                    "waiting for fence; acquired image index is {} ",
-                    acquired_image_index
-                );
-                self.device
-                    .wait_for_fences(&[image_in_flight], true, std::u64::MAX)
-                    .context("failed to wait for in flight fence")
-            })
-            .transpose()?;
-        self.frame_state.images_in_flight[acquired_image_index as usize] = Some(*in_flight_fence);
+        // the compute shader writes the image before any overlay can draw on top of it
+        let (command_buffer, wait_stage) = match self.compute_present.as_mut() {
+            Some(compute_present) => {
+                let pass = events::Pass::ComputePresent;
+                self.events
+                    .emit(events::RenderEvent::PassBegin(frame, pass));
+                let command_buffer = compute_present.record(&frame)?;
+                self.events.emit(events::RenderEvent::PassEnd(frame, pass));
+
+                (command_buffer, vk::PipelineStageFlags::COMPUTE_SHADER)
+            }
+            None => {
+                let pass = events::Pass::Scene;
+                self.events
+                    .emit(events::RenderEvent::PassBegin(frame, pass));
+                // picks up a debug view switch now that the image's commands are not in use
+                self.buffers.record_frame_commands(&self.device, &frame)?;
+                self.events.emit(events::RenderEvent::PassEnd(frame, pass));
+
+                (
+                    *frame.per_image(&self.buffers.commands.command_buffers)?,
+                    vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                )
+            }
+        };
+
+        let post_process_command_buffers = match self.viewports.as_mut() {
+            Some(viewports) => {
+                let pass = events::Pass::PostProcess;
+                self.events
+                    .emit(events::RenderEvent::PassBegin(frame, pass));
+                let command_buffers = viewports.record(&frame)?;
+                self.events.emit(events::RenderEvent::PassEnd(frame, pass));
+
+                command_buffers
+            }
+            None => vec![],
+        };
+
+        let image_effect_command_buffer = match self.image_effects.as_mut() {
+            Some(image_effects) => {
+                let pass = events::Pass::ImageEffects;
+                self.events
+                    .emit(events::RenderEvent::PassBegin(frame, pass));
+                let command_buffer = image_effects.record(&frame)?;
+                self.events.emit(events::RenderEvent::PassEnd(frame, pass));
 
-        Objects::submit_buffers_to_queue(self, acquired_image_index)?;
+                Some(command_buffer)
+            }
+            None => None,
+        };
+
+        let particle_command_buffer = match self.particles.as_mut() {
+            Some(particles) => {
+                let pass = events::Pass::Particles;
+                self.events
+                    .emit(events::RenderEvent::PassBegin(frame, pass));
+                let command_buffer = particles.record(&frame)?;
+                self.events.emit(events::RenderEvent::PassEnd(frame, pass));
+
+                Some(command_buffer)
+            }
+            None => None,
+        };
+
+        let debug_line_command_buffer = match self.debug_lines.as_mut() {
+            Some(debug_lines) => {
+                let pass = events::Pass::DebugLines;
+                self.events
+                    .emit(events::RenderEvent::PassBegin(frame, pass));
+                let command_buffer = debug_lines.record(&frame)?;
+                self.events.emit(events::RenderEvent::PassEnd(frame, pass));
+
+                Some(command_buffer)
+            }
+            None => None,
+        };
 
-        self.frame_state.current_frame =
-            ((self.frame_state.current_frame + 1) % self.frames_in_flight as usize) as usize;
+        let overlay_command_buffer = match self.overlay.as_mut() {
+            Some(overlay) => {
+                let pass = events::Pass::UiOverlay;
+                self.events
+                    .emit(events::RenderEvent::PassBegin(frame, pass));
+                let command_buffer = overlay.record(&frame)?;
+                self.events.emit(events::RenderEvent::PassEnd(frame, pass));
+
+                Some(command_buffer)
+            }
+            None => None,
+        };
+
+        // post processing finishes the scene and the image effects run on the result,
+        // debug lines go below the ui
+        let overlay_command_buffers: Vec<vk::CommandBuffer> = post_process_command_buffers
+            .into_iter()
+            .chain(image_effect_command_buffer)
+            .chain(particle_command_buffer)
+            .chain(debug_line_command_buffer)
+            .chain(overlay_command_buffer)
+            .collect();
+
+        let timeline_value = self.frame_state.submitted_value + 1;
+        let submitted = Objects::submit_buffers_to_queue(
+            self,
+            &frame,
+            command_buffer,
+            wait_stage,
+            &overlay_command_buffers,
+            timeline_value,
+        );
+
+        // the submission signals the value even when presenting reports an outdated swapchain
+        if self.timeline.is_some() {
+            let submitted_to_queue = match submitted.as_ref() {
+                Ok(_) => true,
+                Err(err) => err.is_swapchain_out_of_date(),
+            };
+
+            if submitted_to_queue {
+                self.frame_state.submitted_value = timeline_value;
+                self.frame_state.frame_values[current_frame] = timeline_value;
+                *frame.per_image_mut(&mut self.frame_state.image_values)? = timeline_value;
+            }
+        }
+        self.observe_out_of_date(submitted)?;
+
+        self.garbage.step(&self.device, caches);
+        self.garbage.end_frame();
+
+        self.buffers.profiler.end_cpu_frame();
+        self.events.emit(events::RenderEvent::FrameEnd(
+            frame,
+            self.buffers.profiler.stats(),
+        ));
+
+        self.frame_state.advance(self.frames_in_flight);
 
         Ok(())
     }
+
+    // Waits for the last submission that used the image, then marks the frame as its user.
+    // Returns whether the image had been submitted before.
+    fn wait_for_image(
+        &mut self,
+        frame: &frame::FrameContext,
+        in_flight_fence: vk::Fence,
+    ) -> Result<bool> {
+        if let Some(timeline) = self.timeline.as_ref() {
+            let image_value = *frame.per_image(&self.frame_state.image_values)?;
+            if image_value > 0 {
+                tracing::trace!(
+                    "waiting for timeline value of image {}",
+                    frame.image_index()
+                );
+                timeline.wait(image_value, std::u64::MAX)?;
+            }
+
+            return Ok(image_value > 0);
+        }
+
+        self.frame_state
+            .wait_for_image_fence(&self.device, frame, in_flight_fence)
+    }
+
+    pub fn wait_for_in_flight_frames(&self) -> Result<()> {
+        if let Some(timeline) = self.timeline.as_ref() {
+            return timeline
+                .wait(self.frame_state.submitted_value, std::u64::MAX)
+                .context("failed to wait for in flight frames");
+        }
+
+        unsafe {
+            self.device
+                .wait_for_fences(&self.in_flight_fences, true, std::u64::MAX)
+                .context("failed to wait for in flight frames")
+        }
+    }
+
+    // Tears down everything owned by the frame objects and empties the caches, the device
+    // has to be idle
+    pub fn destroy(&mut self, device: &device::Device, caches: &mut [&mut dyn gc::Evictable]) {
+        self.garbage.flush(&self.device, caches);
+
+        if let Some(mut overlay) = self.overlay.take() {
+            overlay.destroy();
+        }
+
+        if let Some(mut debug_lines) = self.debug_lines.take() {
+            debug_lines.destroy();
+        }
+
+        if let Some(mut particles) = self.particles.take() {
+            particles.destroy();
+        }
+
+        if let Some(mut picking) = self.picking.take() {
+            picking.destroy();
+        }
+
+        if let Some(mut compute_present) = self.compute_present.take() {
+            compute_present.destroy();
+        }
+
+        if let Some(mut image_effects) = self.image_effects.take() {
+            image_effects.destroy();
+        }
+
+        self.buffers.destroy(device);
+
+        // after the scene framebuffers using its targets
+        if let Some(mut viewports) = self.viewports.take() {
+            viewports.destroy();
+        }
+
+        unsafe {
+            for &semaphore in self
+                .image_available_semaphores
+                .iter()
+                .chain(self.render_finished_semaphores.iter())
+            {
+                self.device.destroy_semaphore(semaphore, None);
+            }
+
+            for &fence in self.in_flight_fences.iter() {
+                self.device.destroy_fence(fence, None);
+            }
+        }
+
+        if let Some(timeline) = self.timeline.take() {
+            timeline.destroy();
+        }
+
+        self.swapchain_details.destroy(&self.device);
+    }
 }
 
 impl<T: buffers::UniformBuffers> Iterator for Objects<T> {
     type Item = Result<()>;
 
+    // frames drawn by iterating have no caches besides the deferred destructions
     fn next(&mut self) -> Option<Self::Item> {
-        Some(self.draw_next_frame())
+        Some(self.draw_next_frame(&mut []))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::vulkan::headless::{Call, HeadlessDevice};
+
+    // One frame of draw_next_frame's fence path with a single command buffer
+    fn draw_frame(
+        device: &HeadlessDevice,
+        state: &mut FrameState,
+        fences: &[vk::Fence],
+        frames_in_flight: u32,
+    ) -> Result<bool> {
+        let fence = fences[state.current_frame()];
+        backend::FenceOps::wait_for_fences(device, &[fence], std::u64::MAX)?;
+
+        let image_index = device.acquire_next_image(vk::Semaphore::null(), std::u64::MAX)?;
+        let frame = frame::FrameContext::new(state.current_frame(), image_index);
+        let image_was_in_flight = state.wait_for_image_fence(device, &frame, fence)?;
+
+        let command_buffers = [vk::CommandBuffer::null()];
+        let submit_info = vk::SubmitInfo {
+            command_buffer_count: 1,
+            p_command_buffers: command_buffers.as_ptr(),
+            ..Default::default()
+        };
+        let present_info = vk::PresentInfoKHR {
+            swapchain_count: 1,
+            p_image_indices: &image_index,
+            ..Default::default()
+        };
+        submit_and_present(device, device, &submit_info, fence, &present_info)?;
+
+        state.advance(frames_in_flight);
+        Ok(image_was_in_flight)
+    }
+
+    #[test]
+    fn frames_wait_for_the_fence_of_their_image() {
+        let device = HeadlessDevice::new(3);
+        let fences = [device.create_fence(true), device.create_fence(true)];
+        let mut state = FrameState::default(3, 2);
+
+        // the first use of each image has nothing to wait for
+        for _ in 0..3 {
+            assert!(!draw_frame(&device, &mut state, &fences, 2).unwrap());
+        }
+        device.take_calls();
+
+        // image 0 was last submitted by frame 0, now frame 1 of the next round uses it
+        assert!(draw_frame(&device, &mut state, &fences, 2).unwrap());
+        assert_eq!(
+            device.calls(),
+            vec![
+                Call::WaitForFences(vec![fences[1]]),
+                Call::AcquireNextImage(0),
+                Call::WaitForFences(vec![fences[0]]),
+                Call::ResetFences(vec![fences[1]]),
+                Call::Submit {
+                    command_buffers: vec![vk::CommandBuffer::null()],
+                    fence: fences[1],
+                },
+                Call::Present(vec![0]),
+            ]
+        );
+    }
+
+    #[test]
+    fn frames_in_flight_limit_the_submissions() {
+        let device = HeadlessDevice::new(3);
+        let fences = [device.create_fence(true), device.create_fence(true)];
+        let mut state = FrameState::default(3, 2);
+
+        device.hold_submissions(true);
+        draw_frame(&device, &mut state, &fences, 2).unwrap();
+        draw_frame(&device, &mut state, &fences, 2).unwrap();
+
+        // the third frame reuses the first frame's fence, which the gpu did not signal yet
+        let err = draw_frame(&device, &mut state, &fences, 2).unwrap_err();
+        assert_eq!(err.vk_result(), Some(vk::Result::TIMEOUT));
+
+        device.complete_submissions();
+        assert!(device.is_signaled(fences[0]));
+        draw_frame(&device, &mut state, &fences, 2).unwrap();
+    }
+
+    #[test]
+    fn a_suboptimal_swapchain_is_out_of_date() {
+        let device = HeadlessDevice::new(2);
+        let fences = [device.create_fence(true)];
+        let mut state = FrameState::default(2, 1);
+
+        device.set_suboptimal(true);
+        let err = draw_frame(&device, &mut state, &fences, 1).unwrap_err();
+        assert!(err.is_swapchain_out_of_date());
+
+        device.set_suboptimal(false);
+        device.set_out_of_date(true);
+        let err = draw_frame(&device, &mut state, &fences, 1).unwrap_err();
+        assert!(err.is_swapchain_out_of_date());
     }
 }
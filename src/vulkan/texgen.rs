@@ -0,0 +1,365 @@
+use ash::version::DeviceV1_0;
+use ash::vk;
+
+use crate::error::{Context, Result};
+
+use std::ffi::CString;
+
+use crate::shaderc;
+
+use super::buffers;
+use super::device;
+use super::image;
+use super::pipeline;
+use super::registry;
+use super::texture;
+
+pub const TEXGEN_SHADER_FILE: &'static str = "shaders/texgen.comp";
+
+// Matches the image format declared in shaders/texgen.comp
+pub const TEXGEN_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+const WORKGROUP_SIZE: u32 = 8;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ProceduralTexture {
+    // split sum lookup table of the specular brdf, red: scale, green: bias
+    BrdfLut {
+        samples: u32,
+    },
+    Noise {
+        scale: f32,
+        seed: u32,
+        from: [f32; 4],
+        to: [f32; 4],
+    },
+    // vertical gradient
+    Gradient {
+        from: [f32; 4],
+        to: [f32; 4],
+    },
+    Checkerboard {
+        cells: u32,
+        a: [f32; 4],
+        b: [f32; 4],
+    },
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct PushConstants {
+    color_a: [f32; 4],
+    color_b: [f32; 4],
+    mode: u32,
+    param: u32,
+    scale: f32,
+}
+
+impl ProceduralTexture {
+    fn push_constants(&self) -> PushConstants {
+        let blank = PushConstants {
+            color_a: [0.0; 4],
+            color_b: [0.0; 4],
+            mode: 0,
+            param: 0,
+            scale: 1.0,
+        };
+
+        match *self {
+            ProceduralTexture::BrdfLut { samples } => PushConstants {
+                mode: 0,
+                param: samples,
+                ..blank
+            },
+            ProceduralTexture::Noise {
+                scale,
+                seed,
+                from,
+                to,
+            } => PushConstants {
+                color_a: from,
+                color_b: to,
+                mode: 1,
+                param: seed,
+                scale,
+            },
+            ProceduralTexture::Gradient { from, to } => PushConstants {
+                color_a: from,
+                color_b: to,
+                mode: 2,
+                ..blank
+            },
+            ProceduralTexture::Checkerboard { cells, a, b } => PushConstants {
+                color_a: a,
+                color_b: b,
+                mode: 3,
+                param: cells,
+                ..blank
+            },
+        }
+    }
+}
+
+// Renders procedural textures with a compute shader, eg. the brdf lut used for
+// image based lighting or placeholder textures that do not need to ship as files
+pub struct TextureGenerator {
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+impl TextureGenerator {
+    fn create_descriptor_set_layout(device: &ash::Device) -> Result<vk::DescriptorSetLayout> {
+        let bindings = [vk::DescriptorSetLayoutBinding {
+            binding: 0,
+            descriptor_type: vk::DescriptorType::STORAGE_IMAGE,
+            descriptor_count: 1,
+            stage_flags: vk::ShaderStageFlags::COMPUTE,
+            ..Default::default()
+        }];
+
+        let layout_info = vk::DescriptorSetLayoutCreateInfo {
+            binding_count: bindings.len() as u32,
+            p_bindings: bindings.as_ptr(),
+            ..Default::default()
+        };
+
+        unsafe {
+            device
+                .create_descriptor_set_layout(&layout_info, None)
+                .context("failed to create texgen descriptor set layout")
+        }
+    }
+
+    fn create_descriptor_pool(device: &ash::Device) -> Result<vk::DescriptorPool> {
+        let pool_size = vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::STORAGE_IMAGE,
+            descriptor_count: 1,
+        };
+
+        // a single set which is freed again after every generated texture
+        let pool_info = vk::DescriptorPoolCreateInfo {
+            flags: vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET,
+            pool_size_count: 1,
+            p_pool_sizes: &pool_size,
+            max_sets: 1,
+            ..Default::default()
+        };
+
+        unsafe {
+            device
+                .create_descriptor_pool(&pool_info, None)
+                .context("failed to create texgen descriptor pool")
+        }
+    }
+
+    fn create_pipeline(
+        device: &ash::Device,
+        pipeline_layout: vk::PipelineLayout,
+    ) -> Result<vk::Pipeline> {
+        let code = shaderc::ShaderSource::compile_compute(&TEXGEN_SHADER_FILE.to_string())?;
+        let shader_module = pipeline::PipelineDetail::create_shader_module(device, code)?;
+
+        let main_function_name = CString::new("main").unwrap();
+
+        let pipeline_info = vk::ComputePipelineCreateInfo {
+            stage: vk::PipelineShaderStageCreateInfo {
+                module: shader_module,
+                p_name: main_function_name.as_ptr(),
+                stage: vk::ShaderStageFlags::COMPUTE,
+                ..Default::default()
+            },
+            layout: pipeline_layout,
+            base_pipeline_index: -1,
+            ..Default::default()
+        };
+
+        let pipelines = unsafe {
+            device.create_compute_pipelines(vk::PipelineCache::null(), &[pipeline_info], None)
+        };
+
+        unsafe { device.destroy_shader_module(shader_module, None) };
+
+        pipelines
+            .map(|pipelines| pipelines[0])
+            .map_err(|(_, err)| err)
+            .context("failed to create texgen pipeline")
+    }
+
+    pub fn new(device: &ash::Device) -> Result<TextureGenerator> {
+        let descriptor_set_layout = TextureGenerator::create_descriptor_set_layout(device)?;
+        let descriptor_pool = TextureGenerator::create_descriptor_pool(device)?;
+
+        let push_constant_ranges = [vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::COMPUTE,
+            offset: 0,
+            size: ::std::mem::size_of::<PushConstants>() as u32,
+        }];
+
+        let set_layouts = [descriptor_set_layout];
+        let layout_info = vk::PipelineLayoutCreateInfo {
+            set_layout_count: set_layouts.len() as u32,
+            p_set_layouts: set_layouts.as_ptr(),
+            push_constant_range_count: push_constant_ranges.len() as u32,
+            p_push_constant_ranges: push_constant_ranges.as_ptr(),
+            ..Default::default()
+        };
+
+        let pipeline_layout = unsafe {
+            device
+                .create_pipeline_layout(&layout_info, None)
+                .context("failed to create texgen pipeline layout")
+        }?;
+
+        let pipeline = TextureGenerator::create_pipeline(device, pipeline_layout)?;
+
+        Ok(TextureGenerator {
+            descriptor_set_layout,
+            descriptor_pool,
+            pipeline_layout,
+            pipeline,
+        })
+    }
+
+    // Generates the texture and waits for it to be ready for sampling
+    pub fn generate(
+        &self,
+        device: &device::Device,
+        command_pool: vk::CommandPool,
+        queue: vk::Queue,
+        kind: ProceduralTexture,
+        extent: vk::Extent2D,
+    ) -> Result<texture::Texture> {
+        let logical_device = &device.logical_device;
+
+        let image_data = image::ImageData::new(
+            device,
+            command_pool,
+            queue,
+            image::ImagePropertyType::storage_property(extent, TEXGEN_FORMAT),
+        )?;
+
+        let layouts = [self.descriptor_set_layout];
+        let alloc_info = vk::DescriptorSetAllocateInfo {
+            descriptor_pool: self.descriptor_pool,
+            descriptor_set_count: 1,
+            p_set_layouts: layouts.as_ptr(),
+            ..Default::default()
+        };
+
+        let descriptor_set = unsafe {
+            logical_device
+                .allocate_descriptor_sets(&alloc_info)
+                .context("failed to allocate texgen descriptor set")
+        }?[0];
+
+        let image_info = [vk::DescriptorImageInfo {
+            sampler: vk::Sampler::null(),
+            image_view: image_data.image_view,
+            image_layout: vk::ImageLayout::GENERAL,
+        }];
+
+        let descriptor_write = vk::WriteDescriptorSet {
+            dst_set: descriptor_set,
+            dst_binding: 0,
+            descriptor_count: 1,
+            descriptor_type: vk::DescriptorType::STORAGE_IMAGE,
+            p_image_info: image_info.as_ptr(),
+            ..Default::default()
+        };
+
+        unsafe { logical_device.update_descriptor_sets(&[descriptor_write], &[]) };
+
+        let push_constants = [kind.push_constants()];
+        let barrier = image::TransitionBarrier::from_layout(
+            vk::ImageLayout::GENERAL,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        )?;
+
+        let image_barrier = vk::ImageMemoryBarrier {
+            src_access_mask: barrier.src_access_mask,
+            dst_access_mask: barrier.dst_access_mask,
+            old_layout: vk::ImageLayout::GENERAL,
+            new_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            image: image_data.image,
+            subresource_range: vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: 1,
+            },
+            ..Default::default()
+        };
+
+        buffers::CommandBuffer::record_and_submit_single_command(
+            logical_device,
+            command_pool,
+            queue,
+            |command_buffer| unsafe {
+                logical_device.cmd_bind_pipeline(
+                    command_buffer,
+                    vk::PipelineBindPoint::COMPUTE,
+                    self.pipeline,
+                );
+                logical_device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::COMPUTE,
+                    self.pipeline_layout,
+                    0,
+                    &[descriptor_set],
+                    &[],
+                );
+                logical_device.cmd_push_constants(
+                    command_buffer,
+                    self.pipeline_layout,
+                    vk::ShaderStageFlags::COMPUTE,
+                    0,
+                    std::slice::from_raw_parts(
+                        push_constants.as_ptr() as *const u8,
+                        ::std::mem::size_of::<PushConstants>(),
+                    ),
+                );
+                logical_device.cmd_dispatch(
+                    command_buffer,
+                    (extent.width + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE,
+                    (extent.height + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE,
+                    1,
+                );
+                logical_device.cmd_pipeline_barrier(
+                    command_buffer,
+                    barrier.source_stage,
+                    barrier.destination_stage,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &[image_barrier],
+                );
+            },
+        )?;
+
+        // the submission has completed, so the set is not in use anymore
+        unsafe { logical_device.free_descriptor_sets(self.descriptor_pool, &[descriptor_set]) };
+
+        let sampler = texture::Texture::create_texture_sampler(device)?;
+        device.track(registry::ResourceKind::Sampler, sampler);
+
+        Ok(texture::Texture {
+            image_data,
+            sampler,
+            color_space: texture::ColorSpace::Linear,
+        })
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        unsafe {
+            device.destroy_pipeline(self.pipeline, None);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            device.destroy_descriptor_pool(self.descriptor_pool, None);
+            device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+        }
+    }
+}
@@ -4,12 +4,41 @@ use ash::vk;
 use image;
 use image::GenericImageView;
 
-use std::path::Path;
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
 
-use anyhow::anyhow;
-use anyhow::{Context, Result};
+use crate::error::{Context, Error, Result};
+use crate::foreign;
 
-use super::{device, image as img};
+use super::{device, gc, image as img, registry, requirements};
+
+// How the texels of a texture are encoded. Colors authored for display, eg. albedo
+// maps, are sRGB and decoded to linear by the sampler. Data like normals or roughness
+// is stored linear and read as is.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ColorSpace {
+    Srgb,
+    Linear,
+}
+
+impl ColorSpace {
+    // Format of an RGBA8 texture in the color space
+    pub fn format(&self) -> vk::Format {
+        match self {
+            ColorSpace::Srgb => vk::Format::R8G8B8A8_SRGB,
+            ColorSpace::Linear => vk::Format::R8G8B8A8_UNORM,
+        }
+    }
+
+    // None for formats that are not 8 bit color, eg. depth or float formats
+    pub fn of_format(format: vk::Format) -> Option<ColorSpace> {
+        match format {
+            vk::Format::R8G8B8A8_SRGB | vk::Format::B8G8R8A8_SRGB => Some(ColorSpace::Srgb),
+            vk::Format::R8G8B8A8_UNORM | vk::Format::B8G8R8A8_UNORM => Some(ColorSpace::Linear),
+            _ => None,
+        }
+    }
+}
 
 // Represents data obtained for raw image file
 pub struct RawImage {
@@ -29,13 +58,15 @@ impl RawImage {
             image::DynamicImage::ImageBgra8(_)
             | image::DynamicImage::ImageLumaA8(_)
             | image::DynamicImage::ImageRgba8(_) => Ok(object.to_bytes()),
-            _ => Err(anyhow!("image cannot be converted to bytes")),
+            _ => Err(Error::Unsupported(
+                "image cannot be converted to bytes".to_string(),
+            )),
         }?;
         let size = (::std::mem::size_of::<u8>() as u32 * object.width() * object.height() * 4)
             as vk::DeviceSize;
 
         if size <= 0 {
-            Err(anyhow!(format!("failed to load image: {:?}", path)))
+            Err(Error::msg(format!("failed to load image: {:?}", path)))
         } else {
             Ok(RawImage { object, data, size })
         }
@@ -45,6 +76,7 @@ impl RawImage {
 pub struct Texture {
     pub image_data: img::ImageData,
     pub sampler: vk::Sampler,
+    pub color_space: ColorSpace,
 }
 
 impl Texture {
@@ -53,53 +85,174 @@ impl Texture {
         command_pool: vk::CommandPool,
         submit_queue: vk::Queue,
         image_path: &Path,
+        color_space: ColorSpace,
     ) -> Result<img::ImageData> {
         let image = RawImage::new(image_path)?;
 
-        let texture_property =
-            img::ImagePropertyType::texture_property(device, command_pool, submit_queue, image)?;
+        let texture_property = img::ImagePropertyType::texture_property(
+            device,
+            command_pool,
+            submit_queue,
+            image,
+            color_space.format(),
+        )?;
 
         img::ImageData::new(device, command_pool, submit_queue, texture_property)
     }
 
-    pub fn create_texture_sampler(device: &ash::Device) -> Result<vk::Sampler> {
+    // Anisotropic filtering is only used when the feature was enabled on the device, see
+    // requirements::DeviceRequirements
+    pub fn create_texture_sampler(device: &device::Device) -> Result<vk::Sampler> {
+        let anisotropy = device.is_feature_enabled(requirements::DeviceFeature::SamplerAnisotropy);
+
         let sampler_info = vk::SamplerCreateInfo {
             mag_filter: vk::Filter::LINEAR,
             min_filter: vk::Filter::LINEAR,
             address_mode_u: vk::SamplerAddressMode::REPEAT,
             address_mode_v: vk::SamplerAddressMode::REPEAT,
             address_mode_w: vk::SamplerAddressMode::REPEAT,
-            max_anisotropy: 16.0,
+            max_anisotropy: if anisotropy {
+                device.limits.max_sampler_anisotropy.min(16.0)
+            } else {
+                1.0
+            },
             compare_enable: vk::FALSE,
             compare_op: vk::CompareOp::ALWAYS,
             mipmap_mode: vk::SamplerMipmapMode::LINEAR,
             border_color: vk::BorderColor::INT_OPAQUE_BLACK,
-            anisotropy_enable: vk::TRUE,
+            anisotropy_enable: foreign::to_vk_bool(anisotropy),
             unnormalized_coordinates: vk::FALSE,
             ..Default::default()
         };
 
         unsafe {
             device
+                .logical_device
                 .create_sampler(&sampler_info, None)
                 .context("failed to create sampler!")
         }
     }
 
+    // A color texture, eg. an albedo map
     pub fn new(
         device: &device::Device,
         command_pool: vk::CommandPool,
         submit_queue: vk::Queue,
         image_path: &Path,
     ) -> Result<Texture> {
-        let image_data =
-            Texture::create_texture_image(device, command_pool, submit_queue, image_path)?;
+        Texture::with_color_space(
+            device,
+            command_pool,
+            submit_queue,
+            image_path,
+            ColorSpace::Srgb,
+        )
+    }
 
-        let sampler = Texture::create_texture_sampler(&device.logical_device)?;
+    pub fn with_color_space(
+        device: &device::Device,
+        command_pool: vk::CommandPool,
+        submit_queue: vk::Queue,
+        image_path: &Path,
+        color_space: ColorSpace,
+    ) -> Result<Texture> {
+        let image_data = Texture::create_texture_image(
+            device,
+            command_pool,
+            submit_queue,
+            image_path,
+            color_space,
+        )?;
+
+        let sampler = Texture::create_texture_sampler(device)?;
+        device.track(registry::ResourceKind::Sampler, sampler);
 
         Ok(Texture {
             image_data,
             sampler,
+            color_space,
         })
     }
+
+    pub fn destroy(&self, device: &device::Device) {
+        device.untrack(self.sampler);
+        unsafe { device.logical_device.destroy_sampler(self.sampler, None) };
+        self.image_data.destroy(device);
+    }
+}
+
+// Textures cached by default, see TextureCache
+pub const TEXTURE_CACHE_CAPACITY: usize = 16;
+
+// Textures no longer referenced by a handle, kept so loading the same file again, eg. when
+// a scene is reloaded, skips importing it. The gpu has to be done with a texture when it
+// is added. The oldest ones are destroyed by the garbage collector once there are more
+// than capacity, see gc::Evictable.
+pub struct TextureCache {
+    // the textures are destroyed with the device they were created on
+    device: device::Device,
+    textures: VecDeque<(PathBuf, Texture)>,
+    pub capacity: usize,
+}
+
+impl TextureCache {
+    pub fn new(device: &device::Device, capacity: usize) -> TextureCache {
+        TextureCache {
+            device: device.clone(),
+            textures: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    pub fn insert<P: Into<PathBuf>>(&mut self, path: P, texture: Texture) {
+        self.textures.push_back((path.into(), texture));
+    }
+
+    // The cached texture of the file, which is the caller's to destroy afterwards
+    pub fn take(&mut self, path: &Path) -> Option<Texture> {
+        let index = self
+            .textures
+            .iter()
+            .rposition(|(cached, _)| cached == path)?;
+        self.textures.remove(index).map(|(_, texture)| texture)
+    }
+
+    // eg. once the file changed
+    pub fn remove(&mut self, path: &Path) {
+        while let Some(texture) = self.take(path) {
+            texture.destroy(&self.device);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.textures.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.textures.is_empty()
+    }
+}
+
+impl gc::Evictable for TextureCache {
+    fn evict_next(&mut self, _device: &ash::Device) -> bool {
+        if self.textures.len() <= self.capacity {
+            return false;
+        }
+
+        match self.textures.pop_front() {
+            Some((_, texture)) => {
+                texture.destroy(&self.device);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn evict_all(&mut self, _device: &ash::Device) -> usize {
+        let evicted = self.textures.len();
+        for (_, texture) in self.textures.drain(..) {
+            texture.destroy(&self.device);
+        }
+        evicted
+    }
 }
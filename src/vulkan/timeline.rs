@@ -0,0 +1,191 @@
+use ash::version::DeviceV1_0;
+use ash::vk;
+
+use crate::error::{Context, Error, Result};
+
+use std::os::raw::c_void;
+
+use super::device;
+
+pub const TIMELINE_SEMAPHORE_EXTENSION: device::DeviceExtension = device::DeviceExtension {
+    names: ["VK_KHR_timeline_semaphore"],
+};
+
+// ash 0.29 predates VK_KHR_timeline_semaphore, so the parts of it used here are
+// declared by hand following vulkan_core.h
+const PHYSICAL_DEVICE_TIMELINE_SEMAPHORE_FEATURES: i32 = 1_000_207_000;
+const SEMAPHORE_TYPE_CREATE_INFO: i32 = 1_000_207_002;
+const TIMELINE_SEMAPHORE_SUBMIT_INFO: i32 = 1_000_207_003;
+const SEMAPHORE_WAIT_INFO: i32 = 1_000_207_004;
+const SEMAPHORE_TYPE_TIMELINE: i32 = 1;
+
+#[repr(C)]
+pub struct PhysicalDeviceTimelineSemaphoreFeatures {
+    s_type: vk::StructureType,
+    p_next: *mut c_void,
+    timeline_semaphore: vk::Bool32,
+}
+
+#[repr(C)]
+struct SemaphoreTypeCreateInfo {
+    s_type: vk::StructureType,
+    p_next: *const c_void,
+    semaphore_type: i32,
+    initial_value: u64,
+}
+
+#[repr(C)]
+pub struct TimelineSemaphoreSubmitInfo {
+    s_type: vk::StructureType,
+    p_next: *const c_void,
+    wait_semaphore_value_count: u32,
+    p_wait_semaphore_values: *const u64,
+    signal_semaphore_value_count: u32,
+    p_signal_semaphore_values: *const u64,
+}
+
+#[repr(C)]
+struct SemaphoreWaitInfo {
+    s_type: vk::StructureType,
+    p_next: *const c_void,
+    flags: u32,
+    semaphore_count: u32,
+    p_semaphores: *const vk::Semaphore,
+    p_values: *const u64,
+}
+
+type GetSemaphoreCounterValue =
+    unsafe extern "system" fn(vk::Device, vk::Semaphore, *mut u64) -> vk::Result;
+type WaitSemaphores =
+    unsafe extern "system" fn(vk::Device, *const SemaphoreWaitInfo, u64) -> vk::Result;
+
+// Chained into vk::DeviceCreateInfo to enable timeline semaphores, which every device
+// exposing the extension supports
+pub fn device_features() -> PhysicalDeviceTimelineSemaphoreFeatures {
+    PhysicalDeviceTimelineSemaphoreFeatures {
+        s_type: vk::StructureType::from_raw(PHYSICAL_DEVICE_TIMELINE_SEMAPHORE_FEATURES),
+        p_next: ::std::ptr::null_mut(),
+        timeline_semaphore: vk::TRUE,
+    }
+}
+
+// Values the submission waits for and signals, chained into vk::SubmitInfo. There is one
+// value per semaphore of the submit info, the ones of binary semaphores are ignored.
+// The info refers to the values, which have to outlive it.
+pub fn submit_info(wait_values: &[u64], signal_values: &[u64]) -> TimelineSemaphoreSubmitInfo {
+    TimelineSemaphoreSubmitInfo {
+        s_type: vk::StructureType::from_raw(TIMELINE_SEMAPHORE_SUBMIT_INFO),
+        p_next: ::std::ptr::null(),
+        wait_semaphore_value_count: wait_values.len() as u32,
+        p_wait_semaphore_values: wait_values.as_ptr(),
+        signal_semaphore_value_count: signal_values.len() as u32,
+        p_signal_semaphore_values: signal_values.as_ptr(),
+    }
+}
+
+// A semaphore holding a counter that only increases. Each submission signals a higher
+// value, so waiting for the value of a submission replaces a fence per frame, and other
+// queues can wait for the same value instead of needing a binary semaphore of their own.
+pub struct TimelineSemaphore {
+    device: ash::Device,
+    pub semaphore: vk::Semaphore,
+    get_counter_value: GetSemaphoreCounterValue,
+    wait_semaphores: WaitSemaphores,
+}
+
+impl TimelineSemaphore {
+    // Checked when creating the logical device, see device::Device::timeline_semaphore
+    pub fn is_supported(
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+    ) -> Result<bool> {
+        device::Device::check_device_extension_support(
+            instance,
+            physical_device,
+            &TIMELINE_SEMAPHORE_EXTENSION,
+        )
+    }
+
+    pub fn new(instance: &ash::Instance, device: &device::Device) -> Result<TimelineSemaphore> {
+        if !device.timeline_semaphore {
+            return Err(Error::Unsupported(
+                "timeline semaphores are not enabled on the device".to_string(),
+            ));
+        }
+
+        let logical_device = &device.logical_device;
+        let (get_counter_value, wait_semaphores) = unsafe {
+            (
+                device.load_function::<GetSemaphoreCounterValue>(
+                    instance,
+                    "vkGetSemaphoreCounterValueKHR",
+                )?,
+                device.load_function::<WaitSemaphores>(instance, "vkWaitSemaphoresKHR")?,
+            )
+        };
+
+        let type_info = SemaphoreTypeCreateInfo {
+            s_type: vk::StructureType::from_raw(SEMAPHORE_TYPE_CREATE_INFO),
+            p_next: ::std::ptr::null(),
+            semaphore_type: SEMAPHORE_TYPE_TIMELINE,
+            initial_value: 0,
+        };
+
+        let semaphore_info = vk::SemaphoreCreateInfo {
+            p_next: &type_info as *const SemaphoreTypeCreateInfo as *const c_void,
+            ..Default::default()
+        };
+
+        let semaphore = unsafe {
+            logical_device
+                .create_semaphore(&semaphore_info, None)
+                .context("failed to create timeline semaphore")
+        }?;
+        device.name_resource(semaphore, "frame timeline semaphore");
+
+        Ok(TimelineSemaphore {
+            device: logical_device.clone(),
+            semaphore,
+            get_counter_value,
+            wait_semaphores,
+        })
+    }
+
+    // Highest value signaled so far
+    pub fn value(&self) -> Result<u64> {
+        let mut value = 0;
+        let result =
+            unsafe { (self.get_counter_value)(self.device.handle(), self.semaphore, &mut value) };
+
+        match result {
+            vk::Result::SUCCESS => Ok(value),
+            err => Err(Error::Vk(err)).context("failed to read timeline semaphore value"),
+        }
+    }
+
+    // Blocks until the value is signaled, timeout in nanoseconds
+    pub fn wait(&self, value: u64, timeout: u64) -> Result<()> {
+        let semaphores = [self.semaphore];
+        let values = [value];
+
+        let wait_info = SemaphoreWaitInfo {
+            s_type: vk::StructureType::from_raw(SEMAPHORE_WAIT_INFO),
+            p_next: ::std::ptr::null(),
+            flags: 0,
+            semaphore_count: 1,
+            p_semaphores: semaphores.as_ptr(),
+            p_values: values.as_ptr(),
+        };
+
+        let result = unsafe { (self.wait_semaphores)(self.device.handle(), &wait_info, timeout) };
+
+        match result {
+            vk::Result::SUCCESS => Ok(()),
+            err => Err(Error::Vk(err)).context("failed to wait for timeline semaphore"),
+        }
+    }
+
+    pub fn destroy(&self) {
+        unsafe { self.device.destroy_semaphore(self.semaphore, None) };
+    }
+}
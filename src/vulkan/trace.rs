@@ -0,0 +1,30 @@
+// Call level logging of the vulkan api.
+// With the `vk-trace` feature every wrapped call is reported to the `tracing` subscriber
+// along with its parameters and how long it took. Without the feature the wrapper is just the call.
+
+#[cfg(feature = "vk-trace")]
+pub fn call<T, F>(name: &'static str, params: &dyn std::fmt::Debug, f: F) -> T
+where
+    F: FnOnce() -> T,
+{
+    let start = std::time::Instant::now();
+    let result = f();
+
+    tracing::trace!(
+        target: "kelsier::vulkan",
+        call = name,
+        params = ?params,
+        elapsed_us = start.elapsed().as_micros() as u64,
+    );
+
+    result
+}
+
+#[cfg(not(feature = "vk-trace"))]
+#[inline(always)]
+pub fn call<T, F>(_name: &'static str, _params: &dyn std::fmt::Debug, f: F) -> T
+where
+    F: FnOnce() -> T,
+{
+    f()
+}
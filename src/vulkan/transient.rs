@@ -0,0 +1,432 @@
+use ash::version::DeviceV1_0;
+use ash::vk;
+use ash::vk::Handle;
+
+use crate::error::{Context, Error, Result};
+
+use super::device;
+use super::image;
+use super::registry;
+
+// The passes an attachment is used in, as indices into the order the passes are recorded
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PassRange {
+    pub first: u32,
+    pub last: u32,
+}
+
+impl PassRange {
+    pub fn new(first: u32, last: u32) -> PassRange {
+        PassRange {
+            first: first.min(last),
+            last: first.max(last),
+        }
+    }
+
+    // a pass using both attachments keeps them apart
+    pub fn overlaps(&self, other: &PassRange) -> bool {
+        self.first <= other.last && other.first <= self.last
+    }
+}
+
+// Memory requirements of an attachment and when it is alive
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AliasRequest {
+    pub size: vk::DeviceSize,
+    pub alignment: vk::DeviceSize,
+    pub memory_type_bits: u32,
+    pub passes: PassRange,
+}
+
+// One allocation shared by attachments that are never alive at the same time. All of
+// them are bound at its start, so it is as large as the largest of them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AliasBlock {
+    pub size: vk::DeviceSize,
+    pub alignment: vk::DeviceSize,
+    // the memory types every member can live in
+    pub memory_type_bits: u32,
+    // indices of the requests, the largest first
+    pub members: Vec<usize>,
+}
+
+impl AliasBlock {
+    fn accepts(&self, request: &AliasRequest, requests: &[AliasRequest]) -> bool {
+        self.memory_type_bits & request.memory_type_bits != 0
+            && self
+                .members
+                .iter()
+                .all(|&member| !requests[member].passes.overlaps(&request.passes))
+    }
+}
+
+// Which attachments share memory, decided from their requirements and pass ranges
+#[derive(Debug, Clone, PartialEq)]
+pub struct AliasingPlan {
+    pub blocks: Vec<AliasBlock>,
+    // block of each request
+    placements: Vec<usize>,
+    unaliased_size: vk::DeviceSize,
+}
+
+impl AliasingPlan {
+    // Largest first, each request joins the first block it is compatible with, so the
+    // small attachments fill in behind the large ones instead of growing the blocks
+    pub fn new(requests: &[AliasRequest]) -> AliasingPlan {
+        let mut order: Vec<usize> = (0..requests.len()).collect();
+        order.sort_by(|&a, &b| requests[b].size.cmp(&requests[a].size));
+
+        let mut blocks: Vec<AliasBlock> = Vec::new();
+        let mut placements = vec![0; requests.len()];
+
+        for index in order {
+            let request = &requests[index];
+
+            match blocks
+                .iter()
+                .position(|block| block.accepts(request, requests))
+            {
+                Some(block_index) => {
+                    let block = &mut blocks[block_index];
+                    block.size = block.size.max(request.size);
+                    block.alignment = block.alignment.max(request.alignment);
+                    block.memory_type_bits &= request.memory_type_bits;
+                    block.members.push(index);
+                    placements[index] = block_index;
+                }
+                None => {
+                    placements[index] = blocks.len();
+                    blocks.push(AliasBlock {
+                        size: request.size,
+                        alignment: request.alignment,
+                        memory_type_bits: request.memory_type_bits,
+                        members: vec![index],
+                    });
+                }
+            }
+        }
+
+        AliasingPlan {
+            blocks,
+            placements,
+            unaliased_size: requests.iter().map(|request| request.size).sum(),
+        }
+    }
+
+    pub fn block_of(&self, request: usize) -> Option<usize> {
+        self.placements.get(request).copied()
+    }
+
+    // memory allocated with aliasing
+    pub fn total_size(&self) -> vk::DeviceSize {
+        self.blocks.iter().map(|block| block.size).sum()
+    }
+
+    // memory a separate allocation per attachment would take
+    pub fn unaliased_size(&self) -> vk::DeviceSize {
+        self.unaliased_size
+    }
+
+    pub fn saved_size(&self) -> vk::DeviceSize {
+        self.unaliased_size - self.total_size()
+    }
+}
+
+// An attachment that only lives during some of the passes of a frame, eg. a depth
+// buffer of an offscreen pass or the intermediates of bloom
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TransientAttachment {
+    pub extent: vk::Extent2D,
+    pub format: vk::Format,
+    pub usage: vk::ImageUsageFlags,
+    pub aspect: vk::ImageAspectFlags,
+    pub passes: PassRange,
+}
+
+impl TransientAttachment {
+    // rendered to and sampled by a later pass
+    pub fn color(
+        extent: vk::Extent2D,
+        format: vk::Format,
+        passes: PassRange,
+    ) -> TransientAttachment {
+        TransientAttachment {
+            extent,
+            format,
+            usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+            aspect: vk::ImageAspectFlags::COLOR,
+            passes,
+        }
+    }
+
+    pub fn depth(
+        extent: vk::Extent2D,
+        format: vk::Format,
+        passes: PassRange,
+    ) -> TransientAttachment {
+        TransientAttachment {
+            extent,
+            format,
+            usage: vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+            aspect: vk::ImageAspectFlags::DEPTH,
+            passes,
+        }
+    }
+
+    pub fn with_usage(mut self, usage: vk::ImageUsageFlags) -> TransientAttachment {
+        self.usage = usage;
+        self
+    }
+
+    fn properties(&self) -> image::ImageProperties {
+        image::ImageProperties {
+            width: self.extent.width,
+            height: self.extent.height,
+            format: self.format,
+            usage_flags: self.usage,
+            aspect_flag: self.aspect,
+        }
+    }
+}
+
+pub struct TransientImage {
+    pub image: vk::Image,
+    pub image_view: vk::ImageView,
+    pub format: vk::Format,
+    pub extent: vk::Extent2D,
+}
+
+// Attachments whose memory is aliased between the ones that are never alive at the same
+// time, see AliasingPlan.
+// The contents of an attachment are undefined when its first pass begins, as another
+// one may have been written over them. Its render pass has to start from the UNDEFINED
+// layout and clear or not load it, and the passes have to be recorded in the order the
+// pass ranges describe with a dependency between them, eg. the external subpass
+// dependencies of their render passes.
+pub struct TransientPool {
+    images: Vec<TransientImage>,
+    memories: Vec<vk::DeviceMemory>,
+    plan: AliasingPlan,
+}
+
+impl TransientPool {
+    pub fn new(
+        device: &device::Device,
+        attachments: &[TransientAttachment],
+    ) -> Result<TransientPool> {
+        let mut pool = TransientPool {
+            images: Vec::with_capacity(attachments.len()),
+            memories: Vec::new(),
+            plan: AliasingPlan::new(&[]),
+        };
+
+        match pool.populate(device, attachments) {
+            Ok(()) => Ok(pool),
+            Err(err) => {
+                pool.destroy(device);
+                Err(err)
+            }
+        }
+    }
+
+    fn populate(
+        &mut self,
+        device: &device::Device,
+        attachments: &[TransientAttachment],
+    ) -> Result<()> {
+        let logical_device = &device.logical_device;
+
+        let mut requests = Vec::with_capacity(attachments.len());
+        for attachment in attachments.iter() {
+            let image = image::ImageData::create_image_handle(
+                device,
+                &attachment.properties(),
+                vk::ImageCreateFlags::empty(),
+                1,
+                1,
+            )?;
+
+            self.images.push(TransientImage {
+                image,
+                image_view: vk::ImageView::null(),
+                format: attachment.format,
+                extent: attachment.extent,
+            });
+
+            let requirements = unsafe { logical_device.get_image_memory_requirements(image) };
+            requests.push(AliasRequest {
+                size: requirements.size,
+                alignment: requirements.alignment,
+                memory_type_bits: requirements.memory_type_bits,
+                passes: attachment.passes,
+            });
+        }
+
+        self.plan = AliasingPlan::new(&requests);
+
+        for block in self.plan.blocks.iter() {
+            // blocks only holding attachments that never leave their passes can stay
+            // in tile memory like single transient attachments, see image::ImageData::new
+            let memory_properties = if block.members.iter().all(|&member| {
+                attachments[member]
+                    .usage
+                    .contains(vk::ImageUsageFlags::TRANSIENT_ATTACHMENT)
+            }) {
+                vk::MemoryPropertyFlags::DEVICE_LOCAL | vk::MemoryPropertyFlags::LAZILY_ALLOCATED
+            } else {
+                vk::MemoryPropertyFlags::DEVICE_LOCAL
+            };
+
+            let memory_allocate_info = vk::MemoryAllocateInfo {
+                allocation_size: block.size,
+                memory_type_index: image::ImageData::memory_type_for(
+                    device,
+                    block.memory_type_bits,
+                    memory_properties,
+                )?,
+                ..Default::default()
+            };
+
+            let memory = unsafe {
+                logical_device
+                    .allocate_memory(&memory_allocate_info, None)
+                    .context("failed to allocate transient attachment memory")
+            }?;
+            self.memories.push(memory);
+
+            for (position, &member) in block.members.iter().enumerate() {
+                let attachment = &attachments[member];
+                let image = self.images[member].image;
+
+                unsafe {
+                    logical_device
+                        .bind_image_memory(image, memory, 0)
+                        .context("failed to bind transient attachment memory")
+                }?;
+
+                // the block's memory is counted once, with its largest member
+                if let Ok(mut resources) = device.resources.lock() {
+                    resources.register_image(
+                        image,
+                        if position == 0 { block.size } else { 0 },
+                        attachment.usage,
+                        attachment.format,
+                        attachment.extent,
+                    );
+                }
+
+                let image_view = image::ImageData::create_image_view(
+                    logical_device,
+                    image,
+                    &attachment.properties(),
+                    1,
+                )?;
+                device.track(registry::ResourceKind::ImageView, image_view);
+                self.images[member].image_view = image_view;
+            }
+        }
+
+        Ok(())
+    }
+
+    // In the order of the attachments the pool was created with
+    pub fn image(&self, index: usize) -> Result<&TransientImage> {
+        self.images.get(index).ok_or_else(|| {
+            Error::OutOfRange(format!(
+                "no transient attachment {}, the pool holds {}",
+                index,
+                self.images.len()
+            ))
+        })
+    }
+
+    pub fn plan(&self) -> &AliasingPlan {
+        &self.plan
+    }
+
+    pub fn set_name(&self, device: &device::Device, index: usize, name: &str) -> Result<()> {
+        let image = self.image(index)?;
+        device.name_resource(image.image, name);
+        device.name_resource(image.image_view, &format!("{} view", name));
+        Ok(())
+    }
+
+    // The device must be idle, none of the attachments may be in use anymore
+    pub fn destroy(&self, device: &device::Device) {
+        let logical_device = &device.logical_device;
+
+        for image in self.images.iter() {
+            if let Ok(mut resources) = device.resources.lock() {
+                resources.unregister(image.image.as_raw());
+            }
+
+            unsafe {
+                if image.image_view != vk::ImageView::null() {
+                    device.untrack(image.image_view);
+                    logical_device.destroy_image_view(image.image_view, None);
+                }
+                logical_device.destroy_image(image.image, None);
+            }
+        }
+
+        for &memory in self.memories.iter() {
+            unsafe { logical_device.free_memory(memory, None) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(size: vk::DeviceSize, first: u32, last: u32) -> AliasRequest {
+        AliasRequest {
+            size,
+            alignment: 256,
+            memory_type_bits: 0b11,
+            passes: PassRange::new(first, last),
+        }
+    }
+
+    #[test]
+    fn attachments_of_disjoint_passes_share_memory() {
+        // depth of a shadow pass, then two bloom intermediates of the post passes
+        let requests = [request(1000, 0, 0), request(400, 2, 3), request(300, 3, 4)];
+
+        let plan = AliasingPlan::new(&requests);
+
+        assert_eq!(plan.blocks.len(), 2);
+        assert_eq!(plan.block_of(0), plan.block_of(1));
+        assert_ne!(plan.block_of(1), plan.block_of(2));
+        assert_eq!(plan.total_size(), 1300);
+        assert_eq!(plan.saved_size(), 400);
+    }
+
+    #[test]
+    fn overlapping_passes_keep_attachments_apart() {
+        assert!(PassRange::new(1, 3).overlaps(&PassRange::new(3, 5)));
+        assert!(!PassRange::new(1, 2).overlaps(&PassRange::new(3, 5)));
+        assert_eq!(PassRange::new(4, 2), PassRange::new(2, 4));
+
+        let plan = AliasingPlan::new(&[request(100, 0, 2), request(100, 1, 1)]);
+        assert_eq!(plan.blocks.len(), 2);
+        assert_eq!(plan.saved_size(), 0);
+    }
+
+    #[test]
+    fn blocks_fit_every_member() {
+        let mut small = request(100, 2, 2);
+        small.alignment = 4096;
+        // lives in memory types none of the others can use
+        let mut other_memory = request(500, 4, 4);
+        other_memory.memory_type_bits = 0b100;
+
+        let plan = AliasingPlan::new(&[request(200, 0, 1), small, other_memory]);
+
+        let block = &plan.blocks[plan.block_of(0).unwrap()];
+        assert_eq!((block.size, block.alignment), (200, 4096));
+        assert_eq!(block.members, vec![0, 1]);
+        assert_ne!(plan.block_of(2), plan.block_of(0));
+        assert_eq!(plan.block_of(3), None);
+    }
+}
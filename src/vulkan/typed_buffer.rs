@@ -0,0 +1,303 @@
+use ash::vk;
+
+use crate::error::{Error, Result};
+
+use std::marker::PhantomData;
+
+use super::buffers;
+use super::device;
+
+// What a typed buffer is bound as, decides its usage flags and element alignment
+pub trait BufferKind {
+    const USAGE: vk::BufferUsageFlags;
+
+    // Required alignment between elements that are bound individually
+    fn alignment(_limits: &vk::PhysicalDeviceLimits) -> vk::DeviceSize {
+        1
+    }
+}
+
+pub enum Vertex {}
+pub enum Index {}
+pub enum Uniform {}
+pub enum Storage {}
+pub enum Indirect {}
+
+impl BufferKind for Vertex {
+    const USAGE: vk::BufferUsageFlags = vk::BufferUsageFlags::VERTEX_BUFFER;
+}
+
+impl BufferKind for Index {
+    const USAGE: vk::BufferUsageFlags = vk::BufferUsageFlags::INDEX_BUFFER;
+}
+
+impl BufferKind for Uniform {
+    const USAGE: vk::BufferUsageFlags = vk::BufferUsageFlags::UNIFORM_BUFFER;
+
+    fn alignment(limits: &vk::PhysicalDeviceLimits) -> vk::DeviceSize {
+        limits.min_uniform_buffer_offset_alignment
+    }
+}
+
+impl BufferKind for Storage {
+    const USAGE: vk::BufferUsageFlags = vk::BufferUsageFlags::STORAGE_BUFFER;
+
+    fn alignment(limits: &vk::PhysicalDeviceLimits) -> vk::DeviceSize {
+        limits.min_storage_buffer_offset_alignment
+    }
+}
+
+impl BufferKind for Indirect {
+    const USAGE: vk::BufferUsageFlags = vk::BufferUsageFlags::INDIRECT_BUFFER;
+
+    // draw commands are read at 4 byte aligned offsets
+    fn alignment(_limits: &vk::PhysicalDeviceLimits) -> vk::DeviceSize {
+        4
+    }
+}
+
+// Element types an index buffer can hold
+pub trait IndexElement: Copy {
+    const INDEX_TYPE: vk::IndexType;
+}
+
+impl IndexElement for u16 {
+    const INDEX_TYPE: vk::IndexType = vk::IndexType::UINT16;
+}
+
+impl IndexElement for u32 {
+    const INDEX_TYPE: vk::IndexType = vk::IndexType::UINT32;
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MemoryLocation {
+    // mapped for every update, eg. per frame uniforms
+    HostVisible,
+    // written once through a staging copy
+    DeviceLocal,
+}
+
+pub type VertexBuffer<T> = TypedBuffer<Vertex, T>;
+pub type IndexBuffer<T> = TypedBuffer<Index, T>;
+pub type UniformBuffer<T> = TypedBuffer<Uniform, T>;
+pub type StorageBuffer<T> = TypedBuffer<Storage, T>;
+pub type IndirectBuffer = TypedBuffer<Indirect, vk::DrawIndexedIndirectCommand>;
+
+// A buffer holding `len` elements of T, each `stride` bytes apart.
+// The stride is padded to the alignment the kind requires, so every element can
+// be bound on its own, eg. as a dynamic uniform buffer offset.
+pub struct TypedBuffer<K: BufferKind, T: Copy> {
+    pub info: buffers::BufferInfo,
+    len: usize,
+    stride: vk::DeviceSize,
+    location: MemoryLocation,
+    kind: PhantomData<(K, T)>,
+}
+
+impl<K: BufferKind, T: Copy> TypedBuffer<K, T> {
+    pub fn stride_for(limits: &vk::PhysicalDeviceLimits) -> vk::DeviceSize {
+        let size = ::std::mem::size_of::<T>() as vk::DeviceSize;
+        let alignment = K::alignment(limits).max(1);
+
+        (size + alignment - 1) / alignment * alignment
+    }
+
+    // Uninitialized host visible buffer with room for len elements
+    pub fn host_visible(device: &device::Device, len: usize) -> Result<TypedBuffer<K, T>> {
+        let stride = TypedBuffer::<K, T>::stride_for(&device.limits);
+
+        let info = buffers::BufferInfo::create(
+            device,
+            stride * len.max(1) as vk::DeviceSize,
+            K::USAGE,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+
+        Ok(TypedBuffer {
+            info,
+            len,
+            stride,
+            location: MemoryLocation::HostVisible,
+            kind: PhantomData,
+        })
+    }
+
+    pub fn with_data(device: &device::Device, data: &[T]) -> Result<TypedBuffer<K, T>> {
+        let buffer = TypedBuffer::host_visible(device, data.len())?;
+        buffer.update(&device.logical_device, data)?;
+        Ok(buffer)
+    }
+
+    // Copies the data into device local memory, the buffer can't be updated afterwards
+    pub fn device_local(
+        device: &device::Device,
+        command_pool: vk::CommandPool,
+        queue: vk::Queue,
+        data: &[T],
+    ) -> Result<TypedBuffer<K, T>> {
+        let stride = TypedBuffer::<K, T>::stride_for(&device.limits);
+        let bytes = TypedBuffer::<K, T>::pack(data, stride);
+
+        let info = buffers::BufferInfo::create_gpu_local_buffer(
+            device,
+            command_pool,
+            queue,
+            K::USAGE,
+            &bytes,
+            None,
+        )?;
+
+        Ok(TypedBuffer {
+            info,
+            len: data.len(),
+            stride,
+            location: MemoryLocation::DeviceLocal,
+            kind: PhantomData,
+        })
+    }
+
+    // Wraps a tightly packed buffer created elsewhere, eg. by the upload manager
+    pub fn from_packed(
+        info: buffers::BufferInfo,
+        len: usize,
+        location: MemoryLocation,
+    ) -> Result<TypedBuffer<K, T>> {
+        let stride = ::std::mem::size_of::<T>() as vk::DeviceSize;
+        if stride * len as vk::DeviceSize > info.size() {
+            return Err(Error::OutOfRange(format!(
+                "buffer of size {} can't hold {} elements of size {}",
+                info.size(),
+                len,
+                stride
+            )));
+        }
+
+        Ok(TypedBuffer {
+            info,
+            len,
+            stride,
+            location,
+            kind: PhantomData,
+        })
+    }
+
+    // Lays out the elements `stride` bytes apart
+    fn pack(data: &[T], stride: vk::DeviceSize) -> Vec<u8> {
+        let size = ::std::mem::size_of::<T>();
+        let mut bytes = vec![0u8; data.len() * stride as usize];
+
+        for (index, element) in data.iter().enumerate() {
+            let element =
+                unsafe { std::slice::from_raw_parts(element as *const T as *const u8, size) };
+            let offset = index * stride as usize;
+            bytes[offset..offset + size].copy_from_slice(element);
+        }
+
+        bytes
+    }
+
+    pub fn update(&self, device: &ash::Device, data: &[T]) -> Result<()> {
+        self.update_at(device, 0, data)
+    }
+
+    // Overwrites the elements starting at `first`
+    pub fn update_at(&self, device: &ash::Device, first: usize, data: &[T]) -> Result<()> {
+        if self.location != MemoryLocation::HostVisible {
+            return Err(Error::Unsupported(
+                "device local buffers can't be updated from the host".to_string(),
+            ));
+        }
+
+        if first + data.len() > self.len {
+            return Err(Error::OutOfRange(format!(
+                "writing {} elements at {} overflows buffer of {} elements",
+                data.len(),
+                first,
+                self.len
+            )));
+        }
+
+        let offset = self.offset_of(first)?;
+        if self.stride == ::std::mem::size_of::<T>() as vk::DeviceSize {
+            self.info.update_region(device, offset, data)
+        } else {
+            self.info.update_region(
+                device,
+                offset,
+                &TypedBuffer::<K, T>::pack(data, self.stride),
+            )
+        }
+    }
+
+    pub fn offset_of(&self, index: usize) -> Result<vk::DeviceSize> {
+        if index > self.len {
+            return Err(Error::OutOfRange(format!(
+                "element {} is out of range, the buffer holds {}",
+                index, self.len
+            )));
+        }
+
+        Ok(index as vk::DeviceSize * self.stride)
+    }
+
+    pub fn buffer(&self) -> vk::Buffer {
+        self.info.buffer
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn stride(&self) -> vk::DeviceSize {
+        self.stride
+    }
+
+    pub fn size(&self) -> vk::DeviceSize {
+        self.info.size()
+    }
+
+    pub fn usage(&self) -> vk::BufferUsageFlags {
+        K::USAGE
+    }
+
+    pub fn location(&self) -> MemoryLocation {
+        self.location
+    }
+
+    pub fn destroy(&self, device: &device::Device) {
+        self.info.destroy(device);
+    }
+}
+
+impl<T: IndexElement> TypedBuffer<Index, T> {
+    pub fn index_type(&self) -> vk::IndexType {
+        T::INDEX_TYPE
+    }
+}
+
+// Descriptors can only point at uniform and storage buffers
+pub trait DescriptorBuffer {}
+impl DescriptorBuffer for Uniform {}
+impl DescriptorBuffer for Storage {}
+
+impl<K: BufferKind + DescriptorBuffer, T: Copy> TypedBuffer<K, T> {
+    // Range of a single element for writing descriptor sets
+    pub fn descriptor_info(&self, index: usize) -> Result<vk::DescriptorBufferInfo> {
+        if index >= self.len {
+            return Err(Error::OutOfRange(format!(
+                "no element {} to bind, the buffer holds {}",
+                index, self.len
+            )));
+        }
+
+        Ok(vk::DescriptorBufferInfo {
+            buffer: self.info.buffer,
+            offset: self.offset_of(index)?,
+            range: ::std::mem::size_of::<T>() as vk::DeviceSize,
+        })
+    }
+}
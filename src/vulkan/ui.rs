@@ -0,0 +1,490 @@
+use ash::version::DeviceV1_0;
+use ash::vk;
+
+use crate::error::{Context, Error, Result};
+
+use crate::shaderc;
+
+use super::device;
+use super::dynamic_buffer;
+use super::frame;
+use super::pipeline;
+use super::preset;
+use super::registry;
+use super::swapchain;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct UiVertex {
+    pub pos: [f32; 2],
+    pub tex_coord: [f32; 2],
+    pub color: [f32; 4],
+}
+
+crate::impl_vertex_data!(UiVertex {
+    pos,
+    tex_coord,
+    color
+});
+
+// Maps pixel coordinates to normalized device coordinates, see shaders/ui.vert
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct PushConstants {
+    scale: [f32; 2],
+    translate: [f32; 2],
+}
+
+// Geometry of the ui for the current frame in pixel coordinates.
+// External ui libraries (eg. egui) hand over their tessellated meshes through `append`.
+#[derive(Default)]
+pub struct UiDrawList {
+    pub vertices: Vec<UiVertex>,
+    pub indices: Vec<u32>,
+}
+
+impl UiDrawList {
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+        self.indices.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+
+    pub fn append(&mut self, vertices: &[UiVertex], indices: &[u32]) {
+        let base = self.vertices.len() as u32;
+
+        self.vertices.extend_from_slice(vertices);
+        self.indices
+            .extend(indices.iter().map(|index| index + base));
+    }
+
+    pub fn rect(&mut self, x: f32, y: f32, width: f32, height: f32, color: [f32; 4]) {
+        let vertex = |pos: [f32; 2], tex_coord: [f32; 2]| UiVertex {
+            pos,
+            tex_coord,
+            color,
+        };
+
+        self.append(
+            &[
+                vertex([x, y], [0.0, 0.0]),
+                vertex([x + width, y], [1.0, 0.0]),
+                vertex([x + width, y + height], [1.0, 1.0]),
+                vertex([x, y + height], [0.0, 1.0]),
+            ],
+            &[0, 1, 2, 2, 3, 0],
+        );
+    }
+}
+
+// Draws the ui on top of the already presented-ready scene image using a second render pass.
+// Command buffers are re-recorded every frame as the ui geometry changes.
+pub struct UiOverlay {
+    device: device::Device,
+
+    pub render_pass: vk::RenderPass,
+    pub pipeline: vk::Pipeline,
+    pub layout: vk::PipelineLayout,
+
+    framebuffers: Vec<vk::Framebuffer>,
+    command_pool: vk::CommandPool,
+    command_buffers: Vec<vk::CommandBuffer>,
+
+    // one set of buffers per swapchain image so that in flight frames are not overwritten
+    vertex_buffers: Vec<dynamic_buffer::DynamicBuffer>,
+    index_buffers: Vec<dynamic_buffer::DynamicBuffer>,
+
+    extent: vk::Extent2D,
+    pub draw_list: UiDrawList,
+}
+
+impl UiOverlay {
+    fn create_render_pass(
+        device: &ash::Device,
+        surface_format: vk::Format,
+    ) -> Result<vk::RenderPass> {
+        // the scene has already been rendered into the image, so load instead of clear
+        let color_attachment = vk::AttachmentDescription {
+            format: surface_format,
+            samples: vk::SampleCountFlags::TYPE_1,
+            load_op: vk::AttachmentLoadOp::LOAD,
+            store_op: vk::AttachmentStoreOp::STORE,
+            stencil_load_op: vk::AttachmentLoadOp::DONT_CARE,
+            stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
+            initial_layout: vk::ImageLayout::PRESENT_SRC_KHR,
+            final_layout: vk::ImageLayout::PRESENT_SRC_KHR,
+            ..Default::default()
+        };
+
+        let color_attachment_ref = vk::AttachmentReference {
+            attachment: 0,
+            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        };
+
+        let subpasses = [vk::SubpassDescription {
+            color_attachment_count: 1,
+            p_color_attachments: &color_attachment_ref,
+            pipeline_bind_point: vk::PipelineBindPoint::GRAPHICS,
+            ..Default::default()
+        }];
+
+        let attachments = [color_attachment];
+
+        let subpass_dependencies = [vk::SubpassDependency {
+            src_subpass: vk::SUBPASS_EXTERNAL,
+            src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            dst_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            src_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            dst_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_READ
+                | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            ..Default::default()
+        }];
+
+        let renderpass_create_info = vk::RenderPassCreateInfo {
+            attachment_count: attachments.len() as u32,
+            p_attachments: attachments.as_ptr(),
+            subpass_count: subpasses.len() as u32,
+            p_subpasses: subpasses.as_ptr(),
+            dependency_count: subpass_dependencies.len() as u32,
+            p_dependencies: subpass_dependencies.as_ptr(),
+            ..Default::default()
+        };
+
+        unsafe {
+            device
+                .create_render_pass(&renderpass_create_info, None)
+                .context("failed to create ui render pass")
+        }
+    }
+
+    fn create_pipeline_layout(device: &ash::Device) -> Result<vk::PipelineLayout> {
+        let push_constant_ranges = [vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::VERTEX,
+            offset: 0,
+            size: ::std::mem::size_of::<PushConstants>() as u32,
+        }];
+
+        let layout_info = vk::PipelineLayoutCreateInfo {
+            push_constant_range_count: push_constant_ranges.len() as u32,
+            p_push_constant_ranges: push_constant_ranges.as_ptr(),
+            ..Default::default()
+        };
+
+        unsafe {
+            device
+                .create_pipeline_layout(&layout_info, None)
+                .context("failed to create ui pipeline layout")
+        }
+    }
+
+    fn create_framebuffers(
+        device: &ash::Device,
+        render_pass: vk::RenderPass,
+        image_views: &Vec<vk::ImageView>,
+        extent: vk::Extent2D,
+    ) -> Result<Vec<vk::Framebuffer>> {
+        image_views
+            .iter()
+            .map(|&image_view| {
+                let attachments = [image_view];
+
+                let framebuffer_info = vk::FramebufferCreateInfo {
+                    render_pass,
+                    attachment_count: attachments.len() as u32,
+                    p_attachments: attachments.as_ptr(),
+                    width: extent.width,
+                    height: extent.height,
+                    layers: 1,
+                    ..Default::default()
+                };
+
+                unsafe {
+                    device
+                        .create_framebuffer(&framebuffer_info, None)
+                        .context("failed to create ui framebuffer")
+                }
+            })
+            .collect()
+    }
+
+    fn create_command_buffers(
+        device: &device::Device,
+        count: u32,
+    ) -> Result<(vk::CommandPool, Vec<vk::CommandBuffer>)> {
+        let queue_index = device
+            .family_indices
+            .graphics
+            .ok_or_else(|| Error::msg("graphics family index not present"))?;
+
+        // buffers are reset and recorded again every frame
+        let command_pool_info = vk::CommandPoolCreateInfo {
+            queue_family_index: queue_index,
+            flags: vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER,
+            ..Default::default()
+        };
+
+        let command_pool = unsafe {
+            device
+                .logical_device
+                .create_command_pool(&command_pool_info, None)
+                .context("failed to create ui command pool")
+        }?;
+
+        let alloc_info = vk::CommandBufferAllocateInfo {
+            command_buffer_count: count,
+            command_pool,
+            level: vk::CommandBufferLevel::PRIMARY,
+            ..Default::default()
+        };
+
+        let command_buffers = unsafe {
+            device
+                .logical_device
+                .allocate_command_buffers(&alloc_info)
+                .context("failed to allocate ui command buffers")
+        }?;
+
+        Ok((command_pool, command_buffers))
+    }
+
+    pub fn new(
+        device: &device::Device,
+        swapchain: &swapchain::SwapchainDetails,
+        shaders: shaderc::ShaderSource,
+    ) -> Result<UiOverlay> {
+        let logical_device = &device.logical_device;
+        let num_images = swapchain.image_views.len();
+
+        let render_pass = UiOverlay::create_render_pass(logical_device, swapchain.format.format)?;
+        let layout = UiOverlay::create_pipeline_layout(logical_device)?;
+
+        let pipeline = pipeline::PipelineDetail::create_pipeline(
+            logical_device,
+            shaders,
+            UiVertex {
+                pos: [0.0; 2],
+                tex_coord: [0.0; 2],
+                color: [0.0; 4],
+            },
+            &preset::FixedFunctionState::from_preset(preset::Preset::Ui2d),
+            layout,
+            render_pass,
+            vk::PipelineCache::null(),
+        )?;
+
+        let framebuffers = UiOverlay::create_framebuffers(
+            logical_device,
+            render_pass,
+            &swapchain.image_views,
+            swapchain.extent,
+        )?;
+
+        let (command_pool, command_buffers) =
+            UiOverlay::create_command_buffers(device, num_images as u32)?;
+
+        device.track(registry::ResourceKind::RenderPass, render_pass);
+        device.track(registry::ResourceKind::Pipeline, pipeline);
+        device.track(registry::ResourceKind::PipelineLayout, layout);
+        device.track(registry::ResourceKind::CommandPool, command_pool);
+        for &framebuffer in framebuffers.iter() {
+            device.track(registry::ResourceKind::Framebuffer, framebuffer);
+        }
+
+        Ok(UiOverlay {
+            device: device.clone(),
+            render_pass,
+            pipeline,
+            layout,
+            framebuffers,
+            command_pool,
+            command_buffers,
+            vertex_buffers: (0..num_images)
+                .map(|_| {
+                    dynamic_buffer::DynamicBuffer::new(
+                        vk::BufferUsageFlags::VERTEX_BUFFER,
+                        dynamic_buffer::DynamicMemory::HostVisible,
+                    )
+                })
+                .collect(),
+            index_buffers: (0..num_images)
+                .map(|_| {
+                    dynamic_buffer::DynamicBuffer::new(
+                        vk::BufferUsageFlags::INDEX_BUFFER,
+                        dynamic_buffer::DynamicMemory::HostVisible,
+                    )
+                })
+                .collect(),
+            extent: swapchain.extent,
+            draw_list: UiDrawList::default(),
+        })
+    }
+
+    fn upload_to_slot<T>(
+        device: &device::Device,
+        slot: &mut dynamic_buffer::DynamicBuffer,
+        data: &[T],
+    ) -> Result<vk::Buffer> {
+        slot.update(device, data)?;
+        slot.buffer()
+            .ok_or_else(|| Error::msg("ui buffer was not allocated for the draw list"))
+    }
+
+    // Uploads the current draw list and records the ui command buffer for the image.
+    // Must only be called once the previous frame using this image has completed.
+    pub fn record(&mut self, frame: &frame::FrameContext) -> Result<vk::CommandBuffer> {
+        let logical_device = &self.device.logical_device;
+
+        let command_buffer = *frame.per_image(&self.command_buffers)?;
+
+        let draw_buffers = if self.draw_list.is_empty() {
+            None
+        } else {
+            let vertex_buffer = UiOverlay::upload_to_slot(
+                &self.device,
+                frame.per_image_mut(&mut self.vertex_buffers)?,
+                &self.draw_list.vertices,
+            )?;
+
+            let index_buffer = UiOverlay::upload_to_slot(
+                &self.device,
+                frame.per_image_mut(&mut self.index_buffers)?,
+                &self.draw_list.indices,
+            )?;
+
+            Some((vertex_buffer, index_buffer))
+        };
+
+        let push_constants = PushConstants {
+            scale: [
+                2.0 / self.extent.width as f32,
+                2.0 / self.extent.height as f32,
+            ],
+            translate: [-1.0, -1.0],
+        };
+
+        let push_constant_bytes = unsafe {
+            ::std::slice::from_raw_parts(
+                &push_constants as *const PushConstants as *const u8,
+                ::std::mem::size_of::<PushConstants>(),
+            )
+        };
+
+        let render_pass_begin_info = vk::RenderPassBeginInfo {
+            render_pass: self.render_pass,
+            framebuffer: *frame.per_image(&self.framebuffers)?,
+            render_area: vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: self.extent,
+            },
+            ..Default::default()
+        };
+
+        let viewports = [vk::Viewport {
+            x: 0.0,
+            y: 0.0,
+            width: self.extent.width as f32,
+            height: self.extent.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        }];
+
+        let scissors = [vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent: self.extent,
+        }];
+
+        unsafe {
+            logical_device
+                .reset_command_buffer(command_buffer, vk::CommandBufferResetFlags::empty())
+                .context("failed to reset ui command buffer")?;
+
+            logical_device
+                .begin_command_buffer(command_buffer, &vk::CommandBufferBeginInfo::default())
+                .context("failed to begin recording ui command buffer")?;
+
+            logical_device.cmd_begin_render_pass(
+                command_buffer,
+                &render_pass_begin_info,
+                vk::SubpassContents::INLINE,
+            );
+
+            if let Some((vertex_buffer, index_buffer)) = draw_buffers {
+                logical_device.cmd_bind_pipeline(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    self.pipeline,
+                );
+
+                logical_device.cmd_set_viewport(command_buffer, 0, &viewports);
+                logical_device.cmd_set_scissor(command_buffer, 0, &scissors);
+
+                logical_device.cmd_push_constants(
+                    command_buffer,
+                    self.layout,
+                    vk::ShaderStageFlags::VERTEX,
+                    0,
+                    push_constant_bytes,
+                );
+
+                logical_device.cmd_bind_vertex_buffers(command_buffer, 0, &[vertex_buffer], &[0]);
+                logical_device.cmd_bind_index_buffer(
+                    command_buffer,
+                    index_buffer,
+                    0,
+                    vk::IndexType::UINT32,
+                );
+
+                logical_device.cmd_draw_indexed(
+                    command_buffer,
+                    self.draw_list.indices.len() as u32,
+                    1,
+                    0,
+                    0,
+                    0,
+                );
+            }
+
+            logical_device.cmd_end_render_pass(command_buffer);
+
+            logical_device
+                .end_command_buffer(command_buffer)
+                .context("failed to end ui command buffer recording")?;
+        }
+
+        Ok(command_buffer)
+    }
+
+    pub fn destroy(&mut self) {
+        for buffer in self
+            .vertex_buffers
+            .iter_mut()
+            .chain(self.index_buffers.iter_mut())
+        {
+            buffer.destroy(&self.device);
+        }
+
+        self.device.untrack(self.command_pool);
+        self.device.untrack(self.pipeline);
+        self.device.untrack(self.layout);
+        self.device.untrack(self.render_pass);
+        for &framebuffer in self.framebuffers.iter() {
+            self.device.untrack(framebuffer);
+        }
+
+        let logical_device = &self.device.logical_device;
+        unsafe {
+            logical_device.destroy_command_pool(self.command_pool, None);
+
+            for &framebuffer in self.framebuffers.iter() {
+                logical_device.destroy_framebuffer(framebuffer, None);
+            }
+
+            logical_device.destroy_pipeline(self.pipeline, None);
+            logical_device.destroy_pipeline_layout(self.layout, None);
+            logical_device.destroy_render_pass(self.render_pass, None);
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use ash::vk;
+
+use crate::error::{Error, Result};
+
+use super::device;
+use super::typed_buffer;
+
+// A single host visible uniform buffer split into one region per swapchain image, bound
+// once as a UNIFORM_BUFFER_DYNAMIC descriptor and selected with the region's dynamic
+// offset. The memory stays mapped for the lifetime of the ring, so writing a region is a
+// plain copy. A region must only be written once the previous frame using the image has
+// completed, which keeps the frames in flight from reading half written data.
+pub struct UniformRing<T: Copy> {
+    buffer: typed_buffer::UniformBuffer<T>,
+    mapped: *mut u8,
+}
+
+// The mapping is only written through &mut self
+unsafe impl<T: Copy + Send> Send for UniformRing<T> {}
+
+impl<T: Copy> UniformRing<T> {
+    pub fn new(device: &device::Device, regions: usize) -> Result<UniformRing<T>> {
+        let size = ::std::mem::size_of::<T>() as u32;
+        if size > device.limits.max_uniform_buffer_range {
+            return Err(Error::Unsupported(format!(
+                "uniform data of {} bytes exceeds the uniform buffer range of {}",
+                size, device.limits.max_uniform_buffer_range
+            )));
+        }
+
+        let buffer = typed_buffer::UniformBuffer::host_visible(device, regions)?;
+        let mapped = match buffer.info.map(&device.logical_device) {
+            Ok(mapped) => mapped,
+            Err(err) => {
+                buffer.destroy(device);
+                return Err(err);
+            }
+        };
+
+        Ok(UniformRing { buffer, mapped })
+    }
+
+    // The range covers one region, the dynamic offset moves it over the buffer
+    pub fn descriptor_info(&self) -> Result<vk::DescriptorBufferInfo> {
+        self.buffer.descriptor_info(0)
+    }
+
+    pub fn regions(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub fn set_name(&self, device: &device::Device, name: &str) {
+        self.buffer.info.set_name(device, name);
+    }
+
+    // Overwrites the region, see the ring for when it is safe to
+    pub fn write(&mut self, region: usize, data: &T) -> Result<()> {
+        let offset = self.region_offset(region)?;
+
+        unsafe {
+            let data_ptr = self.mapped.add(offset as usize) as *mut T;
+            data_ptr.copy_from_nonoverlapping(data, 1);
+        }
+
+        Ok(())
+    }
+
+    fn region_offset(&self, region: usize) -> Result<vk::DeviceSize> {
+        if region >= self.buffer.len() {
+            return Err(Error::OutOfRange(format!(
+                "no uniform region {}, the ring has {}",
+                region,
+                self.buffer.len()
+            )));
+        }
+
+        self.buffer.offset_of(region)
+    }
+
+    // Offset passed to cmd_bind_descriptor_sets to select the region
+    pub fn dynamic_offset(&self, region: usize) -> Result<u32> {
+        self.region_offset(region).map(|offset| offset as u32)
+    }
+
+    // Offsets of every region, indexed by image
+    pub fn region_offsets(&self) -> Result<Vec<u32>> {
+        (0..self.buffer.len())
+            .map(|region| self.dynamic_offset(region))
+            .collect()
+    }
+
+    pub fn destroy(&self, device: &device::Device) {
+        self.buffer.info.unmap(&device.logical_device);
+        self.buffer.destroy(device);
+    }
+}
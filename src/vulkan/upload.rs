@@ -0,0 +1,484 @@
+use ash::version::DeviceV1_0;
+use ash::vk;
+
+use crate::error::{Context, Error, Result};
+
+use super::buffers;
+use super::device;
+use super::queue;
+use super::registry;
+
+// Copies into the staging buffer start at multiples of this, enough for any vertex or texel format
+const STAGING_ALIGNMENT: vk::DeviceSize = 16;
+
+pub const DEFAULT_STAGING_SIZE: vk::DeviceSize = 8 * 1024 * 1024;
+pub const DEFAULT_RING_SIZE: usize = 3;
+
+enum PendingCopy {
+    Buffer {
+        src_offset: vk::DeviceSize,
+        dst: vk::Buffer,
+        dst_offset: vk::DeviceSize,
+        size: vk::DeviceSize,
+    },
+    Image {
+        src_offset: vk::DeviceSize,
+        dst: vk::Image,
+        extent: vk::Extent2D,
+    },
+}
+
+// A staging buffer along with the command buffer and fence of the batch using it
+struct StagingSlot {
+    staging: buffers::BufferInfo,
+    command_buffer: vk::CommandBuffer,
+    fence: vk::Fence,
+    // id of the last batch submitted from this slot
+    batch: Option<u64>,
+}
+
+// Identifies a submitted batch of uploads
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct UploadTicket {
+    batch: u64,
+    slot: usize,
+}
+
+// Uploads data to device local resources through a ring of reused staging buffers.
+// Copies are collected into a batch which is submitted as a single command buffer.
+pub struct UploadManager {
+    device: device::Device,
+    queue: queue::SharedQueue,
+    command_pool: vk::CommandPool,
+
+    slots: Vec<StagingSlot>,
+    current: usize,
+
+    copies: Vec<PendingCopy>,
+    offset: vk::DeviceSize,
+    next_batch: u64,
+}
+
+impl UploadManager {
+    pub fn new(
+        device: &device::Device,
+        queue: vk::Queue,
+        staging_size: vk::DeviceSize,
+        ring_size: usize,
+    ) -> Result<UploadManager> {
+        let logical_device = &device.logical_device;
+
+        let queue_index = device
+            .family_indices
+            .graphics
+            .ok_or_else(|| Error::msg("graphics family index not present"))?;
+
+        let pool_info = vk::CommandPoolCreateInfo {
+            queue_family_index: queue_index,
+            flags: vk::CommandPoolCreateFlags::TRANSIENT
+                | vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER,
+            ..Default::default()
+        };
+
+        let command_pool = unsafe {
+            logical_device
+                .create_command_pool(&pool_info, None)
+                .context("failed to create upload command pool")
+        }?;
+
+        let allocate_info = vk::CommandBufferAllocateInfo {
+            command_pool,
+            level: vk::CommandBufferLevel::PRIMARY,
+            command_buffer_count: ring_size as u32,
+            ..Default::default()
+        };
+
+        let command_buffers = unsafe {
+            logical_device
+                .allocate_command_buffers(&allocate_info)
+                .context("failed to allocate upload command buffers")
+        }?;
+
+        let slots = command_buffers
+            .into_iter()
+            .map(|command_buffer| {
+                let staging = UploadManager::create_staging(device, staging_size)?;
+
+                let fence = unsafe {
+                    logical_device
+                        .create_fence(&vk::FenceCreateInfo::default(), None)
+                        .context("failed to create upload fence")
+                }?;
+                device.track(registry::ResourceKind::Fence, fence);
+
+                Ok(StagingSlot {
+                    staging,
+                    command_buffer,
+                    fence,
+                    batch: None,
+                })
+            })
+            .collect::<Result<Vec<StagingSlot>>>()?;
+
+        device.track(registry::ResourceKind::CommandPool, command_pool);
+
+        Ok(UploadManager {
+            device: device.clone(),
+            queue: queue::SharedQueue::new(queue),
+            command_pool,
+            slots,
+            current: 0,
+            copies: vec![],
+            offset: 0,
+            next_batch: 0,
+        })
+    }
+
+    fn create_staging(
+        device: &device::Device,
+        size: vk::DeviceSize,
+    ) -> Result<buffers::BufferInfo> {
+        buffers::BufferInfo::create(
+            device,
+            size,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )
+    }
+
+    fn wait_for_slot(&mut self, slot: usize) -> Result<()> {
+        let slot = &mut self.slots[slot];
+
+        if slot.batch.take().is_some() {
+            unsafe {
+                self.device
+                    .logical_device
+                    .wait_for_fences(&[slot.fence], true, std::u64::MAX)
+                    .and_then(|_| self.device.logical_device.reset_fences(&[slot.fence]))
+                    .context("failed to wait for upload batch")
+            }?;
+        }
+
+        Ok(())
+    }
+
+    // Reserves space in the current staging buffer, submitting the batch when it is full
+    fn stage<T>(&mut self, data: &[T]) -> Result<vk::DeviceSize> {
+        let size = ::std::mem::size_of_val(data) as vk::DeviceSize;
+        let mut offset = align(self.offset, STAGING_ALIGNMENT);
+
+        if offset + size > self.slots[self.current].staging.size() && !self.copies.is_empty() {
+            self.submit()?;
+            offset = 0;
+        }
+
+        // the slot is free once its previous batch has completed
+        self.wait_for_slot(self.current)?;
+
+        let slot = &mut self.slots[self.current];
+        if size > slot.staging.size() {
+            slot.staging.destroy(&self.device);
+            slot.staging = UploadManager::create_staging(&self.device, size.next_power_of_two())?;
+        }
+
+        slot.staging
+            .update_region(&self.device.logical_device, offset, data)?;
+        self.offset = offset + size;
+
+        Ok(offset)
+    }
+
+    // Creates a device local buffer, the data is copied once the batch is submitted
+    pub fn upload_buffer<T>(
+        &mut self,
+        usage: vk::BufferUsageFlags,
+        data: &[T],
+    ) -> Result<buffers::BufferInfo> {
+        let size = ::std::mem::size_of_val(data) as vk::DeviceSize;
+
+        let buffer = buffers::BufferInfo::create(
+            &self.device,
+            size,
+            vk::BufferUsageFlags::TRANSFER_DST | usage,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+
+        self.upload_to_buffer(buffer.buffer, 0, data)?;
+
+        Ok(buffer)
+    }
+
+    // Copies data into part of an existing buffer with TRANSFER_DST usage, eg. a range
+    // of a mesh_pool::MeshPool. The gpu must not be using that part of it anymore.
+    pub fn upload_to_buffer<T>(
+        &mut self,
+        dst: vk::Buffer,
+        dst_offset: vk::DeviceSize,
+        data: &[T],
+    ) -> Result<()> {
+        let size = ::std::mem::size_of_val(data) as vk::DeviceSize;
+
+        let src_offset = self.stage(data)?;
+        self.copies.push(PendingCopy::Buffer {
+            src_offset,
+            dst,
+            dst_offset,
+            size,
+        });
+
+        Ok(())
+    }
+
+    // Copies tightly packed texels into a color image, which ends up ready to be sampled
+    pub fn upload_image(
+        &mut self,
+        image: vk::Image,
+        extent: vk::Extent2D,
+        data: &[u8],
+    ) -> Result<()> {
+        let src_offset = self.stage(data)?;
+        self.copies.push(PendingCopy::Image {
+            src_offset,
+            dst: image,
+            extent,
+        });
+
+        Ok(())
+    }
+
+    fn image_barrier(
+        image: vk::Image,
+        old_layout: vk::ImageLayout,
+        new_layout: vk::ImageLayout,
+        src_access_mask: vk::AccessFlags,
+        dst_access_mask: vk::AccessFlags,
+    ) -> vk::ImageMemoryBarrier {
+        vk::ImageMemoryBarrier {
+            old_layout,
+            new_layout,
+            src_access_mask,
+            dst_access_mask,
+            src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            image,
+            subresource_range: vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: 1,
+            },
+            ..Default::default()
+        }
+    }
+
+    fn record_copies(&self, command_buffer: vk::CommandBuffer) -> Result<()> {
+        let device = &self.device.logical_device;
+        let staging = self.slots[self.current].staging.buffer;
+
+        let begin_info = vk::CommandBufferBeginInfo {
+            flags: vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT,
+            ..Default::default()
+        };
+
+        unsafe {
+            device
+                .begin_command_buffer(command_buffer, &begin_info)
+                .context("failed to begin upload command buffer")?;
+
+            for copy in self.copies.iter() {
+                match *copy {
+                    PendingCopy::Buffer {
+                        src_offset,
+                        dst,
+                        dst_offset,
+                        size,
+                    } => device.cmd_copy_buffer(
+                        command_buffer,
+                        staging,
+                        dst,
+                        &[vk::BufferCopy {
+                            src_offset,
+                            dst_offset,
+                            size,
+                        }],
+                    ),
+
+                    PendingCopy::Image {
+                        src_offset,
+                        dst,
+                        extent,
+                    } => {
+                        device.cmd_pipeline_barrier(
+                            command_buffer,
+                            vk::PipelineStageFlags::TOP_OF_PIPE,
+                            vk::PipelineStageFlags::TRANSFER,
+                            vk::DependencyFlags::empty(),
+                            &[],
+                            &[],
+                            &[UploadManager::image_barrier(
+                                dst,
+                                vk::ImageLayout::UNDEFINED,
+                                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                                vk::AccessFlags::empty(),
+                                vk::AccessFlags::TRANSFER_WRITE,
+                            )],
+                        );
+
+                        device.cmd_copy_buffer_to_image(
+                            command_buffer,
+                            staging,
+                            dst,
+                            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                            &[vk::BufferImageCopy {
+                                buffer_offset: src_offset,
+                                buffer_row_length: 0,
+                                buffer_image_height: 0,
+                                image_subresource: vk::ImageSubresourceLayers {
+                                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                                    mip_level: 0,
+                                    base_array_layer: 0,
+                                    layer_count: 1,
+                                },
+                                image_offset: vk::Offset3D { x: 0, y: 0, z: 0 },
+                                image_extent: vk::Extent3D {
+                                    width: extent.width,
+                                    height: extent.height,
+                                    depth: 1,
+                                },
+                            }],
+                        );
+
+                        device.cmd_pipeline_barrier(
+                            command_buffer,
+                            vk::PipelineStageFlags::TRANSFER,
+                            vk::PipelineStageFlags::FRAGMENT_SHADER,
+                            vk::DependencyFlags::empty(),
+                            &[],
+                            &[],
+                            &[UploadManager::image_barrier(
+                                dst,
+                                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                                vk::AccessFlags::TRANSFER_WRITE,
+                                vk::AccessFlags::SHADER_READ,
+                            )],
+                        );
+                    }
+                }
+            }
+
+            // make the copied buffers visible to whatever reads them in later submissions
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                vk::DependencyFlags::empty(),
+                &[vk::MemoryBarrier {
+                    src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+                    dst_access_mask: vk::AccessFlags::MEMORY_READ,
+                    ..Default::default()
+                }],
+                &[],
+                &[],
+            );
+
+            device
+                .end_command_buffer(command_buffer)
+                .context("failed to end upload command buffer")
+        }
+    }
+
+    // Submits the pending copies as one batch, returns None when there was nothing to upload
+    pub fn submit(&mut self) -> Result<Option<UploadTicket>> {
+        if self.copies.is_empty() {
+            return Ok(None);
+        }
+
+        let slot = self.current;
+        let command_buffer = self.slots[slot].command_buffer;
+        let fence = self.slots[slot].fence;
+
+        self.record_copies(command_buffer)?;
+
+        let command_buffers = [command_buffer];
+        let submit_info = vk::SubmitInfo {
+            command_buffer_count: 1,
+            p_command_buffers: command_buffers.as_ptr(),
+            ..Default::default()
+        };
+
+        self.queue
+            .submit(&self.device.logical_device, &[submit_info], fence)
+            .context("failed to submit upload batch")?;
+
+        let ticket = UploadTicket {
+            batch: self.next_batch,
+            slot,
+        };
+
+        self.slots[slot].batch = Some(self.next_batch);
+        self.next_batch += 1;
+        self.copies.clear();
+        self.offset = 0;
+        self.current = (self.current + 1) % self.slots.len();
+
+        Ok(Some(ticket))
+    }
+
+    pub fn is_complete(&self, ticket: UploadTicket) -> Result<bool> {
+        let slot = &self.slots[ticket.slot];
+
+        // the slot has been reused by a later batch, so this one finished long ago
+        if slot.batch != Some(ticket.batch) {
+            return Ok(true);
+        }
+
+        match unsafe {
+            self.device
+                .logical_device
+                .wait_for_fences(&[slot.fence], true, 0)
+        } {
+            Ok(_) => Ok(true),
+            Err(vk::Result::TIMEOUT) => Ok(false),
+            Err(err) => Err(err).context("failed to query upload fence"),
+        }
+    }
+
+    pub fn wait(&mut self, ticket: UploadTicket) -> Result<()> {
+        if self.slots[ticket.slot].batch == Some(ticket.batch) {
+            self.wait_for_slot(ticket.slot)?;
+        }
+
+        Ok(())
+    }
+
+    // Submits the pending copies and waits for every batch in flight
+    pub fn flush(&mut self) -> Result<()> {
+        self.submit()?;
+
+        (0..self.slots.len())
+            .map(|slot| self.wait_for_slot(slot))
+            .collect::<Result<Vec<()>>>()
+            .map(|_| ())
+    }
+
+    pub fn destroy(&mut self) {
+        let logical_device = &self.device.logical_device;
+
+        for slot in self.slots.iter() {
+            slot.staging.destroy(&self.device);
+            self.device.untrack(slot.fence);
+            unsafe { logical_device.destroy_fence(slot.fence, None) };
+        }
+
+        // frees the command buffers as well
+        self.device.untrack(self.command_pool);
+        unsafe { logical_device.destroy_command_pool(self.command_pool, None) };
+        self.slots.clear();
+    }
+}
+
+fn align(value: vk::DeviceSize, alignment: vk::DeviceSize) -> vk::DeviceSize {
+    (value + alignment - 1) / alignment * alignment
+}
@@ -0,0 +1,306 @@
+// Counts of the debug messenger's messages per frame, by message id and severity. Attached
+// to the messenger with DebugMessengerConfig::with_stats, frames are ended by
+// Engine::render_frame. Tests set a baseline of the known messages and fail once new
+// ones appear, see ValidationStats::check.
+
+use ash::vk;
+
+use crate::error::{Error, Result};
+
+use super::debug_messenger;
+
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+// Frames of counts kept by default, older ones only remain in the totals
+pub const DEFAULT_HISTORY_FRAMES: usize = 120;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Severity {
+    Verbose,
+    Info,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    pub fn from_flags(severity: vk::DebugUtilsMessageSeverityFlagsEXT) -> Severity {
+        if severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::ERROR) {
+            Severity::Error
+        } else if severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::WARNING) {
+            Severity::Warning
+        } else if severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::INFO) {
+            Severity::Info
+        } else {
+            Severity::Verbose
+        }
+    }
+}
+
+// Messages are told apart by their id and severity, not by their text, which usually
+// names handles that differ between runs
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct MessageKey {
+    pub severity: Severity,
+    pub id_name: String,
+    pub id_number: i32,
+}
+
+impl MessageKey {
+    pub fn from_message(message: &debug_messenger::DebugMessage) -> MessageKey {
+        MessageKey {
+            severity: Severity::from_flags(message.severity),
+            id_name: message.id_name.clone(),
+            id_number: message.id_number,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FrameValidationStats {
+    pub frame: u64,
+    pub counts: BTreeMap<MessageKey, u32>,
+}
+
+impl FrameValidationStats {
+    pub fn total(&self) -> u32 {
+        self.counts.values().sum()
+    }
+
+    pub fn count(&self, severity: Severity) -> u32 {
+        self.counts
+            .iter()
+            .filter(|(key, _)| key.severity == severity)
+            .map(|(_, count)| count)
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty()
+    }
+}
+
+#[derive(Debug)]
+struct StatsState {
+    current: FrameValidationStats,
+    history: VecDeque<FrameValidationStats>,
+    history_frames: usize,
+    totals: BTreeMap<MessageKey, u64>,
+    // the messages expected to appear, see set_baseline
+    baseline: BTreeSet<MessageKey>,
+}
+
+// Shared between clones, so the messenger's config and the application see the same counts
+#[derive(Debug, Clone)]
+pub struct ValidationStats {
+    state: Arc<Mutex<StatsState>>,
+}
+
+impl Default for ValidationStats {
+    fn default() -> ValidationStats {
+        ValidationStats::new(DEFAULT_HISTORY_FRAMES)
+    }
+}
+
+impl ValidationStats {
+    pub fn new(history_frames: usize) -> ValidationStats {
+        ValidationStats {
+            state: Arc::new(Mutex::new(StatsState {
+                current: FrameValidationStats::default(),
+                history: VecDeque::with_capacity(history_frames),
+                history_frames,
+                totals: BTreeMap::new(),
+                baseline: BTreeSet::new(),
+            })),
+        }
+    }
+
+    // A message passed to a callback that panicked must not lose the counts
+    fn lock(&self) -> MutexGuard<StatsState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    // Called by the messenger for every forwarded message, on any thread
+    pub fn record(&self, message: &debug_messenger::DebugMessage) {
+        let key = MessageKey::from_message(message);
+        let mut state = self.lock();
+
+        *state.totals.entry(key.clone()).or_insert(0) += 1;
+        *state.current.counts.entry(key).or_insert(0) += 1;
+    }
+
+    // Closes the counts of the current frame and returns them, the messages recorded
+    // afterwards count towards the next frame
+    pub fn end_frame(&self) -> FrameValidationStats {
+        let mut state = self.lock();
+
+        let next = FrameValidationStats {
+            frame: state.current.frame + 1,
+            counts: BTreeMap::new(),
+        };
+        let ended = std::mem::replace(&mut state.current, next);
+
+        if state.history_frames > 0 {
+            if state.history.len() == state.history_frames {
+                state.history.pop_front();
+            }
+            state.history.push_back(ended.clone());
+        }
+
+        ended
+    }
+
+    // Counts of the frame being rendered so far
+    pub fn current(&self) -> FrameValidationStats {
+        self.lock().current.clone()
+    }
+
+    pub fn last_frame(&self) -> Option<FrameValidationStats> {
+        self.lock().history.back().cloned()
+    }
+
+    // The ended frames kept, oldest first
+    pub fn history(&self) -> Vec<FrameValidationStats> {
+        self.lock().history.iter().cloned().collect()
+    }
+
+    // Counts since the stats were created or reset, of every frame
+    pub fn totals(&self) -> BTreeMap<MessageKey, u64> {
+        self.lock().totals.clone()
+    }
+
+    // Every message seen so far is expected, eg. once a test's setup is done or from a
+    // run known to be good
+    pub fn set_baseline(&self) {
+        let mut state = self.lock();
+        state.baseline = state.totals.keys().cloned().collect();
+    }
+
+    pub fn with_baseline<I: IntoIterator<Item = MessageKey>>(self, keys: I) -> ValidationStats {
+        self.lock().baseline.extend(keys);
+        self
+    }
+
+    // Messages seen that are not in the baseline, with how often they appeared
+    pub fn new_messages(&self) -> Vec<(MessageKey, u64)> {
+        let state = self.lock();
+
+        state
+            .totals
+            .iter()
+            .filter(|(key, _)| !state.baseline.contains(key))
+            .map(|(key, count)| (key.clone(), *count))
+            .collect()
+    }
+
+    // Fails when messages of the severity or above appeared that are not in the baseline
+    pub fn check(&self, min_severity: Severity) -> Result<()> {
+        let new_messages = self
+            .new_messages()
+            .into_iter()
+            .filter(|(key, _)| key.severity >= min_severity)
+            .map(|(key, count)| {
+                format!(
+                    "{:?} {} ({}) x{}",
+                    key.severity, key.id_name, key.id_number, count
+                )
+            })
+            .collect::<Vec<String>>();
+
+        if new_messages.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::msg(format!(
+                "new validation messages: {}",
+                new_messages.join(", ")
+            )))
+        }
+    }
+
+    // Clears the counts and history, the baseline and frame numbers are kept
+    pub fn reset(&self) {
+        let mut state = self.lock();
+
+        let frame = state.current.frame;
+        state.current = FrameValidationStats {
+            frame,
+            counts: BTreeMap::new(),
+        };
+        state.history.clear();
+        state.totals.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(
+        severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+        id_name: &str,
+    ) -> debug_messenger::DebugMessage {
+        debug_messenger::DebugMessage {
+            severity,
+            kind: vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION,
+            id_name: id_name.to_string(),
+            id_number: id_name.len() as i32,
+            message: "test message".to_string(),
+        }
+    }
+
+    #[test]
+    fn messages_are_counted_per_frame() {
+        let stats = ValidationStats::new(2);
+        let warning = message(vk::DebugUtilsMessageSeverityFlagsEXT::WARNING, "VUID-a");
+        let error = message(vk::DebugUtilsMessageSeverityFlagsEXT::ERROR, "VUID-b");
+
+        stats.record(&warning);
+        stats.record(&warning);
+        stats.record(&error);
+        let first = stats.end_frame();
+        assert_eq!(first.frame, 0);
+        assert_eq!(first.total(), 3);
+        assert_eq!(first.count(Severity::Warning), 2);
+        assert_eq!(first.count(Severity::Error), 1);
+
+        stats.record(&error);
+        assert_eq!(stats.current().count(Severity::Error), 1);
+        stats.end_frame();
+        stats.end_frame();
+
+        // only the last two frames are kept, the totals cover all of them
+        let history = stats.history();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].frame, 1);
+        assert!(history[1].is_empty());
+        assert_eq!(stats.totals()[&MessageKey::from_message(&error)], 2);
+    }
+
+    #[test]
+    fn new_messages_fail_the_check() {
+        let stats = ValidationStats::default();
+        let known = message(vk::DebugUtilsMessageSeverityFlagsEXT::WARNING, "VUID-known");
+
+        stats.record(&known);
+        assert!(stats.check(Severity::Warning).is_err());
+        stats.set_baseline();
+        stats.record(&known);
+        assert!(stats.check(Severity::Warning).is_ok());
+
+        stats.record(&message(
+            vk::DebugUtilsMessageSeverityFlagsEXT::INFO,
+            "VUID-info",
+        ));
+        assert!(stats.check(Severity::Warning).is_ok());
+        assert!(stats.check(Severity::Info).is_err());
+
+        stats.record(&message(
+            vk::DebugUtilsMessageSeverityFlagsEXT::ERROR,
+            "VUID-new",
+        ));
+        let err = stats.check(Severity::Warning).unwrap_err();
+        assert!(format!("{}", err).contains("VUID-new"));
+    }
+}
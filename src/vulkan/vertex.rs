@@ -0,0 +1,244 @@
+use ash::vk;
+
+use std::marker::PhantomData;
+
+// Field types that can be read by a vertex shader along with their vulkan format
+pub trait VertexAttribute {
+    const FORMAT: vk::Format;
+}
+
+macro_rules! vertex_attribute {
+    ($ty:ty, $format:ident) => {
+        impl VertexAttribute for $ty {
+            const FORMAT: vk::Format = vk::Format::$format;
+        }
+    };
+}
+
+vertex_attribute!(f32, R32_SFLOAT);
+vertex_attribute!([f32; 2], R32G32_SFLOAT);
+vertex_attribute!([f32; 3], R32G32B32_SFLOAT);
+vertex_attribute!([f32; 4], R32G32B32A32_SFLOAT);
+vertex_attribute!(u32, R32_UINT);
+vertex_attribute!([u32; 2], R32G32_UINT);
+vertex_attribute!([u32; 3], R32G32B32_UINT);
+vertex_attribute!([u32; 4], R32G32B32A32_UINT);
+vertex_attribute!(i32, R32_SINT);
+vertex_attribute!([i32; 2], R32G32_SINT);
+vertex_attribute!([i32; 3], R32G32B32_SINT);
+vertex_attribute!([i32; 4], R32G32B32A32_SINT);
+// read as floats between 0 and 1
+vertex_attribute!([u8; 4], R8G8B8A8_UNORM);
+vertex_attribute!([Half; 2], R16G16_SFLOAT);
+// three component half formats are rarely supported, the fourth one pads
+vertex_attribute!([Half; 4], R16G16B16A16_SFLOAT);
+vertex_attribute!(PackedNormal, A2B10G10R10_SNORM_PACK32);
+
+// An IEEE 754 half precision float, read as a float by the shader. Holds about 3
+// significant digits and magnitudes up to 65504.
+#[repr(transparent)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub struct Half(pub u16);
+
+impl Half {
+    // Rounds to the nearest half, ties to even. Values too large become infinite.
+    pub fn from_f32(value: f32) -> Half {
+        let bits = value.to_bits();
+        let sign = ((bits >> 16) & 0x8000) as u16;
+        let exponent = ((bits >> 23) & 0xff) as i32;
+        let mantissa = bits & 0x7f_ffff;
+
+        // infinity, and nan which keeps a mantissa bit set
+        if exponent == 0xff {
+            let nan = if mantissa != 0 { 0x200 } else { 0 };
+            return Half(sign | 0x7c00 | nan);
+        }
+
+        let exponent = exponent - 127 + 15;
+        if exponent >= 0x1f {
+            return Half(sign | 0x7c00);
+        }
+
+        // the bits shifted out decide the rounding, up past the halfway point or at it
+        // when the result is odd. A carry into the exponent still gives the right half.
+        let round = |value: u32, shift: u32| {
+            let round_bit = 1 << (shift - 1);
+            let shifted = value >> shift;
+            if value & round_bit != 0 && value & (3 * round_bit - 1) != 0 {
+                shifted + 1
+            } else {
+                shifted
+            }
+        };
+
+        if exponent <= 0 {
+            // subnormal halves, or zero for values below half the smallest one
+            if exponent < -10 {
+                return Half(sign);
+            }
+            let mantissa = mantissa | 0x80_0000;
+            return Half(sign | round(mantissa, (14 - exponent) as u32) as u16);
+        }
+
+        Half(sign | round(((exponent as u32) << 23) | mantissa, 13) as u16)
+    }
+
+    pub fn to_f32(self) -> f32 {
+        let sign = ((self.0 & 0x8000) as u32) << 16;
+        let exponent = ((self.0 >> 10) & 0x1f) as u32;
+        let mantissa = (self.0 & 0x3ff) as u32;
+
+        let bits = match exponent {
+            0 if mantissa == 0 => sign,
+            // subnormal halves are normal floats
+            0 => {
+                let shift = mantissa.leading_zeros() - 21;
+                sign | ((113 - shift) << 23) | ((mantissa << shift) & 0x3ff) << 13
+            }
+            0x1f => sign | 0x7f80_0000 | (mantissa << 13),
+            _ => sign | ((exponent + 127 - 15) << 23) | (mantissa << 13),
+        };
+
+        f32::from_bits(bits)
+    }
+}
+
+// A vector with components between -1 and 1 in 10 bits each, x in the lowest bits. The
+// top 2 bits are read as w and left at 0.
+#[repr(transparent)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub struct PackedNormal(pub u32);
+
+impl PackedNormal {
+    pub fn new(normal: [f32; 3]) -> PackedNormal {
+        let pack =
+            |component: f32| ((component.max(-1.0).min(1.0) * 511.0).round() as i32 as u32) & 0x3ff;
+        PackedNormal(pack(normal[0]) | pack(normal[1]) << 10 | pack(normal[2]) << 20)
+    }
+
+    pub fn unpack(self) -> [f32; 3] {
+        // sign extends the component, -512 is read as -1 like -511
+        let unpack = |shift: u32| {
+            let component = ((self.0 >> shift) << 22) as i32 >> 22;
+            (component as f32 / 511.0).max(-1.0)
+        };
+        [unpack(0), unpack(10), unpack(20)]
+    }
+}
+
+// Builds the binding and attribute descriptions of a vertex type.
+// Attributes get consecutive shader locations in the order they are added.
+pub struct VertexLayoutBuilder<V> {
+    binding: u32,
+    input_rate: vk::VertexInputRate,
+    attributes: Vec<vk::VertexInputAttributeDescription>,
+    vertex: PhantomData<V>,
+}
+
+impl<V> VertexLayoutBuilder<V> {
+    pub fn new() -> VertexLayoutBuilder<V> {
+        VertexLayoutBuilder {
+            binding: 0,
+            input_rate: vk::VertexInputRate::VERTEX,
+            attributes: vec![],
+            vertex: PhantomData,
+        }
+    }
+
+    pub fn with_binding(mut self, binding: u32) -> VertexLayoutBuilder<V> {
+        self.binding = binding;
+        self
+    }
+
+    pub fn per_instance(mut self) -> VertexLayoutBuilder<V> {
+        self.input_rate = vk::VertexInputRate::INSTANCE;
+        self
+    }
+
+    pub fn attribute(mut self, format: vk::Format, offset: usize) -> VertexLayoutBuilder<V> {
+        self.attributes.push(vk::VertexInputAttributeDescription {
+            binding: self.binding,
+            location: self.attributes.len() as u32,
+            format,
+            offset: offset as u32,
+        });
+        self
+    }
+
+    // The format is taken from the type of the field, eg. `.field(offset_of!(V, pos), |v| &v.pos)`
+    pub fn field<A: VertexAttribute>(
+        self,
+        offset: usize,
+        _field: fn(&V) -> &A,
+    ) -> VertexLayoutBuilder<V> {
+        self.attribute(A::FORMAT, offset)
+    }
+
+    pub fn binding_descriptions(&self) -> Vec<vk::VertexInputBindingDescription> {
+        vec![vk::VertexInputBindingDescription {
+            binding: self.binding,
+            stride: ::std::mem::size_of::<V>() as u32,
+            input_rate: self.input_rate,
+        }]
+    }
+
+    pub fn attribute_descriptions(&self) -> Vec<vk::VertexInputAttributeDescription> {
+        self.attributes.clone()
+    }
+}
+
+// Implements `pipeline::VertexData` for a #[repr(C)] struct, every listed field
+// becomes an attribute with its location given by the order in the list
+#[macro_export]
+macro_rules! impl_vertex_data {
+    ($vertex:ident { $($field:ident),+ $(,)? }) => {
+        impl $crate::vulkan::pipeline::VertexData for $vertex {
+            fn get_input_binding_description(&self) -> Vec<::ash::vk::VertexInputBindingDescription> {
+                $crate::vulkan::vertex::VertexLayoutBuilder::<$vertex>::new().binding_descriptions()
+            }
+
+            fn get_attribute_description(&self) -> Vec<::ash::vk::VertexInputAttributeDescription> {
+                $crate::vulkan::vertex::VertexLayoutBuilder::<$vertex>::new()
+                    $(.field(::memoffset::offset_of!($vertex, $field), |vertex| &vertex.$field))+
+                    .attribute_descriptions()
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn halves_round_to_nearest_even() {
+        for &value in [0.0, -0.0, 1.0, -2.5, 0.333_251_95, 65504.0, 6.103_515_6e-5].iter() {
+            assert_eq!(Half::from_f32(value).to_f32(), value);
+        }
+        assert_eq!(Half::from_f32(1.0).0, 0x3c00);
+
+        // halfway between 1 and the next half, rounded down to the even one
+        assert_eq!(Half::from_f32(1.0 + 1.0 / 2048.0).0, 0x3c00);
+        assert_eq!(Half::from_f32(1.0 + 3.0 / 2048.0).0, 0x3c02);
+
+        // the smallest subnormal and what is too small for it
+        assert_eq!(Half::from_f32(5.960_464_5e-8).0, 0x0001);
+        assert_eq!(Half::from_f32(5.960_464_5e-8).to_f32(), 5.960_464_5e-8);
+        assert_eq!(Half::from_f32(2.0e-8).0, 0x0000);
+
+        assert_eq!(Half::from_f32(70000.0).to_f32(), std::f32::INFINITY);
+        assert!(Half::from_f32(std::f32::NAN).to_f32().is_nan());
+    }
+
+    #[test]
+    fn normals_pack_into_ten_bits() {
+        let normal = PackedNormal::new([0.0, 1.0, -1.0]);
+        assert_eq!(normal.0 >> 30, 0);
+        assert_eq!(normal.unpack(), [0.0, 1.0, -1.0]);
+
+        let [x, y, z] = PackedNormal::new([0.6, -0.8, 2.0]).unpack();
+        assert!((x - 0.6).abs() < 1.0 / 511.0);
+        assert!((y + 0.8).abs() < 1.0 / 511.0);
+        assert_eq!(z, 1.0);
+    }
+}
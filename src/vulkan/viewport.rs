@@ -0,0 +1,347 @@
+use ash::version::DeviceV1_0;
+use ash::vk;
+
+use crate::error::{Context, Error, Result};
+
+use super::{device, frame, image, pipeline, postprocess, swapchain};
+
+// Name of the viewport covering the swapchain in EngineConfig::default
+pub const MAIN_VIEWPORT: &'static str = "main";
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PostProcessEffect {
+    Bloom,
+    Tonemap,
+    Gamma,
+    Fxaa,
+}
+
+// Curve the tonemap effect maps the exposed HDR color to the displayable range with.
+// The values match TONEMAP_* of shaders/postprocess.frag.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TonemapOperator {
+    Reinhard = 0,
+    // Narkowicz's fit of the ACES filmic curve
+    Aces = 1,
+    // John Hable's Uncharted 2 filmic curve
+    Hable = 2,
+}
+
+// Parameters of the post process chain applied to a single viewport
+#[derive(Debug, Clone)]
+pub struct PostProcessConfig {
+    pub effects: Vec<PostProcessEffect>,
+    pub exposure: f32,
+    pub tonemap: TonemapOperator,
+    pub gamma: f32,
+    // brightness in nits of a color of 1.0 on an HDR swapchain
+    pub paper_white: f32,
+    pub bloom_threshold: f32,
+}
+
+impl PostProcessConfig {
+    pub fn disabled() -> PostProcessConfig {
+        PostProcessConfig {
+            effects: vec![],
+            ..Default::default()
+        }
+    }
+
+    // Effects run in the order they were added, eg. tonemap before gamma before fxaa
+    pub fn with_effect(mut self, effect: PostProcessEffect) -> PostProcessConfig {
+        if !self.is_enabled(effect) {
+            self.effects.push(effect);
+        }
+        self
+    }
+
+    pub fn with_exposure(mut self, exposure: f32) -> PostProcessConfig {
+        self.exposure = exposure;
+        self
+    }
+
+    pub fn with_tonemap(mut self, tonemap: TonemapOperator) -> PostProcessConfig {
+        self.tonemap = tonemap;
+        self
+    }
+
+    pub fn without_effect(mut self, effect: PostProcessEffect) -> PostProcessConfig {
+        self.effects.retain(|e| *e != effect);
+        self
+    }
+
+    pub fn is_enabled(&self, effect: PostProcessEffect) -> bool {
+        self.effects.contains(&effect)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.effects.is_empty()
+    }
+}
+
+impl Default for PostProcessConfig {
+    fn default() -> PostProcessConfig {
+        PostProcessConfig {
+            effects: vec![PostProcessEffect::Tonemap, PostProcessEffect::Gamma],
+            exposure: 1.0,
+            tonemap: TonemapOperator::Reinhard,
+            gamma: 2.2,
+            paper_white: 200.0,
+            bloom_threshold: 1.0,
+        }
+    }
+}
+
+// An area of the swapchain with its own post process chain, eg. the editor view can skip
+// bloom while the game view enables it. The area is a fraction of the swapchain's extent
+// so it follows resizes. Every viewport shows the scene of the active camera.
+#[derive(Debug, Clone)]
+pub struct ViewportConfig {
+    pub name: String,
+    // top left corner and size, from 0.0 to 1.0 of the swapchain's width and height
+    pub offset: [f32; 2],
+    pub size: [f32; 2],
+    pub post_process: PostProcessConfig,
+}
+
+impl ViewportConfig {
+    // Covers the whole swapchain without post processing
+    pub fn new(name: &str) -> ViewportConfig {
+        ViewportConfig {
+            name: name.to_string(),
+            offset: [0.0, 0.0],
+            size: [1.0, 1.0],
+            post_process: PostProcessConfig::disabled(),
+        }
+    }
+
+    pub fn with_area(mut self, offset: [f32; 2], size: [f32; 2]) -> ViewportConfig {
+        self.offset = offset;
+        self.size = size;
+        self
+    }
+
+    pub fn with_post_process(mut self, post_process: PostProcessConfig) -> ViewportConfig {
+        self.post_process = post_process;
+        self
+    }
+
+    // The pixels of the swapchain the viewport covers. Both edges are rounded the same
+    // way, so viewports sharing an edge leave no gap between them.
+    pub fn get_scissor(&self, extent: vk::Extent2D) -> vk::Rect2D {
+        let edge = |fraction: f32, length: u32| {
+            (fraction.max(0.0).min(1.0) * length as f32).round() as u32
+        };
+
+        let left = edge(self.offset[0], extent.width);
+        let top = edge(self.offset[1], extent.height);
+        let right = edge(self.offset[0] + self.size[0], extent.width);
+        let bottom = edge(self.offset[1] + self.size[1], extent.height);
+
+        vk::Rect2D {
+            offset: vk::Offset2D {
+                x: left as i32,
+                y: top as i32,
+            },
+            extent: vk::Extent2D {
+                width: right.saturating_sub(left),
+                height: bottom.saturating_sub(top),
+            },
+        }
+    }
+}
+
+// The post process chain of one viewport, with its own intermediate targets
+pub struct ViewportTarget {
+    pub name: String,
+    pub chain: postprocess::PostProcessChain,
+}
+
+// The scene is rendered once into an offscreen HDR target per swapchain image, then the
+// chain of every viewport writes its area of the swapchain image from it, in the order
+// of the configs. Parts of the swapchain outside of every viewport are undefined.
+pub struct ViewportTargets {
+    device: device::Device,
+    // per swapchain image, rendered by the scene pass
+    scene: Vec<image::ImageData>,
+    pub viewports: Vec<ViewportTarget>,
+}
+
+impl ViewportTargets {
+    // Rendered by the scene pass and read by the chain of every viewport
+    fn create_scene_targets(
+        device: &device::Device,
+        graphics_queue: vk::Queue,
+        swapchain: &swapchain::SwapchainDetails,
+    ) -> Result<Vec<image::ImageData>> {
+        let queue_index = device
+            .family_indices
+            .graphics
+            .ok_or_else(|| Error::msg("graphics family index not present"))?;
+
+        // only used for the layout transitions of the targets
+        let command_pool_info = vk::CommandPoolCreateInfo {
+            queue_family_index: queue_index,
+            flags: vk::CommandPoolCreateFlags::TRANSIENT,
+            ..Default::default()
+        };
+
+        let command_pool = unsafe {
+            device
+                .logical_device
+                .create_command_pool(&command_pool_info, None)
+                .context("failed to create scene target command pool")
+        }?;
+
+        let targets = (0..swapchain.image_views.len())
+            .map(|image_index| {
+                let property = image::ImagePropertyType::color_property(
+                    swapchain.extent,
+                    postprocess::HDR_FORMAT,
+                );
+                let target = image::ImageData::new(device, command_pool, graphics_queue, property)?;
+                target.set_name(device, &format!("scene target {}", image_index));
+
+                Ok(target)
+            })
+            .collect::<Result<Vec<image::ImageData>>>();
+
+        // the transitions have completed by the time the targets are created
+        unsafe {
+            device
+                .logical_device
+                .destroy_command_pool(command_pool, None)
+        };
+
+        targets
+    }
+
+    // What the scene pipeline has to render into for the chains to read it
+    pub fn scene_target(&self) -> pipeline::ColorTarget {
+        pipeline::ColorTarget::offscreen(postprocess::HDR_FORMAT)
+    }
+
+    // Replace the swapchain image views in the scene's framebuffers, one per image
+    pub fn scene_views(&self) -> Vec<vk::ImageView> {
+        self.scene.iter().map(|target| target.image_view).collect()
+    }
+
+    // The images of scene_views, rendered into directly with dynamic rendering
+    pub fn scene_images(&self) -> Vec<vk::Image> {
+        self.scene.iter().map(|target| target.image).collect()
+    }
+
+    pub fn chain_mut(&mut self, name: &str) -> Option<&mut postprocess::PostProcessChain> {
+        self.viewports
+            .iter_mut()
+            .find(|viewport| viewport.name == name)
+            .map(|viewport| &mut viewport.chain)
+    }
+
+    // One command buffer per viewport, submitted in order after the scene's.
+    // Must only be called once the previous frame using this image has completed.
+    pub fn record(&mut self, frame: &frame::FrameContext) -> Result<Vec<vk::CommandBuffer>> {
+        self.viewports
+            .iter_mut()
+            .map(|viewport| viewport.chain.record(frame))
+            .collect()
+    }
+
+    // The device has to be idle
+    pub fn destroy(&mut self) {
+        for viewport in self.viewports.iter_mut() {
+            viewport.chain.destroy();
+        }
+
+        for target in self.scene.iter() {
+            target.destroy(&self.device);
+        }
+    }
+}
+
+// None when no viewport has effects, the scene is then rendered straight to the
+// swapchain and the areas of the viewports are not used. Viewports without effects
+// copy their area when others have some.
+pub fn create_viewport_targets(
+    device: &device::Device,
+    graphics_queue: vk::Queue,
+    swapchain: &swapchain::SwapchainDetails,
+    configs: &[ViewportConfig],
+) -> Result<Option<ViewportTargets>> {
+    if configs.iter().all(|config| config.post_process.is_empty()) {
+        return Ok(None);
+    }
+
+    let scene = ViewportTargets::create_scene_targets(device, graphics_queue, swapchain)?;
+    let scene_views = scene
+        .iter()
+        .map(|target| target.image_view)
+        .collect::<Vec<vk::ImageView>>();
+
+    let viewports = configs
+        .iter()
+        .enumerate()
+        .map(|(index, config)| {
+            // the first chain starts from an undefined swapchain image
+            let keep_output = index > 0;
+            let chain = postprocess::PostProcessChain::new(
+                device,
+                graphics_queue,
+                swapchain,
+                config,
+                &scene_views,
+                keep_output,
+            )?;
+
+            Ok(ViewportTarget {
+                name: config.name.clone(),
+                chain,
+            })
+        })
+        .collect::<Result<Vec<ViewportTarget>>>()?;
+
+    Ok(Some(ViewportTargets {
+        device: device.clone(),
+        scene,
+        viewports,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn side_by_side_viewports_share_their_edge() {
+        let extent = vk::Extent2D {
+            width: 101,
+            height: 50,
+        };
+        let left = ViewportConfig::new("editor").with_area([0.0, 0.0], [0.5, 1.0]);
+        let right = ViewportConfig::new("game").with_area([0.5, 0.0], [0.5, 1.0]);
+
+        let left = left.get_scissor(extent);
+        let right = right.get_scissor(extent);
+
+        assert_eq!(left.offset.x, 0);
+        assert_eq!(left.extent.width as i32, right.offset.x);
+        assert_eq!(right.offset.x as u32 + right.extent.width, extent.width);
+        assert_eq!(right.extent.height, extent.height);
+    }
+
+    #[test]
+    fn areas_are_clamped_to_the_swapchain() {
+        let extent = vk::Extent2D {
+            width: 200,
+            height: 100,
+        };
+        let scissor = ViewportConfig::new("overlay")
+            .with_area([0.75, -0.5], [0.5, 1.0])
+            .get_scissor(extent);
+
+        assert_eq!(scissor.offset.x, 150);
+        assert_eq!(scissor.offset.y, 0);
+        assert_eq!(scissor.extent.width, 50);
+        assert_eq!(scissor.extent.height, 50);
+    }
+}
@@ -0,0 +1,317 @@
+use ash::version::DeviceV1_0;
+use ash::vk;
+
+use crate::error::{Context, Result};
+
+use serde::{Deserialize, Serialize};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+
+use crate::shaderc;
+
+use super::device;
+use super::pipeline;
+use super::preset;
+
+// Fixed function state is stored using the raw vulkan values so the manifest
+// does not depend on ash types being serializable
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PipelineManifestEntry {
+    pub vertex_shader_file: String,
+    pub fragment_shader_file: String,
+    pub topology: i32,
+    pub polygon_mode: i32,
+    pub cull_mode: u32,
+    pub front_face: i32,
+    pub line_width: f32,
+    pub depth_bias: bool,
+    pub depth_test: bool,
+    pub depth_write: bool,
+    pub depth_compare_op: i32,
+    pub blend_mode: preset::BlendMode,
+    pub color_write_mask: u32,
+    // manifests written before the sample count was configurable are single sampled
+    #[serde(default = "single_sample")]
+    pub samples: u32,
+}
+
+fn single_sample() -> u32 {
+    vk::SampleCountFlags::TYPE_1.as_raw()
+}
+
+impl PipelineManifestEntry {
+    pub fn new(
+        shaders: &shaderc::ShaderSource,
+        state: &preset::FixedFunctionState,
+    ) -> PipelineManifestEntry {
+        PipelineManifestEntry {
+            vertex_shader_file: shaders.vertex_shader_file.clone(),
+            fragment_shader_file: shaders.fragment_shader_file.clone(),
+            topology: state.topology.as_raw(),
+            polygon_mode: state.polygon_mode.as_raw(),
+            cull_mode: state.cull_mode.as_raw(),
+            front_face: state.front_face.as_raw(),
+            line_width: state.line_width,
+            depth_bias: state.depth_bias,
+            depth_test: state.depth_test,
+            depth_write: state.depth_write,
+            depth_compare_op: state.depth_compare_op.as_raw(),
+            blend_mode: state.blend_mode,
+            color_write_mask: state.color_write_mask.as_raw(),
+            samples: state.samples.as_raw(),
+        }
+    }
+
+    pub fn shaders(&self) -> shaderc::ShaderSource {
+        shaderc::ShaderSource {
+            vertex_shader_file: self.vertex_shader_file.clone(),
+            fragment_shader_file: self.fragment_shader_file.clone(),
+        }
+    }
+
+    pub fn state(&self) -> preset::FixedFunctionState {
+        preset::FixedFunctionState {
+            topology: vk::PrimitiveTopology::from_raw(self.topology),
+            polygon_mode: vk::PolygonMode::from_raw(self.polygon_mode),
+            cull_mode: vk::CullModeFlags::from_raw(self.cull_mode),
+            front_face: vk::FrontFace::from_raw(self.front_face),
+            line_width: self.line_width,
+            depth_bias: self.depth_bias,
+            depth_test: self.depth_test,
+            depth_write: self.depth_write,
+            depth_compare_op: vk::CompareOp::from_raw(self.depth_compare_op),
+            blend_mode: self.blend_mode,
+            color_write_mask: vk::ColorComponentFlags::from_raw(self.color_write_mask),
+            samples: vk::SampleCountFlags::from_raw(self.samples),
+        }
+    }
+}
+
+// List of pipelines used during a session, saved on exit and replayed on the next startup
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PipelineManifest {
+    pub entries: Vec<PipelineManifestEntry>,
+}
+
+impl PipelineManifest {
+    // A missing or unreadable manifest just means there is nothing to warm up
+    pub fn load(path: &Path) -> PipelineManifest {
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let contents =
+            serde_json::to_string_pretty(self).context("failed to serialize pipeline manifest")?;
+        fs::write(path, contents).context(format!("failed to write manifest {:?}", path))
+    }
+
+    pub fn record(&mut self, entry: PipelineManifestEntry) {
+        if !self.entries.contains(&entry) {
+            self.entries.push(entry);
+        }
+    }
+
+    // Drops entries that can no longer be built, eg. after a shader was removed
+    pub fn prune(&mut self, failed: &[PipelineManifestEntry]) {
+        self.entries.retain(|entry| !failed.contains(entry));
+    }
+}
+
+pub struct PipelineCache {
+    pub cache: vk::PipelineCache,
+    path: PathBuf,
+}
+
+impl PipelineCache {
+    pub fn new(device: &ash::Device, path: &Path) -> Result<PipelineCache> {
+        // the driver validates the header and ignores stale data from another device
+        let initial_data = fs::read(path).unwrap_or_default();
+
+        let cache_info = vk::PipelineCacheCreateInfo {
+            initial_data_size: initial_data.len(),
+            p_initial_data: initial_data.as_ptr() as *const std::os::raw::c_void,
+            ..Default::default()
+        };
+
+        let cache = unsafe {
+            device
+                .create_pipeline_cache(&cache_info, None)
+                .context("failed to create pipeline cache")
+        }?;
+
+        Ok(PipelineCache {
+            cache,
+            path: path.to_path_buf(),
+        })
+    }
+
+    pub fn save(&self, device: &ash::Device) -> Result<()> {
+        let data = unsafe {
+            device
+                .get_pipeline_cache_data(self.cache)
+                .context("failed to get pipeline cache data")
+        }?;
+
+        fs::write(&self.path, data).context(format!("failed to write {:?}", self.path))
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        unsafe { device.destroy_pipeline_cache(self.cache, None) };
+    }
+}
+
+// What the warm-up thread created, see warm_up
+#[derive(Debug, Clone, Default)]
+pub struct WarmupReport {
+    pub warmed: usize,
+    // pruned from the manifest before it is saved
+    pub failed: Vec<PipelineManifestEntry>,
+}
+
+// Creates every pipeline of the manifest on a background thread so that the pipeline
+// cache is populated before the scene needs them. The pipelines themselves are thrown away.
+// Entries that fail are logged and reported instead of stopping the warm-up.
+pub fn warm_up<V>(
+    instance: ash::Instance,
+    device: device::Device,
+    target: pipeline::ColorTarget,
+    pipeline_cache: vk::PipelineCache,
+    manifest: PipelineManifest,
+    vertex_data: V,
+) -> thread::JoinHandle<WarmupReport>
+where
+    V: pipeline::VertexData + Copy + Send + 'static,
+{
+    thread::spawn(move || {
+        let mut report = WarmupReport::default();
+
+        for entry in manifest.entries.iter() {
+            let created = pipeline::PipelineDetail::create_graphics_pipeline_with_cache(
+                &instance,
+                &device,
+                target,
+                entry.shaders(),
+                vertex_data,
+                &entry.state(),
+                pipeline_cache,
+            );
+
+            match created {
+                Ok(pipeline) => {
+                    pipeline.destroy(&device);
+                    report.warmed += 1;
+                }
+                Err(err) => {
+                    tracing::warn!(
+                        "cannot warm up the pipeline of {} and {}: {}",
+                        entry.vertex_shader_file,
+                        entry.fragment_shader_file,
+                        err
+                    );
+                    report.failed.push(entry.clone());
+                }
+            }
+        }
+
+        report
+    })
+}
+
+// Pipeline cache and manifest of a running session along with the warm-up thread
+pub struct PipelineWarmup {
+    pub cache: PipelineCache,
+    pub manifest: PipelineManifest,
+    manifest_path: PathBuf,
+    handle: Option<thread::JoinHandle<WarmupReport>>,
+}
+
+impl PipelineWarmup {
+    pub fn start<V>(
+        instance: &ash::Instance,
+        device: &device::Device,
+        target: pipeline::ColorTarget,
+        cache_path: &Path,
+        manifest_path: &Path,
+        vertex_data: V,
+    ) -> Result<PipelineWarmup>
+    where
+        V: pipeline::VertexData + Copy + Send + 'static,
+    {
+        let cache = PipelineCache::new(&device.logical_device, cache_path)?;
+        let manifest = PipelineManifest::load(manifest_path);
+
+        let handle = if manifest.entries.is_empty() {
+            None
+        } else {
+            Some(warm_up(
+                instance.clone(),
+                device.clone(),
+                target,
+                cache.cache,
+                manifest.clone(),
+                vertex_data,
+            ))
+        };
+
+        Ok(PipelineWarmup {
+            cache,
+            manifest,
+            manifest_path: manifest_path.to_path_buf(),
+            handle,
+        })
+    }
+
+    pub fn record(&mut self, shaders: &shaderc::ShaderSource, state: &preset::FixedFunctionState) {
+        self.manifest
+            .record(PipelineManifestEntry::new(shaders, state));
+    }
+
+    // Waits for the warm-up thread and persists the manifest and pipeline cache for the next
+    // run, without the entries that failed to warm up
+    pub fn finish(&mut self, device: &ash::Device) -> Result<()> {
+        if let Some(handle) = self.handle.take() {
+            match handle.join() {
+                Ok(report) => {
+                    tracing::info!("warmed up {} pipelines", report.warmed);
+                    self.manifest.prune(&report.failed);
+                }
+                Err(_) => tracing::error!("pipeline warm-up thread panicked"),
+            }
+        }
+
+        self.manifest.save(&self.manifest_path)?;
+        self.cache.save(device)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failed_entries_are_pruned() {
+        let shaders = shaderc::ShaderSource {
+            vertex_shader_file: "shaders/shader.vert".to_string(),
+            fragment_shader_file: "shaders/shader.frag".to_string(),
+        };
+        let opaque = preset::FixedFunctionState::from_preset(preset::Preset::Opaque3d);
+        let wireframe = opaque.with_polygon_mode(vk::PolygonMode::LINE);
+
+        let mut manifest = PipelineManifest::default();
+        manifest.record(PipelineManifestEntry::new(&shaders, &opaque));
+        manifest.record(PipelineManifestEntry::new(&shaders, &wireframe));
+        manifest.record(PipelineManifestEntry::new(&shaders, &opaque));
+        assert_eq!(manifest.entries.len(), 2);
+
+        manifest.prune(&[PipelineManifestEntry::new(&shaders, &wireframe)]);
+        assert_eq!(
+            manifest.entries,
+            vec![PipelineManifestEntry::new(&shaders, &opaque)]
+        );
+    }
+}
@@ -0,0 +1,458 @@
+// Rendering to a headset through the system's OpenXR runtime, with the `xr` feature.
+// The runtime decides which gpu renders and which vulkan extensions it needs, so the
+// context is created first and the vulkan instance and device after it:
+//
+//     let context = xr::XrContext::new()?;
+//     let instance = VulkanInstance::with_extensions(&context.instance_extensions()?)?;
+//     let selection = context.device_selection(&instance.instance)?;
+//     let device = Device::with_extensions(.., &selection, &context.device_extensions()?)?;
+//     let session = xr::XrSession::new(&context, &instance.instance, &device, format)?;
+//
+// A session then hands out the images of its swapchain instead of a window surface, see
+// stereo::EyeSink, and the tracked head and eye poses every frame.
+
+use ash::vk;
+use ash::vk::Handle;
+
+use cgmath::{Matrix4, Quaternion, Vector3};
+
+use openxr as xr;
+
+use crate::error::{Context, Error, Result};
+use crate::scene;
+use crate::vulkan::constants::*;
+use crate::vulkan::{adapter, device, stereo};
+
+use std::ffi::CString;
+
+const VIEW_TYPE: xr::ViewConfigurationType = xr::ViewConfigurationType::PRIMARY_STEREO;
+
+// Where a pose places something in the stage space, the floor of the play area
+pub fn transform_from_pose(pose: &xr::Posef) -> scene::Transform {
+    let orientation = pose.orientation;
+    let position = pose.position;
+
+    scene::Transform::at(Vector3::new(position.x, position.y, position.z)).with_rotation(
+        Quaternion::new(orientation.w, orientation.x, orientation.y, orientation.z),
+    )
+}
+
+pub fn fov_from_xr(fov: &xr::Fovf) -> stereo::Fov {
+    stereo::Fov {
+        angle_left: fov.angle_left,
+        angle_right: fov.angle_right,
+        angle_up: fov.angle_up,
+        angle_down: fov.angle_down,
+    }
+}
+
+// The camera of one located view, OpenXR views look down -z like scene::Camera
+pub fn eye_from_view(view: &xr::View, near: f32, far: f32) -> stereo::EyeCamera {
+    let transform = transform_from_pose(&view.pose);
+
+    stereo::EyeCamera {
+        view: Matrix4::from(transform.rotation.conjugate())
+            * Matrix4::from_translation(-transform.position),
+        projection: fov_from_xr(&view.fov).projection(near, far),
+    }
+}
+
+// Space separated extension names as the runtime lists them
+fn extension_names(names: &str) -> Result<Vec<CString>> {
+    names
+        .split_whitespace()
+        .map(|name| CString::new(name).context("invalid extension name"))
+        .collect()
+}
+
+// The runtime's instance and the head mounted system it renders to
+pub struct XrContext {
+    pub instance: xr::Instance,
+    pub system: xr::SystemId,
+    pub blend_mode: xr::EnvironmentBlendMode,
+}
+
+impl XrContext {
+    pub fn new() -> Result<XrContext> {
+        let entry = xr::Entry::load()
+            .map_err(|err| Error::Loading(format!("no openxr loader: {:?}", err)))?;
+
+        let available = entry.enumerate_extensions()?;
+        if !available.khr_vulkan_enable {
+            return Err(Error::Unsupported(
+                "the openxr runtime cannot render with vulkan".to_string(),
+            ));
+        }
+
+        let mut extensions = xr::ExtensionSet::default();
+        extensions.khr_vulkan_enable = true;
+
+        let instance = entry.create_instance(
+            &xr::ApplicationInfo {
+                application_name: WINDOW_TITLE,
+                application_version: APPLICATION_VERSION,
+                engine_name: "Kelsier",
+                engine_version: ENGINE_VERSION,
+            },
+            &extensions,
+            &[],
+        )?;
+
+        let system = instance
+            .system(xr::FormFactor::HEAD_MOUNTED_DISPLAY)
+            .context("no headset is connected")?;
+
+        // opaque for vr headsets, additive or alpha blend for see through ones
+        let blend_mode = *instance
+            .enumerate_environment_blend_modes(system, VIEW_TYPE)?
+            .first()
+            .context("the headset has no blend mode")?;
+
+        Ok(XrContext {
+            instance,
+            system,
+            blend_mode,
+        })
+    }
+
+    // To be passed to instance::VulkanInstance::with_extensions
+    pub fn instance_extensions(&self) -> Result<Vec<CString>> {
+        extension_names(
+            &self
+                .instance
+                .vulkan_legacy_instance_extensions(self.system)?,
+        )
+    }
+
+    // To be passed to device::Device::with_extensions
+    pub fn device_extensions(&self) -> Result<Vec<CString>> {
+        extension_names(&self.instance.vulkan_legacy_device_extensions(self.system)?)
+    }
+
+    // The gpu the headset is connected to, the device has to be created on it
+    pub fn device_selection(&self, instance: &ash::Instance) -> Result<adapter::DeviceSelection> {
+        let requirements = self
+            .instance
+            .graphics_requirements::<xr::Vulkan>(self.system)?;
+        if API_VERSION < vk_version(requirements.min_api_version_supported) {
+            let version = requirements.min_api_version_supported;
+            return Err(Error::Unsupported(format!(
+                "the openxr runtime needs vulkan {}.{}",
+                version.major(),
+                version.minor()
+            )));
+        }
+
+        let physical_device = unsafe {
+            self.instance
+                .vulkan_graphics_device(self.system, instance.handle().as_raw() as _)
+        }?;
+
+        Ok(adapter::DeviceSelection::Handle(
+            vk::PhysicalDevice::from_raw(physical_device as u64),
+        ))
+    }
+}
+
+fn vk_version(version: xr::Version) -> u32 {
+    ash::vk_make_version!(version.major(), version.minor(), version.patch())
+}
+
+// A running session on the device: its swapchain of two layer images, one per eye, the
+// stage space poses are located in and the frame being rendered.
+// Frames go wait_frame, StereoPass::record and record_handoff into the acquired
+// eyes, submit, release_eyes.
+pub struct XrSession {
+    session: xr::Session<xr::Vulkan>,
+    frame_waiter: xr::FrameWaiter,
+    frame_stream: xr::FrameStream<xr::Vulkan>,
+    stage: xr::Space,
+    head: xr::Space,
+    swapchain: xr::Swapchain<xr::Vulkan>,
+    images: Vec<vk::Image>,
+    pub eye_extent: vk::Extent2D,
+    blend_mode: xr::EnvironmentBlendMode,
+    events: xr::EventDataBuffer,
+    running: bool,
+    // of the frame begun by wait_frame
+    frame_state: Option<xr::FrameState>,
+    views: Vec<xr::View>,
+}
+
+impl XrSession {
+    pub fn new(
+        context: &XrContext,
+        instance: &ash::Instance,
+        device: &device::Device,
+        format: vk::Format,
+    ) -> Result<XrSession> {
+        let queue_family_index = device
+            .family_indices
+            .graphics
+            .context("the device has no graphics queue")?;
+
+        let (session, frame_waiter, frame_stream) = unsafe {
+            context.instance.create_session::<xr::Vulkan>(
+                context.system,
+                &xr::vulkan::SessionCreateInfo {
+                    instance: instance.handle().as_raw() as _,
+                    physical_device: device.physical_device.as_raw() as _,
+                    device: device.logical_device.handle().as_raw() as _,
+                    queue_family_index,
+                    queue_index: 0,
+                },
+            )
+        }?;
+
+        let stage =
+            session.create_reference_space(xr::ReferenceSpaceType::STAGE, xr::Posef::IDENTITY)?;
+        let head =
+            session.create_reference_space(xr::ReferenceSpaceType::VIEW, xr::Posef::IDENTITY)?;
+
+        let views = context
+            .instance
+            .enumerate_view_configuration_views(context.system, VIEW_TYPE)?;
+        let view = views.first().context("the headset has no views")?;
+        let eye_extent = vk::Extent2D {
+            width: view.recommended_image_rect_width,
+            height: view.recommended_image_rect_height,
+        };
+
+        // the eyes are copied in by stereo::StereoPass::record_handoff
+        let swapchain = session.create_swapchain(&xr::SwapchainCreateInfo {
+            create_flags: xr::SwapchainCreateFlags::EMPTY,
+            usage_flags: xr::SwapchainUsageFlags::COLOR_ATTACHMENT
+                | xr::SwapchainUsageFlags::TRANSFER_DST,
+            format: format.as_raw() as u32,
+            sample_count: 1,
+            width: eye_extent.width,
+            height: eye_extent.height,
+            face_count: 1,
+            array_size: 2,
+            mip_count: 1,
+        })?;
+
+        let images = swapchain
+            .enumerate_images()?
+            .into_iter()
+            .map(vk::Image::from_raw)
+            .collect();
+
+        Ok(XrSession {
+            session,
+            frame_waiter,
+            frame_stream,
+            stage,
+            head,
+            swapchain,
+            images,
+            eye_extent,
+            blend_mode: context.blend_mode,
+            events: xr::EventDataBuffer::new(),
+            running: false,
+            frame_state: None,
+            views: vec![],
+        })
+    }
+
+    // Follows the runtime's session state, begins and ends the session with it. Returns
+    // false once the application should exit.
+    pub fn poll_events(&mut self, context: &XrContext) -> Result<bool> {
+        while let Some(event) = context.instance.poll_event(&mut self.events)? {
+            match event {
+                xr::Event::SessionStateChanged(change) => match change.state() {
+                    xr::SessionState::READY => {
+                        self.session.begin(VIEW_TYPE)?;
+                        self.running = true;
+                    }
+                    xr::SessionState::STOPPING => {
+                        self.session.end()?;
+                        self.running = false;
+                    }
+                    xr::SessionState::EXITING | xr::SessionState::LOSS_PENDING => return Ok(false),
+                    _ => {}
+                },
+                xr::Event::InstanceLossPending(_) => return Ok(false),
+                _ => {}
+            }
+        }
+
+        Ok(true)
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    // Waits until the runtime wants the next frame and locates the eyes at the time it
+    // will be displayed. Returns false when the frame is not shown, eg. while the headset
+    // is taken off, it is already ended then and nothing has to be rendered.
+    pub fn wait_frame(&mut self) -> Result<bool> {
+        if !self.running {
+            return Ok(false);
+        }
+
+        let frame_state = self.frame_waiter.wait()?;
+        self.frame_stream.begin()?;
+
+        if !frame_state.should_render {
+            self.frame_stream
+                .end(frame_state.predicted_display_time, self.blend_mode, &[])?;
+            return Ok(false);
+        }
+
+        let (_, views) = self.session.locate_views(
+            VIEW_TYPE,
+            frame_state.predicted_display_time,
+            &self.stage,
+        )?;
+
+        self.views = views;
+        self.frame_state = Some(frame_state);
+        Ok(true)
+    }
+
+    // The cameras of the eyes of the frame begun by wait_frame, left then right
+    pub fn eyes(&self, near: f32, far: f32) -> Result<[stereo::EyeCamera; 2]> {
+        match self.views.as_slice() {
+            [left, right, ..] => Ok([
+                eye_from_view(left, near, far),
+                eye_from_view(right, near, far),
+            ]),
+            _ => Err(Error::msg("no frame is being rendered")),
+        }
+    }
+
+    // Where the head is at the frame's display time, eg. for the transform of the scene's
+    // camera so culling and level of detail follow it
+    pub fn head_transform(&self) -> Result<scene::Transform> {
+        let frame_state = self.frame_state.context("no frame is being rendered")?;
+        let location = self
+            .head
+            .locate(&self.stage, frame_state.predicted_display_time)?;
+
+        Ok(transform_from_pose(&location.pose))
+    }
+
+    fn projection_view(&self, eye: usize) -> xr::CompositionLayerProjectionView<xr::Vulkan> {
+        let view = &self.views[eye];
+
+        xr::CompositionLayerProjectionView::new()
+            .pose(view.pose)
+            .fov(view.fov)
+            .sub_image(
+                xr::SwapchainSubImage::new()
+                    .swapchain(&self.swapchain)
+                    .image_array_index(eye as u32)
+                    .image_rect(xr::Rect2Di {
+                        offset: xr::Offset2Di { x: 0, y: 0 },
+                        extent: xr::Extent2Di {
+                            width: self.eye_extent.width as i32,
+                            height: self.eye_extent.height as i32,
+                        },
+                    }),
+            )
+    }
+
+    pub fn destroy(self) {
+        // the openxr handles are destroyed when dropped, the images are owned by the
+        // runtime
+        if self.running {
+            let _ = self.session.end();
+        }
+    }
+}
+
+impl stereo::EyeSink for XrSession {
+    fn acquire_eyes(&mut self) -> Result<[stereo::EyeTarget; 2]> {
+        let index = self.swapchain.acquire_image()?;
+        self.swapchain.wait_image(xr::Duration::INFINITE)?;
+
+        let image = *self
+            .images
+            .get(index as usize)
+            .context("the runtime acquired an unknown image")?;
+
+        // the runtime expects the images back in the layout they are rendered in
+        let eye = |layer: u32| stereo::EyeTarget {
+            image,
+            layer,
+            extent: self.eye_extent,
+            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        };
+
+        Ok([eye(0), eye(1)])
+    }
+
+    fn release_eyes(&mut self) -> Result<()> {
+        self.swapchain.release_image()?;
+
+        let frame_state = self
+            .frame_state
+            .take()
+            .context("no frame is being rendered")?;
+
+        let views = [self.projection_view(0), self.projection_view(1)];
+        let layer = xr::CompositionLayerProjection::new()
+            .space(&self.stage)
+            .views(&views);
+
+        self.frame_stream.end(
+            frame_state.predicted_display_time,
+            self.blend_mode,
+            &[&layer],
+        )?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use cgmath::{InnerSpace, Vector4};
+
+    #[test]
+    fn eye_views_invert_their_pose() {
+        let view = xr::View {
+            pose: xr::Posef {
+                orientation: xr::Quaternionf {
+                    x: 0.0,
+                    y: 0.0,
+                    z: 0.0,
+                    w: 1.0,
+                },
+                position: xr::Vector3f {
+                    x: -0.032,
+                    y: 1.7,
+                    z: 0.0,
+                },
+            },
+            fov: xr::Fovf {
+                angle_left: -0.8,
+                angle_right: 0.8,
+                angle_up: 0.8,
+                angle_down: -0.8,
+            },
+        };
+
+        let eye = eye_from_view(&view, 0.1, 100.0);
+        let at_eye = eye.view * Vector4::new(-0.032, 1.7, 0.0, 1.0);
+
+        assert!(at_eye.truncate().magnitude() < 1e-5);
+    }
+
+    #[test]
+    fn runtime_extension_lists_are_split() {
+        let names = extension_names("VK_KHR_external_memory  VK_KHR_dedicated_allocation").unwrap();
+
+        assert_eq!(
+            names,
+            vec![
+                CString::new("VK_KHR_external_memory").unwrap(),
+                CString::new("VK_KHR_dedicated_allocation").unwrap(),
+            ]
+        );
+    }
+}